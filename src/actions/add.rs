@@ -0,0 +1,123 @@
+use crate::{
+    cli_options::AddSource,
+    dep_types::{GitRef, Req},
+    util::{self, abort},
+};
+
+/// Turn `pyflow add`'s arguments into requirements: parse each spec, including PEP 508 direct
+/// references like `mylib @ git+https://github.com/me/mylib@v1.2`, then apply `--extras`,
+/// `--git`, and `--path`.
+pub fn reqs_to_add(packages: &[String], source: &AddSource) -> Vec<Req> {
+    if packages.is_empty() {
+        abort("`add` must be followed by the packages to add, eg `pyflow add requests`")
+    }
+    if (source.git.is_some() || source.path.is_some()) && packages.len() != 1 {
+        abort(
+            "`--git` and `--path` apply to a single package, eg `pyflow add mylib --path ../mylib`",
+        )
+    }
+
+    let mut result = vec![];
+    for spec in packages {
+        let mut req = match spec.split_once('@') {
+            Some((name, url)) => parse_direct_ref(name, url.trim()),
+            None => util::parse_cli_reqs(std::slice::from_ref(spec)).remove(0),
+        };
+
+        if let Some(repo) = &source.git {
+            req.git = Some(repo.clone());
+            req.path = None;
+            req.git_ref = match (&source.rev, &source.tag, &source.branch) {
+                (Some(r), _, _) => Some(GitRef::Rev(r.clone())),
+                (_, Some(t), _) => Some(GitRef::Tag(t.clone())),
+                (_, _, Some(b)) => Some(GitRef::Branch(b.clone())),
+                _ => None,
+            };
+        }
+        if let Some(path) = &source.path {
+            req.path = Some(path.clone());
+            req.git = None;
+            req.git_ref = None;
+        }
+        if !source.extras.is_empty() {
+            req.install_with_extras =
+                util::merge_extras(&req.install_with_extras, &Some(source.extras.clone()));
+        }
+        result.push(req);
+    }
+    result
+}
+
+/// Parse `name @ url`, where `url` is `git+<repo>`, optionally ending in `@<rev>`, or
+/// `file://<dir>`.
+fn parse_direct_ref(name: &str, url: &str) -> Req {
+    let mut req = util::parse_cli_reqs(&[name.trim().to_owned()]).remove(0);
+    if !req.constraints.is_empty() {
+        abort(&format!(
+            "`{}` has both a version and a URL; use one or the other",
+            name.trim()
+        ))
+    }
+
+    if let Some(repo) = url.strip_prefix("git+") {
+        // A `@` after the last `/` picks the revision, eg `https://host/me/mylib@v1.2`.
+        let last_slash = repo.rfind('/').unwrap_or(0);
+        match repo[last_slash..].find('@') {
+            Some(i) => {
+                let (repo, rev) = repo.split_at(last_slash + i);
+                req.git = Some(repo.to_owned());
+                req.git_ref = Some(GitRef::Rev(rev[1..].to_owned()));
+            }
+            None => req.git = Some(repo.to_owned()),
+        }
+    } else if let Some(path) = url.strip_prefix("file://") {
+        req.path = Some(path.to_owned());
+    } else {
+        abort(&format!(
+            "Can't add `{}`: only `git+` and `file://` URLs are supported",
+            url
+        ))
+    }
+    req
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn direct_references() {
+        let req = parse_direct_ref("mylib ", "git+https://github.com/raz/mylib.git@v1.2");
+        assert_eq!(req.name, "mylib");
+        assert_eq!(req.git.as_deref(), Some("https://github.com/raz/mylib.git"));
+        assert_eq!(req.git_ref, Some(GitRef::Rev("v1.2".into())));
+
+        let req = parse_direct_ref("mylib[fast]", "git+ssh://git@github.com/raz/mylib");
+        assert_eq!(req.git.as_deref(), Some("ssh://git@github.com/raz/mylib"));
+        assert_eq!(req.git_ref, None);
+        assert_eq!(req.install_with_extras, Some(vec!["fast".to_string()]));
+
+        let req = parse_direct_ref("utils", "file:///home/raz/utils");
+        assert_eq!(req.path.as_deref(), Some("/home/raz/utils"));
+    }
+
+    #[test]
+    fn source_options() {
+        let source = AddSource {
+            extras: vec!["socks".into()],
+            git: Some("https://github.com/psf/requests".into()),
+            tag: Some("v2.31.0".into()),
+            ..Default::default()
+        };
+        let reqs = reqs_to_add(&["requests[security]".to_string()], &source);
+        assert_eq!(
+            reqs[0].git.as_deref(),
+            Some("https://github.com/psf/requests")
+        );
+        assert_eq!(reqs[0].git_ref, Some(GitRef::Tag("v2.31.0".into())));
+        assert_eq!(
+            reqs[0].install_with_extras,
+            Some(vec!["security".to_string(), "socks".to_string()])
+        );
+    }
+}
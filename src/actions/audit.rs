@@ -0,0 +1,510 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+use termcolor::Color;
+
+use crate::{
+    cli_options::AuditSeverity,
+    dep_types::{LockPackage, Version},
+    util::{self, abort, paths, print_color, print_color_},
+};
+
+const OSV_BATCH_URL: &str = "https://api.osv.dev/v1/querybatch";
+const OSV_VULN_URL: &str = "https://api.osv.dev/v1/vulns";
+/// The most queries OSV accepts in one batch.
+const BATCH_SIZE: usize = 1000;
+/// Maps `name==version` to the IDs of advisories affecting it, from the last online audit.
+const QUERIES_FILENAME: &str = "queries.json";
+
+/// IDs of advisories affecting a package, and when each was last modified, if known.
+type VulnIds = Vec<(String, Option<String>)>;
+
+#[derive(Serialize)]
+struct BatchQuery<'a> {
+    package: QueryPackage<'a>,
+    version: &'a str,
+}
+
+#[derive(Serialize)]
+struct QueryPackage<'a> {
+    name: &'a str,
+    ecosystem: &'static str,
+}
+
+#[derive(Serialize)]
+struct BatchRequest<'a> {
+    queries: Vec<BatchQuery<'a>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchResponse {
+    results: Vec<BatchResult>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct BatchResult {
+    #[serde(default)]
+    vulns: Vec<VulnRef>,
+}
+
+#[derive(Debug, Deserialize)]
+struct VulnRef {
+    id: String,
+    modified: Option<String>,
+}
+
+/// The parts of an [OSV advisory](https://ossf.github.io/osv-schema/) we use.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct Vuln {
+    id: String,
+    modified: Option<String>,
+    #[serde(default)]
+    summary: Option<String>,
+    #[serde(default)]
+    aliases: Vec<String>,
+    #[serde(default)]
+    affected: Vec<Affected>,
+    #[serde(default)]
+    database_specific: Option<DatabaseSpecific>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct DatabaseSpecific {
+    severity: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct Affected {
+    package: Option<AffectedPackage>,
+    #[serde(default)]
+    ranges: Vec<Range>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct AffectedPackage {
+    name: String,
+    ecosystem: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct Range {
+    #[serde(rename = "type")]
+    type_: String,
+    #[serde(default)]
+    events: Vec<HashMap<String, String>>,
+}
+
+/// An advisory affecting a locked package, for the report.
+#[derive(Debug, PartialEq, Serialize)]
+struct Finding {
+    package: String,
+    version: String,
+    id: String,
+    aliases: Vec<String>,
+    summary: Option<String>,
+    /// `low`, `medium`, `high`, or `critical`; absent if the advisory doesn't say.
+    severity: Option<String>,
+    /// Eg `>=2.0.0, <2.31.0`
+    vulnerable: String,
+    fixed: Option<String>,
+    /// Listed with `--ignore`, or in `[tool.pyflow.audit]`.
+    ignored: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct AuditOutput {
+    vulnerabilities: Vec<Finding>,
+    /// Packages with no cached results, when auditing offline.
+    unchecked: Vec<String>,
+}
+
+/// Check the locked packages against the [OSV](https://osv.dev) database, and report
+/// advisories affecting them. Exits with 1 if any that aren't ignored are at or above
+/// `threshold`; advisories without a severity always count. With `offline`, use the results
+/// cached by the last online audit.
+pub fn audit(
+    lockpacks: &[LockPackage],
+    pyflow_path: &Path,
+    threshold: AuditSeverity,
+    ignore: &[String],
+    offline: bool,
+    json: bool,
+) {
+    let cache_path = paths::index_cache_path(pyflow_path).join("osv");
+    let mut packages: Vec<(&str, &str)> = lockpacks
+        .iter()
+        .map(|p| (p.name.as_str(), p.version.as_str()))
+        .collect();
+    packages.sort_unstable();
+    packages.dedup();
+
+    let (ids, unchecked) = if offline {
+        cached_ids(&cache_path, &packages)
+    } else {
+        (query(&cache_path, &packages), vec![])
+    };
+
+    let mut findings = vec![];
+    for ((name, version), vuln_ids) in packages.iter().zip(ids) {
+        let vulns: Vec<Vuln> = vuln_ids
+            .iter()
+            .map(|(id, modified)| load_vuln(&cache_path, id, modified.as_deref(), offline))
+            .collect();
+        for vuln in dedup_aliases(vulns) {
+            findings.push(finding(&vuln, name, version, ignore));
+        }
+    }
+
+    let failed = findings.iter().any(|f| {
+        !f.ignored
+            && f.severity
+                .as_deref()
+                .and_then(|s| AuditSeverity::from_str(s).ok())
+                .map_or(true, |s| s >= threshold)
+    });
+
+    if json {
+        util::print_json(&AuditOutput {
+            vulnerabilities: findings,
+            unchecked,
+        });
+    } else {
+        print_findings(&findings, &unchecked, packages.len());
+    }
+
+    process::exit(if failed { 1 } else { 0 })
+}
+
+/// Find advisories for each package with OSV's batch API, and cache the results. Returns, for
+/// each package, the IDs and modification times of advisories affecting it.
+fn query(cache_path: &Path, packages: &[(&str, &str)]) -> Vec<VulnIds> {
+    let mut result: Vec<VulnIds> = Vec::with_capacity(packages.len());
+    for chunk in packages.chunks(BATCH_SIZE) {
+        let request = BatchRequest {
+            queries: chunk
+                .iter()
+                .map(|&(name, version)| BatchQuery {
+                    package: QueryPackage {
+                        name,
+                        ecosystem: "PyPI",
+                    },
+                    version,
+                })
+                .collect(),
+        };
+        let resp: BatchResponse = util::http_client()
+            .post(OSV_BATCH_URL)
+            .json(&request)
+            .send()
+            .and_then(|r| r.error_for_status())
+            .and_then(|r| r.json())
+            .unwrap_or_else(|_| {
+                abort("Problem querying the OSV database; use `--offline` to use cached results")
+            });
+        let mut results = resp.results.into_iter();
+        for _ in chunk {
+            let vulns = results.next().unwrap_or_default().vulns;
+            result.push(vulns.into_iter().map(|v| (v.id, v.modified)).collect());
+        }
+    }
+
+    let mut cached: HashMap<String, Vec<String>> =
+        read_json(&cache_path.join(QUERIES_FILENAME)).unwrap_or_default();
+    for ((name, version), ids) in packages.iter().zip(&result) {
+        cached.insert(
+            cache_key(name, version),
+            ids.iter().map(|(id, _)| id.clone()).collect(),
+        );
+    }
+    write_json(&cache_path.join(QUERIES_FILENAME), &cached);
+    result
+}
+
+/// Advisory IDs from the last online audit. Also returns packages it didn't include.
+fn cached_ids(cache_path: &Path, packages: &[(&str, &str)]) -> (Vec<VulnIds>, Vec<String>) {
+    let cached: HashMap<String, Vec<String>> =
+        read_json(&cache_path.join(QUERIES_FILENAME)).unwrap_or_default();
+    let mut unchecked = vec![];
+    let ids = packages
+        .iter()
+        .map(
+            |(name, version)| match cached.get(&cache_key(name, version)) {
+                Some(ids) => ids.iter().map(|id| (id.clone(), None)).collect(),
+                None => {
+                    unchecked.push(format!("{}=={}", name, version));
+                    vec![]
+                }
+            },
+        )
+        .collect();
+    (ids, unchecked)
+}
+
+fn cache_key(name: &str, version: &str) -> String {
+    format!("{}=={}", util::standardize_name(name), version)
+}
+
+fn vuln_path(cache_path: &Path, id: &str) -> PathBuf {
+    cache_path.join("vulns").join(format!("{}.json", id))
+}
+
+/// An advisory's details: from the cache if it's unchanged since then, or we're offline;
+/// otherwise from OSV.
+fn load_vuln(cache_path: &Path, id: &str, modified: Option<&str>, offline: bool) -> Vuln {
+    let path = vuln_path(cache_path, id);
+    let cached: Option<Vuln> = read_json(&path);
+    if let Some(vuln) = cached {
+        if offline || (modified.is_some() && vuln.modified.as_deref() == modified) {
+            return vuln;
+        }
+    } else if offline {
+        util::print_color(
+            &format!("No cached details for {}; run `pyflow audit` online", id),
+            Color::Yellow,
+        );
+        return Vuln {
+            id: id.to_owned(),
+            ..Default::default()
+        };
+    }
+
+    let vuln: Vuln = util::http_client()
+        .get(format!("{}/{}", OSV_VULN_URL, id))
+        .send()
+        .and_then(|r| r.error_for_status())
+        .and_then(|r| r.json())
+        .unwrap_or_else(|_| abort(&format!("Problem fetching details of {} from OSV", id)));
+    write_json(&path, &vuln);
+    vuln
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Option<T> {
+    let data = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&data).ok()
+}
+
+/// Failing to cache isn't a reason to fail the audit.
+fn write_json<T: Serialize>(path: &Path, value: &T) {
+    let written = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(path, serde_json::to_string(value).unwrap_or_default()));
+    if written.is_err() {
+        log::warn!("Problem caching audit results in {:?}", path);
+    }
+}
+
+/// The same advisory is often published by several databases, eg as `PYSEC-` and `GHSA-`
+/// IDs that alias each other. Keep one of each, preferring one with a severity.
+fn dedup_aliases(mut vulns: Vec<Vuln>) -> Vec<Vuln> {
+    vulns.sort_by_key(|v| {
+        v.database_specific
+            .as_ref()
+            .and_then(|d| d.severity.as_ref())
+            .is_none()
+    });
+    let mut result: Vec<Vuln> = vec![];
+    for vuln in vulns {
+        let duplicate = result
+            .iter()
+            .any(|kept| kept.aliases.contains(&vuln.id) || vuln.aliases.contains(&kept.id));
+        if !duplicate {
+            result.push(vuln);
+        }
+    }
+    result.sort_by(|a, b| a.id.cmp(&b.id));
+    result
+}
+
+fn finding(vuln: &Vuln, name: &str, version: &str, ignore: &[String]) -> Finding {
+    let (vulnerable, fixed) = affected_range(vuln, name, version);
+    let ignored = ignore.iter().any(|i| {
+        i.eq_ignore_ascii_case(&vuln.id) || vuln.aliases.iter().any(|a| i.eq_ignore_ascii_case(a))
+    });
+    Finding {
+        package: name.to_owned(),
+        version: version.to_owned(),
+        id: vuln.id.clone(),
+        aliases: vuln.aliases.clone(),
+        summary: vuln.summary.clone(),
+        severity: severity(vuln).map(|s| s.to_string()),
+        vulnerable,
+        fixed,
+        ignored,
+    }
+}
+
+/// GitHub's advisories rate severity as `LOW`, `MODERATE`, `HIGH`, or `CRITICAL`.
+fn severity(vuln: &Vuln) -> Option<AuditSeverity> {
+    let s = vuln.database_specific.as_ref()?.severity.as_deref()?;
+    match s.to_lowercase().as_str() {
+        "moderate" => Some(AuditSeverity::Medium),
+        other => AuditSeverity::from_str(other).ok(),
+    }
+}
+
+/// The versions of `name` the advisory applies to, eg `>=2.0, <2.31.0 || <1.9.5`, and the first
+/// version after `version` that fixes it.
+fn affected_range(vuln: &Vuln, name: &str, version: &str) -> (String, Option<String>) {
+    let current = Version::from_str(version).ok();
+    let mut ranges = vec![];
+    let mut fixes: Vec<String> = vec![];
+
+    let affected = vuln.affected.iter().filter(|a| {
+        a.package
+            .as_ref()
+            .is_some_and(|p| p.ecosystem == "PyPI" && util::compare_names(&p.name, name))
+    });
+    for range in affected
+        .flat_map(|a| &a.ranges)
+        .filter(|r| r.type_ == "ECOSYSTEM")
+    {
+        let mut parts = vec![];
+        for event in &range.events {
+            if let Some(v) = event.get("introduced") {
+                if !parts.is_empty() {
+                    ranges.push(parts.join(", "));
+                    parts.clear();
+                }
+                if v != "0" {
+                    parts.push(format!(">={}", v));
+                }
+            } else if let Some(v) = event.get("fixed") {
+                parts.push(format!("<{}", v));
+                fixes.push(v.clone());
+            } else if let Some(v) = event.get("last_affected") {
+                parts.push(format!("<={}", v));
+            }
+        }
+        ranges.push(if parts.is_empty() {
+            "*".to_owned()
+        } else {
+            parts.join(", ")
+        });
+    }
+
+    let mut later: Vec<(Version, String)> = fixes
+        .iter()
+        .filter_map(|f| Some((Version::from_str(f).ok()?, f.clone())))
+        .filter(|(v, _)| current.as_ref().map_or(true, |c| v > c))
+        .collect();
+    later.sort();
+    let fixed = later.into_iter().next().map(|(_, f)| f);
+
+    let vulnerable = if ranges.is_empty() {
+        "unknown".to_owned()
+    } else {
+        ranges.join(" || ")
+    };
+    (vulnerable, fixed)
+}
+
+fn print_findings(findings: &[Finding], unchecked: &[String], num_packages: usize) {
+    for finding in findings {
+        let color = if finding.ignored {
+            Color::White
+        } else {
+            Color::Red
+        };
+        print_color_(
+            &format!("{} {}", finding.package, finding.version),
+            Color::Cyan,
+        );
+        print_color_(&format!(" {}", finding.id), color);
+        println!(
+            " ({}){}",
+            finding.severity.as_deref().unwrap_or("unknown severity"),
+            if finding.ignored { ", ignored" } else { "" }
+        );
+        if let Some(summary) = &finding.summary {
+            println!("    {}", summary.trim());
+        }
+        println!("    Vulnerable: {}", finding.vulnerable);
+        match &finding.fixed {
+            Some(f) => println!("    Fixed in: {}", f),
+            None => println!("    No fixed version"),
+        }
+    }
+
+    for package in unchecked {
+        print_color(
+            &format!(
+                "No cached results for {}; run `pyflow audit` online",
+                package
+            ),
+            Color::Yellow,
+        );
+    }
+
+    let active = findings.iter().filter(|f| !f.ignored).count();
+    if active == 0 {
+        util::success(&format!(
+            "No known vulnerabilities in {} packages",
+            num_packages - unchecked.len()
+        ));
+    } else {
+        print_color(&format!("{} vulnerabilities found", active), Color::Red);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vuln(id: &str, aliases: &[&str], severity: Option<&str>) -> Vuln {
+        Vuln {
+            id: id.to_owned(),
+            aliases: aliases.iter().map(|a| a.to_string()).collect(),
+            database_specific: severity.map(|s| DatabaseSpecific {
+                severity: Some(s.to_owned()),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn ranges_and_fixes() {
+        let data = r#"{
+            "id": "GHSA-j8r2-6x86-q33q",
+            "aliases": ["CVE-2023-32681", "PYSEC-2023-74"],
+            "affected": [{
+                "package": {"name": "requests", "ecosystem": "PyPI"},
+                "ranges": [
+                    {"type": "ECOSYSTEM", "events": [{"introduced": "2.3.0"}, {"fixed": "2.31.0"}]},
+                    {"type": "ECOSYSTEM", "events": [{"introduced": "0"}, {"fixed": "1.2.0"}]}
+                ]
+            }],
+            "database_specific": {"severity": "MODERATE"}
+        }"#;
+        let v: Vuln = serde_json::from_str(data).unwrap();
+        assert_eq!(
+            affected_range(&v, "Requests", "2.28.0"),
+            (
+                ">=2.3.0, <2.31.0 || <1.2.0".to_owned(),
+                Some("2.31.0".to_owned())
+            )
+        );
+        assert_eq!(severity(&v), Some(AuditSeverity::Medium));
+
+        let f = finding(&v, "requests", "2.28.0", &["cve-2023-32681".to_owned()]);
+        assert!(f.ignored);
+        assert_eq!(f.severity.as_deref(), Some("medium"));
+    }
+
+    #[test]
+    fn aliases() {
+        let vulns = vec![
+            vuln("PYSEC-2023-74", &["GHSA-j8r2-6x86-q33q"], None),
+            vuln("GHSA-j8r2-6x86-q33q", &["PYSEC-2023-74"], Some("MODERATE")),
+            vuln("PYSEC-2022-1", &[], None),
+        ];
+        let ids: Vec<String> = dedup_aliases(vulns).into_iter().map(|v| v.id).collect();
+        assert_eq!(ids, vec!["GHSA-j8r2-6x86-q33q", "PYSEC-2022-1"]);
+    }
+}
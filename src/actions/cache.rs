@@ -0,0 +1,263 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use termcolor::Color;
+
+use crate::{
+    actions::clean::python_installs,
+    cli_options::CacheCommand,
+    util::{self, paths, print_color, print_color_, print_json},
+};
+
+/// What a cached file is, for `cache size`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Category {
+    Wheel,
+    Sdist,
+    Metadata,
+    Interpreter,
+    Other,
+}
+
+/// A file or folder in one of pyflow's caches.
+#[derive(Debug, Serialize)]
+struct Artifact {
+    path: PathBuf,
+    category: Category,
+    package: Option<String>,
+    version: Option<String>,
+    size: u64,
+    /// Seconds since the Unix epoch; the modified time where the filesystem doesn't record
+    /// access times.
+    last_access: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct CategorySize {
+    category: Category,
+    count: usize,
+    size: u64,
+}
+
+/// Show where pyflow caches downloads, what's there, and how much space it uses.
+pub fn cache(cmd: &CacheCommand, pyflow_path: &Path, python_path: &Path) {
+    match cmd {
+        CacheCommand::Dir { json } => {
+            let root = paths::cache_root(pyflow_path);
+            if *json {
+                print_json(&serde_json::json!({ "cache_dir": root }));
+            } else {
+                println!("{}", root.display());
+            }
+        }
+        CacheCommand::List { package, json } => {
+            let mut artifacts = find_artifacts(pyflow_path, python_path);
+            if let Some(name) = package {
+                artifacts.retain(|a| {
+                    a.package
+                        .as_ref()
+                        .is_some_and(|p| util::compare_names(p, name))
+                });
+            }
+            if *json {
+                print_json(&artifacts);
+            } else {
+                print_list(&artifacts);
+            }
+        }
+        CacheCommand::Size { json } => {
+            let sizes = summarize(&find_artifacts(pyflow_path, python_path));
+            if *json {
+                print_json(&sizes);
+            } else {
+                for s in &sizes {
+                    print_color_(&format!("{:<12}", format!("{:?}", s.category)), Color::Cyan);
+                    println!("{:>10}  ({} items)", util::format_size(s.size), s.count);
+                }
+                let total: u64 = sizes.iter().map(|s| s.size).sum();
+                print_color(
+                    &format!("{:<12}{:>10}", "Total", util::format_size(total)),
+                    Color::Blue,
+                );
+            }
+        }
+    }
+}
+
+fn print_list(artifacts: &[Artifact]) {
+    if artifacts.is_empty() {
+        print_color("Nothing is cached.", Color::Blue);
+        return;
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+
+    for a in artifacts {
+        let name = match (&a.package, &a.version) {
+            (Some(p), Some(v)) => format!("{} {}", p, v),
+            _ => a
+                .path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        };
+        print_color_(&name, Color::Cyan);
+        let used = a
+            .last_access
+            .map(|t| format!(", used {}", age(now.saturating_sub(t))))
+            .unwrap_or_default();
+        println!("  {:?}, {}{}", a.category, util::format_size(a.size), used);
+    }
+}
+
+/// Eg `3 days ago`.
+fn age(secs: u64) -> String {
+    let (n, unit) = match secs {
+        s if s < 60 * 60 => return "within the hour".into(),
+        s if s < 24 * 60 * 60 => (s / (60 * 60), "hour"),
+        s => (s / (24 * 60 * 60), "day"),
+    };
+    format!("{} {}{} ago", n, unit, if n == 1 { "" } else { "s" })
+}
+
+fn find_artifacts(pyflow_path: &Path, python_path: &Path) -> Vec<Artifact> {
+    let mut result = vec![];
+
+    if let Ok(entries) = fs::read_dir(paths::dep_cache_path(pyflow_path)) {
+        for entry in entries.flatten() {
+            let filename = entry.file_name().to_string_lossy().to_string();
+            let (category, parsed) = parse_filename(&filename);
+            let (package, version) = match parsed {
+                Some((p, v)) => (Some(p), Some(v)),
+                None => (None, None),
+            };
+            result.push(artifact(entry.path(), category, package, version));
+        }
+    }
+    if let Ok(entries) = fs::read_dir(paths::index_cache_path(pyflow_path)) {
+        for entry in entries.flatten() {
+            result.push(artifact(entry.path(), Category::Metadata, None, None));
+        }
+    }
+    for install in python_installs(python_path) {
+        // Eg `python-3.12.3`, or `pypy-3.10`.
+        let name = install
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let (package, version) = match name.split_once('-') {
+            Some((p, v)) => (Some(p.to_owned()), Some(v.to_owned())),
+            None => (None, None),
+        };
+        result.push(artifact(install, Category::Interpreter, package, version));
+    }
+
+    result.sort_by(|a, b| (&a.package, &a.version).cmp(&(&b.package, &b.version)));
+    result
+}
+
+fn artifact(
+    path: PathBuf,
+    category: Category,
+    package: Option<String>,
+    version: Option<String>,
+) -> Artifact {
+    let last_access = fs::metadata(&path)
+        .and_then(|m| m.accessed().or_else(|_| m.modified()))
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs());
+    Artifact {
+        size: util::dir_size(&path),
+        path,
+        category,
+        package,
+        version,
+        last_access,
+    }
+}
+
+/// Get the category, package name, and version from a downloaded file's name, eg
+/// `requests-2.31.0-py3-none-any.whl`, or `numpy-1.26.0.tar.gz`.
+fn parse_filename(filename: &str) -> (Category, Option<(String, String)>) {
+    if let Some(stem) = filename.strip_suffix(".whl") {
+        let mut parts = stem.split('-');
+        return match (parts.next(), parts.next()) {
+            (Some(name), Some(version)) => {
+                (Category::Wheel, Some((name.to_owned(), version.to_owned())))
+            }
+            _ => (Category::Wheel, None),
+        };
+    }
+
+    let stem = [".tar.gz", ".tar.bz2", ".zip", ".tgz"]
+        .iter()
+        .find_map(|ext| filename.strip_suffix(ext));
+    match stem {
+        Some(stem) => (
+            Category::Sdist,
+            stem.rsplit_once('-')
+                .map(|(name, version)| (name.to_owned(), version.to_owned())),
+        ),
+        None => (Category::Other, None),
+    }
+}
+
+fn summarize(artifacts: &[Artifact]) -> Vec<CategorySize> {
+    let categories = [
+        Category::Wheel,
+        Category::Sdist,
+        Category::Metadata,
+        Category::Interpreter,
+        Category::Other,
+    ];
+    categories
+        .iter()
+        .map(|&category| {
+            let matching: Vec<&Artifact> = artifacts
+                .iter()
+                .filter(|a| a.category == category)
+                .collect();
+            CategorySize {
+                category,
+                count: matching.len(),
+                size: matching.iter().map(|a| a.size).sum(),
+            }
+        })
+        .filter(|s| s.count > 0)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filenames() {
+        assert_eq!(
+            parse_filename("requests-2.31.0-py3-none-any.whl"),
+            (Category::Wheel, Some(("requests".into(), "2.31.0".into())))
+        );
+        assert_eq!(
+            parse_filename("python-dateutil-2.8.2.tar.gz"),
+            (
+                Category::Sdist,
+                Some(("python-dateutil".into(), "2.8.2".into()))
+            )
+        );
+        assert_eq!(parse_filename("notes.txt"), (Category::Other, None));
+    }
+
+    #[test]
+    fn ages() {
+        assert_eq!(age(30), "within the hour");
+        assert_eq!(age(2 * 60 * 60), "2 hours ago");
+        assert_eq!(age(24 * 60 * 60), "1 day ago");
+    }
+}
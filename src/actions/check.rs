@@ -0,0 +1,844 @@
+//! `pyflow check`: look for ways the project, its lock, and its environment disagree, without
+//! changing anything. Each check has a code, used to label its findings, and to skip it.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+    sync::LazyLock,
+};
+
+use regex::Regex;
+use serde::Serialize;
+use termcolor::Color;
+
+use super::new::STDLIB_MODULES;
+use crate::{
+    commands,
+    dep_types::{GitRef, LockPackage, Req, Version},
+    files, py_versions,
+    pyproject::{
+        current, lock_deps_hash, workspace::Workspace, Config, EnvMode, CFG_FILENAME,
+        LOCK_CONSTRAINTS_KEY, LOCK_FILENAME, LOCK_HASH_KEY,
+    },
+    util::{self, abort, deps, hooks, print_color, print_color_},
+};
+
+/// Every check, in the order they run.
+const CHECKS: &[&str] = &[
+    "pyproject",
+    "lock",
+    "environment",
+    "python",
+    "path-deps",
+    "git-deps",
+    "scripts",
+    "user-site",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub(super) struct Finding {
+    pub(super) code: &'static str,
+    pub(super) severity: Severity,
+    pub(super) message: String,
+    /// What to do about it, if it isn't obvious from the message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) fix: Option<String>,
+}
+
+impl Finding {
+    pub(super) fn new(code: &'static str, severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            severity,
+            message: message.into(),
+            fix: None,
+        }
+    }
+
+    pub(super) fn with_fix(mut self, fix: impl Into<String>) -> Self {
+        self.fix = Some(fix.into());
+        self
+    }
+}
+
+/// Run every check not in `skip`, or just those in `only`, and report what they find. Exits with 1
+/// if anything is an error.
+pub fn check(skip: &[String], only: &[String], json: bool) {
+    if let Some(s) = skip
+        .iter()
+        .chain(only)
+        .find(|s| !CHECKS.contains(&s.as_str()))
+    {
+        abort(&format!(
+            "There's no check called `{}`. Checks are: {}",
+            s,
+            CHECKS.join(", ")
+        ))
+    }
+    let config_path = current::find_config_path()
+        .unwrap_or_else(|| abort("Can't find `pyproject.toml` here, or in a parent directory"));
+    let project_path = config_path.parent().unwrap().to_owned();
+    let enabled = |code: &str| {
+        !skip.iter().any(|s| s == code) && (only.is_empty() || only.iter().any(|s| s == code))
+    };
+
+    let mut findings = vec![];
+
+    // The other checks need a config, so parse it even if this one's skipped.
+    let mut config = match check_pyproject(&config_path) {
+        Ok(()) => Config::from_file(&config_path).unwrap_or_default(),
+        Err(e) => {
+            if enabled("pyproject") {
+                findings.push(Finding::new("pyproject", Severity::Error, e));
+            }
+            report(&findings, json)
+        }
+    };
+
+    // Workspace members share the root's lock, environment and Python version.
+    let shared_path = Workspace::find(&project_path).map_or(project_path.clone(), |ws| ws.root);
+    if let Some(root_config) = Config::from_file(&shared_path.join(CFG_FILENAME)) {
+        if let Some(v) = root_config.py_version {
+            config.py_version = Some(v);
+        }
+        config.env_mode = root_config.env_mode;
+    }
+
+    let lock_path = shared_path.join(LOCK_FILENAME);
+    let lock = util::read_lock(&lock_path).ok();
+    if enabled("lock") {
+        findings.extend(check_lock(&lock_path));
+    }
+
+    let vers_path = match config.env_mode {
+        EnvMode::Pypackages => find_env(
+            &current::pypackages_path(&shared_path, &config),
+            config.py_version.as_ref(),
+        ),
+        // The root stands in for a version folder; see `main`.
+        EnvMode::Venv => {
+            Some(shared_path.clone()).filter(|root| py_versions::venv_version(root).is_some())
+        }
+    };
+    if enabled("environment") {
+        match (&vers_path, &lock) {
+            (None, _) => findings.push(Finding::new(
+                "environment",
+                Severity::Warning,
+                match config.env_mode {
+                    EnvMode::Pypackages => {
+                        "There's no environment in `__pypackages__` for this project's Python \
+                         version; run `pyflow install` to create one"
+                    }
+                    EnvMode::Venv => {
+                        "There's no `.venv` for this project; run `pyflow install` to create one"
+                    }
+                },
+            )),
+            (Some(vers_path), Some(lock)) => {
+                let lock_packs = lock.package.clone().unwrap_or_default();
+                let main = deps::reachable_from(&lock_packs, &config.reqs);
+                let mut local: Vec<String> = config
+                    .reqs
+                    .iter()
+                    .chain(config.dev_reqs.iter())
+                    .filter(|r| r.path.is_some() || r.git.is_some())
+                    .map(|r| r.name.clone())
+                    .collect();
+                // The venv's own tools aren't extras, either.
+                if config.env_mode == EnvMode::Venv {
+                    local.extend(deps::VENV_PACKAGES.iter().map(|n| n.to_string()));
+                }
+                findings.extend(compare_env(
+                    &lock_packs,
+                    &main,
+                    &util::find_installed(&env_lib(vers_path, &config)),
+                    &local,
+                ));
+            }
+            // Without a lock, there's nothing to compare with; the lock check reports that.
+            (Some(_), None) => (),
+        }
+    }
+
+    if enabled("python") {
+        if let Some(vers_path) = &vers_path {
+            findings.extend(check_python(vers_path, &config));
+        }
+    }
+
+    let all_reqs: Vec<&Req> = config.reqs.iter().chain(config.dev_reqs.iter()).collect();
+    if enabled("path-deps") {
+        findings.extend(check_path_deps(&project_path, &all_reqs));
+    }
+    if enabled("git-deps") {
+        findings.extend(check_git_deps(&all_reqs));
+    }
+    if enabled("scripts") {
+        findings.extend(check_scripts(&project_path, &config, vers_path.as_deref()));
+    }
+    if enabled("user-site") {
+        findings.extend(check_user_site(
+            &project_path,
+            &config,
+            vers_path.as_deref(),
+        ));
+    }
+
+    report(&findings, json)
+}
+
+fn check_pyproject(config_path: &Path) -> Result<(), String> {
+    let data = util::loaded::read_to_string(config_path)
+        .map_err(|e| format!("Can't read `pyproject.toml`: {}", e))?;
+    toml::from_str::<files::Pyproject>(&data)
+        .map(|_| ())
+        .map_err(|e| format!("Problem parsing `pyproject.toml`: {}", e))
+}
+
+fn check_lock(lock_path: &Path) -> Option<Finding> {
+    if !lock_path.exists() {
+        return Some(Finding::new(
+            "lock",
+            Severity::Error,
+            "There's no `pyflow.lock`; run `pyflow install` to create it",
+        ));
+    }
+    let lock = match util::read_lock(lock_path) {
+        Ok(l) => l,
+        Err(e) => {
+            return Some(Finding::new(
+                "lock",
+                Severity::Error,
+                format!("Problem parsing `pyflow.lock`: {}", e),
+            ))
+        }
+    };
+    let install_files: Vec<PathBuf> = lock
+        .metadata
+        .get(LOCK_CONSTRAINTS_KEY)
+        .map(|files| files.lines().map(PathBuf::from).collect())
+        .unwrap_or_default();
+    match lock.metadata.get(LOCK_HASH_KEY) {
+        None => Some(Finding::new(
+            "lock",
+            Severity::Warning,
+            "`pyflow.lock` doesn't record which dependencies it was made from, so may be out of \
+             date; run `pyflow install` to update it",
+        )),
+        Some(hash)
+            if Some(hash) != lock_deps_hash(lock_path.parent()?, &install_files).as_ref() =>
+        {
+            Some(Finding::new(
+                "lock",
+                Severity::Error,
+                "The dependencies in `pyproject.toml`, or its constraints files, changed since \
+                 `pyflow.lock` was written; run `pyflow install` to update it",
+            ))
+        }
+        Some(_) => None,
+    }
+}
+
+/// The environment `pyflow` would use, if it exists: the one for `py_version`, or if that's
+/// not set, the only one.
+fn find_env(pypackages_path: &Path, py_version: Option<&Version>) -> Option<PathBuf> {
+    let venvs = util::find_venvs(pypackages_path);
+    let (major, minor) = match py_version {
+        Some(v) => *venvs
+            .iter()
+            .find(|(ma, mi)| v.major == Some(*ma) && v.minor == Some(*mi))?,
+        None if venvs.len() == 1 => venvs[0],
+        None => return None,
+    };
+    Some(pypackages_path.join(format!("{}.{}", major, minor)))
+}
+
+/// Where packages are installed in an environment we found.
+fn env_lib(vers_path: &Path, config: &Config) -> PathBuf {
+    match config.env_mode {
+        EnvMode::Pypackages => vers_path.join("lib"),
+        EnvMode::Venv => {
+            util::find_site_packages(vers_path).unwrap_or_else(|| vers_path.join(".venv"))
+        }
+    }
+}
+
+/// Compare what's installed with the lock. `main` is the locked packages needed without dev
+/// dependencies; others may be missing because of `--no-dev`. Path and git dependencies in
+/// `local` aren't locked, so aren't reported as extra.
+fn compare_env(
+    lock_packs: &[LockPackage],
+    main: &[LockPackage],
+    installed: &[(String, Version, Vec<String>)],
+    local: &[String],
+) -> Vec<Finding> {
+    let mut result = vec![];
+
+    // Renamed packages are installed under another name.
+    for lp in lock_packs.iter().filter(|lp| lp.rename.is_none()) {
+        let found = installed
+            .iter()
+            .find(|(name, _, _)| util::compare_names(name, &lp.name));
+        match found {
+            None if main.iter().any(|m| util::compare_names(&m.name, &lp.name)) => {
+                result.push(Finding::new(
+                    "environment",
+                    Severity::Error,
+                    format!("{} {} is locked, but not installed", lp.name, lp.version),
+                ))
+            }
+            None => result.push(Finding::new(
+                "environment",
+                Severity::Warning,
+                format!(
+                    "{} {} is locked, but not installed; it's only needed for development",
+                    lp.name, lp.version
+                ),
+            )),
+            Some((_, version, _)) => {
+                if Version::from_str(&lp.version).map_or(true, |v| v != *version) {
+                    result.push(Finding::new(
+                        "environment",
+                        Severity::Error,
+                        format!(
+                            "{} is locked at {}, but {} is installed",
+                            lp.name, lp.version, version
+                        ),
+                    ))
+                }
+            }
+        }
+    }
+
+    for (name, version, _) in installed {
+        let known = lock_packs
+            .iter()
+            .map(|lp| &lp.name)
+            .chain(local.iter())
+            .any(|n| util::compare_names(n, name));
+        if !known {
+            result.push(Finding::new(
+                "environment",
+                Severity::Warning,
+                format!("{} {} is installed, but isn't in the lock", name, version),
+            ))
+        }
+    }
+    result
+}
+
+fn check_python(vers_path: &Path, config: &Config) -> Vec<Finding> {
+    let mut result = vec![];
+    let version = match py_versions::venv_version(vers_path) {
+        Some(v) => v,
+        None => {
+            result.push(Finding::new(
+                "python",
+                Severity::Warning,
+                format!(
+                    "Can't tell which Python version {:?} uses; its `pyvenv.cfg` is missing",
+                    vers_path
+                ),
+            ));
+            return result;
+        }
+    };
+
+    if let Some(py_version) = &config.py_version {
+        if !py_versions::version_matches(&version, py_version) {
+            result.push(Finding::new(
+                "python",
+                Severity::Error,
+                format!(
+                    "The environment uses Python {}, but `py_version` is {}; run `pyflow switch` \
+                     to change it",
+                    version, py_version
+                ),
+            ))
+        }
+    }
+    let python_requires = py_versions::parse_python_requires(&config.python_requires);
+    if let Some(warning) = py_versions::check_python_requires(vers_path, &python_requires) {
+        result.push(Finding::new("python", Severity::Error, warning));
+    }
+    result
+}
+
+fn check_path_deps(project_path: &Path, reqs: &[&Req]) -> Vec<Finding> {
+    let mut result = vec![];
+    for req in reqs {
+        let path = match &req.path {
+            Some(p) => project_path.join(p),
+            None => continue,
+        };
+        if !path.exists() {
+            result.push(Finding::new(
+                "path-deps",
+                Severity::Error,
+                format!("{}'s path, {:?}, doesn't exist", req.name, path),
+            ));
+        } else if !path.join("setup.py").exists() && !path.join("pyproject.toml").exists() {
+            result.push(Finding::new(
+                "path-deps",
+                Severity::Warning,
+                format!(
+                    "{}'s path, {:?}, has no `setup.py` or `pyproject.toml`, so can't be \
+                     installed",
+                    req.name, path
+                ),
+            ));
+        }
+    }
+    result
+}
+
+/// Check that git dependencies' repos are reachable, and have the tag, branch, or rev asked
+/// for. Unreachable repos are only warnings, since we may be offline.
+fn check_git_deps(reqs: &[&Req]) -> Vec<Finding> {
+    let mut result = vec![];
+    for req in reqs {
+        let repo = match &req.git {
+            Some(r) => r,
+            None => continue,
+        };
+        match commands::git_ls_remote(repo) {
+            Ok(output) => {
+                let refs = parse_ls_remote(&output);
+                result.extend(git_ref_finding(&req.name, req.git_ref.as_ref(), &refs));
+            }
+            Err(e) => result.push(Finding::new(
+                "git-deps",
+                Severity::Warning,
+                format!("Can't reach {}'s repo, {}: {}", req.name, repo, e),
+            )),
+        }
+    }
+    result
+}
+
+/// Parse `git ls-remote` output into `(sha, ref)` pairs.
+fn parse_ls_remote(output: &str) -> Vec<(String, String)> {
+    output
+        .lines()
+        .filter_map(|l| {
+            let mut parts = l.split_whitespace();
+            Some((parts.next()?.to_owned(), parts.next()?.to_owned()))
+        })
+        .collect()
+}
+
+fn git_ref_finding(
+    name: &str,
+    git_ref: Option<&GitRef>,
+    refs: &[(String, String)],
+) -> Option<Finding> {
+    let has_ref = |r: String| refs.iter().any(|(_, ref_)| *ref_ == r);
+    match git_ref? {
+        GitRef::Tag(t) if !has_ref(format!("refs/tags/{}", t)) => Some(Finding::new(
+            "git-deps",
+            Severity::Error,
+            format!("{}'s repo has no tag `{}`", name, t),
+        )),
+        GitRef::Branch(b) if !has_ref(format!("refs/heads/{}", b)) => Some(Finding::new(
+            "git-deps",
+            Severity::Error,
+            format!("{}'s repo has no branch `{}`", name, b),
+        )),
+        GitRef::Rev(r)
+            if !refs.iter().any(|(sha, _)| sha.starts_with(r.as_str()))
+                && !has_ref(format!("refs/tags/{}", r))
+                && !has_ref(format!("refs/heads/{}", r)) =>
+        {
+            // The remote only lists the commits its refs point to; older ones need a clone.
+            Some(Finding::new(
+                "git-deps",
+                Severity::Info,
+                format!(
+                    "{}'s rev `{}` isn't the tip of a branch or tag, so can't be confirmed \
+                     without cloning",
+                    name, r
+                ),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Check that `module:function` scripts refer to a module we can find: in the project, its
+/// `package_dir`, path dependencies, or the environment.
+fn check_scripts(project_path: &Path, config: &Config, vers_path: Option<&Path>) -> Vec<Finding> {
+    let roots = import_roots(project_path, config, vers_path);
+
+    let mut scripts: Vec<(&String, &String)> = config.scripts.iter().collect();
+    scripts.sort();
+
+    let mut result = vec![];
+    for (name, target) in scripts {
+        if !hooks::is_function(target) {
+            continue;
+        }
+        let module = target.trim().split(':').next().unwrap();
+        let top = module.split('.').next().unwrap();
+        if STDLIB_MODULES.contains(&top) || roots.iter().any(|r| module_exists(r, top)) {
+            continue;
+        }
+        // Without an environment, it may be in a package that isn't installed yet.
+        let severity = if vers_path.is_some() {
+            Severity::Error
+        } else {
+            Severity::Warning
+        };
+        result.push(Finding::new(
+            "scripts",
+            severity,
+            format!(
+                "The script `{}` runs `{}`, but can't find the module `{}`",
+                name,
+                target.trim(),
+                top
+            ),
+        ));
+    }
+    result
+}
+
+/// Where the project's imports are found when it runs: the project, its `package_dir`, path
+/// dependencies, and the environment, if there is one.
+fn import_roots(project_path: &Path, config: &Config, vers_path: Option<&Path>) -> Vec<PathBuf> {
+    let mut roots = vec![project_path.to_owned()];
+    if let Some(dir) = &config.package_dir {
+        roots.push(project_path.join(dir));
+    }
+    roots.extend(
+        config
+            .reqs
+            .iter()
+            .chain(config.dev_reqs.iter())
+            .filter_map(|r| r.path.as_ref().map(|p| project_path.join(p))),
+    );
+    if let Some(vers_path) = vers_path {
+        roots.push(env_lib(vers_path, config));
+    }
+    roots
+}
+
+/// Look for modules the project imports that are only installed in the user `site-packages`,
+/// from `pip install --user`. Programs pyflow runs don't see it, so they're likely dependencies
+/// missing from `pyproject.toml`, that worked by accident before.
+fn check_user_site(project_path: &Path, config: &Config, vers_path: Option<&Path>) -> Vec<Finding> {
+    if config.allow_user_site {
+        return vec![];
+    }
+    let version = match vers_path
+        .and_then(py_versions::venv_version)
+        .or_else(|| config.py_version.clone())
+    {
+        Some(v) => v,
+        None => return vec![],
+    };
+    let user_site = match util::user_site_packages(&version).filter(|d| d.is_dir()) {
+        Some(d) => d,
+        None => return vec![],
+    };
+    let roots = import_roots(project_path, config, vers_path);
+
+    let mut files = vec![];
+    python_files(project_path, &mut files);
+    files.sort();
+
+    let mut seen = vec![];
+    let mut result = vec![];
+    for file in files {
+        let source = match fs::read_to_string(&file) {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+        for top in imported_modules(&source) {
+            if seen.contains(&top)
+                || STDLIB_MODULES.contains(&top.as_str())
+                || roots.iter().any(|r| module_exists(r, &top))
+                || !module_exists(&user_site, &top)
+            {
+                continue;
+            }
+            result.push(Finding::new(
+                "user-site",
+                Severity::Warning,
+                format!(
+                    "`{}` is imported in {}, but only installed in the user site-packages, {:?}, \
+                     which programs pyflow runs don't see. Add the package that provides it to \
+                     `pyproject.toml`",
+                    top,
+                    file.strip_prefix(project_path).unwrap_or(&file).display(),
+                    user_site
+                ),
+            ));
+            seen.push(top);
+        }
+    }
+    result
+}
+
+/// The project's `.py` files, leaving out environments, builds, and hidden folders.
+fn python_files(dir: &Path, result: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        if path.is_dir() {
+            let skipped = [
+                "__pypackages__",
+                "__pycache__",
+                "node_modules",
+                "build",
+                "dist",
+            ];
+            if !name.starts_with('.') && !skipped.contains(&name.as_str()) {
+                python_files(&path, result);
+            }
+        } else if name.ends_with(".py") {
+            result.push(path);
+        }
+    }
+}
+
+/// Eg `import numpy.linalg as la, os`: everything after `import`.
+static IMPORT_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\s*import\s+([^#]+)").unwrap());
+
+/// Eg `from numpy import array`: the top-level module.
+static FROM_IMPORT_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*from\s+([A-Za-z_]\w*)[\w.]*\s+import\b").unwrap());
+
+/// The top-level modules `source` imports absolutely, in order, eg `numpy` for
+/// `import numpy.linalg as la`, or `from numpy import array`.
+fn imported_modules(source: &str) -> Vec<String> {
+    let mut result: Vec<String> = vec![];
+    let mut add = |name: &str| {
+        if !name.is_empty() && !result.iter().any(|r| r == name) {
+            result.push(name.to_owned());
+        }
+    };
+    for line in source.lines() {
+        if let Some(caps) = FROM_IMPORT_RE.captures(line) {
+            add(&caps[1]);
+        } else if let Some(caps) = IMPORT_RE.captures(line) {
+            for part in caps[1].split(',') {
+                let name = part.split_whitespace().next().unwrap_or_default();
+                add(name.split('.').next().unwrap_or_default());
+            }
+        }
+    }
+    result
+}
+
+/// A top-level module in `root`: a package, a `.py` file, or an extension module.
+fn module_exists(root: &Path, top: &str) -> bool {
+    if root.join(top).is_dir() || root.join(format!("{}.py", top)).exists() {
+        return true;
+    }
+    let prefix = format!("{}.", top);
+    fs::read_dir(root).is_ok_and(|entries| {
+        entries.filter_map(Result::ok).any(|e| {
+            let file_name = e.file_name().to_string_lossy().into_owned();
+            file_name.starts_with(&prefix)
+                && (file_name.ends_with(".so") || file_name.ends_with(".pyd"))
+        })
+    })
+}
+
+fn report(findings: &[Finding], json: bool) -> ! {
+    let errors = findings
+        .iter()
+        .filter(|f| f.severity == Severity::Error)
+        .count();
+
+    if json {
+        util::print_json(&findings);
+    } else {
+        for finding in findings {
+            print_finding(finding, "");
+        }
+        print_summary(findings);
+    }
+
+    process::exit(if errors > 0 { 1 } else { 0 })
+}
+
+/// One finding, on a line starting with `indent`, and its fix on the next.
+pub(super) fn print_finding(finding: &Finding, indent: &str) {
+    let (label, color) = match finding.severity {
+        Severity::Error => ("error", Color::Red),
+        Severity::Warning => ("warning", Color::Yellow),
+        Severity::Info => ("info", Color::Cyan),
+    };
+    print!("{}", indent);
+    print_color_(&format!("{:<8}", label), color);
+    println!("[{}] {}", finding.code, finding.message);
+    if let Some(fix) = &finding.fix {
+        println!("{}{:<8}{}", indent, "", fix);
+    }
+}
+
+/// How many errors and warnings there are, or that there aren't any.
+pub(super) fn print_summary(findings: &[Finding]) {
+    let count = |severity| findings.iter().filter(|f| f.severity == severity).count();
+    let (errors, warnings) = (count(Severity::Error), count(Severity::Warning));
+    if findings.is_empty() {
+        print_color("No problems found", Color::Green);
+    } else {
+        print_color(
+            &format!("{} errors, {} warnings", errors, warnings),
+            if errors > 0 {
+                Color::Red
+            } else {
+                Color::Yellow
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pyproject::lock_metadata, util::temp_dir::TempDir, Lock};
+
+    fn lock_pack(name: &str, version: &str) -> LockPackage {
+        LockPackage {
+            id: 0,
+            name: name.into(),
+            version: version.into(),
+            source: None,
+            dependencies: None,
+            rename: None,
+        }
+    }
+
+    #[test]
+    fn env_against_lock() {
+        let lock = vec![
+            lock_pack("requests", "2.31.0"),
+            lock_pack("idna", "3.6"),
+            lock_pack("pytest", "8.0.0"),
+        ];
+        let main = lock[..2].to_vec();
+        let installed = vec![
+            ("requests".to_owned(), Version::new(2, 31, 0), vec![]),
+            ("idna".to_owned(), Version::new(3, 4, 0), vec![]),
+            ("mylib".to_owned(), Version::new(0, 1, 0), vec![]),
+            ("stray".to_owned(), Version::new(1, 0, 0), vec![]),
+        ];
+        let findings = compare_env(&lock, &main, &installed, &["mylib".to_owned()]);
+        let found: Vec<(Severity, &str)> = findings
+            .iter()
+            .map(|f| (f.severity, f.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    Severity::Error,
+                    "idna is locked at 3.6, but 3.4.0 is installed"
+                ),
+                (
+                    Severity::Warning,
+                    "pytest 8.0.0 is locked, but not installed; it's only needed for development"
+                ),
+                (
+                    Severity::Warning,
+                    "stray 1.0.0 is installed, but isn't in the lock"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn git_refs() {
+        let refs = parse_ls_remote(
+            "a1b2c3d4e5\tHEAD\na1b2c3d4e5\trefs/heads/main\nf6e5d4c3b2\trefs/tags/v1.2\n",
+        );
+        assert_eq!(refs.len(), 3);
+
+        let tag = |t: &str| GitRef::Tag(t.into());
+        assert_eq!(git_ref_finding("mylib", Some(&tag("v1.2")), &refs), None);
+        assert_eq!(
+            git_ref_finding("mylib", Some(&tag("v2.0")), &refs).map(|f| f.severity),
+            Some(Severity::Error)
+        );
+        assert_eq!(
+            git_ref_finding("mylib", Some(&GitRef::Branch("dev".into())), &refs)
+                .map(|f| f.severity),
+            Some(Severity::Error)
+        );
+        assert_eq!(
+            git_ref_finding("mylib", Some(&GitRef::Rev("a1b2c3".into())), &refs),
+            None
+        );
+        assert_eq!(
+            git_ref_finding("mylib", Some(&GitRef::Rev("0badc0de".into())), &refs)
+                .map(|f| f.severity),
+            Some(Severity::Info)
+        );
+        assert_eq!(git_ref_finding("mylib", None, &refs), None);
+    }
+
+    #[test]
+    fn json_findings() {
+        let finding = Finding::new("lock", Severity::Warning, "Out of date");
+        assert_eq!(
+            serde_json::to_string(&finding).unwrap(),
+            r#"{"code":"lock","severity":"warning","message":"Out of date"}"#
+        );
+    }
+
+    #[test]
+    fn stale_constraints() {
+        let dir = TempDir::new("check-constraints");
+        fs::write(
+            dir.join(CFG_FILENAME),
+            "[tool.pyflow]\nname = \"app\"\nconstraint-files = [\"constraints.txt\"]\n",
+        )
+        .unwrap();
+        fs::write(dir.join("constraints.txt"), "idna==3.6\n").unwrap();
+        fs::write(dir.join("pins.txt"), "urllib3<2\n").unwrap();
+        let lock_path = dir.join(LOCK_FILENAME);
+        let lock = Lock {
+            metadata: lock_metadata(&dir, &[dir.join("pins.txt")]),
+            package: None,
+        };
+        util::write_lock(&lock_path, &lock).unwrap();
+        assert_eq!(lock.metadata[LOCK_CONSTRAINTS_KEY], "pins.txt");
+        assert!(check_lock(&lock_path).is_none());
+
+        fs::write(dir.join("constraints.txt"), "idna==3.7\n").unwrap();
+        assert!(check_lock(&lock_path).is_some());
+        fs::write(dir.join("constraints.txt"), "idna==3.6\n").unwrap();
+        assert!(check_lock(&lock_path).is_none());
+        // An `install -c` file too.
+        fs::write(dir.join("pins.txt"), "urllib3<3\n").unwrap();
+        assert!(check_lock(&lock_path).is_some());
+    }
+
+    #[test]
+    fn imports() {
+        let source = "import os, numpy.linalg as la\nfrom requests.adapters import HTTPAdapter\n\
+                      from . import sibling\n    import yaml  # config\nimportant = 1\n";
+        assert_eq!(
+            imported_modules(source),
+            vec!["os", "numpy", "requests", "yaml"]
+        );
+    }
+}
@@ -0,0 +1,413 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use termcolor::Color;
+
+use crate::{
+    dep_types::Version,
+    pyproject::current,
+    util::{self, abort, paths, print_color, prompts::Fallback},
+};
+
+/// What `pyflow clean` removes; one field per flag.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CleanTargets {
+    pub cache: bool,
+    pub metadata: bool,
+    pub script_envs: bool,
+    pub pypackages: bool,
+    /// Only the environments under `__pypackages__` for Python versions the project no longer
+    /// uses.
+    pub pypackages_stale: bool,
+    pub pythons: bool,
+}
+
+impl CleanTargets {
+    pub fn all() -> Self {
+        Self {
+            cache: true,
+            metadata: true,
+            script_envs: true,
+            pypackages: true,
+            pypackages_stale: false,
+            pythons: true,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        !(self.cache
+            || self.metadata
+            || self.script_envs
+            || self.pypackages
+            || self.pypackages_stale
+            || self.pythons)
+    }
+}
+
+/// Remove pyflow's caches, script environments, downloaded interpreters, or this project's
+/// `__pypackages__`, and report the space freed. Only paths inside a directory pyflow owns
+/// are removed.
+pub fn clean(targets: CleanTargets, pyflow_path: &Path, python_path: &Path) {
+    if targets.is_empty() {
+        util::abort(
+            "Pick what to clean, eg `pyflow clean --cache`; see `pyflow clean --help`, or use \
+             `--all`",
+        )
+    }
+
+    let mut freed = 0;
+    // The caches may be relocated with `cache-dir`.
+    let cache_root = paths::cache_root(pyflow_path);
+    if targets.cache {
+        freed += clean_dir(
+            "Package cache",
+            &paths::dep_cache_path(pyflow_path),
+            &cache_root,
+        );
+    }
+    if targets.metadata {
+        freed += clean_dir(
+            "Index metadata cache",
+            &paths::index_cache_path(pyflow_path),
+            &cache_root,
+        );
+    }
+    if targets.script_envs {
+        freed += clean_dir(
+            "Script environments",
+            &paths::script_env_path(pyflow_path),
+            pyflow_path,
+        );
+    }
+    if targets.pypackages {
+        match current::find_config_path().and_then(|_| current::get_config()) {
+            // With `packages-dir` set, it may be outside the project.
+            Some(pcfg) => {
+                let owner = pcfg
+                    .pypackages_path
+                    .parent()
+                    .unwrap_or(&pcfg.project_path)
+                    .to_owned();
+                freed += clean_dir("`__pypackages__`", &pcfg.pypackages_path, &owner)
+            }
+            // Part of `--all`, which also makes sense outside a project.
+            None => print_color("Not in a project; skipping `__pypackages__`", Color::Yellow),
+        }
+    }
+    // `--pypackages` already removed these.
+    if targets.pypackages_stale && !targets.pypackages {
+        freed += clean_stale_pypackages();
+    }
+    if targets.pythons {
+        freed += clean_pythons(python_path);
+    }
+
+    util::success(&format!("Freed {}", util::format_size(freed)))
+}
+
+/// Remove environments for Python versions other than the project's `py_version`, or if it has
+/// none, other than the newest environment.
+fn clean_stale_pypackages() -> u64 {
+    if current::find_config_path().is_none() {
+        abort("Not in a project; there's no `__pypackages__` to clean")
+    }
+    let pcfg = match current::get_config() {
+        Some(c) => c,
+        None => return 0,
+    };
+    let keep = match &pcfg.config.py_version {
+        Some(Version {
+            major: Some(maj),
+            minor: Some(mi),
+            ..
+        }) => (*maj, *mi),
+        _ => match util::find_venvs(&pcfg.pypackages_path).last() {
+            Some(newest) => *newest,
+            None => {
+                println!("Stale `__pypackages__` environments: already clean");
+                return 0;
+            }
+        },
+    };
+    let stale = stale_envs(&pcfg.pypackages_path, keep);
+    if stale.is_empty() {
+        println!("Stale `__pypackages__` environments: already clean");
+    }
+    stale
+        .iter()
+        .map(|env| clean_dir("Stale environment", env, &pcfg.pypackages_path))
+        .sum()
+}
+
+/// Environments under `__pypackages__` for Python versions other than `keep`, eg `3.9` after
+/// switching to 3.11, including ones left partly created.
+pub fn stale_envs(pypackages_path: &Path, keep: (u32, u32)) -> Vec<PathBuf> {
+    let entries = match pypackages_path.read_dir() {
+        Ok(e) => e,
+        Err(_) => return vec![],
+    };
+    let mut result: Vec<PathBuf> = entries
+        .flatten()
+        .filter(|e| e.path().is_dir())
+        .filter(|e| {
+            e.file_name()
+                .to_str()
+                .and_then(env_version)
+                .is_some_and(|v| v != keep)
+        })
+        .map(|e| e.path())
+        .collect();
+    result.sort();
+    result
+}
+
+/// The Python version of an environment folder's name, eg `(3, 11)` from `3.11`.
+fn env_version(name: &str) -> Option<(u32, u32)> {
+    let mut parts = name.split('.');
+    let result = (parts.next()?.parse().ok()?, parts.next()?.parse().ok()?);
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(result)
+}
+
+/// On `install` or `run`, offer to remove environments left over from earlier Python versions;
+/// without a prompt, only warn about them.
+/// If the project's environment was just created while those exist, the project switched
+/// versions; say its dependencies need installing, and for `run`, confirm before installing them.
+pub fn check_stale_envs(pypackages_path: &Path, py_vers: &Version, fresh: bool, running: bool) {
+    let keep = match (py_vers.major, py_vers.minor) {
+        (Some(maj), Some(mi)) => (maj, mi),
+        _ => return,
+    };
+    let stale = stale_envs(pypackages_path, keep);
+    if stale.is_empty() {
+        return;
+    }
+
+    println!("These environments are for Python versions this project no longer uses:");
+    for env in &stale {
+        println!(
+            "  {} ({})",
+            env.display(),
+            util::format_size(util::dir_size(env))
+        );
+    }
+    // With `--non-interactive`, keep them; `clean --pypackages-stale` removes them explicitly.
+    if util::prompts::confirm("Remove them?", Fallback::Answer("no".into())) {
+        for env in &stale {
+            clean_dir("Stale environment", env, pypackages_path);
+        }
+    } else {
+        log::warn!("Run `pyflow clean --pypackages-stale` to remove them");
+    }
+
+    if fresh {
+        print_color(
+            &format!(
+                "Dependencies need installing for Python {}",
+                py_vers.to_string_med()
+            ),
+            Color::Yellow,
+        );
+        if running && !util::prompts::confirm("Install them now?", Fallback::Answer("yes".into())) {
+            abort("Run `pyflow install` to install them")
+        }
+    }
+}
+
+fn clean_pythons(python_path: &Path) -> u64 {
+    let installs = python_installs(python_path);
+    if installs.is_empty() {
+        println!("No downloaded Python interpreters");
+        return 0;
+    }
+
+    println!("These downloaded Python interpreters will be removed:");
+    for install in &installs {
+        println!("  {}", install.display());
+    }
+    // `--pythons` or `--all` already asks for this.
+    if !util::prompts::confirm("Remove them?", Fallback::Answer("yes".into())) {
+        return 0;
+    }
+
+    installs
+        .iter()
+        .map(|install| clean_dir("Python interpreter", install, python_path))
+        .sum()
+}
+
+/// Interpreters we've downloaded, and leftover partial downloads.
+pub fn python_installs(python_path: &Path) -> Vec<PathBuf> {
+    let entries = match python_path.read_dir() {
+        Ok(e) => e,
+        Err(_) => return vec![],
+    };
+    let mut result: Vec<PathBuf> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("python-") || n.starts_with("pypy-"))
+        })
+        .collect();
+    result.sort();
+    result
+}
+
+/// Remove `path`, if it's inside `owner`, and report its size.
+fn clean_dir(label: &str, path: &Path, owner: &Path) -> u64 {
+    if !path.exists() {
+        println!("{}: already clean", label);
+        return 0;
+    }
+    if !is_inside(path, owner) {
+        print_color(
+            &format!(
+                "{}: not removing {}, since it's outside {}",
+                label,
+                path.display(),
+                owner.display()
+            ),
+            Color::Red,
+        );
+        return 0;
+    }
+
+    let size = util::dir_size(path);
+    let removed = if path.is_dir() && !is_symlink(path) {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+    match removed {
+        Ok(_) => {
+            println!("{}: freed {}", label, util::format_size(size));
+            size
+        }
+        Err(e) => {
+            print_color(
+                &format!("{}: problem removing {}: {}", label, path.display(), e),
+                Color::Red,
+            );
+            0
+        }
+    }
+}
+
+fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink())
+}
+
+/// Whether `path` is strictly inside `owner`, after resolving `..` and symlinks in both. A
+/// symlink itself may be inside, even if it points elsewhere; we remove only the link.
+fn is_inside(path: &Path, owner: &Path) -> bool {
+    let owner = match owner.canonicalize() {
+        Ok(o) => o,
+        Err(_) => return false,
+    };
+    let resolved = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => match parent.canonicalize() {
+            Ok(p) => p.join(name),
+            Err(_) => return false,
+        },
+        _ => return false,
+    };
+    if !is_symlink(path) {
+        match resolved.canonicalize() {
+            Ok(r) => return r != owner && r.starts_with(&owner),
+            Err(_) => return false,
+        }
+    }
+    resolved != owner && resolved.starts_with(&owner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::temp_dir::TempDir;
+
+    #[test]
+    fn only_inside_owner() {
+        let root = TempDir::new("clean-owner");
+        let owner = root.join("pyflow");
+        let cache = owner.join("dependency_cache");
+        let outside = root.join("important");
+        fs::create_dir_all(&cache).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+
+        assert!(is_inside(&cache, &owner));
+        assert!(!is_inside(&owner, &owner));
+        assert!(!is_inside(&outside, &owner));
+        assert!(!is_inside(&cache.join("..").join(".."), &owner));
+        assert!(!is_inside(&owner.join("..").join("important"), &owner));
+    }
+
+    #[test]
+    fn refuses_outside_owner() {
+        let root = TempDir::new("clean-refuse");
+        let owner = root.join("pyflow");
+        let outside = root.join("important");
+        fs::create_dir_all(&owner).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("data.txt"), "keep me").unwrap();
+
+        assert_eq!(clean_dir("Test", &outside, &owner), 0);
+        assert!(outside.join("data.txt").exists());
+        assert_eq!(
+            clean_dir("Test", &owner.join("..").join("important"), &owner),
+            0
+        );
+        assert!(outside.join("data.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_removes_only_link() {
+        let root = TempDir::new("clean-symlink");
+        let owner = root.join("pyflow");
+        let outside = root.join("important");
+        fs::create_dir_all(&owner).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("data.txt"), "keep me").unwrap();
+        let link = owner.join("script_envs");
+        std::os::unix::fs::symlink(&outside, &link).unwrap();
+
+        clean_dir("Test", &link, &owner);
+        assert!(!link.exists());
+        assert!(outside.join("data.txt").exists());
+    }
+
+    #[test]
+    fn finds_stale_envs() {
+        let root = TempDir::new("clean-stale");
+        for name in &["3.9", "3.11", "3.12-old", "lib"] {
+            fs::create_dir_all(root.join(name)).unwrap();
+        }
+        fs::write(root.join("3.10"), "not an environment").unwrap();
+
+        assert_eq!(stale_envs(&root, (3, 11)), vec![root.join("3.9")]);
+        assert_eq!(
+            stale_envs(&root, (3, 12)),
+            vec![root.join("3.11"), root.join("3.9")]
+        );
+        assert_eq!(env_version("3.11"), Some((3, 11)));
+        assert_eq!(env_version("3.11.2"), None);
+    }
+
+    #[test]
+    fn removes_and_measures() {
+        let root = TempDir::new("clean-measure");
+        let cache = root.join("dependency_cache");
+        fs::create_dir_all(cache.join("nested")).unwrap();
+        fs::write(cache.join("a.whl"), vec![0; 1000]).unwrap();
+        fs::write(cache.join("nested").join("b.whl"), vec![0; 24]).unwrap();
+
+        assert_eq!(clean_dir("Test", &cache, &root), 1024);
+        assert!(!cache.exists());
+    }
+}
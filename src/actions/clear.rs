@@ -1,6 +1,6 @@
-use std::{fs, path::Path};
+use std::{fmt, fs, path::Path};
 
-use crate::util::{self, abort, success};
+use crate::util::{self, abort, prompts::Fallback, success};
 
 #[derive(Clone)]
 enum ClearChoice {
@@ -11,9 +11,9 @@ enum ClearChoice {
     All,
 }
 
-impl ToString for ClearChoice {
-    fn to_string(&self) -> String {
-        "".into()
+impl fmt::Display for ClearChoice {
+    fn fmt(&self, _f: &mut fmt::Formatter) -> fmt::Result {
+        Ok(())
     }
 }
 
@@ -32,6 +32,9 @@ pub fn clear(pyflow_path: &Path, cache_path: &Path, script_env_path: &Path) {
             ("All of the above".into(), ClearChoice::All),
         ],
         false,
+        Fallback::Fail(
+            "Use `pyflow clean` with `--cache`, `--script-envs`, `--pythons`, or `--all` instead.",
+        ),
     );
 
     // TODO: DRY
@@ -0,0 +1,406 @@
+//! `pyflow hooks`: run `pyflow check` before each commit, to catch an invalid `pyproject.toml`,
+//! or a lock that doesn't match it. Hooks go in `.pre-commit-config.yaml`, as a `local` repo, or
+//! without pre-commit, in `.git/hooks/pre-commit`. Either way, they call the pyflow that installed
+//! them.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use termcolor::Color;
+
+use crate::{
+    cli_options::HooksCommand,
+    pyproject::current,
+    util::{self, abort},
+};
+
+const PRE_COMMIT_CONFIG: &str = ".pre-commit-config.yaml";
+/// Around our entries in `.pre-commit-config.yaml`, so we can update or remove them.
+const BEGIN: &str = "# pyflow hooks: begin; `pyflow hooks uninstall` removes these";
+const END: &str = "# pyflow hooks: end";
+/// Identifies a `.git/hooks/pre-commit` we wrote.
+const GIT_HOOK_MARKER: &str = "# Installed by `pyflow hooks install`";
+/// Where a git hook we replaced goes; ours runs it first.
+const PREVIOUS_HOOK: &str = "pre-commit.pyflow-previous";
+
+pub fn hooks(cmd: &HooksCommand) {
+    let config_path = current::find_config_path()
+        .unwrap_or_else(|| abort("Can't find `pyproject.toml` here, or in a parent directory"));
+    let project_path = config_path.parent().unwrap().to_owned();
+    let repo = project_path
+        .ancestors()
+        .find(|p| p.join(".git").exists())
+        .unwrap_or_else(|| abort("This project isn't in a git repository"))
+        .to_owned();
+    let subdir = project_path
+        .strip_prefix(&repo)
+        .unwrap_or_else(|_| Path::new(""))
+        .to_string_lossy()
+        .replace('\\', "/");
+    let pre_commit_path = repo.join(PRE_COMMIT_CONFIG);
+    let hook_path = hooks_dir(&repo).join("pre-commit");
+
+    match cmd {
+        HooksCommand::Install { git } => {
+            let pyflow = env::current_exe()
+                .and_then(fs::canonicalize)
+                .unwrap_or_else(|_| abort("Can't find pyflow's own executable"));
+            let pyflow = pyflow.to_string_lossy();
+            let pre_commit =
+                pre_commit_path.exists() || !util::find_on_path("pre-commit").is_empty();
+            if *git || !pre_commit {
+                install_git_hook(&hook_path, &pyflow, &subdir);
+                util::success(&format!("Added a hook to {}", hook_path.to_string_lossy()));
+            } else {
+                let existing = fs::read_to_string(&pre_commit_path).unwrap_or_default();
+                let updated = merge_pre_commit(&existing, &pre_commit_block(&pyflow, &subdir));
+                if fs::write(&pre_commit_path, updated).is_err() {
+                    abort(&format!("Problem writing {}", PRE_COMMIT_CONFIG))
+                }
+                util::success(&format!(
+                    "Added hooks to {}; run `pre-commit install` if you haven't",
+                    PRE_COMMIT_CONFIG
+                ));
+            }
+        }
+        HooksCommand::Uninstall => {
+            let mut removed = false;
+            if let Ok(existing) = fs::read_to_string(&pre_commit_path) {
+                if let Some(remaining) = remove_pre_commit(&existing) {
+                    let result = if remaining.trim() == "repos:" {
+                        // Only our hooks were there.
+                        fs::remove_file(&pre_commit_path)
+                    } else {
+                        fs::write(&pre_commit_path, remaining)
+                    };
+                    if result.is_err() {
+                        abort(&format!("Problem writing {}", PRE_COMMIT_CONFIG))
+                    }
+                    removed = true;
+                }
+            }
+            removed |= uninstall_git_hook(&hook_path);
+            if removed {
+                util::success("Removed pyflow's hooks");
+            } else {
+                util::print_color("There are no pyflow hooks to remove", Color::Yellow);
+            }
+        }
+    }
+}
+
+/// Where git runs hooks from: `core.hooksPath` if it's set, or else the `hooks` folder of the
+/// repository's common git dir, which its worktrees share.
+fn hooks_dir(repo: &Path) -> PathBuf {
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args(args)
+            .current_dir(repo)
+            .output()
+            .ok()
+            .filter(|o| o.status.success())
+            .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_owned())
+            .filter(|o| !o.is_empty())
+    };
+    // Relative paths are from the working tree's root.
+    if let Some(path) = git(&["config", "--path", "--get", "core.hooksPath"]) {
+        return repo.join(path);
+    }
+    if let Some(dir) = git(&["rev-parse", "--git-common-dir"]) {
+        return repo.join(dir).join("hooks");
+    }
+
+    // Without git, follow a worktree's or submodule's `.git` file, and a worktree's `commondir`.
+    let dot_git = repo.join(".git");
+    let git_dir = match fs::read_to_string(&dot_git) {
+        Ok(data) => match data.trim().strip_prefix("gitdir:") {
+            Some(dir) => repo.join(dir.trim()),
+            None => dot_git,
+        },
+        Err(_) => dot_git,
+    };
+    match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(common) => git_dir.join(common.trim()).join("hooks"),
+        Err(_) => git_dir.join("hooks"),
+    }
+}
+
+/// Quote for `sh`, and pre-commit's `entry`, which is split the same way.
+fn sh_quote(s: &str) -> String {
+    if s.chars()
+        .all(|c| c.is_ascii_alphanumeric() || "/._-".contains(c))
+    {
+        s.to_owned()
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+}
+
+/// The command a hook runs: `pyflow check` with `args`, from the project's folder.
+fn check_command(pyflow: &str, subdir: &str, args: &str) -> String {
+    let cmd = format!("{} check {}", sh_quote(pyflow), args);
+    if subdir.is_empty() {
+        cmd
+    } else {
+        // Hooks run from the repository's root.
+        format!(
+            "sh -c {}",
+            sh_quote(&format!("cd {} && exec {}", sh_quote(subdir), cmd))
+        )
+    }
+}
+
+/// Our `local` repo for `.pre-commit-config.yaml`, unindented.
+fn pre_commit_block(pyflow: &str, subdir: &str) -> String {
+    // JSON strings are valid YAML, and need no thought about escaping.
+    let s = |v: &str| serde_json::to_string(v).unwrap();
+    format!(
+        "{begin}\n\
+         - repo: local\n  \
+           hooks:\n    \
+             - id: pyflow-check-config\n      \
+               name: {config_name}\n      \
+               entry: {config_entry}\n      \
+               language: system\n      \
+               files: {config_files}\n      \
+               pass_filenames: false\n    \
+             - id: pyflow-check-lock\n      \
+               name: {lock_name}\n      \
+               entry: {lock_entry}\n      \
+               language: system\n      \
+               files: {lock_files}\n      \
+               pass_filenames: false\n\
+         {end}\n",
+        begin = BEGIN,
+        end = END,
+        config_name = s("pyproject.toml is valid"),
+        config_entry = s(&check_command(pyflow, subdir, "--only pyproject")),
+        config_files = s(r"(^|/)pyproject\.toml$"),
+        lock_name = s("pyflow.lock matches pyproject.toml"),
+        lock_entry = s(&check_command(pyflow, subdir, "--only lock")),
+        lock_files = s(r"(^|/)(pyproject\.toml|pyflow\.lock)$"),
+    )
+}
+
+/// Add `block` to a `.pre-commit-config.yaml`'s `repos`, replacing ours if it's there, and
+/// indented like the repos already listed.
+fn merge_pre_commit(existing: &str, block: &str) -> String {
+    let existing = remove_pre_commit(existing).unwrap_or_else(|| existing.to_owned());
+    let lines: Vec<&str> = existing.lines().collect();
+    // Eg `repos:  # formatters`; the comment is kept.
+    let repos = lines.iter().enumerate().find_map(|(i, l)| {
+        let key = l.split(" #").next().unwrap_or_default().trim_end();
+        matches!(key, "repos:" | "repos: []").then_some((i, &l[key.len()..]))
+    });
+    let (i, comment) = match repos {
+        Some(r) => r,
+        None => {
+            let mut result = existing.clone();
+            if !result.is_empty() && !result.ends_with('\n') {
+                result.push('\n');
+            }
+            return result + "repos:\n" + block;
+        }
+    };
+    let indent: String = lines[i + 1..]
+        .iter()
+        .find(|l| l.trim_start().starts_with("- "))
+        .map_or("  ".to_owned(), |l| {
+            l[..l.len() - l.trim_start().len()].to_owned()
+        });
+
+    let mut result: Vec<String> = lines[..i].iter().map(|l| (*l).to_owned()).collect();
+    result.push(format!("repos:{}", comment));
+    result.extend(block.lines().map(|l| format!("{}{}", indent, l)));
+    result.extend(lines[i + 1..].iter().map(|l| (*l).to_owned()));
+    result.join("\n") + "\n"
+}
+
+/// The config without our entries, or `None` if they aren't there.
+fn remove_pre_commit(existing: &str) -> Option<String> {
+    let lines: Vec<&str> = existing.lines().collect();
+    let begin = lines.iter().position(|l| l.trim() == BEGIN)?;
+    let end = lines[begin..].iter().position(|l| l.trim() == END)? + begin;
+    let remaining: Vec<&str> = lines[..begin]
+        .iter()
+        .chain(&lines[end + 1..])
+        .copied()
+        .collect();
+    Some(remaining.join("\n") + "\n")
+}
+
+fn git_hook(pyflow: &str, subdir: &str) -> String {
+    let root = if subdir.is_empty() {
+        "\"$(git rev-parse --show-toplevel)\"".to_owned()
+    } else {
+        format!("\"$(git rev-parse --show-toplevel)\"/{}", sh_quote(subdir))
+    };
+    format!(
+        "#!/bin/sh\n\
+         {marker}; `pyflow hooks uninstall` removes it, restoring any hook it replaced.\n\
+         previous=\"$(dirname \"$0\")/{previous}\"\n\
+         if [ -x \"$previous\" ]; then\n    \
+             \"$previous\" \"$@\" || exit $?\n\
+         fi\n\
+         if git diff --cached --name-only | grep -Eq '(^|/)(pyproject\\.toml|pyflow\\.lock)$'; then\n    \
+             cd {root} && exec {pyflow} check --only pyproject,lock\n\
+         fi\n",
+        marker = GIT_HOOK_MARKER,
+        previous = PREVIOUS_HOOK,
+        root = root,
+        pyflow = sh_quote(pyflow),
+    )
+}
+
+/// Write our `pre-commit` hook, keeping any other one to run first.
+fn install_git_hook(hook_path: &Path, pyflow: &str, subdir: &str) {
+    let dir = hook_path.parent().unwrap();
+    if fs::create_dir_all(dir).is_err() {
+        abort(&format!("Problem creating {}", dir.to_string_lossy()))
+    }
+    let existing = fs::read_to_string(hook_path).ok();
+    if existing.is_some_and(|h| !h.contains(GIT_HOOK_MARKER)) {
+        let previous = dir.join(PREVIOUS_HOOK);
+        if previous.exists() {
+            abort(&format!(
+                "Both {} and {} exist; remove one, then try again",
+                hook_path.to_string_lossy(),
+                previous.to_string_lossy()
+            ))
+        }
+        if fs::rename(hook_path, &previous).is_err() {
+            abort("Problem moving the existing `pre-commit` hook aside")
+        }
+        util::print_color(
+            &format!(
+                "Your existing hook still runs first, from {}",
+                previous.to_string_lossy()
+            ),
+            Color::Yellow,
+        );
+    }
+    if fs::write(hook_path, git_hook(pyflow, subdir)).is_err() {
+        abort(&format!("Problem writing {}", hook_path.to_string_lossy()))
+    }
+    make_executable(hook_path);
+}
+
+/// Remove our `pre-commit` hook, putting back any we replaced. `false` if it isn't ours.
+fn uninstall_git_hook(hook_path: &Path) -> bool {
+    match fs::read_to_string(hook_path) {
+        Ok(h) if h.contains(GIT_HOOK_MARKER) => (),
+        _ => return false,
+    }
+    let previous = hook_path.with_file_name(PREVIOUS_HOOK);
+    let result = if previous.exists() {
+        fs::rename(&previous, hook_path)
+    } else {
+        fs::remove_file(hook_path)
+    };
+    if result.is_err() {
+        abort(&format!("Problem removing {}", hook_path.to_string_lossy()))
+    }
+    true
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap_or(());
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::temp_dir::TempDir;
+
+    #[test]
+    fn pre_commit_round_trip() {
+        let existing = "\
+repos:
+-   repo: https://github.com/psf/black
+    rev: 24.1.0
+    hooks:
+    -   id: black
+";
+        let block = pre_commit_block("/opt/pyflow/pyflow", "backend");
+        let merged = merge_pre_commit(existing, &block);
+        assert!(merged.starts_with(&format!("repos:\n{}\n- repo: local\n", BEGIN)));
+        assert!(merged.contains(
+            r#"entry: "sh -c 'cd backend && exec /opt/pyflow/pyflow check --only lock'""#
+        ));
+        assert!(merged.ends_with("    -   id: black\n"));
+
+        // Installing again replaces our entries, rather than adding more.
+        assert_eq!(merge_pre_commit(&merged, &block), merged);
+        assert_eq!(remove_pre_commit(&merged).unwrap(), existing);
+        assert_eq!(remove_pre_commit(existing), None);
+    }
+
+    #[test]
+    fn repos_with_comment() {
+        let existing = "repos:  # formatters\n- repo: https://github.com/psf/black\n";
+        let merged = merge_pre_commit(existing, &pre_commit_block("pyflow", ""));
+        assert_eq!(merged.matches("repos:").count(), 1);
+        assert!(merged.starts_with(&format!("repos:  # formatters\n{}\n", BEGIN)));
+
+        let merged = merge_pre_commit("repos: [] # none yet\n", &pre_commit_block("pyflow", ""));
+        assert!(merged.starts_with(&format!("repos: # none yet\n  {}\n", BEGIN)));
+    }
+
+    #[test]
+    fn hooks_dirs() {
+        if Command::new("git").arg("--version").output().is_err() {
+            return;
+        }
+        let dir = TempDir::new("hooks-dir");
+        let repo = dir.join("repo");
+        let git = |cwd: &Path, args: &[&str]| {
+            let status = Command::new("git")
+                .args(["-c", "user.name=a", "-c", "user.email=a@b.c"])
+                .args(args)
+                .current_dir(cwd)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {:?}", args);
+        };
+        fs::create_dir_all(&repo).unwrap();
+        git(&repo, &["init", "-q"]);
+        git(&repo, &["commit", "-q", "--allow-empty", "-m", "init"]);
+        git(&repo, &["worktree", "add", "-q", "../tree"]);
+        let hooks = repo.join(".git").join("hooks").canonicalize().unwrap();
+
+        // A worktree shares the main repository's hooks.
+        let tree = dir.join("tree");
+        assert_eq!(hooks_dir(&tree).canonicalize().unwrap(), hooks);
+
+        git(&repo, &["config", "core.hooksPath", ".githooks"]);
+        assert_eq!(hooks_dir(&repo), repo.join(".githooks"));
+        assert_eq!(hooks_dir(&tree), tree.join(".githooks"));
+    }
+
+    #[test]
+    fn git_hook_chains() {
+        let dir = TempDir::new("hooks");
+        let hook_path = dir.join("hooks/pre-commit");
+        fs::create_dir_all(hook_path.parent().unwrap()).unwrap();
+        fs::write(&hook_path, "#!/bin/sh\nmake lint\n").unwrap();
+
+        install_git_hook(&hook_path, "/usr/bin/pyflow", "");
+        let installed = fs::read_to_string(&hook_path).unwrap();
+        let previous = fs::read_to_string(dir.join("hooks").join(PREVIOUS_HOOK)).unwrap();
+        assert!(uninstall_git_hook(&hook_path));
+        let restored = fs::read_to_string(&hook_path).unwrap();
+
+        assert!(installed.contains("exec /usr/bin/pyflow check --only pyproject,lock"));
+        assert_eq!(previous, "#!/bin/sh\nmake lint\n");
+        assert_eq!(restored, previous);
+    }
+}
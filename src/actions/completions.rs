@@ -0,0 +1,211 @@
+use structopt::{clap::Shell, StructOpt};
+
+use crate::{
+    cli_options::Opt,
+    pyproject::{current, Config},
+};
+
+/// Called by the generated scripts for names that depend on the project; see `complete`.
+const COMPLETE_CMD: &str = "complete-names";
+
+/// Print a completion script for `shell`.
+pub fn completions(shell: Shell) {
+    print!("{}", generate(shell));
+}
+
+/// Print names for the completion scripts to offer: `packages` from `pyproject.toml`, or
+/// `scripts` declared there. Prints nothing outside a project.
+pub fn complete(kind: &str) {
+    let cfg = match current::find_config_path().and_then(|p| Config::from_file(&p)) {
+        Some(c) => c,
+        None => return,
+    };
+
+    let mut names: Vec<String> = match kind {
+        "packages" => cfg
+            .reqs
+            .iter()
+            .chain(cfg.dev_reqs.iter())
+            .map(|r| r.name.clone())
+            .collect(),
+        "scripts" => cfg
+            .scripts
+            .keys()
+            .chain(cfg.script_chains.keys())
+            .cloned()
+            .collect(),
+        _ => vec![],
+    };
+    names.sort();
+    names.dedup();
+    for name in names {
+        println!("{}", name);
+    }
+}
+
+/// The parser's own completions for subcommands and flags, with package names for
+/// `remove` and `update`, and script names for `run`, added where the shell allows.
+fn generate(shell: Shell) -> String {
+    let mut app = Opt::clap();
+    // The documentation-only entries, like `run `, end in a space, which clap can't complete.
+    for sc in &mut app.p.subcommands {
+        sc.p.meta.name = sc.p.meta.name.trim_end().to_owned();
+    }
+
+    let mut buf = vec![];
+    app.gen_completions_to("pyflow", shell, &mut buf);
+    let script = String::from_utf8(buf).unwrap_or_default();
+
+    match shell {
+        Shell::Bash => script + &bash_dynamic(),
+        Shell::Zsh => zsh_dynamic(&script),
+        Shell::Fish => script + &fish_dynamic(),
+        // These register a single completer, which we can't wrap.
+        Shell::PowerShell | Shell::Elvish => script,
+    }
+}
+
+fn bash_dynamic() -> String {
+    format!(
+        r#"
+_pyflow_dynamic() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    if [[ ${{COMP_CWORD}} -ge 2 ]]; then
+        case "${{COMP_WORDS[1]}}" in
+            remove|uninstall|update)
+                # Values for `--group` and `--within`, and flags, are the parser's.
+                case "${{COMP_WORDS[COMP_CWORD-1]}}" in
+                    --group|--within) ;;
+                    *)
+                        if [[ "${{cur}}" != -* ]]; then
+                            COMPREPLY=( $(compgen -W "$(pyflow {cmd} packages 2>/dev/null)" -- "${{cur}}") )
+                            return 0
+                        fi
+                        ;;
+                esac
+                ;;
+            run)
+                if [[ ${{COMP_CWORD}} -eq 2 ]]; then
+                    COMPREPLY=( $(compgen -W "$(pyflow {cmd} scripts 2>/dev/null)" -- "${{cur}}") )
+                    return 0
+                fi
+                ;;
+        esac
+    fi
+    _pyflow "$@"
+}}
+
+complete -F _pyflow_dynamic -o bashdefault -o default pyflow
+"#,
+        cmd = COMPLETE_CMD
+    )
+}
+
+/// Zsh runs the generated file as the `_pyflow` function, ending with a call to it; call ours
+/// instead.
+fn zsh_dynamic(script: &str) -> String {
+    let dynamic = format!(
+        r#"_pyflow_dynamic() {{
+    if (( CURRENT >= 3 )); then
+        case ${{words[2]}} in
+            remove|uninstall|update)
+                if [[ ${{words[CURRENT-1]}} != --(group|within) && ${{words[CURRENT]}} != -* ]]; then
+                    compadd -- ${{(f)"$(pyflow {cmd} packages 2>/dev/null)"}}
+                    return
+                fi
+                ;;
+            run)
+                if (( CURRENT == 3 )); then
+                    compadd -- ${{(f)"$(pyflow {cmd} scripts 2>/dev/null)"}}
+                    return
+                fi
+                ;;
+        esac
+    fi
+    _pyflow "$@"
+}}
+
+_pyflow_dynamic "$@"
+"#,
+        cmd = COMPLETE_CMD
+    );
+
+    let call = "_pyflow \"$@\"";
+    match script.trim_end().strip_suffix(call) {
+        Some(definitions) => format!("{}\n{}", definitions, dynamic),
+        None => script.to_owned(),
+    }
+}
+
+fn fish_dynamic() -> String {
+    format!(
+        r#"
+complete -c pyflow -n "__fish_seen_subcommand_from remove uninstall update" -f -a "(pyflow {cmd} packages 2>/dev/null)"
+complete -c pyflow -n "__fish_seen_subcommand_from run" -f -a "(pyflow {cmd} scripts 2>/dev/null)"
+"#,
+        cmd = COMPLETE_CMD
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        process::{Command, Stdio},
+    };
+
+    use super::*;
+
+    #[test]
+    fn bash_script_parses() {
+        let script = generate(Shell::Bash);
+        assert!(script.contains("_pyflow_dynamic"));
+
+        // Skip where bash isn't available.
+        let mut child = match Command::new("bash").arg("-n").stdin(Stdio::piped()).spawn() {
+            Ok(c) => c,
+            Err(_) => return,
+        };
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(script.as_bytes())
+            .unwrap();
+        assert!(child.wait().unwrap().success());
+    }
+
+    /// `update` completes package names, but not where `--within` takes a value.
+    #[test]
+    fn bash_completes_update() {
+        let complete = |words: &str| {
+            let script = format!(
+                "{}\npyflow() {{ printf 'idna\\nrequests\\n'; }}\n\
+                 COMP_WORDS=({}); COMP_CWORD=$(( ${{#COMP_WORDS[@]}} - 1 ))\n\
+                 _pyflow_dynamic; echo \"${{COMPREPLY[@]}}\"",
+                generate(Shell::Bash),
+                words
+            );
+            Command::new("bash")
+                .args(["-c", &script])
+                .output()
+                .ok()
+                .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_owned())
+        };
+        // Skip where bash isn't available.
+        if let Some(names) = complete("pyflow update re") {
+            assert_eq!(names, "requests");
+            assert_eq!(
+                complete("pyflow update --within ''").unwrap(),
+                "minor patch"
+            );
+            assert_eq!(complete("pyflow remove ''").unwrap(), "idna requests");
+        }
+    }
+
+    #[test]
+    fn zsh_calls_dynamic() {
+        let script = generate(Shell::Zsh);
+        assert!(script.trim_end().ends_with("_pyflow_dynamic \"$@\""));
+    }
+}
@@ -0,0 +1,98 @@
+use std::{fs, path::Path, process};
+
+use termcolor::Color;
+
+use crate::{
+    cli_options::ConfigCommand,
+    util::{
+        self, abort, paths, print_color, print_color_,
+        settings::{self, Layer, KEYS},
+    },
+};
+
+/// Show, or change, settings in the global config file. `cli` holds settings from flags, which
+/// take precedence over it.
+pub fn config(cmd: &ConfigCommand, cli: &Layer) {
+    match cmd {
+        ConfigCommand::Get { key } => {
+            check_key(key);
+            let (resolved, _) = settings::resolve_all(cli);
+            match resolved
+                .into_iter()
+                .find(|s| s.key.name == key.as_str())
+                .and_then(|s| s.value)
+            {
+                Some((value, _)) => println!("{}", value),
+                None => process::exit(1),
+            }
+        }
+        ConfigCommand::Set { key, value } => {
+            check_key(key);
+            if let Err(e) = settings::validate(key, value) {
+                abort(&e)
+            }
+            let path = paths::global_config_path();
+            set(&path, key, value);
+            util::success(&format!("Set `{}` to `{}` in {:?}", key, value, path));
+        }
+        ConfigCommand::List => list(cli),
+    }
+}
+
+fn check_key(key: &str) {
+    if settings::find_key(key).is_none() {
+        let names: Vec<&str> = KEYS.iter().map(|k| k.name).collect();
+        abort(&format!(
+            "There's no setting called `{}`. Settings are: {}",
+            key,
+            names.join(", ")
+        ))
+    }
+}
+
+/// Update one key, keeping the rest of the file.
+fn set(path: &Path, key: &str, value: &str) {
+    let mut table = match fs::read_to_string(path) {
+        Ok(data) => match data.parse::<toml::Value>() {
+            Ok(toml::Value::Table(t)) => t,
+            _ => abort(&format!(
+                "Problem parsing {:?}; fix or remove it, and try again",
+                path
+            )),
+        },
+        Err(_) => toml::value::Table::new(),
+    };
+
+    let value = match key {
+        "non-interactive" => toml::Value::Boolean(settings::parse_bool(value).unwrap_or(false)),
+        _ => toml::Value::String(value.to_owned()),
+    };
+    table.insert(key.to_owned(), value);
+
+    let written = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| {
+            let data = toml::to_string(&table).unwrap_or_default();
+            fs::write(path, data)
+        });
+    if written.is_err() {
+        abort(&format!("Problem writing {:?}", path))
+    }
+}
+
+fn list(cli: &Layer) {
+    let (resolved, _) = settings::resolve_all(cli);
+    print_color(
+        &format!("Global config: {:?}", paths::global_config_path()),
+        Color::Blue,
+    );
+    for setting in resolved {
+        print_color_(&format!("{:<16}", setting.key.name), Color::Cyan);
+        match setting.value {
+            Some((value, origin)) => println!("{:<32} ({})", value, origin),
+            None => println!("{}(not set)", " ".repeat(33)),
+        }
+        println!("    {}; env: {}", setting.key.about, setting.key.env);
+    }
+}
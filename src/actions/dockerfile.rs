@@ -0,0 +1,314 @@
+//! `pyflow dockerfile`: write a multi-stage Dockerfile for the project. The build stage installs
+//! pyflow and the locked dependencies, without dev ones; the final stage holds only the project
+//! and its environment, run by a non-root user, without pyflow.
+
+use std::{fs, path::Path, sync::LazyLock};
+
+use regex::Regex;
+use termcolor::Color;
+
+use crate::{
+    pyproject::{EnvMode, PresentConfig, CFG_FILENAME, LOCK_FILENAME},
+    util::{self, abort, hooks},
+};
+
+/// Where the project goes in the image.
+const APP_DIR: &str = "/app";
+
+/// The Python version at the start of an image tag, eg `3.11` in `3.11-slim`.
+static IMAGE_VERSION_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(\d+\.\d+)").unwrap());
+
+/// Write the Dockerfile to `output`, or stdout. `python` is the tag of the `python` base image,
+/// eg `3.11-slim`; by default, the project's `py_version`'s slim image. `entry` is the script
+/// the container runs.
+pub fn dockerfile(
+    pcfg: &PresentConfig,
+    python: Option<&str>,
+    entry: Option<&str>,
+    output: Option<&Path>,
+    force: bool,
+) {
+    let cfg = &pcfg.config;
+    let tag = match (python, &cfg.py_version) {
+        (Some(t), _) => t.to_owned(),
+        (None, Some(v)) => format!("{}-slim", v.to_string_med()),
+        (None, None) => abort(
+            "Can't tell which Python image to use; set `py_version` in `pyproject.toml`, or \
+             pass eg `--python 3.11-slim`",
+        ),
+    };
+    let py_vers = match IMAGE_VERSION_RE.captures(&tag) {
+        Some(caps) => caps[1].to_owned(),
+        None => match &cfg.py_version {
+            Some(v) => v.to_string_med(),
+            None => abort(&format!(
+                "Can't tell the Python version of the `python:{}` image; set `py_version` in \
+                 `pyproject.toml`",
+                tag
+            )),
+        },
+    };
+
+    // The environment must be inside the project, to be copied with it.
+    let env_dir = match cfg.env_mode {
+        EnvMode::Pypackages => match pcfg.pypackages_path.strip_prefix(&pcfg.project_path) {
+            Ok(rel) => format!("{}/{}", rel.to_string_lossy().replace('\\', "/"), py_vers),
+            Err(_) => abort(
+                "`packages-dir` puts the environment outside the project, so it can't be \
+                 copied into the image",
+            ),
+        },
+        EnvMode::Venv => ".venv".to_owned(),
+    };
+
+    let entry = entry.map(str::to_owned).or_else(|| {
+        // With one script, it's the obvious one to run.
+        match (cfg.scripts.len(), cfg.script_chains.is_empty()) {
+            (1, true) => cfg.scripts.keys().next().cloned(),
+            _ => None,
+        }
+    });
+    let cmd = match &entry {
+        Some(name) => command(cfg, name),
+        None => {
+            util::print_color(
+                "No `--entry` script given, so the container runs `python`",
+                Color::Yellow,
+            );
+            vec!["python".to_owned()]
+        }
+    };
+
+    let has_lock = pcfg.lock_path.exists();
+    if !has_lock {
+        util::print_color(
+            &format!(
+                "There's no `{}`, so the image resolves dependencies when it's built; run \
+                 `pyflow install` first to pin them",
+                LOCK_FILENAME
+            ),
+            Color::Yellow,
+        );
+    }
+    let env_root = env_dir.split('/').next().unwrap_or_default().to_owned();
+    let ignored = fs::read_to_string(pcfg.project_path.join(".dockerignore"))
+        .is_ok_and(|d| d.lines().any(|l| l.trim().trim_matches('/') == env_root));
+    if !ignored {
+        util::print_color(
+            &format!(
+                "Add `{}` to `.dockerignore`, so your local environment isn't copied over the \
+                 one built in the image",
+                env_root
+            ),
+            Color::Yellow,
+        );
+    }
+
+    let rendered = render(&Dockerfile {
+        tag: &tag,
+        env_dir: &env_dir,
+        env_mode: cfg.env_mode,
+        has_lock,
+        path_deps: cfg.reqs.iter().any(|r| r.path.is_some()),
+        env: sorted_env(pcfg, entry.as_deref()),
+        cmd: &cmd,
+    });
+
+    match output {
+        Some(path) => {
+            if path.exists() && !force {
+                abort(&format!(
+                    "{} already exists; pass `--force` to overwrite it",
+                    path.to_string_lossy()
+                ))
+            }
+            if fs::write(path, rendered).is_err() {
+                abort(&format!("Problem writing {}", path.to_string_lossy()))
+            }
+            util::success(&format!("Wrote {}", path.to_string_lossy()));
+        }
+        None => print!("{}", rendered),
+    }
+}
+
+/// The `CMD` for a script, run as `pyflow run` would: a `module:function` with the project's
+/// Python, a shell command, or a console script installed by a package.
+fn command(cfg: &crate::Config, name: &str) -> Vec<String> {
+    if cfg.script_chains.contains_key(name) {
+        abort(&format!(
+            "`{}` runs other scripts in order, which a container's `CMD` can't; pick one of them \
+             with `--entry`",
+            name
+        ))
+    }
+    match cfg.scripts.get(name) {
+        Some(script) if hooks::is_function(script) => {
+            let (module, function) = script.split_at(script.find(':').unwrap());
+            vec![
+                "python".to_owned(),
+                "-c".to_owned(),
+                format!("import {}; {}.{}()", module, module, &function[1..]),
+            ]
+        }
+        Some(script) => vec!["sh".to_owned(), "-c".to_owned(), script.clone()],
+        None => vec![name.to_owned()],
+    }
+}
+
+/// The names in `[tool.pyflow.env]`, and the entry script's own `env`, sorted, so output is
+/// stable. Only names: values may be secrets, which don't belong in an image.
+fn sorted_env(pcfg: &PresentConfig, entry: Option<&str>) -> Vec<String> {
+    let cfg = &pcfg.config;
+    let mut env: Vec<String> = cfg.env.keys().cloned().collect();
+    if let Some(script_env) = entry.and_then(|e| cfg.script_env.get(e)) {
+        env.extend(script_env.keys().cloned());
+    }
+    env.sort();
+    env.dedup();
+    env
+}
+
+struct Dockerfile<'a> {
+    tag: &'a str,
+    env_dir: &'a str,
+    env_mode: EnvMode,
+    has_lock: bool,
+    path_deps: bool,
+    env: Vec<String>,
+    cmd: &'a [String],
+}
+
+/// A JSON string, as the exec form of `CMD` takes.
+fn quote(s: &str) -> String {
+    serde_json::to_string(s).unwrap_or_else(|_| abort("Problem writing the Dockerfile"))
+}
+
+fn render(d: &Dockerfile) -> String {
+    let mut result = format!(
+        "# Generated by `pyflow dockerfile`.\n\
+         FROM python:{tag} AS build\n\
+         RUN pip install --no-cache-dir pyflow\n\
+         WORKDIR {app}\n",
+        tag = d.tag,
+        app = APP_DIR
+    );
+    let install = "RUN pyflow -y install --no-dev\n";
+    if d.path_deps {
+        // Path dependencies are installed from the source, so it's needed first.
+        result.push_str("COPY . .\n");
+        result.push_str(install);
+    } else {
+        result.push_str("# Dependencies first, so this layer is only rebuilt when they change.\n");
+        if d.has_lock {
+            result.push_str(&format!("COPY {} {} ./\n", CFG_FILENAME, LOCK_FILENAME));
+        } else {
+            result.push_str(&format!("COPY {} ./\n", CFG_FILENAME));
+        }
+        result.push_str(install);
+        result.push_str("COPY . .\n");
+    }
+
+    let env_path = format!("{}/{}", APP_DIR, d.env_dir);
+    let path = match d.env_mode {
+        EnvMode::Pypackages => format!("{0}/.venv/bin:{0}/bin:$PATH", env_path),
+        EnvMode::Venv => format!("{}/bin:$PATH", env_path),
+    };
+    // Alpine images have BusyBox's `adduser`, not `useradd`.
+    let add_user = if d.tag.contains("alpine") {
+        "adduser -D app"
+    } else {
+        "useradd --create-home app"
+    };
+    result.push_str(&format!(
+        "\nFROM python:{tag}\n\
+         RUN {add_user}\n\
+         WORKDIR {app}\n\
+         COPY --from=build --chown=app:app {app} {app}\n\
+         ENV PATH=\"{path}\"\n",
+        tag = d.tag,
+        add_user = add_user,
+        app = APP_DIR,
+        path = path
+    ));
+    if d.env_mode == EnvMode::Pypackages {
+        result.push_str(&format!("ENV PYTHONPATH=\"{}/lib\"\n", env_path));
+    }
+    if !d.env.is_empty() {
+        result.push_str(&format!(
+            "# Set when running, eg with `docker run --env-file`: {}\n",
+            d.env.join(", ")
+        ));
+    }
+    let cmd: Vec<String> = d.cmd.iter().map(|c| quote(c)).collect();
+    result.push_str(&format!("USER app\nCMD [{}]\n", cmd.join(", ")));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    #[test]
+    fn multi_stage() {
+        let mut cfg = Config::default();
+        cfg.scripts.insert("serve".into(), "app.main:serve".into());
+        cfg.scripts
+            .insert("migrate".into(), "alembic upgrade head".into());
+        assert_eq!(
+            command(&cfg, "serve"),
+            vec!["python", "-c", "import app.main; app.main.serve()"]
+        );
+        assert_eq!(
+            command(&cfg, "migrate"),
+            vec!["sh", "-c", "alembic upgrade head"]
+        );
+        assert_eq!(command(&cfg, "uvicorn"), vec!["uvicorn"]);
+
+        let rendered = render(&Dockerfile {
+            tag: "3.11-slim",
+            env_dir: "__pypackages__/3.11",
+            env_mode: EnvMode::Pypackages,
+            has_lock: true,
+            path_deps: false,
+            env: vec!["DATABASE_URL".into(), "PORT".into()],
+            cmd: &command(&cfg, "serve"),
+        });
+        assert_eq!(
+            rendered,
+            "# Generated by `pyflow dockerfile`.\n\
+             FROM python:3.11-slim AS build\n\
+             RUN pip install --no-cache-dir pyflow\n\
+             WORKDIR /app\n\
+             # Dependencies first, so this layer is only rebuilt when they change.\n\
+             COPY pyproject.toml pyflow.lock ./\n\
+             RUN pyflow -y install --no-dev\n\
+             COPY . .\n\
+             \n\
+             FROM python:3.11-slim\n\
+             RUN useradd --create-home app\n\
+             WORKDIR /app\n\
+             COPY --from=build --chown=app:app /app /app\n\
+             ENV PATH=\"/app/__pypackages__/3.11/.venv/bin:/app/__pypackages__/3.11/bin:$PATH\"\n\
+             ENV PYTHONPATH=\"/app/__pypackages__/3.11/lib\"\n\
+             # Set when running, eg with `docker run --env-file`: DATABASE_URL, PORT\n\
+             USER app\n\
+             CMD [\"python\", \"-c\", \"import app.main; app.main.serve()\"]\n"
+        );
+    }
+    #[test]
+    fn alpine_user() {
+        let rendered = render(&Dockerfile {
+            tag: "3.12-alpine",
+            env_dir: ".venv",
+            env_mode: EnvMode::Venv,
+            has_lock: true,
+            path_deps: false,
+            env: vec![],
+            cmd: &["python".to_owned()],
+        });
+        assert!(rendered.contains("RUN adduser -D app\n"));
+        assert!(!rendered.contains("useradd"));
+        assert!(!rendered.contains("--env-file"));
+    }
+}
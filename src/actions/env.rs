@@ -0,0 +1,441 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+use termcolor::Color;
+
+use crate::{
+    cli_options::{EnvCommand, EvalShell},
+    dep_types::Version,
+    py_versions,
+    pyproject::{EnvMode, PresentConfig},
+    util::{
+        self, print_color, print_color_,
+        settings::{self, Layer},
+        Paths,
+    },
+    CliConfig,
+};
+
+/// A setting's effective value, and where it came from.
+#[derive(Debug, Serialize)]
+struct Sourced {
+    value: String,
+    origin: String,
+}
+
+#[derive(Debug, Serialize)]
+struct EnvInfo {
+    project: PathBuf,
+    workspace_root: Option<PathBuf>,
+    python: PathBuf,
+    python_version: String,
+    implementation: String,
+    pypackages: PathBuf,
+    environment: PathBuf,
+    lock: PathBuf,
+    lock_exists: bool,
+    cache_dir: PathBuf,
+    index_url: Sourced,
+    proxy: Option<Sourced>,
+    ca_certificates: String,
+    /// The `PYTHONPATH` `pyflow run` sets, in order.
+    pythonpath: Vec<PathBuf>,
+}
+
+/// The environment's folders, for `env path --json`.
+#[derive(Debug, Serialize)]
+struct EnvPaths<'a> {
+    lib: &'a Path,
+    bin: &'a Path,
+    scripts: &'a Path,
+}
+
+/// Report what installs and runs use here, or print code to use the environment from a shell.
+/// Everything's taken from what `main` resolved for them, so this can't disagree with what they
+/// do.
+pub fn env(
+    cmd: &EnvCommand,
+    pcfg: &PresentConfig,
+    paths: &Paths,
+    vers_path: &Path,
+    py_vers: &Version,
+    pythonpath: &[PathBuf],
+    cli: &Layer,
+) {
+    // `pyflow run` activates the `.venv` with `env-mode = "venv"`.
+    let virtual_env = match pcfg.config.env_mode {
+        EnvMode::Pypackages => None,
+        EnvMode::Venv => Some(vers_path.join(".venv")),
+    };
+    match cmd {
+        EnvCommand::Info { json } => info(pcfg, paths, vers_path, py_vers, pythonpath, cli, *json),
+        EnvCommand::Path { json } => {
+            let env_paths = EnvPaths {
+                lib: &paths.lib,
+                bin: &paths.bin,
+                scripts: &paths.entry_pt,
+            };
+            if *json {
+                util::print_json(&env_paths);
+            } else {
+                for path in &[env_paths.lib, env_paths.bin, env_paths.scripts] {
+                    println!("{}", path.display());
+                }
+            }
+        }
+        EnvCommand::Activate { shell } => print!(
+            "{}",
+            activate_code(
+                shell.unwrap_or_else(default_shell),
+                &[&paths.bin, &paths.entry_pt],
+                pythonpath,
+                virtual_env.as_deref(),
+            )
+        ),
+        EnvCommand::Deactivate { shell } => print!(
+            "{}",
+            deactivate_code(shell.unwrap_or_else(default_shell), virtual_env.is_some())
+        ),
+        EnvCommand::Create { .. } => {
+            unreachable!("`env create` doesn't use the project's environment")
+        }
+    }
+}
+
+fn info(
+    pcfg: &PresentConfig,
+    paths: &Paths,
+    vers_path: &Path,
+    py_vers: &Version,
+    pythonpath: &[PathBuf],
+    cli: &Layer,
+    json: bool,
+) {
+    let (resolved, _) = settings::resolve_all(cli);
+    let origin = |key: &str| {
+        resolved
+            .iter()
+            .find(|s| s.key.name == key)
+            .and_then(|s| s.value.as_ref())
+            .map_or("default".to_owned(), |(_, o)| o.to_string())
+    };
+    let current = CliConfig::current();
+
+    let info = EnvInfo {
+        project: pcfg.project_path.clone(),
+        workspace_root: pcfg.workspace.as_ref().map(|ws| ws.root.clone()),
+        // What `commands::run_python` runs; `Command` adds the suffix on Windows itself.
+        python: paths.bin.join(if cfg!(target_os = "windows") {
+            "python.exe"
+        } else {
+            "python"
+        }),
+        python_version: py_versions::venv_version(vers_path)
+            .unwrap_or_else(|| py_vers.clone())
+            .to_string(),
+        implementation: pcfg.config.python_implementation.to_string(),
+        pypackages: pcfg.pypackages_path.clone(),
+        environment: match pcfg.config.env_mode {
+            EnvMode::Pypackages => vers_path.to_owned(),
+            EnvMode::Venv => vers_path.join(".venv"),
+        },
+        lock: pcfg.lock_path.clone(),
+        lock_exists: pcfg.lock_path.exists(),
+        cache_dir: paths.cache.clone(),
+        index_url: Sourced {
+            value: current.settings.index_url.clone(),
+            origin: origin("index-url"),
+        },
+        proxy: current.settings.proxy.as_ref().map(|p| Sourced {
+            value: p.clone(),
+            origin: origin("proxy"),
+        }),
+        ca_certificates: ca_certificates(),
+        pythonpath: pythonpath.to_vec(),
+    };
+
+    if json {
+        util::print_json(&info);
+    } else {
+        print_info(&info);
+    }
+}
+
+/// Where HTTPS certificates are trusted from. We use `reqwest` with `rustls`, which only trusts
+/// the Mozilla roots built into pyflow; these variables, which other tools read, have no effect.
+fn ca_certificates() -> String {
+    let ignored: Vec<String> = ["SSL_CERT_FILE", "SSL_CERT_DIR", "REQUESTS_CA_BUNDLE"]
+        .iter()
+        .filter(|var| env::var_os(var).is_some())
+        .map(|var| format!("`{}`", var))
+        .collect();
+    if ignored.is_empty() {
+        "pyflow's built-in Mozilla roots".to_owned()
+    } else {
+        format!(
+            "pyflow's built-in Mozilla roots; {} is set, but not used",
+            ignored.join(", ")
+        )
+    }
+}
+
+fn print_info(info: &EnvInfo) {
+    let sourced = |s: &Sourced| format!("{} ({})", s.value, s.origin);
+    let path = |p: &Path| p.display().to_string();
+
+    let mut fields = vec![
+        ("Project", path(&info.project)),
+        (
+            "Python",
+            format!(
+                "{} {}, at {}",
+                info.implementation,
+                info.python_version,
+                path(&info.python)
+            ),
+        ),
+        ("__pypackages__", path(&info.pypackages)),
+        ("Environment", path(&info.environment)),
+        (
+            "Lock",
+            if info.lock_exists {
+                path(&info.lock)
+            } else {
+                format!("{} (not created yet)", path(&info.lock))
+            },
+        ),
+        ("Cache", path(&info.cache_dir)),
+        ("Index", sourced(&info.index_url)),
+        (
+            "Proxy",
+            info.proxy.as_ref().map_or("none".to_owned(), sourced),
+        ),
+        ("CA certificates", info.ca_certificates.clone()),
+    ];
+    if let Some(root) = &info.workspace_root {
+        fields.insert(1, ("Workspace", path(root)));
+    }
+    for (label, value) in &fields {
+        print_color_(&format!("{}: ", label), Color::Blue);
+        println!("{}", value);
+    }
+
+    print_color("\nPYTHONPATH for `pyflow run`:", Color::Blue);
+    for p in &info.pythonpath {
+        println!("    {}", p.display());
+    }
+}
+
+/// The shell in `SHELL`, or PowerShell on Windows; Bash if we can't tell.
+fn default_shell() -> EvalShell {
+    let shell = env::var("SHELL").unwrap_or_default();
+    if shell.ends_with("fish") {
+        EvalShell::Fish
+    } else if shell.ends_with("zsh") {
+        EvalShell::Zsh
+    } else if shell.is_empty() && cfg!(target_os = "windows") {
+        EvalShell::PowerShell
+    } else {
+        EvalShell::Bash
+    }
+}
+
+/// `value` in single quotes, for `shell`.
+fn quote(shell: EvalShell, value: &str) -> String {
+    match shell {
+        EvalShell::Bash | EvalShell::Zsh => format!("'{}'", value.replace('\'', r"'\''")),
+        EvalShell::Fish => format!("'{}'", value.replace('\\', r"\\").replace('\'', r"\'")),
+        EvalShell::PowerShell => format!("'{}'", value.replace('\'', "''")),
+    }
+}
+
+/// Shell code that puts `bins` first on the `PATH`, and `pythonpath` first on the `PYTHONPATH`,
+/// and sets `VIRTUAL_ENV`, if there's one. What they were is kept, for `deactivate_code`, which
+/// runs first, so activating twice is harmless.
+fn activate_code(
+    shell: EvalShell,
+    bins: &[&Path],
+    pythonpath: &[PathBuf],
+    virtual_env: Option<&Path>,
+) -> String {
+    let mut bins: Vec<String> = bins.iter().map(|p| p.display().to_string()).collect();
+    bins.dedup();
+    let pythonpath: Vec<String> = pythonpath.iter().map(|p| p.display().to_string()).collect();
+    let sep = if cfg!(target_os = "windows") {
+        ";"
+    } else {
+        ":"
+    };
+    let q = |v: &str| quote(shell, v);
+
+    let mut result = deactivate_code(shell, virtual_env.is_some());
+    match shell {
+        EvalShell::Bash | EvalShell::Zsh => {
+            result.push_str("_PYFLOW_OLD_PATH=\"$PATH\"; export _PYFLOW_OLD_PATH\n");
+            result.push_str(
+                "if [ -n \"${PYTHONPATH+x}\" ]; then _PYFLOW_OLD_PYTHONPATH=\"$PYTHONPATH\"; \
+                 export _PYFLOW_OLD_PYTHONPATH; fi\n",
+            );
+            result.push_str(&format!(
+                "PYTHONPATH={}${{PYTHONPATH:+{}$PYTHONPATH}}; export PYTHONPATH\n",
+                q(&pythonpath.join(sep)),
+                sep
+            ));
+            result.push_str(&format!(
+                "PATH={}{}\"$PATH\"; export PATH\n",
+                q(&bins.join(sep)),
+                sep
+            ));
+            if let Some(venv) = virtual_env {
+                result.push_str(&format!(
+                    "VIRTUAL_ENV={}; export VIRTUAL_ENV\n",
+                    q(&venv.display().to_string())
+                ));
+            }
+            // Forget where commands were found, so the environment's are.
+            result.push_str("hash -r 2>/dev/null\n");
+        }
+        EvalShell::Fish => {
+            let list = |items: &[String]| {
+                items
+                    .iter()
+                    .map(|i| q(i))
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            };
+            result.push_str("set -gx _PYFLOW_OLD_PATH $PATH\n");
+            result.push_str(
+                "if set -q PYTHONPATH; set -gx _PYFLOW_OLD_PYTHONPATH $PYTHONPATH; end\n",
+            );
+            result.push_str(&format!(
+                "set -gx PYTHONPATH {} $PYTHONPATH\n",
+                list(&pythonpath)
+            ));
+            result.push_str(&format!("set -gx PATH {} $PATH\n", list(&bins)));
+            if let Some(venv) = virtual_env {
+                result.push_str(&format!(
+                    "set -gx VIRTUAL_ENV {}\n",
+                    q(&venv.display().to_string())
+                ));
+            }
+        }
+        EvalShell::PowerShell => {
+            result.push_str("$env:_PYFLOW_OLD_PATH = $env:PATH\n");
+            result.push_str(&format!(
+                "if (Test-Path Env:PYTHONPATH) {{ $env:_PYFLOW_OLD_PYTHONPATH = $env:PYTHONPATH; \
+                 $env:PYTHONPATH = {} + $env:PYTHONPATH }} else {{ $env:PYTHONPATH = {} }}\n",
+                q(&format!("{}{}", pythonpath.join(sep), sep)),
+                q(&pythonpath.join(sep))
+            ));
+            result.push_str(&format!(
+                "$env:PATH = {} + $env:PATH\n",
+                q(&format!("{}{}", bins.join(sep), sep))
+            ));
+            if let Some(venv) = virtual_env {
+                result.push_str(&format!(
+                    "$env:VIRTUAL_ENV = {}\n",
+                    q(&venv.display().to_string())
+                ));
+            }
+        }
+    }
+    result
+}
+
+/// Shell code that restores the `PATH` and `PYTHONPATH` from before `activate_code`, and unsets
+/// `VIRTUAL_ENV` if it set it. Does nothing if the environment isn't active.
+fn deactivate_code(shell: EvalShell, virtual_env: bool) -> String {
+    match shell {
+        EvalShell::Bash | EvalShell::Zsh => format!(
+            "if [ -n \"${{_PYFLOW_OLD_PATH+x}}\" ]; then\n    \
+             PATH=\"$_PYFLOW_OLD_PATH\"; export PATH; unset _PYFLOW_OLD_PATH\n    \
+             if [ -n \"${{_PYFLOW_OLD_PYTHONPATH+x}}\" ]; then\n        \
+             PYTHONPATH=\"$_PYFLOW_OLD_PYTHONPATH\"; export PYTHONPATH; \
+             unset _PYFLOW_OLD_PYTHONPATH\n    \
+             else\n        \
+             unset PYTHONPATH\n    \
+             fi\n{}    \
+             hash -r 2>/dev/null\n\
+             fi\n",
+            if virtual_env {
+                "    unset VIRTUAL_ENV\n"
+            } else {
+                ""
+            }
+        ),
+        EvalShell::Fish => format!(
+            "if set -q _PYFLOW_OLD_PATH\n    \
+             set -gx PATH $_PYFLOW_OLD_PATH; set -e _PYFLOW_OLD_PATH\n    \
+             if set -q _PYFLOW_OLD_PYTHONPATH\n        \
+             set -gx PYTHONPATH $_PYFLOW_OLD_PYTHONPATH; set -e _PYFLOW_OLD_PYTHONPATH\n    \
+             else\n        \
+             set -e PYTHONPATH\n    \
+             end\n{}\
+             end\n",
+            if virtual_env {
+                "    set -e VIRTUAL_ENV\n"
+            } else {
+                ""
+            }
+        ),
+        EvalShell::PowerShell => format!(
+            "if (Test-Path Env:_PYFLOW_OLD_PATH) {{\n    \
+             $env:PATH = $env:_PYFLOW_OLD_PATH; Remove-Item Env:_PYFLOW_OLD_PATH\n    \
+             if (Test-Path Env:_PYFLOW_OLD_PYTHONPATH) {{\n        \
+             $env:PYTHONPATH = $env:_PYFLOW_OLD_PYTHONPATH; \
+             Remove-Item Env:_PYFLOW_OLD_PYTHONPATH\n    \
+             }} else {{\n        \
+             Remove-Item Env:PYTHONPATH -ErrorAction SilentlyContinue\n    \
+             }}\n{}\
+             }}\n",
+            if virtual_env {
+                "    Remove-Item Env:VIRTUAL_ENV -ErrorAction SilentlyContinue\n"
+            } else {
+                ""
+            }
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shell_quoting() {
+        assert_eq!(quote(EvalShell::Bash, "/it's here"), r"'/it'\''s here'");
+        assert_eq!(quote(EvalShell::Fish, "/it's here"), r"'/it\'s here'");
+        assert_eq!(quote(EvalShell::PowerShell, "/it's here"), "'/it''s here'");
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn activate_bash() {
+        let code = activate_code(
+            EvalShell::Bash,
+            &[
+                Path::new("/proj/__pypackages__/3.11/.venv/bin"),
+                Path::new("/proj/__pypackages__/3.11/bin"),
+            ],
+            &[
+                PathBuf::from("/proj/__pypackages__/3.11/lib"),
+                PathBuf::from("/proj/src"),
+            ],
+            None,
+        );
+        // Deactivates first, so activating twice keeps the original paths.
+        assert!(code.starts_with(&deactivate_code(EvalShell::Bash, false)));
+        assert!(code.contains(
+            "PYTHONPATH='/proj/__pypackages__/3.11/lib:/proj/src'${PYTHONPATH:+:$PYTHONPATH}; \
+             export PYTHONPATH\n"
+        ));
+        assert!(code.contains(
+            "PATH='/proj/__pypackages__/3.11/.venv/bin:/proj/__pypackages__/3.11/bin':\"$PATH\"; \
+             export PATH\n"
+        ));
+        assert!(!code.contains("VIRTUAL_ENV"));
+    }
+}
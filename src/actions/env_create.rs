@@ -0,0 +1,209 @@
+//! `pyflow env create`: a standard virtualenv at any path, eg for a tox or nox session, holding
+//! exactly the locked packages, instead of ones the tool resolves itself.
+
+use std::{collections::HashMap, env, fs, path::Path};
+
+use crate::{
+    dep_resolution,
+    dep_types::{Req, Version},
+    py_versions::{self, Implementation},
+    pyproject::{Config, EnvMode, Hooks, PresentConfig, LOCK_FILENAME},
+    util::{self, abort, deps, Os, Paths},
+};
+
+/// Create the environment at `path`, or update it if it's there, with the locked packages the
+/// `groups` need on this Python. Packages that aren't needed are removed, so it matches the lock.
+#[allow(clippy::too_many_arguments)]
+pub fn create_env(
+    pcfg: &PresentConfig,
+    path: &Path,
+    python: Option<&str>,
+    groups: &[String],
+    pyflow_dir: &Path,
+    dep_cache_path: &Path,
+    default_vers: &Version,
+    os: Os,
+) {
+    let (implementation, version) = match python {
+        Some(p) => {
+            let (implementation, v) = py_versions::split_implementation(p);
+            let version: Version = v.parse().unwrap_or_else(|_| {
+                abort(&format!(
+                    "`{}` isn't a Python version; try eg `--python 3.11`",
+                    p
+                ))
+            });
+            (
+                implementation.unwrap_or(pcfg.config.python_implementation),
+                version,
+            )
+        }
+        None => (pcfg.config.python_implementation, default_vers.clone()),
+    };
+    let python_requires = py_versions::parse_python_requires(&pcfg.config.python_requires);
+    if !py_versions::could_satisfy(&version, &python_requires) {
+        abort(&format!(
+            "Python {} doesn't satisfy `python_requires = \"{}\"` in `pyproject.toml`",
+            version.to_string_med(),
+            py_versions::requires_str(&python_requires)
+        ))
+    }
+
+    let lock = util::read_lock(&pcfg.lock_path).unwrap_or_else(|_| {
+        abort(&format!(
+            "Can't find `{}`; run `pyflow install` first",
+            LOCK_FILENAME
+        ))
+    });
+    let lock_packs = lock.package.unwrap_or_default();
+
+    // Relative to where we're run from, as tox and nox pass it.
+    let path = env::current_dir()
+        .map(|d| d.join(path))
+        .unwrap_or_else(|_| path.to_owned());
+    let py_vers = match existing_version(&path, &version, implementation) {
+        Some(v) => v,
+        None => {
+            if path.exists() && fs::remove_dir_all(&path).is_err() {
+                abort(&format!("Problem removing {:?}", path))
+            }
+            py_versions::create_venv_at(&path, pyflow_dir, dep_cache_path, &version, implementation)
+        }
+    };
+    let py_vers = Version::new_opt(py_vers.major, py_vers.minor, None);
+
+    let bin_path = util::venv_bin_path(&path);
+    let paths = Paths {
+        lib: util::venv_site_packages(&path)
+            .unwrap_or_else(|| abort(&format!("Can't find `site-packages` in {:?}", path))),
+        bin: bin_path.clone(),
+        entry_pt: bin_path,
+        cache: dep_cache_path.to_owned(),
+        mode: EnvMode::Venv,
+    };
+
+    let roots = roots(&pcfg.config, groups, os, &py_vers);
+    for r in roots.iter().filter(|r| r.path.is_some() || r.git.is_some()) {
+        log::warn!(
+            "`{}` is a path or git dependency, so isn't in `{}`, and isn't installed",
+            r.name,
+            LOCK_FILENAME
+        );
+    }
+    let packs = deps::reachable_from(&lock_packs, &roots);
+    // The project's hooks are for its own environment.
+    deps::sync_exact(
+        &paths,
+        &packs,
+        &HashMap::new(),
+        os,
+        &py_vers,
+        &Hooks::default(),
+    );
+    util::success(&format!("Environment ready at {}", path.display()));
+}
+
+/// The version of the environment at `path`, if it's there and made with the requested Python,
+/// so can be updated in place.
+fn existing_version(
+    path: &Path,
+    version: &Version,
+    implementation: Implementation,
+) -> Option<Version> {
+    let existing = py_versions::venv_cfg_version(path)?;
+    let bin_path = util::venv_bin_path(path);
+    if !py_versions::version_matches(&existing, version)
+        || py_versions::venv_implementation(&bin_path) != implementation
+    {
+        return None;
+    }
+    util::venv_site_packages(path).map(|_| existing)
+}
+
+/// The direct requirements of the `groups`, whose markers match this Python: `default` is the
+/// main dependencies, `dev` the dev ones, and any other name a group under
+/// `[tool.pyflow.extras]`. Packages only in groups that weren't asked for are left out.
+fn roots(cfg: &Config, groups: &[String], os: Os, py_vers: &Version) -> Vec<Req> {
+    for group in groups {
+        if !["default", "dev"].contains(&group.as_str()) && !cfg.extras.contains_key(group) {
+            abort(&format!(
+                "There's no `{}` group; use `default`, `dev`, or one under \
+                 `[tool.pyflow.extras]`",
+                group
+            ))
+        }
+    }
+    let selected: Vec<&String> = cfg
+        .extras
+        .iter()
+        .filter(|(group, _)| groups.contains(group))
+        .flat_map(|(_, names)| names)
+        .collect();
+    let optional = |name: &str| {
+        cfg.extras
+            .values()
+            .flatten()
+            .any(|n| util::compare_names(n, name))
+            && !selected.iter().any(|n| util::compare_names(n, name))
+    };
+    let wanted = |group: &str, reqs: &[Req]| -> Vec<Req> {
+        if !groups.iter().any(|g| g == group) {
+            return vec![];
+        }
+        reqs.iter()
+            .filter(|r| !optional(&r.name))
+            .cloned()
+            .collect()
+    };
+
+    let mut result = wanted("default", &cfg.reqs);
+    result.append(&mut wanted("dev", &cfg.dev_reqs));
+    for name in selected {
+        if !result.iter().any(|r| util::compare_names(&r.name, name)) {
+            let req = cfg
+                .reqs
+                .iter()
+                .chain(cfg.dev_reqs.iter())
+                .find(|r| util::compare_names(&r.name, name))
+                .cloned()
+                .unwrap_or_else(|| Req::new(name.clone(), vec![]));
+            result.push(req);
+        }
+    }
+    result.retain(|r| dep_resolution::markers_match(r, os, py_vers));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn group_roots() {
+        let mut cfg = Config {
+            reqs: vec![
+                Req::from_str("requests", true).unwrap(),
+                Req::from_str("pysocks", true).unwrap(),
+                Req::from_str("tomli ; python_version < \"3.11\"", true).unwrap(),
+            ],
+            dev_reqs: vec![Req::from_str("pytest", true).unwrap()],
+            ..Config::default()
+        };
+        cfg.extras.insert("socks".into(), vec!["pysocks".into()]);
+
+        let names = |groups: &[&str], py_vers: &Version| -> Vec<String> {
+            let groups: Vec<String> = groups.iter().map(|g| (*g).to_owned()).collect();
+            roots(&cfg, &groups, Os::Linux, py_vers)
+                .into_iter()
+                .map(|r| r.name)
+                .collect()
+        };
+        let py311 = Version::new_short(3, 11);
+        assert_eq!(names(&["default"], &py311), vec!["requests"]);
+        assert_eq!(
+            names(&["default", "dev"], &Version::new_short(3, 10)),
+            vec!["requests", "tomli", "pytest"]
+        );
+        assert_eq!(names(&["dev", "socks"], &py311), vec!["pytest", "pysocks"]);
+    }
+}
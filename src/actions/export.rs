@@ -0,0 +1,725 @@
+//! `pyflow export`: write the locked dependencies in formats other tools read. Every format is
+//! rendered from one `Graph`, built from the lock, so they can't disagree with each other.
+
+use std::{fs, path::Path, str::FromStr};
+
+use serde::Serialize;
+
+use crate::{
+    cli_options::ExportFormat,
+    dep_resolution,
+    dep_resolution::res,
+    dep_types::{LockPackage, Req, Version},
+    pyproject::Config,
+    util::{self, abort, deps, settings::Settings, Os},
+};
+
+/// Everything we know about the locked packages, in a stable order.
+#[derive(Debug, PartialEq, Serialize)]
+struct Graph {
+    python_requires: Option<String>,
+    packages: Vec<Package>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct Package {
+    name: String,
+    /// Git and path dependencies aren't locked, so have no version.
+    version: Option<String>,
+    /// Only needed by dev dependencies.
+    dev: bool,
+    /// A PEP 508 environment marker, for direct dependencies limited to a platform or Python
+    /// version.
+    marker: Option<String>,
+    source: Source,
+    dependencies: Vec<String>,
+    files: Vec<File>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Source {
+    Index {
+        url: String,
+    },
+    Git {
+        url: String,
+        reference: Option<String>,
+    },
+    Path {
+        path: String,
+    },
+}
+
+/// A wheel or sdist on the index.
+#[derive(Debug, PartialEq, Serialize)]
+struct File {
+    name: String,
+    url: String,
+    sha256: String,
+    wheel: bool,
+}
+
+/// What to export, from `--dev`, `--no-dev`, and `--extras`.
+pub struct Selection<'a> {
+    pub dev: bool,
+    pub extras: &'a [String],
+}
+
+/// Render the lock in `format`, and write it to `output`, or stdout. `os` and `py_vers` are the
+/// environment formats without markers are rendered for.
+#[allow(clippy::too_many_arguments)]
+pub fn export(
+    cfg: &Config,
+    project_path: &Path,
+    lock_packs: &[LockPackage],
+    format: ExportFormat,
+    selection: &Selection,
+    output: Option<&Path>,
+    os: Os,
+    py_vers: &Version,
+) {
+    if let Some(e) = selection
+        .extras
+        .iter()
+        .find(|e| !cfg.extras.contains_key(*e))
+    {
+        abort(&format!(
+            "There's no `{}` group under `[tool.pyflow.extras]` in `pyproject.toml`",
+            e
+        ))
+    }
+
+    let mut graph = build_graph(cfg, project_path, lock_packs, selection);
+    // Fetching files is slow, and only some formats need them.
+    if !matches!(format, ExportFormat::Requirements | ExportFormat::Conda) {
+        for package in graph.packages.iter_mut() {
+            if let (Source::Index { .. }, Some(version)) = (&package.source, &package.version) {
+                package.files = find_files(&package.name, version);
+            }
+        }
+    }
+
+    let rendered = match format {
+        ExportFormat::Requirements => requirements(&graph, false),
+        ExportFormat::RequirementsHashes => requirements(&graph, true),
+        ExportFormat::Pylock => pylock(&graph),
+        ExportFormat::Json => serde_json::to_string_pretty(&graph)
+            .map(|s| s + "\n")
+            .unwrap_or_else(|_| abort("Problem serializing the dependency graph")),
+        ExportFormat::Conda => {
+            let name = cfg.name.clone().unwrap_or_else(|| {
+                project_path
+                    .file_name()
+                    .map_or("pyflow".to_owned(), |n| n.to_string_lossy().into_owned())
+            });
+            conda(&graph, cfg, &name, os, py_vers)
+        }
+        ExportFormat::Poetry => unreachable!("Poetry tables come from the config, not the lock"),
+    };
+
+    match output {
+        Some(path) => {
+            if fs::write(path, rendered).is_err() {
+                abort(&format!("Problem writing {:?}", path))
+            }
+            log::info!("Exported {} packages to {:?}", graph.packages.len(), path);
+        }
+        None => print!("{}", rendered),
+    }
+}
+
+fn build_graph(
+    cfg: &Config,
+    project_path: &Path,
+    lock_packs: &[LockPackage],
+    selection: &Selection,
+) -> Graph {
+    // Packages named only in groups under `[tool.pyflow.extras]` that weren't asked for are
+    // left out, along with whatever only they need.
+    let in_groups = |selected: bool| -> Vec<&String> {
+        cfg.extras
+            .iter()
+            .filter(|(group, _)| selection.extras.contains(group) == selected)
+            .flat_map(|(_, names)| names)
+            .collect()
+    };
+    let (selected, unselected) = (in_groups(true), in_groups(false));
+    let optional = |name: &str| {
+        unselected.iter().any(|n| util::compare_names(n, name))
+            && !selected.iter().any(|n| util::compare_names(n, name))
+    };
+
+    let mut main_roots: Vec<Req> = cfg
+        .reqs
+        .iter()
+        .filter(|r| !optional(&r.name))
+        .cloned()
+        .collect();
+    for name in &selected {
+        if !lock_packs
+            .iter()
+            .any(|lp| util::compare_names(&lp.name, name))
+        {
+            log::warn!(
+                "`{}` from `[tool.pyflow.extras]` isn't in `pyflow.lock`, so isn't exported; \
+                 add it to your dependencies, and run `pyflow install`",
+                name
+            );
+        }
+        main_roots.push(Req::new((*name).clone(), vec![]));
+    }
+    let dev_roots: Vec<Req> = if selection.dev {
+        cfg.dev_reqs
+            .iter()
+            .filter(|r| !optional(&r.name))
+            .cloned()
+            .collect()
+    } else {
+        vec![]
+    };
+
+    let main = deps::reachable_from(lock_packs, &main_roots);
+    let dev = deps::reachable_from(lock_packs, &dev_roots);
+    let is_main = |name: &str| main.iter().any(|lp| util::compare_names(&lp.name, name));
+
+    let direct: Vec<(&Req, bool)> = main_roots
+        .iter()
+        .map(|r| (r, false))
+        .chain(dev_roots.iter().map(|r| (r, true)))
+        .collect();
+
+    // The `index-url` setting's simple API, as pip and PEP 751 name an index.
+    let index_url = util::index_simple_url().trim_end_matches('/').to_owned();
+    let mut packages: Vec<Package> = main
+        .iter()
+        .chain(dev.iter().filter(|lp| !is_main(&lp.name)))
+        .map(|lp| {
+            let mut dependencies: Vec<String> = lp
+                .dependencies
+                .iter()
+                .flatten()
+                .filter_map(|d| d.split_whitespace().next().map(str::to_owned))
+                .collect();
+            dependencies.sort_by_key(|d| d.to_lowercase());
+            dependencies.dedup();
+            Package {
+                name: lp.name.clone(),
+                version: Some(lp.version.clone()),
+                dev: !is_main(&lp.name),
+                marker: direct
+                    .iter()
+                    .find(|(r, _)| util::compare_names(&r.name, &lp.name))
+                    .and_then(|(r, _)| r.marker()),
+                source: Source::Index {
+                    url: index_url.clone(),
+                },
+                dependencies,
+                files: vec![],
+            }
+        })
+        .collect();
+
+    // Git and path dependencies are installed from their source, not the lock.
+    for (req, dev) in &direct {
+        let source = match (&req.git, &req.path) {
+            (Some(url), _) => Source::Git {
+                url: url.clone(),
+                reference: req.git_ref.as_ref().map(|r| r.parts().1.to_owned()),
+            },
+            (None, Some(path)) => Source::Path {
+                path: project_path.join(path).to_string_lossy().into_owned(),
+            },
+            (None, None) => continue,
+        };
+        packages.push(Package {
+            name: req.name.clone(),
+            version: None,
+            dev: *dev,
+            marker: req.marker(),
+            source,
+            dependencies: vec![],
+            files: vec![],
+        });
+    }
+
+    packages.sort_by(|a, b| {
+        (a.name.to_lowercase(), &a.version).cmp(&(b.name.to_lowercase(), &b.version))
+    });
+    packages.dedup_by(|a, b| util::compare_names(&a.name, &b.name) && a.version == b.version);
+
+    Graph {
+        python_requires: cfg.python_requires.clone(),
+        packages,
+    }
+}
+
+/// The package's wheels and sdist for this version, sorted by name.
+fn find_files(name: &str, version: &str) -> Vec<File> {
+    let parsed = Version::from_str(version)
+        .unwrap_or_else(|_| abort(&format!("Problem parsing {}'s locked version", name)));
+    let releases = res::get_warehouse_release(name, &parsed).unwrap_or_else(|_| {
+        abort(&format!(
+            "Problem getting the files for {} {} from PyPi",
+            name, version
+        ))
+    });
+    let mut files: Vec<File> = releases
+        .into_iter()
+        .map(|r| File {
+            name: r.filename,
+            url: r.url,
+            sha256: r.digests.sha256,
+            wheel: r.packagetype == "bdist_wheel",
+        })
+        .collect();
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    files
+}
+
+/// How pip names a package's source, eg `idna==3.6`, or `mylib @ git+https://...`.
+fn requirement(package: &Package) -> String {
+    match (&package.source, &package.version) {
+        (Source::Git { url, reference }, _) => match reference {
+            Some(r) => format!("{} @ git+{}@{}", package.name, url, r),
+            None => format!("{} @ git+{}", package.name, url),
+        },
+        (Source::Path { path }, _) => format!("{} @ file://{}", package.name, path),
+        (Source::Index { .. }, Some(v)) => format!("{}=={}", package.name, v),
+        (Source::Index { .. }, None) => package.name.clone(),
+    }
+}
+
+/// `requirements.txt` lines, optionally with `--hash` options for pip's hash-checking mode.
+/// Packages from an index other than PyPI's get an `--index-url` line first.
+fn requirements(graph: &Graph, hashes: bool) -> String {
+    let mut result = String::new();
+    let index = graph.packages.iter().find_map(|p| match &p.source {
+        Source::Index { url } => Some(url),
+        _ => None,
+    });
+    let pypi = Settings::default().simple_url();
+    if let Some(url) = index.filter(|u| **u != pypi.trim_end_matches('/')) {
+        result.push_str(&format!("--index-url {}\n", url));
+    }
+    for package in &graph.packages {
+        let mut line = requirement(package);
+        if let Some(m) = &package.marker {
+            line.push_str(&format!(" ; {}", m));
+        }
+        if hashes {
+            if !matches!(package.source, Source::Index { .. }) {
+                abort(&format!(
+                    "{} is a git or path dependency, so has no hashes; pip's hash-checking \
+                     mode can't install it. Use `--format requirements` instead",
+                    package.name
+                ))
+            }
+            for file in &package.files {
+                line.push_str(&format!(" \\\n    --hash=sha256:{}", file.sha256));
+            }
+        }
+        result.push_str(&line);
+        result.push('\n');
+    }
+    result
+}
+
+/// A conda `environment.yml`, with the packages installed by pip. It has no markers, so direct
+/// dependencies whose markers don't match `os` and `py_vers` are left out, with whatever only
+/// they need.
+fn conda(graph: &Graph, cfg: &Config, name: &str, os: Os, py_vers: &Version) -> String {
+    let python = cfg.py_version.as_ref().unwrap_or(py_vers);
+    let mut result = format!(
+        "name: {}\ndependencies:\n  - python={}.{}\n  - pip\n  - pip:\n",
+        name,
+        python.major.unwrap_or(3),
+        python.minor.unwrap_or(0)
+    );
+    let direct: Vec<&Req> = cfg.reqs.iter().chain(cfg.dev_reqs.iter()).collect();
+    let is_root = |package: &Package| {
+        let reqs: Vec<&&Req> = direct
+            .iter()
+            .filter(|r| util::compare_names(&r.name, &package.name))
+            .collect();
+        let in_extras = cfg
+            .extras
+            .values()
+            .flatten()
+            .any(|n| util::compare_names(n, &package.name));
+        (in_extras || !reqs.is_empty())
+            && reqs
+                .iter()
+                .all(|r| dep_resolution::markers_match(r, os, python))
+    };
+
+    let mut to_visit: Vec<&str> = graph
+        .packages
+        .iter()
+        .filter(|p| is_root(p))
+        .map(|p| p.name.as_str())
+        .collect();
+    let mut needed: Vec<&str> = vec![];
+    while let Some(name) = to_visit.pop() {
+        if needed.iter().any(|n| util::compare_names(n, name)) {
+            continue;
+        }
+        needed.push(name);
+        if let Some(package) = graph
+            .packages
+            .iter()
+            .find(|p| util::compare_names(&p.name, name))
+        {
+            to_visit.extend(package.dependencies.iter().map(String::as_str));
+        }
+    }
+
+    for package in &graph.packages {
+        if needed.iter().any(|n| util::compare_names(n, &package.name)) {
+            result.push_str(&format!("    - {}\n", requirement(package)));
+        }
+    }
+    result
+}
+
+/// `pylock.toml`, as described in PEP 751.
+fn pylock(graph: &Graph) -> String {
+    #[derive(Serialize)]
+    #[serde(rename_all = "kebab-case")]
+    struct Pylock<'a> {
+        lock_version: &'a str,
+        created_by: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        requires_python: Option<&'a str>,
+        packages: Vec<PylockPackage<'a>>,
+    }
+
+    // Tables last; TOML can't have plain values after them.
+    #[derive(Serialize)]
+    #[serde(rename_all = "kebab-case")]
+    struct PylockPackage<'a> {
+        name: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        version: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        marker: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        index: Option<&'a str>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        dependencies: Vec<PylockDependency<'a>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        vcs: Option<PylockVcs<'a>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        directory: Option<PylockDirectory<'a>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sdist: Option<PylockFile<'a>>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        wheels: Vec<PylockFile<'a>>,
+    }
+
+    #[derive(Serialize)]
+    struct PylockDependency<'a> {
+        name: &'a str,
+    }
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "kebab-case")]
+    struct PylockVcs<'a> {
+        #[serde(rename = "type")]
+        type_: &'a str,
+        url: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        requested_revision: Option<&'a str>,
+    }
+
+    #[derive(Serialize)]
+    struct PylockDirectory<'a> {
+        path: &'a str,
+    }
+
+    #[derive(Serialize)]
+    struct PylockFile<'a> {
+        name: &'a str,
+        url: &'a str,
+        hashes: PylockHashes<'a>,
+    }
+
+    #[derive(Serialize)]
+    struct PylockHashes<'a> {
+        sha256: &'a str,
+    }
+
+    fn file(f: &File) -> PylockFile<'_> {
+        PylockFile {
+            name: &f.name,
+            url: &f.url,
+            hashes: PylockHashes { sha256: &f.sha256 },
+        }
+    }
+
+    let packages = graph
+        .packages
+        .iter()
+        .map(|p| {
+            let (index, vcs, directory) = match &p.source {
+                Source::Index { url } => (Some(url.as_str()), None, None),
+                Source::Git { url, reference } => (
+                    None,
+                    Some(PylockVcs {
+                        type_: "git",
+                        url,
+                        requested_revision: reference.as_deref(),
+                    }),
+                    None,
+                ),
+                Source::Path { path } => (None, None, Some(PylockDirectory { path })),
+            };
+            PylockPackage {
+                name: &p.name,
+                version: p.version.as_deref(),
+                marker: p.marker.as_deref(),
+                index,
+                dependencies: p
+                    .dependencies
+                    .iter()
+                    .map(|d| PylockDependency { name: d })
+                    .collect(),
+                vcs,
+                directory,
+                sdist: p.files.iter().find(|f| !f.wheel).map(file),
+                wheels: p.files.iter().filter(|f| f.wheel).map(file).collect(),
+            }
+        })
+        .collect();
+
+    let lock = Pylock {
+        lock_version: "1.0",
+        created_by: "pyflow",
+        requires_python: graph.python_requires.as_deref(),
+        packages,
+    };
+    toml::to_string(&lock).unwrap_or_else(|_| abort("Problem serializing `pylock.toml`"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::dep_types::GitRef;
+
+    fn lock_pack(name: &str, version: &str, deps: &[&str]) -> LockPackage {
+        LockPackage {
+            id: 0,
+            name: name.into(),
+            version: version.into(),
+            source: None,
+            dependencies: Some(
+                deps.iter()
+                    .map(|d| format!("{} 1.0.0 pypi+https://pypi.org/pypi/{}/1.0.0/json", d, d))
+                    .collect(),
+            ),
+            rename: None,
+        }
+    }
+
+    fn cfg() -> Config {
+        let mut git = Req::new("mylib".into(), vec![]);
+        git.git = Some("https://github.com/me/mylib".into());
+        git.git_ref = Some(GitRef::Tag("v1.2".into()));
+        Config {
+            reqs: vec![
+                Req::new("requests".into(), vec![]),
+                Req::new("uvloop".into(), vec![]),
+                git,
+            ],
+            dev_reqs: vec![Req::new("pytest".into(), vec![])],
+            extras: HashMap::from([("fast".to_owned(), vec!["uvloop".to_owned()])]),
+            ..Default::default()
+        }
+    }
+
+    fn lock() -> Vec<LockPackage> {
+        vec![
+            lock_pack("requests", "2.31.0", &["urllib3", "idna"]),
+            lock_pack("idna", "3.6", &[]),
+            lock_pack("urllib3", "2.2.0", &[]),
+            lock_pack("uvloop", "0.19.0", &[]),
+            lock_pack("pytest", "8.0.0", &["pluggy"]),
+            lock_pack("pluggy", "1.4.0", &[]),
+        ]
+    }
+
+    fn names(graph: &Graph) -> Vec<&str> {
+        graph.packages.iter().map(|p| p.name.as_str()).collect()
+    }
+
+    #[test]
+    fn selection() {
+        let all = build_graph(
+            &cfg(),
+            Path::new("/proj"),
+            &lock(),
+            &Selection {
+                dev: true,
+                extras: &["fast".to_owned()],
+            },
+        );
+        assert_eq!(
+            names(&all),
+            vec!["idna", "mylib", "pluggy", "pytest", "requests", "urllib3", "uvloop"]
+        );
+        assert!(
+            all.packages
+                .iter()
+                .find(|p| p.name == "pluggy")
+                .unwrap()
+                .dev
+        );
+
+        let main = build_graph(
+            &cfg(),
+            Path::new("/proj"),
+            &lock(),
+            &Selection {
+                dev: false,
+                extras: &[],
+            },
+        );
+        assert_eq!(names(&main), vec!["idna", "mylib", "requests", "urllib3"]);
+        assert_eq!(
+            main.packages
+                .iter()
+                .find(|p| p.name == "requests")
+                .unwrap()
+                .dependencies,
+            vec!["idna".to_owned(), "urllib3".to_owned()]
+        );
+    }
+
+    #[test]
+    fn requirements_txt() {
+        let graph = build_graph(
+            &cfg(),
+            Path::new("/proj"),
+            &lock(),
+            &Selection {
+                dev: false,
+                extras: &[],
+            },
+        );
+        assert_eq!(
+            requirements(&graph, false),
+            "idna==3.6\n\
+             mylib @ git+https://github.com/me/mylib@v1.2\n\
+             requests==2.31.0\n\
+             urllib3==2.2.0\n"
+        );
+    }
+
+    #[test]
+    fn hashes_and_pylock() {
+        let graph = Graph {
+            python_requires: Some(">=3.8".into()),
+            packages: vec![Package {
+                name: "idna".into(),
+                version: Some("3.6".into()),
+                dev: false,
+                marker: Some(r#"sys_platform == "linux""#.into()),
+                source: Source::Index {
+                    url: "https://pypi.org/simple".into(),
+                },
+                dependencies: vec![],
+                files: vec![
+                    File {
+                        name: "idna-3.6-py3-none-any.whl".into(),
+                        url: "https://files/idna-3.6-py3-none-any.whl".into(),
+                        sha256: "aaa".into(),
+                        wheel: true,
+                    },
+                    File {
+                        name: "idna-3.6.tar.gz".into(),
+                        url: "https://files/idna-3.6.tar.gz".into(),
+                        sha256: "bbb".into(),
+                        wheel: false,
+                    },
+                ],
+            }],
+        };
+        assert_eq!(
+            requirements(&graph, true),
+            "idna==3.6 ; sys_platform == \"linux\" \\\n    --hash=sha256:aaa \\\n    --hash=sha256:bbb\n"
+        );
+
+        let pylock = pylock(&graph);
+        assert!(pylock.starts_with(
+            "lock-version = \"1.0\"\ncreated-by = \"pyflow\"\nrequires-python = \">=3.8\"\n"
+        ));
+        let parsed: toml::Value = toml::from_str(&pylock).unwrap();
+        let package = &parsed["packages"][0];
+        assert_eq!(package["index"].as_str(), Some("https://pypi.org/simple"));
+        assert_eq!(package["sdist"]["hashes"]["sha256"].as_str(), Some("bbb"));
+        assert_eq!(
+            package["wheels"][0]["name"].as_str(),
+            Some("idna-3.6-py3-none-any.whl")
+        );
+    }
+
+    #[test]
+    fn other_index() {
+        let package = |url: &str| Package {
+            name: "idna".into(),
+            version: Some("3.6".into()),
+            dev: false,
+            marker: None,
+            source: Source::Index { url: url.into() },
+            dependencies: vec![],
+            files: vec![],
+        };
+        let graph = |url: &str| Graph {
+            python_requires: None,
+            packages: vec![package(url)],
+        };
+        assert_eq!(
+            requirements(&graph("https://pypi.org/simple"), false),
+            "idna==3.6\n"
+        );
+        assert_eq!(
+            requirements(&graph("https://mirror.local/simple"), false),
+            "--index-url https://mirror.local/simple\nidna==3.6\n"
+        );
+    }
+
+    #[test]
+    fn conda_environment() {
+        let mut cfg = cfg();
+        cfg.name = Some("myapp".into());
+        cfg.py_version = Some(Version::new_short(3, 11));
+        cfg.reqs[1].sys_platform = Some((crate::dep_types::ReqType::Exact, Os::Windows));
+        // What only the Windows-only `uvloop` needs is left out, but not what others need too.
+        let mut lock = lock();
+        lock[3] = lock_pack("uvloop", "0.19.0", &["idna", "winloop"]);
+        lock.push(lock_pack("winloop", "1.0.0", &[]));
+        let graph = build_graph(
+            &cfg,
+            Path::new("/proj"),
+            &lock,
+            &Selection {
+                dev: false,
+                extras: &["fast".to_owned()],
+            },
+        );
+        assert_eq!(
+            conda(&graph, &cfg, "myapp", Os::Linux, &Version::new(3, 12, 0)),
+            "name: myapp\n\
+             dependencies:\n  \
+             - python=3.11\n  \
+             - pip\n  \
+             - pip:\n    \
+             - idna==3.6\n    \
+             - mylib @ git+https://github.com/me/mylib@v1.2\n    \
+             - requests==2.31.0\n    \
+             - urllib3==2.2.0\n"
+        );
+    }
+}
@@ -0,0 +1,326 @@
+//! `pyflow ide`: point VS Code, or PyCharm, at the project's interpreter and packages, which
+//! they can't find in `__pypackages__` on their own.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde_json::{Map, Value};
+use termcolor::Color;
+
+use crate::{
+    cli_options::IdeKind,
+    pyproject::{EnvMode, PresentConfig},
+    util::{self, abort, print_color, Paths},
+};
+
+const VSCODE_SETTINGS: &str = ".vscode/settings.json";
+const INTERPRETER_KEY: &str = "python.defaultInterpreterPath";
+const EXTRA_PATHS_KEY: &str = "python.analysis.extraPaths";
+
+/// Write, or with `print`, show, the settings `kind` needs to use the environment: its
+/// interpreter, and the paths `pyflow run` puts on the `PYTHONPATH`.
+pub fn ide(
+    kind: IdeKind,
+    print: bool,
+    pcfg: &PresentConfig,
+    paths: &Paths,
+    pythonpath: &[PathBuf],
+) {
+    let root = pcfg
+        .project_path
+        .canonicalize()
+        .unwrap_or_else(|_| pcfg.project_path.clone());
+    let python = paths.bin.join(if cfg!(target_os = "windows") {
+        "python.exe"
+    } else {
+        "python"
+    });
+    // With `env-mode = "venv"`, IDEs find `site-packages` from the interpreter.
+    let extra_paths: Vec<&Path> = pythonpath
+        .iter()
+        .filter(|p| pcfg.config.env_mode == EnvMode::Pypackages || **p != paths.lib)
+        .map(PathBuf::as_path)
+        .collect();
+
+    match kind {
+        IdeKind::VsCode => vscode(&root, &pcfg.pypackages_path, &python, &extra_paths, print),
+        IdeKind::PyCharm => pycharm(&root, &pcfg.pypackages_path, &python, &extra_paths, print),
+    }
+}
+
+fn vscode(root: &Path, pypackages_path: &Path, python: &Path, extra_paths: &[&Path], print: bool) {
+    let interpreter = vscode_path(root, python);
+    let extra_paths: Vec<String> = extra_paths.iter().map(|p| vscode_path(root, p)).collect();
+
+    if print {
+        let mut settings = Map::new();
+        merge_vscode(&mut settings, &interpreter, &extra_paths, "");
+        util::print_json(&settings);
+        return;
+    }
+
+    let settings_path = root.join(VSCODE_SETTINGS);
+    let mut settings = match fs::read_to_string(&settings_path) {
+        Ok(data) if !data.trim().is_empty() => match serde_json::from_str(&data) {
+            Ok(Value::Object(s)) => s,
+            _ => abort(&format!(
+                "Can't read {:?}; it may have comments, which pyflow can't keep. Run \
+                 `pyflow ide --print`, and add the settings yourself",
+                settings_path
+            )),
+        },
+        _ => Map::new(),
+    };
+    merge_vscode(
+        &mut settings,
+        &interpreter,
+        &extra_paths,
+        &vscode_path(root, pypackages_path),
+    );
+
+    let data = serde_json::to_string_pretty(&settings).expect("Problem serializing settings");
+    if fs::create_dir_all(root.join(".vscode")).is_err()
+        || fs::write(&settings_path, data + "\n").is_err()
+    {
+        abort(&format!("Problem writing {:?}", settings_path))
+    }
+    util::success(&format!("Updated {:?}", settings_path));
+}
+
+/// `path` the way VS Code settings write it: relative to `${workspaceFolder}`, if it's in the
+/// project.
+fn vscode_path(root: &Path, path: &Path) -> String {
+    match path.strip_prefix(root) {
+        Ok(rel) => format!(
+            "${{workspaceFolder}}/{}",
+            rel.to_string_lossy().replace('\\', "/")
+        ),
+        Err(_) => path.to_string_lossy().to_string(),
+    }
+}
+
+/// Set the interpreter, and our extra paths, in VS Code's `settings`, keeping everything else.
+/// Extra paths under `managed`, the environments' folder, are ones we set before, eg for another
+/// Python version, so are replaced.
+fn merge_vscode(
+    settings: &mut Map<String, Value>,
+    interpreter: &str,
+    extra_paths: &[String],
+    managed: &str,
+) {
+    settings.insert(INTERPRETER_KEY.into(), Value::String(interpreter.into()));
+
+    let existing: Vec<String> = match settings.get(EXTRA_PATHS_KEY) {
+        Some(Value::Array(a)) => a
+            .iter()
+            .filter_map(|v| v.as_str().map(String::from))
+            .collect(),
+        _ => vec![],
+    };
+    let kept = existing
+        .into_iter()
+        .filter(|p| (managed.is_empty() || !p.starts_with(managed)) && !extra_paths.contains(p));
+    let merged = extra_paths.iter().cloned().chain(kept).map(Value::String);
+    settings.insert(EXTRA_PATHS_KEY.into(), Value::Array(merged.collect()));
+}
+
+fn pycharm(root: &Path, pypackages_path: &Path, python: &Path, extra_paths: &[&Path], print: bool) {
+    let urls: Vec<String> = extra_paths.iter().map(|p| pycharm_url(root, p)).collect();
+
+    if print {
+        println!("Interpreter: {}", python.display());
+        for path in extra_paths {
+            println!("Source folder: {}", path.display());
+        }
+        return;
+    }
+
+    // PyCharm keeps the module file in `.idea`, and interpreters in its own settings, not the
+    // project's.
+    let module_path = fs::read_dir(root.join(".idea"))
+        .ok()
+        .and_then(|entries| {
+            let mut imls: Vec<PathBuf> = entries
+                .flatten()
+                .map(|e| e.path())
+                .filter(|p| p.extension().is_some_and(|e| e == "iml"))
+                .collect();
+            imls.sort();
+            imls.into_iter().next()
+        })
+        .unwrap_or_else(|| {
+            abort(
+                "Can't find PyCharm's settings in `.idea`. Open the project in PyCharm once, \
+                 then run this again",
+            )
+        });
+
+    let data = fs::read_to_string(&module_path)
+        .unwrap_or_else(|_| abort(&format!("Problem reading {:?}", module_path)));
+    let updated =
+        merge_iml(&data, &urls, &pycharm_url(root, pypackages_path)).unwrap_or_else(|| {
+            abort(&format!(
+                "Can't find the project's folder in {:?}",
+                module_path
+            ))
+        });
+    if fs::write(&module_path, updated).is_err() {
+        abort(&format!("Problem writing {:?}", module_path))
+    }
+    util::success(&format!("Updated {:?}", module_path));
+    print_color(
+        &format!(
+            "Choose this interpreter in PyCharm's settings, under Project → Python Interpreter: \
+             {}",
+            python.display()
+        ),
+        Color::Yellow,
+    );
+}
+
+/// `path` as a PyCharm module file writes it: relative to `$MODULE_DIR$`, the project folder,
+/// if it's in it.
+fn pycharm_url(root: &Path, path: &Path) -> String {
+    match path.strip_prefix(root) {
+        Ok(rel) => format!(
+            "file://$MODULE_DIR$/{}",
+            rel.to_string_lossy().replace('\\', "/")
+        ),
+        Err(_) => format!("file://{}", path.to_string_lossy().replace('\\', "/")),
+    }
+}
+
+/// Mark `urls` as source folders in a PyCharm module file, replacing ones under `managed` we
+/// set before. Ones in the project go in its content root; others get their own, just before it,
+/// on one line so we can find them again. `None` if there's no content root for the project.
+fn merge_iml(data: &str, urls: &[String], managed: &str) -> Option<String> {
+    let ours = |line: &str| {
+        line.contains(&format!("url=\"{}", managed))
+            || urls
+                .iter()
+                .any(|u| line.contains(&format!("url=\"{}\"", u)))
+    };
+    let mut result = vec![];
+    let mut found = false;
+
+    for line in data.lines() {
+        let trimmed = line.trim();
+        if (trimmed.starts_with("<sourceFolder") || trimmed.starts_with("<content")) && ours(line) {
+            continue;
+        }
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let main_root = trimmed.starts_with("<content url=\"file://$MODULE_DIR$\"");
+        if !main_root {
+            result.push(line.to_owned());
+            continue;
+        }
+        found = true;
+        for url in urls
+            .iter()
+            .filter(|u| !u.starts_with("file://$MODULE_DIR$"))
+        {
+            result.push(format!(
+                "{}<content url=\"{}\"><sourceFolder url=\"{}\" isTestSource=\"false\" /></content>",
+                indent, url, url
+            ));
+        }
+        result.push(format!("{}<content url=\"file://$MODULE_DIR$\">", indent));
+        for url in urls.iter().filter(|u| u.starts_with("file://$MODULE_DIR$")) {
+            result.push(format!(
+                "{}  <sourceFolder url=\"{}\" isTestSource=\"false\" />",
+                indent, url
+            ));
+        }
+        if trimmed.ends_with("/>") {
+            result.push(format!("{}</content>", indent));
+        }
+    }
+
+    if !found {
+        return None;
+    }
+    let mut result = result.join("\n");
+    if data.ends_with('\n') {
+        result.push('\n');
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vscode_settings_merge() {
+        let mut settings: Map<String, Value> = serde_json::from_str(
+            r#"{
+                "editor.formatOnSave": true,
+                "python.analysis.extraPaths": [
+                    "${workspaceFolder}/__pypackages__/3.10/lib",
+                    "${workspaceFolder}/vendor"
+                ]
+            }"#,
+        )
+        .unwrap();
+        merge_vscode(
+            &mut settings,
+            "${workspaceFolder}/__pypackages__/3.11/.venv/bin/python",
+            &["${workspaceFolder}/__pypackages__/3.11/lib".into()],
+            "${workspaceFolder}/__pypackages__",
+        );
+
+        assert_eq!(settings["editor.formatOnSave"], Value::Bool(true));
+        assert_eq!(
+            settings[INTERPRETER_KEY],
+            "${workspaceFolder}/__pypackages__/3.11/.venv/bin/python"
+        );
+        assert_eq!(
+            settings[EXTRA_PATHS_KEY],
+            serde_json::json!([
+                "${workspaceFolder}/__pypackages__/3.11/lib",
+                "${workspaceFolder}/vendor"
+            ])
+        );
+    }
+
+    #[test]
+    fn iml_merge() {
+        let data = r#"<?xml version="1.0" encoding="UTF-8"?>
+<module type="PYTHON_MODULE" version="4">
+  <component name="NewModuleRootManager">
+    <content url="file://$MODULE_DIR$">
+      <sourceFolder url="file://$MODULE_DIR$/__pypackages__/3.10/lib" isTestSource="false" />
+      <excludeFolder url="file://$MODULE_DIR$/build" />
+    </content>
+    <orderEntry type="inheritedJdk" />
+  </component>
+</module>
+"#;
+        let urls = vec![
+            "file://$MODULE_DIR$/__pypackages__/3.11/lib".to_owned(),
+            "file:///work/shared".to_owned(),
+        ];
+        let expected = r#"<?xml version="1.0" encoding="UTF-8"?>
+<module type="PYTHON_MODULE" version="4">
+  <component name="NewModuleRootManager">
+    <content url="file:///work/shared"><sourceFolder url="file:///work/shared" isTestSource="false" /></content>
+    <content url="file://$MODULE_DIR$">
+      <sourceFolder url="file://$MODULE_DIR$/__pypackages__/3.11/lib" isTestSource="false" />
+      <excludeFolder url="file://$MODULE_DIR$/build" />
+    </content>
+    <orderEntry type="inheritedJdk" />
+  </component>
+</module>
+"#;
+        let merged = merge_iml(data, &urls, "file://$MODULE_DIR$/__pypackages__").unwrap();
+        assert_eq!(merged, expected);
+        // Running it again changes nothing.
+        assert_eq!(
+            merge_iml(&merged, &urls, "file://$MODULE_DIR$/__pypackages__").unwrap(),
+            expected
+        );
+        assert_eq!(merge_iml("<module />", &urls, ""), None);
+    }
+}
@@ -0,0 +1,231 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use termcolor::Color;
+
+use crate::{
+    pyproject::{current, LOCK_FILENAME},
+    util::{self, abort, print_color, print_color_},
+};
+
+#[derive(Debug, Deserialize)]
+struct ProjectData {
+    info: ProjectInfo,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProjectInfo {
+    name: String,
+    version: String,
+    summary: Option<String>,
+    license: Option<String>,
+    home_page: Option<String>,
+    project_urls: Option<HashMap<String, String>>,
+    requires_python: Option<String>,
+    requires_dist: Option<Vec<String>>,
+    provides_extra: Option<Vec<String>>,
+}
+
+/// What `pyflow info` shows, from the index or a local wheel.
+#[derive(Debug, Default, Serialize)]
+struct PackageInfo {
+    name: String,
+    version: String,
+    summary: Option<String>,
+    license: Option<String>,
+    homepage: Option<String>,
+    requires_python: Option<String>,
+    /// The newest release on the index; absent for local metadata.
+    latest_version: Option<String>,
+    /// The version in this project's `pyflow.lock`, if any.
+    locked_version: Option<String>,
+    extras: Vec<String>,
+    dependencies: Vec<String>,
+}
+
+/// Show a package's metadata, from the index, or from a local `.dist-info` folder or wheel.
+/// `version` picks a release other than the latest.
+pub fn info(package: &str, version: Option<&str>, json: bool) {
+    let path = Path::new(package);
+    let mut result = if path.is_dir() || package.ends_with(".whl") {
+        if version.is_some() {
+            abort("`--version` only applies to packages on the index")
+        }
+        local_info(path)
+    } else {
+        index_info(package, version)
+    };
+    result.locked_version = locked_version(&result.name);
+
+    if json {
+        match serde_json::to_string_pretty(&result) {
+            Ok(s) => println!("{}", s),
+            Err(_) => abort("Problem serializing package info"),
+        }
+    } else {
+        print_info(&result);
+    }
+}
+
+fn index_info(name: &str, version: Option<&str>) -> PackageInfo {
+    let info = match version {
+        Some(v) => fetch(&util::index_json_url(name, Some(v)))
+            .unwrap_or_else(|| abort(&format!("Can't find version {} of `{}` on PyPi", v, name))),
+        None => fetch(&util::index_json_url(name, None))
+            .unwrap_or_else(|| abort(&format!("Can't find `{}` on PyPi", name))),
+    };
+
+    // A specific version's page doesn't know about newer ones.
+    let latest_version = match version {
+        Some(_) => fetch(&util::index_json_url(name, None)).map(|i| i.version),
+        None => Some(info.version.clone()),
+    };
+
+    let project_urls = info.project_urls;
+    let homepage = info.home_page.filter(|h| !h.is_empty()).or_else(|| {
+        project_urls.and_then(|urls| {
+            urls.into_iter()
+                .find(|(label, _)| label.eq_ignore_ascii_case("homepage"))
+                .map(|(_, url)| url)
+        })
+    });
+
+    PackageInfo {
+        name: info.name,
+        version: info.version,
+        summary: info.summary.filter(|s| !s.is_empty()),
+        license: info.license.filter(|l| !l.is_empty()),
+        homepage,
+        requires_python: info.requires_python.filter(|r| !r.is_empty()),
+        latest_version,
+        locked_version: None,
+        extras: info.provides_extra.unwrap_or_default(),
+        dependencies: info.requires_dist.unwrap_or_default(),
+    }
+}
+
+fn fetch(url: &str) -> Option<ProjectInfo> {
+    let data: ProjectData = util::http_client()
+        .get(url)
+        .send()
+        .and_then(|r| r.error_for_status()?.json())
+        .ok()?;
+    Some(data.info)
+}
+
+/// Read metadata from a `.dist-info` folder, or a wheel.
+fn local_info(path: &Path) -> PackageInfo {
+    let data = if path.is_dir() {
+        let metadata_path = path.join("METADATA");
+        fs::read_to_string(&metadata_path).unwrap_or_else(|_| {
+            abort(&format!(
+                "Can't find `METADATA` in {}; is it a `.dist-info` folder?",
+                path.display()
+            ))
+        })
+    } else {
+        read_wheel_metadata(path)
+    };
+    let metadata = util::parse_metadata_str(&data);
+
+    PackageInfo {
+        name: metadata.name,
+        version: metadata.version.to_string(),
+        summary: metadata.summary,
+        license: metadata.license,
+        homepage: metadata.home_page,
+        requires_python: metadata.requires_python,
+        extras: metadata.provides_extra,
+        dependencies: metadata
+            .requires_dist
+            .iter()
+            .map(|r| match &r.extra {
+                Some(extra) => format!("{}; extra == \"{}\"", r.to_setup_py_string(), extra),
+                None => r.to_setup_py_string(),
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+fn read_wheel_metadata(path: &Path) -> String {
+    let file =
+        fs::File::open(path).unwrap_or_else(|_| abort(&format!("Can't open {}", path.display())));
+    let mut archive = zip::ZipArchive::new(file).unwrap_or_else(|_| {
+        abort(&format!(
+            "Problem reading the wheel archive: {}. Is it corrupted?",
+            path.display()
+        ))
+    });
+
+    for i in 0..archive.len() {
+        let mut entry = match archive.by_index(i) {
+            Ok(e) => e,
+            Err(_) => continue,
+        };
+        let entry_path = PathBuf::from(entry.name());
+        let in_dist_info = entry_path
+            .parent()
+            .and_then(|p| p.to_str())
+            .is_some_and(|p| p.ends_with(".dist-info") && !p.contains('/'));
+        if in_dist_info && entry_path.file_name().is_some_and(|f| f == "METADATA") {
+            let mut data = String::new();
+            if entry.read_to_string(&mut data).is_ok() {
+                return data;
+            }
+        }
+    }
+    abort(&format!("Can't find `METADATA` in {}", path.display()))
+}
+
+/// The version of `name` in the lock file of the project we're in, if any.
+fn locked_version(name: &str) -> Option<String> {
+    let cfg_path = current::find_config_path()?;
+    let lock = util::read_lock(&cfg_path.with_file_name(LOCK_FILENAME)).ok()?;
+    lock.package?
+        .into_iter()
+        .find(|lp| util::compare_names(&lp.name, name))
+        .map(|lp| lp.version)
+}
+
+fn print_info(info: &PackageInfo) {
+    print_color_(&info.name, Color::Cyan);
+    print_color(&format!(" {}", info.version), Color::White);
+    if let Some(summary) = &info.summary {
+        println!("{}", summary);
+    }
+    println!();
+
+    let fields = [
+        ("License", info.license.clone()),
+        ("Homepage", info.homepage.clone()),
+        ("Requires Python", info.requires_python.clone()),
+        ("Latest version", info.latest_version.clone()),
+        ("Locked version", info.locked_version.clone()),
+    ];
+    for (label, value) in fields.iter() {
+        if let Some(v) = value {
+            print_color_(&format!("{}: ", label), Color::Blue);
+            println!("{}", v);
+        }
+    }
+
+    if !info.extras.is_empty() {
+        print_color_("Extras: ", Color::Blue);
+        println!("{}", info.extras.join(", "));
+    }
+
+    if info.dependencies.is_empty() {
+        print_color("\nNo dependencies", Color::Blue);
+    } else {
+        print_color("\nDependencies:", Color::Blue);
+        for dep in &info.dependencies {
+            println!("  {}", dep);
+        }
+    }
+}
@@ -1,28 +1,315 @@
-use std::path::PathBuf;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use termcolor::Color;
 
 use crate::{
+    dep_types::{Constraint, Req, ReqType, Version},
     files,
-    pyproject::Config,
+    pyproject::{setup_py, Config},
     util::{self, abort},
 };
 
+/// Requirements files `init` imports from, and whether they're for development.
+const REQUIREMENTS_FILES: &[(&str, bool)] = &[
+    ("requirements.txt", false),
+    ("requirements-dev.txt", true),
+    ("dev-requirements.txt", true),
+    ("requirements/dev.txt", true),
+];
+
+/// Requirements found in one of the files `init` imports from.
+#[derive(Debug, Default)]
+struct Source {
+    filename: &'static str,
+    reqs: Vec<Req>,
+    dev_reqs: Vec<Req>,
+}
+
 pub fn init(cfg_filename: &str) {
     let cfg_path = PathBuf::from(cfg_filename);
     if cfg_path.exists() {
         abort("pyproject.toml already exists - not overwriting.")
     }
 
-    let mut cfg = match PathBuf::from("Pipfile").exists() {
-        true => Config::from_pipfile(&PathBuf::from("Pipfile")).unwrap_or_default(),
-        false => Config::default(),
-    };
+    let mut cfg = Config::default();
+    let mut sources = vec![];
+    let mut skipped = vec![];
+    let mut partial = None;
 
-    cfg.py_version = Some(util::prompts::py_vers());
+    if let Some(pipfile) = Config::from_pipfile(&PathBuf::from("Pipfile")) {
+        sources.push(Source {
+            filename: "Pipfile",
+            reqs: pipfile.reqs,
+            dev_reqs: pipfile.dev_reqs,
+        });
+    }
+    let mut index_urls = vec![];
+    for &(filename, dev) in REQUIREMENTS_FILES {
+        if let Some(parsed) = files::parse_requirements_file(Path::new(filename)) {
+            skipped.extend(parsed.skipped);
+            index_urls.extend(parsed.index_url.into_iter().chain(parsed.extra_index_urls));
+            let mut source = Source {
+                filename,
+                ..Default::default()
+            };
+            if dev {
+                source.dev_reqs = parsed.reqs;
+            } else {
+                source.reqs = parsed.reqs;
+            }
+            sources.push(source);
+        }
+    }
+    index_urls.dedup();
+    if let Some(url) = index_urls.first() {
+        cfg.index_url = Some(json_api_url(url));
+    }
+    if let Ok(data) = fs::read_to_string("setup.py") {
+        let setup = setup_py::parse(&data);
+        let (mut reqs, mut bad) = setup.reqs();
+        let (extras, mut extras_bad) = setup.extra_reqs();
+        bad.append(&mut extras_bad);
+        skipped.extend(
+            bad.iter()
+                .map(|spec| format!("`{}` in setup.py, which we couldn't parse", spec)),
+        );
+
+        // Extras are optional dependencies, in groups under `[tool.pyflow.extras]`.
+        for (group, mut group_reqs) in extras {
+            let names = group_reqs.iter().map(|r| r.name.clone()).collect();
+            cfg.extras.insert(group, names);
+            reqs.append(&mut group_reqs);
+        }
+        sources.push(Source {
+            filename: "setup.py",
+            reqs,
+            ..Default::default()
+        });
+        if !setup.dynamic.is_empty() {
+            partial = Some(setup.dynamic.clone());
+        }
+        cfg.name = setup.name;
+        cfg.version = setup.version.and_then(|v| Version::from_str(&v).ok());
+        cfg.python_requires = setup.python_requires;
+    }
+
+    let (reqs, dev_reqs, conflicts) = merge(&sources);
+    cfg.reqs = reqs;
+    cfg.dev_reqs = dev_reqs;
+
+    print_summary(&cfg, &sources, &conflicts, &skipped);
+    if let Some(url) = &cfg.index_url {
+        util::print_color(
+            &format!(
+                "Using {} as the package index, from `--index-url`; pyflow reads its JSON API, \
+                 so check `index-url` in `pyproject.toml` is right",
+                url
+            ),
+            Color::Yellow,
+        );
+    }
+    if index_urls.len() > 1 {
+        util::print_color(
+            &format!(
+                "pyflow uses one package index, so {} aren't used",
+                index_urls[1..].join(", ")
+            ),
+            Color::Yellow,
+        );
+    }
+    if let Some(args) = partial {
+        util::print_color(
+            &format!(
+                "setup.py computes `{}`, so we can't read it without running it; add it to \
+                 `pyproject.toml` yourself",
+                args.join("`, `")
+            ),
+            Color::Yellow,
+        );
+    }
 
-    files::parse_req_dot_text(&mut cfg, &PathBuf::from("requirements.txt"));
+    cfg.py_version = Some(util::prompts::py_vers());
 
     cfg.write_file(&cfg_path);
-    util::print_color("Created `pyproject.toml`", Color::Green);
+    util::print_status("Created `pyproject.toml`", Color::Green);
+}
+
+fn print_summary(cfg: &Config, sources: &[Source], conflicts: &[String], skipped: &[String]) {
+    if sources.is_empty() {
+        return;
+    }
+    for source in sources {
+        util::print_color(
+            &format!(
+                "Found {} dependencies and {} dev dependencies in {}",
+                source.reqs.len(),
+                source.dev_reqs.len(),
+                source.filename
+            ),
+            Color::Blue,
+        );
+    }
+    if let Some(name) = &cfg.name {
+        println!("Name: {}", name);
+    }
+    if let Some(version) = &cfg.version {
+        println!("Version: {}", version);
+    }
+    for (label, reqs) in &[
+        ("Dependencies", &cfg.reqs),
+        ("Dev dependencies", &cfg.dev_reqs),
+    ] {
+        if !reqs.is_empty() {
+            println!("{}:", label);
+            for req in reqs.iter() {
+                println!("  {}", req.to_cfg_string());
+            }
+        }
+    }
+    for conflict in conflicts {
+        util::print_color(conflict, Color::Yellow);
+    }
+    for line in skipped {
+        util::print_color(&format!("Skipped {}", line), Color::Yellow);
+    }
+}
+
+/// pyflow's `index-url` is the index's JSON API; pip's is its simple one.
+fn json_api_url(simple_url: &str) -> String {
+    let url = simple_url.trim_end_matches('/');
+    match url.strip_suffix("/simple") {
+        Some(base) => format!("{}/pypi", base),
+        None => url.to_owned(),
+    }
+}
+
+/// How narrowly a requirement pins its version: an exact version, then any constraint, then
+/// none.
+fn specificity(req: &Req) -> u8 {
+    match req.constraints.as_slice() {
+        [] => 0,
+        [c] if c.type_ == ReqType::Exact => 2,
+        _ => 1,
+    }
+}
+
+fn constraint_str(constraints: &[Constraint]) -> String {
+    if constraints.is_empty() {
+        return "any version".into();
+    }
+    constraints
+        .iter()
+        .map(|c| c.to_string2(false, true))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Combine requirements from all sources, with one per package. Where sources disagree, keep
+/// the more specific constraint, and report the conflict. A package that's both a dependency
+/// and a dev dependency is kept only as a dependency.
+fn merge(sources: &[Source]) -> (Vec<Req>, Vec<Req>, Vec<String>) {
+    let mut merged: Vec<(Req, &str, bool)> = vec![];
+    let mut conflicts = vec![];
+
+    for source in sources {
+        let tagged = source
+            .reqs
+            .iter()
+            .map(|r| (r, false))
+            .chain(source.dev_reqs.iter().map(|r| (r, true)));
+        for (req, dev) in tagged {
+            let existing = merged
+                .iter_mut()
+                .find(|(r, _, _)| util::compare_names(&r.name, &req.name));
+            let (kept, origin, kept_dev) = match existing {
+                Some(e) => e,
+                None => {
+                    merged.push((req.clone(), source.filename, dev));
+                    continue;
+                }
+            };
+
+            *kept_dev = *kept_dev && dev;
+            if kept.constraints == req.constraints || req.constraints.is_empty() {
+                continue;
+            }
+            if !kept.constraints.is_empty() {
+                let (winner, winner_origin) = if specificity(req) > specificity(kept) {
+                    (req, source.filename)
+                } else {
+                    (&*kept, *origin)
+                };
+                conflicts.push(format!(
+                    "`{}`: `{}` from {} conflicts with `{}` from {}; using `{}` from {}",
+                    req.name,
+                    constraint_str(&kept.constraints),
+                    origin,
+                    constraint_str(&req.constraints),
+                    source.filename,
+                    constraint_str(&winner.constraints),
+                    winner_origin
+                ));
+            }
+            if specificity(req) > specificity(kept) {
+                *kept = req.clone();
+                *origin = source.filename;
+            }
+        }
+    }
+
+    let (dev, main): (Vec<_>, Vec<_>) = merged.into_iter().partition(|(_, _, dev)| *dev);
+    (
+        main.into_iter().map(|(r, _, _)| r).collect(),
+        dev.into_iter().map(|(r, _, _)| r).collect(),
+        conflicts,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_urls() {
+        assert_eq!(
+            json_api_url("https://pypi.org/simple/"),
+            "https://pypi.org/pypi"
+        );
+        assert_eq!(
+            json_api_url("https://mirror.example.com/pypi"),
+            "https://mirror.example.com/pypi"
+        );
+    }
+
+    #[test]
+    fn merge_prefers_specific() {
+        let req = |s: &str| Req::from_pip_str(s).unwrap();
+        let sources = vec![
+            Source {
+                filename: "requirements.txt",
+                reqs: vec![req("requests>=2.0"), req("numpy")],
+                dev_reqs: vec![],
+            },
+            Source {
+                filename: "requirements-dev.txt",
+                reqs: vec![],
+                dev_reqs: vec![req("pytest"), req("numpy")],
+            },
+            Source {
+                filename: "setup.py",
+                reqs: vec![req("Requests==2.31.0"), req("numpy>=1.20")],
+                dev_reqs: vec![],
+            },
+        ];
+
+        let (reqs, dev_reqs, conflicts) = merge(&sources);
+        assert_eq!(reqs, vec![req("Requests==2.31.0"), req("numpy>=1.20")]);
+        assert_eq!(dev_reqs, vec![req("pytest")]);
+        assert_eq!(conflicts.len(), 1);
+        assert!(conflicts[0].contains("requirements.txt") && conflicts[0].contains("setup.py"));
+    }
 }
@@ -1,55 +1,150 @@
-use std::path::Path;
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
 
 use termcolor::Color;
 
 use crate::{
-    dep_types::{LockPackage, Version},
-    util::{self, process_reqs, Os, Paths},
-    Config,
+    dep_types::{LockPackage, Req, Version},
+    pyproject::{Hooks, PresentConfig},
+    util::{self, gitignore, paths, process_reqs, prompts::Fallback, Os, Paths},
+    CliConfig,
 };
 
-use util::deps::sync;
+use util::deps::{sync, SyncSummary};
 
 // TODO: Refactor this function
 #[allow(clippy::too_many_arguments)]
 pub fn install(
-    cfg_path: &Path,
-    cfg: &Config,
+    pcfg: &PresentConfig,
     git_path: &Path,
     paths: &Paths,
     found_lock: bool,
-    packages: &[String],
+    added: Vec<Req>,
     dev: bool,
+    no_dev: bool,
     lockpacks: &[LockPackage],
     os: &Os,
     py_vers: &Version,
-    lock_path: &Path,
-) {
+    hooks: &Hooks,
+) -> SyncSummary {
+    let cfg_path = &pcfg.config_path;
     if !cfg_path.exists() {
-        cfg.write_file(cfg_path);
+        pcfg.config.write_file(cfg_path);
     }
 
     if found_lock {
-        util::print_color("Found lockfile", Color::Green);
+        util::print_status("Found lockfile", Color::Green);
     }
 
     // Merge reqs added via cli with those in `pyproject.toml`.
-    let (updated_reqs, up_dev_reqs) = util::merge_reqs(packages, dev, cfg, cfg_path);
+    let (updated_reqs, up_dev_reqs) = util::merge_reqs(added, dev, &pcfg.config, cfg_path);
+    let (updated_reqs, up_dev_reqs) = pcfg.sync_reqs(&updated_reqs, &up_dev_reqs);
 
     let dont_uninstall = util::find_dont_uninstall(&updated_reqs, &up_dev_reqs);
 
     let updated_reqs = process_reqs(updated_reqs, git_path, paths);
     let up_dev_reqs = process_reqs(up_dev_reqs, git_path, paths);
 
-    sync(
+    let summary = sync(
         paths,
         lockpacks,
         &updated_reqs,
         &up_dev_reqs,
+        &pcfg.constraints,
         &dont_uninstall,
         *os,
         py_vers,
-        lock_path,
+        &pcfg.lock_path,
+        no_dev,
+        hooks,
     );
-    util::print_color("Installation complete", Color::Green);
+    if summary.up_to_date > 0 {
+        util::print_status(
+            &format!("{} packages already up to date", summary.up_to_date),
+            Color::Green,
+        );
+    }
+    util::print_status("Installation complete", Color::Green);
+    summary
+}
+
+/// In a git repository that doesn't ignore `__pypackages__`, or the `.venv` with
+/// `env-mode = "venv"`, offer to add it to the `.gitignore` beside it, so its packages aren't
+/// committed by accident. Non-interactively, only warns; the file is never changed without asking.
+pub fn check_gitignore(env_path: &Path) {
+    // Resolve any `..` from `packages-dir`, which may put it outside the repository.
+    let path = match env_path.canonicalize() {
+        Ok(p) => p,
+        Err(_) => return,
+    };
+    let root = match gitignore::find_repo_root(&path) {
+        Some(r) => r,
+        None => return,
+    };
+    if gitignore::is_ignored(&root, &path, true) {
+        return;
+    }
+    let (folder, name) = match (path.parent(), path.file_name()) {
+        (Some(f), Some(n)) => (f, n.to_string_lossy()),
+        _ => return,
+    };
+    let gitignore_path = folder.join(".gitignore");
+    let entry = format!("{}/", name);
+    let message = format!(
+        "Git doesn't ignore {:?}, so its packages may be committed",
+        path
+    );
+
+    if CliConfig::current().non_interactive {
+        log::warn!("{}; add `{}` to {:?}", message, entry, gitignore_path);
+        return;
+    }
+    let declined_path = paths::gitignore_declined_path(&paths::pyflow_path());
+    let key = path.to_string_lossy();
+    let declined = fs::read_to_string(&declined_path).unwrap_or_default();
+    if declined.lines().any(|l| l == key) {
+        return;
+    }
+    util::print_color(&message, Color::Yellow);
+    // An empty fallback, eg from a closed stdin, isn't an answer, so we don't remember it.
+    let answer = util::prompts::prompt(
+        &format!("Add `{}` to {:?}? (yes / no) ", entry, gitignore_path),
+        Fallback::Answer(String::new()),
+    )
+    .to_lowercase();
+    if !answer.starts_with('y') {
+        if answer.starts_with('n') {
+            let saved = declined_path
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| append_line(&declined_path, &key));
+            if let Err(e) = saved {
+                log::warn!("Problem saving the answer to {:?}: {}", declined_path, e);
+            }
+        }
+        return;
+    }
+    if let Err(e) = append_line(&gitignore_path, &entry) {
+        util::print_color(
+            &format!("Problem updating {:?}: {}", gitignore_path, e),
+            Color::Red,
+        );
+    }
+}
+
+/// Add `line` to the end of a file, creating it if needed, and starting a new line if the file
+/// doesn't end with one.
+fn append_line(path: &Path, line: &str) -> io::Result<()> {
+    let existing = fs::read_to_string(path).unwrap_or_default();
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    if !existing.is_empty() && !existing.ends_with('\n') {
+        writeln!(file)?;
+    }
+    writeln!(file, "{}", line)
 }
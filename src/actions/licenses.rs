@@ -0,0 +1,379 @@
+use std::{collections::BTreeMap, path::Path, process};
+
+use serde::Serialize;
+use termcolor::Color;
+
+use crate::util::{self, patterns::DIST_INFO_RE, print_color, print_color_, Metadata};
+
+/// Used for packages whose metadata doesn't name a license we can recognize.
+const UNKNOWN: &str = "UNKNOWN";
+
+/// SPDX identifiers, and the ways packages commonly write them, after `normalize`.
+const ALIASES: &[(&str, &[&str])] = &[
+    ("MIT", &["mit", "expat", "mitx11"]),
+    (
+        "Apache-2.0",
+        &[
+            "apache20",
+            "apache2",
+            "apachev2",
+            "asl20",
+            "apachesoftware20",
+        ],
+    ),
+    (
+        "BSD-2-Clause",
+        &[
+            "bsd2clause",
+            "bsd2",
+            "simplifiedbsd",
+            "freebsd",
+            "2clausebsd",
+        ],
+    ),
+    (
+        "BSD-3-Clause",
+        &[
+            "bsd3clause",
+            "bsd3",
+            "newbsd",
+            "modifiedbsd",
+            "revisedbsd",
+            "3clausebsd",
+        ],
+    ),
+    ("0BSD", &["0bsd", "zeroclausebsd"]),
+    ("ISC", &["isc", "iscl", "isciscl"]),
+    (
+        "PSF-2.0",
+        &[
+            "psf",
+            "psf20",
+            "pythonsoftwarefoundation",
+            "pythonsoftwarefoundationpsf",
+        ],
+    ),
+    (
+        "MPL-2.0",
+        &["mpl20", "mplv2", "mozillapublic20", "mozillapublic20mpl20"],
+    ),
+    ("Unlicense", &["unlicense", "unlicenseunlicense"]),
+    ("Zlib", &["zlib", "zlibzlib", "zliblibpng"]),
+    (
+        "CC0-1.0",
+        &["cc010", "cc0", "cc010universalcc010publicdomaindedication"],
+    ),
+    (
+        "HPND",
+        &["hpnd", "historicalpermissionnoticeanddisclaimerhpnd"],
+    ),
+    (
+        "GPL-2.0-only",
+        &[
+            "gpl20",
+            "gpl20only",
+            "gplv2",
+            "gpl2",
+            "gnugeneralpublicv2gplv2",
+        ],
+    ),
+    (
+        "GPL-2.0-or-later",
+        &[
+            "gpl20orlater",
+            "gplv2+",
+            "gpl2+",
+            "gnugeneralpublicv2orlatergplv2+",
+        ],
+    ),
+    (
+        "GPL-3.0-only",
+        &[
+            "gpl30",
+            "gpl30only",
+            "gplv3",
+            "gpl3",
+            "gnugeneralpublicv3gplv3",
+        ],
+    ),
+    (
+        "GPL-3.0-or-later",
+        &[
+            "gpl30orlater",
+            "gplv3+",
+            "gpl3+",
+            "gnugeneralpublicv3orlatergplv3+",
+        ],
+    ),
+    (
+        "LGPL-2.1-only",
+        &["lgpl21", "lgpl21only", "lgplv21", "lgplv2"],
+    ),
+    (
+        "LGPL-2.1-or-later",
+        &[
+            "lgpl21orlater",
+            "lgplv21+",
+            "lgplv2+",
+            "gnulessergeneralpublicv2orlaterlgplv2+",
+        ],
+    ),
+    (
+        "LGPL-3.0-only",
+        &[
+            "lgpl30",
+            "lgpl30only",
+            "lgplv3",
+            "lgpl3",
+            "gnulessergeneralpublicv3lgplv3",
+        ],
+    ),
+    (
+        "LGPL-3.0-or-later",
+        &[
+            "lgpl30orlater",
+            "lgplv3+",
+            "lgpl3+",
+            "gnulessergeneralpublicv3orlaterlgplv3+",
+        ],
+    ),
+    (
+        "AGPL-3.0-only",
+        &[
+            "agpl30",
+            "agpl30only",
+            "agplv3",
+            "agpl3",
+            "gnuafferogeneralpublicv3",
+        ],
+    ),
+    (
+        "AGPL-3.0-or-later",
+        &[
+            "agpl30orlater",
+            "agplv3+",
+            "agpl3+",
+            "gnuafferogeneralpublicv3orlateragplv3+",
+        ],
+    ),
+    ("EPL-2.0", &["epl20", "eclipsepublic20epl20"]),
+];
+
+/// An installed package, and the license we found for it.
+#[derive(Debug, PartialEq, Serialize)]
+struct PackageLicense {
+    name: String,
+    version: String,
+    license: String,
+}
+
+#[derive(Debug, Serialize)]
+struct LicensesOutput {
+    packages: Vec<PackageLicense>,
+    /// Packages whose license is in `--fail-on`.
+    disallowed: Vec<String>,
+}
+
+/// Report the license of every package installed in `lib_path`, grouped by license. Exits
+/// with 1 if any package's license is in `fail_on`.
+pub fn licenses(lib_path: &Path, json: bool, fail_on: &[String]) {
+    let packages = find_licenses(lib_path);
+    let disallowed: Vec<String> = packages
+        .iter()
+        .filter(|p| is_disallowed(&p.license, fail_on))
+        .map(|p| p.name.clone())
+        .collect();
+
+    if json {
+        util::print_json(&LicensesOutput {
+            packages,
+            disallowed: disallowed.clone(),
+        });
+    } else if packages.is_empty() {
+        print_color("No packages are installed.", Color::Blue);
+    } else {
+        let mut groups: BTreeMap<&str, Vec<&PackageLicense>> = BTreeMap::new();
+        for package in &packages {
+            groups.entry(&package.license).or_default().push(package);
+        }
+        // Keep unrecognized licenses at the end, where they're easy to find.
+        let unknown = groups.remove(UNKNOWN);
+        for (license, packs) in groups.into_iter().chain(unknown.map(|u| (UNKNOWN, u))) {
+            print_color(&format!("{} ({})", license, packs.len()), Color::Cyan);
+            for pack in packs {
+                print_color_(&format!("    {}", pack.name), Color::White);
+                println!(" {}", pack.version);
+            }
+        }
+    }
+
+    if !disallowed.is_empty() {
+        print_color(
+            &format!("Disallowed licenses found in: {}", disallowed.join(", ")),
+            Color::Red,
+        );
+        process::exit(1)
+    }
+}
+
+fn find_licenses(lib_path: &Path) -> Vec<PackageLicense> {
+    if !lib_path.exists() {
+        return vec![];
+    }
+
+    let mut result: Vec<PackageLicense> = util::find_folders(lib_path)
+        .iter()
+        .filter_map(|folder| {
+            let caps = DIST_INFO_RE.captures(folder)?;
+            let metadata_path = lib_path.join(folder).join("METADATA");
+            let license = if metadata_path.exists() {
+                license(&util::parse_metadata(&metadata_path))
+            } else {
+                UNKNOWN.to_owned()
+            };
+            Some(PackageLicense {
+                name: caps.get(1)?.as_str().to_owned(),
+                version: caps.get(2)?.as_str().to_owned(),
+                license,
+            })
+        })
+        .collect();
+    result.sort_by_key(|a| a.name.to_lowercase());
+    result
+}
+
+/// The license a package declares: its SPDX expression if it has one, otherwise the `License`
+/// field or license classifiers, as SPDX identifiers where we recognize them.
+fn license(metadata: &Metadata) -> String {
+    if let Some(expr) = &metadata.license_expression {
+        return expr.clone();
+    }
+
+    // Some packages paste their whole license text here; that isn't a name.
+    let field =
+        metadata.license.as_deref().map(str::trim).filter(|l| {
+            !l.is_empty() && l.len() <= 80 && !l.to_lowercase().starts_with("copyright")
+        });
+
+    let mut classifiers: Vec<&str> = metadata
+        .classifiers
+        .iter()
+        .filter_map(|c| c.strip_prefix("License ::"))
+        .filter_map(|c| c.rsplit("::").next())
+        .map(str::trim)
+        .filter(|c| *c != "OSI Approved")
+        .collect();
+    classifiers.dedup();
+
+    if let Some(id) = field.and_then(spdx) {
+        return id.to_owned();
+    }
+    let ids: Vec<&str> = classifiers.iter().filter_map(|c| spdx(c)).collect();
+    if !ids.is_empty() {
+        return ids.join(" OR ");
+    }
+    if let Some(f) = field {
+        return f.to_owned();
+    }
+    if !classifiers.is_empty() {
+        return classifiers.join(" OR ");
+    }
+    UNKNOWN.to_owned()
+}
+
+/// Lowercase, without punctuation or filler words, so `Apache License, Version 2.0` and
+/// `Apache-2.0` compare equal.
+fn normalize(license: &str) -> String {
+    license
+        .to_lowercase()
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '+'))
+        .filter(|w| {
+            ![
+                "", "license", "licence", "licensed", "version", "the", "under",
+            ]
+            .contains(w)
+        })
+        .collect()
+}
+
+/// The SPDX identifier for a license name, if we recognize it.
+pub fn spdx(license: &str) -> Option<&'static str> {
+    let normalized = normalize(license);
+    ALIASES
+        .iter()
+        .find(|(id, aliases)| normalize(id) == normalized || aliases.contains(&normalized.as_str()))
+        .map(|(id, _)| *id)
+}
+
+/// Whether `license`, which may be an expression like `MIT OR Apache-2.0`, names any license
+/// in `fail_on`.
+fn is_disallowed(license: &str, fail_on: &[String]) -> bool {
+    let parts: Vec<&str> = license
+        .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+        .filter(|p| !p.is_empty())
+        .collect();
+    fail_on.iter().any(|disallowed| {
+        parts.iter().any(|p| p.eq_ignore_ascii_case(disallowed))
+            || license.eq_ignore_ascii_case(disallowed)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::parse_metadata_str;
+    use rstest::rstest;
+
+    #[rstest(
+        input,
+        expected,
+        case("MIT License", Some("MIT")),
+        case("Apache License, Version 2.0", Some("Apache-2.0")),
+        case("apache-2.0", Some("Apache-2.0")),
+        case("BSD 3-Clause", Some("BSD-3-Clause")),
+        case("GNU General Public License v3 (GPLv3)", Some("GPL-3.0-only")),
+        case(
+            "GNU Lesser General Public License v3 or later (LGPLv3+)",
+            Some("LGPL-3.0-or-later")
+        ),
+        case("The Unlicense (Unlicense)", Some("Unlicense")),
+        case("Python Software Foundation License", Some("PSF-2.0")),
+        case("BSD", None),
+        case("Proprietary", None)
+    )]
+    fn spdx_ids(input: &str, expected: Option<&str>) {
+        assert_eq!(spdx(input), expected);
+    }
+
+    #[test]
+    fn package_license() {
+        let from = |headers: &str| license(&parse_metadata_str(headers));
+        assert_eq!(
+            from("Name: a\nLicense-Expression: MIT OR Apache-2.0\nLicense: BSD\n"),
+            "MIT OR Apache-2.0"
+        );
+        assert_eq!(from("Name: a\nLicense: MIT License\n"), "MIT");
+        assert_eq!(
+            from("Name: a\nLicense: UNKNOWN\nClassifier: License :: OSI Approved :: BSD License\n"),
+            "BSD License"
+        );
+        assert_eq!(
+            from("Name: a\nLicense: Copyright 2020 Someone\nClassifier: License :: OSI Approved :: MIT License\n"),
+            "MIT"
+        );
+        assert_eq!(from("Name: a\nLicense: Dual\n"), "Dual");
+        assert_eq!(
+            from("Name: a\nClassifier: Programming Language :: Python\n"),
+            UNKNOWN
+        );
+    }
+
+    #[test]
+    fn fail_on() {
+        let fail_on = vec!["GPL-3.0-only".to_string(), "AGPL-3.0-only".to_string()];
+        assert!(is_disallowed("GPL-3.0-only", &fail_on));
+        assert!(is_disallowed("MIT OR (gpl-3.0-only)", &fail_on));
+        assert!(!is_disallowed("GPL-3.0-or-later", &fail_on));
+        assert!(!is_disallowed("MIT", &fail_on));
+    }
+}
@@ -1,28 +1,63 @@
-use std::{path::Path, process};
+use std::{path::Path, process, str::FromStr};
 
+use serde::Serialize;
 use termcolor::Color;
 
 use crate::{
-    dep_types::Req,
-    pyproject,
+    cli_options::Within,
+    dep_resolution::res,
+    dep_types::{Constraint, LockPackage, Req, ReqType, Version},
     util::{self, abort, print_color, print_color_},
 };
 
+/// A locked package with a newer release on the index.
+#[derive(Debug, Serialize)]
+pub(super) struct Outdated {
+    pub(super) name: String,
+    pub(super) current: String,
+    /// The newest release the project's constraints and Python version allow, if any.
+    pub(super) latest_compatible: Option<String>,
+    latest: String,
+    direct: bool,
+}
+
+/// A package in the environment, for `list --json`.
+#[derive(Debug, PartialEq, Serialize)]
+struct InstalledPackage {
+    name: String,
+    /// Absent for path requirements, which aren't installed into `__pypackages__`.
+    version: Option<String>,
+    /// Declared in `pyproject.toml`, rather than pulled in by another package.
+    direct: bool,
+    /// Loaded from a local path, so edits take effect without reinstalling.
+    editable: bool,
+    path: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ListOutput {
+    packages: Vec<InstalledPackage>,
+    scripts: Vec<String>,
+}
+
 /// List all installed dependencies and console scripts, by examining the `libs` and `bin` folders.
-/// Also include path requirements, which won't appear in the `lib` folder.
-pub fn list(lib_path: &Path, path_reqs: &[Req]) {
-    // This part check that project and venvs exists
-    let pcfg = pyproject::current::get_config().unwrap_or_else(|| process::exit(1));
-    let num_venvs = util::find_venvs(&pcfg.pypackages_path).len();
-
-    if !pcfg.config_path.exists() && num_venvs == 0 {
-        abort("Can't find a project in this directory")
-    } else if num_venvs == 0 {
+/// Also include path requirements, which won't appear in the `lib` folder. `reqs` are the
+/// project's direct requirements, including dev ones.
+pub fn list(lib_path: &Path, entry_pt_path: &Path, reqs: &[Req], json: bool) {
+    if !lib_path.exists() {
         abort("There's no python environment set up for this project")
     }
 
     let installed = util::find_installed(lib_path);
-    let scripts = find_console_scripts(&lib_path.join("../bin"));
+    let scripts = find_console_scripts(entry_pt_path);
+
+    if json {
+        util::print_json(&ListOutput {
+            packages: installed_packages(&installed, reqs),
+            scripts,
+        });
+        return;
+    }
 
     if installed.is_empty() {
         print_color("No packages are installed.", Color::Blue); // Dark
@@ -32,7 +67,7 @@ pub fn list(lib_path: &Path, path_reqs: &[Req]) {
             print_color_(&name, Color::Cyan);
             print_color(&format!("=={}", version.to_string_color()), Color::White);
         }
-        for req in path_reqs {
+        for req in reqs.iter().filter(|r| r.path.is_some()) {
             print_color_(&req.name, Color::Cyan);
             print_color(
                 &format!(", at path: {}", req.path.as_ref().unwrap()),
@@ -51,6 +86,183 @@ pub fn list(lib_path: &Path, path_reqs: &[Req]) {
     }
 }
 
+/// Installed packages, marked as direct where they're in `reqs`, followed by path requirements.
+fn installed_packages(
+    installed: &[(String, Version, Vec<String>)],
+    reqs: &[Req],
+) -> Vec<InstalledPackage> {
+    let mut result: Vec<InstalledPackage> = installed
+        .iter()
+        .map(|(name, version, _tops)| InstalledPackage {
+            name: name.clone(),
+            version: Some(version.to_string()),
+            direct: reqs.iter().any(|r| util::compare_names(&r.name, name)),
+            editable: false,
+            path: None,
+        })
+        .collect();
+    for req in reqs {
+        if let Some(path) = &req.path {
+            result.push(InstalledPackage {
+                name: req.name.clone(),
+                version: None,
+                direct: true,
+                editable: true,
+                path: Some(path.clone()),
+            });
+        }
+    }
+    result
+}
+
+/// List locked packages with newer releases on the index. Direct dependencies are checked
+/// against their constraints in `pyproject.toml`; transitive ones against the Python version
+/// only. With `within`, only list packages with a compatible release inside that bound. Exits
+/// with 1 if anything is outdated and `exit_code` is set.
+pub fn outdated(
+    lockpacks: &[LockPackage],
+    reqs: &[Req],
+    py_vers: &Version,
+    within: Option<Within>,
+    json: bool,
+    exit_code: bool,
+) {
+    let result = find_outdated(lockpacks, reqs, py_vers, within);
+
+    if json {
+        match serde_json::to_string_pretty(&result) {
+            Ok(s) => println!("{}", s),
+            Err(_) => abort("Problem serializing outdated packages"),
+        }
+    } else {
+        print_outdated(&result);
+    }
+
+    if exit_code && !result.is_empty() {
+        process::exit(1)
+    }
+}
+
+/// Locked packages with newer releases on the index, sorted by name. With `within`, the
+/// newest compatible release must be inside that bound, and packages without one are left out.
+pub(super) fn find_outdated(
+    lockpacks: &[LockPackage],
+    reqs: &[Req],
+    py_vers: &Version,
+    within: Option<Within>,
+) -> Vec<Outdated> {
+    let mut result = vec![];
+    for lp in lockpacks {
+        // Packages from git or a path aren't on the index.
+        if let Some(source) = &lp.source {
+            if !source.starts_with("pypi+") {
+                continue;
+            }
+        }
+        let current = match Version::from_str(&lp.version) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        let direct = reqs.iter().find(|r| util::compare_names(&r.name, &lp.name));
+        let mut constraints = direct.map(|r| r.constraints.clone()).unwrap_or_default();
+        if let Some(w) = within {
+            constraints.push(upper_bound(&current, w));
+        }
+
+        let (latest, latest_compatible) =
+            match res::get_latest_versions(&lp.name, &constraints, py_vers) {
+                Ok(v) => v,
+                Err(e) => {
+                    util::print_color(
+                        &format!("Problem checking {} on the index: {}", lp.name, e),
+                        Color::Yellow,
+                    );
+                    continue;
+                }
+            };
+        let latest_compatible = latest_compatible.filter(|v| *v > current);
+        if latest <= current || (within.is_some() && latest_compatible.is_none()) {
+            continue;
+        }
+        result.push(Outdated {
+            name: lp.name.clone(),
+            current: current.to_string(),
+            latest_compatible: latest_compatible.map(|v| v.to_string()),
+            latest: latest.to_string(),
+            direct: direct.is_some(),
+        });
+    }
+    result.sort_by_key(|a| a.name.to_lowercase());
+    result
+}
+
+/// The lowest version outside `within` of `current`: the next major version for `minor`, and
+/// the next minor one for `patch`.
+fn upper_bound(current: &Version, within: Within) -> Constraint {
+    let major = current.major.unwrap_or(0);
+    let bound = match within {
+        Within::Minor => Version::new(major + 1, 0, 0),
+        Within::Patch => Version::new(major, current.minor.unwrap_or(0) + 1, 0),
+    };
+    Constraint::new(ReqType::Lt, bound)
+}
+
+fn print_outdated(packages: &[Outdated]) {
+    if packages.is_empty() {
+        print_color("All locked packages are up to date.", Color::Green);
+        return;
+    }
+
+    let headers = ["Package", "Current", "Compatible", "Latest", "Type"];
+    let rows: Vec<[String; 5]> = packages
+        .iter()
+        .map(|p| {
+            [
+                p.name.clone(),
+                p.current.clone(),
+                p.latest_compatible.clone().unwrap_or_else(|| "-".into()),
+                p.latest.clone(),
+                if p.direct { "direct" } else { "transitive" }.into(),
+            ]
+        })
+        .collect();
+
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let line = |cells: &[String]| -> String {
+        cells
+            .iter()
+            .zip(widths.iter())
+            .map(|(c, w)| format!("{:<w$}", c, w = w))
+            .collect::<Vec<String>>()
+            .join("  ")
+            .trim_end()
+            .to_owned()
+    };
+    let headers: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
+    print_color(&line(&headers), Color::Blue);
+    for row in &rows {
+        println!("{}", line(row));
+    }
+}
+
+/// What `python -m venv` puts in a venv's `bin`, besides the interpreter.
+const VENV_SCRIPTS: &[&str] = &[
+    "activate",
+    "activate.bat",
+    "activate.csh",
+    "activate.fish",
+    "Activate.ps1",
+    "deactivate.bat",
+    "python.exe",
+    "pythonw.exe",
+];
+
 /// Find console scripts installed, by browsing the (custom) bin folder
 pub fn find_console_scripts(bin_path: &Path) -> Vec<String> {
     let mut result = vec![];
@@ -63,9 +275,80 @@ pub fn find_console_scripts(bin_path: &Path) -> Vec<String> {
         .expect("Trouble opening bin path")
         .flatten()
     {
-        if entry.file_type().unwrap().is_file() {
-            result.push(entry.file_name().to_str().unwrap().to_owned())
+        let name = entry.file_name().to_string_lossy().to_string();
+        // With `env-mode = "venv"`, scripts are installed beside the venv's own.
+        if entry.file_type().unwrap().is_file() && !VENV_SCRIPTS.contains(&name.as_str()) {
+            result.push(name)
         }
     }
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_json() {
+        let installed = vec![
+            ("Requests".to_owned(), Version::new(2, 31, 0), vec![]),
+            ("idna".to_owned(), Version::new(3, 4, 0), vec![]),
+        ];
+        let mut local = Req::from_pip_str("mylib").unwrap();
+        local.path = Some("../mylib".into());
+        let reqs = vec![Req::from_pip_str("requests>=2.0").unwrap(), local];
+
+        let output = ListOutput {
+            packages: installed_packages(&installed, &reqs),
+            scripts: vec!["normalizer".into()],
+        };
+        assert_eq!(
+            serde_json::to_string_pretty(&output).unwrap(),
+            r#"{
+  "packages": [
+    {
+      "name": "Requests",
+      "version": "2.31.0",
+      "direct": true,
+      "editable": false,
+      "path": null
+    },
+    {
+      "name": "idna",
+      "version": "3.4.0",
+      "direct": false,
+      "editable": false,
+      "path": null
+    },
+    {
+      "name": "mylib",
+      "version": null,
+      "direct": true,
+      "editable": true,
+      "path": "../mylib"
+    }
+  ],
+  "scripts": [
+    "normalizer"
+  ]
+}"#
+        );
+    }
+
+    #[test]
+    fn update_bounds() {
+        let v = |s: &str| Version::from_str(s).unwrap();
+        let minor = upper_bound(&v("2.31.0"), Within::Minor);
+        assert!(minor.is_compatible(&v("2.32.3")));
+        assert!(!minor.is_compatible(&v("3.0.0")));
+
+        let patch = upper_bound(&v("2.31.0"), Within::Patch);
+        assert!(patch.is_compatible(&v("2.31.5")));
+        assert!(!patch.is_compatible(&v("2.32.0")));
+
+        // Calendar versions: the year is the major version.
+        let calver = upper_bound(&v("2024.3.1"), Within::Minor);
+        assert!(calver.is_compatible(&v("2024.12.0")));
+        assert!(!calver.is_compatible(&v("2025.1.0")));
+    }
+}
@@ -1,19 +1,77 @@
+mod add;
+mod audit;
+mod cache;
+mod check;
+mod clean;
 mod clear;
+mod commit_hooks;
+mod completions;
+mod config;
+mod dockerfile;
+mod doctor;
+mod env;
+mod env_create;
+mod export;
+mod ide;
+mod info;
 mod init;
 mod install;
+mod licenses;
 mod list;
 mod new;
 mod package;
+mod poetry;
+mod python;
+mod remove;
 mod reset;
 mod run;
+mod script_envs;
+mod scripts;
+mod search;
+mod self_update;
+mod shell;
 mod switch;
+mod sync;
+mod update;
+mod verify;
+mod version;
+mod which;
 
+pub use add::reqs_to_add;
+pub use audit::audit;
+pub use cache::cache;
+pub use check::check;
+pub use clean::{check_stale_envs, clean, CleanTargets};
 pub use clear::clear;
+pub use commit_hooks::hooks;
+pub use completions::{complete, completions};
+pub use config::config;
+pub use dockerfile::dockerfile;
+pub use doctor::doctor;
+pub use env::env;
+pub use env_create::create_env;
+pub use export::{export, Selection};
+pub use ide::ide;
+pub use info::info;
 pub use init::init;
-pub use install::install;
-pub use list::list;
-pub use new::new;
+pub use install::{check_gitignore, install};
+pub use licenses::{licenses, spdx};
+pub use list::{list, outdated};
+pub use new::{new, Template};
 pub use package::package;
+pub use poetry::export_poetry;
+pub use python::python;
+pub use remove::remove;
 pub use reset::reset;
-pub use run::run;
+pub use run::{run, working_dir};
+pub use script_envs::script_envs;
+pub use scripts::scripts;
+pub use search::{refresh_index, search};
+pub use self_update::self_cmd;
+pub use shell::shell;
 pub use switch::switch;
+pub use sync::sync;
+pub use update::update;
+pub use verify::verify;
+pub use version::version;
+pub use which::which;
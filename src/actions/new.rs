@@ -1,14 +1,16 @@
 use std::{
+    collections::HashMap,
     error::Error,
     fs,
     path::{Path, PathBuf},
 };
 
+use regex::Regex;
 use termcolor::Color;
 
 use crate::{
     commands,
-    util::{self, abort, success},
+    util::{self, abort, prompts::Fallback, success},
     Config,
 };
 
@@ -32,38 +34,310 @@ Problem creating the project. This may be due to a permissions problem.
 If on linux, please try again with `sudo`.
 "#};
 
-pub fn new(name: &str) {
-    if new_internal(name).is_err() {
+/// Top-level standard library modules; a project with one of these names would shadow it.
+pub(super) const STDLIB_MODULES: &[&str] = &[
+    "abc",
+    "argparse",
+    "array",
+    "ast",
+    "asyncio",
+    "base64",
+    "bisect",
+    "builtins",
+    "bz2",
+    "calendar",
+    "cmath",
+    "code",
+    "codecs",
+    "collections",
+    "colorsys",
+    "configparser",
+    "contextlib",
+    "copy",
+    "csv",
+    "ctypes",
+    "curses",
+    "dataclasses",
+    "datetime",
+    "dbm",
+    "decimal",
+    "difflib",
+    "dis",
+    "doctest",
+    "email",
+    "encodings",
+    "enum",
+    "errno",
+    "faulthandler",
+    "filecmp",
+    "fileinput",
+    "fnmatch",
+    "fractions",
+    "ftplib",
+    "functools",
+    "gc",
+    "getopt",
+    "getpass",
+    "gettext",
+    "glob",
+    "graphlib",
+    "gzip",
+    "hashlib",
+    "heapq",
+    "hmac",
+    "html",
+    "http",
+    "imaplib",
+    "importlib",
+    "inspect",
+    "io",
+    "ipaddress",
+    "itertools",
+    "json",
+    "keyword",
+    "linecache",
+    "locale",
+    "logging",
+    "lzma",
+    "mailbox",
+    "marshal",
+    "math",
+    "mimetypes",
+    "mmap",
+    "multiprocessing",
+    "netrc",
+    "numbers",
+    "operator",
+    "os",
+    "pathlib",
+    "pdb",
+    "pickle",
+    "pkgutil",
+    "platform",
+    "plistlib",
+    "poplib",
+    "posix",
+    "pprint",
+    "profile",
+    "pstats",
+    "pty",
+    "pwd",
+    "queue",
+    "random",
+    "re",
+    "readline",
+    "reprlib",
+    "resource",
+    "runpy",
+    "sched",
+    "secrets",
+    "select",
+    "selectors",
+    "shelve",
+    "shlex",
+    "shutil",
+    "signal",
+    "site",
+    "smtplib",
+    "socket",
+    "socketserver",
+    "sqlite3",
+    "ssl",
+    "stat",
+    "statistics",
+    "string",
+    "struct",
+    "subprocess",
+    "symtable",
+    "sys",
+    "sysconfig",
+    "syslog",
+    "tarfile",
+    "tempfile",
+    "termios",
+    "test",
+    "textwrap",
+    "threading",
+    "time",
+    "timeit",
+    "tkinter",
+    "token",
+    "tokenize",
+    "tomllib",
+    "trace",
+    "traceback",
+    "tracemalloc",
+    "tty",
+    "turtle",
+    "types",
+    "typing",
+    "unicodedata",
+    "unittest",
+    "urllib",
+    "uuid",
+    "venv",
+    "warnings",
+    "wave",
+    "weakref",
+    "webbrowser",
+    "winreg",
+    "wsgiref",
+    "xml",
+    "xmlrpc",
+    "zipapp",
+    "zipfile",
+    "zipimport",
+    "zlib",
+    "zoneinfo",
+];
+
+/// The layout `pyflow new` creates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Template {
+    /// A package folder next to `pyproject.toml`.
+    Default,
+    /// A `src/<name>` package with a `py.typed` marker, and tests.
+    Lib,
+    /// A package with a `__main__.py`, and a script that runs it.
+    App,
+    /// Only `pyproject.toml`.
+    Minimal,
+}
+
+pub fn new(name: &str, template: Template, license: Option<&str>) {
+    let license = license.map(|l| {
+        crate::build::parse_expression(l)
+            .unwrap_or_else(|e| abort(&format!("`--license` must be an SPDX expression. {}", e)))
+    });
+    let warnings = name_warnings(name);
+    if !warnings.is_empty() {
+        for warning in &warnings {
+            util::print_color(warning, Color::Yellow);
+        }
+        if !util::prompts::confirm(
+            "Create the project anyway?",
+            Fallback::Fail("Pick a name without these problems."),
+        ) {
+            abort("Not creating the project")
+        }
+    }
+
+    if new_internal(name, template, license.as_deref()).is_err() {
         abort(NEW_ERROR_MESSAGE);
     }
     success(&format!("Created a new Python project named {}", name))
 }
 
-// TODO: Join this function after refactoring
-/// Create a template directory for a python project.
-fn new_internal(name: &str) -> Result<(), Box<dyn Error>> {
-    if !PathBuf::from(name).exists() {
-        fs::create_dir_all(format!("{}/{}", name, name.replace('-', "_")))?;
-        fs::File::create(format!("{}/{}/__init__.py", name, name.replace('-', "_")))?;
-        fs::File::create(format!("{}/README.md", name))?;
-        fs::File::create(format!("{}/.gitignore", name))?;
+/// The name the project's package is imported by.
+fn module_name(name: &str) -> String {
+    name.replace(['-', '.'], "_")
+}
+
+/// Problems with a project name that we can work around, but probably shouldn't.
+fn name_warnings(name: &str) -> Vec<String> {
+    let mut result = vec![];
+    // https://packaging.python.org/en/latest/specifications/name-normalization/
+    let valid_re = Regex::new(r"(?i)^([a-z0-9]|[a-z0-9][a-z0-9._-]*[a-z0-9])$").unwrap();
+    if !valid_re.is_match(name) {
+        result.push(format!(
+            "`{}` isn't a valid package name: use letters, numbers, `-`, `_`, and `.`, starting \
+             and ending with a letter or number",
+            name
+        ));
+    }
+
+    let module = module_name(name);
+    if module.starts_with(|c: char| c.is_ascii_digit()) {
+        result.push(format!(
+            "`{}` starts with a number, so it can't be imported",
+            module
+        ));
+    }
+    if STDLIB_MODULES.contains(&module.to_lowercase().as_str()) {
+        result.push(format!(
+            "`{}` is the name of a standard library module, which this project would shadow",
+            module
+        ));
     }
+    result
+}
 
-    let readme_init = &format!("# {}\n\n{}", name, "(A description)");
+/// Write a file, unless it already exists.
+fn write_new(path: &Path, contents: &str) -> Result<(), Box<dyn Error>> {
+    if !path.exists() {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, contents)?;
+    }
+    Ok(())
+}
 
-    fs::write(format!("{}/.gitignore", name), GITIGNORE_INIT)?;
-    fs::write(format!("{}/README.md", name), readme_init)?;
+// TODO: Join this function after refactoring
+/// Create a template directory for a python project.
+fn new_internal(
+    name: &str,
+    template: Template,
+    license: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let root = PathBuf::from(name);
+    let module = module_name(name);
+    fs::create_dir_all(&root)?;
 
-    let cfg = Config {
+    let mut cfg = Config {
         name: Some(name.to_string()),
         authors: util::get_git_author(),
-        py_version: Some(util::prompts::py_vers()),
+        license: license.map(|l| l.to_owned()),
         ..Default::default()
     };
 
-    cfg.write_file(&PathBuf::from(format!("{}/pyproject.toml", name)));
+    match template {
+        Template::Default => {
+            write_new(&root.join(&module).join("__init__.py"), "")?;
+        }
+        Template::Lib => {
+            let package = root.join("src").join(&module);
+            write_new(&package.join("__init__.py"), "__version__ = \"0.1.0\"\n")?;
+            // Marks the package as having type hints; PEP 561.
+            write_new(&package.join("py.typed"), "")?;
+            write_new(&root.join("tests").join("__init__.py"), "")?;
+            write_new(
+                &root.join("tests").join(format!("test_{}.py", module)),
+                &format!(
+                    "import {0}\n\n\ndef test_version():\n    assert {0}.__version__ == \"0.1.0\"\n",
+                    module
+                ),
+            )?;
+            cfg.package_dir = Some("src".into());
+        }
+        Template::App => {
+            write_new(&root.join(&module).join("__init__.py"), "")?;
+            write_new(
+                &root.join(&module).join("__main__.py"),
+                &format!(
+                    "def main():\n    print(\"Hello from {}!\")\n\n\nif __name__ == \"__main__\":\n    main()\n",
+                    name
+                ),
+            )?;
+            let mut scripts = HashMap::new();
+            scripts.insert(name.to_owned(), format!("{}.__main__:main", module));
+            cfg.scripts = scripts;
+        }
+        Template::Minimal => (),
+    }
 
-    if commands::git_init(Path::new(name)).is_err() {
+    if template != Template::Minimal {
+        write_new(&root.join(".gitignore"), GITIGNORE_INIT)?;
+        write_new(
+            &root.join("README.md"),
+            &format!("# {}\n\n{}", name, "(A description)"),
+        )?;
+    }
+
+    cfg.py_version = Some(util::prompts::py_vers());
+    cfg.write_file(&root.join("pyproject.toml"));
+
+    if template != Template::Minimal && commands::git_init(&root).is_err() {
         util::print_color(
             "Unable to initialize a git repo for your project",
             Color::Yellow, // Dark
@@ -72,3 +346,17 @@ fn new_internal(name: &str) -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name_checks() {
+        assert!(name_warnings("my-project").is_empty());
+        assert!(name_warnings("Flask_Thing.ext").is_empty());
+        assert_eq!(name_warnings("json").len(), 1);
+        assert_eq!(name_warnings("-bad name").len(), 1);
+        assert_eq!(name_warnings("3d-tools").len(), 1);
+    }
+}
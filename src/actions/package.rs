@@ -1,8 +1,7 @@
-use std::path::Path;
-
 use crate::{
     build,
     dep_types::{LockPackage, Version},
+    pyproject::{Hooks, PresentConfig},
     util::{self, deps::sync},
 };
 
@@ -11,20 +10,24 @@ pub fn package(
     lockpacks: &[LockPackage],
     os: util::Os,
     py_vers: &Version,
-    lock_path: &Path,
-    cfg: &crate::Config,
+    pcfg: &PresentConfig,
     extras: &[String],
+    hooks: &Hooks,
 ) {
+    let (reqs, dev_reqs) = pcfg.sync_reqs(&pcfg.config.reqs, &pcfg.config.dev_reqs);
     sync(
         paths,
         lockpacks,
-        &cfg.reqs,
-        &cfg.dev_reqs,
-        &util::find_dont_uninstall(&cfg.reqs, &cfg.dev_reqs),
+        &reqs,
+        &dev_reqs,
+        &pcfg.constraints,
+        &util::find_dont_uninstall(&reqs, &dev_reqs),
         os,
         py_vers,
-        lock_path,
+        &pcfg.lock_path,
+        false,
+        hooks,
     );
 
-    build::build(lockpacks, paths, cfg, extras)
+    build::build(lockpacks, paths, &pcfg.config, extras)
 }
@@ -0,0 +1,389 @@
+//! `pyflow export --format poetry`: write the project's config as `[tool.poetry]` tables, so it
+//! can move to Poetry. Unlike the other formats, this comes from `pyproject.toml`, not the lock.
+
+use std::{fs, path::Path};
+
+use crate::{
+    dep_types::{Constraint, Req, ReqType, Version},
+    pyproject::{toml_key, toml_quote, Config},
+    util::{self, abort, hooks, Os},
+};
+
+/// Render `cfg` as Poetry tables, and write them to `output`, into `pyproject.toml` with
+/// `write`, or to stdout.
+pub fn export_poetry(cfg: &Config, cfg_path: &Path, output: Option<&Path>, write: bool) {
+    let mut as_is = vec![];
+    let rendered = poetry(cfg, &mut as_is);
+    if !as_is.is_empty() {
+        log::warn!(
+            "Poetry has no equivalent for these, so they're written as they are; check them \
+             before switching: {}",
+            as_is.join(", ")
+        );
+    }
+
+    if write {
+        let data = util::loaded::read_to_string(cfg_path)
+            .unwrap_or_else(|_| abort(&format!("Problem reading {:?}", cfg_path)));
+        if util::loaded::write(cfg_path, replace_poetry_tables(&data, &rendered)).is_err() {
+            abort(&format!("Problem writing {:?}", cfg_path))
+        }
+        util::success(&format!("Added `[tool.poetry]` to {:?}", cfg_path));
+        return;
+    }
+    match output {
+        Some(path) => {
+            if fs::write(path, rendered).is_err() {
+                abort(&format!("Problem writing {:?}", path))
+            }
+            log::info!("Exported `[tool.poetry]` to {:?}", path);
+        }
+        None => print!("{}", rendered),
+    }
+}
+
+/// `[tool.poetry]`, and its dependency, extras, and script tables. Anything Poetry can't
+/// express the same way is added to `as_is`.
+fn poetry(cfg: &Config, as_is: &mut Vec<String>) -> String {
+    let mut result = String::from("[tool.poetry]\n");
+    let string = |key: &str, value: Option<String>| match value {
+        Some(v) => format!("{} = {}\n", key, toml_quote(&v)),
+        None => String::new(),
+    };
+    result.push_str(&string("name", cfg.name.clone()));
+    result.push_str(&string(
+        "version",
+        cfg.version.as_ref().map(Version::to_string),
+    ));
+    result.push_str(&string("description", cfg.description.clone()));
+    // Poetry requires `authors`, even if it's empty.
+    result.push_str(&format!("authors = {}\n", list(&cfg.authors)));
+    result.push_str(&string("license", cfg.license.clone()));
+    result.push_str(&string("readme", cfg.readme.clone()));
+    result.push_str(&string("homepage", cfg.homepage.clone()));
+    result.push_str(&string("repository", cfg.repository.clone()));
+    if !cfg.keywords.is_empty() {
+        result.push_str(&format!("keywords = {}\n", list(&cfg.keywords)));
+    }
+    if !cfg.classifiers.is_empty() {
+        result.push_str(&format!("classifiers = {}\n", list(&cfg.classifiers)));
+    }
+
+    result.push_str("\n[tool.poetry.dependencies]\n");
+    let python = match (&cfg.python_requires, &cfg.py_version) {
+        (Some(requires), _) => Some(requires.clone()),
+        (None, Some(v)) => Some(format!(
+            "^{}.{}",
+            v.major.unwrap_or(3),
+            v.minor.unwrap_or(0)
+        )),
+        (None, None) => None,
+    };
+    if let Some(python) = python {
+        result.push_str(&format!("python = {}\n", toml_quote(&python)));
+    }
+    let optional: Vec<&String> = cfg.extras.values().flatten().collect();
+    for req in sorted(&cfg.reqs) {
+        let optional = optional.iter().any(|n| util::compare_names(n, &req.name));
+        result.push_str(&dependency(req, optional, as_is));
+    }
+
+    if !cfg.dev_reqs.is_empty() {
+        result.push_str("\n[tool.poetry.dev-dependencies]\n");
+        for req in sorted(&cfg.dev_reqs) {
+            result.push_str(&dependency(req, false, as_is));
+        }
+    }
+
+    if !cfg.extras.is_empty() {
+        result.push_str("\n[tool.poetry.extras]\n");
+        let mut extras: Vec<_> = cfg.extras.iter().collect();
+        extras.sort();
+        for (group, names) in extras {
+            result.push_str(&format!("{} = {}\n", toml_key(group), list(names)));
+        }
+    }
+
+    // Poetry's scripts are entry points; shell commands only make sense with `pyflow run`.
+    let mut scripts: Vec<_> = cfg.scripts.iter().collect();
+    scripts.sort();
+    let (entry_pts, commands): (Vec<_>, Vec<_>) = scripts
+        .into_iter()
+        .partition(|(_, run)| hooks::is_function(run));
+    for (name, _) in commands {
+        log::warn!(
+            "The script `{}` isn't in the format `module:function`, so Poetry can't run it; it's \
+             left out",
+            name
+        );
+    }
+    if !entry_pts.is_empty() {
+        result.push_str("\n[tool.poetry.scripts]\n");
+        for (name, run) in entry_pts {
+            result.push_str(&format!(
+                "{} = {}\n",
+                toml_key(name),
+                toml_quote(run.trim())
+            ));
+        }
+    }
+    result
+}
+
+/// One line of a Poetry dependency table: `name = "constraint"`, or an inline table for
+/// anything more.
+fn dependency(req: &Req, optional: bool, as_is: &mut Vec<String>) -> String {
+    let mut fields = vec![];
+    if let Some(repo) = &req.git {
+        fields.push(format!("git = {}", toml_quote(repo)));
+        if let Some(git_ref) = &req.git_ref {
+            let (k, v) = git_ref.parts();
+            fields.push(format!("{} = {}", k, toml_quote(v)));
+        }
+    } else if let Some(path) = &req.path {
+        fields.push(format!("path = {}", toml_quote(path)));
+    } else {
+        let constrs = constraints(&req.name, &req.constraints, as_is);
+        fields.push(format!("version = {}", toml_quote(&constrs)));
+    }
+    if let Some(extras) = req.install_with_extras.as_ref().filter(|e| !e.is_empty()) {
+        fields.push(format!("extras = {}", list(extras)));
+    }
+    if let Some(python) = req.python_version.as_ref().filter(|p| !p.is_empty()) {
+        let python = constraints(&req.name, python, as_is);
+        fields.push(format!("python = {}", toml_quote(&python)));
+    }
+    match &req.sys_platform {
+        Some((ReqType::Exact, os)) => {
+            if let Some(platform) = sys_platform(*os) {
+                fields.push(format!("platform = {}", toml_quote(platform)));
+            }
+        }
+        // Poetry's `platform` can only say where a package is needed, not where it isn't.
+        Some((type_, os)) => {
+            if let Some(platform) = sys_platform(*os) {
+                let marker = format!("sys_platform {} \"{}\"", type_, platform);
+                as_is.push(format!("{} ({})", req.name, marker));
+                fields.push(format!("markers = {}", toml_quote(&marker)));
+            }
+        }
+        None => (),
+    }
+    if optional {
+        fields.push("optional = true".into());
+    }
+
+    if fields.len() == 1 && fields[0].starts_with("version = ") {
+        format!(
+            "{} = {}\n",
+            toml_key(&req.name),
+            &fields[0]["version = ".len()..]
+        )
+    } else {
+        format!("{} = {{ {} }}\n", toml_key(&req.name), fields.join(", "))
+    }
+}
+
+/// Constraints as Poetry writes them: `*` for any version, and `^` for a `>=`, `<` pair that
+/// means the same. Wildcards and local versions are only allowed with `==` and `!=`; others are
+/// added to `as_is`.
+fn constraints(name: &str, constraints: &[Constraint], as_is: &mut Vec<String>) -> String {
+    if constraints.is_empty() {
+        return "*".into();
+    }
+    if let [lower, upper] = constraints {
+        if lower.type_ == ReqType::Gte
+            && upper.type_ == ReqType::Lt
+            && caret_max(&lower.version).is_some_and(|max| max == upper.version)
+        {
+            return format!("^{}", lower.version);
+        }
+    }
+    for constr in constraints {
+        let v = &constr.version;
+        if v.major.is_some()
+            && (v.star || v.local.is_some())
+            && !matches!(constr.type_, ReqType::Exact | ReqType::Ne)
+        {
+            as_is.push(format!("{} {}", name, constr.to_string2(false, false)));
+        }
+    }
+    constraints
+        .iter()
+        .map(|c| c.to_string2(true, false))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// The upper bound of `^version`: the next release that changes its first non-zero part.
+fn caret_max(version: &Version) -> Option<Version> {
+    if version.star || version.modifier.is_some() || version.local.is_some() {
+        return None;
+    }
+    let major = version.major?;
+    let minor = version.minor.unwrap_or(0);
+    Some(if major > 0 {
+        Version::new(major + 1, 0, 0)
+    } else if minor > 0 {
+        Version::new(0, minor + 1, 0)
+    } else {
+        Version::new(0, 0, version.patch.unwrap_or(0) + 1)
+    })
+}
+
+/// Poetry's `platform` values, which are `sys.platform`'s.
+fn sys_platform(os: Os) -> Option<&'static str> {
+    match os {
+        Os::Linux | Os::Linux32 => Some("linux"),
+        Os::Windows | Os::Windows32 => Some("win32"),
+        Os::Mac => Some("darwin"),
+        Os::Any => None,
+    }
+}
+
+fn sorted(reqs: &[Req]) -> Vec<&Req> {
+    let mut result: Vec<&Req> = reqs.iter().collect();
+    result.sort_by_key(|r| r.name.to_lowercase());
+    result
+}
+
+fn list(values: &[String]) -> String {
+    let quoted: Vec<String> = values.iter().map(|v| toml_quote(v)).collect();
+    format!("[{}]", quoted.join(", "))
+}
+
+/// `data`, a `pyproject.toml`, with `tables` in place of its `[tool.poetry]` tables, or at the
+/// end if it has none. Everything else is kept as it is.
+fn replace_poetry_tables(data: &str, tables: &str) -> String {
+    let mut result: Vec<String> = vec![];
+    let mut skipping = false;
+    let mut inserted = false;
+    for line in data.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            let header = trimmed.split('#').next().unwrap_or_default();
+            let name = header.trim().trim_matches(|c| c == '[' || c == ']').trim();
+            skipping = name == "tool.poetry" || name.starts_with("tool.poetry.");
+            if skipping && !inserted {
+                result.push(tables.trim_end().to_owned());
+                result.push(String::new());
+                inserted = true;
+            }
+        }
+        if !skipping {
+            result.push(line.to_owned());
+        }
+    }
+    if !inserted {
+        if result.last().is_some_and(|l| !l.trim().is_empty()) {
+            result.push(String::new());
+        }
+        result.push(tables.trim_end().to_owned());
+    }
+    while result.last().is_some_and(|l| l.trim().is_empty()) {
+        result.pop();
+    }
+    result.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, str::FromStr};
+
+    use super::*;
+    use crate::dep_types::GitRef;
+    use crate::util::temp_dir::TempDir;
+
+    fn req(name: &str, constrs: &str) -> Req {
+        Req::new(name.into(), Constraint::from_str_multiple(constrs).unwrap())
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut uvicorn = req("uvicorn", "^0.23.1");
+        uvicorn.install_with_extras = Some(vec!["standard".into()]);
+        let mut uvloop = req("uvloop", ">=0.19");
+        uvloop.sys_platform = Some((ReqType::Exact, Os::Linux));
+        uvloop.python_version = Some(vec![Constraint::from_str(">=3.8").unwrap()]);
+        let mut mylib = Req::new("mylib".into(), vec![]);
+        mylib.git = Some("https://github.com/me/mylib".into());
+        mylib.git_ref = Some(GitRef::Tag("v1.2".into()));
+        let cfg = Config {
+            name: Some("proj".into()),
+            version: Some(Version::new(0, 2, 0)),
+            authors: vec!["Me <me@example.com>".into()],
+            py_version: Some(Version::new_short(3, 11)),
+            reqs: vec![
+                req("requests", "^2.31"),
+                req("zope.interface", "==6.1"),
+                uvicorn,
+                uvloop,
+                mylib,
+            ],
+            dev_reqs: vec![req("pytest", "~=8.0")],
+            extras: HashMap::from([("fast".to_owned(), vec!["uvloop".to_owned()])]),
+            scripts: HashMap::from([
+                ("serve".to_owned(), "proj.cli:main".to_owned()),
+                ("lint".to_owned(), "ruff check .".to_owned()),
+            ]),
+            ..Default::default()
+        };
+
+        let mut as_is = vec![];
+        let rendered = poetry(&cfg, &mut as_is);
+        assert!(as_is.is_empty());
+        assert!(rendered.contains("\"zope.interface\" = \"6.1\"\n"));
+        assert!(rendered.contains(
+            "uvloop = { version = \">=0.19\", python = \">=3.8\", platform = \"linux\", \
+             optional = true }\n"
+        ));
+
+        let dir = TempDir::new("poetry");
+        let path = dir.join("pyproject.toml");
+        fs::write(&path, &rendered).unwrap();
+        let parsed = Config::from_file(&path).unwrap();
+
+        assert_eq!(parsed.name, cfg.name);
+        assert_eq!(parsed.version, cfg.version);
+        assert_eq!(parsed.authors, cfg.authors);
+        assert_eq!(parsed.py_version, cfg.py_version);
+        assert_eq!(sorted(&parsed.reqs), sorted(&cfg.reqs));
+        assert_eq!(parsed.dev_reqs, cfg.dev_reqs);
+        assert_eq!(parsed.extras, cfg.extras);
+        assert_eq!(
+            parsed.scripts,
+            HashMap::from([("serve".to_owned(), "proj.cli:main".to_owned())])
+        );
+    }
+
+    #[test]
+    fn constraint_translation() {
+        let mut as_is = vec![];
+        let c = |s: &str| Constraint::from_str_multiple(s).unwrap();
+        assert_eq!(constraints("a", &[], &mut as_is), "*");
+        assert_eq!(constraints("a", &c(">=1.2, <2"), &mut as_is), "^1.2");
+        assert_eq!(constraints("a", &c(">=0.3.1, <0.4"), &mut as_is), "^0.3.1");
+        assert_eq!(constraints("a", &c(">=1.2, <3"), &mut as_is), ">=1.2, <3");
+        assert!(as_is.is_empty());
+        assert_eq!(constraints("a", &c(">=1.2+cpu"), &mut as_is), ">=1.2+cpu");
+        assert_eq!(as_is, vec!["a >=1.2+cpu"]);
+    }
+
+    #[test]
+    fn write_into_pyproject() {
+        let tables = "[tool.poetry]\nname = \"proj\"\n\n[tool.poetry.dependencies]\n";
+        let data = "[tool.pyflow]\nname = \"proj\"\n\n[tool.poetry]\nname = \"old\"\n\n\
+                    [tool.poetry.dependencies]\nrequests = \"*\"\n\n[build-system]\n\
+                    requires = []\n";
+        assert_eq!(
+            replace_poetry_tables(data, tables),
+            "[tool.pyflow]\nname = \"proj\"\n\n[tool.poetry]\nname = \"proj\"\n\n\
+             [tool.poetry.dependencies]\n\n[build-system]\nrequires = []\n"
+        );
+        assert_eq!(
+            replace_poetry_tables("[tool.pyflow]\nname = \"proj\"\n", tables),
+            "[tool.pyflow]\nname = \"proj\"\n\n[tool.poetry]\nname = \"proj\"\n\n\
+             [tool.poetry.dependencies]\n"
+        );
+    }
+}
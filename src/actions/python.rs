@@ -0,0 +1,52 @@
+use std::{path::Path, process};
+
+use termcolor::Color;
+
+use crate::{
+    py_versions::{self, InterpreterSource},
+    util,
+};
+
+/// Handle `pyflow python list`, and `pyflow python install <archive>`, which manage
+/// interpreters rather than running one. Exits if `args` is one of these; otherwise returns,
+/// and the args are passed to the project's Python.
+pub fn python(args: &[String], python_dir: &Path) {
+    match args.first().map(String::as_str) {
+        Some("list") if args.len() == 1 => list(python_dir),
+        Some("install") => match args.get(1) {
+            Some(archive) if args.len() == 2 => {
+                let i = py_versions::install_archive(python_dir, Path::new(archive));
+                util::print_status(
+                    &format!("Installed {} to {}", i.name(), i.path.display()),
+                    Color::Green,
+                );
+            }
+            _ => util::abort("Usage: `pyflow python install <path-to-archive>`"),
+        },
+        _ => return,
+    }
+    process::exit(0)
+}
+
+/// Print every interpreter we can find, and where each one we installed came from.
+fn list(python_dir: &Path) {
+    let mut interpreters = py_versions::find_all_interpreters(python_dir);
+    if interpreters.is_empty() {
+        util::print_color(
+            "No Python interpreters found on this system.",
+            Color::Yellow,
+        );
+        return;
+    }
+    interpreters.sort_by(|a, b| b.version.cmp(&a.version));
+
+    println!("{:<16} {:<20} Path", "Version", "Source");
+    for i in &interpreters {
+        println!("{:<16} {:<20} {}", i.name(), i.source, i.path.display());
+        if i.source == InterpreterSource::Pyflow {
+            if let Some(origin) = py_versions::install_origin(python_dir, i) {
+                println!("{:<37} from {}", "", origin);
+            }
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::path::Path;
+
+use crate::{
+    dep_types::Req,
+    files::{self, DepTable},
+    pyproject::Config,
+    util::{self, abort},
+};
+
+/// Remove `names` from `pyproject.toml`, and return the remaining reqs and dev reqs, for
+/// syncing. Each name must be declared in exactly one place, or in the table picked by
+/// `dev` or `group`.
+pub fn remove(
+    cfg_path: &Path,
+    cfg: &Config,
+    names: &[String],
+    dev: bool,
+    group: Option<&str>,
+) -> (Vec<Req>, Vec<Req>) {
+    if dev && group.is_some() {
+        abort("Use either `--dev` or `--group`, not both")
+    }
+    if let Some(g) = group {
+        if !cfg.extras.contains_key(g) {
+            abort(&format!(
+                "There's no `{}` group under `[tool.pyflow.extras]` in `pyproject.toml`",
+                g
+            ))
+        }
+    }
+
+    let mut removals: Vec<(DepTable, String)> = vec![];
+    for name in names {
+        let name = util::parse_cli_reqs(std::slice::from_ref(name))
+            .remove(0)
+            .name;
+        let found = declared_in(cfg, &name);
+
+        let table = match (dev, group) {
+            (true, _) => DepTable::Dev,
+            (_, Some(g)) => DepTable::Extras(g.to_owned()),
+            _ if found.len() > 1 => {
+                let places: Vec<String> = found.iter().map(|t| t.to_string()).collect();
+                abort(&format!(
+                    "`{}` is declared in {}; pick one with `--dev` or `--group <name>`",
+                    name,
+                    places.join(", ")
+                ))
+            }
+            _ if found.is_empty() => not_declared(cfg, &name),
+            _ => found[0].clone(),
+        };
+        if !found.contains(&table) {
+            abort(&format!("`{}` isn't declared in {}", name, table))
+        }
+        removals.push((table, name));
+    }
+
+    for table in [DepTable::Main, DepTable::Dev]
+        .iter()
+        .cloned()
+        .chain(cfg.extras.keys().cloned().map(DepTable::Extras))
+    {
+        let in_table: Vec<String> = removals
+            .iter()
+            .filter(|(t, _)| *t == table)
+            .map(|(_, n)| n.clone())
+            .collect();
+        if !in_table.is_empty() {
+            files::remove_reqs_from_cfg(cfg_path, &in_table, &table);
+        }
+    }
+
+    // Filter reqs here instead of re-reading the config from file.
+    let remaining = |reqs: &[Req], table: DepTable| -> Vec<Req> {
+        reqs.iter()
+            .filter(|r| {
+                !removals
+                    .iter()
+                    .any(|(t, n)| *t == table && util::compare_names(&r.name, n))
+            })
+            .cloned()
+            .collect()
+    };
+    (
+        remaining(&cfg.reqs, DepTable::Main),
+        remaining(&cfg.dev_reqs, DepTable::Dev),
+    )
+}
+
+/// The tables in `pyproject.toml` that declare `name`.
+fn declared_in(cfg: &Config, name: &str) -> Vec<DepTable> {
+    let mut result = vec![];
+    if cfg.reqs.iter().any(|r| util::compare_names(&r.name, name)) {
+        result.push(DepTable::Main);
+    }
+    if cfg
+        .dev_reqs
+        .iter()
+        .any(|r| util::compare_names(&r.name, name))
+    {
+        result.push(DepTable::Dev);
+    }
+    let mut groups: Vec<&String> = cfg.extras.keys().collect();
+    groups.sort();
+    for group in groups {
+        if extras_names(cfg, group).any(|n| util::compare_names(&n, name)) {
+            result.push(DepTable::Extras(group.clone()));
+        }
+    }
+    result
+}
+
+fn extras_names<'a>(cfg: &'a Config, group: &str) -> impl Iterator<Item = String> + 'a {
+    cfg.extras
+        .get(group)
+        .into_iter()
+        .flatten()
+        .filter_map(|spec| Req::from_pip_str(spec).map(|r| r.name))
+}
+
+fn not_declared(cfg: &Config, name: &str) -> ! {
+    let mut declared: Vec<String> = cfg
+        .reqs
+        .iter()
+        .chain(cfg.dev_reqs.iter())
+        .map(|r| r.name.clone())
+        .collect();
+    for group in cfg.extras.keys() {
+        declared.extend(extras_names(cfg, group));
+    }
+    declared.sort();
+    declared.dedup();
+
+    let close = util::close_matches(name, &declared);
+    if close.is_empty() {
+        abort(&format!(
+            "`{}` isn't a dependency in `pyproject.toml`",
+            name
+        ))
+    }
+    abort(&format!(
+        "`{}` isn't a dependency in `pyproject.toml`. Did you mean {}?",
+        name,
+        close
+            .iter()
+            .map(|n| format!("`{}`", n))
+            .collect::<Vec<String>>()
+            .join(", ")
+    ))
+}
@@ -1,11 +1,74 @@
-use std::path::Path;
+use std::{
+    path::{Path, PathBuf},
+    process,
+    sync::LazyLock,
+};
 
 use regex::Regex;
 
-use crate::{commands, pyproject::Config, util::abort};
+use crate::{
+    commands,
+    pyproject::Config,
+    util::{self, abort, hooks},
+};
 
-/// Execute a python CLI tool, either specified in `pyproject.toml`, or in a dependency.
-pub fn run(lib_path: &Path, bin_path: &Path, vers_path: &Path, cfg: &Config, args: Vec<String>) {
+/// What `pyflow run <name>` runs.
+pub enum Target {
+    /// From `[tool.pyflow.scripts]`; holds what's declared.
+    Script(String),
+    /// The environment's Python, for names like `python3`.
+    Python(PathBuf),
+    /// A console script installed in the environment.
+    Installed(PathBuf),
+}
+
+/// Eg `python`, `python3.12`, or `python.exe`.
+static PYTHON_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^python(\d+(\.\d+)?)?(\.exe)?$").unwrap());
+
+/// Find what `pyflow run <name>` runs. We look, in order, in `[tool.pyflow.scripts]`, so a
+/// project's scripts win over a dependency's; for the environment's Python; then at console
+/// scripts installed in the environment. We never fall back to the `PATH`.
+pub fn resolve(name: &str, cfg: &Config, bin_path: &Path, entry_pt_path: &Path) -> Option<Target> {
+    if let Some(script) = cfg.scripts.get(name) {
+        return Some(Target::Script(script.clone()));
+    }
+    if let Some(steps) = cfg.script_chains.get(name) {
+        return Some(Target::Script(format!("[{}]", steps.join(", "))));
+    }
+
+    // Eg `pyflow run python -c "print(1)"`; the environment's Python isn't in `bin`.
+    if PYTHON_RE.is_match(name) {
+        return Some(Target::Python(bin_path.join("python")));
+    }
+
+    let script_path = entry_pt_path.join(name);
+    if script_path.exists() {
+        return Some(Target::Installed(script_path));
+    }
+    None
+}
+
+/// Executables on the `PATH` with the same name as `path`, other than it; ones a shell would
+/// run instead.
+pub fn shadowed_by(path: &Path, name: &str) -> Vec<PathBuf> {
+    let ours = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+    util::find_on_path(name)
+        .into_iter()
+        .filter(|p| p.canonicalize().unwrap_or_else(|_| p.clone()) != ours)
+        .collect()
+}
+
+/// Execute a python CLI tool, either specified in `pyproject.toml`, or in a dependency. Unless
+/// `quiet`, say so when a program with the same name is on the `PATH`.
+pub fn run(
+    pythonpath: &[PathBuf],
+    bin_path: &Path,
+    entry_pt_path: &Path,
+    cfg: &Config,
+    args: Vec<String>,
+    quiet: bool,
+) {
     // Allow both `pyflow run ipython` (args), and `pyflow ipython` (opt.script)
     if args.is_empty() {
         return;
@@ -17,56 +80,203 @@ pub fn run(lib_path: &Path, bin_path: &Path, vers_path: &Path, cfg: &Config, arg
         abort("`run` must be followed by the script to run, eg `pyflow run black`");
     };
 
-    // If the script we're calling is specified in `pyproject.toml`, ensure it exists.
-
-    // TODO: Delete these scripts as required to sync with pyproject.toml.
-    let re = Regex::new(r"(.*?):(.*)").unwrap();
-
     let mut specified_args: Vec<String> = args.into_iter().skip(1).collect();
 
-    // If a script name is specified by by this project and a dependency, favor
-    // this project.
-    if let Some(s) = cfg.scripts.get(&name) {
-        let abort_msg = format!(
-            "Problem running the function {}, specified in `pyproject.toml`",
-            name,
-        );
-
-        if let Some(caps) = re.captures(s) {
-            let module = caps.get(1).unwrap().as_str();
-            let function = caps.get(2).unwrap().as_str();
-            let mut args_to_pass = vec![
-                "-c".to_owned(),
-                format!(r#"import {}; {}.{}()"#, module, module, function),
-            ];
-
-            args_to_pass.append(&mut specified_args);
-            if commands::run_python(bin_path, &[lib_path.to_owned()], &args_to_pass).is_err() {
-                abort(&abort_msg);
-            }
-        } else {
-            abort(&format!("Problem parsing the following script: {:#?}. Must be in the format module:function_name", s));
+    // Eg `pyflow run -m http.server 8000`; for tools without console scripts, or to be sure
+    // they run with the environment's Python.
+    if name == "-m" {
+        if specified_args.is_empty() {
+            abort("`-m` must be followed by a module to run, eg `pyflow run -m http.server`")
         }
+        let mut python_args = vec!["-m".to_owned()];
+        python_args.append(&mut specified_args);
+        commands::exit_on_failure(
+            commands::run_python(bin_path, pythonpath, &python_args),
+            "Problem running Python",
+        );
         return;
     }
-    //            None => {
+
     let abort_msg = format!(
         "Problem running the CLI tool {}. Is it installed? \
          Try running `pyflow install {}`",
         name, name
     );
-    let script_path = vers_path.join("bin").join(name);
-    if !script_path.exists() {
-        abort(&abort_msg);
+
+    match resolve(&name, cfg, bin_path, entry_pt_path) {
+        Some(Target::Script(_)) => {
+            util::set_pythonpath(pythonpath);
+            let code = run_script(
+                &name,
+                cfg,
+                bin_path,
+                entry_pt_path,
+                &specified_args,
+                &mut vec![],
+            );
+            if code != 0 {
+                util::run_env::explain_user_site();
+                process::exit(code)
+            }
+        }
+        Some(Target::Python(_)) => {
+            commands::exit_on_failure(
+                commands::run_python(bin_path, pythonpath, &specified_args),
+                "Problem running Python",
+            );
+        }
+        Some(Target::Installed(script_path)) => {
+            if !quiet {
+                if let Some(other) = shadowed_by(&script_path, &name).first() {
+                    eprintln!(
+                        "pyflow: running `{}` from {}, not {}",
+                        name,
+                        script_path.display(),
+                        other.display()
+                    );
+                }
+            }
+
+            let mut args_to_pass = vec![script_path
+                .to_str()
+                .expect("Can't find script path")
+                .to_owned()];
+
+            args_to_pass.append(&mut specified_args);
+            commands::exit_on_failure(
+                commands::run_python(bin_path, pythonpath, &args_to_pass),
+                &abort_msg,
+            );
+        }
+        None => abort(&abort_msg),
+    }
+}
+
+/// Where `pyflow run` runs from: `--cwd`, relative to where pyflow was run; the script's own
+/// `cwd`, relative to the project root; or the project root, if `run-from-root` is set. `None`
+/// means stay put. Arguments passed to the program aren't changed, so relative paths in them are
+/// relative to the directory chosen here.
+pub fn working_dir(
+    cli_cwd: Option<&str>,
+    cfg: &Config,
+    project_path: &Path,
+    script: Option<&str>,
+    current_dir: &Path,
+) -> Option<PathBuf> {
+    if let Some(dir) = cli_cwd {
+        return Some(current_dir.join(dir));
+    }
+    if let Some(dir) = script.and_then(|s| cfg.script_cwd.get(s)) {
+        return Some(project_path.join(dir));
+    }
+    if cfg.run_from_root {
+        return Some(project_path.to_owned());
+    }
+    None
+}
+
+/// Run a script from `[tool.pyflow.scripts]`: a `module:function`, a shell command, or a list
+/// of other scripts, run in order until one fails. Returns the exit code. `stack` holds the
+/// scripts we're running this from, to catch cycles.
+fn run_script(
+    name: &str,
+    cfg: &Config,
+    bin_path: &Path,
+    entry_pt_path: &Path,
+    args: &[String],
+    stack: &mut Vec<String>,
+) -> i32 {
+    if stack.iter().any(|s| s == name) {
+        abort(&format!(
+            "The scripts in `pyproject.toml` run each other in a cycle: {} → {}",
+            stack.join(" → "),
+            name
+        ))
     }
 
-    let mut args_to_pass = vec![script_path
-        .to_str()
-        .expect("Can't find script path")
-        .to_owned()];
+    if let Some(steps) = cfg.script_chains.get(name) {
+        stack.push(name.to_owned());
+        for step in steps {
+            if !cfg.scripts.contains_key(step) && !cfg.script_chains.contains_key(step) {
+                abort(&format!(
+                    "The script `{}` runs `{}`, which isn't in `[tool.pyflow.scripts]`",
+                    name, step
+                ))
+            }
+            let code = run_script(step, cfg, bin_path, entry_pt_path, &[], stack);
+            if code != 0 {
+                return code;
+            }
+        }
+        stack.pop();
+        return 0;
+    }
+
+    let script = &cfg.scripts[name];
+    let mut cmd = if hooks::is_function(script) {
+        let mut c = hooks::command(script, bin_path, entry_pt_path);
+        c.args(args);
+        c
+    } else {
+        let mut line = script.clone();
+        for arg in args {
+            line.push(' ');
+            line.push_str(&shell_quote(arg));
+        }
+        hooks::command(&line, bin_path, entry_pt_path)
+    };
 
-    args_to_pass.append(&mut specified_args);
-    if commands::run_python(bin_path, &[lib_path.to_owned()], &args_to_pass).is_err() {
-        abort(&abort_msg);
+    match commands::run_interactive(&mut cmd) {
+        Ok(code) => code,
+        Err(_) => abort(&format!(
+            "Problem running the script `{}`, specified in `pyproject.toml`",
+            name
+        )),
+    }
+}
+
+/// Quote an argument to append to a shell command, so it's passed as-is.
+fn shell_quote(arg: &str) -> String {
+    if cfg!(target_os = "windows") {
+        format!("\"{}\"", arg.replace('"', "\\\""))
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn quote_for_shell() {
+        assert_eq!(shell_quote("not slow"), "'not slow'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn working_dir_precedence() {
+        let root = Path::new("/proj");
+        let here = Path::new("/proj/src/app");
+        let mut cfg = Config::default();
+        cfg.script_cwd.insert("docs".into(), "docs".into());
+
+        assert_eq!(working_dir(None, &cfg, root, Some("test"), here), None);
+        assert_eq!(
+            working_dir(None, &cfg, root, Some("docs"), here),
+            Some(PathBuf::from("/proj/docs"))
+        );
+        assert_eq!(
+            working_dir(Some("../lib"), &cfg, root, Some("docs"), here),
+            Some(PathBuf::from("/proj/src/app/../lib"))
+        );
+
+        cfg.run_from_root = true;
+        assert_eq!(
+            working_dir(None, &cfg, root, Some("test"), here),
+            Some(PathBuf::from("/proj"))
+        );
     }
 }
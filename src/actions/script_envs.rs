@@ -0,0 +1,130 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use termcolor::Color;
+
+use crate::{
+    cli_options::ScriptEnvsCommand,
+    script,
+    util::{self, print_color, print_color_, success},
+};
+
+/// A cached script environment, and what we know about it.
+struct ScriptEnv {
+    path: PathBuf,
+    hash: String,
+    requirements: Option<Vec<String>>, // `None` if it was never finished
+    scripts: Vec<String>,
+}
+
+impl ScriptEnv {
+    fn load(path: PathBuf) -> Self {
+        let read_lines = |name: &str| -> Option<Vec<String>> {
+            let data = fs::read_to_string(path.join(name)).ok()?;
+            Some(
+                data.lines()
+                    .filter(|l| !l.is_empty())
+                    .map(str::to_owned)
+                    .collect(),
+            )
+        };
+
+        Self {
+            hash: path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            requirements: read_lines(script::ENV_READY_FILE),
+            scripts: read_lines(script::ENV_SCRIPTS_FILE).unwrap_or_default(),
+            path,
+        }
+    }
+
+    /// Some script that used this environment still exists.
+    fn in_use(&self) -> bool {
+        self.scripts.iter().any(|s| Path::new(s).exists())
+    }
+
+    /// Another process is building this environment.
+    fn is_locked(&self) -> bool {
+        script::env_locked(&self.path)
+    }
+}
+
+fn find_envs(script_env_path: &Path) -> Vec<ScriptEnv> {
+    let envs_path = script::envs_path(script_env_path);
+    if !envs_path.exists() {
+        return vec![];
+    }
+    let mut result: Vec<ScriptEnv> = util::find_folders(&envs_path)
+        .into_iter()
+        .map(|f| ScriptEnv::load(envs_path.join(f)))
+        .collect();
+    result.sort_by(|a, b| a.hash.cmp(&b.hash));
+    result
+}
+
+/// List or clean the environments cached for standalone scripts.
+pub fn script_envs(cmd: &ScriptEnvsCommand, script_env_path: &Path) {
+    match cmd {
+        ScriptEnvsCommand::List => list(script_env_path),
+        ScriptEnvsCommand::Clean { all } => clean(script_env_path, *all),
+    }
+}
+
+fn list(script_env_path: &Path) {
+    let envs = find_envs(script_env_path);
+    if envs.is_empty() {
+        print_color("No script environments are cached.", Color::Blue);
+        return;
+    }
+
+    for env in &envs {
+        print_color_(&env.hash, Color::Cyan);
+        match &env.requirements {
+            Some(reqs) if reqs.is_empty() => print_color(": no requirements", Color::White),
+            Some(reqs) => print_color(&format!(": {}", reqs.join(", ")), Color::White),
+            None => print_color(": incomplete", Color::Yellow),
+        }
+        for script in &env.scripts {
+            if Path::new(script).exists() {
+                println!("    {}", script);
+            } else {
+                print_color(&format!("    {} (missing)", script), Color::Yellow);
+            }
+        }
+    }
+}
+
+/// Remove environments no existing script uses, or all of them. Environments being built
+/// are left alone.
+fn clean(script_env_path: &Path, all: bool) {
+    let mut removed = 0;
+    for env in find_envs(script_env_path) {
+        if env.is_locked() || (!all && env.in_use()) {
+            continue;
+        }
+        if fs::remove_dir_all(&env.path).is_err() {
+            util::abort(&format!(
+                "Problem removing the script environment {:?}",
+                env.path
+            ))
+        }
+        removed += 1;
+    }
+
+    // Each script's chosen Python version is kept outside the shared environments.
+    if all && script_env_path.exists() {
+        let envs_path = script::envs_path(script_env_path);
+        for folder in util::find_folders(script_env_path) {
+            let path = script_env_path.join(folder);
+            if path != envs_path {
+                fs::remove_dir_all(&path).unwrap_or(());
+            }
+        }
+    }
+
+    success(&format!("Removed {} script environment(s)", removed));
+}
@@ -0,0 +1,155 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use serde::Serialize;
+use termcolor::Color;
+
+use crate::{
+    actions::list::find_console_scripts,
+    install,
+    util::{self, patterns::DIST_INFO_RE, print_color, print_color_},
+};
+
+/// A script from `[tool.pyflow.scripts]`.
+#[derive(Debug, Serialize)]
+struct DeclaredScript {
+    name: String,
+    target: String,
+}
+
+/// An executable in the environment's `bin` folder, and the package that installed it.
+#[derive(Debug, Serialize)]
+struct InstalledScript {
+    name: String,
+    package: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ScriptsReport {
+    declared: Vec<DeclaredScript>,
+    installed: Vec<InstalledScript>,
+    /// Names both declared and installed; `pyflow <name>` runs the declared one.
+    collisions: Vec<String>,
+}
+
+/// List what can be run with `pyflow <name>`: scripts declared in `pyproject.toml`, and ones
+/// installed by packages.
+pub fn scripts(
+    declared: &HashMap<String, String>,
+    chains: &HashMap<String, Vec<String>>,
+    lib_path: &Path,
+    bin_path: &Path,
+    json: bool,
+) {
+    let report = find_scripts(declared, chains, lib_path, bin_path);
+
+    if json {
+        match serde_json::to_string_pretty(&report) {
+            Ok(s) => println!("{}", s),
+            Err(_) => util::abort("Problem serializing scripts"),
+        }
+    } else {
+        print_report(&report);
+    }
+}
+
+fn find_scripts(
+    declared: &HashMap<String, String>,
+    chains: &HashMap<String, Vec<String>>,
+    lib_path: &Path,
+    bin_path: &Path,
+) -> ScriptsReport {
+    // Composite scripts show the scripts they run, eg `[lint, test]`.
+    let declared: BTreeMap<&String, String> = declared
+        .iter()
+        .map(|(name, target)| (name, target.clone()))
+        .chain(
+            chains
+                .iter()
+                .map(|(name, steps)| (name, format!("[{}]", steps.join(", ")))),
+        )
+        .collect();
+
+    // Map each console script to the package whose `entry_points.txt` declares it.
+    let mut owners = HashMap::new();
+    if lib_path.exists() {
+        for folder in util::find_folders(lib_path) {
+            if let Some(caps) = DIST_INFO_RE.captures(&folder) {
+                let package = caps.get(1).unwrap().as_str();
+                for script in install::read_console_scripts(&lib_path.join(&folder)) {
+                    if let Some(name) = script.split('=').next() {
+                        owners.insert(name.to_owned(), package.to_owned());
+                    }
+                }
+            }
+        }
+    }
+
+    let mut installed = find_console_scripts(bin_path);
+    installed.sort();
+
+    let collisions = installed
+        .iter()
+        .filter(|name| declared.contains_key(name))
+        .cloned()
+        .collect();
+
+    ScriptsReport {
+        declared: declared
+            .into_iter()
+            .map(|(name, target)| DeclaredScript {
+                name: name.clone(),
+                target,
+            })
+            .collect(),
+        installed: installed
+            .into_iter()
+            .map(|name| InstalledScript {
+                package: owners.get(&name).cloned(),
+                name,
+            })
+            .collect(),
+        collisions,
+    }
+}
+
+fn print_report(report: &ScriptsReport) {
+    if report.declared.is_empty() {
+        print_color(
+            "No scripts are declared in `[tool.pyflow.scripts]`.",
+            Color::Blue,
+        );
+    } else {
+        print_color("Declared in `pyproject.toml`:", Color::Blue);
+        for s in &report.declared {
+            print_color_(&format!("  {}", s.name), Color::Cyan);
+            println!(" → {}", s.target);
+        }
+    }
+
+    if report.installed.is_empty() {
+        print_color("\nNo console scripts are installed.", Color::Blue);
+    } else {
+        print_color("\nInstalled by packages:", Color::Blue);
+        for s in &report.installed {
+            print_color_(&format!("  {}", s.name), Color::Cyan);
+            match &s.package {
+                Some(p) => println!(" ({})", p),
+                None => println!(),
+            }
+        }
+    }
+
+    for name in &report.collisions {
+        print_color(
+            &format!(
+                "\n`{}` is declared in `pyproject.toml`, and installed by a package; \
+                 `pyflow {}` runs the declared one.",
+                name, name
+            ),
+            Color::Yellow,
+        );
+    }
+}
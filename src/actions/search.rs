@@ -0,0 +1,245 @@
+use std::{
+    env, fs,
+    path::Path,
+    process::{self, Command, Stdio},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use termcolor::Color;
+
+use crate::util::{self, abort, paths, print_color, print_color_, settings::Settings};
+
+/// The cached list of every project name on PyPI. Indexes have no search endpoint, so we
+/// match against a copy of their simple API's list.
+const NAMES_FILENAME: &str = "pypi_names.txt";
+/// How long before we refresh the cached name list, in the background.
+const NAMES_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Called to refresh the name list without blocking a search; see `refresh_index`.
+const REFRESH_CMD: &str = "refresh-index";
+
+#[derive(Debug, Deserialize)]
+struct SimpleIndex {
+    projects: Vec<SimpleProject>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SimpleProject {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProjectData {
+    info: ProjectInfo,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ProjectInfo {
+    name: String,
+    version: String,
+    summary: Option<String>,
+}
+
+/// Search the package index for projects named like `term`, and show the latest version and
+/// summary of the best `limit` matches.
+pub fn search(term: &str, limit: usize, json: bool, pyflow_path: &Path) {
+    let names = load_names(&paths::index_cache_path(pyflow_path));
+
+    // Look up the exact name even if it isn't in the list yet, eg one published today.
+    let mut candidates = vec![term.to_owned()];
+    candidates.extend(rank(term, &names, limit));
+
+    let mut found: Vec<ProjectInfo> = vec![];
+    for name in candidates {
+        if found.len() == limit {
+            break;
+        }
+        if found.iter().any(|p| util::compare_names(&p.name, &name)) {
+            continue;
+        }
+        if let Some(info) = fetch_info(&name) {
+            found.push(info);
+        }
+    }
+
+    if json {
+        match serde_json::to_string_pretty(&found) {
+            Ok(s) => println!("{}", s),
+            Err(_) => abort("Problem serializing search results"),
+        }
+        return;
+    }
+
+    if found.is_empty() {
+        print_color(
+            &format!("No packages found matching `{}`", term),
+            Color::Yellow,
+        );
+        return;
+    }
+    for info in found {
+        print_color_(&info.name, Color::Cyan);
+        print_color(&format!(" {}", info.version), Color::White);
+        if let Some(summary) = info.summary.filter(|s| !s.trim().is_empty()) {
+            println!("    {}", summary.trim());
+        }
+    }
+}
+
+/// Download the index's name list, and replace the cached copy.
+pub fn refresh_index(pyflow_path: &Path) {
+    let cache_path = paths::index_cache_path(pyflow_path);
+    if let Some(names) = fetch_names() {
+        save_names(&cache_path, &names);
+    }
+}
+
+/// Read the cached name list. If it's out of date, refresh it in a separate process and use the
+/// old one for now. If there isn't one, download it.
+fn load_names(cache_path: &Path) -> Vec<String> {
+    let file = cache_path.join(names_filename());
+    if let Ok(data) = fs::read_to_string(&file) {
+        let age = fs::metadata(&file)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+        if age.map_or(true, |a| a > NAMES_TTL) {
+            refresh_in_background();
+        }
+        return data.lines().map(|l| l.to_owned()).collect();
+    }
+
+    util::print_status("Downloading the package index's name list...", Color::Blue);
+    match fetch_names() {
+        Some(names) => {
+            save_names(cache_path, &names);
+            names
+        }
+        None => {
+            print_color(
+                "Problem downloading the name list; only checking for an exact match",
+                Color::Yellow,
+            );
+            vec![]
+        }
+    }
+}
+
+fn refresh_in_background() {
+    if let Ok(exe) = env::current_exe() {
+        // The refresh outlives us; if it fails, we'll try again next search.
+        let _ = Command::new(exe)
+            .arg(REFRESH_CMD)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+    }
+}
+
+/// Each index's names are cached separately, so switching `index-url` doesn't mix them.
+fn names_filename() -> String {
+    let url = util::index_simple_url();
+    if url == Settings::default().simple_url() {
+        return NAMES_FILENAME.to_owned();
+    }
+    let hash = ring::digest::digest(&ring::digest::SHA256, url.as_bytes());
+    format!(
+        "names-{}.txt",
+        &data_encoding::HEXLOWER.encode(hash.as_ref())[..16]
+    )
+}
+
+fn fetch_names() -> Option<Vec<String>> {
+    let index: SimpleIndex = util::http_client()
+        .get(util::index_simple_url())
+        .header("Accept", "application/vnd.pypi.simple.v1+json")
+        .header("User-Agent", "pyflow")
+        .send()
+        .and_then(|r| r.json())
+        .ok()?;
+    Some(index.projects.into_iter().map(|p| p.name).collect())
+}
+
+/// Write to a temporary file first, so a search never reads a partial list.
+fn save_names(cache_path: &Path, names: &[String]) {
+    if fs::create_dir_all(cache_path).is_err() {
+        return;
+    }
+    let temp = cache_path.join(format!("{}.{}", names_filename(), process::id()));
+    if fs::write(&temp, names.join("\n")).is_ok()
+        && fs::rename(&temp, cache_path.join(names_filename())).is_err()
+    {
+        let _ = fs::remove_file(&temp);
+    }
+}
+
+fn fetch_info(name: &str) -> Option<ProjectInfo> {
+    let url = util::index_json_url(name, None);
+    let data: ProjectData = util::http_client().get(&url).send().ok()?.json().ok()?;
+    Some(data.info)
+}
+
+/// The best `limit` matches for `term`: an exact name, then names starting with it, then
+/// names containing it, then near-misspellings. Shorter names first within each.
+fn rank(term: &str, names: &[String], limit: usize) -> Vec<String> {
+    let term = util::standardize_name(term);
+    if term.is_empty() {
+        return vec![];
+    }
+
+    let mut scored: Vec<(u8, usize, &String)> = names
+        .iter()
+        .filter_map(|name| {
+            let std_name = util::standardize_name(name);
+            let score = if std_name == term {
+                0
+            } else if std_name.starts_with(&term) {
+                1
+            } else if std_name.contains(&term) {
+                2
+            } else if std_name.len().max(term.len()) - std_name.len().min(term.len()) <= 2
+                && util::edit_distance(&term, &std_name) <= 2.max(term.len() / 4)
+            {
+                3
+            } else {
+                return None;
+            };
+            Some((score, name.len(), name))
+        })
+        .collect();
+    scored.sort();
+    scored
+        .into_iter()
+        .take(limit)
+        .map(|(_, _, name)| name.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranking() {
+        let names: Vec<String> = [
+            "flask-login",
+            "Flask",
+            "pyflask",
+            "flake8",
+            "flasq",
+            "numpy",
+            "flask_cors",
+        ]
+        .iter()
+        .map(|n| n.to_string())
+        .collect();
+
+        assert_eq!(
+            rank("flask", &names, 10),
+            vec!["Flask", "flask_cors", "flask-login", "pyflask", "flasq",]
+        );
+        assert_eq!(rank("FLASK", &names, 2), vec!["Flask", "flask_cors"]);
+        assert!(rank("", &names, 10).is_empty());
+    }
+}
@@ -0,0 +1,361 @@
+use std::{env, fs, io, path::Path, process};
+
+use serde::Deserialize;
+use termcolor::Color;
+
+use crate::{
+    cli_options::SelfCommand,
+    dep_types::Version,
+    py_versions,
+    util::{self, abort},
+};
+
+/// pyflow's releases, newest first.
+const RELEASES_URL: &str = "https://api.github.com/repos/David-OConnor/pyflow/releases";
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    #[serde(default)]
+    draft: bool,
+    #[serde(default)]
+    prerelease: bool,
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Release {
+    fn version(&self) -> Option<Version> {
+        self.tag_name.trim_start_matches('v').parse().ok()
+    }
+
+    fn asset_url(&self, name: &str) -> Option<&str> {
+        self.assets
+            .iter()
+            .find(|a| a.name == name)
+            .map(|a| a.browser_download_url.as_str())
+    }
+}
+
+pub fn self_cmd(cmd: &SelfCommand) {
+    match cmd {
+        SelfCommand::Update { version, check } => update(version.as_deref(), *check),
+    }
+}
+
+/// Replace this executable with the newest release, or the one `pinned`. With `check`, only
+/// report whether there's a newer one.
+fn update(pinned: Option<&str>, check: bool) {
+    let exe = env::current_exe()
+        .and_then(fs::canonicalize)
+        .unwrap_or_else(|_| abort("Can't find the location of the pyflow executable"));
+    let current: Version = env!("CARGO_PKG_VERSION").parse().unwrap();
+
+    let releases = fetch_releases();
+    let release = match pinned {
+        Some(v) => find_release(&releases, v)
+            .unwrap_or_else(|| abort(&format!("There's no pyflow release {}", v))),
+        None => {
+            newest_release(&releases).unwrap_or_else(|| abort("Can't find any pyflow releases"))
+        }
+    };
+    // `newest_release` only returns releases with a version.
+    let target = release.version().unwrap_or_else(|| current.clone());
+
+    if check {
+        if target > current {
+            println!("pyflow {} is available; you have {}", target, current);
+        } else {
+            println!("pyflow {} is up to date", current);
+        }
+        process::exit(0)
+    }
+
+    if pinned.is_none() && target <= current {
+        util::success(&format!("pyflow {} is up to date", current));
+        process::exit(0)
+    }
+
+    if let Some(manager) = package_manager(&exe) {
+        abort(&format!(
+            "This pyflow was installed by {}; update it with `{}` instead",
+            manager.0, manager.1
+        ))
+    }
+
+    let name = asset_name(env::consts::OS, env::consts::ARCH).unwrap_or_else(|| {
+        abort(&format!(
+            "There are no pyflow builds for {} {}; install it with `cargo install pyflow`",
+            env::consts::OS,
+            env::consts::ARCH
+        ))
+    });
+    // Older releases have a single Linux build, called `pyflow`.
+    let name = match release.asset_url(&name) {
+        None if env::consts::OS == "linux" && env::consts::ARCH == "x86_64" => "pyflow".into(),
+        _ => name,
+    };
+    let url = release.asset_url(&name).unwrap_or_else(|| {
+        abort(&format!(
+            "pyflow {} doesn't have a build for this machine ({})",
+            target, name
+        ))
+    });
+
+    let expected = py_versions::fetch_checksum(
+        &name,
+        release.asset_url(&format!("{}.sha256", name)),
+        release.asset_url("SHA256SUMS"),
+    )
+    .unwrap_or_else(|| {
+        abort(&format!(
+            "pyflow {} doesn't publish a checksum for {}, so it can't be verified",
+            target, name
+        ))
+    });
+
+    util::print_status(&format!("Downloading pyflow {}...", target), Color::Cyan);
+    let tmp_path = exe.with_file_name(format!(".pyflow-{}.tmp", target));
+    download(url, &tmp_path);
+    py_versions::check_digest(&tmp_path, &expected);
+    make_executable(&tmp_path);
+
+    if let Err(e) = replace_exe(&exe, &tmp_path) {
+        fs::remove_file(&tmp_path).unwrap_or(());
+        abort(&format!("Problem replacing {:?}: {}", exe, e))
+    }
+    util::success(&format!("Updated pyflow from {} to {}", current, target));
+}
+
+fn fetch_releases() -> Vec<Release> {
+    util::http_client()
+        .get(RELEASES_URL)
+        .header("User-Agent", "pyflow")
+        .send()
+        .and_then(|r| r.json())
+        .unwrap_or_else(|_| abort("Problem fetching the list of pyflow releases"))
+}
+
+/// The newest release that isn't a draft or pre-release.
+fn newest_release(releases: &[Release]) -> Option<&Release> {
+    releases
+        .iter()
+        .filter(|r| !r.draft && !r.prerelease)
+        .filter_map(|r| r.version().map(|v| (v, r)))
+        .max_by(|a, b| a.0.cmp(&b.0))
+        .map(|(_, r)| r)
+}
+
+/// The release tagged `version`, with or without a leading `v`.
+fn find_release<'a>(releases: &'a [Release], version: &str) -> Option<&'a Release> {
+    let version = version.trim_start_matches('v');
+    releases
+        .iter()
+        .find(|r| r.tag_name.trim_start_matches('v') == version)
+}
+
+/// The release asset for this platform, eg `pyflow-x86_64-apple-darwin`.
+fn asset_name(os: &str, arch: &str) -> Option<String> {
+    let triple = match (os, arch) {
+        ("linux", "x86_64") => "x86_64-unknown-linux-musl",
+        ("linux", "aarch64") => "aarch64-unknown-linux-musl",
+        ("macos", "x86_64") => "x86_64-apple-darwin",
+        ("macos", "aarch64") => "aarch64-apple-darwin",
+        ("windows", "x86_64") => "x86_64-pc-windows-msvc",
+        ("windows", "x86") => "i686-pc-windows-msvc",
+        _ => return None,
+    };
+    let ext = if os == "windows" { ".exe" } else { "" };
+    Some(format!("pyflow-{}{}", triple, ext))
+}
+
+/// The package manager that installed the executable at `exe`, if any, and the command that
+/// updates it.
+fn package_manager(exe: &Path) -> Option<(&'static str, &'static str)> {
+    let path = exe.to_string_lossy().replace('\\', "/").to_lowercase();
+    if path.starts_with("/snap/") {
+        Some(("Snap", "snap refresh pyflow"))
+    } else if path.contains("/cellar/")
+        || path.contains("/homebrew/")
+        || path.contains("/linuxbrew/")
+    {
+        Some(("Homebrew", "brew upgrade pyflow"))
+    } else if path.contains("/scoop/") {
+        Some(("Scoop", "scoop update pyflow"))
+    } else if path.contains("/.cargo/bin/") {
+        Some(("Cargo", "cargo install pyflow --force"))
+    } else if path.starts_with("/usr/bin/") {
+        Some((
+            "your system's package manager",
+            "your package manager's upgrade command",
+        ))
+    } else if pip_installed(exe) {
+        Some(("Pip", "pip install --upgrade pyflow"))
+    } else {
+        None
+    }
+}
+
+/// Whether `exe` is in a Python environment's scripts folder, and that environment has pyflow's
+/// dist-info. A `pip` next to the executable isn't enough: a hand-copied binary often sits beside
+/// the system Python's.
+fn pip_installed(exe: &Path) -> bool {
+    let root = match exe.parent().and_then(Path::parent) {
+        Some(r) => r,
+        None => return false,
+    };
+    // `lib/python3.x/site-packages` on unix; `Lib/site-packages`, or `site-packages` for user
+    // installs, on Windows.
+    let mut site_dirs = vec![root.join("Lib/site-packages"), root.join("site-packages")];
+    if let Ok(entries) = fs::read_dir(root.join("lib")) {
+        site_dirs.extend(entries.flatten().map(|e| e.path().join("site-packages")));
+    }
+    site_dirs
+        .iter()
+        .filter_map(|d| fs::read_dir(d).ok())
+        .any(|entries| {
+            entries.flatten().any(|e| {
+                let name = e.file_name().to_string_lossy().to_lowercase();
+                name.starts_with("pyflow-") && name.ends_with(".dist-info")
+            })
+        })
+}
+
+fn download(url: &str, path: &Path) {
+    let result = util::http_client()
+        .get(url)
+        .header("User-Agent", "pyflow")
+        .send()
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| e.to_string())
+        .and_then(|mut resp| {
+            let mut out = fs::File::create(path).map_err(|e| e.to_string())?;
+            io::copy(&mut resp, &mut out).map_err(|e| e.to_string())
+        });
+    if let Err(e) = result {
+        fs::remove_file(path).unwrap_or(());
+        abort(&format!("Problem downloading pyflow: {}", e))
+    }
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap_or(());
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) {}
+
+/// Where the running executable goes while its replacement is moved in.
+#[cfg(windows)]
+fn old_path(exe: &Path) -> std::path::PathBuf {
+    exe.with_extension("old.exe")
+}
+
+/// Swap `new` into `exe`'s place. Renaming over the running executable is atomic.
+#[cfg(not(windows))]
+fn replace_exe(exe: &Path, new: &Path) -> io::Result<()> {
+    fs::rename(new, exe)
+}
+
+/// Swap `new` into `exe`'s place. Windows locks a running executable against deletion, but
+/// not renaming, so move it aside first, and put it back if the swap fails. The old copy is
+/// removed on the next update.
+#[cfg(windows)]
+fn replace_exe(exe: &Path, new: &Path) -> io::Result<()> {
+    let old = old_path(exe);
+    if old.exists() {
+        fs::remove_file(&old)?;
+    }
+    fs::rename(exe, &old)?;
+    if let Err(e) = fs::rename(new, exe) {
+        fs::rename(&old, exe).unwrap_or(());
+        return Err(e);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::temp_dir::TempDir;
+
+    fn release(tag: &str, prerelease: bool) -> Release {
+        Release {
+            tag_name: tag.to_owned(),
+            draft: false,
+            prerelease,
+            assets: vec![],
+        }
+    }
+
+    #[test]
+    fn picks_release() {
+        let releases = vec![
+            release("v0.4.0-rc1", true),
+            release("v0.3.1", false),
+            release("0.3.2", false),
+            release("v0.2.9", false),
+        ];
+        assert_eq!(newest_release(&releases).unwrap().tag_name, "0.3.2");
+        assert_eq!(find_release(&releases, "0.3.1").unwrap().tag_name, "v0.3.1");
+        assert_eq!(
+            find_release(&releases, "v0.2.9").unwrap().tag_name,
+            "v0.2.9"
+        );
+        assert!(find_release(&releases, "0.1.0").is_none());
+    }
+
+    #[test]
+    fn asset_names() {
+        assert_eq!(
+            asset_name("linux", "x86_64").unwrap(),
+            "pyflow-x86_64-unknown-linux-musl"
+        );
+        assert_eq!(
+            asset_name("windows", "x86_64").unwrap(),
+            "pyflow-x86_64-pc-windows-msvc.exe"
+        );
+        assert!(asset_name("freebsd", "x86_64").is_none());
+    }
+
+    #[test]
+    fn detects_package_managers() {
+        let manager = |p: &str| package_manager(Path::new(p)).map(|m| m.0);
+        assert_eq!(manager("/snap/pyflow/12/bin/pyflow"), Some("Snap"));
+        assert_eq!(
+            manager("/opt/homebrew/Cellar/pyflow/0.3.1/bin/pyflow"),
+            Some("Homebrew")
+        );
+        assert_eq!(
+            manager("C:\\Users\\a\\scoop\\apps\\pyflow\\current\\pyflow.exe"),
+            Some("Scoop")
+        );
+        assert_eq!(manager("/home/a/.cargo/bin/pyflow"), Some("Cargo"));
+        assert_eq!(manager("/home/a/.local/share/pyflow-bin/pyflow"), None);
+    }
+
+    #[test]
+    fn detects_pip() {
+        let root = TempDir::new("self-update-pip");
+        fs::create_dir_all(root.join("bin")).unwrap();
+        fs::write(root.join("bin/pip"), "").unwrap();
+        fs::create_dir_all(root.join("lib/python3.11/site-packages/requests-2.31.0.dist-info"))
+            .unwrap();
+        // A binary copied next to an environment's pip wasn't installed by it.
+        assert!(!pip_installed(&root.join("bin/pyflow")));
+
+        fs::create_dir_all(root.join("lib/python3.11/site-packages/pyflow-0.3.1.dist-info"))
+            .unwrap();
+        assert!(pip_installed(&root.join("bin/pyflow")));
+
+        fs::create_dir_all(root.join("Lib/site-packages/pyflow-0.3.1.dist-info")).unwrap();
+        assert!(pip_installed(&root.join("Scripts/pyflow.exe")));
+    }
+}
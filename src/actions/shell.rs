@@ -0,0 +1,89 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use termcolor::Color;
+
+use crate::{commands, util};
+
+/// Run at startup by the interactive interpreter, via `PYTHONSTARTUP`.
+const STARTUP_FILE: &str = "pyflow_startup.py";
+
+/// Put our paths first on `sys.path`, mark the prompt, then run the user's own
+/// `PYTHONSTARTUP`, if they have one.
+fn startup_script(paths: &[PathBuf]) -> String {
+    let paths: Vec<String> = paths
+        .iter()
+        .map(|p| p.to_string_lossy().to_string())
+        .collect();
+    let paths = serde_json::to_string(&paths).unwrap_or_else(|_| "[]".into());
+
+    format!(
+        r#"import os as _os, sys as _sys
+for _p in reversed({}):
+    if _p in _sys.path:
+        _sys.path.remove(_p)
+    _sys.path.insert(0, _p)
+_sys.ps1 = "(pyflow) >>> "
+_sys.ps2 = "(pyflow) ... "
+_startup = _os.environ.get("PYFLOW_USER_STARTUP")
+if _startup and _os.path.isfile(_startup):
+    with open(_startup) as _f:
+        exec(_f.read())
+del _os, _sys, _p, _startup
+"#,
+        paths
+    )
+}
+
+/// Start an interactive interpreter, or IPython if `ipython` is set and it's installed, with
+/// the project's packages importable. Returns the interpreter's exit code.
+pub fn shell(
+    paths: &util::Paths,
+    pythonpath: &[PathBuf],
+    startup_dir: &Path,
+    ipython: bool,
+) -> i32 {
+    let startup_path = startup_dir.join(STARTUP_FILE);
+    if fs::write(&startup_path, startup_script(pythonpath)).is_err() {
+        util::abort("Problem writing the shell's startup file")
+    }
+
+    if let Ok(user_startup) = env::var("PYTHONSTARTUP") {
+        env::set_var("PYFLOW_USER_STARTUP", user_startup);
+    }
+    env::set_var("PYTHONSTARTUP", &startup_path);
+    env::set_var("PYFLOW_ACTIVE", "1");
+    util::set_pythonpath(pythonpath);
+
+    let mut cmd = Command::new(paths.bin.join("python"));
+    if ipython {
+        if paths.lib.join("IPython").exists() {
+            cmd.args(["-m", "IPython"]);
+        } else {
+            util::print_color(
+                "IPython isn't installed in this environment; using Python. To install it, run \
+                 `pyflow install ipython`",
+                Color::Yellow,
+            );
+        }
+    }
+
+    match commands::run_interactive(&mut cmd) {
+        Ok(code) => code,
+        Err(_) => util::abort("Problem starting Python"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn startup_paths() {
+        let script = startup_script(&[PathBuf::from("/proj/__pypackages__/3.10/lib")]);
+        assert!(script.contains(r#"reversed(["/proj/__pypackages__/3.10/lib"])"#));
+    }
+}
@@ -1,19 +1,178 @@
-use std::{path::PathBuf, process};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+};
 
 use termcolor::Color;
 
-use crate::{files, pyproject, util};
+use crate::{
+    dep_types::{Constraint, Version},
+    files,
+    py_versions::{self, Implementation, Interpreter},
+    pyproject,
+    util::{self, prompts::Fallback},
+};
 
-/// Updates `pyproject.toml` with a new python version
-pub fn switch(version: &str) {
+/// Updates `pyproject.toml` with a new python version. Accepts an implementation prefix,
+/// eg `pypy3.10`, and exact versions, eg `3.11.8`. With no version, lists interpreters instead.
+pub fn switch(version: &Option<String>, latest: bool, pyflow_dir: &Path) {
     let mut pcfg = pyproject::current::get_config().unwrap_or_else(|| process::exit(1));
+    let current = pcfg.config.py_version.clone();
+
+    let (implementation, mut specified) = match version {
+        Some(v) => {
+            let (implementation, v) = py_versions::split_implementation(v);
+            (
+                implementation.unwrap_or(pcfg.config.python_implementation),
+                util::fallible_v_parse(v),
+            )
+        }
+        None if latest => match &current {
+            Some(v) => (pcfg.config.python_implementation, v.clone()),
+            None => util::abort("Please specify a version, eg `pyflow switch --latest 3.11`"),
+        },
+        None => {
+            list_interpreters(&pcfg, pyflow_dir);
+            process::exit(0)
+        }
+    };
+
+    let python_requires = py_versions::parse_python_requires(&pcfg.config.python_requires);
+    if latest {
+        specified = newest_patch(&specified, implementation, pyflow_dir, &python_requires);
+    }
+    if !py_versions::could_satisfy(&specified, &python_requires) {
+        util::abort(&format!(
+            "Python {} doesn't satisfy `python_requires = \"{}\"` in `pyproject.toml`",
+            specified,
+            py_versions::requires_str(&python_requires)
+        ))
+    }
+
+    if current.as_ref() == Some(&specified) && implementation == pcfg.config.python_implementation {
+        util::print_status(
+            &format!("Already using {} {}", implementation, specified),
+            Color::Green,
+        );
+        process::exit(0)
+    }
+
+    if let Some(current) = &current {
+        if !util::prompts::confirm(
+            &format!(
+                "Change `py_version` in `pyproject.toml` from {}{} to {}{}?",
+                pcfg.config.python_implementation.prefix(),
+                current,
+                implementation.prefix(),
+                specified
+            ),
+            Fallback::Answer("yes".into()),
+        ) {
+            util::abort("Python version unchanged")
+        }
+    }
 
-    let specified = util::fallible_v_parse(version);
     pcfg.config.py_version = Some(specified.clone());
-    files::change_py_vers(&PathBuf::from(&pcfg.config_path), &specified);
-    util::print_color(
-        &format!("Switched to Python version {}", specified),
+    pcfg.config.python_implementation = implementation;
+    files::change_py_vers(
+        &PathBuf::from(&pcfg.config_path),
+        &specified,
+        implementation,
+    );
+    util::print_status(
+        &format!("Switched to {} version {}", implementation, specified),
         Color::Green,
     );
+
+    // List both implementations, so PyPy options are visible when switching to CPython.
+    let found: Vec<Interpreter> = py_versions::find_all_interpreters(pyflow_dir)
+        .into_iter()
+        .filter(|i| i.version.major == specified.major && i.version.minor == specified.minor)
+        .collect();
+    if !found.iter().any(|i| i.matches(&specified, implementation)) {
+        util::print_color(
+            &format!(
+                "No matching {} found on this system; one will be downloaded.",
+                implementation
+            ),
+            Color::Yellow,
+        );
+    }
+    if !found.is_empty() {
+        util::print_color("Interpreters for this version on this system:", Color::Cyan);
+        for i in found {
+            println!("  {}", i);
+        }
+    }
     // Don't exit program here; now that we've changed the cfg version, let's run the normal flow.
 }
+
+/// The newest patch of `version` that's installed, or available to download.
+fn newest_patch(
+    version: &Version,
+    implementation: Implementation,
+    pyflow_dir: &Path,
+    python_requires: &[Constraint],
+) -> Version {
+    let minor = Version::new_opt(version.major, version.minor, None);
+    let installed = py_versions::find_all_interpreters(pyflow_dir)
+        .into_iter()
+        .filter(|i| i.matches(&minor, implementation))
+        .map(|i| i.version)
+        .filter(|v| py_versions::satisfies(v, python_requires))
+        .max();
+    let available = py_versions::newest_available(&minor, implementation)
+        .filter(|v| py_versions::satisfies(v, python_requires));
+
+    installed.max(available).unwrap_or_else(|| {
+        util::abort(&format!(
+            "Can't find an installed or available {} {}",
+            implementation, minor
+        ))
+    })
+}
+
+/// Print a table of the interpreters we can find, marking the one this project uses.
+fn list_interpreters(pcfg: &pyproject::PresentConfig, pyflow_dir: &Path) {
+    let mut interpreters = py_versions::find_all_interpreters(pyflow_dir);
+    interpreters.sort_by(|a, b| b.version.cmp(&a.version));
+
+    let selected = pcfg
+        .config
+        .py_version
+        .as_ref()
+        .and_then(|v| {
+            py_versions::recorded_interpreter(&pcfg.pypackages_path.join(v.to_string_med()))
+        })
+        .map(|p| fs::canonicalize(&p).unwrap_or(p));
+
+    if interpreters.is_empty() {
+        util::print_color(
+            "No Python interpreters found on this system. Run `pyflow switch 3.x` to download one.",
+            Color::Yellow,
+        );
+        return;
+    }
+
+    println!("  {:<16} {:<20} Path", "Version", "Source");
+    for i in &interpreters {
+        let canon = fs::canonicalize(&i.path).unwrap_or_else(|_| i.path.clone());
+        let line = format!(
+            "{} {:<16} {:<20} {}",
+            if selected.as_ref() == Some(&canon) {
+                "*"
+            } else {
+                " "
+            },
+            i.name(),
+            i.source,
+            i.path.display()
+        );
+        if selected.as_ref() == Some(&canon) {
+            util::print_color(&line, Color::Green);
+        } else {
+            println!("{}", line);
+        }
+    }
+}
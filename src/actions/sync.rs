@@ -0,0 +1,195 @@
+use std::{collections::HashMap, path::Path};
+
+use termcolor::Color;
+
+use crate::{
+    dep_resolution,
+    dep_types::{Lock, LockPackage, Req, ReqType, Version},
+    files,
+    pyproject::{self, Hooks, PresentConfig, LOCK_FILENAME},
+    util::{
+        self, abort,
+        deps::{self, SyncSummary},
+        Os, Paths,
+    },
+};
+
+/// Install exactly what a fully-pinned requirements file, eg from `pip-compile`, lists for this
+/// environment, checking its hashes, and optionally save it as `pyflow.lock`.
+#[allow(clippy::too_many_arguments)]
+pub fn sync(
+    pcfg: &PresentConfig,
+    paths: &Paths,
+    path: &Path,
+    write_lock: bool,
+    require_hashes: bool,
+    os: Os,
+    py_vers: &Version,
+    hooks: &Hooks,
+) -> SyncSummary {
+    let parsed = files::parse_requirements_file(path)
+        .unwrap_or_else(|| abort(&format!("Can't read {}", path.to_string_lossy())));
+    // Leaving one out would install something other than what the file lists.
+    if !parsed.unparsed.is_empty() {
+        abort(&format!(
+            "Can't parse {}; pyflow understands only `sys_platform`, `python_version`, and `extra` \
+             markers, joined with `and`",
+            parsed.unparsed.join(", ")
+        ));
+    }
+    for line in &parsed.skipped {
+        util::print_color(&format!("Skipped {}", line), Color::Yellow);
+    }
+
+    let pins = pins(&parsed.reqs, os, py_vers);
+    let unhashed: Vec<&str> = pins
+        .iter()
+        .filter(|(name, _)| !parsed.hashes.contains_key(&util::standardize_name(name)))
+        .map(|(name, _)| name.as_str())
+        .collect();
+    if !unhashed.is_empty() {
+        let msg = format!("No `--hash` for {}", unhashed.join(", "));
+        if require_hashes {
+            abort(&format!("{}, so `--require-hashes` can't check them", msg));
+        }
+        log::warn!("{}; installing without checking them", msg);
+    }
+
+    let lock_packs = lock_packs(&pins, &parsed.via);
+    let summary = deps::sync_exact(paths, &lock_packs, &parsed.hashes, os, py_vers, hooks);
+
+    if write_lock {
+        let lock = Lock {
+            metadata: pcfg
+                .lock_path
+                .parent()
+                .map(|dir| pyproject::lock_metadata(dir, &[]))
+                .unwrap_or_default(),
+            package: Some(lock_packs),
+        };
+        if util::write_lock(&pcfg.lock_path, &lock).is_err() {
+            abort("Problem writing lock file");
+        }
+        util::print_status(
+            &format!("Wrote `{}` from {}", LOCK_FILENAME, path.to_string_lossy()),
+            Color::Green,
+        );
+    }
+    summary
+}
+
+/// The name and version of each requirement whose markers match this environment. Each must be
+/// pinned to one version. No extras are being installed, so `extra` markers never match.
+fn pins(reqs: &[Req], os: Os, py_vers: &Version) -> Vec<(String, Version)> {
+    reqs.iter()
+        .filter(|r| r.extra.is_none() && dep_resolution::markers_match(r, os, py_vers))
+        .map(|r| match r.constraints.as_slice() {
+            [c] if c.type_ == ReqType::Exact && !c.version.star => {
+                (r.name.clone(), c.version.clone())
+            }
+            _ => abort(&format!(
+                "{} isn't pinned to one version with `==`; `--from-requirements` installs \
+                 exactly what a file like pip-compile's lists",
+                r.name
+            )),
+        })
+        .collect()
+}
+
+/// Lock packages for the pins, with dependencies from pip-compile's `# via` comments.
+fn lock_packs(pins: &[(String, Version)], via: &HashMap<String, Vec<String>>) -> Vec<LockPackage> {
+    let source = |name: &str, version: &Version| {
+        format!("pypi+https://pypi.org/pypi/{}/{}/json", name, version)
+    };
+    pins.iter()
+        .enumerate()
+        .map(|(i, (name, version))| {
+            let dependencies = pins
+                .iter()
+                .filter(|(dep, _)| {
+                    via.get(&util::standardize_name(dep))
+                        .is_some_and(|parents| parents.iter().any(|p| util::compare_names(p, name)))
+                })
+                .map(|(dep, v)| format!("{} {} {}", dep, v, source(dep, v)))
+                .collect();
+            LockPackage {
+                id: i as u32 + 1,
+                name: name.clone(),
+                version: version.to_string(),
+                source: Some(source(name, version)),
+                dependencies: Some(dependencies),
+                rename: None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::temp_dir::TempDir;
+
+    #[test]
+    fn pip_compile_lock() {
+        let data = "\
+#
+# This file is autogenerated by pip-compile with Python 3.11
+#
+certifi==2024.2.2 \\
+    --hash=sha256:0569859f95fc761b18b45ef421b1290a0f65f147e92a1e5eb3e635f9a5e4e66f
+    # via requests
+idna==3.6 \\
+    --hash=sha256:c05567e9c24a6b9faaa835c4821bad0590fbb9d5779e7caa6e1cc4978e7eb24f
+    # via
+    #   anyio
+    #   requests
+requests==2.31.0
+    # via -r requirements.in
+pywin32==306 ; sys_platform == \"win32\"
+    # via -r requirements.in
+sphinx==7.2.6 ; extra == \"docs\"
+    # via -r requirements.in
+";
+        let dir = TempDir::new("sync");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("requirements.txt");
+        std::fs::write(&path, data).unwrap();
+        let parsed = files::parse_requirements_file(&path).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(parsed.hashes["idna"].len(), 1);
+        assert!(!parsed.hashes.contains_key("requests"));
+
+        let pins = pins(&parsed.reqs, Os::Linux, &Version::new(3, 11, 0));
+        let names: Vec<&str> = pins.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, vec!["certifi", "idna", "requests"]);
+
+        let packs = lock_packs(&pins, &parsed.via);
+        assert_eq!(packs[2].version, "2.31.0");
+        assert_eq!(
+            packs[2].dependencies.as_ref().unwrap(),
+            &vec![
+                "certifi 2024.2.2 pypi+https://pypi.org/pypi/certifi/2024.2.2/json".to_owned(),
+                "idna 3.6 pypi+https://pypi.org/pypi/idna/3.6/json".to_owned(),
+            ]
+        );
+        assert!(packs[0].dependencies.as_ref().unwrap().is_empty());
+    }
+
+    #[test]
+    fn unknown_markers() {
+        let dir = TempDir::new("sync-markers");
+        let path = dir.join("requirements.txt");
+        std::fs::write(
+            &path,
+            "idna==3.6 ; python_version >= \"3.8\"\n\
+             pyobjc==10.1 ; platform_machine == \"arm64\"\n\
+             colorama==0.4.6 ; sys_platform == \"win32\" or sys_platform == \"cygwin\"\n",
+        )
+        .unwrap();
+        let parsed = files::parse_requirements_file(&path).unwrap();
+        assert_eq!(parsed.reqs.len(), 1);
+        assert_eq!(parsed.unparsed.len(), 2);
+        assert!(parsed.unparsed[0].starts_with("requirements.txt:2: `pyobjc"));
+    }
+}
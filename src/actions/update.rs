@@ -0,0 +1,170 @@
+use std::{process, str::FromStr};
+
+use termcolor::Color;
+
+use super::list::find_outdated;
+use crate::{
+    cli_options::Within,
+    dep_types::{Constraint, LockPackage, Req, ReqType, Version},
+    pyproject::{Hooks, PresentConfig},
+    util::{
+        self, abort,
+        deps::{sync, SyncSummary},
+        patterns, Os, Paths,
+    },
+};
+
+/// Upgrade locked packages, or just `names`, to their newest compatible releases, limited by
+/// `within`. The upgraded packages, and those that depend on them, are re-resolved with their
+/// new (or, for dependents, current) versions pinned, so nothing else in the lock changes.
+#[allow(clippy::too_many_arguments)]
+pub fn update(
+    pcfg: &PresentConfig,
+    paths: &Paths,
+    names: &[String],
+    within: Option<Within>,
+    lockpacks: &[LockPackage],
+    os: Os,
+    py_vers: &Version,
+    no_dev: bool,
+    hooks: &Hooks,
+) -> SyncSummary {
+    for name in names {
+        if !lockpacks
+            .iter()
+            .any(|lp| util::compare_names(&lp.name, name))
+        {
+            abort(&format!("{} isn't in `pyflow.lock`", name))
+        }
+    }
+
+    // In a workspace, the lock covers every project's requirements.
+    let (mut reqs, mut dev_reqs) = pcfg.sync_reqs(&pcfg.config.reqs, &pcfg.config.dev_reqs);
+    let all_reqs = [reqs.as_slice(), dev_reqs.as_slice()].concat();
+    let targets: Vec<(String, Version)> = find_outdated(lockpacks, &all_reqs, py_vers, within)
+        .into_iter()
+        .filter(|o| names.is_empty() || names.iter().any(|n| util::compare_names(n, &o.name)))
+        .filter_map(|o| Some((o.name, Version::from_str(&o.latest_compatible?).ok()?)))
+        .collect();
+
+    if targets.is_empty() {
+        util::print_status("Nothing to update", Color::Green);
+        process::exit(0)
+    }
+
+    let dependents = dependents(lockpacks, &targets);
+    let dont_uninstall = util::find_dont_uninstall(&reqs, &dev_reqs);
+    for (name, version) in &targets {
+        pin(&mut reqs, &mut dev_reqs, name, version);
+    }
+    for lp in &dependents {
+        if let Ok(version) = Version::from_str(&lp.version) {
+            pin(&mut reqs, &mut dev_reqs, &lp.name, &version);
+        }
+    }
+
+    let unpinned: Vec<LockPackage> = lockpacks
+        .iter()
+        .filter(|lp| {
+            !targets
+                .iter()
+                .any(|(n, _)| util::compare_names(n, &lp.name))
+                && !dependents.iter().any(|d| d.name == lp.name)
+        })
+        .cloned()
+        .collect();
+
+    sync(
+        paths,
+        &unpinned,
+        &reqs,
+        &dev_reqs,
+        &pcfg.constraints,
+        &dont_uninstall,
+        os,
+        py_vers,
+        &pcfg.lock_path,
+        no_dev,
+        hooks,
+    )
+}
+
+/// Locked packages that depend on any of `targets`.
+fn dependents<'a>(
+    lockpacks: &'a [LockPackage],
+    targets: &[(String, Version)],
+) -> Vec<&'a LockPackage> {
+    lockpacks
+        .iter()
+        .filter(|lp| {
+            lp.dependencies.iter().flatten().any(|dep| {
+                patterns::LOCK_DEP_RE.captures(dep).is_some_and(|caps| {
+                    targets
+                        .iter()
+                        .any(|(n, _)| util::compare_names(n, caps.get(1).unwrap().as_str()))
+                })
+            })
+        })
+        .collect()
+}
+
+/// Require exactly `version` of `name` while resolving: added to its existing requirement if it
+/// has one, or as a new one otherwise. `pyproject.toml` isn't changed.
+fn pin(reqs: &mut Vec<Req>, dev_reqs: &mut [Req], name: &str, version: &Version) {
+    let constraint = Constraint::new(ReqType::Exact, version.clone());
+    let existing = reqs
+        .iter_mut()
+        .chain(dev_reqs.iter_mut())
+        .find(|r| util::compare_names(&r.name, name));
+    match existing {
+        Some(req) => req.constraints.push(constraint),
+        None => reqs.push(Req::new(name.to_owned(), vec![constraint])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lockpack(name: &str, version: &str, deps: &[&str]) -> LockPackage {
+        LockPackage {
+            id: 0,
+            name: name.to_owned(),
+            version: version.to_owned(),
+            source: None,
+            dependencies: Some(deps.iter().map(|d| d.to_string()).collect()),
+            rename: None,
+        }
+    }
+
+    #[test]
+    fn pins_and_dependents() {
+        let lock = vec![
+            lockpack("requests", "2.31.0", &["urllib3 2.0.7 >=1.21.1, <3"]),
+            lockpack("urllib3", "2.0.7", &[]),
+            lockpack("idna", "3.4.0", &[]),
+        ];
+        let targets = vec![("urllib3".to_owned(), Version::new(2, 0, 9))];
+        let names: Vec<&str> = dependents(&lock, &targets)
+            .iter()
+            .map(|lp| lp.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["requests"]);
+
+        let mut reqs = vec![Req::from_pip_str("requests>=2.0").unwrap()];
+        let mut dev_reqs: Vec<Req> = vec![];
+        pin(
+            &mut reqs,
+            &mut dev_reqs,
+            "Requests",
+            &Version::new(2, 31, 0),
+        );
+        pin(&mut reqs, &mut dev_reqs, "urllib3", &Version::new(2, 0, 9));
+        assert_eq!(reqs.len(), 2);
+        assert_eq!(reqs[0].constraints.len(), 2);
+        assert_eq!(
+            reqs[1].constraints,
+            vec![Constraint::new(ReqType::Exact, Version::new(2, 0, 9))]
+        );
+    }
+}
@@ -0,0 +1,376 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Component, Path, PathBuf},
+    process,
+};
+
+use serde::Serialize;
+use termcolor::Color;
+
+use crate::install::{hash_file, parse_record_line, RecordEntry};
+use crate::util::{self, normalize_path, patterns::DIST_INFO_RE, print_color, print_color_};
+
+/// Problems found for a single installed package.
+#[derive(Debug, Default, Serialize)]
+struct PackageReport {
+    name: String,
+    version: String,
+    modified: Vec<String>,
+    missing: Vec<String>,
+    extra: Vec<String>,
+    /// Files we couldn't read, and why; eg permission denied.
+    unreadable: Vec<String>,
+    skipped: Option<String>,
+    /// A modified file is hardlinked from pyflow's cache, so the cached copy changed too.
+    modified_in_cache: bool,
+}
+
+impl PackageReport {
+    fn is_ok(&self) -> bool {
+        self.modified.is_empty()
+            && self.missing.is_empty()
+            && self.extra.is_empty()
+            && self.unreadable.is_empty()
+    }
+}
+
+/// Re-hash every file listed in each installed package's `RECORD`, spread across cores, and
+/// compare with the recorded digests. Report modified, missing, extra, and unreadable files,
+/// grouped by package. Exits non-zero if any problems are found.
+pub fn verify(lib_path: &Path, json: bool) {
+    let reports = verify_lib(lib_path);
+    let failed = reports.iter().any(|r| !r.is_ok());
+
+    if json {
+        match serde_json::to_string_pretty(&reports) {
+            Ok(s) => println!("{}", s),
+            Err(_) => util::abort("Problem serializing verification results"),
+        }
+    } else {
+        print_reports(&reports);
+    }
+
+    process::exit(if failed { 1 } else { 0 })
+}
+
+fn verify_lib(lib_path: &Path) -> Vec<PackageReport> {
+    if !lib_path.exists() {
+        return vec![];
+    }
+
+    let mut dist_infos: Vec<(String, String, String)> = util::find_folders(lib_path)
+        .into_iter()
+        .filter_map(|folder| {
+            DIST_INFO_RE.captures(&folder).map(|caps| {
+                (
+                    caps.get(1).unwrap().as_str().to_owned(),
+                    caps.get(2).unwrap().as_str().to_owned(),
+                    folder.clone(),
+                )
+            })
+        })
+        .collect();
+    dist_infos.sort();
+
+    // Collect every file recorded by any package first, so files in shared (eg namespace)
+    // directories aren't reported as extra against a sibling package.
+    let mut records = HashMap::new();
+    let mut all_recorded = HashSet::new();
+    for (_, _, folder) in &dist_infos {
+        if let Ok(data) = fs::read_to_string(lib_path.join(folder).join("RECORD")) {
+            let entries: Vec<RecordEntry> = data.lines().filter_map(parse_record_line).collect();
+            for entry in &entries {
+                all_recorded.insert(normalize_path(&lib_path.join(&entry.path)));
+            }
+            records.insert(folder.clone(), entries);
+        }
+    }
+
+    let mut result = vec![];
+    // Each file to hash: its package's index in `result`, its entry, and its path.
+    let mut to_hash: Vec<(usize, &RecordEntry, PathBuf)> = vec![];
+    for (name, version, folder) in &dist_infos {
+        let mut report = PackageReport {
+            name: name.clone(),
+            version: version.clone(),
+            ..Default::default()
+        };
+
+        let dist_info_path = lib_path.join(folder);
+        if is_editable(&dist_info_path) {
+            report.skipped = Some("installed in editable mode".into());
+            result.push(report);
+            continue;
+        }
+
+        let entries = match records.get(folder) {
+            Some(e) => e,
+            None => {
+                report.skipped = Some("no RECORD file".into());
+                result.push(report);
+                continue;
+            }
+        };
+
+        let mut top_dirs = HashSet::new();
+        top_dirs.insert(dist_info_path.clone());
+
+        for entry in entries {
+            let path = lib_path.join(&entry.path);
+            if let Some(Component::Normal(first)) = Path::new(&entry.path).components().next() {
+                let top = lib_path.join(first);
+                if top.is_dir() {
+                    top_dirs.insert(top);
+                }
+            }
+
+            if !path.exists() {
+                report.missing.push(entry.path.clone());
+                continue;
+            }
+            // Eg the `RECORD` file itself, and `.pyc` files, have no hash.
+            if entry.hash.is_some() {
+                to_hash.push((result.len(), entry, path));
+            }
+        }
+
+        let mut top_dirs: Vec<PathBuf> = top_dirs.into_iter().collect();
+        top_dirs.sort();
+        for dir in top_dirs {
+            for file in find_files(&dir) {
+                if is_compiled(&file) || all_recorded.contains(&normalize_path(&file)) {
+                    continue;
+                }
+                let rel = file.strip_prefix(lib_path).unwrap_or(&file);
+                report.extra.push(rel.to_string_lossy().replace('\\', "/"));
+            }
+        }
+
+        result.push(report);
+    }
+
+    let hashes = util::pipeline::map(&to_hash, |(_, entry, path)| {
+        hash_file(path, &entry.hash.as_ref().unwrap().0)
+    });
+    for ((i, entry, path), actual) in to_hash.iter().zip(hashes) {
+        let report = &mut result[*i];
+        match actual {
+            Ok(Some(actual)) if actual == entry.hash.as_ref().unwrap().1 => (),
+            // Removed since we checked.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                report.missing.push(entry.path.clone())
+            }
+            Err(e) => report.unreadable.push(format!("{} ({})", entry.path, e)),
+            _ => {
+                report.modified_in_cache |= is_linked(path);
+                report.modified.push(entry.path.clone())
+            }
+        }
+    }
+    result
+}
+
+fn print_reports(reports: &[PackageReport]) {
+    if reports.is_empty() {
+        print_color("No packages are installed.", Color::Blue);
+        return;
+    }
+
+    let mut num_failed = 0;
+    for report in reports {
+        if let Some(reason) = &report.skipped {
+            print_color_(&report.name, Color::Cyan);
+            print_color(
+                &format!(" {}: skipped ({})", report.version, reason),
+                Color::Yellow,
+            );
+            continue;
+        }
+        if report.is_ok() {
+            continue;
+        }
+        num_failed += 1;
+
+        print_color_(&report.name, Color::Cyan);
+        print_color(&format!(" {}:", report.version), Color::White);
+        for (label, files) in &[
+            ("modified", &report.modified),
+            ("missing", &report.missing),
+            ("extra", &report.extra),
+            ("unreadable", &report.unreadable),
+        ] {
+            for file in files.iter() {
+                print_color(&format!("    {}: {}", label, file), Color::Red);
+            }
+        }
+        if report.modified_in_cache {
+            print_color(
+                &format!(
+                    "    These are linked from pyflow's cache, so the cached copies changed too. \
+                     Run `pyflow install --reinstall {}`, which extracts it again",
+                    report.name
+                ),
+                Color::Yellow,
+            );
+        }
+    }
+
+    if num_failed == 0 {
+        print_color(
+            &format!("All {} installed packages verified", reports.len()),
+            Color::Green,
+        );
+    } else {
+        print_color(
+            &format!("{} package(s) failed verification", num_failed),
+            Color::Red,
+        );
+    }
+}
+
+/// Whether a file has other hardlinks, eg to the cache, from `--link-mode hardlink` or `auto`.
+#[cfg(unix)]
+fn is_linked(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).is_ok_and(|m| m.nlink() > 1)
+}
+
+/// Only Unix protects linked files, so they're copied elsewhere.
+#[cfg(not(unix))]
+fn is_linked(_path: &Path) -> bool {
+    false
+}
+
+/// Editable installs point at source elsewhere, so their files won't match `RECORD`. They're
+/// marked by `dir_info.editable` in `direct_url.json`, per PEP 610.
+fn is_editable(dist_info_path: &Path) -> bool {
+    fs::read_to_string(dist_info_path.join("direct_url.json"))
+        .ok()
+        .and_then(|data| serde_json::from_str::<serde_json::Value>(&data).ok())
+        .and_then(|url| url.pointer("/dir_info/editable")?.as_bool())
+        .unwrap_or(false)
+}
+
+/// Bytecode is generated on import, and isn't listed in `RECORD`.
+fn is_compiled(path: &Path) -> bool {
+    path.components()
+        .any(|c| c.as_os_str().to_string_lossy() == "__pycache__")
+        || path.extension().is_some_and(|e| e == "pyc")
+}
+
+fn find_files(dir: &Path) -> Vec<PathBuf> {
+    let mut result = vec![];
+    if let Ok(entries) = dir.read_dir() {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                result.append(&mut find_files(&path));
+            } else {
+                result.push(path);
+            }
+        }
+    }
+    result.sort();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::util::temp_dir::TempDir;
+
+    #[test]
+    fn reports_in_record_order() {
+        let lib = TempDir::new("verify");
+        fs::create_dir_all(lib.join("tool-1.0.dist-info")).unwrap();
+        fs::create_dir_all(lib.join("tool")).unwrap();
+
+        let mut record = String::new();
+        for i in 0..50 {
+            let rel = format!("tool/m{}.py", i);
+            fs::write(lib.join(&rel), format!("VALUE = {}\n", i)).unwrap();
+            let hash = hash_file(&lib.join(&rel), "sha256").unwrap().unwrap();
+            record.push_str(&format!("{},sha256={},\n", rel, hash));
+        }
+        record.push_str("tool-1.0.dist-info/RECORD,,\n");
+        fs::write(lib.join("tool-1.0.dist-info/RECORD"), record).unwrap();
+        for i in &[31, 7, 44] {
+            fs::write(lib.join(format!("tool/m{}.py", i)), "CHANGED = True\n").unwrap();
+        }
+        fs::remove_file(lib.join("tool/m12.py")).unwrap();
+
+        let reports = verify_lib(&lib);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(
+            reports[0].modified,
+            vec!["tool/m7.py", "tool/m31.py", "tool/m44.py"]
+        );
+        assert_eq!(reports[0].missing, vec!["tool/m12.py"]);
+        assert!(reports[0].extra.is_empty() && reports[0].unreadable.is_empty());
+    }
+
+    /// Times hashing a package of 4000 files, 16 KiB each, on one thread and with
+    /// `pipeline::map`, after a first pass that loads them into the page cache. Run with
+    /// `cargo test hashing_speedup -- --ignored --nocapture`; with 4 or more cores, `map` must
+    /// take under three quarters of the time.
+    #[test]
+    #[ignore]
+    fn hashing_speedup() {
+        let lib = TempDir::new("verify-bench");
+        fs::create_dir_all(lib.join("big")).unwrap();
+        let paths: Vec<PathBuf> = (0..4000)
+            .map(|i| {
+                let path = lib.join(format!("big/m{}.py", i));
+                fs::write(&path, format!("# {}\n", i).repeat(16 * 1024 / 8)).unwrap();
+                path
+            })
+            .collect();
+        let hash = |path: &PathBuf| hash_file(path, "sha256").unwrap().unwrap();
+        let warm: Vec<String> = paths.iter().map(hash).collect();
+
+        let start = Instant::now();
+        let single: Vec<String> = paths.iter().map(hash).collect();
+        let single_time = start.elapsed();
+        let start = Instant::now();
+        let parallel = util::pipeline::map(&paths, hash);
+        let parallel_time = start.elapsed();
+
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+        println!(
+            "{} files on {} cores: {:.2?} on one thread, {:.2?} with `map`",
+            paths.len(),
+            cores,
+            single_time,
+            parallel_time
+        );
+        assert_eq!(single, warm);
+        assert_eq!(parallel, warm);
+        if cores >= 4 {
+            assert!(parallel_time * 4 < single_time * 3);
+        }
+    }
+
+    #[test]
+    fn editable_installs() {
+        let dist_info = TempDir::new("editable");
+        let editable = |json: &str| {
+            fs::write(dist_info.join("direct_url.json"), json).unwrap();
+            is_editable(&dist_info)
+        };
+        assert!(!is_editable(&dist_info));
+        assert!(editable(
+            r#"{"url": "file:///src/tool", "dir_info": {"editable": true}}"#
+        ));
+        assert!(!editable(
+            r#"{"url": "file:///src/tool", "dir_info": {"editable": false}}"#
+        ));
+        // Elsewhere than `dir_info`, it's not the marker.
+        assert!(!editable(
+            r#"{"url": "https://example.com/editable:true.whl", "archive_info": {"editable":true}}"#
+        ));
+        assert!(!editable("not json"));
+    }
+}
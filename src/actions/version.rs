@@ -0,0 +1,243 @@
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::{
+    cli_options::{BumpPart, VersionCommand},
+    commands,
+    dep_types::{Version, VersionModifier},
+    pyproject::{current, Config},
+    util::{self, abort},
+};
+
+/// Tables in `pyproject.toml` whose `version` is the project's.
+const VERSION_TABLES: &[&str] = &["[tool.pyflow]", "[tool.poetry]", "[project]"];
+
+/// Print the project's version, or change it in `pyproject.toml`, and in its `version-file` if
+/// it has one.
+pub fn version(cmd: Option<&VersionCommand>) {
+    let cfg_path = current::find_config_path()
+        .unwrap_or_else(|| abort("Can't find `pyproject.toml` here, or in a parent directory"));
+    let project_path = cfg_path.parent().unwrap();
+    let mut cfg = Config::from_file(&cfg_path).unwrap_or_default();
+    if cfg.version_from_git.is_some() && cmd.is_some() {
+        abort("The version comes from git tags; tag a release instead, eg `git tag v1.4.3`")
+    }
+    cfg.resolve_version();
+
+    let (new, tag) = match cmd {
+        None => {
+            match &cfg.version {
+                Some(v) => println!("{}", v),
+                None => abort("`pyproject.toml` doesn't have a version"),
+            }
+            return;
+        }
+        Some(VersionCommand::Bump { part, tag }) => {
+            let current = cfg.version.as_ref().unwrap_or_else(|| {
+                abort("`pyproject.toml` doesn't have a version; set one with `pyflow version set`")
+            });
+            (bump(current, *part), *tag)
+        }
+        Some(VersionCommand::Set { version, tag }) => {
+            let v: Version = version
+                .parse()
+                .unwrap_or_else(|_| abort(&format!("{} isn't a valid version", version)));
+            (v, *tag)
+        }
+    };
+
+    let mut changed = vec![cfg_path.as_path()];
+    rewrite(&cfg_path, &version_re(), Some(VERSION_TABLES), &new);
+
+    let version_file = cfg.version_file.as_ref().map(|f| project_path.join(f));
+    if let Some(path) = &version_file {
+        rewrite(path, &dunder_version_re(), None, &new);
+        changed.push(path.as_path());
+    }
+
+    match &cfg.version {
+        Some(old) => util::success(&format!("Changed the version from {} to {}", old, new)),
+        None => util::success(&format!("Set the version to {}", new)),
+    }
+
+    if tag {
+        let tag_name = format!("v{}", new);
+        if let Err(e) = commands::git_commit_and_tag(
+            project_path,
+            &changed,
+            &format!("Release {}", new),
+            &tag_name,
+        ) {
+            abort(&format!("Problem running git: {}", e))
+        }
+        util::success(&format!("Committed, and tagged {}", tag_name));
+    }
+}
+
+/// `version` with `part` incremented. Lower parts are reset, and a pre-release becomes the
+/// release it precedes, if that's the next one: `1.2.0a1` bumped by `minor` is `1.2.0`. Bumping
+/// `prerelease` increments a pre-release's number, or starts the next patch's first alpha.
+fn bump(version: &Version, part: BumpPart) -> Version {
+    let mut result = version.clone();
+    let (major, minor, patch) = (
+        version.major.unwrap_or(0),
+        version.minor.unwrap_or(0),
+        version.patch.unwrap_or(0),
+    );
+    let prerelease = matches!(
+        version.modifier,
+        Some((VersionModifier::Alpha, _))
+            | Some((VersionModifier::Beta, _))
+            | Some((VersionModifier::ReleaseCandidate, _))
+    );
+    // Keep unused parts unused, eg `1.2` to `1.3`, not `1.3.0`.
+    let zero = |v: Option<u32>| v.map(|_| 0);
+
+    match part {
+        BumpPart::Major => {
+            if !(prerelease && minor == 0 && patch == 0) {
+                result.major = Some(major + 1);
+                result.minor = zero(version.minor);
+                result.patch = zero(version.patch);
+            }
+        }
+        BumpPart::Minor => {
+            if !(prerelease && patch == 0) {
+                result.minor = Some(minor + 1);
+                result.patch = zero(version.patch);
+            }
+        }
+        BumpPart::Patch => {
+            if !prerelease {
+                result.patch = Some(patch + 1);
+            }
+        }
+        BumpPart::Prerelease => {
+            match &version.modifier {
+                Some((m, n)) if prerelease => result.modifier = Some((m.clone(), n + 1)),
+                Some(_) => abort(&format!(
+                    "{} isn't a pre-release; pre-releases end in `a`, `b`, or `rc`, and a number",
+                    version
+                )),
+                None => {
+                    result.minor = Some(minor);
+                    result.patch = Some(patch + 1);
+                    result.modifier = Some((VersionModifier::Alpha, 1));
+                }
+            }
+            result.extra_num = None;
+            return result;
+        }
+    }
+    result.extra_num = None;
+    result.modifier = None;
+    result
+}
+
+/// `version = "..."`, keeping its quotes and anything after.
+fn version_re() -> Regex {
+    Regex::new(r#"^(\s*version\s*=\s*["'])[^"']*(["'].*)$"#).unwrap()
+}
+
+/// `__version__ = "..."`, with or without a type annotation.
+fn dunder_version_re() -> Regex {
+    Regex::new(r#"^(\s*__version__\s*(?::\s*str\s*)?=\s*["'])[^"']*(["'].*)$"#).unwrap()
+}
+
+/// Set the version on the first line of `data` `re` matches, only looking in `tables` if given.
+/// The rest of `data` is left as-is. `None` if there's no such line.
+fn replace_version(
+    data: &str,
+    re: &Regex,
+    tables: Option<&[&str]>,
+    version: &Version,
+) -> Option<String> {
+    let mut table = "";
+    let mut found = false;
+    let mut result = String::with_capacity(data.len());
+    for line in data.split_inclusive('\n') {
+        let content = line.trim_end_matches(&['\n', '\r'][..]);
+        let trimmed = content.trim();
+        if trimmed.starts_with('[') {
+            table = trimmed;
+        }
+        let in_table = tables.map_or(true, |t| t.contains(&table));
+        match re.captures(content) {
+            Some(caps) if in_table && !found => {
+                found = true;
+                result.push_str(&caps[1]);
+                result.push_str(&version.to_string());
+                result.push_str(&caps[2]);
+                result.push_str(&line[content.len()..]);
+            }
+            _ => result.push_str(line),
+        }
+    }
+    if found {
+        Some(result)
+    } else {
+        None
+    }
+}
+
+fn rewrite(path: &Path, re: &Regex, tables: Option<&[&str]>, version: &Version) {
+    let data = util::loaded::read_to_string(path)
+        .unwrap_or_else(|e| abort(&format!("Problem reading {:?}: {}", path, e)));
+    let updated = replace_version(&data, re, tables, version).unwrap_or_else(|| {
+        abort(&format!(
+            "Can't find a version to change in {:?}",
+            path.file_name().unwrap_or_default()
+        ))
+    });
+    util::loaded::write(path, updated)
+        .unwrap_or_else(|e| abort(&format!("Problem writing {:?}: {}", path, e)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        version,
+        part,
+        expected,
+        case("1.2.3", BumpPart::Major, "2.0.0"),
+        case("1.2.3", BumpPart::Minor, "1.3.0"),
+        case("1.2.3", BumpPart::Patch, "1.2.4"),
+        case("1.2", BumpPart::Minor, "1.3"),
+        case("1.2.0a1", BumpPart::Prerelease, "1.2.0a2"),
+        case("1.2.0rc3", BumpPart::Prerelease, "1.2.0rc4"),
+        case("1.2.3", BumpPart::Prerelease, "1.2.4a1"),
+        case("1.2.0a1", BumpPart::Patch, "1.2.0"),
+        case("1.2.0b2", BumpPart::Minor, "1.2.0"),
+        case("1.2.1b2", BumpPart::Minor, "1.3.0"),
+        case("2.0.0rc1", BumpPart::Major, "2.0.0"),
+        case("1.2.3.4", BumpPart::Patch, "1.2.4")
+    )]
+    fn bumps(version: &str, part: BumpPart, expected: &str) {
+        assert_eq!(bump(&version.parse().unwrap(), part).to_string(), expected);
+    }
+
+    #[test]
+    fn replaces_version() {
+        let cfg = "[tool.pyflow.dependencies]\nversion = \"^1.0\"\n\n[tool.pyflow]\r\n\
+                   name = \"acme\"\r\nversion = '0.1.0'  # Keep in sync\r\n";
+        let new = Version::new(0, 2, 0);
+        assert_eq!(
+            replace_version(cfg, &version_re(), Some(VERSION_TABLES), &new).unwrap(),
+            "[tool.pyflow.dependencies]\nversion = \"^1.0\"\n\n[tool.pyflow]\r\n\
+             name = \"acme\"\r\nversion = '0.2.0'  # Keep in sync\r\n"
+        );
+        assert!(
+            replace_version("[tool.pyflow]\n", &version_re(), Some(VERSION_TABLES), &new).is_none()
+        );
+
+        let module = "\"\"\"Acme.\"\"\"\n__version__: str = \"0.1.0\"\n";
+        assert_eq!(
+            replace_version(module, &dunder_version_re(), None, &new).unwrap(),
+            "\"\"\"Acme.\"\"\"\n__version__: str = \"0.2.0\"\n"
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use std::path::Path;
+
+use termcolor::Color;
+
+use crate::{
+    actions::run::{resolve, shadowed_by, Target},
+    pyproject::Config,
+    util::{self, print_color, print_color_},
+};
+
+/// Show where `pyflow run <name>` looks, in order, what it finds, and programs on the `PATH`
+/// with the same name, which it doesn't run. If `quiet`, only print what it runs.
+pub fn which(name: &str, cfg: &Config, bin_path: &Path, entry_pt_path: &Path, quiet: bool) {
+    let target = resolve(name, cfg, bin_path, entry_pt_path);
+    let chosen = match &target {
+        Some(Target::Script(s)) => Some(s.clone()),
+        Some(Target::Python(p)) | Some(Target::Installed(p)) => Some(p.display().to_string()),
+        None => None,
+    };
+
+    if quiet {
+        match chosen {
+            Some(c) => println!("{}", c),
+            None => std::process::exit(1),
+        }
+        return;
+    }
+
+    let found = |n: usize| -> String {
+        match (&target, n) {
+            (Some(Target::Script(_)), 1)
+            | (Some(Target::Python(_)), 2)
+            | (Some(Target::Installed(_)), 3) => chosen.clone().unwrap_or_default(),
+            _ => "-".into(),
+        }
+    };
+
+    print_color(
+        &format!("`pyflow run {}` looks, in order, at:", name),
+        Color::Blue,
+    );
+    println!("  1. [tool.pyflow.scripts]: {}", found(1));
+    println!("  2. The environment's Python: {}", found(2));
+    println!(
+        "  3. Console scripts in {}: {}",
+        entry_pt_path.display(),
+        found(3)
+    );
+
+    let on_path = match &target {
+        Some(Target::Installed(p)) => shadowed_by(p, name),
+        _ => util::find_on_path(name),
+    };
+    if !on_path.is_empty() {
+        print_color("\nOn the PATH, but not run by pyflow:", Color::Blue);
+        for p in on_path {
+            println!("  {}", p.display());
+        }
+    }
+
+    match chosen {
+        Some(c) => {
+            print_color_("\nRuns: ", Color::Green);
+            println!("{}", c);
+        }
+        None => util::abort(&format!(
+            "\nNothing found for `{}`. To install it, try `pyflow install {}`",
+            name, name
+        )),
+    }
+}
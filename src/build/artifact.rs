@@ -0,0 +1,272 @@
+//! Checks that a wheel or sdist is intact before it's uploaded: that it opens and reads to the
+//! end, that a wheel's files match its `RECORD`, and that an sdist has one top-level folder,
+//! with a `PKG-INFO`.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, Cursor, Read},
+};
+
+use flate2::read::GzDecoder;
+use ring::digest;
+
+use crate::install::{parse_record_line, RecordEntry};
+
+/// Problems with the archive `data`, named `file_name`.
+pub(super) fn verify(file_name: &str, data: &[u8]) -> Vec<String> {
+    if file_name.ends_with(".whl") {
+        verify_wheel(data)
+    } else {
+        verify_sdist(data)
+    }
+}
+
+fn verify_wheel(data: &[u8]) -> Vec<String> {
+    let mut archive = match zip::ZipArchive::new(Cursor::new(data)) {
+        Ok(a) => a,
+        Err(e) => return vec![format!("It isn't a valid zip file: {}", e)],
+    };
+    let mut result = vec![];
+    let mut contents = BTreeMap::new();
+    for i in 0..archive.len() {
+        let mut file = match archive.by_index(i) {
+            Ok(f) => f,
+            Err(e) => {
+                result.push(format!("Can't read the zip file's entry {}: {}", i, e));
+                continue;
+            }
+        };
+        let name = file.name().to_owned();
+        if name.ends_with('/') {
+            continue;
+        }
+        let mut buf = vec![];
+        match file.read_to_end(&mut buf) {
+            Ok(_) => {
+                contents.insert(name, buf);
+            }
+            Err(e) => result.push(format!("Can't read {}: {}", name, e)),
+        }
+    }
+
+    let dist_infos: Vec<&str> = contents
+        .keys()
+        .filter_map(|n| n.strip_suffix("/RECORD"))
+        .filter(|d| d.ends_with(".dist-info") && !d.contains('/'))
+        .collect();
+    let dist_info = match dist_infos.as_slice() {
+        [d] => d.to_string(),
+        [] => {
+            result.push("There's no `.dist-info/RECORD`".to_owned());
+            return result;
+        }
+        _ => {
+            result.push(format!(
+                "There's more than one `.dist-info` folder: {}",
+                dist_infos.join(", ")
+            ));
+            return result;
+        }
+    };
+    for required in &["METADATA", "WHEEL"] {
+        if !contents.contains_key(&format!("{}/{}", dist_info, required)) {
+            result.push(format!("There's no {}/{}", dist_info, required));
+        }
+    }
+
+    let record_path = format!("{}/RECORD", dist_info);
+    let record = String::from_utf8_lossy(&contents[&record_path]).into_owned();
+    let entries: BTreeMap<String, RecordEntry> = record
+        .lines()
+        .filter_map(parse_record_line)
+        .map(|e| (e.path.clone(), e))
+        .collect();
+    // Signatures of `RECORD` can't be listed in it.
+    let unlisted = [
+        record_path.clone(),
+        format!("{}.jws", record_path),
+        format!("{}.p7s", record_path),
+    ];
+    for (name, data) in &contents {
+        if unlisted.contains(name) {
+            continue;
+        }
+        let entry = match entries.get(name) {
+            Some(e) => e,
+            None => {
+                result.push(format!("{} isn't in RECORD", name));
+                continue;
+            }
+        };
+        match &entry.hash {
+            Some((algorithm, expected)) => match hash(algorithm, data) {
+                Some(actual) if &actual == expected => (),
+                Some(_) => result.push(format!("{} doesn't match its hash in RECORD", name)),
+                None => result.push(format!(
+                    "{} is hashed with {} in RECORD, which isn't allowed",
+                    name, algorithm
+                )),
+            },
+            None => result.push(format!("{} has no hash in RECORD", name)),
+        }
+        if entry.size.is_some_and(|s| s != data.len() as u64) {
+            result.push(format!("{} isn't the size RECORD says", name));
+        }
+    }
+    for path in entries.keys() {
+        if !contents.contains_key(path) {
+            result.push(format!("{} is in RECORD, but not in the wheel", path));
+        }
+    }
+    result
+}
+
+fn verify_sdist(data: &[u8]) -> Vec<String> {
+    let mut archive = tar::Archive::new(GzDecoder::new(data));
+    let entries = match archive.entries() {
+        Ok(e) => e,
+        Err(e) => return vec![format!("It isn't a valid .tar.gz file: {}", e)],
+    };
+    let mut result = vec![];
+    let mut roots = BTreeSet::new();
+    let mut has_pkg_info = false;
+    for entry in entries {
+        let mut entry = match entry {
+            Ok(e) => e,
+            Err(e) => {
+                result.push(format!("It isn't a valid .tar.gz file: {}", e));
+                return result;
+            }
+        };
+        let path = match entry.path() {
+            Ok(p) => p.to_string_lossy().replace('\\', "/"),
+            Err(e) => {
+                result.push(format!("An entry has an unreadable path: {}", e));
+                continue;
+            }
+        };
+        // Read it through, so a truncated archive is caught.
+        if let Err(e) = io::copy(&mut entry, &mut io::sink()) {
+            result.push(format!("Can't read {}: {}", path, e));
+            return result;
+        }
+        let path = path.trim_start_matches("./");
+        if path.starts_with('/') || path.split('/').any(|p| p == "..") {
+            result.push(format!(
+                "{} would be extracted outside the sdist's folder",
+                path
+            ));
+            continue;
+        }
+        let mut parts = path.splitn(2, '/');
+        roots.insert(parts.next().unwrap_or_default().to_owned());
+        if parts.next() == Some("PKG-INFO") {
+            has_pkg_info = true;
+        }
+    }
+    if roots.len() != 1 {
+        let roots: Vec<String> = roots.into_iter().collect();
+        result.push(format!(
+            "It should have one top-level folder, but has {}: {}",
+            roots.len(),
+            roots.join(", ")
+        ));
+    } else if !has_pkg_info {
+        result.push("There's no PKG-INFO in its top-level folder".to_owned());
+    }
+    result
+}
+
+/// A `RECORD` hash: urlsafe base64, without padding. Only SHA-2 hashes are allowed.
+fn hash(algorithm: &str, data: &[u8]) -> Option<String> {
+    let algorithm = match algorithm {
+        "sha256" => &digest::SHA256,
+        "sha384" => &digest::SHA384,
+        "sha512" => &digest::SHA512,
+        _ => return None,
+    };
+    Some(data_encoding::BASE64URL_NOPAD.encode(digest::digest(algorithm, data).as_ref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    fn wheel(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        for (name, data) in files {
+            zip.start_file(*name, zip::write::FileOptions::default())
+                .unwrap();
+            zip.write_all(data.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn record_line(name: &str, data: &str) -> String {
+        format!(
+            "{},sha256={},{}\n",
+            name,
+            hash("sha256", data.as_bytes()).unwrap(),
+            data.len()
+        )
+    }
+
+    #[test]
+    fn wheels() {
+        let (module, metadata, wheel_file) = ("a = 1\n", "Name: a\n", "Wheel-Version: 1.0\n");
+        let record = record_line("a.py", module)
+            + &record_line("a-1.0.dist-info/METADATA", metadata)
+            + &record_line("a-1.0.dist-info/WHEEL", wheel_file)
+            + "a-1.0.dist-info/RECORD,,\n";
+        let good = wheel(&[
+            ("a.py", module),
+            ("a-1.0.dist-info/METADATA", metadata),
+            ("a-1.0.dist-info/WHEEL", wheel_file),
+            ("a-1.0.dist-info/RECORD", &record),
+        ]);
+        assert!(verify("a-1.0-py3-none-any.whl", &good).is_empty());
+
+        let tampered = wheel(&[
+            ("a.py", "a = 2\n"),
+            ("b.py", ""),
+            ("a-1.0.dist-info/METADATA", metadata),
+            ("a-1.0.dist-info/RECORD", &record),
+        ]);
+        assert_eq!(
+            verify("a-1.0-py3-none-any.whl", &tampered),
+            vec![
+                "There's no a-1.0.dist-info/WHEEL",
+                "a.py doesn't match its hash in RECORD",
+                "b.py isn't in RECORD",
+                "a-1.0.dist-info/WHEEL is in RECORD, but not in the wheel",
+            ]
+        );
+        assert_eq!(verify("a-1.0-py3-none-any.whl", b"nope").len(), 1);
+    }
+
+    #[test]
+    fn sdists() {
+        let sdist = |paths: &[&str]| {
+            let mut builder = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+            for path in paths {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(0);
+                header.set_mode(0o644);
+                header.set_cksum();
+                builder.append_data(&mut header, path, io::empty()).unwrap();
+            }
+            builder.into_inner().unwrap().finish().unwrap()
+        };
+        assert!(verify("a-1.0.tar.gz", &sdist(&["a-1.0/PKG-INFO", "a-1.0/a.py"])).is_empty());
+        assert_eq!(
+            verify("a-1.0.tar.gz", &sdist(&["a-1.0/PKG-INFO", "a.py"])),
+            vec!["It should have one top-level folder, but has 2: a-1.0, a.py"]
+        );
+        assert_eq!(
+            verify("a-1.0.tar.gz", &sdist(&["a-1.0/a.py"])),
+            vec!["There's no PKG-INFO in its top-level folder"]
+        );
+    }
+}
@@ -0,0 +1,246 @@
+//! Checking trove classifiers against PyPI's list, since it rejects uploads with ones it doesn't
+//! know, after everything's been built. The list is downloaded when first needed, and cached.
+//! https://pypi.org/classifiers/
+
+use std::{
+    fs,
+    path::Path,
+    process,
+    time::{Duration, SystemTime},
+};
+
+use termcolor::Color;
+
+use crate::util;
+
+/// Every classifier PyPI accepts, one per line. Deprecated ones aren't listed.
+const CLASSIFIERS_URL: &str = "https://pypi.org/pypi?%3Aaction=list_classifiers";
+const CLASSIFIERS_FILENAME: &str = "classifiers.txt";
+/// How long before we download the list again. New classifiers are added now and then; they're
+/// rarely removed.
+const CLASSIFIERS_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Classifiers PyPI no longer lists, and what to use instead, if anything. From the
+/// `trove-classifiers` package.
+const DEPRECATED: &[(&str, &[&str])] = &[
+    (
+        "Natural Language :: Ukranian",
+        &["Natural Language :: Ukrainian"],
+    ),
+    (
+        "Topic :: Communications :: Chat :: AOL Instant Messenger",
+        &[],
+    ),
+    ("License :: OSI Approved :: Intel Open Source License", &[]),
+    ("License :: OSI Approved :: Jabber Open Source License", &[]),
+    (
+        "License :: OSI Approved :: MITRE Collaborative Virtual Workspace License (CVW)",
+        &[],
+    ),
+    ("License :: OSI Approved :: Sleepycat License", &[]),
+    ("License :: OSI Approved :: Sun Public License", &[]),
+    (
+        "License :: OSI Approved :: Vovida Software License 1.0",
+        &[],
+    ),
+    ("License :: OSI Approved :: W3C License", &[]),
+];
+
+/// Check the project's classifiers before building, and abort if PyPI would reject any.
+/// Deprecated ones are warned about, including license ones if there's a `license` expression.
+pub fn verify_classifiers(pyflow_path: &Path, classifiers: &[String], license_expression: bool) {
+    if classifiers.is_empty() {
+        return;
+    }
+    let known = match known(pyflow_path) {
+        Some(k) => k,
+        None => return,
+    };
+    let classifiers: Vec<&str> = classifiers.iter().map(String::as_str).collect();
+    let (errors, warnings) = check(&classifiers, &known, license_expression);
+    for warning in warnings {
+        log::warn!("{}", warning);
+    }
+    if !errors.is_empty() {
+        util::abort(&format!(
+            "PyPI would reject these classifiers:\n  {}\nFix them under `[tool.pyflow]`, or pass \
+             `--no-verify-classifiers` if the index accepts them",
+            errors.join("\n  ")
+        ))
+    }
+}
+
+/// PyPI's classifiers: cached, and downloaded again once they're a week old. If they can't be
+/// downloaded, an older copy will do. `None`, with a warning, if there's nothing to check against.
+pub(super) fn known(pyflow_path: &Path) -> Option<Vec<String>> {
+    let cache_path = util::paths::index_cache_path(pyflow_path);
+    let file = cache_path.join(CLASSIFIERS_FILENAME);
+    let cached = fs::read_to_string(&file).ok();
+    let age = fs::metadata(&file)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+
+    if cached.is_none() || age.map_or(true, |a| a > CLASSIFIERS_TTL) {
+        if let Some(data) = fetch() {
+            save(&cache_path, &data);
+            return Some(parse(&data));
+        }
+    }
+    match cached {
+        Some(data) => Some(parse(&data)),
+        None => {
+            util::print_color(
+                "Problem downloading PyPI's list of classifiers; they won't be checked",
+                Color::Yellow,
+            );
+            None
+        }
+    }
+}
+
+fn fetch() -> Option<String> {
+    let response = util::http_client()
+        .get(CLASSIFIERS_URL)
+        .header(
+            "User-Agent",
+            format!("pyflow/{}", env!("CARGO_PKG_VERSION")),
+        )
+        .send()
+        .ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+    let data = response.text().ok()?;
+    // A sanity check, so an error page isn't cached as the list.
+    if !data
+        .lines()
+        .any(|l| l.starts_with("Programming Language :: Python"))
+    {
+        return None;
+    }
+    Some(data)
+}
+
+/// Write to a temporary file first, so nothing reads a partial list.
+fn save(cache_path: &Path, data: &str) {
+    if fs::create_dir_all(cache_path).is_err() {
+        return;
+    }
+    let temp = cache_path.join(format!("{}.{}", CLASSIFIERS_FILENAME, process::id()));
+    if fs::write(&temp, data).is_ok()
+        && fs::rename(&temp, cache_path.join(CLASSIFIERS_FILENAME)).is_err()
+    {
+        let _ = fs::remove_file(&temp);
+    }
+}
+
+fn parse(data: &str) -> Vec<String> {
+    data.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+/// Classifiers PyPI would reject, with the closest known one, and warnings about deprecated
+/// ones. With a `License-Expression`, `License ::` classifiers are deprecated too; PEP 639.
+pub(super) fn check(
+    classifiers: &[&str],
+    known: &[String],
+    license_expression: bool,
+) -> (Vec<String>, Vec<String>) {
+    let (mut errors, mut warnings) = (vec![], vec![]);
+    for classifier in classifiers {
+        if let Some((_, instead)) = DEPRECATED.iter().find(|(d, _)| d == classifier) {
+            warnings.push(if instead.is_empty() {
+                format!("`{}` is deprecated", classifier)
+            } else {
+                format!(
+                    "`{}` is deprecated; use `{}` instead",
+                    classifier,
+                    instead.join("`, `")
+                )
+            });
+            continue;
+        }
+        if classifier.starts_with("Private ::") {
+            errors.push(format!(
+                "`{}`: PyPI rejects `Private ::` classifiers, to stop private packages being \
+                 published by mistake",
+                classifier
+            ));
+            continue;
+        }
+        if !known.iter().any(|k| k == classifier) {
+            errors.push(match closest(classifier, known) {
+                Some(c) => format!("`{}` isn't a classifier; did you mean `{}`?", classifier, c),
+                None => format!("`{}` isn't a classifier", classifier),
+            });
+            continue;
+        }
+        if license_expression && classifier.starts_with("License ::") {
+            warnings.push(format!(
+                "`{}` is deprecated, since there's a license expression",
+                classifier
+            ));
+        }
+    }
+    (errors, warnings)
+}
+
+/// The known classifier most like `classifier`, if any is close. Case is ignored.
+fn closest<'a>(classifier: &str, known: &'a [String]) -> Option<&'a str> {
+    let lower = classifier.to_lowercase();
+    known
+        .iter()
+        .map(|k| (util::edit_distance(&lower, &k.to_lowercase()), k))
+        .filter(|(d, _)| *d <= 3.max(classifier.len() / 5))
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, k)| k.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks() {
+        let known = parse(
+            "Development Status :: 4 - Beta\n\
+             License :: OSI Approved :: MIT License\n\
+             Natural Language :: Ukrainian\n\
+             Programming Language :: Python :: 3.12\n",
+        );
+        let classifiers = [
+            "Development Status :: 4 - Beta",
+            "Programing Language :: Python :: 3.12",
+            "Topic :: Made Up",
+            "Private :: Do Not Upload",
+            "Natural Language :: Ukranian",
+            "License :: OSI Approved :: MIT License",
+        ];
+        let (errors, warnings) = check(&classifiers, &known, false);
+        assert_eq!(
+            errors,
+            vec![
+                "`Programing Language :: Python :: 3.12` isn't a classifier; did you mean \
+                 `Programming Language :: Python :: 3.12`?",
+                "`Topic :: Made Up` isn't a classifier",
+                "`Private :: Do Not Upload`: PyPI rejects `Private ::` classifiers, to stop \
+                 private packages being published by mistake",
+            ]
+        );
+        assert_eq!(
+            warnings,
+            vec![
+                "`Natural Language :: Ukranian` is deprecated; use `Natural Language :: \
+                 Ukrainian` instead"
+            ]
+        );
+
+        let (errors, warnings) = check(&classifiers[5..], &known, true);
+        assert!(errors.is_empty());
+        assert_eq!(warnings.len(), 1);
+    }
+}
@@ -0,0 +1,183 @@
+//! Checks that a package's description will render on PyPI, which rejects uploads whose
+//! description can't be, as `twine check` does.
+//!
+//! Markdown and plain text always render. reStructuredText, which is also what's assumed when no
+//! content type is given, is rendered with warnings treated as errors, so it's checked for the
+//! mistakes docutils reports most often.
+
+use regex::Regex;
+
+/// Description formats PyPI can render.
+const CONTENT_TYPES: &[&str] = &["text/plain", "text/x-rst", "text/markdown"];
+
+const MARKDOWN_VARIANTS: &[&str] = &["gfm", "commonmark"];
+
+/// Characters docutils accepts for section underlines.
+const UNDERLINE_CHARS: &str = "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+
+/// Directives docutils knows; others are errors.
+const DIRECTIVES: &[&str] = &[
+    "admonition",
+    "attention",
+    "caution",
+    "class",
+    "code",
+    "code-block",
+    "compound",
+    "container",
+    "contents",
+    "csv-table",
+    "danger",
+    "date",
+    "default-role",
+    "epigraph",
+    "error",
+    "figure",
+    "footer",
+    "header",
+    "highlights",
+    "hint",
+    "image",
+    "important",
+    "include",
+    "line-block",
+    "list-table",
+    "math",
+    "meta",
+    "note",
+    "parsed-literal",
+    "pull-quote",
+    "raw",
+    "replace",
+    "role",
+    "rubric",
+    "section-numbering",
+    "sectnum",
+    "sidebar",
+    "sourcecode",
+    "table",
+    "target-notes",
+    "tip",
+    "title",
+    "topic",
+    "unicode",
+    "warning",
+];
+
+/// Reasons PyPI would reject `description`, given its `Description-Content-Type`.
+pub(super) fn check(content_type: Option<&str>, description: &str) -> Vec<String> {
+    let mut result = vec![];
+    let mut parts = content_type.unwrap_or("text/x-rst").split(';');
+    let mime = parts.next().unwrap_or_default().trim().to_lowercase();
+    if !CONTENT_TYPES.contains(&mime.as_str()) {
+        result.push(format!(
+            "Description-Content-Type `{}` isn't one of {}",
+            content_type.unwrap_or_default(),
+            CONTENT_TYPES.join(", ")
+        ));
+        return result;
+    }
+    for param in parts {
+        let (key, value) = match param.split_once('=') {
+            Some((k, v)) => (k.trim().to_lowercase(), v.trim().to_lowercase()),
+            None => continue,
+        };
+        match key.as_str() {
+            "charset" if value != "utf-8" => result.push(format!(
+                "The description's charset is `{}`, but PyPI only accepts UTF-8",
+                value
+            )),
+            "variant"
+                if mime == "text/markdown" && !MARKDOWN_VARIANTS.contains(&value.as_str()) =>
+            {
+                result.push(format!(
+                    "The Markdown variant `{}` isn't GFM, or CommonMark",
+                    value
+                ))
+            }
+            _ => (),
+        }
+    }
+    if mime == "text/x-rst" {
+        result.extend(check_rst(description));
+    }
+    result
+}
+
+/// Problems docutils reports as warnings or errors, with line numbers.
+fn check_rst(text: &str) -> Vec<String> {
+    let directive = Regex::new(r"^\s*\.\.\s+([A-Za-z0-9_:+.-]+?)::").unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    let mut result = vec![];
+
+    for (i, line) in lines.iter().enumerate() {
+        if let Some(caps) = directive.captures(line) {
+            let name = caps[1].to_lowercase();
+            if !DIRECTIVES.contains(&name.as_str()) {
+                result.push(format!(
+                    "line {}: unknown reStructuredText directive `{}`",
+                    i + 1,
+                    &caps[1]
+                ));
+            }
+        }
+
+        // A title, then an underline of one repeated punctuation character. Shorter ones are
+        // only taken as titles from 4 characters up.
+        let title = line.trim_end();
+        let underline = match lines.get(i + 1) {
+            Some(u) => u.trim_end(),
+            None => continue,
+        };
+        if title.is_empty() || title.starts_with(char::is_whitespace) || is_underline(title) {
+            continue;
+        }
+        if is_underline(underline)
+            && underline.chars().count() >= 4
+            && underline.chars().count() < title.chars().count()
+        {
+            result.push(format!("line {}: title underline too short", i + 2));
+        }
+    }
+    result
+}
+
+fn is_underline(line: &str) -> bool {
+    let mut chars = line.chars();
+    match chars.next() {
+        Some(first) if UNDERLINE_CHARS.contains(first) => chars.all(|c| c == first),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_types() {
+        assert!(check(Some("text/markdown; charset=UTF-8; variant=GFM"), "# Hi").is_empty());
+        assert!(check(Some("text/plain"), "Title\n==\n.. nope::").is_empty());
+        assert_eq!(check(Some("text/html"), "").len(), 1);
+        assert_eq!(
+            check(Some("text/markdown; charset=latin-1; variant=mystery"), "").len(),
+            2
+        );
+    }
+
+    #[test]
+    fn rst() {
+        let good = "Everything killer\n=================\n\nSmall, but packs a punch!\n\n\
+                    .. code-block:: python\n\n    import everything_killer\n\n.. note::\n\n    Hi\n";
+        assert!(check(Some("text/x-rst"), good).is_empty());
+
+        let bad = "Everything killer\n=======\n\n.. badge:: x\n\n----\n\nShort\n-----\n";
+        assert_eq!(
+            check(None, bad),
+            vec![
+                "line 2: title underline too short",
+                "line 4: unknown reStructuredText directive `badge`",
+            ]
+        );
+    }
+}
@@ -0,0 +1,188 @@
+//! Which of a project's files go in its wheel and sdist: `include` and `exclude` patterns from
+//! `[tool.pyflow.build]`, on top of defaults that leave out caches, VCS folders, environments,
+//! and build output.
+//!
+//! Paths are matched relative to the project root, with `/` separators, whatever the platform,
+//! so a set of patterns selects the same files on Windows as elsewhere.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+
+use crate::{pyproject::Config, util};
+
+/// Left out unless they're named in `include`: VCS and tool folders, caches, environments,
+/// and build output. Patterns without a `/` match at any depth.
+const DEFAULT_EXCLUDE: &[&str] = &[
+    ".*",
+    "__pycache__",
+    "*.pyc",
+    "*.pyo",
+    "*.so",
+    "*.pyd",
+    "*.egg-info",
+    "/__pypackages__",
+    "/build",
+    "/dist",
+    "/venv",
+];
+
+/// The project's `include` and `exclude` patterns, and the defaults.
+pub(super) struct FileSet {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+    defaults: Vec<Regex>,
+}
+
+impl FileSet {
+    pub(super) fn new(cfg: &Config) -> Self {
+        let patterns = |ps: &[String]| ps.iter().map(|p| glob_regex(p)).collect();
+        Self {
+            include: patterns(&cfg.include),
+            exclude: patterns(&cfg.exclude),
+            defaults: DEFAULT_EXCLUDE.iter().map(|p| glob_regex(p)).collect(),
+        }
+    }
+
+    /// Only the project's own patterns, without the defaults.
+    pub(super) fn without_defaults(&self) -> Self {
+        Self {
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+            defaults: vec![],
+        }
+    }
+
+    /// If there are `include` patterns, which limit an sdist's files.
+    pub(super) fn has_include(&self) -> bool {
+        !self.include.is_empty()
+    }
+
+    /// Named by an `include` pattern.
+    pub(super) fn is_included(&self, rel: &str) -> bool {
+        self.include.iter().any(|re| re.is_match(rel))
+    }
+
+    /// Named by an `exclude` pattern, or a default one, unless it's included.
+    pub(super) fn is_excluded(&self, rel: &str) -> bool {
+        self.exclude.iter().any(|re| re.is_match(rel))
+            || (!self.is_included(rel) && self.defaults.iter().any(|re| re.is_match(rel)))
+    }
+
+    /// Files at or under `start` that aren't excluded, sorted. Excluded folders aren't entered,
+    /// and neither is `skip`, eg the folder being built into.
+    pub(super) fn walk(&self, project_path: &Path, start: &Path, skip: &Path) -> Vec<PathBuf> {
+        // Compared resolved, since `skip` may be relative to the working directory, or name the
+        // folder another way.
+        let skip = skip.canonicalize().ok();
+        let mut result = vec![];
+        let mut pending = vec![start.to_owned()];
+        while let Some(path) = pending.pop() {
+            if path != project_path && self.is_excluded(&relative(project_path, &path)) {
+                continue;
+            }
+            // Don't follow links to folders, which could loop.
+            let is_dir = fs::symlink_metadata(&path).is_ok_and(|m| m.is_dir());
+            if !is_dir {
+                if path.is_file() {
+                    result.push(path);
+                }
+                continue;
+            }
+            if skip.is_some() && path.canonicalize().ok() == skip {
+                continue;
+            }
+            let entries = fs::read_dir(&path)
+                .unwrap_or_else(|e| util::abort(&format!("Problem reading {:?}: {}", path, e)));
+            pending.extend(entries.flatten().map(|e| e.path()));
+        }
+        result.sort();
+        result
+    }
+}
+
+/// A regex for a gitignore-style pattern, matched against paths relative to the project, with
+/// `/` separators. `*` matches within a folder's name, `**` across folders, and a pattern
+/// without a `/`, other than a trailing one, matches at any depth. Matching a folder matches
+/// everything in it. `\` is taken as a separator too, so patterns written on Windows work.
+pub fn glob_regex(pattern: &str) -> Regex {
+    let pattern = pattern.replace('\\', "/");
+    let trimmed = pattern.trim().trim_end_matches('/');
+    let anchored = trimmed.contains('/');
+    let trimmed = trimmed.trim_start_matches('/');
+    let body = regex::escape(trimmed)
+        .replace(r"\*\*/", "\u{0}")
+        .replace(r"\*\*", "\u{1}")
+        .replace(r"\*", "[^/]*")
+        .replace(r"\?", "[^/]")
+        .replace('\u{0}', "(?:.*/)?")
+        .replace('\u{1}', ".*");
+    let prefix = if anchored { "" } else { "(?:.*/)?" };
+    Regex::new(&format!("^{}{}(?:/.*)?$", prefix, body)).unwrap()
+}
+
+/// `path` relative to `base`, with `/` separators.
+pub(super) fn relative(base: &Path, path: &Path) -> String {
+    path.strip_prefix(base)
+        .unwrap_or(path)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        pattern,
+        path,
+        matches,
+        case("*.pyc", "pkg/__pycache__/a.pyc", true),
+        case("docs", "docs/index.md", true),
+        case("docs", "src/docs/index.md", true),
+        case("/build", "build/lib/a.py", true),
+        case("/build", "src/build/a.py", false),
+        case("docs/*.md", "docs/index.md", true),
+        case("docs/*.md", "docs/api/index.md", false),
+        case("docs/**/*.md", "docs/api/index.md", true),
+        case("docs/**/*.md", "docs/index.md", true),
+        case("tests/", "tests/test_cli.py", true),
+        case(".*", ".git/HEAD", true),
+        case(".*", "src/a.py", false),
+        case("**/fixtures/**", "mylib/tests/fixtures/big.json", true),
+        case("**/fixtures/**", "fixtures/big.json", true),
+        case("mylib/templates/**", "mylib/templates/base/page.html", true),
+        case("mylib\\templates\\*.html", "mylib/templates/page.html", true)
+    )]
+    fn globs(pattern: &str, path: &str, matches: bool) {
+        assert_eq!(glob_regex(pattern).is_match(path), matches);
+    }
+
+    #[test]
+    fn include_overrides_defaults() {
+        let cfg = Config {
+            include: vec!["mylib/.schema.json".into()],
+            exclude: vec!["**/fixtures/**".into()],
+            ..Default::default()
+        };
+        let files = FileSet::new(&cfg);
+        assert!(files.has_include());
+        assert!(!files.is_excluded("mylib/.schema.json"));
+        assert!(files.is_excluded("mylib/.cache"));
+        assert!(files.is_excluded("mylib/tests/fixtures/big.json"));
+        assert!(!files.is_excluded("mylib/templates/page.html"));
+        assert_eq!(
+            relative(
+                Path::new("/proj"),
+                &Path::new("/proj").join("src").join("a.py")
+            ),
+            "src/a.py"
+        );
+    }
+}
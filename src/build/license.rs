@@ -0,0 +1,293 @@
+//! The project's license, as an SPDX expression, like `MIT OR Apache-2.0`, and the license files
+//! that go in its distributions. PEP 639.
+//! https://spdx.github.io/spdx-spec/v2.3/SPDX-license-expressions/
+
+use std::{fs, path::Path};
+
+use termcolor::Color;
+
+use super::{
+    fileset::{glob_regex, relative},
+    spdx::{EXCEPTIONS, LICENSES},
+};
+use crate::{actions, pyproject::Config, util};
+
+/// License files in the project root, included in sdists and wheels, and named by `License-File`.
+pub(super) const LICENSE_FILES: &[&str] =
+    &["LICENSE*", "LICENCE*", "COPYING*", "NOTICE*", "AUTHORS*"];
+
+/// Listed when a license isn't recognized.
+const COMMON: &[&str] = &[
+    "MIT",
+    "Apache-2.0",
+    "BSD-3-Clause",
+    "BSD-2-Clause",
+    "GPL-3.0-or-later",
+    "LGPL-3.0-or-later",
+    "MPL-2.0",
+    "ISC",
+    "Unlicense",
+];
+
+/// Check `license` is an SPDX expression, and normalize its identifiers' and operators' case,
+/// eg `mit or apache-2.0` to `MIT OR Apache-2.0`.
+pub fn parse_expression(license: &str) -> Result<String, String> {
+    let spaced = license.replace('(', " ( ").replace(')', " ) ");
+    let tokens: Vec<&str> = spaced.split_whitespace().collect();
+    if tokens.is_empty() {
+        return Err("The license is empty".to_owned());
+    }
+    let mut parser = Parser {
+        tokens: &tokens,
+        pos: 0,
+    };
+    let result = parser.or_expr().and_then(|r| match parser.next() {
+        None => Ok(r),
+        Some(t) => Err(format!("`{}` is out of place in the license expression", t)),
+    });
+    // Eg `MIT License`, or `Apache 2`. Parentheses mean it's meant as an expression.
+    let guess = Some(license)
+        .filter(|l| !l.contains(&['(', ')'][..]))
+        .and_then(actions::spdx);
+    result.map_err(|e| match guess {
+        Some(id) => format!(
+            "`{}` isn't an SPDX expression; did you mean `{}`?",
+            license, id
+        ),
+        None => e,
+    })
+}
+
+/// A recursive descent parser; `AND` binds tighter than `OR`.
+struct Parser<'a> {
+    tokens: &'a [&'a str],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Option<&'a str> {
+        let result = self.tokens.get(self.pos).copied();
+        self.pos += 1;
+        result
+    }
+
+    fn peek_operator(&self, op: &str) -> bool {
+        self.tokens
+            .get(self.pos)
+            .is_some_and(|t| t.eq_ignore_ascii_case(op))
+    }
+
+    fn or_expr(&mut self) -> Result<String, String> {
+        let mut result = self.and_expr()?;
+        while self.peek_operator("OR") {
+            self.pos += 1;
+            result = format!("{} OR {}", result, self.and_expr()?);
+        }
+        Ok(result)
+    }
+
+    fn and_expr(&mut self) -> Result<String, String> {
+        let mut result = self.term()?;
+        while self.peek_operator("AND") {
+            self.pos += 1;
+            result = format!("{} AND {}", result, self.term()?);
+        }
+        Ok(result)
+    }
+
+    fn term(&mut self) -> Result<String, String> {
+        let token = match self.next() {
+            Some(t) => t,
+            None => return Err("The license expression ends early".to_owned()),
+        };
+        if token == "(" {
+            let inner = self.or_expr()?;
+            return match self.next() {
+                Some(")") => Ok(format!("({})", inner)),
+                _ => Err("A `(` in the license expression isn't closed".to_owned()),
+            };
+        }
+        let mut result = identifier(token)?;
+        if self.peek_operator("WITH") {
+            self.pos += 1;
+            let exception = self
+                .next()
+                .ok_or_else(|| "`WITH` needs an exception after it".to_owned())?;
+            let canonical = EXCEPTIONS
+                .iter()
+                .find(|e| e.eq_ignore_ascii_case(exception))
+                .ok_or_else(|| format!("`{}` isn't an SPDX license exception", exception))?;
+            result = format!("{} WITH {}", result, canonical);
+        }
+        Ok(result)
+    }
+}
+
+/// A license identifier, in its canonical case, or a reason it isn't one.
+fn identifier(token: &str) -> Result<String, String> {
+    if ["AND", "OR", "WITH", ")"]
+        .iter()
+        .any(|op| token.eq_ignore_ascii_case(op))
+    {
+        return Err(format!(
+            "`{}` is out of place in the license expression",
+            token
+        ));
+    }
+    let (id, plus) = match token.strip_suffix('+') {
+        Some(id) => (id, "+"),
+        None => (token, ""),
+    };
+    let custom = |prefix: &str| {
+        id.get(..prefix.len())
+            .is_some_and(|p| p.eq_ignore_ascii_case(prefix))
+            && id.len() > prefix.len()
+            && id[prefix.len()..]
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+    };
+    if custom("LicenseRef-") {
+        return Ok(format!("LicenseRef-{}{}", &id["LicenseRef-".len()..], plus));
+    }
+    if let Some(known) = LICENSES.iter().find(|i| i.eq_ignore_ascii_case(id)) {
+        return Ok(format!("{}{}", known, plus));
+    }
+
+    let suggestion = actions::spdx(token).map(ToOwned::to_owned).or_else(|| {
+        LICENSES
+            .iter()
+            .map(|i| {
+                (
+                    util::edit_distance(&id.to_lowercase(), &i.to_lowercase()),
+                    i,
+                )
+            })
+            .filter(|(d, _)| *d <= 2)
+            .min_by_key(|(d, _)| *d)
+            .map(|(_, i)| (*i).to_owned())
+    });
+    Err(match suggestion {
+        Some(s) => format!(
+            "`{}` isn't an SPDX license identifier; did you mean `{}`?",
+            token, s
+        ),
+        None => format!(
+            "`{}` isn't an SPDX license identifier. Common ones are {}; see \
+             https://spdx.org/licenses/, or use `LicenseRef-<name>` for your own",
+            token,
+            COMMON.join(", ")
+        ),
+    })
+}
+
+/// The project's license expression, normalized. Aborts if `license` isn't one.
+pub(super) fn expression(cfg: &Config) -> Option<String> {
+    let license = cfg.license.as_ref()?;
+    match parse_expression(license) {
+        Ok(e) => Some(e),
+        Err(e) => util::abort(&format!(
+            "`license` in `pyproject.toml` must be an SPDX expression, like `MIT`, or \
+             `MIT OR Apache-2.0`. {}",
+            e
+        )),
+    }
+}
+
+/// Check the license before building, and note that license classifiers are deprecated.
+pub fn check_license(cfg: &Config) {
+    let expression = expression(cfg);
+    let classifiers: Vec<&String> = cfg
+        .classifiers
+        .iter()
+        .filter(|c| c.starts_with("License ::"))
+        .collect();
+    if classifiers.is_empty() {
+        return;
+    }
+    let advice = match expression {
+        Some(_) => "`license` already says what the license is; remove them",
+        None => "set `license` under `[tool.pyflow]` to an SPDX expression instead, eg `MIT`",
+    };
+    util::print_color(
+        &format!(
+            "License classifiers, like `{}`, are deprecated: {}",
+            classifiers[0], advice
+        ),
+        Color::Yellow,
+    );
+}
+
+/// License files in the project root, relative to it, sorted.
+pub(super) fn license_files(project_path: &Path) -> Vec<String> {
+    let patterns: Vec<_> = LICENSE_FILES
+        .iter()
+        .map(|p| glob_regex(&format!("/{}", p)))
+        .collect();
+    let mut result: Vec<String> = fs::read_dir(project_path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_file())
+        .map(|p| relative(project_path, &p))
+        .filter(|rel| patterns.iter().any(|re| re.is_match(rel)))
+        .collect();
+    result.sort();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::temp_dir::TempDir;
+    use rstest::rstest;
+
+    #[rstest(
+        input,
+        expected,
+        case("MIT", "MIT"),
+        case("mit or apache-2.0", "MIT OR Apache-2.0"),
+        case(
+            "(MIT AND BSD-3-Clause) OR GPL-2.0-or-later WITH classpath-exception-2.0",
+            "(MIT AND BSD-3-Clause) OR GPL-2.0-or-later WITH Classpath-exception-2.0"
+        ),
+        case("MPL-2.0+", "MPL-2.0+"),
+        case("licenseref-Proprietary", "LicenseRef-Proprietary"),
+        case(
+            "unicode-3.0 OR blueoak-1.0.0 WITH cgal-linking-exception",
+            "Unicode-3.0 OR BlueOak-1.0.0 WITH CGAL-linking-exception"
+        )
+    )]
+    fn valid(input: &str, expected: &str) {
+        assert_eq!(parse_expression(input), Ok(expected.to_owned()));
+    }
+
+    #[rstest(
+        input,
+        error,
+        case(
+            "MIT License",
+            "`MIT License` isn't an SPDX expression; did you mean `MIT`?"
+        ),
+        case("Apache 2", "did you mean `Apache-2.0`?"),
+        case("MIT AND AND", "`AND` is out of place"),
+        case("BSD-3-Clase", "did you mean `BSD-3-Clause`?"),
+        case("MIT OR", "ends early"),
+        case("(MIT", "isn't closed"),
+        case("Frobnicate", "Common ones are MIT, Apache-2.0")
+    )]
+    fn invalid(input: &str, error: &str) {
+        let e = parse_expression(input).unwrap_err();
+        assert!(e.contains(error), "{}", e);
+    }
+
+    #[test]
+    fn finds_license_files() {
+        let project = TempDir::new("license-files");
+        fs::create_dir_all(project.join("docs")).unwrap();
+        for file in &["LICENSE", "COPYING.txt", "README.md", "docs/LICENSE"] {
+            fs::write(project.join(file), "").unwrap();
+        }
+        assert_eq!(license_files(&project), vec!["COPYING.txt", "LICENSE"]);
+    }
+}
@@ -1,6 +1,25 @@
-use crate::{dep_types::Req, util};
+mod artifact;
+mod classifiers;
+mod description;
+mod fileset;
+mod license;
+mod native;
+mod publish;
+mod sdist;
+mod sign;
+mod spdx;
+mod wheel;
+
+pub use classifiers::verify_classifiers;
+pub use license::{check_license, parse_expression};
+pub use native::{BuildEnv, DEFAULT_OUTPUT};
+pub use publish::{publish, Mode as PublishMode};
+pub use sdist::{build_sdist, list_sdist, DEFAULT_EPOCH};
+pub use wheel::{build_wheel, list_wheel};
+
+use crate::util;
 use regex::Regex;
-use std::{collections::HashMap, path::Path};
+use std::{collections::HashMap, path::Path, sync::LazyLock};
 use std::{env, fs, process::Command};
 use termcolor::Color;
 
@@ -32,7 +51,7 @@ fn _serialize_py_dict(hm: &HashMap<String, Vec<String>>) -> String {
     result
 }
 
-/// Serialize to a Python dict of strings.
+// Serialize to a Python dict of strings.
 //fn serialize_scripts(hm: &HashMap<String, String>) -> String {
 //    let mut result = "{\n".to_string();
 //
@@ -53,10 +72,75 @@ fn _serialize_py_dict(hm: &HashMap<String, Vec<String>>) -> String {
 //    result
 //}
 
-fn cfg_to_setup(cfg: &crate::Config) -> String {
+/// Eg `package.module:function`.
+static FUNCTION_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[A-Za-z_][\w.]*:[A-Za-z_][\w.]*$").unwrap());
+
+/// Convert `[tool.pyflow.scripts]` into `console_scripts` entry points, eg
+/// `name = module:function`, sorted so builds are reproducible. Shell-command scripts only make
+/// sense in the project, so they're left out.
+fn console_scripts(scripts: &HashMap<String, String>) -> Vec<String> {
+    let mut result: Vec<String> = scripts
+        .iter()
+        .filter_map(|(name, mod_fn)| {
+            let mod_fn = mod_fn.trim();
+            if !FUNCTION_RE.is_match(mod_fn) {
+                util::print_color(
+                    &format!(
+                        "The script `{}` isn't in the format `module:function`; it won't be \
+                         installed with the package",
+                        name
+                    ),
+                    Color::Yellow,
+                );
+                return None;
+            }
+            Some(format!("{} = {}", name, mod_fn))
+        })
+        .collect();
+    result.sort();
+    result
+}
+
+/// Warn about scripts that share a name with one a dependency installed; installing the
+/// package would replace it.
+fn warn_script_collisions(scripts: &HashMap<String, String>, entry_pt_path: &Path) {
+    let mut names: Vec<&String> = scripts.keys().collect();
+    names.sort();
+    for name in names {
+        if entry_pt_path.join(name).exists() {
+            util::print_color(
+                &format!(
+                    "The script `{}` in `pyproject.toml` has the same name as one installed \
+                     by a dependency; installing this package will replace it.",
+                    name
+                ),
+                Color::Yellow,
+            );
+        }
+    }
+}
+
+/// A name, then optionally an email in angle brackets.
+static AUTHOR_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(.*?)\s*(?:<(.*?)>)?\s*$").unwrap());
+
+/// Split an entry in `authors`, eg `Fraa Erasmas <raz@edhar.math>`, into a name and email.
+fn parse_author(author: &str) -> (String, Option<String>) {
+    match AUTHOR_RE.captures(author) {
+        Some(caps) => (
+            caps[1].to_owned(),
+            caps.get(2).map(|m| m.as_str().to_owned()),
+        ),
+        None => (author.to_owned(), None),
+    }
+}
+
+/// A `setup.py` for setuptools, reading the long description from `readme`, if there is one.
+fn cfg_to_setup(cfg: &crate::Config, readme: Option<&str>) -> String {
     let cfg = cfg.clone();
 
-    let version = match cfg.version {
+    let version = match &cfg.version {
         Some(v) => v.to_string(),
         None => "".into(),
     };
@@ -69,33 +153,90 @@ fn cfg_to_setup(cfg: &crate::Config) -> String {
         keywords.push_str(kw);
     }
 
-    let author_re = Regex::new(r"^(.*?)\s*(?:<(.*?)>)?\s*$").unwrap();
-
     let mut author = "".to_string();
     let mut author_email = "".to_string();
     if let Some(first) = cfg.authors.first() {
-        let caps = if let Some(c) = author_re.captures(first) {
-            c
-        } else {
-            util::abort(&format!(
-                "Problem parsing the `authors` field in `pyproject.toml`: {:?}",
-                &cfg.authors
-            ));
-        };
-        author = caps.get(1).unwrap().as_str().to_owned();
-        author_email = caps.get(2).unwrap().as_str().to_owned();
+        let (name, email) = parse_author(first);
+        author = name;
+        author_email = email.unwrap_or_default();
     }
 
-    let deps: Vec<String> = cfg.reqs.iter().map(Req::to_setup_py_string).collect();
+    // Dependencies in `[tool.pyflow.extras]` groups are only required with that extra.
+    let in_group = |name: &str| {
+        cfg.extras
+            .values()
+            .any(|names| names.iter().any(|n| util::compare_names(n, name)))
+    };
+    let deps: Vec<String> = cfg
+        .reqs
+        .iter()
+        .filter(|r| !in_group(&r.name))
+        .map(|r| r.to_pep508_string(None))
+        .collect();
+    let mut extras: Vec<(&String, Vec<String>)> = cfg
+        .extras
+        .iter()
+        .map(|(group, names)| {
+            let reqs = names
+                .iter()
+                .map(|name| {
+                    cfg.reqs
+                        .iter()
+                        .find(|r| util::compare_names(&r.name, name))
+                        .map_or(name.clone(), |r| r.to_pep508_string(None))
+                })
+                .collect();
+            (group, reqs)
+        })
+        .collect();
+    extras.sort();
+    let extras_require = if extras.is_empty() {
+        "{}".to_owned()
+    } else {
+        let mut result = "{\n".to_owned();
+        for (group, reqs) in &extras {
+            result.push_str(&format!(
+                "        \"{}\": {},\n",
+                group,
+                serialize_py_list(reqs, 2)
+            ));
+        }
+        result.push_str("    }");
+        result
+    };
+
+    let long_description = match readme {
+        Some(path) => format!(
+            "with open(\"{}\", \"r\", encoding=\"utf-8\") as fh:\n    long_description = fh.read()",
+            path
+        ),
+        None => "long_description = \"\"".to_owned(),
+    };
+    let content_type = wheel::readme_content_type(
+        Path::new(readme.unwrap_or_default()),
+        cfg.readme_content_type.as_deref(),
+    );
+
+    // Eg a `src` layout.
+    let packages = match &cfg.package_dir {
+        Some(dir) => format!(
+            "packages=setuptools.find_packages(\"{0}\"),\n    package_dir={{\"\": \"{0}\"}}",
+            dir
+        ),
+        None => "packages=setuptools.find_packages()".into(),
+    };
+
+    let (build_ext, ext_args) = match &cfg.build {
+        Some(script) => native::setup_py_build(script),
+        None => Default::default(),
+    };
 
-    // TODO: Entry pts!
     format!(
         r#"import setuptools
 
-with open("{}", "r") as fh:
-    long_description = fh.read()
+{}
 
-setuptools.setup(
+{}setuptools.setup(
     name="{}",
     version="{}",
     author="{}",
@@ -103,43 +244,50 @@ setuptools.setup(
     license="{}",
     description="{}",
     long_description=long_description,
-    long_description_content_type="text/markdown",
+    long_description_content_type="{}",
     url="{}",
-    packages=setuptools.find_packages(),
-    keywords="{}",
+    {},
+    {}keywords="{}",
     classifiers={},
     python_requires="{}",
     install_requires={},
+    extras_require={},
+    entry_points={{
+        "console_scripts": {},
+    }},
 )
 "#,
-        //            entry_points={{
-        //        "console_scripts": ,
-        //    }},
-        cfg.readme.unwrap_or_else(|| "README.md".into()),
+        long_description,
+        build_ext,
         cfg.name.unwrap_or_else(|| "".into()),
         version,
         author,
         author_email,
         cfg.license.unwrap_or_else(|| "".into()),
         cfg.description.unwrap_or_else(|| "".into()),
+        content_type,
         cfg.homepage.unwrap_or_else(|| "".into()),
+        packages,
+        ext_args,
         keywords,
         serialize_py_list(&cfg.classifiers, 1),
         //        serialize_py_list(&cfg.console_scripts),
         cfg.python_requires.unwrap_or_else(|| "".into()),
         serialize_py_list(&deps, 1),
-        // todo:
-        //            extras_require="{}",
-        //        match cfg.extras {
-        //            Some(e) => serialize_py_dict(&e),
-        //            None => "".into(),
-        //        }
+        extras_require,
+        serialize_py_list(&console_scripts(&cfg.scripts), 2),
     )
 }
 
 /// Creates a temporary file which imitates setup.py
 fn create_dummy_setup(cfg: &crate::Config, filename: &str) {
-    fs::write(filename, cfg_to_setup(cfg)).expect("Problem writing dummy setup.py");
+    let readme = cfg.readme.as_deref().unwrap_or("README.md");
+    // `build` runs the build script itself, first.
+    let cfg = crate::Config {
+        build: None,
+        ..cfg.clone()
+    };
+    fs::write(filename, cfg_to_setup(&cfg, Some(readme))).expect("Problem writing dummy setup.py");
     if util::wait_for_dirs(&[env::current_dir()
         .expect("Problem finding current dir")
         .join(filename)])
@@ -169,32 +317,11 @@ pub fn build(
 
     let dummy_setup_fname = "setup_temp_pyflow.py";
 
-    // Twine has too many dependencies to install when the environment, like we do with `wheel`, and
-    // for now, it's easier to install using pip
-    // TODO: Install using own tools instead of pip; this is the last dependence on pip.
-    let output = Command::new(paths.bin.join("python"))
-        .args(["-m", "pip", "install", "twine"])
-        .output()
-        .expect("Problem installing Twine");
-    util::check_command_output(&output, "failed to install twine");
-
-    //    let twine_url = "https://files.pythonhosted.org/packages/c4/43/b9c56d378f5d0b9bee7be564b5c5fb65c65e5da6e82a97b6f50c2769249a/twine-2.0.0-py3-none-any.whl";
-    //    install::download_and_install_package(
-    //        "twine",
-    //        &Version::new(2, 0, 0),
-    //        twine_url,
-    //        "twine-2.0.0-py3-none-any.whl",
-    //        "5319dd3e02ac73fcddcd94f0…1f4699d57365199d85261e1",
-    //        &paths,
-    //        install::PackageType::Wheel,
-    //        &None,
-    //    )
-    //    .expect("Problem installing `twine`");
-
+    warn_script_collisions(&cfg.scripts, &paths.entry_pt);
     create_dummy_setup(cfg, dummy_setup_fname);
 
     util::set_pythonpath(&[paths.lib.to_owned()]);
-    println!("🛠️️ Building the package...");
+    log::info!("🛠️️ Building the package...");
     // TODO: Run build script first, right?
     if let Some(build_file) = &cfg.build {
         let output = Command::new(paths.bin.join("python"))
@@ -204,36 +331,17 @@ pub fn build(
         util::check_command_output(&output, "failed to run build script");
     }
 
-    //    Command::new(paths.bin.join("python"))
-    //        .args(&[dummy_setup_fname, "sdist", "bdist_wheel"])
-    //        .status()
-    //        .expect("Problem building");
+    let output = Command::new(paths.bin.join("python"))
+        .args([dummy_setup_fname, "sdist", "bdist_wheel"])
+        .output()
+        .expect("Problem building");
+    util::check_command_output(&output, "failed to build the package");
 
-    util::print_color("Build complete.", Color::Green);
+    util::print_status("Build complete.", Color::Green);
 
     if fs::remove_file(dummy_setup_fname).is_err() {
-        println!("Problem removing temporary setup file while building ")
-    };
-}
-
-pub(crate) fn publish(bin_path: &Path, cfg: &crate::Config) {
-    let repo_url = match cfg.package_url.clone() {
-        Some(pu) => {
-            let mut r = pu;
-            if !r.ends_with('/') {
-                r.push('/');
-            }
-            r
-        }
-        None => "https://test.pypi.org/legacy/".to_string(),
+        log::warn!("Problem removing temporary setup file while building ")
     };
-
-    println!("Uploading to {}", repo_url);
-    let output = Command::new(bin_path.join("twine"))
-        .args(["upload", "--repository-url", &repo_url, "dist/*"])
-        .output()
-        .expect("Problem publishing");
-    util::check_command_output(&output, "publishing");
 }
 
 #[cfg(test)]
@@ -289,11 +397,12 @@ pub mod test {
             extras: HashMap::new(),
             repo_url: None,
             build: None,
+            ..Default::default()
         };
 
         let expected = r#"import setuptools
 
-with open("README.md", "r") as fh:
+with open("README.md", "r", encoding="utf-8") as fh:
     long_description = fh.read()
 
 setuptools.setup(
@@ -314,14 +423,36 @@ setuptools.setup(
     ],
     python_requires=">=3.6",
     install_requires=[
-        "numpy>=1.16.4",
-        "manimlib==0.1.8",
-        "ipython>=7.7.0",
+        "numpy (>=1.16.4,<2.0.0)",
+        "manimlib (==0.1.8)",
+        "ipython (>=7.7.0,<8.0.0)",
     ],
+    extras_require={},
+    entry_points={
+        "console_scripts": [
+            "activate = jeejah:activate",
+        ],
+    },
 )
 "#;
 
-        assert_eq!(expected, &cfg_to_setup(&cfg));
+        assert_eq!(expected, &cfg_to_setup(&cfg, Some("README.md")));
+    }
+
+    #[test]
+    fn setup_runs_build_script() {
+        let cfg = crate::Config {
+            name: Some("adder".into()),
+            build: Some("build.py".into()),
+            ..Default::default()
+        };
+        let setup = cfg_to_setup(&cfg, None);
+        assert!(setup.contains("class BuildScript(build_ext):"));
+        assert!(setup.contains("[sys.executable, \"build.py\"]"));
+        assert!(setup.contains(
+            "    ext_modules=[setuptools.Extension(\"_pyflow_build_script\", sources=[])],\n    \
+             cmdclass={\"build_ext\": BuildScript},\n    keywords=\"\",\n"
+        ));
     }
 
     #[test]
@@ -344,6 +475,22 @@ setuptools.setup(
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn console_scripts_sorted() {
+        let mut scripts = HashMap::new();
+        scripts.insert("zap".into(), "everythingkiller.cli:zap".into());
+        scripts.insert("activate".into(), " jeejah:activate ".into());
+        scripts.insert("lint".into(), "ruff check .".into());
+
+        assert_eq!(
+            console_scripts(&scripts),
+            vec![
+                "activate = jeejah:activate".to_string(),
+                "zap = everythingkiller.cli:zap".to_string(),
+            ]
+        );
+    }
+
     // TODO: Re-impl if you end up using this
     //    #[test]
     //    fn py_dict() {
@@ -0,0 +1,430 @@
+//! Extension modules for `pyflow build`, from the `build` script. The contract:
+//!
+//! - The script runs with the project's interpreter, from the project root. Requirements from
+//!   `[build-system] requires` are installed into an isolated prefix, under `build/`, and
+//!   importable; nothing from the project's environment is.
+//! - `PYFLOW_BUILD_OUT` is the output folder, `output` under `[tool.pyflow.build]`, emptied
+//!   first. What the script writes there goes in the wheel at the same relative path, eg
+//!   `mylib/_speedups.cpython-311-x86_64-linux-gnu.so`.
+//! - If it exits non-zero, the build fails, with its output.
+//!
+//! The wheel is then tagged for the interpreter and platform, eg `cp311-cp311-linux_x86_64`.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use termcolor::Color;
+
+use super::fileset::relative;
+use crate::{
+    dep_types::{Req, Version},
+    pyproject::{Config, EnvMode},
+    util::{self, deps},
+};
+
+/// Where the build script writes, if `output` isn't set.
+pub const DEFAULT_OUTPUT: &str = "build/ext";
+
+/// Where build requirements are installed.
+const PREFIX: &str = "build/pyflow-build-env";
+
+/// Prints the wheel tag for the running interpreter and platform. The ABI is CPython's, with
+/// its flags, or the first two parts of `SOABI` elsewhere, eg `pypy310_pp73`.
+const TAG_SCRIPT: &str = r#"import sys, sysconfig
+impl = {"cpython": "cp", "pypy": "pp"}.get(sys.implementation.name, sys.implementation.name)
+py = impl + "%d%d" % sys.version_info[:2]
+soabi = sysconfig.get_config_var("SOABI") or ""
+if impl == "cp" or not soabi:
+    abi = py + getattr(sys, "abiflags", "")
+else:
+    abi = "_".join(soabi.split("-")[:2])
+plat = sysconfig.get_platform().replace("-", "_").replace(".", "_")
+print("%s-%s-%s" % (py, abi, plat))"#;
+
+/// For an sdist's `setup.py`: a `build_ext` command that runs the `build` script the same way,
+/// into an empty folder, and copies what it writes into the build's `build_lib`, and a
+/// placeholder extension, so setuptools builds a platform wheel and runs it. `{script}` is the
+/// script's path.
+const SETUP_BUILD_EXT: &str = r#"import os
+import shutil
+import subprocess
+import sys
+import tempfile
+
+from setuptools.command.build_ext import build_ext
+
+
+class BuildScript(build_ext):
+    """Runs `{script}`, which writes extension modules to `PYFLOW_BUILD_OUT`."""
+
+    def run(self):
+        root = os.path.dirname(os.path.abspath(__file__))
+        target = root if self.inplace else os.path.abspath(self.build_lib)
+        with tempfile.TemporaryDirectory() as out:
+            env = dict(os.environ, PYFLOW_BUILD_OUT=out)
+            subprocess.check_call([sys.executable, "{script}"], cwd=root, env=env)
+            shutil.copytree(out, target, dirs_exist_ok=True)
+
+    def get_outputs(self):
+        return []
+
+"#;
+
+/// The code `setup.py` needs before `setuptools.setup`, and the arguments to add to it, to run
+/// the `build` script `script`.
+pub(super) fn setup_py_build(script: &str) -> (String, String) {
+    (
+        SETUP_BUILD_EXT.replace("{script}", &script.replace('\\', "/")),
+        "ext_modules=[setuptools.Extension(\"_pyflow_build_script\", sources=[])],\n    \
+         cmdclass={\"build_ext\": BuildScript},\n    "
+            .to_owned(),
+    )
+}
+
+/// What running the `build` script needs: the project's environment, for its interpreter.
+pub struct BuildEnv<'a> {
+    pub paths: &'a util::Paths,
+    pub py_vers: &'a Version,
+    pub os: util::Os,
+}
+
+/// What the `build` script made, and the wheel tag it needs.
+pub(super) struct Native {
+    /// Each file, and its path in the wheel.
+    pub(super) files: Vec<(PathBuf, String)>,
+    pub(super) tag: String,
+}
+
+/// Run the `build` script `script`, and collect what it wrote.
+pub(super) fn build(project_path: &Path, cfg: &Config, script: &str, env: &BuildEnv) -> Native {
+    let python = env.paths.bin.join("python");
+    let prefix = install_build_reqs(project_path, cfg, env);
+
+    let out = project_path.join(cfg.build_output.as_deref().unwrap_or(DEFAULT_OUTPUT));
+    if out.exists() {
+        if let Err(e) = fs::remove_dir_all(&out) {
+            util::abort(&format!("Problem emptying {:?}: {}", out, e))
+        }
+    }
+    if let Err(e) = fs::create_dir_all(&out) {
+        util::abort(&format!("Problem creating {:?}: {}", out, e))
+    }
+
+    // Build requirements' scripts, eg `cythonize`, come first on the `PATH`.
+    let mut path_var = vec![prefix.join("bin"), env.paths.bin.clone()];
+    if let Some(existing) = env::var_os("PATH") {
+        path_var.extend(env::split_paths(&existing));
+    }
+    // `-S`, so the environment's `site-packages`, which has `__pypackages__` on its path, isn't.
+    let mut cmd = Command::new(&python);
+    cmd.arg("-S")
+        .arg(script)
+        .current_dir(project_path)
+        .env("PYTHONPATH", prefix.join("lib"))
+        .env("PYFLOW_BUILD_OUT", &out);
+    if let Ok(joined) = env::join_paths(path_var) {
+        cmd.env("PATH", joined);
+    }
+
+    util::print_status(
+        &format!("Running the build script, {}", script),
+        Color::Blue,
+    );
+    let output = cmd.output().unwrap_or_else(|e| {
+        util::abort(&format!(
+            "Problem running the build script, {}: {}",
+            script, e
+        ))
+    });
+    if !output.status.success() {
+        util::abort(&format!(
+            "The build script, {}, failed, with {}:\n{}{}",
+            script,
+            output.status,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ))
+    }
+
+    let files: Vec<(PathBuf, String)> = collect(&out)
+        .into_iter()
+        .map(|path| {
+            let rel = relative(&out, &path);
+            (path, rel)
+        })
+        .collect();
+    if files.is_empty() {
+        util::abort(&format!(
+            "The build script, {}, ran, but wrote nothing to {:?}; it should write what it \
+             builds to the folder in `PYFLOW_BUILD_OUT`",
+            script, out
+        ))
+    }
+    Native {
+        files,
+        tag: tag(&python),
+    }
+}
+
+/// Install `[build-system] requires` into the build prefix, and return it.
+fn install_build_reqs(project_path: &Path, cfg: &Config, env: &BuildEnv) -> PathBuf {
+    let prefix = project_path.join(PREFIX);
+    let reqs: Vec<Req> = cfg
+        .build_requires
+        .iter()
+        .map(|r| {
+            Req::from_cli_str(r).unwrap_or_else(|_| {
+                util::abort(&format!(
+                    "Can't parse `{}` in `[build-system] requires`; use a name, with optional \
+                     extras and version specifiers, eg `cffi>=1.15`",
+                    r
+                ))
+            })
+        })
+        .collect();
+    if reqs.is_empty() {
+        return prefix;
+    }
+
+    let paths = util::Paths {
+        bin: env.paths.bin.clone(),
+        lib: prefix.join("lib"),
+        entry_pt: prefix.join("bin"),
+        cache: env.paths.cache.clone(),
+        mode: EnvMode::Pypackages,
+    };
+    let lock_path = prefix.join("pyflow.lock");
+    let lockpacks = util::read_lock(&lock_path)
+        .ok()
+        .and_then(|l| l.package)
+        .unwrap_or_default();
+    log::info!("Installing build requirements into {:?}", prefix);
+    deps::sync(
+        &paths,
+        &lockpacks,
+        &reqs,
+        &[],
+        &Default::default(),
+        &[],
+        env.os,
+        env.py_vers,
+        &lock_path,
+        false,
+        &Default::default(),
+    );
+    prefix
+}
+
+/// Every file under `dir`, sorted.
+fn collect(dir: &Path) -> Vec<PathBuf> {
+    let mut result = vec![];
+    let mut pending = vec![dir.to_owned()];
+    while let Some(path) = pending.pop() {
+        if path.is_dir() {
+            let entries = fs::read_dir(&path)
+                .unwrap_or_else(|e| util::abort(&format!("Problem reading {:?}: {}", path, e)));
+            pending.extend(entries.flatten().map(|e| e.path()));
+        } else if path.is_file() {
+            result.push(path);
+        }
+    }
+    result.sort();
+    result
+}
+
+/// The wheel tag for `python`'s implementation, ABI, and platform.
+fn tag(python: &Path) -> String {
+    let output = Command::new(python)
+        .args(["-c", TAG_SCRIPT])
+        .output()
+        .unwrap_or_else(|e| util::abort(&format!("Problem running {:?}: {}", python, e)));
+    let tag = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    if !output.status.success() || tag.split('-').count() != 3 {
+        util::abort(&format!(
+            "Problem finding the wheel tag for {:?}: {}",
+            python,
+            String::from_utf8_lossy(&output.stderr)
+        ))
+    }
+    tag
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::build::{sdist::build_sdist, wheel::build_wheel};
+    use crate::util::temp_dir::TempDir;
+    use std::io::Read;
+
+    /// A build script for a trivial C extension, compiled with the interpreter's own settings.
+    const BUILD_SCRIPT: &str = r#"import os, subprocess, sysconfig
+out = os.path.join(os.environ["PYFLOW_BUILD_OUT"], "adder")
+os.makedirs(out)
+cc = (sysconfig.get_config_var("CC") or "cc").split()
+suffix = sysconfig.get_config_var("EXT_SUFFIX")
+subprocess.check_call(cc + ["-shared", "-fPIC", "-I", sysconfig.get_paths()["include"],
+    "adder/_add.c", "-o", os.path.join(out, "_add" + suffix)]
+    + (["-undefined", "dynamic_lookup"] if sysconfig.get_platform().startswith("macosx") else []))
+"#;
+
+    const EXTENSION: &str = r#"#include <Python.h>
+static PyObject *add(PyObject *self, PyObject *args) {
+    long a, b;
+    if (!PyArg_ParseTuple(args, "ll", &a, &b)) return NULL;
+    return PyLong_FromLong(a + b);
+}
+static PyMethodDef methods[] = {{"add", add, METH_VARARGS, ""}, {NULL, NULL, 0, NULL}};
+static struct PyModuleDef module = {PyModuleDef_HEAD_INIT, "_add", NULL, -1, methods};
+PyMODINIT_FUNC PyInit__add(void) { return PyModule_Create(&module); }
+"#;
+
+    /// A `python3` with the headers to build extensions for it, if there's one.
+    fn python_with_headers() -> Option<PathBuf> {
+        let python3 = Command::new("sh")
+            .args(["-c", "command -v python3"])
+            .output()
+            .ok()
+            .filter(|o| o.status.success())
+            .map(|o| PathBuf::from(String::from_utf8_lossy(&o.stdout).trim()))?;
+        Command::new(&python3)
+            .args([
+                "-c",
+                "import os, sysconfig; \
+                 assert os.path.isfile(os.path.join(sysconfig.get_paths()['include'], 'Python.h'))",
+            ])
+            .status()
+            .is_ok_and(|s| s.success())
+            .then_some(python3)
+    }
+
+    /// The sample extension's project.
+    fn project(name: &str) -> (TempDir, Config) {
+        let project = TempDir::new(name);
+        fs::create_dir_all(project.join("adder")).unwrap();
+        fs::write(project.join("adder/__init__.py"), "from ._add import add\n").unwrap();
+        fs::write(project.join("adder/_add.c"), EXTENSION).unwrap();
+        fs::write(project.join("build.py"), BUILD_SCRIPT).unwrap();
+        let cfg = Config {
+            name: Some("adder".into()),
+            version: Some(Version::new(0, 1, 0)),
+            build: Some("build.py".into()),
+            ..Default::default()
+        };
+        (project, cfg)
+    }
+
+    /// Builds and imports a sample extension, if there's a `python3` and compiler to do it with.
+    #[test]
+    fn builds_extension() {
+        // No interpreter, compiler, or headers; nothing to test with.
+        let python3 = match python_with_headers() {
+            Some(p) => p,
+            None => return,
+        };
+        let (project, cfg) = project("native-build");
+        let bin = project.join("env/bin");
+        fs::create_dir_all(&bin).unwrap();
+        std::os::unix::fs::symlink(&python3, bin.join("python")).unwrap();
+        let paths = util::Paths {
+            bin: bin.clone(),
+            lib: project.join("env/lib"),
+            entry_pt: bin,
+            cache: project.join("cache"),
+            mode: EnvMode::Pypackages,
+        };
+        let env = BuildEnv {
+            paths: &paths,
+            py_vers: &Version::new_short(3, 11),
+            os: util::get_os(),
+        };
+
+        let wheel = build_wheel(&project, &cfg, &project.join("dist"), Some(&env));
+        let file_name = wheel.file_name().unwrap().to_string_lossy().into_owned();
+        assert!(file_name.starts_with("adder-0.1.0-cp3"));
+        assert!(!file_name.ends_with("py3-none-any.whl"));
+
+        let mut archive = zip::ZipArchive::new(fs::File::open(&wheel).unwrap()).unwrap();
+        let mut wheel_file = String::new();
+        archive
+            .by_name("adder-0.1.0.dist-info/WHEEL")
+            .unwrap()
+            .read_to_string(&mut wheel_file)
+            .unwrap();
+        assert!(wheel_file.contains("Root-Is-Purelib: false\n"));
+        let installed = project.join("installed");
+        archive.extract(&installed).unwrap();
+        let imported = Command::new(&python3)
+            .args(["-c", "import adder; assert adder.add(2, 3) == 5"])
+            .env("PYTHONPATH", &installed)
+            .status()
+            .unwrap();
+        assert!(imported.success());
+    }
+
+    /// pip builds a wheel with the extension from the sdist, through its `setup.py`.
+    #[test]
+    fn sdist_builds_extension() {
+        let python3 = match python_with_headers() {
+            Some(p) => p,
+            None => return,
+        };
+        // Building without isolation, so offline, needs these installed.
+        let has_setuptools = Command::new(&python3)
+            .args(["-c", "import setuptools, wheel"])
+            .output()
+            .is_ok_and(|o| o.status.success());
+        if !has_setuptools {
+            return;
+        }
+        let (project, cfg) = project("native-sdist");
+        fs::write(
+            project.join("pyproject.toml"),
+            "[tool.pyflow]\nname = \"adder\"\nversion = \"0.1.0\"\nbuild = \"build.py\"\n",
+        )
+        .unwrap();
+        let sdist = build_sdist(&project, &cfg, &project.join("dist"));
+
+        let wheels = project.join("wheels");
+        let built = Command::new(&python3)
+            .args([
+                "-m",
+                "pip",
+                "wheel",
+                "--no-build-isolation",
+                "--no-deps",
+                "-q",
+                "-w",
+            ])
+            .arg(&wheels)
+            .arg(&sdist)
+            .output()
+            .unwrap();
+        assert!(
+            built.status.success(),
+            "{}",
+            String::from_utf8_lossy(&built.stderr)
+        );
+        let wheel = fs::read_dir(&wheels)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        assert!(!wheel.to_string_lossy().ends_with("py3-none-any.whl"));
+
+        let installed = project.join("installed");
+        zip::ZipArchive::new(fs::File::open(&wheel).unwrap())
+            .unwrap()
+            .extract(&installed)
+            .unwrap();
+        let imported = Command::new(&python3)
+            .args(["-c", "import adder; assert adder.add(2, 3) == 5"])
+            .env("PYTHONPATH", &installed)
+            .status()
+            .unwrap();
+        assert!(imported.success());
+    }
+}
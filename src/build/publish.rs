@@ -0,0 +1,1034 @@
+//! `pyflow publish`: upload the built wheel and sdist to a package index, with the same upload
+//! API twine uses, without needing twine.
+//! https://warehouse.pypa.io/api-reference/legacy.html#upload-api
+
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::Read,
+    path::{Path, PathBuf},
+    process::{self, Command},
+    sync::LazyLock,
+};
+
+use flate2::read::GzDecoder;
+use regex::Regex;
+use reqwest::{blocking::multipart, StatusCode};
+use serde::{Deserialize, Serialize};
+use termcolor::Color;
+
+use super::{
+    artifact, classifiers, description,
+    sign::{self, Signature},
+    wheel::{dist_name, name_and_version},
+};
+use crate::{
+    pyproject::{Config, Publish},
+    util::{
+        self, prompts,
+        settings::{self, Repository},
+    },
+};
+
+/// Where we upload when neither `--repository`, `--repository-url`, nor `package_url` is set.
+const DEFAULT_REPOSITORY_URL: &str = "https://upload.pypi.org/legacy/";
+
+/// The username for API tokens.
+const TOKEN_USERNAME: &str = "__token__";
+
+/// Core metadata versions indexes accept.
+const METADATA_VERSIONS: &[&str] = &["1.0", "1.1", "1.2", "2.0", "2.1", "2.2", "2.3", "2.4"];
+
+/// Valid project names; PEP 508.
+static NAME_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)^([a-z0-9]|[a-z0-9][a-z0-9._-]*[a-z0-9])$").unwrap());
+
+/// Valid versions, normalized or not; PEP 440's appendix.
+static VERSION_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)^v?(\d+!)?\d+(\.\d+)*([-_.]?(a|b|c|rc|alpha|beta|pre|preview)[-_.]?\d*)?(-\d+|[-_.]?(post|rev|r)[-_.]?\d*)?([-_.]?dev[-_.]?\d*)?(\+[a-z0-9]+([-_.][a-z0-9]+)*)?$").unwrap()
+});
+
+/// A distribution to upload.
+#[derive(Debug, PartialEq)]
+struct Dist {
+    path: PathBuf,
+    /// `bdist_wheel`, or `sdist`.
+    filetype: &'static str,
+    /// The Python tag for wheels, eg `py3`, or `source`.
+    pyversion: String,
+}
+
+/// A distribution's upload form, checked and ready to send.
+struct Upload {
+    path: PathBuf,
+    file_name: String,
+    data: Vec<u8>,
+    /// The form's text fields, in order; the file goes after them, as `content`.
+    fields: Vec<(String, String)>,
+    /// A detached `.asc` signature's filename and contents, sent as `gpg_signature`.
+    signature: Option<(String, Vec<u8>)>,
+    /// PEP 740 attestations, from Sigstore bundles, sent as `attestations`.
+    attestations: Vec<serde_json::Value>,
+}
+
+enum Outcome {
+    Uploaded,
+    /// The index already had it, and `--skip-existing` was passed.
+    Skipped,
+}
+
+/// How `publish` runs, besides uploading.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    Upload,
+    /// Read and check everything, and show what would be sent, but send nothing.
+    DryRun,
+    /// Only check the files, and report what's found, as JSON if `json`.
+    Check {
+        json: bool,
+    },
+}
+
+/// What checking a file found. Errors would stop it being uploaded.
+#[derive(Debug, Serialize)]
+struct Finding {
+    file: String,
+    passed: bool,
+    errors: Vec<String>,
+    warnings: Vec<String>,
+}
+
+/// What files are checked against.
+struct Checks<'a> {
+    /// PyPI's classifiers, if they're checked.
+    known: Option<&'a [String]>,
+    url: &'a str,
+    client: &'a reqwest::blocking::Client,
+    allow_dev: bool,
+    skip_existing: bool,
+    /// The files the index has for each release, by JSON API URL, so each is only fetched once.
+    released: HashMap<String, Result<Vec<String>, String>>,
+}
+
+/// The part of PyPI's JSON API for a release that's used.
+#[derive(Deserialize)]
+struct Release {
+    urls: Vec<ReleaseFile>,
+}
+
+#[derive(Deserialize)]
+struct ReleaseFile {
+    filename: String,
+}
+
+/// Upload `files`, or the project's wheels and sdist for its current version from `dist`. Every
+/// file is checked first, and nothing's sent if any fails. Classifiers are checked against
+/// PyPI's list, cached under `pyflow_path`, if it's set. Dev versions, and ones with a local
+/// label, are refused unless `allow_dev`.
+#[allow(clippy::too_many_arguments)]
+pub fn publish(
+    project_path: &Path,
+    cfg: &Config,
+    files: &[PathBuf],
+    repository: Option<&str>,
+    repository_url: Option<&str>,
+    skip_existing: bool,
+    mode: Mode,
+    pyflow_path: Option<&Path>,
+    allow_dev: bool,
+) {
+    let (repos, warnings) = settings::repositories();
+    for warning in warnings {
+        log::warn!("{}", warning);
+    }
+    let (mut url, configured_token) = find_repository(
+        &repos,
+        repository,
+        repository_url,
+        cfg.package_url.as_deref(),
+    )
+    .unwrap_or_else(|e| util::abort(&e));
+    if !url.ends_with('/') {
+        // The index redirects otherwise, and the upload would be lost in the redirect.
+        url.push('/');
+    }
+
+    let dists: Vec<Dist> = if files.is_empty() {
+        let (name, version) = name_and_version(cfg);
+        find_dists(&project_path.join("dist"), name, &version.to_string())
+    } else {
+        files
+            .iter()
+            .map(|f| {
+                let name = f.file_name().unwrap_or_default().to_string_lossy();
+                classify(&name)
+                    .map(|(filetype, pyversion)| Dist {
+                        path: f.clone(),
+                        filetype,
+                        pyversion,
+                    })
+                    .unwrap_or_else(|| {
+                        util::abort(&format!("{:?} isn't a wheel, or a `.tar.gz` sdist", f))
+                    })
+            })
+            .collect()
+    };
+    if dists.is_empty() {
+        util::abort(
+            "There's nothing in `dist` to upload for this version; build it with `pyflow build \
+             --sdist --wheel`",
+        )
+    }
+
+    let known = pyflow_path.and_then(classifiers::known);
+    let client = util::http_client();
+    let mut checks = Checks {
+        known: known.as_deref(),
+        url: &url,
+        client: &client,
+        allow_dev,
+        skip_existing,
+        released: HashMap::new(),
+    };
+    // Check every file before sending any, so a bad one doesn't leave a release half-uploaded.
+    let (uploads, findings): (Vec<Option<Upload>>, Vec<Finding>) =
+        dists.iter().map(|d| checks.check(d)).unzip();
+    let failed = findings.iter().filter(|f| !f.passed).count();
+
+    if let Mode::Check { json } = mode {
+        if json {
+            util::print_json(&findings);
+            process::exit(if failed > 0 { 1 } else { 0 })
+        }
+        print_findings(&findings);
+        if failed > 0 {
+            util::abort(&format!("{} of {} files failed", failed, findings.len()))
+        }
+        util::success(&format!("All {} files passed", findings.len()));
+        return;
+    }
+    for finding in &findings {
+        for warning in &finding.warnings {
+            log::warn!("{}: {}", finding.file, warning);
+        }
+    }
+    if failed > 0 {
+        for finding in findings.iter().filter(|f| !f.passed) {
+            eprintln!("{}:\n  {}", finding.file, finding.errors.join("\n  "));
+        }
+        util::abort(&format!(
+            "{} of {} files failed the checks, so nothing was uploaded",
+            failed,
+            findings.len()
+        ))
+    }
+    let mut uploads: Vec<Upload> = uploads.into_iter().flatten().collect();
+    // Sign everything before sending anything, so a failing sign command stops the release.
+    sign_uploads(&cfg.publish, &mut uploads).unwrap_or_else(|e| util::abort(&e));
+
+    if mode == Mode::DryRun {
+        print_dry_run(&url, &uploads);
+        util::success(&format!(
+            "Dry run: {} files are ready to upload to {}; nothing was sent",
+            uploads.len(),
+            url
+        ));
+        return;
+    }
+
+    let token = find_token(&url, configured_token);
+    let mut uploaded = 0;
+    for upload in uploads {
+        let file_name = upload.file_name.clone();
+        log::info!("Uploading {} to {}", file_name, url);
+        match send(&client, &url, &token, upload, skip_existing) {
+            Ok(Outcome::Uploaded) => uploaded += 1,
+            Ok(Outcome::Skipped) => log::warn!("{} is already there; skipping it", file_name),
+            Err(e) => util::abort(&e),
+        }
+    }
+    util::success(&format!("Uploaded {} files to {}", uploaded, url));
+}
+
+/// The upload URL, and the token the global config has for it, if any. `--repository` names
+/// one; otherwise it's `--repository-url`, `package_url`, or the default, with the token of a
+/// configured repository at that URL.
+fn find_repository(
+    repos: &[Repository],
+    name: Option<&str>,
+    url: Option<&str>,
+    package_url: Option<&str>,
+) -> Result<(String, Option<String>), String> {
+    if let Some(name) = name {
+        return match repos.iter().find(|r| r.name == name) {
+            Some(r) => Ok((r.url.clone(), r.token.clone())),
+            None => {
+                let names: Vec<&str> = repos.iter().map(|r| r.name.as_str()).collect();
+                Err(format!(
+                    "There's no repository called `{}`. Use one of {}, or add it to the global \
+                     config, under `[repositories.{}]`",
+                    name,
+                    names.join(", "),
+                    name
+                ))
+            }
+        };
+    }
+    let url = url.or(package_url).unwrap_or(DEFAULT_REPOSITORY_URL);
+    let token = repos
+        .iter()
+        .find(|r| r.url.trim_end_matches('/') == url.trim_end_matches('/'))
+        .and_then(|r| r.token.clone());
+    Ok((url.to_owned(), token))
+}
+
+/// The API token: from `PYFLOW_PYPI_TOKEN`, the repository's entry in the global config, the
+/// keyring, or a prompt. A prompted token is never saved.
+fn find_token(url: &str, configured: Option<String>) -> String {
+    if let Ok(t) = env::var("PYFLOW_PYPI_TOKEN") {
+        if !t.trim().is_empty() {
+            return t.trim().to_owned();
+        }
+    }
+    if let Some(t) = configured {
+        if !t.trim().is_empty() {
+            return t.trim().to_owned();
+        }
+    }
+    // The `keyring` CLI, from the Python package, as used by twine: `keyring set <url> __token__`.
+    if !util::find_on_path("keyring").is_empty() {
+        if let Ok(output) = Command::new("keyring")
+            .args(["get", url, TOKEN_USERNAME])
+            .output()
+        {
+            let t = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+            if output.status.success() && !t.is_empty() {
+                return t;
+            }
+        }
+    }
+    prompts::secret(
+        &format!("API token for {}: ", url),
+        &format!(
+            "Set `PYFLOW_PYPI_TOKEN` to an API token, or save one with `keyring set {} {}`",
+            url, TOKEN_USERNAME
+        ),
+    )
+}
+
+/// Wheels and sdists in `dir` for `name` at `version`, sorted.
+fn find_dists(dir: &Path, name: &str, version: &str) -> Vec<Dist> {
+    let mut result: Vec<Dist> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let (file_dist, file_version) = name_and_version_of(&file_name)?;
+            if dist_name(&file_dist) != dist_name(name) || file_version != version {
+                return None;
+            }
+            let (filetype, pyversion) = classify(&file_name)?;
+            Some(Dist {
+                path: entry.path(),
+                filetype,
+                pyversion,
+            })
+        })
+        .collect();
+    result.sort_by(|a, b| a.path.cmp(&b.path));
+    result
+}
+
+/// The name and version in a wheel or sdist's filename, eg `everything_killer-0.1.0.tar.gz`.
+fn name_and_version_of(file_name: &str) -> Option<(String, String)> {
+    if let Some(stem) = file_name.strip_suffix(".whl") {
+        let mut parts = stem.split('-');
+        return Some((parts.next()?.to_owned(), parts.next()?.to_owned()));
+    }
+    let stem = file_name.strip_suffix(".tar.gz")?;
+    let (name, version) = stem.rsplit_once('-')?;
+    Some((name.to_owned(), version.to_owned()))
+}
+
+/// The upload's `filetype` and `pyversion` fields, from its filename.
+fn classify(file_name: &str) -> Option<(&'static str, String)> {
+    if let Some(stem) = file_name.strip_suffix(".whl") {
+        // `{name}-{version}(-{build})?-{python}-{abi}-{platform}`
+        let parts: Vec<&str> = stem.split('-').collect();
+        if parts.len() < 5 {
+            return None;
+        }
+        Some(("bdist_wheel", parts[parts.len() - 3].to_owned()))
+    } else if file_name.ends_with(".tar.gz") {
+        Some(("sdist", "source".to_owned()))
+    } else {
+        None
+    }
+}
+
+/// Read a distribution into its upload form. Fails if it can't be read, or has no metadata.
+fn prepare(dist: &Dist) -> Result<Upload, String> {
+    let file_name = dist
+        .path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let data =
+        fs::read(&dist.path).map_err(|e| format!("Problem reading {:?}: {}", dist.path, e))?;
+    let metadata = read_metadata(&dist.path, &data)
+        .ok_or_else(|| "Can't find the package metadata in it".to_owned())?;
+    let metadata = form_fields(&metadata);
+
+    let hash = ring::digest::digest(&ring::digest::SHA256, &data);
+    let mut fields: Vec<(String, String)> = vec![
+        (":action", "file_upload".to_owned()),
+        ("protocol_version", "1".to_owned()),
+        ("filetype", dist.filetype.to_owned()),
+        ("pyversion", dist.pyversion.clone()),
+        (
+            "sha256_digest",
+            data_encoding::HEXLOWER.encode(hash.as_ref()),
+        ),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_owned(), v))
+    .collect();
+    fields.extend(metadata);
+
+    Ok(Upload {
+        path: dist.path.clone(),
+        file_name,
+        data,
+        fields,
+        signature: None,
+        attestations: vec![],
+    })
+}
+
+/// Problems the index would reject a file for, from its metadata, as form fields.
+fn check_metadata(fields: &[(String, String)], file_name: &str) -> Vec<String> {
+    let get = |k: &str| {
+        fields
+            .iter()
+            .find(|(f, _)| f == k)
+            .map(|(_, v)| v.as_str())
+            .filter(|v| !v.is_empty())
+    };
+    let mut result = vec![];
+
+    match get("metadata_version") {
+        Some(v) if !METADATA_VERSIONS.contains(&v) => {
+            result.push(format!("Metadata-Version `{}` isn't one indexes accept", v))
+        }
+        Some(_) => (),
+        None => result.push("There's no Metadata-Version".to_owned()),
+    }
+    let (name, version) = (get("name"), get("version"));
+    match name {
+        Some(n) if !NAME_RE.is_match(n) => result.push(format!(
+            "`{}` isn't a valid name: it can only have letters, digits, `-`, `_`, and `.`, and \
+             must start and end with a letter or digit",
+            n
+        )),
+        Some(_) => (),
+        None => result.push("There's no Name".to_owned()),
+    }
+    match version {
+        Some(v) if !VERSION_RE.is_match(v) => {
+            result.push(format!("`{}` isn't a PEP 440 version", v))
+        }
+        Some(_) => (),
+        None => result.push("There's no Version".to_owned()),
+    }
+    if let (Some(name), Some(version), Some((file_dist, file_version))) =
+        (name, version, name_and_version_of(file_name))
+    {
+        if dist_name(name) != dist_name(&file_dist) || version != file_version {
+            result.push(format!(
+                "The metadata is for {} {}, but the filename says {} {}",
+                name, version, file_dist, file_version
+            ));
+        }
+    }
+    let description = get("description").unwrap_or_default();
+    result.extend(description::check(
+        get("description_content_type"),
+        description,
+    ));
+    result
+}
+
+impl Checks<'_> {
+    /// Check a file, as `twine check` does and more, finding every problem rather than stopping
+    /// at the first. Its upload form, if it passed.
+    fn check(&mut self, dist: &Dist) -> (Option<Upload>, Finding) {
+        let file_name = dist
+            .path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let mut finding = Finding {
+            file: file_name.clone(),
+            passed: false,
+            errors: vec![],
+            warnings: vec![],
+        };
+        let upload = match prepare(dist) {
+            Ok(u) => u,
+            Err(e) => {
+                finding.errors.push(e);
+                return (None, finding);
+            }
+        };
+
+        finding
+            .errors
+            .extend(artifact::verify(&file_name, &upload.data));
+        finding
+            .errors
+            .extend(check_metadata(&upload.fields, &file_name));
+        let (errors, warnings) = check_classifiers(&upload.fields, self.known);
+        finding.errors.extend(errors);
+        finding.warnings.extend(warnings);
+        if let Some(version) = dev_version(&upload.fields).filter(|_| !self.allow_dev) {
+            finding.errors.push(format!(
+                "{} is a dev version, or one built from uncommitted changes; tag a release, or \
+                 pass `--allow-dev` to upload it anyway",
+                version
+            ));
+        }
+        if field(&upload.fields, "description").map_or(true, |d| d.trim().is_empty()) {
+            finding.warnings.push(
+                "There's no description, so its page on the index will be blank; set `readme` \
+                 under `[tool.pyflow]`"
+                    .to_owned(),
+            );
+        }
+        if let (Some(name), Some(version)) = (
+            field(&upload.fields, "name"),
+            field(&upload.fields, "version"),
+        ) {
+            match self.released(name, version) {
+                Some(Ok(files)) if files.contains(&file_name) && self.skip_existing => finding
+                    .warnings
+                    .push(format!("{} already has it, so it'll be skipped", self.url)),
+                Some(Ok(files)) if files.contains(&file_name) => finding.errors.push(format!(
+                    "{} already has it, and files can't be replaced; change the version",
+                    self.url
+                )),
+                Some(Err(e)) => finding.warnings.push(format!(
+                    "Can't check whether {} already has it: {}",
+                    self.url, e
+                )),
+                _ => (),
+            }
+        }
+
+        finding.passed = finding.errors.is_empty();
+        let upload = Some(upload).filter(|_| finding.passed);
+        (upload, finding)
+    }
+
+    /// The files the index has for a release, if it has PyPI's JSON API.
+    fn released(&mut self, name: &str, version: &str) -> Option<Result<Vec<String>, String>> {
+        let url = release_url(self.url, name, version)?;
+        let client = self.client;
+        let result = self
+            .released
+            .entry(url)
+            .or_insert_with_key(|url| fetch_release(client, url));
+        Some(result.clone())
+    }
+}
+
+/// A release's JSON API URL, for PyPI and TestPyPI; other indexes mostly don't have it.
+fn release_url(upload_url: &str, name: &str, version: &str) -> Option<String> {
+    let host = match upload_url.trim_end_matches('/') {
+        "https://upload.pypi.org/legacy" => "https://pypi.org",
+        "https://test.pypi.org/legacy" => "https://test.pypi.org",
+        _ => return None,
+    };
+    Some(format!("{}/pypi/{}/{}/json", host, name, version))
+}
+
+/// The release's filenames; none if the index doesn't have it.
+fn fetch_release(client: &reqwest::blocking::Client, url: &str) -> Result<Vec<String>, String> {
+    let response = client
+        .get(url)
+        .header(
+            "User-Agent",
+            format!("pyflow/{}", env!("CARGO_PKG_VERSION")),
+        )
+        .send()
+        .map_err(|e| e.to_string())?;
+    match response.status() {
+        StatusCode::NOT_FOUND => Ok(vec![]),
+        s if s.is_success() => response
+            .json::<Release>()
+            .map(|r| r.urls.into_iter().map(|f| f.filename).collect())
+            .map_err(|e| e.to_string()),
+        s => Err(s.to_string()),
+    }
+}
+
+/// Write digest files, and run the sign command for each file, as `[tool.pyflow.publish]` sets.
+/// Signatures the index takes are added to the uploads.
+fn sign_uploads(settings: &Publish, uploads: &mut [Upload]) -> Result<(), String> {
+    for upload in uploads.iter_mut() {
+        if settings.digests || settings.sign_command.is_some() {
+            // The index checks the upload against the digest it's sent, the `.sha256`'s.
+            let digest = sign::write_digest(&upload.path, &upload.data)?;
+            for (field, value) in upload.fields.iter_mut() {
+                if field == "sha256_digest" {
+                    *value = digest.clone();
+                }
+            }
+        }
+        let command = match &settings.sign_command {
+            Some(c) => c,
+            None => continue,
+        };
+        for signature in sign::sign(command, &upload.path)? {
+            if let Signature::Gpg(path) = &signature {
+                let data =
+                    fs::read(path).map_err(|e| format!("Problem reading {:?}: {}", path, e))?;
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                upload.signature = Some((name.into_owned(), data));
+            }
+            upload.attestations.extend(sign::attestation(&signature)?);
+        }
+    }
+    Ok(())
+}
+
+/// Each file, whether it passed, and what was found.
+fn print_findings(findings: &[Finding]) {
+    for finding in findings {
+        if finding.passed {
+            util::print_color(&format!("{}: passed", finding.file), Color::Green);
+        } else {
+            util::print_color(&format!("{}: failed", finding.file), Color::Red);
+        }
+        for error in &finding.errors {
+            eprintln!("  {}", error);
+        }
+        for warning in &finding.warnings {
+            log::warn!("{}: {}", finding.file, warning);
+        }
+    }
+}
+
+/// A field's value, if it's set.
+fn field<'a>(fields: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    fields
+        .iter()
+        .find(|(f, _)| f == name)
+        .map(|(_, v)| v.as_str())
+        .filter(|v| !v.is_empty())
+}
+
+/// The version, if it's a dev release, or has a local label, eg `1.4.2.dev3+g1a2b3c4`.
+fn dev_version(fields: &[(String, String)]) -> Option<&str> {
+    field(fields, "version").filter(|v| v.contains(".dev") || v.contains('+'))
+}
+
+/// Classifiers PyPI would reject, if there's a list to check against, and deprecated ones.
+fn check_classifiers(
+    fields: &[(String, String)],
+    known: Option<&[String]>,
+) -> (Vec<String>, Vec<String>) {
+    let known = match known {
+        Some(k) => k,
+        None => return (vec![], vec![]),
+    };
+    let values = |name: &str| {
+        fields
+            .iter()
+            .filter(|(f, _)| f == name)
+            .map(|(_, v)| v.as_str())
+            .collect::<Vec<_>>()
+    };
+    let license_expression = !values("license_expression").is_empty();
+    let (mut errors, warnings) =
+        classifiers::check(&values("classifiers"), known, license_expression);
+    if !errors.is_empty() {
+        errors.push("Pass `--no-verify-classifiers` if the index accepts these".to_owned());
+    }
+    (errors, warnings)
+}
+
+/// Show what each upload would send, with the description summarized.
+fn print_dry_run(url: &str, uploads: &[Upload]) {
+    util::print_color(&format!("Would upload to {}:", url), Color::Blue);
+    for upload in uploads {
+        util::print_color(
+            &format!(
+                "{} ({}, {} bytes)",
+                upload.file_name,
+                util::format_size(upload.data.len() as u64),
+                upload.data.len()
+            ),
+            Color::Cyan,
+        );
+        for (field, value) in &upload.fields {
+            match field.as_str() {
+                "description" => println!("    {}: ({} characters)", field, value.chars().count()),
+                _ => println!("    {}: {}", field, value),
+            }
+        }
+        if let Some((name, data)) = &upload.signature {
+            println!("    gpg_signature: {} ({} bytes)", name, data.len());
+        }
+        if !upload.attestations.is_empty() {
+            println!("    attestations: {}", upload.attestations.len());
+        }
+    }
+}
+
+fn send(
+    client: &reqwest::blocking::Client,
+    url: &str,
+    token: &str,
+    upload: Upload,
+    skip_existing: bool,
+) -> Result<Outcome, String> {
+    let Upload {
+        file_name,
+        data,
+        fields,
+        signature,
+        attestations,
+        ..
+    } = upload;
+    let mut form = multipart::Form::new();
+    for (field, value) in fields {
+        form = form.text(field, value);
+    }
+    if !attestations.is_empty() {
+        form = form.text(
+            "attestations",
+            serde_json::to_string(&attestations).unwrap_or_default(),
+        );
+    }
+    if let Some((name, signature)) = signature {
+        let part = multipart::Part::bytes(signature)
+            .file_name(name)
+            .mime_str("application/octet-stream")
+            .map_err(|e| e.to_string())?;
+        form = form.part("gpg_signature", part);
+    }
+    let content = multipart::Part::bytes(data)
+        .file_name(file_name.clone())
+        .mime_str("application/octet-stream")
+        .map_err(|e| e.to_string())?;
+    form = form.part("content", content);
+
+    let response = client
+        .post(url)
+        .basic_auth(TOKEN_USERNAME, Some(token))
+        .header(
+            "User-Agent",
+            format!("pyflow/{}", env!("CARGO_PKG_VERSION")),
+        )
+        .multipart(form)
+        .send()
+        .map_err(|e| format!("Problem uploading {} to {}: {}", file_name, url, e))?;
+
+    let status = response.status();
+    if status.is_success() {
+        return Ok(Outcome::Uploaded);
+    }
+    let body = response.text().unwrap_or_default();
+    // Indexes explain what's wrong in the page, eg "400 File already exists. See ...".
+    let reason = error_reason(&body).unwrap_or_else(|| status.to_string());
+    if skip_existing && already_exists(status, &reason) {
+        return Ok(Outcome::Skipped);
+    }
+    let hint = match status {
+        StatusCode::FORBIDDEN | StatusCode::UNAUTHORIZED => {
+            "\nCheck the API token is right, and can upload to this project"
+        }
+        _ => "",
+    };
+    Err(format!(
+        "{} rejected {}: {}{}",
+        url, file_name, reason, hint
+    ))
+}
+
+fn already_exists(status: StatusCode, reason: &str) -> bool {
+    let reason = reason.to_lowercase();
+    status == StatusCode::CONFLICT
+        || (status == StatusCode::BAD_REQUEST && reason.contains("already exist"))
+}
+
+/// The message in an index's error page: its title, or the body, if it isn't HTML.
+fn error_reason(body: &str) -> Option<String> {
+    let body = body.trim();
+    if body.is_empty() {
+        return None;
+    }
+    let text = match (body.find("<title>"), body.find("</title>")) {
+        (Some(start), Some(end)) if start < end => &body[start + "<title>".len()..end],
+        _ if body.starts_with('<') => return None,
+        _ => body,
+    };
+    let text = text
+        .trim()
+        .replace("&#39;", "'")
+        .replace("&quot;", "\"")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&");
+    Some(text)
+}
+
+/// The core metadata in a wheel's `.dist-info/METADATA`, or an sdist's `PKG-INFO`.
+fn read_metadata(path: &Path, data: &[u8]) -> Option<String> {
+    let file_name = path.file_name()?.to_string_lossy();
+    let mut result = String::new();
+    if file_name.ends_with(".whl") {
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data)).ok()?;
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i).ok()?;
+            let name = file.name().to_owned();
+            if name.ends_with(".dist-info/METADATA") && name.matches('/').count() == 1 {
+                file.read_to_string(&mut result).ok()?;
+                return Some(result);
+            }
+        }
+    } else {
+        let mut archive = tar::Archive::new(GzDecoder::new(data));
+        for entry in archive.entries().ok()? {
+            let mut entry = entry.ok()?;
+            let name = entry.path().ok()?.to_string_lossy().into_owned();
+            let parts: Vec<&str> = name.split('/').collect();
+            if parts.len() == 2 && parts[1] == "PKG-INFO" {
+                entry.read_to_string(&mut result).ok()?;
+                return Some(result);
+            }
+        }
+    }
+    None
+}
+
+/// Core metadata, as the upload form's fields: `Home-page` is `home_page`, and so on, with
+/// repeatable fields repeated, and the description from the body.
+fn form_fields(metadata: &str) -> Vec<(String, String)> {
+    let mut result: Vec<(String, String)> = vec![];
+    let mut lines = metadata.lines();
+    for line in lines.by_ref() {
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with(' ') || line.starts_with('\t') {
+            // A continuation of the previous field, eg an old-style multi-line `Description`.
+            if let Some((_, value)) = result.last_mut() {
+                let cont = line.trim_start();
+                value.push('\n');
+                value.push_str(cont.strip_prefix('|').unwrap_or(cont));
+            }
+            continue;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            let field = match key.trim().to_lowercase().replace('-', "_").as_str() {
+                "classifier" => "classifiers".to_owned(),
+                "project_url" => "project_urls".to_owned(),
+                other => other.to_owned(),
+            };
+            result.push((field, value.trim().to_owned()));
+        }
+    }
+    let body: Vec<&str> = lines.collect();
+    if !body.is_empty() {
+        result.retain(|(k, _)| k != "description");
+        result.push(("description".to_owned(), body.join("\n")));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields() {
+        let metadata = "Metadata-Version: 2.1\nName: everything-killer\nVersion: 0.1.0\n\
+                        Home-page: https://everything.math\nClassifier: Topic :: A\n\
+                        Classifier: Topic :: B\nProject-URL: Repository, https://github.com/raz\n\
+                        Requires-Dist: numpy>=1.16.4,<2.0.0\n\
+                        Description-Content-Type: text/markdown\n\n# Everything killer\n\nBoom.\n";
+        let fields = form_fields(metadata);
+        let get = |k: &str| -> Vec<&str> {
+            fields
+                .iter()
+                .filter(|(f, _)| f == k)
+                .map(|(_, v)| v.as_str())
+                .collect()
+        };
+        assert_eq!(get("metadata_version"), vec!["2.1"]);
+        assert_eq!(get("name"), vec!["everything-killer"]);
+        assert_eq!(get("home_page"), vec!["https://everything.math"]);
+        assert_eq!(get("classifiers"), vec!["Topic :: A", "Topic :: B"]);
+        assert_eq!(
+            get("project_urls"),
+            vec!["Repository, https://github.com/raz"]
+        );
+        assert_eq!(get("requires_dist"), vec!["numpy>=1.16.4,<2.0.0"]);
+        assert_eq!(get("description"), vec!["# Everything killer\n\nBoom."]);
+
+        let old = "Metadata-Version: 1.1\nName: a\nDescription: Line one\n        |Line two\n";
+        assert_eq!(
+            form_fields(old).last().unwrap(),
+            &("description".to_owned(), "Line one\nLine two".to_owned())
+        );
+    }
+
+    #[test]
+    fn dist_files() {
+        assert_eq!(
+            classify("everything_killer-0.1.0-py3-none-any.whl"),
+            Some(("bdist_wheel", "py3".to_owned()))
+        );
+        assert_eq!(
+            classify("everything_killer-0.1.0.tar.gz"),
+            Some(("sdist", "source".to_owned()))
+        );
+        assert_eq!(classify("everything_killer-0.1.0.zip"), None);
+        assert_eq!(
+            name_and_version_of("everything-killer-0.1.0.tar.gz"),
+            Some(("everything-killer".to_owned(), "0.1.0".to_owned()))
+        );
+        assert_eq!(
+            name_and_version_of("everything_killer-0.1.0-1-cp38-cp38-linux_x86_64.whl"),
+            Some(("everything_killer".to_owned(), "0.1.0".to_owned()))
+        );
+    }
+
+    #[test]
+    fn checks() {
+        let fields = |metadata: &str| form_fields(metadata);
+        let good = fields(
+            "Metadata-Version: 2.1\nName: everything-killer\nVersion: 0.1.0\n\
+             Description-Content-Type: text/markdown; charset=UTF-8\n",
+        );
+        assert!(check_metadata(&good, "everything_killer-0.1.0-py3-none-any.whl").is_empty());
+
+        let problems = check_metadata(&good, "everything_killer-0.2.0.tar.gz");
+        assert_eq!(
+            problems,
+            vec![
+                "The metadata is for everything-killer 0.1.0, but the filename says \
+                  everything_killer 0.2.0"
+            ]
+        );
+
+        let bad = fields("Metadata-Version: 3.0\nName: a\nDescription-Content-Type: text/html\n");
+        let problems = check_metadata(&bad, "a-1.0.tar.gz");
+        assert_eq!(problems.len(), 3);
+        assert!(problems[0].contains("3.0"));
+        assert_eq!(problems[1], "There's no Version");
+        assert!(problems[2].contains("text/html"));
+    }
+
+    #[test]
+    fn names_and_versions() {
+        let fields = |metadata: &str| form_fields(metadata);
+        let bad = fields("Metadata-Version: 2.1\nName: -killer\nVersion: 0.1.0-beta!\n");
+        let problems = check_metadata(&bad, "-killer-0.1.0-beta!.tar.gz");
+        assert!(problems[0].starts_with("`-killer` isn't a valid name"));
+        assert_eq!(problems[1], "`0.1.0-beta!` isn't a PEP 440 version");
+
+        let dev = fields("Name: a\nVersion: 1.4.2.dev3+g1a2b3c4\n");
+        assert_eq!(dev_version(&dev), Some("1.4.2.dev3+g1a2b3c4"));
+        assert!(check_metadata(&dev, "a-1.4.2.dev3+g1a2b3c4.tar.gz")
+            .iter()
+            .all(|p| !p.contains("PEP 440")));
+    }
+
+    #[test]
+    fn release_urls() {
+        assert_eq!(
+            release_url("https://upload.pypi.org/legacy/", "a", "1.0").as_deref(),
+            Some("https://pypi.org/pypi/a/1.0/json")
+        );
+        assert_eq!(
+            release_url("https://test.pypi.org/legacy/", "a", "1.0").as_deref(),
+            Some("https://test.pypi.org/pypi/a/1.0/json")
+        );
+        assert_eq!(
+            release_url("https://pypi.internal/legacy/", "a", "1.0"),
+            None
+        );
+    }
+
+    #[test]
+    fn repositories() {
+        let repos = vec![
+            Repository {
+                name: "testpypi".into(),
+                url: "https://test.pypi.org/legacy/".into(),
+                token: Some("pypi-test".into()),
+            },
+            Repository {
+                name: "internal".into(),
+                url: "https://pypi.internal/legacy/".into(),
+                token: None,
+            },
+        ];
+        assert_eq!(
+            find_repository(&repos, Some("internal"), None, None),
+            Ok(("https://pypi.internal/legacy/".to_owned(), None))
+        );
+        assert!(find_repository(&repos, Some("elsewhere"), None, None)
+            .unwrap_err()
+            .contains("testpypi, internal"));
+        // A configured repository's token is used for its URL, however it's given.
+        assert_eq!(
+            find_repository(&repos, None, None, Some("https://test.pypi.org/legacy")),
+            Ok((
+                "https://test.pypi.org/legacy".to_owned(),
+                Some("pypi-test".to_owned())
+            ))
+        );
+        assert_eq!(
+            find_repository(
+                &repos,
+                None,
+                Some("https://other.org/"),
+                Some("https://x.org/")
+            ),
+            Ok(("https://other.org/".to_owned(), None))
+        );
+        assert_eq!(
+            find_repository(&repos, None, None, None),
+            Ok(("https://upload.pypi.org/legacy/".to_owned(), None))
+        );
+    }
+
+    #[test]
+    fn reasons() {
+        let page = "<html>\n <head>\n  <title>400 File already exists. See \
+                    https://pypi.org/help/#file-name-reuse for more information.</title>\n \
+                    </head>\n <body>\n  <h1>400 File already exists.</h1>\n </body>\n</html>";
+        let reason = error_reason(page).unwrap();
+        assert_eq!(
+            reason,
+            "400 File already exists. See https://pypi.org/help/#file-name-reuse for more \
+             information."
+        );
+        assert!(already_exists(StatusCode::BAD_REQUEST, &reason));
+        assert!(!already_exists(
+            StatusCode::BAD_REQUEST,
+            "400 &#39;1.0&#39; is an invalid value for Version."
+        ));
+        assert_eq!(
+            error_reason("Invalid &#39;version&#39;").unwrap(),
+            "Invalid 'version'"
+        );
+        assert_eq!(error_reason(""), None);
+    }
+}
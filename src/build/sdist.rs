@@ -0,0 +1,351 @@
+//! `pyflow build --sdist`: a source distribution, as a `.tar.gz` that's the same, byte for byte,
+//! each time the same sources are built, as long as `SOURCE_DATE_EPOCH` is set.
+//! https://packaging.python.org/specifications/source-distribution-format/
+//!
+//! pyflow isn't a PEP 517 backend, so the sdist includes a generated `setup.py`, which pip builds
+//! with setuptools.
+
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use flate2::{write::GzEncoder, Compression};
+use regex::Regex;
+
+use super::{
+    cfg_to_setup,
+    fileset::{glob_regex, relative, FileSet},
+    license::{license_files, LICENSE_FILES},
+    wheel::{dist_name, find_readme, metadata, name_and_version, package_roots, Readme},
+};
+use crate::{
+    pyproject::{Config, CFG_FILENAME},
+    util,
+};
+
+/// A file in the archive; a wheel's too.
+#[derive(Debug)]
+pub(super) struct Entry {
+    pub(super) path: String,
+    pub(super) data: Vec<u8>,
+    pub(super) mode: u32,
+    pub(super) mtime: u64,
+}
+
+/// Build an sdist for the project at `project_path` into `out_dir`, and return its path.
+pub fn build_sdist(project_path: &Path, cfg: &Config, out_dir: &Path) -> PathBuf {
+    write_sdist(project_path, cfg, out_dir, source_date_epoch())
+}
+
+/// What `--reproducible` sets `SOURCE_DATE_EPOCH` to if it isn't set: 1980-01-01, the earliest
+/// date zip files can have.
+pub const DEFAULT_EPOCH: u64 = 315_532_800;
+
+/// `SOURCE_DATE_EPOCH`, which no file in a distribution is dated later than, if it's set.
+/// https://reproducible-builds.org/specs/source-date-epoch/
+pub(super) fn source_date_epoch() -> Option<u64> {
+    env::var("SOURCE_DATE_EPOCH").ok().map(|v| {
+        v.trim().parse().unwrap_or_else(|_| {
+            util::abort(&format!(
+                "`SOURCE_DATE_EPOCH` must be a number of seconds, not {:?}",
+                v
+            ))
+        })
+    })
+}
+
+fn write_sdist(project_path: &Path, cfg: &Config, out_dir: &Path, epoch: Option<u64>) -> PathBuf {
+    let (name, version) = name_and_version(cfg);
+    let base = format!("{}-{}", dist_name(name), version);
+    let readme = find_readme(project_path, cfg);
+    let clamp = |mtime: u64| epoch.map_or(mtime, |e| mtime.min(e));
+
+    let mut entries: Vec<Entry> = sources(project_path, cfg, readme.as_ref(), out_dir)
+        .into_iter()
+        .map(|rel| {
+            let path = project_path.join(&rel);
+            let data = fs::read(&path)
+                .unwrap_or_else(|e| util::abort(&format!("Problem reading {:?}: {}", path, e)));
+            Entry {
+                path: format!("{}/{}", base, rel),
+                data,
+                mode: mode(&path),
+                mtime: clamp(mtime(&path)),
+            }
+        })
+        .collect();
+
+    // Generated files are as new as the project's config.
+    let generated_mtime = clamp(mtime(&project_path.join(CFG_FILENAME)));
+    entries.push(Entry {
+        path: format!("{}/PKG-INFO", base),
+        data: metadata(cfg, readme.as_ref(), &license_files(project_path)).into_bytes(),
+        mode: 0o644,
+        mtime: generated_mtime,
+    });
+    if !project_path.join("setup.py").exists() {
+        // Match where `pyflow build` finds packages.
+        let mut cfg = cfg.clone();
+        if cfg.package_dir.is_none() && project_path.join("src").is_dir() {
+            cfg.package_dir = Some("src".into());
+        }
+        let readme_rel = readme.as_ref().map(|r| relative(project_path, &r.path));
+        entries.push(Entry {
+            path: format!("{}/setup.py", base),
+            data: cfg_to_setup(&cfg, readme_rel.as_deref()).into_bytes(),
+            mode: 0o644,
+            mtime: generated_mtime,
+        });
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    if let Err(e) = fs::create_dir_all(out_dir) {
+        util::abort(&format!("Problem creating {:?}: {}", out_dir, e))
+    }
+    let sdist_path = out_dir.join(format!("{}.tar.gz", base));
+    if let Err(e) = write_tar_gz(&sdist_path, &entries) {
+        util::abort(&format!("Problem writing {:?}: {}", sdist_path, e))
+    }
+    sdist_path
+}
+
+/// The paths the sdist would have, without building it.
+pub fn list_sdist(project_path: &Path, cfg: &Config, out_dir: &Path) -> Vec<String> {
+    let (name, version) = name_and_version(cfg);
+    let base = format!("{}-{}", dist_name(name), version);
+    let readme = find_readme(project_path, cfg);
+
+    let mut result: Vec<String> = sources(project_path, cfg, readme.as_ref(), out_dir)
+        .into_iter()
+        .map(|rel| format!("{}/{}", base, rel))
+        .collect();
+    result.push(format!("{}/PKG-INFO", base));
+    if !project_path.join("setup.py").exists() {
+        result.push(format!("{}/setup.py", base));
+    }
+    result.sort();
+    result
+}
+
+/// The project's files to include: its packages, readme, and build script, and whatever `select_files` picks.
+fn sources(
+    project_path: &Path,
+    cfg: &Config,
+    readme: Option<&Readme>,
+    out_dir: &Path,
+) -> Vec<String> {
+    let (name, _) = name_and_version(cfg);
+    let mut always = package_roots(project_path, cfg, name);
+    always.extend(readme.map(|r| r.path.clone()));
+    // The generated `setup.py` runs it.
+    always.extend(cfg.build.as_ref().map(|script| project_path.join(script)));
+    select_files(project_path, cfg, &always, out_dir)
+}
+
+/// Paths of files to include, relative to the project, with `/` separators. `pyproject.toml` is
+/// always included, and so are license files and files under `always`, unless excluded. Without
+/// `include`, so is everything else that isn't excluded.
+fn select_files(
+    project_path: &Path,
+    cfg: &Config,
+    always: &[PathBuf],
+    out_dir: &Path,
+) -> Vec<String> {
+    let files = FileSet::new(cfg);
+    let licenses: Vec<Regex> = LICENSE_FILES
+        .iter()
+        .map(|p| glob_regex(&format!("/{}", p)))
+        .collect();
+
+    let mut result: Vec<String> = files
+        .walk(project_path, project_path, out_dir)
+        .into_iter()
+        .map(|path| (relative(project_path, &path), path))
+        .filter(|(rel, path)| {
+            !files.has_include()
+                || files.is_included(rel)
+                || licenses.iter().any(|re| re.is_match(rel))
+                || always.iter().any(|a| path.starts_with(a))
+        })
+        .map(|(rel, _)| rel)
+        .collect();
+    if !result.iter().any(|r| r == CFG_FILENAME) {
+        result.push(CFG_FILENAME.to_owned());
+    }
+    result.sort();
+    result
+}
+
+pub(super) fn mtime(path: &Path) -> u64 {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Permissions are normalized, so they don't depend on the umask: executable, or not.
+#[cfg(unix)]
+pub(super) fn mode(path: &Path) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+
+    match fs::metadata(path) {
+        Ok(m) if m.permissions().mode() & 0o111 != 0 => 0o755,
+        _ => 0o644,
+    }
+}
+
+#[cfg(not(unix))]
+pub(super) fn mode(_path: &Path) -> u32 {
+    0o644
+}
+
+/// Write `entries` in order, with no owner, and a gzip header without a name or timestamp.
+fn write_tar_gz(path: &Path, entries: &[Entry]) -> io::Result<()> {
+    let encoder = GzEncoder::new(fs::File::create(path)?, Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    for entry in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(entry.data.len() as u64);
+        header.set_mode(entry.mode);
+        header.set_mtime(entry.mtime);
+        header.set_uid(0);
+        header.set_gid(0);
+        builder.append_data(&mut header, &entry.path, entry.data.as_slice())?;
+    }
+    builder.into_inner()?.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dep_types::Version;
+    use crate::util::temp_dir::TempDir;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn project(name: &str) -> TempDir {
+        let project = TempDir::new(&format!("sdist-{}", name));
+        let files = [
+            "pyproject.toml",
+            "README.md",
+            "LICENSE",
+            "notes.txt",
+            "docs/index.md",
+            "tests/test_cli.py",
+            "src/everything_killer/__init__.py",
+            "src/everything_killer/cli.py",
+            "src/everything_killer/__pycache__/cli.cpython-38.pyc",
+            "__pypackages__/3.8/lib/idna/__init__.py",
+            ".git/HEAD",
+            "dist/everything_killer-0.0.9.tar.gz",
+        ];
+        for file in &files {
+            let path = project.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, file).unwrap();
+        }
+        project
+    }
+
+    fn cfg() -> Config {
+        Config {
+            name: Some("everything-killer".into()),
+            version: Some(Version::new(0, 1, 0)),
+            description: Some("Small, but packs a punch!".into()),
+            exclude: vec!["notes.txt".into()],
+            ..Default::default()
+        }
+    }
+
+    /// Each entry's path, mode, and mtime, and `PKG-INFO`.
+    fn read(sdist: &Path) -> (Vec<(String, u32, u64)>, String) {
+        let mut archive = tar::Archive::new(GzDecoder::new(fs::File::open(sdist).unwrap()));
+        let mut entries = vec![];
+        let mut pkg_info = String::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().into_owned();
+            let header = entry.header();
+            entries.push((
+                path.clone(),
+                header.mode().unwrap(),
+                header.mtime().unwrap(),
+            ));
+            if path.ends_with("/PKG-INFO") {
+                entry.read_to_string(&mut pkg_info).unwrap();
+            }
+        }
+        (entries, pkg_info)
+    }
+
+    #[test]
+    fn builds_sdist() {
+        let project = project("build");
+        let sdist = write_sdist(&project, &cfg(), &project.join("dist"), Some(1_000_000));
+        assert_eq!(sdist.file_name().unwrap(), "everything_killer-0.1.0.tar.gz");
+
+        let (entries, pkg_info) = read(&sdist);
+        let paths: Vec<&str> = entries.iter().map(|(p, _, _)| p.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "everything_killer-0.1.0/LICENSE",
+                "everything_killer-0.1.0/PKG-INFO",
+                "everything_killer-0.1.0/README.md",
+                "everything_killer-0.1.0/docs/index.md",
+                "everything_killer-0.1.0/pyproject.toml",
+                "everything_killer-0.1.0/setup.py",
+                "everything_killer-0.1.0/src/everything_killer/__init__.py",
+                "everything_killer-0.1.0/src/everything_killer/cli.py",
+                "everything_killer-0.1.0/tests/test_cli.py",
+            ]
+        );
+        assert!(entries
+            .iter()
+            .all(|(_, mode, mtime)| *mode == 0o644 && *mtime == 1_000_000));
+        assert!(pkg_info.starts_with(
+            "Metadata-Version: 2.4\nName: everything-killer\nVersion: 0.1.0\n\
+             Summary: Small, but packs a punch!\n"
+        ));
+        assert!(pkg_info.contains("\nLicense-File: LICENSE\n"));
+        assert!(pkg_info.ends_with("Description-Content-Type: text/markdown\n\nREADME.md"));
+
+        // The same sources build the same archive.
+        let again = write_sdist(&project, &cfg(), &project.join("dist2"), Some(1_000_000));
+        assert_eq!(fs::read(&sdist).unwrap(), fs::read(&again).unwrap());
+
+        fs::remove_dir_all(&project).unwrap();
+    }
+
+    #[test]
+    fn include_limits_files() {
+        let project = project("include");
+        let cfg = Config {
+            include: vec!["docs/**/*.md".into()],
+            ..cfg()
+        };
+        let sdist = write_sdist(&project, &cfg, &project.join("dist"), None);
+        let (entries, _) = read(&sdist);
+        let paths: Vec<&str> = entries.iter().map(|(p, _, _)| p.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "everything_killer-0.1.0/LICENSE",
+                "everything_killer-0.1.0/PKG-INFO",
+                "everything_killer-0.1.0/README.md",
+                "everything_killer-0.1.0/docs/index.md",
+                "everything_killer-0.1.0/pyproject.toml",
+                "everything_killer-0.1.0/setup.py",
+                "everything_killer-0.1.0/src/everything_killer/__init__.py",
+                "everything_killer-0.1.0/src/everything_killer/cli.py",
+            ]
+        );
+
+        fs::remove_dir_all(&project).unwrap();
+    }
+}
@@ -0,0 +1,217 @@
+//! Signing for `pyflow publish`, from `[tool.pyflow.publish]`: `.sha256` digest files, and
+//! detached signatures from an external command, eg `gpg --detach-sign --armor {file}`.
+//! Sigstore bundles are uploaded as PEP 740 attestations.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use serde_json::{json, Value};
+
+/// What the sign command wrote, by the extension it added to the artifact's filename.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum Signature {
+    /// An armored OpenPGP signature, `.asc`; the upload API takes it as `gpg_signature`.
+    Gpg(PathBuf),
+    /// A Sigstore bundle, `.sigstore` or `.sigstore.json`, uploaded as a PEP 740 attestation.
+    Sigstore(PathBuf),
+    /// A PEP 740 attestation, `.publish.attestation`, as `pypi-attestations sign` writes.
+    Attestation(PathBuf),
+}
+
+type SignatureKind = fn(PathBuf) -> Signature;
+
+/// Extensions sign commands add, and the kind of signature each is.
+const OUTPUTS: &[(&str, SignatureKind)] = &[
+    (".asc", Signature::Gpg),
+    (".sigstore", Signature::Sigstore),
+    (".sigstore.json", Signature::Sigstore),
+    (".publish.attestation", Signature::Attestation),
+];
+
+/// Write `<artifact>.sha256`, as `sha256sum` would, so it can be checked with `sha256sum -c`.
+/// Returns the digest, which is what's uploaded with the artifact.
+pub(super) fn write_digest(artifact: &Path, data: &[u8]) -> Result<String, String> {
+    let digest = ring::digest::digest(&ring::digest::SHA256, data);
+    let hex = data_encoding::HEXLOWER.encode(digest.as_ref());
+    let file_name = artifact.file_name().unwrap_or_default().to_string_lossy();
+    let path = with_suffix(artifact, ".sha256");
+    fs::write(&path, format!("{}  {}\n", hex, file_name))
+        .map_err(|e| format!("Problem writing {:?}: {}", path, e))?;
+    Ok(hex)
+}
+
+/// The PEP 740 attestation to upload for a Sigstore bundle, or attestation file.
+pub(super) fn attestation(signature: &Signature) -> Result<Option<Value>, String> {
+    let (path, bundle) = match signature {
+        Signature::Gpg(_) => return Ok(None),
+        Signature::Sigstore(path) => (path, true),
+        Signature::Attestation(path) => (path, false),
+    };
+    let data =
+        fs::read_to_string(path).map_err(|e| format!("Problem reading {:?}: {}", path, e))?;
+    let value: Value =
+        serde_json::from_str(&data).map_err(|e| format!("{:?} isn't valid JSON: {}", path, e))?;
+    if !bundle {
+        return Ok(Some(value));
+    }
+    from_bundle(&value).map(Some).ok_or_else(|| {
+        format!(
+            "{:?} can't be uploaded as an attestation: it needs to sign an in-toto statement \
+             about the file, in a DSSE envelope, eg from `pypi-attestations sign`, rather than \
+             the file itself",
+            path
+        )
+    })
+}
+
+/// A PEP 740 attestation from a Sigstore bundle of a DSSE envelope. Its fields are already
+/// base64, as the attestation's are.
+fn from_bundle(bundle: &Value) -> Option<Value> {
+    let material = bundle.get("verificationMaterial")?;
+    let certificate = material
+        .get("certificate")
+        .or_else(|| material.pointer("/x509CertificateChain/certificates/0"))?
+        .get("rawBytes")?;
+    let envelope = bundle.get("dsseEnvelope")?;
+    Some(json!({
+        "version": 1,
+        "verification_material": {
+            "certificate": certificate,
+            "transparency_entries": material.get("tlogEntries")?,
+        },
+        "envelope": {
+            "statement": envelope.get("payload")?,
+            "signature": envelope.pointer("/signatures/0/sig")?,
+        },
+    }))
+}
+
+/// Run `command` for `artifact`, and find the signatures it wrote next to it. `{file}` in the
+/// command is replaced with the artifact's path; without it, the path is added to the end. Old
+/// signatures are removed first, so a command that writes nothing isn't mistaken for one that
+/// worked.
+pub(super) fn sign(command: &str, artifact: &Path) -> Result<Vec<Signature>, String> {
+    let file_name = artifact.file_name().unwrap_or_default().to_string_lossy();
+    for (suffix, _) in OUTPUTS {
+        let path = with_suffix(artifact, suffix);
+        if path.exists() {
+            fs::remove_file(&path)
+                .map_err(|e| format!("Problem removing the old signature {:?}: {}", path, e))?;
+        }
+    }
+
+    let line = command_line(command, &artifact.to_string_lossy());
+    log::info!("Signing {}: {}", file_name, line);
+    // Run in the terminal, so it can ask for a passphrase, and show its own errors.
+    #[cfg(target_os = "windows")]
+    let status = Command::new("cmd").args(["/C", &line]).status();
+    #[cfg(not(target_os = "windows"))]
+    let status = Command::new("sh").args(["-c", &line]).status();
+    let status = status.map_err(|e| format!("Can't run the sign command `{}`: {}", line, e))?;
+    if !status.success() {
+        return Err(format!(
+            "The sign command failed for {}, with {}:\n  {}\nCheck `sign-command` in \
+             `[tool.pyflow.publish]`; nothing was uploaded",
+            file_name, status, line
+        ));
+    }
+
+    let signatures: Vec<Signature> = OUTPUTS
+        .iter()
+        .map(|(suffix, kind)| (with_suffix(artifact, suffix), kind))
+        .filter(|(path, _)| path.exists())
+        .map(|(path, kind)| kind(path))
+        .collect();
+    if signatures.is_empty() {
+        return Err(format!(
+            "The sign command ran for {}, but didn't write {}.asc, {}.sigstore, \
+             {}.sigstore.json, or {}.publish.attestation next to it",
+            file_name, file_name, file_name, file_name, file_name
+        ));
+    }
+    Ok(signatures)
+}
+
+/// The shell line to run: `{file}` replaced with the quoted path, or the path added to the end.
+fn command_line(command: &str, path: &str) -> String {
+    let quoted = if cfg!(target_os = "windows") {
+        format!("\"{}\"", path)
+    } else {
+        format!("'{}'", path.replace('\'', r"'\''"))
+    };
+    if command.contains("{file}") {
+        command.replace("{file}", &quoted)
+    } else {
+        format!("{} {}", command.trim_end(), quoted)
+    }
+}
+
+/// The artifact's path with `suffix` added, eg `a-1.0.tar.gz.asc`.
+fn with_suffix(artifact: &Path, suffix: &str) -> PathBuf {
+    let mut path = artifact.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn command_lines() {
+        assert_eq!(
+            command_line("gpg --detach-sign --armor {file}", "dist/a-1.0.tar.gz"),
+            "gpg --detach-sign --armor 'dist/a-1.0.tar.gz'"
+        );
+        assert_eq!(
+            command_line("./sign.sh ", "dist/it's.whl"),
+            r"./sign.sh 'dist/it'\''s.whl'"
+        );
+    }
+
+    #[test]
+    fn attestation_from_bundle() {
+        let bundle = json!({
+            "mediaType": "application/vnd.dev.sigstore.bundle.v0.3+json",
+            "verificationMaterial": {
+                "certificate": {"rawBytes": "Y2VydA=="},
+                "tlogEntries": [{"logIndex": "1"}],
+            },
+            "dsseEnvelope": {
+                "payload": "c3RhdGVtZW50",
+                "payloadType": "application/vnd.in-toto+json",
+                "signatures": [{"sig": "c2ln"}],
+            },
+        });
+        assert_eq!(
+            from_bundle(&bundle),
+            Some(json!({
+                "version": 1,
+                "verification_material": {
+                    "certificate": "Y2VydA==",
+                    "transparency_entries": [{"logIndex": "1"}],
+                },
+                "envelope": {"statement": "c3RhdGVtZW50", "signature": "c2ln"},
+            }))
+        );
+
+        // Signing the file itself gives a message signature, which can't be one.
+        let message = json!({
+            "verificationMaterial": bundle["verificationMaterial"],
+            "messageSignature": {"signature": "c2ln"},
+        });
+        assert_eq!(from_bundle(&message), None);
+    }
+
+    #[test]
+    fn suffixes() {
+        assert_eq!(
+            with_suffix(Path::new("dist/a-1.0.tar.gz"), ".asc"),
+            PathBuf::from("dist/a-1.0.tar.gz.asc")
+        );
+    }
+}
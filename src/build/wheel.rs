@@ -0,0 +1,865 @@
+//! `pyflow build`: a wheel, written directly from `pyproject.toml`, without setuptools.
+//! https://packaging.python.org/specifications/binary-distribution-format/
+//!
+//! Wheels are pure-Python, unless there's a `build` script to build extension modules; see
+//! `native`. Like sdists, they're the same, byte for byte, each time the same sources are built,
+//! as long as `SOURCE_DATE_EPOCH` is set.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use zip::{write::FileOptions, CompressionMethod, DateTime, ZipWriter};
+
+use super::{
+    console_scripts,
+    fileset::{relative, FileSet},
+    license::{self, license_files},
+    native::{self, BuildEnv},
+    parse_author,
+    sdist::{mode, mtime, source_date_epoch, Entry},
+};
+use crate::{
+    dep_types::{Req, Version},
+    pyproject::{Config, CFG_FILENAME},
+    util::{self, patterns::NAME_SEPARATORS_RE},
+};
+
+/// What we write to `WHEEL`'s `Tag`, and the wheel's filename, for pure-Python wheels.
+const TAG: &str = "py3-none-any";
+
+/// Readmes we look for if `readme` isn't set, in order.
+const READMES: &[&str] = &["README.md", "README.rst", "README.txt", "README"];
+
+/// Build a wheel for the project at `project_path` into `out_dir`, and return its path. If there's
+/// a `build` script, it's run in `env`, and what it builds is added, for a platform wheel.
+pub fn build_wheel(
+    project_path: &Path,
+    cfg: &Config,
+    out_dir: &Path,
+    env: Option<&BuildEnv>,
+) -> PathBuf {
+    write_wheel(project_path, cfg, out_dir, env, source_date_epoch())
+}
+
+fn write_wheel(
+    project_path: &Path,
+    cfg: &Config,
+    out_dir: &Path,
+    env: Option<&BuildEnv>,
+    epoch: Option<u64>,
+) -> PathBuf {
+    let (name, version) = name_and_version(cfg);
+    let clamp = |mtime: u64| epoch.map_or(mtime, |e| mtime.min(e));
+    let native = cfg.build.as_ref().map(|script| {
+        let env = env.unwrap_or_else(|| {
+            util::abort("The `build` script needs the project's environment to run in")
+        });
+        native::build(project_path, cfg, script, env)
+    });
+    for req in cfg.reqs.iter().filter(|r| r.path.is_some()) {
+        log::warn!(
+            "`{}` is a path dependency; the wheel requires it by name, so it must be installed \
+             from the index, or alongside this one",
+            req.name
+        );
+    }
+
+    let mut sources = find_sources(project_path, cfg, name);
+    if let Some(native) = &native {
+        for (path, archive_path) in &native.files {
+            if sources.iter().any(|(_, a)| a == archive_path) {
+                util::abort(&format!(
+                    "The build script wrote {:?}, but {} is in the sources too",
+                    path, archive_path
+                ))
+            }
+        }
+        sources.extend(native.files.iter().cloned());
+        sources.sort_by(|a, b| a.1.cmp(&b.1));
+    }
+    let tag = native.as_ref().map_or(TAG, |n| n.tag.as_str());
+    let readme = find_readme(project_path, cfg);
+    let dist_info = format!("{}-{}.dist-info", dist_name(name), version);
+
+    let read = |path: &Path, archive_path: String| {
+        let data = fs::read(path)
+            .unwrap_or_else(|e| util::abort(&format!("Problem reading {:?}: {}", path, e)));
+        Entry {
+            path: archive_path,
+            data,
+            mode: mode(path),
+            mtime: clamp(mtime(path)),
+        }
+    };
+    let mut files: Vec<Entry> = sources
+        .into_iter()
+        .map(|(path, archive_path)| read(&path, archive_path))
+        .collect();
+
+    // Generated files are as new as the project's config.
+    let generated = |path: String, data: String| Entry {
+        path,
+        data: data.into_bytes(),
+        mode: 0o644,
+        mtime: clamp(mtime(&project_path.join(CFG_FILENAME))),
+    };
+    let licenses = license_files(project_path);
+    files.push(generated(
+        format!("{}/METADATA", dist_info),
+        metadata(cfg, readme.as_ref(), &licenses),
+    ));
+    files.push(generated(
+        format!("{}/WHEEL", dist_info),
+        wheel_file(tag, native.is_none()),
+    ));
+    let scripts = console_scripts(&cfg.scripts);
+    if !scripts.is_empty() {
+        files.push(generated(
+            format!("{}/entry_points.txt", dist_info),
+            format!("[console_scripts]\n{}\n", scripts.join("\n")),
+        ));
+    }
+    for rel in &licenses {
+        files.push(read(
+            &project_path.join(rel),
+            format!("{}/licenses/{}", dist_info, rel),
+        ));
+    }
+    // The packages, then `.dist-info`, each sorted, with `RECORD` last, as installers expect.
+    let dist_info_dir = format!("{}/", dist_info);
+    let in_dist_info = |e: &Entry| e.path.starts_with(&dist_info_dir);
+    files.sort_by(|a, b| (in_dist_info(a), &a.path).cmp(&(in_dist_info(b), &b.path)));
+    let record_path = format!("{}/RECORD", dist_info);
+    let record = record(&files, &record_path);
+    files.push(generated(record_path, record));
+
+    if let Err(e) = fs::create_dir_all(out_dir) {
+        util::abort(&format!("Problem creating {:?}: {}", out_dir, e))
+    }
+    let wheel_path = out_dir.join(format!("{}-{}-{}.whl", dist_name(name), version, tag));
+    if let Err(e) = write_zip(&wheel_path, &files) {
+        util::abort(&format!("Problem writing {:?}: {}", wheel_path, e))
+    }
+    wheel_path
+}
+
+/// The project's name and version, which every distribution needs.
+pub(super) fn name_and_version(cfg: &Config) -> (&String, &Version) {
+    let name = cfg.name.as_ref().unwrap_or_else(|| {
+        util::abort("`pyproject.toml` needs a `name` under `[tool.pyflow]` to build the package")
+    });
+    let version = cfg.version.as_ref().unwrap_or_else(|| {
+        util::abort("`pyproject.toml` needs a `version` under `[tool.pyflow]` to build the package")
+    });
+    (name, version)
+}
+
+/// A name as it appears in wheel and `.dist-info` filenames: lowercase, with runs of `-`, `_`,
+/// and `.` replaced by `_`.
+pub(super) fn dist_name(name: &str) -> String {
+    NAME_SEPARATORS_RE
+        .replace_all(&name.to_lowercase(), "_")
+        .into_owned()
+}
+
+/// Extras' names, normalized per PEP 685.
+fn extra_name(name: &str) -> String {
+    NAME_SEPARATORS_RE
+        .replace_all(&name.to_lowercase(), "-")
+        .into_owned()
+}
+
+/// The paths the wheel would have, without building it, or running the `build` script.
+pub fn list_wheel(project_path: &Path, cfg: &Config) -> Vec<String> {
+    let (name, version) = name_and_version(cfg);
+    let dist_info = format!("{}-{}.dist-info", dist_name(name), version);
+    let mut result: Vec<String> = find_sources(project_path, cfg, name)
+        .into_iter()
+        .map(|(_, archive_path)| archive_path)
+        .collect();
+    result.push(format!("{}/METADATA", dist_info));
+    result.push(format!("{}/WHEEL", dist_info));
+    if !console_scripts(&cfg.scripts).is_empty() {
+        result.push(format!("{}/entry_points.txt", dist_info));
+    }
+    for rel in license_files(project_path) {
+        result.push(format!("{}/licenses/{}", dist_info, rel));
+    }
+    result.push(format!("{}/RECORD", dist_info));
+    result
+}
+
+/// Files to package, and their paths in the wheel, sorted so builds are reproducible: everything
+/// in the packages, other than what's excluded. Other files in the project, even ones named by
+/// `include`, are only for sdists.
+fn find_sources(project_path: &Path, cfg: &Config, name: &str) -> Vec<(PathBuf, String)> {
+    let files = FileSet::new(cfg);
+    let mut result = vec![];
+    for top in package_roots(project_path, cfg, name) {
+        let base = top.parent().unwrap().to_owned();
+        // Extension modules are excluded by default, but they mean the wheel can't be pure.
+        for path in files
+            .without_defaults()
+            .walk(project_path, &top, &project_path.join("dist"))
+        {
+            check_pure(&path);
+            if !files.is_excluded(&relative(project_path, &path)) {
+                result.push((path.clone(), relative(&base, &path)));
+            }
+        }
+    }
+    result.sort_by(|a, b| a.1.cmp(&b.1));
+    result
+}
+
+/// The top-level packages and modules to build. With `package_dir` set, or a `src` folder,
+/// everything in it; otherwise, the package or module named like the project.
+pub(super) fn package_roots(project_path: &Path, cfg: &Config, name: &str) -> Vec<PathBuf> {
+    let src = match &cfg.package_dir {
+        Some(dir) => Some(project_path.join(dir)),
+        None => Some(project_path.join("src")).filter(|p| p.is_dir()),
+    };
+
+    let mut tops = vec![];
+    match &src {
+        Some(src) => {
+            let entries = fs::read_dir(src)
+                .unwrap_or_else(|e| util::abort(&format!("Problem reading {:?}: {}", src, e)));
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if is_package(&path) || is_module(&path) {
+                    tops.push(path);
+                }
+            }
+        }
+        None => {
+            let import_name = name.replace(['-', '.'], "_");
+            let candidates = [
+                project_path.join(&import_name),
+                project_path.join(import_name.to_lowercase()),
+                project_path.join(format!("{}.py", import_name)),
+                project_path.join(format!("{}.py", import_name.to_lowercase())),
+            ];
+            if let Some(found) = candidates.iter().find(|p| is_package(p) || is_module(p)) {
+                tops.push(found.clone());
+            }
+        }
+    }
+    if tops.is_empty() {
+        util::abort(&format!(
+            "Can't find anything to package in {:?}. Put the code in a package named like the \
+             project, eg `{}/__init__.py`, or set `package_dir` under `[tool.pyflow]`, eg to \
+             `src`",
+            src.as_deref().unwrap_or(project_path),
+            name.replace(['-', '.'], "_")
+        ))
+    }
+
+    tops.sort();
+    tops
+}
+
+fn is_package(path: &Path) -> bool {
+    path.join("__init__.py").is_file()
+}
+
+fn is_module(path: &Path) -> bool {
+    path.is_file() && path.extension().is_some_and(|e| e == "py")
+}
+
+/// Abort on extension modules in the sources; they're only packaged from the `build` script's
+/// output, so the wheel is tagged for them.
+fn check_pure(path: &Path) {
+    if let Some("so") | Some("pyd") | Some("dylib") = path.extension().and_then(|e| e.to_str()) {
+        util::abort(&format!(
+            "{:?} is an extension module. Build it with a `build` script that writes it to \
+             `PYFLOW_BUILD_OUT`, not into the sources, so the wheel is tagged for the platform",
+            path
+        ))
+    }
+}
+
+/// The project's readme, used as the package's description.
+pub(super) struct Readme {
+    pub(super) path: PathBuf,
+    pub(super) contents: String,
+    pub(super) content_type: String,
+}
+
+/// The readme set by `readme`, or a `README` in the project root. Aborts if `readme` names a
+/// file that isn't there, or isn't UTF-8.
+pub(super) fn find_readme(project_path: &Path, cfg: &Config) -> Option<Readme> {
+    let path = match &cfg.readme {
+        Some(r) => project_path.join(r),
+        None => READMES
+            .iter()
+            .map(|r| project_path.join(r))
+            .find(|p| p.is_file())?,
+    };
+    let data = match fs::read(&path) {
+        Ok(d) => d,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => util::abort(&format!(
+            "`readme` in `pyproject.toml` is `{}`, but there's no such file, at {:?}",
+            cfg.readme.as_deref().unwrap_or_default(),
+            path
+        )),
+        Err(e) => util::abort(&format!("Problem reading the readme, {:?}: {}", path, e)),
+    };
+    let contents = String::from_utf8(data).unwrap_or_else(|_| {
+        util::abort(&format!(
+            "The readme, {:?}, isn't UTF-8, which package indexes require",
+            path
+        ))
+    });
+    Some(Readme {
+        content_type: readme_content_type(&path, cfg.readme_content_type.as_deref()),
+        // The same on every platform, whatever the checkout's line endings.
+        contents: contents.replace("\r\n", "\n"),
+        path,
+    })
+}
+
+/// The readme's `Description-Content-Type`: as set, or from its extension.
+pub(super) fn readme_content_type(path: &Path, explicit: Option<&str>) -> String {
+    if let Some(t) = explicit {
+        return t.to_owned();
+    }
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase);
+    match extension.as_deref() {
+        Some("md") | Some("markdown") => "text/markdown",
+        Some("rst") => "text/x-rst",
+        _ => "text/plain",
+    }
+    .to_owned()
+}
+
+/// A header field's value. Newlines would end the field early, so continuation lines are
+/// indented, as in email headers; `Summary` has to be one line, so it's joined with spaces.
+fn fold(key: &str, value: &str) -> String {
+    let lines: Vec<&str> = value.trim().lines().map(str::trim_end).collect();
+    match key {
+        "Summary" => lines
+            .iter()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty())
+            .collect::<Vec<_>>()
+            .join(" "),
+        _ => lines.join("\n        "),
+    }
+}
+
+/// The `METADATA` file, in core metadata format; also an sdist's `PKG-INFO`. It's version 2.4 if
+/// there's a license expression or license files, which need it, and 2.1 otherwise, which older
+/// tools understand. `license_files` are relative to the project root.
+/// https://packaging.python.org/specifications/core-metadata/
+pub(super) fn metadata(cfg: &Config, readme: Option<&Readme>, license_files: &[String]) -> String {
+    let expression = license::expression(cfg);
+    let metadata_version = if expression.is_some() || !license_files.is_empty() {
+        "2.4"
+    } else {
+        "2.1"
+    };
+    let mut fields: Vec<(&str, String)> = vec![
+        ("Metadata-Version", metadata_version.to_owned()),
+        ("Name", cfg.name.clone().unwrap_or_default()),
+        (
+            "Version",
+            cfg.version
+                .as_ref()
+                .map(|v| v.to_string())
+                .unwrap_or_default(),
+        ),
+    ];
+    let mut optional = |key, value: &Option<String>| {
+        if let Some(v) = value {
+            fields.push((key, v.clone()));
+        }
+    };
+    optional("Summary", &cfg.description);
+    optional("Home-page", &cfg.homepage);
+    optional("License-Expression", &expression);
+    optional("Requires-Python", &cfg.python_requires);
+    for file in license_files {
+        fields.push(("License-File", file.clone()));
+    }
+
+    // Authors with an email go in `Author-email`, as `Name <email>`; the rest in `Author`.
+    let (mut names, mut emails) = (vec![], vec![]);
+    for author in &cfg.authors {
+        match parse_author(author) {
+            (name, Some(email)) if name.is_empty() => emails.push(email),
+            (name, Some(email)) => emails.push(format!("{} <{}>", name, email)),
+            (name, None) => names.push(name),
+        }
+    }
+    if !names.is_empty() {
+        fields.push(("Author", names.join(", ")));
+    }
+    if !emails.is_empty() {
+        fields.push(("Author-email", emails.join(", ")));
+    }
+    if !cfg.keywords.is_empty() {
+        fields.push(("Keywords", cfg.keywords.join(",")));
+    }
+    for classifier in &cfg.classifiers {
+        fields.push(("Classifier", classifier.clone()));
+    }
+    if let Some(repo) = &cfg.repository {
+        fields.push(("Project-URL", format!("Repository, {}", repo)));
+    }
+
+    // Dependencies named in `[tool.pyflow.extras]` groups are only required with that extra.
+    let mut groups: Vec<(&String, &Vec<String>)> = cfg.extras.iter().collect();
+    groups.sort();
+    let in_group = |name: &str| {
+        groups
+            .iter()
+            .any(|(_, names)| names.iter().any(|n| util::compare_names(n, name)))
+    };
+    for req in cfg.reqs.iter().filter(|r| !in_group(&r.name)) {
+        fields.push(("Requires-Dist", req.to_pep508_string(None)));
+    }
+    for (group, names) in &groups {
+        let extra = extra_name(group);
+        fields.push(("Provides-Extra", extra.clone()));
+        for name in names.iter() {
+            let spec = match cfg.reqs.iter().find(|r| util::compare_names(&r.name, name)) {
+                Some(req) => req.to_pep508_string(Some(&extra)),
+                None => Req::new(name.clone(), vec![]).to_pep508_string(Some(&extra)),
+            };
+            fields.push(("Requires-Dist", spec));
+        }
+    }
+
+    if let Some(readme) = readme {
+        fields.push(("Description-Content-Type", readme.content_type.clone()));
+    }
+
+    let mut result: String = fields
+        .iter()
+        .map(|(k, v)| format!("{}: {}\n", k, fold(k, v)))
+        .collect();
+    if let Some(readme) = readme {
+        result.push('\n');
+        result.push_str(&readme.contents);
+    }
+    result
+}
+
+fn wheel_file(tag: &str, purelib: bool) -> String {
+    format!(
+        "Wheel-Version: 1.0\nGenerator: pyflow {}\nRoot-Is-Purelib: {}\nTag: {}\n",
+        env!("CARGO_PKG_VERSION"),
+        purelib,
+        tag
+    )
+}
+
+/// The `RECORD` file: each file's path, SHA256 hash, and size, in the order they're archived.
+/// `RECORD` itself has neither.
+fn record(files: &[Entry], record_path: &str) -> String {
+    let mut result = String::new();
+    for file in files {
+        let hash = ring::digest::digest(&ring::digest::SHA256, &file.data);
+        result.push_str(&format!(
+            "{},sha256={},{}\n",
+            csv_field(&file.path),
+            data_encoding::BASE64URL_NOPAD.encode(hash.as_ref()),
+            file.data.len()
+        ));
+    }
+    result.push_str(&format!("{},,\n", csv_field(record_path)));
+    result
+}
+
+/// Quote a CSV field if it needs it.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Write `files` in order, with their normalized permissions and timestamps.
+fn write_zip(path: &Path, files: &[Entry]) -> io::Result<()> {
+    let mut zip = ZipWriter::new(fs::File::create(path)?);
+    for file in files {
+        let options = FileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .last_modified_time(zip_time(file.mtime))
+            .unix_permissions(file.mode);
+        zip.start_file(file.path.as_str(), options)?;
+        zip.write_all(&file.data)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+/// A zip timestamp, in UTC, for seconds since the Unix epoch. Zip files can't be dated before
+/// 1980, or after 2107, so times outside those are clamped.
+fn zip_time(secs: u64) -> DateTime {
+    // Days to a civil date; https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    if year < 1980 {
+        return DateTime::default();
+    }
+    DateTime::from_date_and_time(
+        year.min(2107) as u16,
+        month as u8,
+        day as u8,
+        (rem / 3600) as u8,
+        (rem % 3600 / 60) as u8,
+        (rem % 60) as u8,
+    )
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dep_types::{Constraint, ReqType};
+    use crate::util::temp_dir::TempDir;
+    use std::{collections::HashMap, io::Read};
+
+    fn cfg() -> Config {
+        let mut scripts = HashMap::new();
+        scripts.insert("activate".into(), "everythingkiller.cli:activate".into());
+        scripts.insert("lint".into(), "ruff check .".into());
+        let mut uvloop = Req::new(
+            "uvloop".into(),
+            vec![Constraint::new(ReqType::Gte, Version::new_short(0, 17))],
+        );
+        uvloop.sys_platform = Some((ReqType::Ne, util::Os::Windows));
+
+        Config {
+            name: Some("everything-killer".into()),
+            version: Some(Version::new(0, 1, 0)),
+            authors: vec!["Fraa Erasmas <raz@edhar.math>".into(), "Fraa Jad".into()],
+            description: Some("Small, but packs a punch!".into()),
+            license: Some("MIT".into()),
+            keywords: vec!["nanotech".into(), "weapons".into()],
+            classifiers: vec!["Topic :: System :: Hardware".into()],
+            python_requires: Some(">=3.8".into()),
+            repository: Some("https://github.com/raz/everythingkiller".into()),
+            scripts,
+            reqs: vec![
+                Req::new(
+                    "numpy".into(),
+                    vec![Constraint::new(ReqType::Caret, Version::new(1, 16, 4))],
+                ),
+                uvloop,
+            ],
+            extras: HashMap::from([("Fast".to_owned(), vec!["uvloop".to_owned()])]),
+            ..Default::default()
+        }
+    }
+
+    fn read(archive: &mut zip::ZipArchive<fs::File>, name: &str) -> String {
+        let mut result = String::new();
+        archive
+            .by_name(name)
+            .unwrap()
+            .read_to_string(&mut result)
+            .unwrap();
+        result
+    }
+
+    #[test]
+    fn builds_wheel() {
+        let project = TempDir::new("wheel-build");
+        let package = project.join("src/everything_killer");
+        fs::create_dir_all(package.join("__pycache__")).unwrap();
+        fs::write(package.join("__init__.py"), "__version__ = \"0.1.0\"\n").unwrap();
+        fs::write(package.join("cli.py"), "def activate():\n    pass\n").unwrap();
+        fs::write(package.join("__pycache__/cli.cpython-38.pyc"), b"\0").unwrap();
+        fs::write(project.join("src/helpers.py"), "").unwrap();
+        fs::write(project.join("README.md"), "# Everything killer\n").unwrap();
+        fs::write(project.join("LICENSE"), "MIT License\n").unwrap();
+
+        let wheel = build_wheel(&project, &cfg(), &project.join("dist"), None);
+        assert_eq!(
+            wheel.file_name().unwrap(),
+            "everything_killer-0.1.0-py3-none-any.whl"
+        );
+
+        let mut archive = zip::ZipArchive::new(fs::File::open(&wheel).unwrap()).unwrap();
+        let names: Vec<String> = (0..archive.len())
+            .map(|i| archive.by_index(i).unwrap().name().to_owned())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "everything_killer/__init__.py",
+                "everything_killer/cli.py",
+                "helpers.py",
+                "everything_killer-0.1.0.dist-info/METADATA",
+                "everything_killer-0.1.0.dist-info/WHEEL",
+                "everything_killer-0.1.0.dist-info/entry_points.txt",
+                "everything_killer-0.1.0.dist-info/licenses/LICENSE",
+                "everything_killer-0.1.0.dist-info/RECORD",
+            ]
+        );
+
+        assert_eq!(
+            read(&mut archive, "everything_killer-0.1.0.dist-info/METADATA"),
+            "Metadata-Version: 2.4\n\
+             Name: everything-killer\n\
+             Version: 0.1.0\n\
+             Summary: Small, but packs a punch!\n\
+             License-Expression: MIT\n\
+             Requires-Python: >=3.8\n\
+             License-File: LICENSE\n\
+             Author: Fraa Jad\n\
+             Author-email: Fraa Erasmas <raz@edhar.math>\n\
+             Keywords: nanotech,weapons\n\
+             Classifier: Topic :: System :: Hardware\n\
+             Project-URL: Repository, https://github.com/raz/everythingkiller\n\
+             Requires-Dist: numpy (>=1.16.4,<2.0.0)\n\
+             Provides-Extra: fast\n\
+             Requires-Dist: uvloop (>=0.17) ; sys_platform != \"win32\" and extra == \"fast\"\n\
+             Description-Content-Type: text/markdown\n\
+             \n\
+             # Everything killer\n"
+        );
+        assert!(
+            read(&mut archive, "everything_killer-0.1.0.dist-info/WHEEL")
+                .contains("Root-Is-Purelib: true\nTag: py3-none-any\n")
+        );
+        assert_eq!(
+            read(
+                &mut archive,
+                "everything_killer-0.1.0.dist-info/entry_points.txt"
+            ),
+            "[console_scripts]\nactivate = everythingkiller.cli:activate\n"
+        );
+
+        let record = read(&mut archive, "everything_killer-0.1.0.dist-info/RECORD");
+        let cli = "def activate():\n    pass\n";
+        let hash = ring::digest::digest(&ring::digest::SHA256, cli.as_bytes());
+        assert!(record.contains(&format!(
+            "everything_killer/cli.py,sha256={},{}\n",
+            data_encoding::BASE64URL_NOPAD.encode(hash.as_ref()),
+            cli.len()
+        )));
+        assert!(record.ends_with("everything_killer-0.1.0.dist-info/RECORD,,\n"));
+        assert_eq!(record.lines().count(), names.len());
+    }
+
+    #[test]
+    fn flat_layout() {
+        let project = TempDir::new("wheel-flat");
+        fs::create_dir_all(project.join("everything_killer")).unwrap();
+        fs::write(project.join("everything_killer/__init__.py"), "").unwrap();
+        fs::create_dir_all(project.join("tests")).unwrap();
+        fs::write(project.join("tests/__init__.py"), "").unwrap();
+
+        let sources = find_sources(&project, &cfg(), "everything-killer");
+        let paths: Vec<&str> = sources.iter().map(|(_, p)| p.as_str()).collect();
+        assert_eq!(paths, vec!["everything_killer/__init__.py"]);
+    }
+
+    #[test]
+    fn package_data() {
+        let project = TempDir::new("wheel-data");
+        let files = [
+            "everything_killer/__init__.py",
+            "everything_killer/py.typed",
+            "everything_killer/templates/page.html",
+            "everything_killer/tests/fixtures/big.json",
+            "everything_killer/.schema.json",
+            "everything_killer/.cache/x",
+            "docs/index.md",
+        ];
+        for file in &files {
+            let path = project.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, "").unwrap();
+        }
+        let cfg = Config {
+            include: vec!["everything_killer/.schema.json".into(), "docs/**".into()],
+            exclude: vec!["**/fixtures/**".into()],
+            ..cfg()
+        };
+
+        let sources = find_sources(&project, &cfg, "everything-killer");
+        let paths: Vec<&str> = sources.iter().map(|(_, p)| p.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "everything_killer/.schema.json",
+                "everything_killer/__init__.py",
+                "everything_killer/py.typed",
+                "everything_killer/templates/page.html",
+            ]
+        );
+    }
+
+    /// What pip reads from `METADATA` should be what pyflow installs from `pyproject.toml`.
+    #[test]
+    fn requires_dist_round_trip() {
+        let mut cfg = cfg();
+        let mut pywin32 = Req::new(
+            "pywin32".into(),
+            vec![Constraint::new(
+                ReqType::Gte,
+                Version::new_opt(Some(300), None, None),
+            )],
+        );
+        pywin32.sys_platform = Some((ReqType::Exact, util::Os::Windows32));
+        let mut uvicorn = Req::new(
+            "uvicorn".into(),
+            vec![
+                Constraint::new(ReqType::Gte, Version::new_short(0, 23)),
+                Constraint::new(ReqType::Ne, Version::new(0, 23, 1)),
+            ],
+        );
+        uvicorn.install_with_extras = Some(vec!["standard".into()]);
+        uvicorn.python_version = Some(vec![Constraint::new(
+            ReqType::Caret,
+            Version::new_short(3, 8),
+        )]);
+        cfg.reqs
+            .extend([pywin32, uvicorn, Req::new("rich".into(), vec![])]);
+        cfg.extras
+            .insert("cli".into(), vec!["rich".into(), "uvicorn".into()]);
+
+        let parsed = util::parse_metadata_str(&metadata(&cfg, None, &[]));
+        assert_eq!(parsed.provides_extra, vec!["fast", "cli"]);
+        assert_eq!(parsed.requires_dist.len(), cfg.reqs.len());
+        for req in &cfg.reqs {
+            let extra = cfg
+                .extras
+                .iter()
+                .find(|(_, names)| names.contains(&req.name))
+                .map(|(group, _)| extra_name(group));
+            let back = parsed
+                .requires_dist
+                .iter()
+                .find(|r| r.name == req.name)
+                .unwrap();
+            assert_eq!(back.extra, extra);
+            assert_eq!(back.install_with_extras, req.install_with_extras);
+            assert_eq!(
+                back.sys_platform.map(|(type_, _)| type_),
+                req.sys_platform.map(|(type_, _)| type_)
+            );
+            assert_eq!(
+                back.to_pep508_string(extra.as_deref()),
+                req.to_pep508_string(extra.as_deref())
+            );
+        }
+    }
+
+    #[test]
+    fn readme_fields() {
+        assert_eq!(
+            readme_content_type(Path::new("README.rst"), None),
+            "text/x-rst"
+        );
+        assert_eq!(
+            readme_content_type(Path::new("docs/Intro.MD"), None),
+            "text/markdown"
+        );
+        assert_eq!(
+            readme_content_type(Path::new("README.txt"), Some("text/markdown; variant=GFM")),
+            "text/markdown; variant=GFM"
+        );
+        assert_eq!(readme_content_type(Path::new("README"), None), "text/plain");
+
+        assert_eq!(
+            fold("Summary", "Small,\n  but packs a punch!\n"),
+            "Small, but packs a punch!"
+        );
+        assert_eq!(
+            fold("License", "MIT\n\nCopyright 2024"),
+            "MIT\n        \n        Copyright 2024"
+        );
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(dist_name("Everything.Killer--2"), "everything_killer_2");
+        assert_eq!(extra_name("Fast_IO"), "fast-io");
+        assert_eq!(csv_field("a,b.py"), "\"a,b.py\"");
+    }
+
+    #[test]
+    fn reproducible() {
+        let project = TempDir::new("wheel-reproducible");
+        let package = project.join("everything_killer");
+        fs::create_dir_all(&package).unwrap();
+        fs::write(project.join("pyproject.toml"), "").unwrap();
+        fs::write(package.join("__init__.py"), "").unwrap();
+        fs::write(package.join("cli.py"), "def activate():\n    pass\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let run = package.join("run.py");
+            fs::write(&run, "#!/usr/bin/env python\n").unwrap();
+            fs::set_permissions(&run, fs::Permissions::from_mode(0o700)).unwrap();
+        }
+
+        let epoch = 1_600_000_000;
+        let first = write_wheel(&project, &cfg(), &project.join("first"), None, Some(epoch));
+        // Newer, with the same contents.
+        fs::write(package.join("cli.py"), "def activate():\n    pass\n").unwrap();
+        let second = write_wheel(&project, &cfg(), &project.join("second"), None, Some(epoch));
+        let digest = |path: &Path| {
+            let hash = ring::digest::digest(&ring::digest::SHA256, &fs::read(path).unwrap());
+            data_encoding::HEXLOWER.encode(hash.as_ref())
+        };
+        assert_eq!(digest(&first), digest(&second));
+
+        let mut archive = zip::ZipArchive::new(fs::File::open(&first).unwrap()).unwrap();
+        let cli = archive.by_name("everything_killer/cli.py").unwrap();
+        assert_eq!(cli.unix_mode().map(|m| m & 0o777), Some(0o644));
+        assert_eq!(cli.last_modified().year(), 2020);
+        drop(cli);
+        #[cfg(unix)]
+        assert_eq!(
+            archive
+                .by_name("everything_killer/run.py")
+                .unwrap()
+                .unix_mode()
+                .map(|m| m & 0o777),
+            Some(0o755)
+        );
+    }
+
+    #[test]
+    fn zip_times() {
+        let t = zip_time(1_600_000_000);
+        assert_eq!(
+            (
+                t.year(),
+                t.month(),
+                t.day(),
+                t.hour(),
+                t.minute(),
+                t.second()
+            ),
+            (2020, 9, 13, 12, 26, 40)
+        );
+        let t = zip_time(0);
+        assert_eq!((t.year(), t.month(), t.day()), (1980, 1, 1));
+        let t = zip_time(951_782_400);
+        assert_eq!((t.year(), t.month(), t.day()), (2000, 2, 29));
+    }
+}
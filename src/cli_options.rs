@@ -1,6 +1,9 @@
-use std::str::FromStr;
+use std::{fmt, path::PathBuf, str::FromStr};
 
-use structopt::StructOpt;
+use structopt::{
+    clap::{AppSettings, Shell},
+    StructOpt,
+};
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "pyflow", about = "Python packaging and publishing")]
@@ -8,9 +11,37 @@ pub struct Opt {
     #[structopt(subcommand)]
     pub subcmds: SubCommand,
 
-    /// Force a color option: auto (default), always, ansi, never
-    #[structopt(short, long)]
+    /// Force a color option: auto (default), always, ansi, never. `auto` colors output only
+    /// when it's a terminal, and `NO_COLOR` isn't set
+    #[structopt(short, long, possible_values = &["auto", "always", "ansi", "never"])]
     pub color: Option<String>,
+
+    /// Don't run `pre-install` or `post-install` hooks from `pyproject.toml`
+    #[structopt(long)]
+    pub no_hooks: bool,
+
+    /// Never prompt: take each prompt's default, or fail with an error explaining how to answer
+    /// it up front. Can also be set with `PYFLOW_NON_INTERACTIVE=1`
+    #[structopt(short = "y", long)]
+    pub non_interactive: bool,
+
+    /// How packages get from the cache into `__pypackages__`: `copy` extracts each one, `hardlink`
+    /// links files from an extracted copy in the cache, and `auto` (default) links where it can
+    #[structopt(long, possible_values = crate::install::LinkMode::NAMES)]
+    pub link_mode: Option<String>,
+
+    /// Show more detail: `-v` for debug messages and timings, `-vv` for everything. Use
+    /// `PYFLOW_LOG`, eg `PYFLOW_LOG=pyflow::dep_resolution=trace`, to pick modules
+    #[structopt(short, long, parse(from_occurrences))]
+    pub verbose: u8,
+
+    /// Only show warnings and errors
+    #[structopt(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Run in this workspace member, by project or folder name, from anywhere in the workspace
+    #[structopt(short, long)]
+    pub package: Option<String>,
 }
 
 #[derive(StructOpt, Debug)]
@@ -20,9 +51,23 @@ pub enum SubCommand {
     New {
         #[structopt(name = "name")]
         name: String, // holds the project name.
+        /// A library: a `src/<name>` package with a `py.typed` marker, and tests
+        #[structopt(long, conflicts_with_all = &["app", "minimal"])]
+        lib: bool,
+        /// An application: a package with a `__main__.py`, and a script that runs it
+        #[structopt(long, conflicts_with = "minimal")]
+        app: bool,
+        /// Only create `pyproject.toml`
+        #[structopt(long)]
+        minimal: bool,
+        /// The project's license, as an SPDX expression, eg `MIT`, or `MIT OR Apache-2.0`
+        #[structopt(long)]
+        license: Option<String>,
     },
 
-    /// Add packages to `pyproject.toml` and sync an environment
+    /// Add packages to `pyproject.toml` and sync an environment. Takes PEP 508 specs, eg
+    /// `"uvicorn[standard]>=0.23"`, or `"mylib @ git+https://github.com/me/mylib@v1.2"`. A package
+    /// that's already there has its entry updated.
     #[structopt(name = "add")]
     Add {
         #[structopt(name = "packages")]
@@ -30,6 +75,15 @@ pub enum SubCommand {
         /// Save package to your dev-dependencies section
         #[structopt(short, long)]
         dev: bool,
+        /// Don't install dev dependencies, or packages only required by them. Can also be set
+        /// with `PYFLOW_NO_DEV=1`
+        #[structopt(long)]
+        no_dev: bool,
+        #[structopt(flatten)]
+        source: AddSource,
+        /// Output a summary of packages added, removed, and upgraded as JSON
+        #[structopt(long)]
+        json: bool,
     },
 
     /** Install packages from `pyproject.toml`, `pyflow.lock`, or specified ones. Example:
@@ -43,25 +97,270 @@ pub enum SubCommand {
         /// Save package to your dev-dependencies section
         #[structopt(short, long)]
         dev: bool,
+        /// Don't install dev dependencies, or packages only required by them. Can also be set
+        /// with `PYFLOW_NO_DEV=1`
+        #[structopt(long)]
+        no_dev: bool,
+        /// Constrain versions to the pins in this pip constraints file, as with `pip install -c`,
+        /// in addition to any in `constraint-files`
+        #[structopt(short, long = "constraint", parse(from_os_str))]
+        constraint: Vec<PathBuf>,
+        /// Wipe and re-extract these packages from the lock, eg if their files are corrupted
+        #[structopt(long, alias = "force-reinstall")]
+        reinstall: Vec<String>,
+        /// Wipe and re-extract all locked packages
+        #[structopt(long, alias = "force-reinstall-all")]
+        reinstall_all: bool,
+        /// Output a summary of packages added, removed, and upgraded as JSON
+        #[structopt(long, conflicts_with_all = &["reinstall", "reinstall-all"])]
+        json: bool,
     },
-    /// Uninstall all packages, or ones specified
-    #[structopt(name = "uninstall")]
-    Uninstall {
-        #[structopt(name = "packages")]
+    /// Remove dependencies from `pyproject.toml`, the lock file, and the environment
+    #[structopt(name = "remove", alias = "uninstall")]
+    Remove {
+        #[structopt(name = "packages", required = true)]
         packages: Vec<String>,
+        /// Remove from `dev-dependencies`, where a package is declared in several places
+        #[structopt(short, long)]
+        dev: bool,
+        /// Remove from this group under `[tool.pyflow.extras]`
+        #[structopt(long)]
+        group: Option<String>,
+        /// Output a summary of packages added, removed, and upgraded as JSON
+        #[structopt(long)]
+        json: bool,
     },
     /// Display all installed packages and console scripts
     #[structopt(name = "list")]
-    List,
+    List {
+        /// Show locked packages that have newer releases on the index
+        #[structopt(long)]
+        outdated: bool,
+        /// Output results as JSON
+        #[structopt(long)]
+        json: bool,
+        /// Exit with 1 if any packages are outdated, eg to fail a CI job
+        #[structopt(long, requires = "outdated")]
+        exit_code: bool,
+        /// Only show updates that keep the major version (`minor`), or the major and minor
+        /// versions (`patch`)
+        #[structopt(long, requires = "outdated", possible_values = Within::NAMES)]
+        within: Option<Within>,
+    },
+    /// Install exactly the packages pinned in a requirements file, eg from `pip-compile`, instead
+    /// of resolving `pyproject.toml`
+    #[structopt(name = "sync")]
+    Sync {
+        /// The fully-pinned requirements file, with `==` versions, and ideally `--hash`es
+        #[structopt(long, parse(from_os_str))]
+        from_requirements: PathBuf,
+        /// Save the pinned packages as `pyflow.lock`
+        #[structopt(long)]
+        write_lock: bool,
+        /// Fail if any package has no `--hash`, instead of installing it unchecked
+        #[structopt(long)]
+        require_hashes: bool,
+        /// Output a summary of packages added, removed, and upgraded as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Upgrade locked packages to the newest releases `pyproject.toml` allows, eg
+    /// `pyflow update --within patch`. Updates every package if none are named
+    #[structopt(name = "update")]
+    Update {
+        #[structopt(name = "packages")]
+        packages: Vec<String>,
+        /// Only apply updates that keep the major version (`minor`), or the major and minor
+        /// versions (`patch`)
+        #[structopt(long, possible_values = Within::NAMES)]
+        within: Option<Within>,
+        /// Output a summary of packages added, removed, and upgraded as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+    /// List what can be run with `pyflow <name>`: scripts declared in `pyproject.toml`, and
+    /// console scripts installed by packages
+    #[structopt(name = "scripts")]
+    Scripts {
+        /// Output results as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Check installed packages against the hashes recorded in their `RECORD` files
+    #[structopt(name = "verify")]
+    Verify {
+        /// Output results as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+    /// List the license of every installed package, grouped by license
+    #[structopt(name = "licenses")]
+    Licenses {
+        /// Output results as JSON
+        #[structopt(long)]
+        json: bool,
+        /// Exit with 1 if any package uses one of these licenses, eg
+        /// `--fail-on GPL-3.0-only,AGPL-3.0-only`
+        #[structopt(long, use_delimiter = true)]
+        fail_on: Vec<String>,
+    },
+    /// Check that the project, its lock, and its environment agree. Exits with 1 on any error
+    #[structopt(name = "check")]
+    Check {
+        /// Skip a check, by its code, eg `--skip git-deps`. Repeat, or separate with commas
+        #[structopt(long, number_of_values = 1, use_delimiter = true)]
+        skip: Vec<String>,
+        /// Run only these checks, eg `--only pyproject,lock`
+        #[structopt(
+            long,
+            number_of_values = 1,
+            use_delimiter = true,
+            conflicts_with = "skip"
+        )]
+        only: Vec<String>,
+        /// Output findings as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Diagnose problems with this machine's setup: missing interpreters, folders that can't be
+    /// written to, an unreachable package index, an old lock, and shadowed commands. Exits with
+    /// 1 if any would stop pyflow from working
+    #[structopt(name = "doctor")]
+    Doctor {
+        /// Skip a probe, eg `--skip network`. Repeat, or separate with commas
+        #[structopt(long, number_of_values = 1, use_delimiter = true)]
+        skip: Vec<String>,
+        /// Seconds to give each probe before giving up on it
+        #[structopt(long, default_value = "10")]
+        timeout: u64,
+        /// Output results as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Write the locked dependencies for other tools: as `requirements.txt`, with or without
+    /// hashes, as `pylock.toml`, or as a JSON dependency graph. `--format poetry` writes the
+    /// project's config as `[tool.poetry]` instead
+    #[structopt(name = "export")]
+    Export {
+        #[structopt(long, default_value = "requirements", possible_values = ExportFormat::NAMES)]
+        format: ExportFormat,
+        /// Include dev dependencies, even if `PYFLOW_NO_DEV` is set. They're included by default
+        #[structopt(long, conflicts_with = "no-dev")]
+        dev: bool,
+        /// Leave out dev dependencies, and packages only required by them
+        #[structopt(long)]
+        no_dev: bool,
+        /// Include these groups from `[tool.pyflow.extras]`, eg `--extras test,docs`
+        #[structopt(long, number_of_values = 1, use_delimiter = true)]
+        extras: Vec<String>,
+        /// Write to this file, instead of stdout
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// With `--format poetry`, add the tables to `pyproject.toml`, replacing any Poetry ones
+        #[structopt(long, conflicts_with = "output")]
+        write: bool,
+    },
+    /// Check the locked packages for known vulnerabilities, in the OSV database. Exits with 1 if
+    /// any are found
+    #[structopt(name = "audit")]
+    Audit {
+        /// Only fail for vulnerabilities at least this severe. Those without a severity always
+        /// count
+        #[structopt(long, default_value = "low", possible_values = AuditSeverity::NAMES)]
+        severity: AuditSeverity,
+        /// Advisories to accept, by ID or alias, eg `--ignore GHSA-j8r2-6x86-q33q`
+        #[structopt(long, use_delimiter = true)]
+        ignore: Vec<String>,
+        /// Use results cached by the last audit, instead of querying OSV
+        #[structopt(long)]
+        offline: bool,
+        /// Output results as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Write a multi-stage Dockerfile that installs the locked dependencies, without dev ones, and
+    /// runs a script as a non-root user
+    #[structopt(name = "dockerfile")]
+    Dockerfile {
+        /// The `python` base image's tag. Defaults to the slim image for `py_version`, eg
+        /// `3.11-slim`
+        #[structopt(long)]
+        python: Option<String>,
+        /// The script the container runs: from `[tool.pyflow.scripts]`, or a console script.
+        /// Defaults to the only script in `pyproject.toml`, if there's one
+        #[structopt(long)]
+        entry: Option<String>,
+        /// Write to this file, instead of stdout
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// Overwrite the `--output` file if it exists
+        #[structopt(long, requires = "output")]
+        force: bool,
+    },
+    /// Build a wheel directly from `pyproject.toml`, without setuptools. Pure-Python packages only
+    #[structopt(name = "build")]
+    Build {
+        /// Build a source distribution, as a `.tar.gz`
+        #[structopt(long)]
+        sdist: bool,
+        /// Build a wheel. This is the default, unless `--sdist` is passed
+        #[structopt(long)]
+        wheel: bool,
+        /// Where to write what's built. Defaults to `dist`, in the project
+        #[structopt(short, long, parse(from_os_str))]
+        out_dir: Option<PathBuf>,
+        /// Print the files each distribution would have, without building it
+        #[structopt(long)]
+        list_files: bool,
+        /// Don't check classifiers against PyPI's list, eg for a private index with its own
+        #[structopt(long)]
+        no_verify_classifiers: bool,
+        /// Date every file 1980-01-01 if `SOURCE_DATE_EPOCH` isn't set, so the same sources
+        /// always build the same files
+        #[structopt(long)]
+        reproducible: bool,
+    },
     /// Build the package - source and wheel
     #[structopt(name = "package")]
     Package {
         #[structopt(name = "extras")]
         extras: Vec<String>,
     },
-    /// Publish to `pypi`
+    /// Upload the built wheel and sdist to PyPI, or another index
     #[structopt(name = "publish")]
-    Publish,
+    Publish {
+        /// Files to upload. Defaults to the wheels and sdist in `dist` for the current version
+        #[structopt(parse(from_os_str))]
+        files: Vec<PathBuf>,
+        /// An index to upload to by name: `pypi`, `testpypi`, or one from the global config
+        #[structopt(long, conflicts_with = "repository-url")]
+        repository: Option<String>,
+        /// The index's upload URL. Defaults to `package_url` in `pyproject.toml`
+        #[structopt(long)]
+        repository_url: Option<String>,
+        /// Skip files the index already has, instead of failing
+        #[structopt(long)]
+        skip_existing: bool,
+        /// Check everything, and show what would be uploaded, without uploading it
+        #[structopt(long)]
+        dry_run: bool,
+        /// Only check the files: that they're intact, their metadata, that the description will
+        /// render, and that the index doesn't already have them
+        #[structopt(long, conflicts_with = "dry-run")]
+        check: bool,
+        /// With `--check`, print what's found as JSON, eg for CI
+        #[structopt(long, requires = "check")]
+        json: bool,
+        /// Don't check classifiers against PyPI's list, eg for a private index with its own
+        #[structopt(long)]
+        no_verify_classifiers: bool,
+        /// Upload dev versions, and ones built from uncommitted changes, eg to a test index
+        #[structopt(long)]
+        allow_dev: bool,
+        /// Upload what's already in `dist`, instead of building the sdist and wheel first
+        #[structopt(long)]
+        no_build: bool,
+    },
     /// Create a `pyproject.toml` from requirements.txt, pipfile etc, setup.py etc
     #[structopt(name = "init")]
     Init,
@@ -71,6 +370,62 @@ pub enum SubCommand {
     /// Remove cached packages, Python installs, or script-environments. Eg to free up hard drive space.
     #[structopt(name = "clear")]
     Clear,
+    /// Show or change per-user defaults, in pyflow's global config file
+    #[structopt(name = "config")]
+    Config {
+        #[structopt(subcommand)]
+        cmd: ConfigCommand,
+    },
+    /// Print the project's version, or change it, eg `pyflow version bump minor`
+    #[structopt(name = "version")]
+    Version {
+        #[structopt(subcommand)]
+        cmd: Option<VersionCommand>,
+    },
+    /// Manage pyflow itself
+    #[structopt(name = "self")]
+    SelfCmd {
+        #[structopt(subcommand)]
+        cmd: SelfCommand,
+    },
+    /// Run `pyflow check` before each commit, from pre-commit, or a plain git hook
+    #[structopt(name = "hooks")]
+    Hooks {
+        #[structopt(subcommand)]
+        cmd: HooksCommand,
+    },
+    /// Inspect downloaded packages, index metadata, and interpreters
+    #[structopt(name = "cache")]
+    Cache {
+        #[structopt(subcommand)]
+        cmd: CacheCommand,
+    },
+    /// Remove caches, script environments, downloaded interpreters, or this project's
+    /// `__pypackages__`, and report the space freed
+    #[structopt(name = "clean")]
+    Clean {
+        /// Downloaded packages
+        #[structopt(long)]
+        cache: bool,
+        /// Metadata fetched from the package index, like the name list `search` uses
+        #[structopt(long)]
+        metadata: bool,
+        /// Environments for standalone scripts
+        #[structopt(long)]
+        script_envs: bool,
+        /// This project's `__pypackages__` folder
+        #[structopt(long)]
+        pypackages: bool,
+        /// Only environments in `__pypackages__` for Python versions the project no longer uses
+        #[structopt(long)]
+        pypackages_stale: bool,
+        /// Python interpreters pyflow downloaded, after confirming
+        #[structopt(long)]
+        pythons: bool,
+        /// All of the above
+        #[structopt(long)]
+        all: bool,
+    },
     /// Run a CLI script like `ipython` or `black`. Note that you can simply run `pyflow black`
     /// as a shortcut.
     // Dummy option with space at the end for documentation
@@ -78,7 +433,9 @@ pub enum SubCommand {
     Run,
 
     /// Run the project python or script with the project python environment.
-    /// As a shortcut you can simply specify a script name ending in `.py`
+    /// As a shortcut you can simply specify a script name ending in `.py`. `pyflow python list`
+    /// shows the interpreters found, and `pyflow python install <archive>` installs a
+    /// python-build-standalone archive without downloading it
     // Dummy option with space at the end for documentation
     #[structopt(name = "python ")]
     Python,
@@ -87,6 +444,89 @@ pub enum SubCommand {
     // Dummy option with space at the end for documentation
     #[structopt(name = "script ")]
     Script,
+    /// Manage the environments cached for standalone scripts. Scripts with the same
+    /// requirements and Python version share one.
+    #[structopt(name = "script-envs")]
+    ScriptEnvs {
+        #[structopt(subcommand)]
+        cmd: ScriptEnvsCommand,
+    },
+    /// Inspect the project's environment
+    #[structopt(name = "env")]
+    Env {
+        #[structopt(subcommand)]
+        cmd: EnvCommand,
+    },
+    /// Point VS Code, or PyCharm, at the project's interpreter and packages, merging with
+    /// existing settings. Run it again after `pyflow switch`
+    #[structopt(name = "ide")]
+    Ide {
+        #[structopt(default_value = "vscode", possible_values = IdeKind::NAMES)]
+        editor: IdeKind,
+        /// Configure PyCharm; the same as `pyflow ide pycharm`
+        #[structopt(long)]
+        pycharm: bool,
+        /// Print the settings, instead of writing them
+        #[structopt(long = "print")]
+        print: bool,
+    },
+    /// Show where `pyflow run <name>` looks for a program, and which one it runs
+    #[structopt(name = "which")]
+    Which {
+        #[structopt(name = "name")]
+        name: String,
+        /// Only print what would run
+        #[structopt(short, long)]
+        quiet: bool,
+    },
+    /// Start an interactive Python, with the project's packages importable
+    #[structopt(name = "shell")]
+    Repl {
+        /// Use IPython, if it's installed in the environment
+        #[structopt(long)]
+        ipython: bool,
+    },
+    /// Print a completion script for bash, zsh, fish, powershell, or elvish. Eg
+    /// `pyflow completions bash > /etc/bash_completion.d/pyflow`
+    #[structopt(name = "completions")]
+    Completions {
+        #[structopt(possible_values = &Shell::variants(), case_insensitive = true)]
+        shell: Shell,
+    },
+    /// Search the package index by name
+    #[structopt(name = "search")]
+    Search {
+        #[structopt(name = "term")]
+        term: String,
+        /// How many matches to show
+        #[structopt(long, default_value = "10")]
+        limit: usize,
+        /// Output results as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Show a package's metadata, versions, and dependencies, from the index, or from a local
+    /// `.dist-info` folder or wheel
+    #[structopt(name = "info")]
+    Info {
+        #[structopt(name = "package")]
+        package: String,
+        /// Show this release instead of the latest
+        #[structopt(long)]
+        version: Option<String>,
+        /// Output results as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Refresh the cached list of package names `search` matches against
+    #[structopt(name = "refresh-index", setting = AppSettings::Hidden)]
+    RefreshIndex,
+    /// Print package or script names from `pyproject.toml`, for completion scripts
+    #[structopt(name = "complete-names", setting = AppSettings::Hidden)]
+    Complete {
+        #[structopt(possible_values = &["packages", "scripts"])]
+        kind: String,
+    },
     //    /// Run a package globally; used for CLI tools like `ipython` and `black`. Doesn't
     //    /// interfere Python installations. Must have been installed with `pyflow install -g black` etc
     //    #[structopt(name = "global")]
@@ -94,12 +534,16 @@ pub enum SubCommand {
     //        #[structopt(name = "name")]
     //        name: String,
     //    },
-    /// Change the Python version for this project. eg `pyflow switch 3.8`. Equivalent to setting
-    /// `py_version` in `pyproject.toml`.
+    /// Change the Python version for this project. eg `pyflow switch 3.8`, `pyflow switch 3.11.8`,
+    /// or `pyflow switch --latest 3.11`. Equivalent to setting `py_version` in `pyproject.toml`.
+    /// With no version, list the Python interpreters found on this system.
     #[structopt(name = "switch")]
     Switch {
         #[structopt(name = "version")]
-        version: String,
+        version: Option<String>,
+        /// Use the newest installed or available patch of this version
+        #[structopt(long)]
+        latest: bool,
     },
     // Documentation for supported external subcommands can be documented by
     // adding a `dummy` subcommand with the name having a trailing space.
@@ -108,6 +552,396 @@ pub enum SubCommand {
     External(Vec<String>),
 }
 
+impl SubCommand {
+    /// Whether this command prints a JSON document on stdout, so anything meant for people
+    /// goes to stderr instead.
+    pub fn json(&self) -> bool {
+        match self {
+            Self::Add { json, .. }
+            | Self::Install { json, .. }
+            | Self::Remove { json, .. }
+            | Self::Update { json, .. }
+            | Self::Sync { json, .. }
+            | Self::List { json, .. }
+            | Self::Scripts { json }
+            | Self::Verify { json }
+            | Self::Licenses { json, .. }
+            | Self::Audit { json, .. }
+            | Self::Check { json, .. }
+            | Self::Doctor { json, .. }
+            | Self::Search { json, .. }
+            | Self::Info { json, .. } => *json,
+            // Any format written to stdout is for another program.
+            Self::Export { output, write, .. } => output.is_none() && !write,
+            Self::Dockerfile { output, .. } => output.is_none(),
+            Self::Ide { print, .. } => *print,
+            Self::Env { cmd } => match cmd {
+                EnvCommand::Info { json } | EnvCommand::Path { json } => *json,
+                // Output is for `eval`.
+                EnvCommand::Activate { .. } | EnvCommand::Deactivate { .. } => true,
+                EnvCommand::Create { .. } => false,
+            },
+            Self::Cache { cmd } => match cmd {
+                CacheCommand::Dir { json }
+                | CacheCommand::List { json, .. }
+                | CacheCommand::Size { json } => *json,
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Options for `pyflow add`. `--git` and `--path` apply to a single package.
+#[derive(StructOpt, Debug, Default)]
+pub struct AddSource {
+    /// Extras to install, eg `--extras socks,security`; the same as `requests[socks,security]`
+    #[structopt(long, use_delimiter = true)]
+    pub extras: Vec<String>,
+    /// Install the package from this git repo
+    #[structopt(long, conflicts_with = "path")]
+    pub git: Option<String>,
+    /// The commit to check out of the `--git` repo
+    #[structopt(long, requires = "git", conflicts_with_all = &["tag", "branch"])]
+    pub rev: Option<String>,
+    /// The tag to check out of the `--git` repo
+    #[structopt(long, requires = "git", conflicts_with = "branch")]
+    pub tag: Option<String>,
+    /// The branch to check out of the `--git` repo
+    #[structopt(long, requires = "git")]
+    pub branch: Option<String>,
+    /// Use the package in this directory, eg a local checkout
+    #[structopt(long)]
+    pub path: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum CacheCommand {
+    /// Print where downloads are cached; set `cache-dir` with `pyflow config` to change it
+    #[structopt(name = "dir")]
+    Dir {
+        /// Output results as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+    /// List cached packages, index metadata, and interpreters, with sizes and last use
+    #[structopt(name = "list")]
+    List {
+        /// Only show files for this package
+        #[structopt(long)]
+        package: Option<String>,
+        /// Output results as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Show how much space each kind of cached file uses
+    #[structopt(name = "size")]
+    Size {
+        /// Output results as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub enum ConfigCommand {
+    /// Print a setting's value, wherever it's set. Exits with 1 if it isn't set
+    #[structopt(name = "get")]
+    Get { key: String },
+    /// Save a setting in the global config file
+    #[structopt(name = "set")]
+    Set { key: String, value: String },
+    /// List every setting, its value, and where the value comes from
+    #[structopt(name = "list")]
+    List,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum VersionCommand {
+    /// Increment part of the version, eg `pyflow version bump patch`
+    #[structopt(name = "bump")]
+    Bump {
+        #[structopt(possible_values = BumpPart::NAMES)]
+        part: BumpPart,
+        /// Commit the change, and tag the commit with the new version
+        #[structopt(long)]
+        tag: bool,
+    },
+    /// Set the version, eg `pyflow version set 1.4.0`
+    #[structopt(name = "set")]
+    Set {
+        version: String,
+        /// Commit the change, and tag the commit with the new version
+        #[structopt(long)]
+        tag: bool,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub enum SelfCommand {
+    /// Replace this executable with the newest release, verified against its published
+    /// checksum
+    #[structopt(name = "update")]
+    Update {
+        /// Install this release instead, eg `0.3.1`
+        #[structopt(long)]
+        version: Option<String>,
+        /// Only report whether there's a newer release
+        #[structopt(long)]
+        check: bool,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub enum EnvCommand {
+    /// Show the interpreter, paths, and settings that installing and running use here
+    #[structopt(name = "info")]
+    Info {
+        /// Output as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Print the folders the environment's packages, interpreter, and console scripts are in,
+    /// one per line
+    #[structopt(name = "path")]
+    Path {
+        /// Output as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Print shell code that puts the environment on the `PATH` and `PYTHONPATH`, as
+    /// `pyflow run` does, eg for `eval "$(pyflow env activate)"`
+    #[structopt(name = "activate")]
+    Activate {
+        /// The shell to write code for. Defaults to the one in `SHELL`, or PowerShell on Windows
+        #[structopt(long, possible_values = EvalShell::NAMES)]
+        shell: Option<EvalShell>,
+    },
+    /// Print shell code that undoes `pyflow env activate`
+    #[structopt(name = "deactivate")]
+    Deactivate {
+        /// The shell to write code for. Defaults to the one in `SHELL`, or PowerShell on Windows
+        #[structopt(long, possible_values = EvalShell::NAMES)]
+        shell: Option<EvalShell>,
+    },
+    /// Make a standard virtualenv with exactly the locked packages, eg for a tox or nox
+    /// session. Running it again updates the environment
+    #[structopt(name = "create")]
+    Create {
+        /// Where to make the environment, eg `.tox/py311`
+        #[structopt(long, parse(from_os_str))]
+        path: PathBuf,
+        /// The Python version, eg `3.11` or `pypy3.10`. Defaults to the project's
+        #[structopt(long)]
+        python: Option<String>,
+        /// Dependency groups to install: `default`, `dev`, or groups under
+        /// `[tool.pyflow.extras]`, eg `--groups default,dev`
+        #[structopt(long, use_delimiter = true, default_value = "default")]
+        groups: Vec<String>,
+    },
+}
+
+/// Shells `pyflow env activate` writes code for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EvalShell {
+    Bash,
+    Zsh,
+    Fish,
+    PowerShell,
+}
+
+impl EvalShell {
+    pub const NAMES: &'static [&'static str] = &["bash", "zsh", "fish", "powershell"];
+}
+
+impl FromStr for EvalShell {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "bash" => Self::Bash,
+            "zsh" => Self::Zsh,
+            "fish" => Self::Fish,
+            "powershell" => Self::PowerShell,
+            _ => anyhow::bail!("Unknown shell: {}", s),
+        })
+    }
+}
+
+#[derive(StructOpt, Debug)]
+pub enum HooksCommand {
+    /// Add hooks that check `pyproject.toml` parses, and `pyflow.lock` matches it, to
+    /// `.pre-commit-config.yaml`; or, without pre-commit, to `.git/hooks/pre-commit`
+    #[structopt(name = "install")]
+    Install {
+        /// Write `.git/hooks/pre-commit`, even if pre-commit is used. An existing hook still runs
+        #[structopt(long)]
+        git: bool,
+    },
+    /// Remove the hooks `pyflow hooks install` added, restoring any git hook they replaced
+    #[structopt(name = "uninstall")]
+    Uninstall,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum ScriptEnvsCommand {
+    /// List cached environments, their requirements, and the scripts that use them
+    #[structopt(name = "list")]
+    List,
+    /// Remove environments no existing script uses
+    #[structopt(name = "clean")]
+    Clean {
+        /// Remove every cached environment, and each script's chosen Python version
+        #[structopt(long)]
+        all: bool,
+    },
+}
+
+/// Formats for `pyflow export`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Requirements,
+    RequirementsHashes,
+    Pylock,
+    Json,
+    Poetry,
+    Conda,
+}
+
+impl ExportFormat {
+    pub const NAMES: &'static [&'static str] = &[
+        "requirements",
+        "requirements-hashes",
+        "pylock",
+        "json",
+        "poetry",
+        "conda",
+    ];
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "requirements" => Self::Requirements,
+            "requirements-hashes" => Self::RequirementsHashes,
+            "pylock" => Self::Pylock,
+            "json" => Self::Json,
+            "poetry" => Self::Poetry,
+            "conda" => Self::Conda,
+            _ => anyhow::bail!("Unknown export format: {}", s),
+        })
+    }
+}
+
+/// IDEs `pyflow ide` configures.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IdeKind {
+    VsCode,
+    PyCharm,
+}
+
+impl IdeKind {
+    pub const NAMES: &'static [&'static str] = &["vscode", "pycharm"];
+}
+
+impl FromStr for IdeKind {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "vscode" => Self::VsCode,
+            "pycharm" => Self::PyCharm,
+            _ => anyhow::bail!("Unknown IDE: {}", s),
+        })
+    }
+}
+
+/// The part of the version `pyflow version bump` increments.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BumpPart {
+    Major,
+    Minor,
+    Patch,
+    /// The pre-release number, eg `1.2.0a1` to `1.2.0a2`.
+    Prerelease,
+}
+
+impl BumpPart {
+    pub const NAMES: &'static [&'static str] = &["major", "minor", "patch", "prerelease"];
+}
+
+impl FromStr for BumpPart {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "major" => Self::Major,
+            "minor" => Self::Minor,
+            "patch" => Self::Patch,
+            "prerelease" => Self::Prerelease,
+            _ => anyhow::bail!("Unknown version part: {}", s),
+        })
+    }
+}
+
+/// How far `list --outdated` and `update` may move a version. Calendar versions, like
+/// `2024.3.1`, are treated the same way, with the year as the major version.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Within {
+    Minor,
+    Patch,
+}
+
+impl Within {
+    pub const NAMES: &'static [&'static str] = &["minor", "patch"];
+}
+
+impl FromStr for Within {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "minor" => Self::Minor,
+            "patch" => Self::Patch,
+            _ => anyhow::bail!("Unknown update level: {}", s),
+        })
+    }
+}
+
+/// Severities for `pyflow audit --severity`, lowest first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AuditSeverity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl AuditSeverity {
+    pub const NAMES: &'static [&'static str] = &["low", "medium", "high", "critical"];
+}
+
+impl FromStr for AuditSeverity {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "low" => Self::Low,
+            "medium" => Self::Medium,
+            "high" => Self::High,
+            "critical" => Self::Critical,
+            _ => anyhow::bail!("Unknown severity: {}", s),
+        })
+    }
+}
+
+impl fmt::Display for AuditSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+            Self::Critical => "critical",
+        };
+        write!(f, "{}", name)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum ExternalSubcommands {
     Run,
@@ -117,15 +951,15 @@ pub enum ExternalSubcommands {
     ImpliedPython(String),
 }
 
-impl ToString for ExternalSubcommands {
-    fn to_string(&self) -> String {
-        match self {
-            Self::Run => "run".into(),
-            Self::Script => "script".into(),
-            Self::Python => "python".into(),
-            Self::ImpliedRun(x) => x.into(),
-            Self::ImpliedPython(x) => x.into(),
-        }
+impl fmt::Display for ExternalSubcommands {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Run => "run",
+            Self::Script => "script",
+            Self::Python => "python",
+            Self::ImpliedRun(x) => x,
+            Self::ImpliedPython(x) => x,
+        })
     }
 }
 
@@ -147,6 +981,14 @@ impl FromStr for ExternalSubcommands {
 pub struct ExternalCommand {
     pub cmd: ExternalSubcommands,
     pub args: Vec<String>,
+    /// From `pyflow run --cwd <dir>`; the directory to run in.
+    pub cwd: Option<String>,
+    /// From `pyflow run --quiet`; don't mention programs on the `PATH` with the same name.
+    pub quiet: bool,
+    /// From `pyflow run -p <member>`; the workspace member to run in.
+    pub package: Option<String>,
+    /// From `pyflow run --system-site`; let the program see `pip install --user` packages.
+    pub system_site: bool,
 }
 
 impl ExternalCommand {
@@ -163,9 +1005,137 @@ impl ExternalCommand {
             ExternalSubcommands::ImpliedPython(_) => ExternalSubcommands::Python,
             x => x,
         };
-        Self {
+        let mut result = Self {
             cmd,
             args: cmd_args.to_vec(),
+            cwd: None,
+            quiet: false,
+            package: None,
+            system_site: false,
+        };
+        if let ExternalSubcommands::Run = result.cmd {
+            result.take_run_options();
+        }
+        if let ExternalSubcommands::Run | ExternalSubcommands::Script = result.cmd {
+            result.args = strip_separator(result.args);
         }
+        result
+    }
+
+    /// Take `--cwd <dir>`, `--cwd=<dir>`, `-p <member>`, `--quiet`, and `--system-site` from
+    /// before the program name, eg `pyflow run --cwd docs make html`. After the program name,
+    /// they're the program's.
+    fn take_run_options(&mut self) {
+        loop {
+            match self.args.first().map(String::as_str) {
+                Some("--cwd") if self.args.len() >= 2 => {
+                    self.cwd = Some(self.args.remove(1));
+                }
+                Some(a) if a.starts_with("--cwd=") => {
+                    self.cwd = Some(a["--cwd=".len()..].to_owned());
+                }
+                Some("-p") | Some("--package") if self.args.len() >= 2 => {
+                    self.package = Some(self.args.remove(1));
+                }
+                Some(a) if a.starts_with("--package=") => {
+                    self.package = Some(a["--package=".len()..].to_owned());
+                }
+                Some("--quiet") | Some("-q") => self.quiet = true,
+                Some("--system-site") => self.system_site = true,
+                _ => return,
+            }
+            self.args.remove(0);
+        }
+    }
+}
+
+/// Drop a `--` separating pyflow's args from the program's, if it's just before or after the
+/// program name, eg `pyflow run -- black .`, or `pyflow run pytest -- -k "not slow"`. With `-m`,
+/// the module is the program name, eg `pyflow run -m pytest -- -x`. Everything else, including
+/// later `--`s, is passed to the program untouched.
+fn strip_separator(mut args: Vec<String>) -> Vec<String> {
+    let after_name = if args.first().map(String::as_str) == Some("-m") {
+        2
+    } else {
+        1
+    };
+    if args.first().map(String::as_str) == Some("--") {
+        args.remove(0);
+    } else if args.get(after_name).map(String::as_str) == Some("--") {
+        args.remove(after_name);
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest(
+        input,
+        expected,
+        case(&["run", "pytest", "-x"], &["pytest", "-x"]),
+        case(&["run", "pytest", "--", "-k", "not slow", "-x", "--lf"], &["pytest", "-k", "not slow", "-x", "--lf"]),
+        case(&["run", "--", "black", "--check", "."], &["black", "--check", "."]),
+        case(&["run", "python", "-c", "print(1)"], &["python", "-c", "print(1)"]),
+        case(&["run", "-m", "http.server", "8000", "--bind", "127.0.0.1"], &["-m", "http.server", "8000", "--bind", "127.0.0.1"]),
+        case(&["run", "-m", "pytest", "--", "-k", "not slow"], &["-m", "pytest", "-k", "not slow"]),
+        case(&["run", "--", "-m", "pip", "--version"], &["-m", "pip", "--version"]),
+        case(&["run", "pytest", "--", "-k", "", "--", "x"], &["pytest", "-k", "", "--", "x"]),
+        case(&["pytest", "--", "-k", "not slow"], &["pytest", "-k", "not slow"]),
+        case(&["script", "myscript.py", "--", "--verbose"], &["myscript.py", "--verbose"]),
+        case(&["python", "-c", "print(1)"], &["-c", "print(1)"])
+    )]
+    fn external_command_args(input: &[&str], expected: &[&str]) {
+        let input = input.iter().map(|a| a.to_string()).collect();
+        assert_eq!(ExternalCommand::from_opt(input).args, expected);
+    }
+
+    #[rstest(
+        input,
+        cwd,
+        args,
+        case(&["run", "--cwd", "docs", "make", "html"], Some("docs"), &["make", "html"]),
+        case(&["run", "--cwd=../other", "--", "pytest", "-x"], Some("../other"), &["pytest", "-x"]),
+        case(&["run", "pytest", "--cwd", "docs"], None, &["pytest", "--cwd", "docs"]),
+        case(&["python", "--cwd", "docs"], None, &["--cwd", "docs"]),
+        case(&["run", "-q", "--cwd", "docs", "black", "-q"], Some("docs"), &["black", "-q"])
+    )]
+    fn external_command_cwd(input: &[&str], cwd: Option<&str>, args: &[&str]) {
+        let input = input.iter().map(|a| a.to_string()).collect();
+        let cmd = ExternalCommand::from_opt(input);
+        assert_eq!(cmd.cwd.as_deref(), cwd);
+        assert_eq!(cmd.args, args);
+    }
+
+    #[rstest(
+        input,
+        package,
+        args,
+        case(&["run", "-p", "api", "uvicorn", "app:app"], Some("api"), &["uvicorn", "app:app"]),
+        case(&["run", "--package=core", "pytest"], Some("core"), &["pytest"]),
+        case(&["run", "pytest", "-p", "no:cacheprovider"], None, &["pytest", "-p", "no:cacheprovider"])
+    )]
+    fn external_command_package(input: &[&str], package: Option<&str>, args: &[&str]) {
+        let input = input.iter().map(|a| a.to_string()).collect();
+        let cmd = ExternalCommand::from_opt(input);
+        assert_eq!(cmd.package.as_deref(), package);
+        assert_eq!(cmd.args, args);
+    }
+
+    #[rstest(
+        input,
+        system_site,
+        args,
+        case(&["run", "--system-site", "pytest"], true, &["pytest"]),
+        case(&["run", "pytest", "--system-site"], false, &["pytest", "--system-site"])
+    )]
+    fn external_command_system_site(input: &[&str], system_site: bool, args: &[&str]) {
+        let input = input.iter().map(|a| a.to_string()).collect();
+        let cmd = ExternalCommand::from_opt(input);
+        assert_eq!(cmd.system_site, system_site);
+        assert_eq!(cmd.args, args);
     }
 }
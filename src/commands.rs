@@ -1,9 +1,11 @@
+use crate::py_versions::Implementation;
 use crate::util;
-use regex::Regex;
+#[cfg(unix)]
+use std::sync::atomic::{AtomicI32, Ordering};
 use std::{error::Error, fmt};
 use std::{
     path::{Path, PathBuf},
-    process::{Command, Stdio},
+    process::{self, Command, ExitStatus, Stdio},
 };
 
 #[derive(Debug)]
@@ -23,31 +25,14 @@ impl fmt::Display for _ExecutionError {
     }
 }
 
-/// TODO: Dry from `find_py_version`
-pub fn find_py_dets(alias: &str) -> Option<String> {
-    let output = Command::new(alias).args(["--version, --version"]).output();
-
-    let output_bytes = match output {
-        Ok(ob) => {
-            // Old versions of python output `--version` to `stderr`; newer ones to `stdout`,
-            // so check both.
-            if ob.stdout.is_empty() {
-                ob.stderr
-            } else {
-                ob.stdout
-            }
-        }
-        Err(_) => return None,
-    };
-
-    match std::str::from_utf8(&output_bytes) {
-        Ok(r) => Some(r.to_owned()),
-        Err(_) => None,
-    }
-}
-
 /// Find the Python version from the `python --py_version` command. Eg: "Python 3.7".
 pub fn find_py_version(alias: &str) -> Option<crate::Version> {
+    find_py_details(alias).map(|(version, _)| version)
+}
+
+/// Find the Python version and implementation from the `python --version` command. PyPy
+/// reports its own version on a second line, eg "Python 3.10.13 (...)\n[PyPy 7.3.15 ...]".
+pub fn find_py_details(alias: &str) -> Option<(crate::Version, Implementation)> {
     let output = Command::new(alias).arg("--version").output();
 
     let output_bytes = match output {
@@ -64,13 +49,17 @@ pub fn find_py_version(alias: &str) -> Option<crate::Version> {
     };
 
     if let Ok(version) = std::str::from_utf8(&output_bytes) {
-        let re = Regex::new(r"Python\s+(\d{1,4})\.(\d{1,4})\.(\d{1,4})").unwrap();
-        match re.captures(version) {
+        let implementation = if version.contains("PyPy") {
+            Implementation::PyPy
+        } else {
+            Implementation::CPython
+        };
+        match util::patterns::PYTHON_VERSION_RE.captures(version) {
             Some(caps) => {
                 let major = caps.get(1).unwrap().as_str().parse::<u32>().unwrap();
                 let minor = caps.get(2).unwrap().as_str().parse::<u32>().unwrap();
                 let patch = caps.get(3).unwrap().as_str().parse::<u32>().unwrap();
-                Some(crate::Version::new(major, minor, patch))
+                Some((crate::Version::new(major, minor, patch), implementation))
             }
             None => None,
         }
@@ -80,43 +69,121 @@ pub fn find_py_version(alias: &str) -> Option<crate::Version> {
 }
 
 /// Create the virtual env. Assume we're running Python 3.3+, where `venv` is included.
-/// Additionally, create the __pypackages__ directory if not already created.
-pub fn create_venv(py_alias: &str, lib_path: &Path, name: &str) -> Result<(), Box<dyn Error>> {
-    // While creating the lib path, we're creating the __pypackages__ structure.
+/// The environment is `name`, in `dir`.
+pub fn create_venv2(py_alias: &Path, dir: &Path, name: &str) -> Result<(), Box<dyn Error>> {
+    // Eg `__pypackages__/3.11`, or the project root with `env-mode = "venv"`.
     let output = Command::new(py_alias)
         .args(["-m", "venv", name])
-        .current_dir(lib_path.join("../"))
-        .output()?;
-    util::check_command_output(&output, "creating virtual environment");
-
-    Ok(())
-}
-
-// TODO: DRY for using a path instead of str. use impl Into<PathBuf> ?
-pub fn create_venv2(py_alias: &Path, lib_path: &Path, name: &str) -> Result<(), Box<dyn Error>> {
-    // While creating the lib path, we're creating the __pypackages__ structure.
-    let output = Command::new(py_alias)
-        .args(["-m", "venv", name])
-        .current_dir(lib_path.join("../"))
+        .current_dir(dir)
         .output()?;
     util::check_command_output(&output, "creating virtual environment");
 
     Ok(())
 }
 
+/// Run the environment's Python with `args`, and return its exit code.
 pub fn run_python(
     bin_path: &Path,
     lib_paths: &[PathBuf],
     args: &[String],
-) -> Result<(), Box<dyn Error>> {
+) -> Result<i32, Box<dyn Error>> {
     util::set_pythonpath(lib_paths);
-    Command::new(bin_path.join("python"))
-        .args(args)
+    run_interactive(Command::new(bin_path.join("python")).args(args))
+}
+
+/// Run `cmd` attached to the terminal, and return its exit code. While it runs, signals meant
+/// for it are left to it, or forwarded; see `ForwardSignals`.
+pub fn run_interactive(cmd: &mut Command) -> Result<i32, Box<dyn Error>> {
+    let mut child = cmd
         .stdin(Stdio::inherit())
         .stdout(Stdio::inherit())
         .stderr(Stdio::inherit())
-        .output()?;
-    Ok(())
+        .spawn()?;
+    let _forward = ForwardSignals::new(child.id());
+    let status = child.wait()?;
+    Ok(exit_code(status))
+}
+
+/// Exit with a child's exit code if it failed, so eg CI sees a failing test run. Abort with
+/// `msg` if it couldn't be run at all.
+pub fn exit_on_failure(result: Result<i32, Box<dyn Error>>, msg: &str) {
+    match result {
+        Ok(0) => (),
+        Ok(code) => {
+            util::run_env::explain_user_site();
+            process::exit(code)
+        }
+        Err(_) => util::abort(msg),
+    }
+}
+
+/// The exit code to pass on for a child's status. On Unix, a child killed by a signal is
+/// reported as 128 + the signal number, as shells do.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    1
+}
+
+/// The child `ForwardSignals` sends signals to.
+#[cfg(unix)]
+static CHILD_PID: AtomicI32 = AtomicI32::new(0);
+
+#[cfg(unix)]
+extern "C" fn forward_signal(signal: libc::c_int) {
+    let pid = CHILD_PID.load(Ordering::SeqCst);
+    if pid > 0 {
+        unsafe {
+            libc::kill(pid, signal);
+        }
+    }
+}
+
+/// While alive, pyflow ignores Ctrl-C: the terminal sends it to the child too, which decides
+/// what to do, eg interrupt a line in a REPL, or stop a test run. On Unix, SIGTERM, which is only
+/// sent to us, is forwarded to the child; we exit once it does.
+struct ForwardSignals {
+    sigint: libc::sighandler_t,
+    #[cfg(unix)]
+    sigterm: libc::sighandler_t,
+}
+
+impl ForwardSignals {
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    fn new(child_pid: u32) -> Self {
+        #[cfg(unix)]
+        CHILD_PID.store(child_pid as i32, Ordering::SeqCst);
+        unsafe {
+            Self {
+                sigint: libc::signal(libc::SIGINT, libc::SIG_IGN),
+                #[cfg(unix)]
+                sigterm: libc::signal(
+                    libc::SIGTERM,
+                    forward_signal as *const () as libc::sighandler_t,
+                ),
+            }
+        }
+    }
+}
+
+impl Drop for ForwardSignals {
+    fn drop(&mut self) {
+        unsafe {
+            libc::signal(libc::SIGINT, self.sigint);
+            #[cfg(unix)]
+            libc::signal(libc::SIGTERM, self.sigterm);
+        }
+        #[cfg(unix)]
+        CHILD_PID.store(0, Ordering::SeqCst);
+    }
 }
 
 pub fn download_git_repo(repo: &str, dest_path: &Path) -> Result<(), Box<dyn Error>> {
@@ -143,3 +210,114 @@ pub fn git_init(dir: &Path) -> Result<(), Box<dyn Error>> {
     util::check_command_output(&output, "initializing git repository");
     Ok(())
 }
+
+/// Check out a commit, tag, or branch in a cloned repo.
+pub fn git_checkout(repo_path: &Path, reference: &str) -> Result<(), Box<dyn Error>> {
+    let output = Command::new("git")
+        .current_dir(repo_path)
+        .args(["checkout", "--quiet", reference])
+        .output()?;
+    util::check_command_output_with(&output, |s| {
+        util::abort(&format!("Problem checking out `{}`: {}", reference, s))
+    });
+    Ok(())
+}
+
+/// List a remote repo's refs, without cloning it. Never prompts for credentials.
+pub fn git_ls_remote(repo: &str) -> Result<String, Box<dyn Error>> {
+    let output = Command::new("git")
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .args(["ls-remote", repo])
+        .output()?;
+    if !output.status.success() {
+        return Err(Box::new(_ExecutionError {
+            details: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        }));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The latest version tag reachable from `HEAD`, how many commits past it `HEAD` is, `HEAD`'s
+/// abbreviated hash, and `-dirty` if there are uncommitted changes, eg `v1.4.2-3-g1a2b3c4-dirty`.
+pub fn git_describe(repo_path: &Path) -> Result<String, Box<dyn Error>> {
+    let output = Command::new("git")
+        .current_dir(repo_path)
+        .args(["describe", "--tags", "--long", "--dirty"])
+        .args(["--match", "v[0-9]*", "--match", "[0-9]*"])
+        .output()?;
+    if !output.status.success() {
+        return Err(Box::new(_ExecutionError {
+            details: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        }));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// Commit `files`, and only them, then tag the commit, eg for a release.
+pub fn git_commit_and_tag(
+    dir: &Path,
+    files: &[&Path],
+    message: &str,
+    tag: &str,
+) -> Result<(), Box<dyn Error>> {
+    let abort = |s: &str| util::abort(&format!("Problem committing and tagging: {}", s));
+
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args(["add", "--"])
+        .args(files)
+        .output()?;
+    util::check_command_output_with(&output, abort);
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args(["commit", "--quiet", "-m", message, "--"])
+        .args(files)
+        .output()?;
+    util::check_command_output_with(&output, abort);
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args(["tag", "-a", tag, "-m", message])
+        .output()?;
+    util::check_command_output_with(&output, abort);
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    // Signal handling is per-process; don't let these tests overlap.
+    static SIGNALS: Mutex<()> = Mutex::new(());
+
+    fn run_sh(script: &str) -> i32 {
+        let _lock = SIGNALS.lock().unwrap_or_else(|e| e.into_inner());
+        run_interactive(Command::new("sh").args(["-c", script])).unwrap()
+    }
+
+    #[test]
+    fn exit_code_passed_on() {
+        assert_eq!(run_sh("exit 3"), 3);
+        assert_eq!(run_sh("exit 0"), 0);
+    }
+
+    #[test]
+    fn interrupted_child() {
+        assert_eq!(run_sh("kill -INT $$"), 128 + libc::SIGINT);
+    }
+
+    #[test]
+    fn crashed_child() {
+        assert_eq!(run_sh("kill -SEGV $$"), 128 + libc::SIGSEGV);
+    }
+
+    #[test]
+    fn terminate_forwarded() {
+        // The child asks us to stop; it should get the signal, not us.
+        assert_eq!(
+            run_sh("sleep 1; kill -TERM $PPID; sleep 5"),
+            128 + libc::SIGTERM
+        );
+    }
+}
@@ -1,8 +1,8 @@
 use std::str::FromStr;
 
-use nom::bytes::complete::{tag, take, take_till};
+use nom::bytes::complete::{tag, take_till, take_while1};
 use nom::character::complete::{digit1, space0, space1};
-use nom::combinator::{flat_map, map, map_parser, map_res, opt, value};
+use nom::combinator::{flat_map, map, map_res, opt, value};
 use nom::multi::separated_list;
 use nom::sequence::{delimited, preceded, separated_pair, tuple};
 use nom::{branch::alt, character::is_alphabetic};
@@ -68,6 +68,33 @@ pub fn parse_req_pypi_fmt(input: &str) -> IResult<&str, Req> {
     )(input)
 }
 
+pub fn parse_req_cli(input: &str) -> IResult<&str, Req> {
+    // eg uvicorn[standard]>=0.23, or numpy==1.16.4, as specified with `pyflow install`.
+    map(
+        tuple((
+            parse_package_name,
+            opt(parse_install_with_extras),
+            preceded(
+                space0,
+                alt((
+                    delimited(tag("("), parse_constraints, tag(")")),
+                    parse_constraints,
+                )),
+            ),
+            opt(preceded(tuple((space0, tag(";"), space0)), parse_extras)),
+        )),
+        |(name, install_with_extras, constraints, extras_opt)| {
+            let mut r = if let Some(extras) = extras_opt {
+                Req::new_with_extras(name.to_string(), constraints, extras)
+            } else {
+                Req::new(name.to_string(), constraints)
+            };
+            r.install_with_extras = install_with_extras;
+            r
+        },
+    )(input)
+}
+
 pub fn parse_pip_str(input: &str) -> IResult<&str, Req> {
     map(
         tuple((parse_package_name, opt(parse_constraint))),
@@ -93,16 +120,24 @@ fn parse_wh_py_ver(input: &str) -> IResult<&str, Constraint> {
         tuple((
             alt((tag("cp"), tag("py"), tag("pp"))),
             alt((tag("2"), tag("3"), tag("4"))),
-            opt(map_parser(take(1u8), digit1)),
             opt(digit1),
         )),
-        |(_, major, minor, patch): (_, &str, Option<&str>, Option<&str>)| {
+        |(implementation, major, rest): (&str, &str, Option<&str>)| {
             let major: u32 = major.parse().unwrap();
-            let patch = patch.map(|p| p.parse().unwrap());
-            match minor {
+            match rest {
+                // Old PyPy tags give PyPy's own version, eg `pp257` for PyPy 2.5.7.
+                Some(r) if implementation == "pp" && major == 2 && r.len() > 1 => Constraint::new(
+                    ReqType::Exact,
+                    Version::new_opt(
+                        Some(major),
+                        Some(r[..1].parse().unwrap()),
+                        Some(r[1..].parse().unwrap()),
+                    ),
+                ),
+                // Eg `cp310` or `pp310`; everything after the major version is the minor.
                 Some(mi) => Constraint::new(
                     ReqType::Exact,
-                    Version::new_opt(Some(major), Some(mi.parse().unwrap()), patch),
+                    Version::new_opt(Some(major), Some(mi.parse().unwrap()), None),
                 ),
                 None => {
                     if major == 2 {
@@ -123,9 +158,9 @@ fn quote(input: &str) -> IResult<&str, &str> {
 fn parse_install_with_extras(input: &str) -> IResult<&str, Vec<String>> {
     map(
         delimited(
-            tag("["),
-            separated_list(tag(","), parse_package_name),
-            tag("]"),
+            tuple((tag("["), space0)),
+            separated_list(tuple((space0, tag(","), space0)), parse_package_name),
+            tuple((space0, tag("]"))),
         ),
         |extras| extras.iter().map(|x| x.to_string()).collect(),
     )(input)
@@ -144,20 +179,21 @@ pub fn parse_extras(input: &str) -> IResult<&str, Extras> {
         |ps| {
             let mut extra = None;
             let mut sys_platform = None;
-            let mut python_version = None;
+            // Eg `python_version >= "3.8" and python_version < "4.0"`.
+            let mut python_version = vec![];
 
             for p in ps {
                 match p {
                     ExtrasPart::Extra(s) => extra = Some(s),
                     ExtrasPart::SysPlatform(r, o) => sys_platform = Some((r, o)),
-                    ExtrasPart::PythonVersion(c) => python_version = Some(c),
+                    ExtrasPart::PythonVersion(c) => python_version.push(c),
                 }
             }
 
             Extras {
                 extra,
                 sys_platform,
-                python_version,
+                python_version: Some(python_version).filter(|v| !v.is_empty()),
             }
         },
     )(input)
@@ -175,12 +211,19 @@ fn parse_extra_part(input: &str) -> IResult<&str, ExtrasPart> {
                     ),
                     |x| ExtrasPart::Extra(x.to_string()),
                 )(input),
-                "sys_platform" => map(
+                "sys_platform" => map_res(
                     tuple((
-                        delimited(space0, tag("=="), space0),
+                        delimited(space0, alt((tag("=="), tag("!="))), space0),
                         delimited(quote, parse_package_name, quote),
                     )),
-                    |(_, o)| ExtrasPart::SysPlatform(ReqType::Exact, Os::from_str(o).unwrap()),
+                    |(op, o)| {
+                        let type_ = if op == "!=" {
+                            ReqType::Ne
+                        } else {
+                            ReqType::Exact
+                        };
+                        Os::from_str(o).map(|os| ExtrasPart::SysPlatform(type_, os))
+                    },
                 )(input),
                 "python_version" => map(
                     tuple((
@@ -217,9 +260,14 @@ pub fn parse_version(input: &str) -> IResult<&str, Version> {
         opt(preceded(tag("."), parse_digit_or_wildcard)),
     ))(input)?;
     let (remain, modifire) = parse_modifier(remain)?;
+    let (remain, local) = opt(preceded(
+        tag("+"),
+        take_while1(|c: char| c.is_ascii_alphanumeric() || c == '.'),
+    ))(remain)?;
     let mut version = Version::new_opt(Some(major), minor, patch);
     version.extra_num = extra_num;
     version.modifier = modifire;
+    version.local = local.map(str::to_owned);
     // check if u32::MAX in any version. (marker for `*`). then set that field
     // and any subsequent fields to `None`
     version.star = [Some(major), minor, patch, extra_num].contains(&Some(u32::MAX));
@@ -331,6 +379,7 @@ mod tests {
             extra_num: None,
             modifier: None,
             star: false,
+            local: None,
         }))),
         case("0.1.0", Ok(("", Version {
             major: Some(0),
@@ -339,6 +388,7 @@ mod tests {
             extra_num: None,
             modifier: None,
             star: false,
+            local: None,
         }))),
         case("3.7", Ok(("", Version {
             major: Some(3),
@@ -347,6 +397,7 @@ mod tests {
             extra_num: None,
             modifier: None,
             star: false,
+            local: None,
         }))),
         case("1", Ok(("", Version {
             major: Some(1),
@@ -355,6 +406,7 @@ mod tests {
             extra_num: None,
             modifier: None,
             star: false,
+            local: None,
         }))),
         case("3.2.*", Ok(("", Version {
             major: Some(3),
@@ -363,6 +415,7 @@ mod tests {
             extra_num: None,
             modifier: None,
             star: true,
+            local: None,
         }))),
         case("1.*", Ok(("", Version {
             major: Some(1),
@@ -371,6 +424,7 @@ mod tests {
             extra_num: None,
             modifier: None,
             star: true,
+            local: None,
         }))),
         case("1.*.*", Ok(("", Version {
             major: Some(1),
@@ -379,6 +433,7 @@ mod tests {
             extra_num: None,
             modifier: None,
             star: true,
+            local: None,
         }))),
         case("19.3", Ok(("", Version {
             major: Some(19),
@@ -387,6 +442,7 @@ mod tests {
             extra_num: None,
             modifier: None,
             star: false,
+            local: None,
         }))),
         case("19.3b0", Ok(("", Version {
                  major: Some(19),
@@ -395,6 +451,7 @@ mod tests {
                  extra_num: None,
                  modifier: Some((VersionModifier::Beta, 0)),
                  star: false,
+                 local: None,
         }))),
         // This package version showed up in boltons history
         case("0.4.3.dev0", Ok(("", Version {
@@ -404,6 +461,16 @@ mod tests {
                  extra_num: None,
                  modifier: Some((VersionModifier::Other("dev".to_string()), 0)),
                  star: false,
+                 local: None,
+        }))),
+        case("1.4.2+g1a2b3c4.dirty", Ok(("", Version {
+                 major: Some(1),
+                 minor: Some(4),
+                 patch: Some(2),
+                 extra_num: None,
+                 modifier: None,
+                 star: false,
+                 local: Some("g1a2b3c4.dirty".to_string()),
         }))),
     )]
     fn test_parse_version(input: &str, expected: IResult<&str, Version>) {
@@ -427,7 +494,7 @@ mod tests {
             Ok(("", Extras{
                 extra: Some("test".to_string()),
                 sys_platform: None,
-                python_version: Some(Constraint{ type_: ReqType::Exact, version: Version::new(2, 7, 0)})
+                python_version: Some(vec![Constraint{ type_: ReqType::Exact, version: Version::new(2, 7, 0)}])
             }))
         ),
        case(
@@ -435,7 +502,7 @@ mod tests {
             Ok(("", Extras{
                 extra: None,
                 sys_platform: None,
-                python_version: Some(Constraint{ type_: ReqType::Exact, version: Version::new(2, 7, 0)})
+                python_version: Some(vec![Constraint{ type_: ReqType::Exact, version: Version::new(2, 7, 0)}])
             }))
         ),
        case(
@@ -443,7 +510,7 @@ mod tests {
             Ok(("", Extras{
                 extra: None,
                 sys_platform: None,
-                python_version: Some(Constraint{ type_: ReqType::Exact, version: Version::new(2, 7, 0)})
+                python_version: Some(vec![Constraint{ type_: ReqType::Exact, version: Version::new(2, 7, 0)}])
             }))
         ),
         case(
@@ -451,7 +518,7 @@ mod tests {
             Ok(("", Extras{
                 extra: None,
                 sys_platform: None,
-                python_version: Some(Constraint{ type_: ReqType::Exact, version: Version::new(2, 7, 0)})
+                python_version: Some(vec![Constraint{ type_: ReqType::Exact, version: Version::new(2, 7, 0)}])
             }))
         ),
         case(
@@ -459,7 +526,18 @@ mod tests {
             Ok(("", Extras{
                 extra: None,
                 sys_platform: Some((ReqType::Exact, Os::Windows32)),
-                python_version: Some(Constraint{ type_: ReqType::Lt, version: Version::new(3, 6, 0)})
+                python_version: Some(vec![Constraint{ type_: ReqType::Lt, version: Version::new(3, 6, 0)}])
+            }))
+        ),
+        case(
+            "sys_platform != \"win32\" and python_version >= \"3.8\" and python_version < \"4.0\"",
+            Ok(("", Extras{
+                extra: None,
+                sys_platform: Some((ReqType::Ne, Os::Windows32)),
+                python_version: Some(vec![
+                    Constraint{ type_: ReqType::Gte, version: Version::new(3, 8, 0)},
+                    Constraint{ type_: ReqType::Lt, version: Version::new(4, 0, 0)},
+                ])
             }))
         ),
     )]
@@ -495,4 +573,32 @@ mod tests {
     fn test_parse_req_pypi(input: &str, expected: IResult<&str, Req>) {
         assert_eq!(parse_req_pypi_fmt(input), expected);
     }
+
+    fn req_with_extras(name: &str, constraints: Vec<Constraint>, extras: &[&str]) -> Req {
+        let mut r = Req::new(name.to_string(), constraints);
+        r.install_with_extras = Some(extras.iter().map(|e| e.to_string()).collect());
+        r
+    }
+
+    #[rstest(input, expected,
+        case::name_only("requests", Ok(("", Req::new("requests".to_string(), vec![])))),
+        case::exact("numpy==1.16.4", Ok(("", Req::new(
+            "numpy".to_string(),
+            vec![Constraint::new(ReqType::Exact, Version::new(1, 16, 4))])))),
+        case::extras_and_version("uvicorn[standard]>=0.23", Ok(("", req_with_extras(
+            "uvicorn",
+            vec![Constraint::new(ReqType::Gte, Version::new_short(0, 23))],
+            &["standard"])))),
+        case::multiple_extras("uvicorn[standard, watchfiles]", Ok(("", req_with_extras(
+            "uvicorn", vec![], &["standard", "watchfiles"])))),
+        case::multiple_constraints("django>=3.2,<4", Ok(("", Req::new(
+            "django".to_string(),
+            vec![
+                Constraint::new(ReqType::Gte, Version::new_short(3, 2)),
+                Constraint::new(ReqType::Lt, Version::new_opt(Some(4), None, None)),
+            ])))),
+    )]
+    fn test_parse_req_cli(input: &str, expected: IResult<&str, Req>) {
+        assert_eq!(parse_req_cli(input), expected);
+    }
 }
@@ -14,6 +14,7 @@ use termcolor::Color;
 use mockall::automock;
 
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 struct WarehouseInfo {
     name: String, // Pulling this ensure proper capitalization
     requires_dist: Option<Vec<String>>,
@@ -22,12 +23,14 @@ struct WarehouseInfo {
 }
 
 #[derive(Clone, Debug, Deserialize)]
+#[allow(dead_code)]
 pub struct WarehouseDigests {
     pub md5: String,
     pub sha256: String,
 }
 
 #[derive(Clone, Debug, Deserialize)]
+#[allow(dead_code)]
 pub struct WarehouseRelease {
     // Could use digests field, which has sha256 as well as md5.
     pub filename: String,
@@ -42,12 +45,45 @@ pub struct WarehouseRelease {
 
 /// Only deserialize the info we need to resolve dependencies etc.
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 struct WarehouseData {
     info: WarehouseInfo,
     releases: HashMap<String, Vec<WarehouseRelease>>,
     urls: Vec<WarehouseRelease>,
 }
 
+impl WarehouseData {
+    fn candidates(&self) -> Candidates {
+        let mut files = vec![];
+        for (version, releases) in &self.releases {
+            for release in releases {
+                let req = Req::from_warehouse_release(
+                    self.info.name.clone(),
+                    version.clone(),
+                    release.clone(),
+                );
+                files.push((
+                    req.constraints[0].version.clone(),
+                    req.python_version.unwrap_or_default(),
+                ));
+            }
+        }
+        Candidates {
+            name: self.info.name.clone(),
+            files,
+        }
+    }
+}
+
+/// What resolving uses from a package's index data: its name, capitalized as the index has it,
+/// and each release file's version, and the Python versions it supports. Versions that don't
+/// parse are stars. Cached by `util::metadata_cache`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidates {
+    pub name: String,
+    pub files: Vec<(Version, Vec<Constraint>)>,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 struct ReqCache {
     // Name is present from pydeps if gestruct packagetting deps for multiple package names. Otherwise, we commit
@@ -74,6 +110,26 @@ struct MultipleBody {
     packages: HashMap<String, Vec<String>>,
 }
 
+/// Whether a req's `sys_platform` and `python_version` markers apply to this environment.
+pub fn markers_match(req: &Req, os: util::Os, py_vers: &Version) -> bool {
+    let os_match = match req.sys_platform {
+        Some((rt, os_)) => match rt {
+            // A specified win32 req could apply to 64-bit windows too.
+            ReqType::Exact => os_ == os || (os_ == util::Os::Windows32 && os == util::Os::Windows),
+            ReqType::Ne => os_ != os,
+            _ => {
+                util::abort("Reqtypes for Os must be == or !=");
+            }
+        },
+        None => true,
+    };
+    os_match
+        && match &req.python_version {
+            Some(v) => res::is_compat(v, py_vers),
+            None => true,
+        }
+}
+
 // TODO: figure out lifetimes so we can automock this function
 // guess_graph removed from mod res because of lifetime issue with automock
 // Build a graph: Start by assuming we can pick the newest compatible dependency at each step.
@@ -82,6 +138,7 @@ struct MultipleBody {
 fn guess_graph(
     parent_id: u32,
     reqs: &[Req],
+    constraints: &[Req],
     locked: &[crate::Package],
     os: util::Os,
     extras: &[String],
@@ -112,7 +169,9 @@ fn guess_graph(
                     if req.extra.is_none() && c.extra.is_some() {
                         c.extra = None
                     }
-                    // TODO: Should merge sys_platform, python_version, install_with_extras too.
+                    c.install_with_extras =
+                        util::merge_extras(&c.install_with_extras, &req.install_with_extras);
+                    // TODO: Should merge sys_platform and python_version too.
                 }
             }
 
@@ -121,6 +180,21 @@ fn guess_graph(
         cleaned_reqs.push(req.clone());
     }
 
+    // Constraints narrow the versions we'll pick for a package, but don't require it.
+    for req in cleaned_reqs.iter_mut() {
+        let name = req.name.clone();
+        for pin in constraints
+            .iter()
+            .filter(|c| util::compare_names(&c.name, &name))
+        {
+            for constr in &pin.constraints {
+                if !req.constraints.contains(constr) {
+                    req.constraints.push(constr.clone());
+                }
+            }
+        }
+    }
+
     let reqs: Vec<&Req> = cleaned_reqs
         .iter()
         // If we've already satisfied this req, don't query it again. Otherwise we'll make extra
@@ -130,23 +204,7 @@ fn guess_graph(
             Some(ex) => extras.contains(ex),
             None => true,
         })
-        .filter(|r| match r.sys_platform {
-            Some((rt, os_)) => match rt {
-                // A specified win32 req could apply to 64-bit windows too.
-                ReqType::Exact => {
-                    os_ == os || (os_ == util::Os::Windows32 && os == util::Os::Windows)
-                }
-                ReqType::Ne => os_ != os,
-                _ => {
-                    util::abort("Reqtypes for Os must be == or !=");
-                }
-            },
-            None => true,
-        })
-        .filter(|r| match &r.python_version {
-            Some(v) => res::is_compat(v, py_vers),
-            None => true,
-        })
+        .filter(|r| markers_match(r, os, py_vers))
         .collect();
 
     let mut non_locked_reqs = vec![];
@@ -157,6 +215,17 @@ fn guess_graph(
     for req in &reqs {
         reqs_searched.push((*req).clone());
 
+        // Locked dependencies don't record which extras pulled them in, so query reqs with
+        // extras fresh; otherwise a newly-added extra's requirements would be missed.
+        if req
+            .install_with_extras
+            .as_ref()
+            .is_some_and(|e| !e.is_empty())
+        {
+            non_locked_reqs.push((*req).clone());
+            continue;
+        }
+
         let mut found_in_locked = false;
         for package in locked.iter() {
             if !util::compare_names(&package.name, &req.name) {
@@ -271,6 +340,7 @@ fn guess_graph(
         if let Err(e) = guess_graph(
             newest_compat.id,
             &newest_compat.reqs,
+            constraints,
             locked,
             os,
             req.install_with_extras.as_ref().unwrap_or(&vec![]),
@@ -280,7 +350,7 @@ fn guess_graph(
             vers_cache,
             reqs_searched,
         ) {
-            println!("Problem pulling dependency info for {}", &req.name);
+            log::warn!("Problem pulling dependency info for {}", &req.name);
             util::abort(&e.details)
         }
     }
@@ -301,10 +371,29 @@ pub(super) mod res {
     }
 
     /// Fetch data about a package from the [Pypi Warehouse](https://warehouse.pypa.io/api-reference/json/).
-    fn get_warehouse_data(name: &str) -> Result<WarehouseData, reqwest::Error> {
-        let url = format!("https://pypi.org/pypi/{}/json", name);
-        let resp = reqwest::get(&url)?.json()?;
-        Ok(resp)
+    fn get_warehouse_data(name: &str) -> Result<WarehouseData, DependencyError> {
+        let url = util::index_json_url(name, None);
+        let raw = util::http::get_text(&url).map_err(|e| DependencyError::new(&e))?;
+        serde_json::from_str(&raw).map_err(|_| {
+            DependencyError::new(&format!("Problem parsing package data for {}", name))
+        })
+    }
+
+    /// A package's release candidates, from the warehouse. Parsing them is skipped if the
+    /// warehouse's data hasn't changed since we last did.
+    fn get_candidates(name: &str) -> Result<Candidates, DependencyError> {
+        let url = util::index_json_url(name, None);
+        let raw = util::http::get_text(&url).map_err(|e| DependencyError::new(&e))?;
+        if let Some(candidates) = util::metadata_cache::load(&url, &raw) {
+            util::http::record_cache_hit();
+            return Ok(candidates);
+        }
+        let data: WarehouseData = serde_json::from_str(&raw).map_err(|_| {
+            DependencyError::new(&format!("Problem parsing package data for {}", name))
+        })?;
+        let candidates = data.candidates();
+        util::metadata_cache::store(&url, &raw, &candidates);
+        Ok(candidates)
     }
 
     /// Find the latest version of a package by querying the warehouse.  Also return
@@ -314,50 +403,20 @@ pub(super) mod res {
         name: &str,
         req: Option<Req>,
     ) -> Result<(String, Version, Vec<Version>), DependencyError> {
-        let data = get_warehouse_data(name)?;
+        let data = get_candidates(name)?;
 
-        let all_versions = data
-            .releases
-            .keys()
-            .filter_map(|v| {
-                data.releases.get(v).map(|releases| {
-                    releases.iter().map(move |release| {
-                        Req::from_warehouse_release(
-                            name.to_string(),
-                            v.to_string(),
-                            release.clone(),
-                        )
-                    })
-                })
-            })
-            .flatten();
         let py_vers = if let Some(ref r) = req {
             r.py_ver_or_default()
         } else {
             Version::new_star(None, None, None, true)
         };
         let select_version = if let Some(ref r) = req {
-            let av: Vec<Req> = all_versions.clone().collect();
-            let compat_av: Vec<Version> = av
+            data.files
                 .iter()
-                .filter_map(|x: &Req| {
-                    if is_compat(&r.constraints, &x.constraints[0].version) {
-                        if let Some(ref pv) = x.python_version {
-                            if is_compat(pv, &py_vers) {
-                                Some(x.constraints[0].version.clone())
-                            } else {
-                                None
-                            }
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    }
-                })
-                .collect();
-            let v = compat_av.iter().max();
-            v.cloned()
+                .filter(|(v, py)| is_compat(&r.constraints, v) && is_compat(py, &py_vers))
+                .map(|(v, _)| v)
+                .max()
+                .cloned()
         } else {
             None
         };
@@ -367,28 +426,21 @@ pub(super) mod res {
         #[cfg(debug_assertions)]
         let mut all_compat: Vec<Version>;
 
-        all_compat = all_versions
-            .filter_map(|x| {
-                if let Some(y) = x.python_version {
-                    if is_compat(&y, &py_vers) {
-                        Some(x.constraints.first().unwrap().version.clone())
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            })
+        all_compat = data
+            .files
+            .iter()
+            .filter(|(_, py)| is_compat(py, &py_vers))
+            .map(|(v, _)| v.clone())
             .collect();
 
         #[cfg(debug_assertions)]
         all_compat.sort();
 
         if let Some(v) = select_version {
-            Ok((data.info.name, v, all_compat))
+            Ok((data.name, v, all_compat))
         } else {
             Ok((
-                data.info.name,
+                data.name,
                 all_compat
                     .iter()
                     .max()
@@ -399,11 +451,47 @@ pub(super) mod res {
         }
     }
 
+    /// Find the newest release of a package, and the newest that satisfies `constraints` and
+    /// supports `py_vers`. Skips pre-releases, and versions with no files uploaded.
+    pub fn get_latest_versions(
+        name: &str,
+        constraints: &[Constraint],
+        py_vers: &Version,
+    ) -> Result<(Version, Option<Version>), DependencyError> {
+        let data = get_candidates(name)?;
+
+        let mut latest: Option<Version> = None;
+        let mut latest_compat: Option<Version> = None;
+        // Versions that don't parse are stars.
+        for (version, python) in data.files.iter().filter(|(v, _)| !v.star) {
+            if version.modifier.is_some() {
+                continue;
+            }
+            if latest.as_ref().map_or(true, |l| version > l) {
+                latest = Some(version.clone());
+            }
+            if is_compat(python, py_vers)
+                && is_compat(constraints, version)
+                && latest_compat.as_ref().map_or(true, |l| version > l)
+            {
+                latest_compat = Some(version.clone());
+            }
+        }
+
+        match latest {
+            Some(l) => Ok((l, latest_compat)),
+            None => Err(DependencyError::new(&format!(
+                "Can't find any releases for {}",
+                name
+            ))),
+        }
+    }
+
     /// Get release data from the warehouse, ie the file url, name, and hash.
     pub fn get_warehouse_release(
         name: &str,
         version: &Version,
-    ) -> Result<Vec<WarehouseRelease>, reqwest::Error> {
+    ) -> Result<Vec<WarehouseRelease>, DependencyError> {
         let data = get_warehouse_data(name)?;
         // some packages 0-pad their version numbers or have less digits. Lets map
         // the parsed version to the key.
@@ -412,7 +500,7 @@ pub(super) mod res {
             if let Ok(ver) = Version::from_str(key) {
                 version_map.insert(ver, key.as_str());
             } else if cfg!(debug_assertions) {
-                eprintln!("Unable to parse \"{}\" version \"{}\"; skipped.", name, key);
+                log::debug!("Unable to parse \"{}\" version \"{}\"; skipped.", name, key);
             }
         }
 
@@ -442,7 +530,7 @@ pub(super) mod res {
         let url = "https://pydeps.herokuapp.com/multiple/";
         //                let url = "http://localhost:8000/multiple/";
 
-        reqwest::Client::new()
+        util::http_client()
             .post(url)
             .json(&MultipleBody {
                 packages: packages2,
@@ -586,7 +674,7 @@ pub(super) mod res {
                 )
             })
             .collect();
-        println!("Installing these versions: {:#?}", &dep_display);
+        log::debug!("Installing these versions: {:#?}", &dep_display);
 
         let mut result = vec![];
         // We were unable to resolve using the newest version; add and rename packages.
@@ -638,8 +726,11 @@ pub(super) mod res {
 
     /// Determine which dependencies we need to install, using the newest ones which meet
     /// all constraints. Gets data from a cached repo, and Pypi. Returns name, version, and name/version of its deps.
+    /// `constraints` narrow the versions of any package that's required, like pip's `-c`, but
+    /// don't add requirements; ones whose markers don't match the environment are ignored.
     pub fn resolve(
         reqs: &[Req],
+        constraints: &[Req],
         locked: &[crate::Package],
         os: util::Os,
         py_vers: &Version,
@@ -649,10 +740,17 @@ pub(super) mod res {
         let mut cache = HashMap::new();
         let mut reqs_searched = Vec::new();
 
+        let constraints: Vec<Req> = constraints
+            .iter()
+            .filter(|c| markers_match(c, os, py_vers))
+            .cloned()
+            .collect();
+
         let mut version_cache = HashMap::new();
         if guess_graph(
             0,
             reqs,
+            &constraints,
             locked,
             os,
             &[],
@@ -763,7 +861,7 @@ pub(super) mod res {
                     } else {
                         // We consider the possibility there's a compatible version
                         // that wasn't one of the best-per-req we queried.
-                        println!("⛏️ Digging deeper to resolve dependencies for {}...", name);
+                        log::info!("⛏️ Digging deeper to resolve dependencies for {}...", name);
 
                         // I think we should query with the raw name, not fmted?
                         let versions = &version_cache.get(name).unwrap().2;
@@ -1,20 +1,25 @@
 use crate::dep_parser::{
-    parse_constraint, parse_pip_str, parse_req, parse_req_pypi_fmt, parse_version, parse_wh_py_vers,
+    parse_constraint, parse_pip_str, parse_req, parse_req_cli, parse_req_pypi_fmt, parse_version,
+    parse_wh_py_vers,
 };
 #[mockall_double::double]
 use crate::dep_resolution::res;
 use crate::dep_resolution::WarehouseRelease;
-use crate::{util, CliConfig};
+use crate::pyproject::{toml_kv, TomlValue};
+use crate::util;
 use nom::combinator::all_consuming;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::error::Error;
 use std::hash::{Hash, Hasher};
 use std::io::Write;
-use std::{cmp, fmt, num, str::FromStr};
-use termcolor::{Buffer, BufferWriter, Color, ColorSpec, WriteColor};
+use std::{borrow::Cow, cmp, fmt, num, str::FromStr};
+use termcolor::{Buffer, Color, ColorSpec, WriteColor};
 
 pub const MAX_VER: u32 = 999_999; // Represents the highest major version we can have
+/// Bump when `Version` or `Constraint` change, so metadata cached in their old shape is parsed
+/// again; see `util::metadata_cache`.
+pub const SCHEMA_VERSION: u32 = 1;
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Dependency {
@@ -97,16 +102,16 @@ impl FromStr for VersionModifier {
     }
 }
 
-impl ToString for VersionModifier {
-    fn to_string(&self) -> String {
-        match self {
-            Self::Alpha => "a".into(),
-            Self::Beta => "b".into(),
-            Self::ReleaseCandidate => "rc".into(),
-            Self::Dep => "dep".into(),
+impl fmt::Display for VersionModifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Alpha => "a",
+            Self::Beta => "b",
+            Self::ReleaseCandidate => "rc",
+            Self::Dep => "dep",
             Self::Null => panic!("Can't convert Null to string; misused"),
-            Self::Other(x) => x.into(),
-        }
+            Self::Other(x) => x,
+        })
     }
 }
 
@@ -145,6 +150,9 @@ pub struct Version {
     pub modifier: Option<(VersionModifier, u32)>, // eg a1
     /// if `true` the star goes in the first `None` slot. Remaining slots should be `None`
     pub star: bool,
+    /// A local version label, eg `g1a2b3c4` in `1.4.2.dev3+g1a2b3c4`. Ignored in comparisons, so
+    /// `==1.4.2` matches `1.4.2+cpu`, as in PEP 440.
+    pub local: Option<String>,
 }
 
 impl Version {
@@ -156,6 +164,7 @@ impl Version {
             extra_num: None,
             modifier: None,
             star: false,
+            local: None,
         }
     }
 
@@ -167,6 +176,7 @@ impl Version {
             extra_num: None,
             modifier: None,
             star: true,
+            local: None,
         }
     }
 
@@ -179,6 +189,7 @@ impl Version {
             extra_num: None,
             modifier: None,
             star: false,
+            local: None,
         }
     }
 
@@ -190,6 +201,7 @@ impl Version {
             extra_num: None,
             modifier: None,
             star: false,
+            local: None,
         }
     }
 
@@ -207,6 +219,7 @@ impl Version {
             extra_num: None,
             modifier: None,
             star,
+            local: None,
         }
     }
 
@@ -219,6 +232,7 @@ impl Version {
             extra_num: self.extra_num,
             modifier: self.modifier.clone(),
             star: false,
+            local: self.local.clone(),
         }
     }
 
@@ -232,7 +246,7 @@ impl Version {
             s.push_str(&format!(".{}", extra_num));
         }
         if let Some((modifier, num)) = self.modifier.clone() {
-            s.push_str(&format!("{}{}", modifier.to_string(), num));
+            s.push_str(&format!("{}{}", modifier, num));
         }
     }
 
@@ -259,8 +273,7 @@ impl Version {
     }
 
     fn colorize(&self) -> anyhow::Result<String> {
-        let bufwtr = BufferWriter::stdout(CliConfig::current().color_choice);
-        let mut buf: Buffer = bufwtr.buffer();
+        let mut buf: Buffer = util::color::buffer();
         let num_c = Some(Color::Blue);
         let dot_c = Some(Color::Yellow); // Dark
 
@@ -410,6 +423,10 @@ impl fmt::Display for Version {
             if self.star && !star_handled {
                 version.push('*');
             }
+            if let Some(local) = &self.local {
+                version.push('+');
+                version.push_str(local);
+            }
         }
         write!(f, "{}", version)
     }
@@ -436,20 +453,20 @@ pub enum ReqType {
              // todo wildcard
 }
 
-impl ToString for ReqType {
+impl fmt::Display for ReqType {
     /// These show immediately before the version numbers
-    fn to_string(&self) -> String {
-        match self {
-            Self::Exact => "==".into(),
-            Self::Gte => ">=".into(),
-            Self::Lte => "<=".into(),
-            Self::Gt => ">".into(),
-            Self::Lt => "<".into(),
-            Self::Ne => "!=".into(),
-            Self::Caret => "^".into(),
-            Self::Tilde => "~".into(),
-            Self::TildeEq => "~=".into(),
-        }
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Exact => "==",
+            Self::Gte => ">=",
+            Self::Lte => "<=",
+            Self::Gt => ">",
+            Self::Lt => "<",
+            Self::Ne => "!=",
+            Self::Caret => "^",
+            Self::Tilde => "~",
+            Self::TildeEq => "~=",
+        })
     }
 }
 
@@ -505,19 +522,17 @@ impl Constraint {
 
     /// From a comma-separated list
     pub fn from_str_multiple(vers: &str) -> Result<Vec<Self>, DependencyError> {
-        let mut result = vec![];
-        let vers = vers.replace(' ', "");
-        let vers = if vers.is_empty() {
-            ">=2.0".to_string()
+        // Only copy the string if there are spaces to remove.
+        let vers: Cow<str> = if vers.contains(' ') {
+            Cow::Owned(vers.replace(' ', ""))
         } else {
-            vers
+            Cow::Borrowed(vers)
         };
+        let vers = if vers.is_empty() { ">=2.0" } else { &vers };
 
+        let mut result = Vec::with_capacity(vers.matches(',').count() + 1);
         for req in vers.split(',') {
-            match Self::from_str(req) {
-                Ok(r) => result.push(r),
-                Err(e) => return Err(e),
-            }
+            result.push(Self::from_str(req)?);
         }
         Ok(result)
     }
@@ -552,6 +567,18 @@ impl Constraint {
         format!("{}{}", type_str, self.version)
     }
 
+    /// A PEP 440 version specifier, eg for a wheel's `Requires-Dist`. There's no `^` or `~`, so
+    /// those become a range. `None` if it allows any version.
+    pub fn to_pep440_string(&self) -> Option<String> {
+        self.version.major?;
+        Some(match self.type_ {
+            ReqType::Caret | ReqType::Tilde => {
+                format!(">={},<{}", self.version, self.get_max_version())
+            }
+            _ => self.to_string2(false, true),
+        })
+    }
+
     /// Find the lowest and highest compatible versions. Return a vec, since the != requirement type
     /// has two ranges.
     pub fn compatible_range(&self) -> Vec<(Version, Version)> {
@@ -745,7 +772,7 @@ impl Constraint {
 
 impl fmt::Display for Constraint {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}{}", self.type_.to_string(), self.version)
+        write!(f, "{}{}", self.type_, self.version)
     }
 }
 
@@ -781,7 +808,7 @@ fn intersection_many2(reqs: &[(Version, Version)]) -> Vec<(Version, Version)> {
     // TODO: Broken for notequals, which involves joining two ranges with OR logic.
     let init = vec![(Version::new(0, 0, 0), Version::new(MAX_VER, 0, 0))];
     reqs.iter().fold(init, |acc, constraint_set| {
-        intersection(&[constraint_set.clone()], &acc)
+        intersection(std::slice::from_ref(constraint_set), &acc)
     })
 }
 
@@ -811,15 +838,15 @@ pub fn intersection(
 pub struct Extras {
     pub extra: Option<String>,
     pub sys_platform: Option<(ReqType, util::Os)>,
-    pub python_version: Option<Constraint>,
+    pub python_version: Option<Vec<Constraint>>,
 }
 
 impl Extras {
-    pub const fn new_py(python_version: Constraint) -> Self {
+    pub fn new_py(python_version: Constraint) -> Self {
         Self {
             extra: None,
             sys_platform: None,
-            python_version: Some(python_version),
+            python_version: Some(vec![python_version]),
         }
     }
 }
@@ -833,7 +860,28 @@ pub struct Req {
     pub python_version: Option<Vec<Constraint>>,
     pub install_with_extras: Option<Vec<String>>,
     pub path: Option<String>,
-    pub git: Option<String>, // String is the git repo. // TODO: Branch
+    pub git: Option<String>, // String is the git repo.
+    pub git_ref: Option<GitRef>,
+}
+
+/// What to check out of a git dependency's repo, as set by `rev`, `tag`, or `branch` in
+/// `pyproject.toml`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub enum GitRef {
+    Rev(String),
+    Tag(String),
+    Branch(String),
+}
+
+impl GitRef {
+    /// The `pyproject.toml` key, and what to pass to `git checkout`.
+    pub fn parts(&self) -> (&str, &str) {
+        match self {
+            Self::Rev(r) => ("rev", r),
+            Self::Tag(t) => ("tag", t),
+            Self::Branch(b) => ("branch", b),
+        }
+    }
 }
 
 impl Req {
@@ -847,6 +895,7 @@ impl Req {
             install_with_extras: None,
             path: None,
             git: None,
+            git_ref: None,
         }
     }
 
@@ -856,10 +905,11 @@ impl Req {
             constraints,
             extra: extras.extra,
             sys_platform: extras.sys_platform,
-            python_version: extras.python_version.map(|x| vec![x]),
+            python_version: extras.python_version,
             install_with_extras: None,
             path: None,
             git: None,
+            git_ref: None,
         }
     }
 
@@ -873,6 +923,26 @@ impl Req {
         .map(|x| x.1)
     }
 
+    /// Parse a requirement passed on the command line, in PEP 508 form, eg
+    /// `uvicorn[standard]>=0.23`. Surrounding quotes are stripped, since Windows `cmd`
+    /// passes single quotes through literally.
+    pub fn from_cli_str(s: &str) -> Result<Self, DependencyError> {
+        let s = s.trim();
+        let s = if s.len() >= 2
+            && ((s.starts_with('"') && s.ends_with('"'))
+                || (s.starts_with('\'') && s.ends_with('\'')))
+        {
+            &s[1..s.len() - 1]
+        } else {
+            s
+        };
+
+        all_consuming(parse_req_cli)(s)
+            .or_else(|_| all_consuming(parse_req)(s))
+            .map_err(|_| DependencyError::new(&format!("Problem parsing requirement: {}", s)))
+            .map(|x| x.1)
+    }
+
     /// We use this for parsing requirements.txt.
     pub fn from_pip_str(s: &str) -> Option<Self> {
         // todo multiple ie single quotes support?
@@ -891,11 +961,7 @@ impl Req {
         let py_ver = Constraint::from_wh_py_vers(&release.python_version);
         let requires = if let Some(x) = release.requires_python {
             if x.as_str() > "" {
-                if let Ok(c) = Constraint::from_str_multiple(&x) {
-                    Some(c)
-                } else {
-                    None
-                }
+                Constraint::from_str_multiple(&x).ok()
             } else {
                 None
             }
@@ -913,6 +979,7 @@ impl Req {
             install_with_extras: None,
             path: None,
             git: None,
+            git_ref: None,
         }
     }
 
@@ -931,12 +998,44 @@ impl Req {
             install_with_extras: self.install_with_extras.clone(),
             path: self.path.clone(),
             git: self.path.clone(),
+            git_ref: self.git_ref.clone(),
         }
     }
 
-    /// eg `saturn = "^0.3.1"` or `matplotlib = "3.1.1"`
+    /// eg `saturn = "^0.3.1"` or `matplotlib = "3.1.1"`. If extras are specified, use the
+    /// inline-table form: `uvicorn = { version = "^0.23", extras = ["standard"] }`.
     pub fn to_cfg_string(&self) -> String {
-        match self.constraints.len() {
+        let (name, value) = self.to_cfg_kv();
+        toml_kv(&name, &value)
+    }
+
+    /// The key and value `to_cfg_string` writes.
+    pub fn to_cfg_kv(&self) -> (String, TomlValue) {
+        let extras = self
+            .install_with_extras
+            .clone()
+            .filter(|extras| !extras.is_empty())
+            .map(|extras| ("extras".to_owned(), extras.into()));
+
+        // Git and path dependencies aren't on PyPi, so have no version to look up.
+        let source = if let Some(repo) = &self.git {
+            let mut fields = vec![("git".to_owned(), repo.as_str().into())];
+            if let Some(git_ref) = &self.git_ref {
+                let (key, value) = git_ref.parts();
+                fields.push((key.to_owned(), value.into()));
+            }
+            Some(fields)
+        } else {
+            self.path
+                .as_ref()
+                .map(|path| vec![("path".to_owned(), path.as_str().into())])
+        };
+        if let Some(mut fields) = source {
+            fields.extend(extras);
+            return (self.name.clone(), TomlValue::Table(fields));
+        }
+
+        let (name, constrs) = match self.constraints.len() {
             0 => {
                 let (name, latest_version) = if let Ok((fmtd_name, version, _)) =
                     res::get_version_info(
@@ -954,21 +1053,27 @@ impl Req {
                 } else {
                     util::abort(&format!("Unable to find version info for {:?}", &self.name));
                 };
-                format!(
-                    r#"{} = "{}""#,
+                (
                     name,
-                    Constraint::new(ReqType::Caret, latest_version).to_string2(true, false)
+                    Constraint::new(ReqType::Caret, latest_version).to_string2(true, false),
                 )
             }
-            _ => format!(
-                r#"{} = "{}""#,
-                self.name,
+            _ => (
+                self.name.clone(),
                 self.constraints
                     .iter()
                     .map(|r| r.to_string2(true, false))
                     .collect::<Vec<String>>()
-                    .join(", ")
+                    .join(", "),
+            ),
+        };
+
+        match extras {
+            Some(extras) => (
+                name,
+                TomlValue::Table(vec![("version".to_owned(), constrs.into()), extras]),
             ),
+            None => (name, constrs.into()),
         }
     }
 
@@ -996,6 +1101,84 @@ impl Req {
         )
         .replace(['^', '~'], ">") // TODO: Sloppy, but perhaps the best way.
     }
+
+    /// A PEP 508 requirement, eg for a wheel's `Requires-Dist`:
+    /// `uvicorn[standard] (>=0.23,<0.24) ; python_version >= "3.8"`, only required with `extra`
+    /// if given. Version specifiers are in parentheses, which PEP 508 allows, and which
+    /// `Req::from_str` reads back. Git dependencies point at their repo; path dependencies only
+    /// have a name, since the path means nothing once installed.
+    pub fn to_pep508_string(&self, extra: Option<&str>) -> String {
+        let mut result = self.name.clone();
+        if let Some(extras) = &self.install_with_extras {
+            if !extras.is_empty() {
+                result.push_str(&format!("[{}]", extras.join(",")));
+            }
+        }
+        if let Some(repo) = &self.git {
+            result.push_str(&format!(" @ git+{}", repo));
+            if let Some(git_ref) = &self.git_ref {
+                result.push_str(&format!("@{}", git_ref.parts().1));
+            }
+        } else {
+            let specs: Vec<String> = self
+                .constraints
+                .iter()
+                .filter_map(Constraint::to_pep440_string)
+                .collect();
+            if !specs.is_empty() {
+                result.push_str(&format!(" ({})", specs.join(",")));
+            }
+        }
+        let extra = extra.map(|e| format!(r#"extra == "{}""#, e));
+        let marker = match (self.marker(), extra) {
+            (Some(m), Some(e)) => Some(format!("{} and {}", m, e)),
+            (m, e) => m.or(e),
+        };
+        if let Some(marker) = marker {
+            // A space before `;` keeps it from being read as part of a URL.
+            result.push_str(" ; ");
+            result.push_str(&marker);
+        }
+        result
+    }
+
+    /// A PEP 508 marker for a requirement's platform and Python version limits.
+    pub fn marker(&self) -> Option<String> {
+        let mut clauses = vec![];
+        if let Some((type_, os)) = &self.sys_platform {
+            let platform = match os {
+                util::Os::Linux | util::Os::Linux32 => Some("linux"),
+                util::Os::Windows | util::Os::Windows32 => Some("win32"),
+                util::Os::Mac => Some("darwin"),
+                util::Os::Any => None,
+            };
+            if let Some(p) = platform {
+                let op = if *type_ == ReqType::Ne { "!=" } else { "==" };
+                clauses.push(format!(r#"sys_platform {} "{}""#, op, p));
+            }
+        }
+        for constr in self.python_version.iter().flatten() {
+            clauses.extend(python_clauses(constr));
+        }
+        if clauses.is_empty() {
+            None
+        } else {
+            Some(clauses.join(" and "))
+        }
+    }
+}
+
+/// PEP 508 has no `^` or `~`, so those become a range.
+fn python_clauses(constr: &Constraint) -> Vec<String> {
+    let clause = |op: &str, v: &Version| format!(r#"python_version {} "{}""#, op, v);
+    match constr.type_ {
+        ReqType::Caret | ReqType::Tilde => constr
+            .compatible_range()
+            .first()
+            .map(|(min, max)| vec![clause(">=", min), clause("<", max)])
+            .unwrap_or_default(),
+        _ => vec![clause(&constr.type_.to_string(), &constr.version)],
+    }
 }
 
 impl fmt::Display for Req {
@@ -1004,8 +1187,7 @@ impl fmt::Display for Req {
         for constr in &self.constraints {
             constraints.push_str(&format!("{}", constr));
         }
-        let bufwtr = BufferWriter::stdout(CliConfig::current().color_choice);
-        let mut buf = bufwtr.buffer();
+        let mut buf = util::color::buffer();
         if let Err(_e) = buf.set_color(ColorSpec::new().set_fg(Some(Color::Cyan))) {
             // Dark
             panic!("An Error occurred formatting Req")
@@ -1021,6 +1203,7 @@ impl fmt::Display for Req {
 }
 
 #[derive(Clone, Debug)]
+#[allow(dead_code)]
 pub enum Rename {
     No,
     // TODO: May not need to store self id.
@@ -1057,6 +1240,7 @@ pub struct LockPackage {
 pub struct Lock {
     pub package: Option<Vec<LockPackage>>,
     //    pub metadata: Option<Vec<String>>, // ie checksums
+    #[serde(default)]
     pub metadata: HashMap<String, String>, // ie checksums
 }
 
@@ -1068,6 +1252,52 @@ pub mod tests {
 
     use super::*;
 
+    #[test]
+    fn markers() {
+        let mut req = Req::new("uvloop".into(), vec![]);
+        req.sys_platform = Some((Ne, util::Os::Windows));
+        req.python_version = Some(vec![Constraint::new(Gte, Version::new_short(3, 8))]);
+        assert_eq!(
+            req.marker(),
+            Some(r#"sys_platform != "win32" and python_version >= "3.8""#.into())
+        );
+        assert_eq!(Req::new("idna".into(), vec![]).marker(), None);
+    }
+
+    #[test]
+    fn pep508() {
+        let mut req = Req::new(
+            "numpy".into(),
+            vec![Constraint::new(Caret, Version::new(1, 16, 4))],
+        );
+        req.python_version = Some(vec![Constraint::new(Gte, Version::new_short(3, 8))]);
+        assert_eq!(
+            req.to_pep508_string(None),
+            r#"numpy (>=1.16.4,<2.0.0) ; python_version >= "3.8""#
+        );
+
+        let mut req = Req::new(
+            "uvicorn".into(),
+            vec![
+                Constraint::new(Gte, Version::new_short(0, 23)),
+                Constraint::new(Ne, Version::new(0, 23, 1)),
+            ],
+        );
+        req.install_with_extras = Some(vec!["standard".into()]);
+        assert_eq!(
+            req.to_pep508_string(None),
+            "uvicorn[standard] (>=0.23,!=0.23.1)"
+        );
+
+        let mut req = Req::new("jeejah".into(), vec![]);
+        req.git = Some("https://github.com/raz/jeejah".into());
+        req.git_ref = Some(GitRef::Tag("v1.0".into()));
+        assert_eq!(
+            req.to_pep508_string(None),
+            "jeejah @ git+https://github.com/raz/jeejah@v1.0"
+        );
+    }
+
     #[rstest(
         req,
         max_compat,
@@ -1229,6 +1459,7 @@ pub mod tests {
                 extra_num: Some(MAX_VER),
                 modifier: Some((VersionModifier::Beta, 1)),
                 star: false,
+                local: None,
             },
             Version::new_star(None, None, None, false)
         ),
@@ -1259,6 +1490,7 @@ pub mod tests {
                 patch: Some(3),
                 extra_num: None,
                 modifier: None,
+                local: None,
                 star:true}),
             Version{
                 major: Some(1),
@@ -1267,6 +1499,7 @@ pub mod tests {
                 extra_num: Some(MAX_VER),
                 modifier: Some((VersionModifier::Beta, 1)),
                 star: false,
+                local: None,
             },
             Version::new(1, 3, 0)
         ),
@@ -1363,6 +1596,7 @@ pub mod tests {
                 extra_num: None,
                 modifier: Some((Beta, 0)),
                 star: false,
+                local: None,
             }
         );
 
@@ -1375,6 +1609,7 @@ pub mod tests {
                 extra_num: None,
                 modifier: Some((ReleaseCandidate, 0)),
                 star: false,
+                local: None,
             }
         );
 
@@ -1387,6 +1622,7 @@ pub mod tests {
                 extra_num: Some(11),
                 modifier: None,
                 star: false,
+                local: None,
             }
         );
 
@@ -1399,6 +1635,7 @@ pub mod tests {
                 extra_num: Some(11),
                 modifier: Some((Beta, 3)),
                 star: false,
+                local: None,
             }
         );
     }
@@ -1428,6 +1665,7 @@ pub mod tests {
                 extra_num: None,
                 modifier: Some((Beta, 3)),
                 star: false,
+                local: None,
             },
         );
         let req_b = Constraint::new(
@@ -1439,6 +1677,7 @@ pub mod tests {
                 extra_num: None,
                 modifier: Some((ReleaseCandidate, 1)),
                 star: false,
+                local: None,
             },
         );
         let req_c = Constraint::new(
@@ -1450,6 +1689,7 @@ pub mod tests {
                 extra_num: None,
                 modifier: Some((Dep, 1)),
                 star: false,
+                local: None,
             },
         );
 
@@ -1519,6 +1759,7 @@ pub mod tests {
             install_with_extras: None,
             path: None,
             git: None,
+            git_ref: None,
         };
 
         let actual2 = Req::from_str(
@@ -1536,6 +1777,7 @@ pub mod tests {
             install_with_extras: None,
             path: None,
             git: None,
+            git_ref: None,
         };
 
         let actual3 = Req::from_str(
@@ -1553,6 +1795,7 @@ pub mod tests {
             install_with_extras: None,
             path: None,
             git: None,
+            git_ref: None,
         };
 
         let actual4 = Req::from_str("envisage ; extra == 'app'", true).unwrap();
@@ -1567,6 +1810,7 @@ pub mod tests {
             install_with_extras: None,
             path: None,
             git: None,
+            git_ref: None,
         };
 
         assert_eq!(actual, expected);
@@ -1593,6 +1837,7 @@ pub mod tests {
             install_with_extras: None,
             path: None,
             git: None,
+            git_ref: None,
         };
 
         let expected2 = Req {
@@ -1607,6 +1852,7 @@ pub mod tests {
             install_with_extras: None,
             path: None,
             git: None,
+            git_ref: None,
         };
 
         assert_eq!(actual1, expected1);
@@ -1801,6 +2047,51 @@ pub mod tests {
         assert_eq!(req.to_cfg_string(), expected.to_string());
     }
 
+    #[test]
+    fn req_to_cfg_string_install_with_extras() {
+        let mut req = Req::new(
+            "uvicorn".to_string(),
+            vec![Constraint::new(Gte, Version::new(0, 23, 0))],
+        );
+        req.install_with_extras = Some(vec!["standard".to_string(), "watchfiles".to_string()]);
+        let expected = r#"uvicorn = { version = ">=0.23.0", extras = ["standard", "watchfiles"] }"#;
+        assert_eq!(req.to_cfg_string(), expected.to_string());
+    }
+
+    #[test]
+    fn req_to_cfg_string_git_and_path() {
+        let mut git = Req::new("mylib".to_string(), vec![]);
+        git.git = Some("https://github.com/raz/mylib".to_string());
+        git.git_ref = Some(GitRef::Tag("v1.2".to_string()));
+        assert_eq!(
+            git.to_cfg_string(),
+            r#"mylib = { git = "https://github.com/raz/mylib", tag = "v1.2" }"#
+        );
+
+        let mut path = Req::new("utils".to_string(), vec![]);
+        path.path = Some("../utils".to_string());
+        path.install_with_extras = Some(vec!["fast".to_string()]);
+        assert_eq!(
+            path.to_cfg_string(),
+            r#"utils = { path = "../utils", extras = ["fast"] }"#
+        );
+    }
+
+    #[rstest(
+        input,
+        case::double_quoted(r#""uvicorn[standard]>=0.23""#),
+        case::single_quoted("'uvicorn[standard]>=0.23'"),
+        case::unquoted("uvicorn[standard]>=0.23")
+    )]
+    fn req_from_cli_str(input: &str) {
+        let mut expected = Req::new(
+            "uvicorn".to_string(),
+            vec![Constraint::new(Gte, Version::new_short(0, 23))],
+        );
+        expected.install_with_extras = Some(vec!["standard".to_string()]);
+        assert_eq!(Req::from_cli_str(input), Ok(expected));
+    }
+
     #[test]
     fn version_ordering() {
         let a = Version::new(4, 9, 4);
@@ -1824,6 +2115,7 @@ pub mod tests {
             extra_num: Some(2),
             modifier: None,
             star: false,
+            local: None,
         };
         let b = Version::new(4, 9, 4);
 
@@ -1834,6 +2126,7 @@ pub mod tests {
             extra_num: None,
             modifier: Some((VersionModifier::ReleaseCandidate, 2)),
             star: false,
+            local: None,
         };
         let d = Version {
             major: Some(4),
@@ -1842,6 +2135,7 @@ pub mod tests {
             extra_num: None,
             modifier: Some((VersionModifier::ReleaseCandidate, 1)),
             star: false,
+            local: None,
         };
         let e = Version {
             major: Some(4),
@@ -1850,6 +2144,7 @@ pub mod tests {
             extra_num: None,
             modifier: Some((VersionModifier::Beta, 6)),
             star: false,
+            local: None,
         };
         let f = Version {
             major: Some(4),
@@ -1858,6 +2153,7 @@ pub mod tests {
             extra_num: None,
             modifier: Some((VersionModifier::Alpha, 7)),
             star: false,
+            local: None,
         };
         let g = Version::new(4, 9, 2);
 
@@ -2001,6 +2297,8 @@ pub mod tests {
                                 Constraint::new(Gte, Version::new(3, 0, 0)),
                             ]),
              case::pp36("pp36", vec![Constraint::new(Exact, Version::new(3, 6, 0))]),
+             case::cp310("cp310", vec![Constraint::new(Exact, Version::new(3, 10, 0))]),
+             case::pp310("pp310", vec![Constraint::new(Exact, Version::new(3, 10, 0))]),
              case::any("any", vec![Constraint::new(Gte, Version::new(2, 0, 0))]),
              case::semver("2.7", vec![Constraint::new(Caret, Version::new(2, 7, 0))]),
              case::pp257("pp257", vec![Constraint::new(Exact, Version::new(2, 5, 7))])
@@ -1,44 +1,104 @@
 use crate::{
     dep_types::{Req, Version},
-    util, Config,
+    util::{self, patterns::SECTION_HEADER_RE},
+    Config,
 };
 use regex::Regex;
+use serde::de::{Deserializer, MapAccess, Visitor};
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::fmt;
 use std::fs;
-use std::io::{BufRead, BufReader};
-use std::path::Path;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
 use termcolor::Color;
 
 #[derive(Debug, Deserialize)]
 pub struct Pipfile {
     // Pipfile doesn't use a prefix; assume `[packages]` and [`dev-packages`] sections
     // are from it, and use the same format as this tool and `Poetry`.
-    pub packages: Option<HashMap<String, DepComponentWrapper>>,
-    #[serde(rename = "dev-packages")]
-    pub dev_packages: Option<HashMap<String, DepComponentWrapper>>,
+    #[serde(default, deserialize_with = "entries")]
+    pub packages: Option<Vec<(String, DepComponentWrapper)>>,
+    #[serde(rename = "dev-packages", default, deserialize_with = "entries")]
+    pub dev_packages: Option<Vec<(String, DepComponentWrapper)>>,
+}
+
+/// A dependency table's entries, in the order they're written, so requirements keep the file's
+/// order, without building a `HashMap` first.
+fn entries<'de, D, V>(deserializer: D) -> Result<Option<Vec<(String, V)>>, D::Error>
+where
+    D: Deserializer<'de>,
+    V: Deserialize<'de>,
+{
+    struct Entries<V>(PhantomData<V>);
+
+    impl<'de, V: Deserialize<'de>> Visitor<'de> for Entries<V> {
+        type Value = Vec<(String, V)>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a table of dependencies")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut result = Vec::with_capacity(map.size_hint().unwrap_or(0));
+            while let Some(entry) = map.next_entry()? {
+                result.push(entry);
+            }
+            Ok(result)
+        }
+    }
+
+    deserializer.deserialize_map(Entries(PhantomData)).map(Some)
 }
 
 /// This nested structure is required based on how the `toml` crate handles dots.
 #[derive(Debug, Deserialize)]
 pub struct Pyproject {
     pub tool: Tool,
+    #[serde(rename = "build-system")]
+    pub build_system: Option<BuildSystem>,
+}
+
+/// PEP 518's `[build-system]` table.
+#[derive(Debug, Deserialize)]
+pub struct BuildSystem {
+    pub requires: Option<Vec<String>>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Tool {
     pub pyflow: Option<Pyflow>,
     pub poetry: Option<Poetry>,
+    pub flit: Option<Flit>,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(untagged)]
+#[allow(clippy::large_enum_variant)]
 /// Allows use of both Strings, ie "ipython = "^7.7.0", and maps: "ipython = {version = "^7.7.0", extras=["qtconsole"]}"
 pub enum DepComponentWrapper {
     A(String),
     B(DepComponent),
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+/// Allows `serve = "app.main:serve"`, `lint = "ruff check ."`, `ci = ["lint", "test"]`, and
+/// `serve = { run = "app.main:serve", env = { PORT = "8000" } }`
+pub enum ScriptComponentWrapper {
+    A(String),
+    B(ScriptComponent),
+    C(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScriptComponent {
+    pub run: String,
+    pub env: Option<HashMap<String, String>>,
+    pub cwd: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(untagged)]
 pub enum DepComponentWrapperPoetry {
@@ -47,35 +107,93 @@ pub enum DepComponentWrapperPoetry {
 }
 
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 pub struct DepComponent {
     #[serde(rename = "version")]
     pub constrs: Option<String>,
     pub extras: Option<Vec<String>>,
     pub path: Option<String>,
     pub git: Option<String>,
+    pub rev: Option<String>,
+    pub tag: Option<String>,
     pub branch: Option<String>,
     pub service: Option<String>,
     pub python: Option<String>,
+    pub sys_platform: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 pub struct DepComponentPoetry {
     #[serde(rename = "version")]
-    pub constrs: String,
+    pub constrs: Option<String>,
     pub python: Option<String>,
+    pub platform: Option<String>,
     pub extras: Option<Vec<String>>,
     pub optional: Option<bool>,
+    pub path: Option<String>,
+    pub git: Option<String>,
+    pub rev: Option<String>,
+    pub tag: Option<String>,
+    pub branch: Option<String>,
     // TODO: more fields
     //    pub repository: Option<String>,
-    //    pub branch: Option<String>,
-    //    pub service: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(untagged)]
+/// Allows `readme = "README.md"`, and `readme = { file = "README.txt", content-type = "text/x-rst" }`
+pub enum ReadmeWrapper {
+    A(String),
+    B(ReadmeTable),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReadmeTable {
+    pub file: String,
+    #[serde(rename = "content-type")]
+    pub content_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+/// Allows `build = "build.py"`, and a `[tool.pyflow.build]` table.
+pub enum BuildWrapper {
+    A(String),
+    B(BuildTable),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BuildTable {
+    pub script: Option<String>,
+    pub output: Option<String>,
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+/// Allows `version = "1.4.2"`, and `version = { from = "git" }`.
+pub enum VersionWrapper {
+    A(String),
+    B(VersionTable),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VersionTable {
+    pub from: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 pub struct Pyflow {
     pub py_version: Option<String>,
     pub name: Option<String>,
-    pub version: Option<String>,
+    pub version: Option<VersionWrapper>,
+    #[serde(rename = "dynamic-version")]
+    pub dynamic_version: Option<String>,
+    #[serde(rename = "fallback-version")]
+    pub fallback_version: Option<String>,
     pub authors: Option<Vec<String>>,
     pub license: Option<String>,
     pub description: Option<String>,
@@ -85,18 +203,44 @@ pub struct Pyflow {
     pub repository: Option<String>,
     pub repo_url: Option<String>,
     pub package_url: Option<String>,
-    pub readme: Option<String>,
-    pub build: Option<String>,
+    pub readme: Option<ReadmeWrapper>,
+    pub build: Option<BuildWrapper>,
     //    pub entry_points: Option<HashMap<String, Vec<String>>>,
-    pub scripts: Option<HashMap<String, String>>,
+    pub scripts: Option<HashMap<String, ScriptComponentWrapper>>,
+    pub env: Option<HashMap<String, String>>,
+    #[serde(rename = "env-file")]
+    pub env_file: Option<String>,
+    #[serde(rename = "run-from-root")]
+    pub run_from_root: Option<bool>,
+    #[serde(rename = "allow-user-site")]
+    pub allow_user_site: Option<bool>,
+    #[serde(rename = "index-url")]
+    pub index_url: Option<String>,
+    #[serde(rename = "constraint-files")]
+    pub constraint_files: Option<Vec<String>>,
+    #[serde(rename = "package-dir")]
+    pub package_dir: Option<String>,
+    #[serde(rename = "version-file")]
+    pub version_file: Option<String>,
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
     pub python_requires: Option<String>,
-    pub dependencies: Option<HashMap<String, DepComponentWrapper>>,
-    #[serde(rename = "dev-dependencies")]
-    pub dev_dependencies: Option<HashMap<String, DepComponentWrapper>>,
-    pub extras: Option<HashMap<String, String>>,
+    pub hooks: Option<crate::pyproject::Hooks>,
+    pub audit: Option<crate::pyproject::Audit>,
+    pub publish: Option<crate::pyproject::Publish>,
+    pub python: Option<String>,
+    pub python_implementation: Option<String>,
+    #[serde(rename = "env-mode")]
+    pub env_mode: Option<String>,
+    #[serde(default, deserialize_with = "entries")]
+    pub dependencies: Option<Vec<(String, DepComponentWrapper)>>,
+    #[serde(rename = "dev-dependencies", default, deserialize_with = "entries")]
+    pub dev_dependencies: Option<Vec<(String, DepComponentWrapper)>>,
+    pub extras: Option<HashMap<String, Vec<String>>>,
 }
 
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 pub struct Poetry {
     pub name: Option<String>,
     pub version: Option<String>,
@@ -113,23 +257,50 @@ pub struct Poetry {
     pub packages: Option<Vec<HashMap<String, String>>>,
     pub include: Option<Vec<String>>,
     pub exclude: Option<Vec<String>>,
-    pub extras: Option<HashMap<String, String>>,
+    pub extras: Option<HashMap<String, Vec<String>>>,
 
-    pub dependencies: Option<HashMap<String, DepComponentWrapperPoetry>>,
-    pub dev_dependencies: Option<HashMap<String, DepComponentWrapperPoetry>>,
+    #[serde(default, deserialize_with = "entries")]
+    pub dependencies: Option<Vec<(String, DepComponentWrapperPoetry)>>,
+    #[serde(rename = "dev-dependencies", default, deserialize_with = "entries")]
+    pub dev_dependencies: Option<Vec<(String, DepComponentWrapperPoetry)>>,
     // TODO: Include these
     //    pub source: Option<HashMap<String, String>>,
     pub scripts: Option<HashMap<String, String>>,
     //    pub extras: Option<HashMap<String, String>>,
 }
 
+/// Flit's `[tool.flit]` tables, from before it used PEP 621's `[project]`.
+#[derive(Debug, Deserialize)]
+pub struct Flit {
+    pub metadata: Option<FlitMetadata>,
+    pub scripts: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FlitMetadata {
+    pub module: Option<String>,
+    pub dist_name: Option<String>,
+    pub author: Option<String>,
+    pub author_email: Option<String>,
+    pub home_page: Option<String>,
+    pub description_file: Option<String>,
+    pub license: Option<String>,
+    pub classifiers: Option<Vec<String>>,
+    pub keywords: Option<String>, // Comma-separated
+    pub requires: Option<Vec<String>>,
+    pub requires_extra: Option<HashMap<String, Vec<String>>>,
+    pub dev_requires: Option<Vec<String>>,
+    pub requires_python: Option<String>,
+}
+
 /// Encapsulate one section of the `pyproject.toml`.
 ///
 /// # Attributes:
 /// * lines: A vector containing each line of the section
 /// * i_start: Zero-indexed indicating the line of the header.
 /// * i_end: Zero-indexed indicating the line number of the next section header,
-///     or the last line of the file.
+///   or the last line of the file.
 struct Section {
     lines: Vec<String>,
     i_start: usize,
@@ -138,15 +309,12 @@ struct Section {
 
 /// Identify the start index, end index, and lines of a particular section.
 fn collect_section(cfg_lines: &[String], title: &str) -> Option<Section> {
-    // This will tell us when we've reached a new section
-    let section_re = Regex::new(r"^\[.*\]$").unwrap();
-
     let mut existing_entries = Vec::new();
     let mut in_section = false;
     let mut i_start = 0usize;
 
     for (i, line) in cfg_lines.iter().enumerate() {
-        if in_section && section_re.is_match(line) {
+        if in_section && SECTION_HEADER_RE.is_match(line) {
             return Some(Section {
                 lines: existing_entries,
                 i_start,
@@ -196,8 +364,23 @@ fn extend_or_insert(mut cfg_lines: Vec<String>, section_header: &str, reqs: &[Re
                 .cloned()
                 .collect();
 
+            // Replace the line for a dependency that's already listed, eg when changing its
+            // version or adding extras; otherwise append it.
             for req in reqs {
-                all_deps.push(req.to_cfg_string())
+                let existing = all_deps.iter().position(|line| {
+                    // Names with dots are quoted.
+                    let key = line
+                        .split('=')
+                        .next()
+                        .unwrap_or("")
+                        .trim()
+                        .trim_matches('"');
+                    !line.trim_start().starts_with('#') && util::compare_names(key, &req.name)
+                });
+                match existing {
+                    Some(i) => all_deps[i] = req.to_cfg_string(),
+                    None => all_deps.push(req.to_cfg_string()),
+                }
             }
             all_deps.push("".into());
 
@@ -257,118 +440,470 @@ fn update_cfg(cfg_data: &str, added: &[Req], added_dev: &[Req]) -> String {
 /// we should update the version. Assume we've already parsed the config, and are only
 /// adding new reqs, or ones with a changed version.
 pub fn add_reqs_to_cfg(cfg_path: &Path, added: &[Req], added_dev: &[Req]) {
-    let data = fs::read_to_string(cfg_path)
+    let data = util::loaded::read_to_string(cfg_path)
         .expect("Unable to read pyproject.toml while attempting to add a dependency");
 
     let updated = update_cfg(&data, added, added_dev);
-    fs::write(cfg_path, updated)
+    util::loaded::write(cfg_path, updated)
         .expect("Unable to write pyproject.toml while attempting to add a dependency");
 }
 
-/// Remove dependencies from pyproject.toml.
-pub fn remove_reqs_from_cfg(cfg_path: &Path, reqs: &[String]) {
-    // TODO: Handle removing dev deps.
-    // TODO: DRY from parsing the config.
-    let mut result = String::new();
-    let data = fs::read_to_string(cfg_path)
-        .expect("Unable to read pyproject.toml while attempting to add a dependency");
+/// Where a dependency is declared in `pyproject.toml`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DepTable {
+    Main,
+    Dev,
+    /// A group under `[tool.pyflow.extras]`.
+    Extras(String),
+}
 
-    let mut in_dep = false;
-    let mut _in_dev_dep = false;
-    let sect_re = Regex::new(r"^\[.*\]$").unwrap();
+impl DepTable {
+    pub fn header(&self) -> &str {
+        match self {
+            Self::Main => "[tool.pyflow.dependencies]",
+            Self::Dev => "[tool.pyflow.dev-dependencies]",
+            Self::Extras(_) => "[tool.pyflow.extras]",
+        }
+    }
+}
 
-    for line in data.lines() {
-        if line.starts_with('#') || line.is_empty() {
-            // todo handle mid-line comements
-            result.push_str(line);
-            result.push('\n');
-            continue;
+impl fmt::Display for DepTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Main => write!(f, "dependencies"),
+            Self::Dev => write!(f, "dev-dependencies"),
+            Self::Extras(group) => write!(f, "the `{}` extras", group),
         }
+    }
+}
 
-        if line == "[tool.pyflow.dependencies]" {
-            in_dep = true;
-            _in_dev_dep = false;
-            result.push_str(line);
-            result.push('\n');
-            continue;
+/// Remove dependencies from one table in pyproject.toml, leaving the rest of the file as-is.
+pub fn remove_reqs_from_cfg(cfg_path: &Path, names: &[String], table: &DepTable) {
+    let data = util::loaded::read_to_string(cfg_path)
+        .expect("Unable to read pyproject.toml while attempting to remove a dependency");
+
+    let updated = match table {
+        DepTable::Extras(group) => remove_from_extras(&data, group, names),
+        _ => remove_from_section(&data, table.header(), names),
+    };
+    util::loaded::write(cfg_path, updated)
+        .expect("Unable to write to pyproject.toml while attempting to remove a dependency");
+}
+
+/// The package name a line in a dependency table, or an entry in an extras group, is for.
+fn entry_name(entry: &str) -> &str {
+    let entry = entry.trim().trim_matches('"').trim_matches('\'');
+    let end = entry
+        .find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_' || c == '.'))
+        .unwrap_or(entry.len());
+    &entry[..end]
+}
+
+/// Drop lines for `names` in the table under `section_header`. Split from
+/// `remove_reqs_from_cfg` to accommodate testing.
+fn remove_from_section(cfg_data: &str, section_header: &str, names: &[String]) -> String {
+    let mut in_section = false;
+    let mut result = vec![];
+
+    for line in cfg_data.lines() {
+        if SECTION_HEADER_RE.is_match(line.trim()) {
+            in_section = line.trim() == section_header;
+        } else if in_section && !line.trim_start().starts_with('#') {
+            let key = line.split('=').next().unwrap_or("");
+            if names
+                .iter()
+                .any(|n| util::compare_names(entry_name(key), n))
+            {
+                continue;
+            }
+        }
+        result.push(line);
+    }
+    result.join("\n") + "\n"
+}
+
+/// Drop `names` from an extras group, eg `test = ["pytest", "nose"]`. The array may span lines;
+/// the entries kept, and any comments, are left as they're written.
+fn remove_from_extras(cfg_data: &str, group: &str, names: &[String]) -> String {
+    let mut in_section = false;
+    let mut result = vec![];
+    let mut lines = cfg_data.lines();
+
+    while let Some(line) = lines.next() {
+        if SECTION_HEADER_RE.is_match(line.trim()) {
+            in_section = line.trim() == DepTable::Extras(group.into()).header();
+        } else if in_section {
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim().trim_matches('"');
+            if let (true, Some(value)) = (key == group, parts.next()) {
+                let mut array = vec![line.to_owned()];
+                let start = line.len() - value.len();
+                while !array_strings(&array.join("\n")[start..]).1 {
+                    match lines.next() {
+                        Some(l) => array.push(l.to_owned()),
+                        None => break,
+                    }
+                }
+                result.extend(drop_entries(&array, start, names));
+                continue;
+            }
         }
+        result.push(line.to_owned());
+    }
+    result.join("\n") + "\n"
+}
 
-        if line == "[tool.pyflow.dev-dependencies]" {
-            in_dep = true;
-            _in_dev_dep = false;
-            result.push_str(line);
-            result.push('\n');
-            continue;
+/// The quoted strings in a TOML array, as byte ranges including their quotes, skipping comments;
+/// and whether its closing bracket was found.
+fn array_strings(text: &str) -> (Vec<(usize, usize)>, bool) {
+    let mut strings = vec![];
+    let mut depth = 0;
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '#' => {
+                chars.find(|(_, c)| *c == '\n');
+            }
+            '"' | '\'' => {
+                let mut escaped = false;
+                let end = chars.find(|(_, next)| {
+                    let closes = *next == c && !escaped;
+                    escaped = c == '"' && *next == '\\' && !escaped;
+                    closes
+                });
+                match end {
+                    Some((j, _)) => strings.push((i, j + 1)),
+                    None => return (strings, false),
+                }
+            }
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return (strings, true);
+                }
+            }
+            _ => (),
         }
+    }
+    (strings, false)
+}
 
-        if in_dep {
-            if sect_re.is_match(line) {
-                in_dep = false;
+/// `array`'s lines, with the entries for `names` removed, along with their commas. The array
+/// starts at byte `start` of the first line. Lines left empty are dropped.
+fn drop_entries(array: &[String], start: usize, names: &[String]) -> Vec<String> {
+    let text = array.join("\n");
+    let (strings, _) = array_strings(&text[start..]);
+    let is_space = |c: u8| c == b' ' || c == b'\t';
+    let bytes = text.as_bytes();
+
+    let mut spans = vec![];
+    for (s, e) in strings {
+        let (mut s, mut e) = (s + start, e + start);
+        let entry = &text[s + 1..e - 1];
+        if !names
+            .iter()
+            .any(|n| util::compare_names(entry_name(entry), n))
+        {
+            continue;
+        }
+        // The comma after it, or, for the last entry, the one before it, on the same line.
+        while e < bytes.len() && is_space(bytes[e]) {
+            e += 1;
+        }
+        if e < bytes.len() && bytes[e] == b',' {
+            e += 1;
+            while e < bytes.len() && is_space(bytes[e]) {
+                e += 1;
             }
-            // TODO: handle comments
-            let req_line = if let Ok(r) = Req::from_str(line, false) {
-                r
-            } else {
-                result.push_str(line);
-                result.push('\n');
-                continue; // Could be caused by a git etc req.
-                          //                util::abort(&format!(
-                          //                    "Can't parse this line in `pyproject.toml`: {}",
-                          //                    line
-                          //                ));
-                          //                unreachable!()
-            };
-
-            if reqs
-                .iter()
-                .map(|r| r.to_lowercase())
-                .any(|x| x == req_line.name.to_lowercase())
-            {
-                continue; // ie don't append this line to result.
+            if e == bytes.len() || bytes[e] == b'\n' {
+                while s > 0 && is_space(bytes[s - 1]) {
+                    s -= 1;
+                }
+            }
+        } else {
+            while s > 0 && is_space(bytes[s - 1]) {
+                s -= 1;
+            }
+            if s > 0 && bytes[s - 1] == b',' {
+                s -= 1;
             }
         }
-        result.push_str(line);
-        result.push('\n');
+        spans.push((s, e));
     }
 
-    fs::write(cfg_path, result)
-        .expect("Unable to write to pyproject.toml while attempting to add a dependency");
+    let mut updated = text.clone();
+    for (s, e) in spans.into_iter().rev() {
+        updated.replace_range(s..e, "");
+    }
+    // Spans never cross lines, so these pair up. A line whose entries were all removed goes,
+    // with any comment on it.
+    let has_code = |line: &str| {
+        let strings = array_strings(line).0;
+        let rest = strings.last().map_or(line, |&(_, e)| &line[e..]);
+        !strings.is_empty() || !rest.split('#').next().unwrap_or("").trim().is_empty()
+    };
+    updated
+        .lines()
+        .zip(array)
+        .filter(|(new, old)| has_code(new) || !has_code(old))
+        .map(|(new, _)| new.to_owned())
+        .collect()
 }
 
-pub fn parse_req_dot_text(cfg: &mut Config, path: &Path) {
-    let file = match fs::File::open(path) {
-        Ok(f) => f,
-        Err(_) => return,
+/// What a pip requirements file, and the files it includes, ask for.
+#[derive(Debug, Default)]
+pub struct ReqsFile {
+    pub reqs: Vec<Req>,
+    /// From `-c` files: versions to use for these packages if something needs them, but not
+    /// requirements themselves.
+    pub constraints: Vec<Req>,
+    pub index_url: Option<String>,
+    pub extra_index_urls: Vec<String>,
+    /// The sha256 hashes allowed for each requirement, by standardized name, from `--hash`
+    /// options, as pip-compile writes them.
+    pub hashes: HashMap<String, Vec<String>>,
+    /// What needs each requirement, by standardized name, from pip-compile's `# via` comments.
+    /// Other requirements files, eg `-r requirements.in`, are left out.
+    pub via: HashMap<String, Vec<String>>,
+    /// Lines we couldn't use, eg `requirements.txt:4: -e .`, and why.
+    pub skipped: Vec<String>,
+    /// The requirements among `skipped` we couldn't parse, eg for markers we don't evaluate,
+    /// like `platform_machine`.
+    pub unparsed: Vec<String>,
+}
+
+/// Parse a pip requirements file, following `-r` and `-c` includes, relative to the file that
+/// includes them. `None` if it can't be read.
+pub fn parse_requirements_file(path: &Path) -> Option<ReqsFile> {
+    let mut result = ReqsFile::default();
+    let mut seen = vec![];
+    if !read_requirements(path, false, &mut seen, &mut result) {
+        return None;
+    }
+    // A constraint pins a requirement that doesn't say which version it needs.
+    for req in result.reqs.iter_mut().filter(|r| r.constraints.is_empty()) {
+        if let Some(c) = result
+            .constraints
+            .iter()
+            .find(|c| util::compare_names(&c.name, &req.name))
+        {
+            req.constraints = c.constraints.clone();
+        }
+    }
+    Some(result)
+}
+
+/// A requirements file option, eg `-r base.txt` or `--index-url=https://...`.
+static OPTION_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(-[a-zA-Z]|--[\w-]+)(?:\s*=\s*|\s+)?(.*)$").unwrap());
+
+/// A pinned hash, eg `--hash=sha256:...`.
+static HASH_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"--hash[=\s]+sha256:([0-9a-fA-F]+)").unwrap());
+
+/// Add what's in one requirements file to `result`. `false` if it can't be read.
+fn read_requirements(
+    path: &Path,
+    constraints: bool,
+    seen: &mut Vec<PathBuf>,
+    result: &mut ReqsFile,
+) -> bool {
+    let data = match fs::read_to_string(path) {
+        Ok(d) => d,
+        Err(_) => return false,
     };
+    // Including a file that includes this one would never end.
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+    if seen.contains(&canonical) {
+        return true;
+    }
+    seen.push(canonical);
 
-    for line in BufReader::new(file).lines().map_while(Result::ok) {
-        match Req::from_pip_str(&line) {
-            Some(r) => {
-                cfg.reqs.push(r.clone());
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    if !constraints {
+        for (name, parents) in via_comments(&data) {
+            result.via.entry(name).or_default().extend(parents);
+        }
+    }
+
+    for (i, line) in logical_lines(&data) {
+        let location = format!("{}:{}", name, i + 1);
+        let caps = match OPTION_RE.captures(&line) {
+            Some(c) => c,
+            None => {
+                // Per-requirement options, like pip-compile's `--hash`s, don't change what's
+                // installed.
+                let spec = line.split(" --").next().unwrap_or_default().trim();
+                match parse_requirement(spec) {
+                    Some(r) if constraints => result.constraints.push(r),
+                    Some(r) => {
+                        let hashes: Vec<String> = HASH_RE
+                            .captures_iter(&line)
+                            .map(|c| c[1].to_lowercase())
+                            .collect();
+                        if !hashes.is_empty() {
+                            result
+                                .hashes
+                                .entry(util::standardize_name(&r.name))
+                                .or_default()
+                                .extend(hashes);
+                        }
+                        result.reqs.push(r)
+                    }
+                    None => {
+                        result
+                            .skipped
+                            .push(format!("{}: `{}`, which we couldn't parse", location, spec));
+                        result.unparsed.push(format!("{}: `{}`", location, spec));
+                    }
+                }
+                continue;
             }
-            None => util::print_color(
-                &format!("Problem parsing {} from requirements.txt", line),
-                Color::Red,
-            ),
         };
+        let value = caps[2].trim();
+        match &caps[1] {
+            "-r" | "--requirement" | "-c" | "--constraint" => {
+                let included = dir.join(value);
+                let as_constraints = constraints || matches!(&caps[1], "-c" | "--constraint");
+                if !read_requirements(&included, as_constraints, seen, result) {
+                    result
+                        .skipped
+                        .push(format!("{}: `{}`, which can't be read", location, line));
+                }
+            }
+            "-i" | "--index-url" => result.index_url = Some(value.to_owned()),
+            "--extra-index-url" => result.extra_index_urls.push(value.to_owned()),
+            _ => result.skipped.push(format!(
+                "{}: `{}`, which pyflow doesn't support",
+                location, line
+            )),
+        }
+    }
+    true
+}
+
+/// The package a `# via` comment lists.
+static VIA_NAME_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^([A-Za-z0-9][A-Za-z0-9._-]*)").unwrap());
+
+/// The parents pip-compile lists after each requirement, by standardized name: either
+/// `# via requests`, or `# via` followed by one per line.
+fn via_comments(data: &str) -> HashMap<String, Vec<String>> {
+    let mut result: HashMap<String, Vec<String>> = HashMap::new();
+    let mut current: Option<String> = None;
+    let mut in_via = false;
+    for line in data.lines() {
+        let comment = match line.trim_start().strip_prefix('#') {
+            Some(c) => c.trim(),
+            None => {
+                in_via = false;
+                // Continuations, like `--hash` lines, are indented.
+                if !line.starts_with(char::is_whitespace) {
+                    current = VIA_NAME_RE
+                        .captures(line)
+                        .map(|c| util::standardize_name(&c[1]));
+                }
+                continue;
+            }
+        };
+        let parent = match comment.strip_prefix("via") {
+            Some(rest) if rest.is_empty() || rest.starts_with(' ') => {
+                in_via = true;
+                rest.trim()
+            }
+            _ if in_via => comment,
+            _ => continue,
+        };
+        if let Some(name) = &current {
+            if !parent.is_empty() && !parent.starts_with('-') {
+                result
+                    .entry(name.clone())
+                    .or_default()
+                    .push(parent.to_owned());
+            }
+        }
+    }
+    result
+}
+
+/// Lines without comments, with `\` continuations joined, and their line numbers, from 0.
+fn logical_lines(data: &str) -> Vec<(usize, String)> {
+    let mut result = vec![];
+    let mut current: Option<(usize, String)> = None;
+    for (i, line) in data.lines().enumerate() {
+        let line = if line.trim_start().starts_with('#') {
+            ""
+        } else {
+            line.find(" #").map_or(line, |j| &line[..j])
+        };
+        let (start, mut text) = current.take().unwrap_or((i, String::new()));
+        match line.trim_end().strip_suffix('\\') {
+            Some(l) => {
+                text.push_str(l.trim());
+                text.push(' ');
+                current = Some((start, text));
+            }
+            None => {
+                text.push_str(line.trim());
+                let text = text.trim().to_owned();
+                if !text.is_empty() {
+                    result.push((start, text));
+                }
+            }
+        }
+    }
+    if let Some((start, text)) = current {
+        if !text.trim().is_empty() {
+            result.push((start, text.trim().to_owned()));
+        }
+    }
+    result
+}
+
+/// A requirement as pip writes it, eg `numpy==1.26.0`, `uvicorn[standard]>=0.23`, or
+/// `uvloop; sys_platform != "win32"`.
+pub fn parse_requirement(spec: &str) -> Option<Req> {
+    Req::from_pip_str(spec)
+        .or_else(|| Req::from_cli_str(spec).ok())
+        .or_else(|| Req::from_str(spec, true).ok())
+}
+
+pub fn parse_req_dot_text(cfg: &mut Config, path: &Path) {
+    let parsed = match parse_requirements_file(path) {
+        Some(p) => p,
+        None => return,
+    };
+    cfg.reqs.extend(parsed.reqs);
+    for line in parsed.skipped {
+        util::print_color(&format!("Skipped {}", line), Color::Yellow);
     }
 }
 
 /// Update the config file with a new version.
-pub fn change_py_vers(cfg_path: &Path, specified: &Version) {
-    let f = fs::File::open(cfg_path)
+pub fn change_py_vers(
+    cfg_path: &Path,
+    specified: &Version,
+    implementation: crate::py_versions::Implementation,
+) {
+    let data = util::loaded::read_to_string(cfg_path)
         .expect("Unable to read pyproject.toml while adding Python version");
     let mut new_data = String::new();
-    for line in BufReader::new(f).lines().map_while(Result::ok) {
+    for line in data.lines() {
         if line.starts_with("py_version") {
-            new_data.push_str(&format!("py_version = \"{}\"\n", specified));
+            new_data.push_str(&format!(
+                "py_version = \"{}{}\"\n",
+                implementation.prefix(),
+                specified
+            ));
         } else {
-            new_data.push_str(&line);
+            new_data.push_str(line);
             new_data.push('\n');
         }
     }
 
-    fs::write(cfg_path, new_data)
+    util::loaded::write(cfg_path, new_data)
         .expect("Unable to write pyproject.toml while adding Python version");
 }
 
@@ -376,6 +911,7 @@ pub fn change_py_vers(cfg_path: &Path, specified: &Version) {
 pub mod tests {
     use super::*;
     use crate::dep_types::{Constraint, ReqType::Caret};
+    use crate::util::temp_dir::TempDir;
 
     // We're not concerned with testing formatting in this func.
     fn base_constrs() -> Vec<Constraint> {
@@ -424,6 +960,31 @@ name = ""
 dev_a = "^1.17.2"
 "#;
 
+    #[test]
+    fn add_deps_replaces_existing() {
+        let mut with_extras = Req::new("a".into(), base_constrs());
+        with_extras.install_with_extras = Some(vec!["x".into()]);
+        let actual = update_cfg(
+            BASELINE,
+            &[with_extras],
+            &[Req::new("dev_b".into(), base_constrs())],
+        );
+
+        let expected = r#"
+[tool.pyflow]
+name = ""
+
+[tool.pyflow.dependencies]
+a = { version = "^0.0.1", extras = ["x"] }
+
+[tool.pyflow.dev-dependencies]
+dev_a = "^1.17.2"
+dev_b = "^0.0.1"
+"#;
+
+        assert_eq!(expected, &actual);
+    }
+
     #[test]
     fn add_deps_baseline() {
         let actual = update_cfg(
@@ -444,6 +1005,34 @@ a = "^0.3.5"
 b = "^0.0.1"
 c = "^0.0.1"
 
+[tool.pyflow.dev-dependencies]
+dev_a = "^1.17.2"
+dev_b = "^0.0.1"
+"#;
+
+        assert_eq!(expected, &actual);
+    }
+
+    /// A header with trailing whitespace still ends the section before it.
+    #[test]
+    fn add_deps_header_trailing_whitespace() {
+        let actual = update_cfg(
+            &BASELINE.replace(
+                "[tool.pyflow.dev-dependencies]",
+                "[tool.pyflow.dev-dependencies]  ",
+            ),
+            &[Req::new("b".into(), base_constrs())],
+            &[Req::new("dev_b".into(), base_constrs())],
+        );
+
+        let expected = r#"
+[tool.pyflow]
+name = ""
+
+[tool.pyflow.dependencies]
+a = "^0.3.5"
+b = "^0.0.1"
+
 [tool.pyflow.dev-dependencies]
 dev_a = "^1.17.2"
 dev_b = "^0.0.1"
@@ -530,4 +1119,103 @@ dev_b = "^0.0.1"
 "#;
         assert_eq!(expected, &actual);
     }
+
+    #[test]
+    fn remove_deps() {
+        let cfg = r#"[tool.pyflow.dependencies]
+numpy = "^1.17.2"
+Requests = { version = "^2.22", extras = ["socks"] }
+
+[tool.pyflow.dev-dependencies]
+requests = "^2.22"
+"#;
+        let expected = r#"[tool.pyflow.dependencies]
+numpy = "^1.17.2"
+
+[tool.pyflow.dev-dependencies]
+requests = "^2.22"
+"#;
+        let actual = remove_from_section(cfg, "[tool.pyflow.dependencies]", &["requests".into()]);
+        assert_eq!(expected, &actual);
+    }
+
+    #[test]
+    fn remove_extras() {
+        let cfg = r#"[tool.pyflow.extras]
+test = ["pytest>=5", "nose"]
+docs = ["sphinx"]
+"#;
+        let expected = r#"[tool.pyflow.extras]
+test = ["nose"]
+docs = ["sphinx"]
+"#;
+        let actual = remove_from_extras(cfg, "test", &["pytest".into()]);
+        assert_eq!(expected, &actual);
+    }
+
+    #[test]
+    fn remove_extras_multiline() {
+        let cfg = r#"[tool.pyflow.extras]
+test = [
+    'pytest>=5',  # The runner
+    "requests[socks]>=2", "nose",
+    "coverage"
+]
+docs = ['sphinx', "furo"]
+"#;
+
+        let expected = r#"[tool.pyflow.extras]
+test = [
+    "requests[socks]>=2",
+]
+docs = ['sphinx']
+"#;
+        let names = [
+            "pytest".into(),
+            "nose".into(),
+            "coverage".into(),
+            "furo".into(),
+        ];
+        let actual = remove_from_extras(cfg, "test", &names);
+        let actual = remove_from_extras(&actual, "docs", &names);
+        assert_eq!(expected, &actual);
+    }
+
+    #[test]
+    fn requirements_includes() {
+        let dir = TempDir::new("reqs");
+        fs::create_dir_all(dir.join("requirements")).unwrap();
+        fs::write(
+            dir.join("requirements/base.txt"),
+            "--index-url https://mirror.example.com/simple\n-c ../constraints.txt\nrequests\n\
+             uvloop; sys_platform != \"win32\"\n",
+        )
+        .unwrap();
+        fs::write(dir.join("constraints.txt"), "requests==2.31.0\nidna==3.6\n").unwrap();
+        fs::write(
+            dir.join("requirements/dev.txt"),
+            "-r base.txt\n-r dev.txt  # Cycles are ignored\npytest>=8.0 \\\n    --hash=sha256:abc\n\
+             -e .\n",
+        )
+        .unwrap();
+
+        let parsed = parse_requirements_file(&dir.join("requirements/dev.txt")).unwrap();
+
+        let names: Vec<&str> = parsed.reqs.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["requests", "uvloop", "pytest"]);
+        assert_eq!(
+            parsed.reqs[0].constraints,
+            parsed.constraints[0].constraints
+        );
+        assert!(parsed.reqs[1].sys_platform.is_some());
+        assert_eq!(parsed.constraints.len(), 2);
+        assert_eq!(
+            parsed.index_url.as_deref(),
+            Some("https://mirror.example.com/simple")
+        );
+        assert_eq!(
+            parsed.skipped,
+            vec!["dev.txt:5: `-e .`, which pyflow doesn't support".to_owned()]
+        );
+    }
 }
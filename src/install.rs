@@ -1,10 +1,19 @@
 use crate::util::print_color;
-use crate::{commands, dep_types::Version, util};
+use crate::{
+    commands,
+    dep_types::{GitRef, Version},
+    util::{self, prompts::Fallback},
+    CliConfig,
+};
 use flate2::read::GzDecoder;
 use regex::Regex;
 use ring::digest;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, Read, Write};
 use std::path::PathBuf;
-use std::{fs, io, io::BufRead, path::Path, process::Command};
+use std::str::FromStr;
+use std::sync::LazyLock;
+use std::{fs, io, path::Path, process, process::Command, time::Instant};
 use tar::Archive;
 use termcolor::Color;
 
@@ -14,8 +23,44 @@ pub enum PackageType {
     Source,
 }
 
+/// How a wheel's files get into `__pypackages__`, from `--link-mode`, or the `link-mode` setting.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum LinkMode {
+    /// Extract the wheel into the environment.
+    Copy,
+    /// Hardlink each file from an extracted copy of the wheel in the cache. Fails if the cache
+    /// is on another filesystem.
+    Hardlink,
+    /// Hardlink where the cache and environment share a filesystem, and copy otherwise. Copies
+    /// on Windows, where the cache's files can't be protected from changes.
+    #[default]
+    Auto,
+}
+
+impl LinkMode {
+    pub const NAMES: &'static [&'static str] = &["copy", "hardlink", "auto"];
+}
+
+impl FromStr for LinkMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "copy" => Self::Copy,
+            "hardlink" => Self::Hardlink,
+            "auto" => Self::Auto,
+            _ => {
+                return Err(format!(
+                    "`{}` isn't a link mode; use one of {}",
+                    s,
+                    Self::NAMES.join(", ")
+                ))
+            }
+        })
+    }
+}
+
 /// [Cookbook](https://rust-lang-nursery.github.io/rust-cookbook/cryptography/hashing.html)
-fn sha256_digest<R: io::Read>(mut reader: R) -> Result<digest::Digest, std::io::Error> {
+pub fn sha256_digest<R: io::Read>(mut reader: R) -> Result<digest::Digest, std::io::Error> {
     let mut context = digest::Context::new(&digest::SHA256);
     let mut buffer = [0; 1024];
 
@@ -30,6 +75,78 @@ fn sha256_digest<R: io::Read>(mut reader: R) -> Result<digest::Digest, std::io::
     Ok(context.finish())
 }
 
+/// Hash a file, returning the urlsafe, unpadded base64 digest used by `RECORD`; `None` for an
+/// algorithm we don't know.
+pub fn hash_file(path: &Path, algorithm: &str) -> io::Result<Option<String>> {
+    let algorithm = match algorithm {
+        "sha256" => &digest::SHA256,
+        "sha384" => &digest::SHA384,
+        "sha512" => &digest::SHA512,
+        _ => return Ok(None),
+    };
+
+    let mut file = fs::File::open(path)?;
+    let mut context = digest::Context::new(algorithm);
+    let mut buffer = [0; 8192];
+    loop {
+        let count = io::Read::read(&mut file, &mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        context.update(&buffer[..count]);
+    }
+    Ok(Some(
+        data_encoding::BASE64URL_NOPAD.encode(context.finish().as_ref()),
+    ))
+}
+
+/// One entry from a `RECORD` file: path (relative to `lib`), hash, and size.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordEntry {
+    pub path: String,
+    pub hash: Option<(String, String)>, // (algorithm, urlsafe-b64 digest)
+    pub size: Option<u64>,
+}
+
+/// Parse a line of a wheel's `RECORD` file. Format is CSV: `path,algorithm=digest,size`,
+/// where the path may be quoted if it contains a comma.
+pub fn parse_record_line(line: &str) -> Option<RecordEntry> {
+    let line = line.trim_end_matches('\r');
+    if line.is_empty() {
+        return None;
+    }
+
+    let (path, rest) = if let Some(stripped) = line.strip_prefix('"') {
+        let end = stripped.find("\",")?;
+        (stripped[..end].replace("\"\"", "\""), &stripped[end + 2..])
+    } else {
+        let i = line.find(',')?;
+        (line[..i].to_owned(), &line[i + 1..])
+    };
+
+    let mut parts = rest.splitn(2, ',');
+    let hash = parts.next().unwrap_or("");
+    let size = parts.next().unwrap_or("");
+
+    let hash = if hash.is_empty() {
+        None
+    } else {
+        let mut split = hash.splitn(2, '=');
+        let algorithm = split.next()?.to_owned();
+        let digest = split.next()?.to_owned();
+        Some((algorithm, digest))
+    };
+
+    Some(RecordEntry {
+        path,
+        hash,
+        size: size.parse().ok(),
+    })
+}
+
+/// `setup.py`s importing `distutils.core` are changed to use setuptools.
+static DISTUTILS_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"distutils.core").unwrap());
+
 /// If the setup.py file uses `distutils.core`, replace with `setuptools`. This is required to build
 /// a wheel. Eg, replace `from distutils.core import setup` with `from setuptools import setup`.
 fn replace_distutils(setup_path: &Path) {
@@ -44,8 +161,7 @@ fn replace_distutils(setup_path: &Path) {
         ));
     };
 
-    let re = Regex::new(r"distutils.core").unwrap();
-    let new_text = re.replace_all(&setup_text, "setuptools");
+    let new_text = DISTUTILS_RE.replace_all(&setup_text, "setuptools");
 
     if new_text != setup_text {
         fs::write(setup_path, new_text.to_string())
@@ -56,6 +172,9 @@ fn replace_distutils(setup_path: &Path) {
 /// Remove scripts. Used when uninstalling.
 fn remove_scripts(scripts: &[String], scripts_path: &Path) {
     // TODO: Likely not a great approach. QC.
+    if !scripts_path.exists() {
+        return;
+    }
     for entry in
         fs::read_dir(scripts_path).expect("Problem reading dist directory when removing scripts")
     {
@@ -67,7 +186,7 @@ fn remove_scripts(scripts: &[String], scripts_path: &Path) {
         for script in scripts {
             if data.contains(&format!("from {}", script)) {
                 fs::remove_file(entry.path()).expect("Problem removing console script");
-                util::print_color(&format!("Removed console script {}:", script), Color::Green);
+                util::print_status(&format!("Removed console script {}:", script), Color::Green);
             }
         }
     }
@@ -104,13 +223,111 @@ fn find_dist_info_path(name: &str, version: &Version, lib_path: &Path) -> PathBu
     dist_info_path
 }
 
-/// Set up entry points (ie scripts like `ipython`, `black` etc) in a single file.
-/// Alternatively, we could just parse all `dist-info` folders every run; this should
-/// be faster.
-pub fn setup_scripts(name: &str, version: &Version, lib_path: &Path, entry_pt_path: &Path) {
-    let mut scripts = vec![];
+/// Written into a package's `dist-info` once it's installed, and listed in its `RECORD`: a digest
+/// of the `RECORD`, which holds each file's hash, and the size of each file it lists. It's only
+/// written once every file matches its hash in the `RECORD`, so a later sync can skip packages
+/// whose digest still matches, without reading their files. Sizes, unlike modification times,
+/// survive copying the environment, or checking it out again.
+const INSTALL_STAMP: &str = "PYFLOW_INSTALL";
+
+fn install_digest(lib_path: &Path, dist_info_path: &Path, record: &str) -> String {
+    let stamp_path = dist_info_path.join(INSTALL_STAMP);
+    let mut context = digest::Context::new(&digest::SHA256);
+    context.update(record.as_bytes());
+    for entry in record.lines().filter_map(parse_record_line) {
+        let path = lib_path.join(&entry.path);
+        if path == stamp_path {
+            continue;
+        }
+        let size = fs::metadata(&path).ok().map(|m| m.len());
+        context.update(format!("\n{}: {:?}", entry.path, size).as_bytes());
+    }
+    data_encoding::HEXLOWER.encode(context.finish().as_ref())
+}
+
+/// Whether every file with a hash in `record` is there, and matches it. A namespace package's
+/// `__init__.py` may be another member's copy.
+fn matches_record(lib_path: &Path, record: &str) -> bool {
+    let entries: Vec<RecordEntry> = record
+        .lines()
+        .filter_map(parse_record_line)
+        .filter(|e| e.hash.is_some())
+        .collect();
+    util::pipeline::map(&entries, |entry| {
+        let (algorithm, expected) = entry.hash.as_ref().unwrap();
+        let path = lib_path.join(&entry.path);
+        match hash_file(&path, algorithm) {
+            Ok(Some(h)) if &h == expected => true,
+            Ok(Some(_)) if entry.path.ends_with("__init__.py") => {
+                fs::read_to_string(&path).is_ok_and(|d| is_namespace_init(&d))
+            }
+            _ => false,
+        }
+    })
+    .into_iter()
+    .all(|matched| matched)
+}
+
+/// Record that a package was installed, and what its files were like then. Not stamped, and
+/// `false`, if a file doesn't match its hash in the `RECORD`.
+pub fn stamp_installed(name: &str, version: &Version, lib_path: &Path) -> bool {
     let dist_info_path = find_dist_info_path(name, version, lib_path);
+    let record_path = dist_info_path.join("RECORD");
+    let mut record = match fs::read_to_string(&record_path) {
+        Ok(r) => r,
+        Err(_) => return false,
+    };
+    if !matches_record(lib_path, &record) {
+        log::debug!(
+            "{} {} doesn't match its RECORD; not marking it installed",
+            name,
+            version
+        );
+        return false;
+    }
+    let listed = format!(
+        "{}/{}",
+        dist_info_path.file_name().unwrap().to_string_lossy(),
+        INSTALL_STAMP
+    );
+    let prefix = format!("{},", listed);
+    if !record.lines().any(|l| l.starts_with(&prefix)) {
+        if !record.is_empty() && !record.ends_with('\n') {
+            record.push('\n');
+        }
+        record.push_str(&format!("{},,\n", listed));
+        // It may be linked from the cache; replace it, rather than write through.
+        fs::remove_file(&record_path).unwrap_or(());
+        if fs::write(&record_path, &record).is_err() {
+            return false;
+        }
+    }
+    let digest = install_digest(lib_path, &dist_info_path, &record);
+    if fs::write(dist_info_path.join(INSTALL_STAMP), digest).is_err() {
+        log::debug!("Problem marking {} {} installed", name, version);
+        return false;
+    }
+    true
+}
 
+/// Whether a package's files are as they were when it was installed. One without a stamp, eg
+/// installed before we stamped packages, or by another tool, is checked against the hashes in
+/// its `RECORD` once, and stamped if it matches.
+pub fn installed_intact(name: &str, version: &Version, lib_path: &Path) -> bool {
+    let dist_info_path = find_dist_info_path(name, version, lib_path);
+    let record = match fs::read_to_string(dist_info_path.join("RECORD")) {
+        Ok(r) => r,
+        Err(_) => return false,
+    };
+    match fs::read_to_string(dist_info_path.join(INSTALL_STAMP)) {
+        Ok(stamp) => stamp.trim() == install_digest(lib_path, &dist_info_path, &record),
+        Err(_) => stamp_installed(name, version, lib_path),
+    }
+}
+
+/// Read the `console_scripts` entry points from a `dist-info` folder, eg `black=black:patched_main`.
+pub fn read_console_scripts(dist_info_path: &Path) -> Vec<String> {
+    let mut scripts = vec![];
     if let Ok(ep_file) = fs::File::open(dist_info_path.join("entry_points.txt")) {
         let mut in_scripts_section = false;
         for line in io::BufReader::new(ep_file).lines().map_while(Result::ok) {
@@ -119,8 +336,11 @@ pub fn setup_scripts(name: &str, version: &Version, lib_path: &Path, entry_pt_pa
                 continue;
             }
             if line.starts_with('[') {
-                // no longer in scripts section.
-                break;
+                if in_scripts_section {
+                    // no longer in scripts section.
+                    break;
+                }
+                continue;
             }
             if in_scripts_section && !line.is_empty() {
                 // Remove potential leading spaces; have seen indents included.
@@ -128,6 +348,15 @@ pub fn setup_scripts(name: &str, version: &Version, lib_path: &Path, entry_pt_pa
             }
         }
     } // else: Probably no scripts.
+    scripts
+}
+
+/// Set up entry points (ie scripts like `ipython`, `black` etc) in a single file.
+/// Alternatively, we could just parse all `dist-info` folders every run; this should
+/// be faster.
+pub fn setup_scripts(name: &str, version: &Version, lib_path: &Path, entry_pt_path: &Path) {
+    let dist_info_path = find_dist_info_path(name, version, lib_path);
+    let scripts = read_console_scripts(&dist_info_path);
 
     // Now that we've found scripts, add them to our unified file.
     // Note that normally, python uses a bin directory.
@@ -145,8 +374,7 @@ pub fn setup_scripts(name: &str, version: &Version, lib_path: &Path, entry_pt_pa
     }
 
     for new_script in scripts {
-        let re = Regex::new(r"^(.*?)\s*=\s*(.*?):(.*)$").unwrap();
-        if let Some(caps) = re.captures(&new_script) {
+        if let Some(caps) = util::patterns::ENTRY_POINT_RE.captures(&new_script) {
             let name = caps.get(1).unwrap().as_str();
             let module = caps.get(2).unwrap().as_str();
             let func = caps.get(3).unwrap().as_str();
@@ -154,7 +382,7 @@ pub fn setup_scripts(name: &str, version: &Version, lib_path: &Path, entry_pt_pa
             make_script(&path, name, module, func);
             // `wheel` is a dependency required internally, but the user doesn't care.
             if name != "wheel" {
-                util::print_color(&format!("Added a console script: {}", name), Color::Green);
+                util::print_status(&format!("Added a console script: {}", name), Color::Green);
             }
         }
     }
@@ -162,6 +390,345 @@ pub fn setup_scripts(name: &str, version: &Version, lib_path: &Path, entry_pt_pa
     //    fs::write(scripts_file, existing_scripts).expect("Unable to write to the console_scripts file");
 }
 
+/// Write a `RECORD` line; the reverse of `parse_record_line`.
+pub fn format_record_line(entry: &RecordEntry) -> String {
+    let path = if entry.path.contains([',', '"', '\n']) {
+        format!("\"{}\"", entry.path.replace('"', "\"\""))
+    } else {
+        entry.path.clone()
+    };
+    let hash = entry
+        .hash
+        .as_ref()
+        .map(|(algorithm, digest)| format!("{}={}", algorithm, digest))
+        .unwrap_or_default();
+    let size = entry.size.map(|s| s.to_string()).unwrap_or_default();
+    format!("{},{},{}", path, hash, size)
+}
+
+/// Files under `dir`, relative to it, with `/` separators.
+fn files_under(dir: &Path) -> Vec<String> {
+    let mut result = vec![];
+    let mut to_visit = vec![dir.to_owned()];
+    while let Some(current) = to_visit.pop() {
+        for entry in fs::read_dir(&current).into_iter().flatten().flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                to_visit.push(path);
+            } else if let Ok(rel) = path.strip_prefix(dir) {
+                let parts: Vec<String> = rel
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy().to_string())
+                    .collect();
+                result.push(parts.join("/"));
+            }
+        }
+    }
+    result.sort();
+    result
+}
+
+/// Point a script's `#!python` shebang, which wheels use as a placeholder, at `python`.
+fn rewrite_shebang(data: &[u8], python: &Path) -> Option<Vec<u8>> {
+    let rest = data
+        .strip_prefix(b"#!pythonw")
+        .or_else(|| data.strip_prefix(b"#!python"))?;
+    if !rest.starts_with(b"\n") && !rest.starts_with(b"\r\n") && !rest.starts_with(b" ") {
+        return None;
+    }
+    let mut result = format!("#!{}", python.display()).into_bytes();
+    result.extend_from_slice(rest);
+    Some(result)
+}
+
+/// Move the contents of a wheel's `{name}-{version}.data` folder where the wheel spec puts
+/// them, instead of leaving them inert in `lib`: `purelib` and `platlib` into `lib`, `scripts`
+/// into the environment's `bin`, with their shebang pointing at its interpreter, `headers` into
+/// `include/{name}`, and `data` into the environment's root. `RECORD` is updated to match, so
+/// uninstalling removes them.
+pub fn install_data(name: &str, version: &Version, paths: &util::Paths) {
+    let dist_info_path = find_dist_info_path(name, version, &paths.lib);
+    let data_path = dist_info_path.with_extension("data");
+    if !data_path.is_dir() {
+        return;
+    }
+    let data_name = data_path.file_name().unwrap().to_string_lossy().to_string();
+    // Eg `__pypackages__/3.11`.
+    let env_path = paths.lib.parent().unwrap_or(&paths.lib).to_owned();
+    let bin_name = paths
+        .entry_pt
+        .file_name()
+        .map_or("bin".into(), |n| n.to_string_lossy().to_string());
+
+    // `RECORD` paths, relative to `lib`, from their old location, to the new one and new
+    // hash and size, if the file changed.
+    let mut moved = HashMap::new();
+    for scheme in fs::read_dir(&data_path).into_iter().flatten().flatten() {
+        let scheme_name = scheme.file_name().to_string_lossy().to_string();
+        let (dest, prefix) = match scheme_name.as_str() {
+            "purelib" | "platlib" => (paths.lib.clone(), String::new()),
+            "scripts" => (paths.entry_pt.clone(), format!("../{}/", bin_name)),
+            "headers" => (
+                env_path.join("include").join(name),
+                format!("../include/{}/", name),
+            ),
+            "data" => (env_path.clone(), "../".to_owned()),
+            _ => {
+                log::warn!(
+                    "Ignoring `{}` in {}'s data folder; it's not in the wheel spec",
+                    scheme_name,
+                    name
+                );
+                continue;
+            }
+        };
+        for rel in files_under(&scheme.path()) {
+            let source = scheme.path().join(&rel);
+            let target = dest.join(&rel);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent).unwrap_or(());
+            }
+            let mut changed = None;
+            // Scripts are written afresh, rather than moved, since they're made executable, and
+            // may be linked from the cache.
+            let result = if scheme_name == "scripts" {
+                fs::read(&source).and_then(|data| {
+                    let data = match rewrite_shebang(&data, &paths.bin.join("python")) {
+                        Some(rewritten) => {
+                            let digest = sha256_digest(rewritten.as_slice())?;
+                            changed = Some((
+                                data_encoding::BASE64URL_NOPAD.encode(digest.as_ref()),
+                                rewritten.len() as u64,
+                            ));
+                            rewritten
+                        }
+                        None => data,
+                    };
+                    fs::remove_file(&target).unwrap_or(());
+                    fs::write(&target, data)?;
+                    fs::remove_file(&source)
+                })
+            } else {
+                fs::rename(&source, &target)
+            };
+            if let Err(e) = result {
+                util::abort(&format!(
+                    "Problem installing {:?} from {}: {}",
+                    target, name, e
+                ));
+            }
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                if scheme_name == "scripts" {
+                    fs::set_permissions(&target, fs::Permissions::from_mode(0o755)).unwrap_or(());
+                }
+            }
+            moved.insert(
+                format!("{}/{}/{}", data_name, scheme_name, rel),
+                (format!("{}{}", prefix, rel), changed),
+            );
+        }
+    }
+    fs::remove_dir_all(&data_path).unwrap_or(());
+
+    let record_path = dist_info_path.join("RECORD");
+    let record = match fs::read_to_string(&record_path) {
+        Ok(r) => r,
+        Err(_) => return,
+    };
+    let mut updated = String::new();
+    for line in record.lines() {
+        let mut entry = match parse_record_line(line) {
+            Some(e) => e,
+            None => continue,
+        };
+        if let Some((path, changed)) = moved.get(&entry.path) {
+            entry.path = path.clone();
+            if let Some((digest, size)) = changed {
+                entry.hash = Some(("sha256".into(), digest.clone()));
+                entry.size = Some(*size);
+            }
+        }
+        updated.push_str(&format_record_line(&entry));
+        updated.push('\n');
+    }
+    // It may be linked from the cache; replace it, rather than write through.
+    fs::remove_file(&record_path).unwrap_or(());
+    fs::write(&record_path, updated).expect("Problem updating RECORD");
+}
+
+/// Where we keep an extracted copy of a wheel to link from, eg
+/// `dependency_cache/extracted/numpy-1.26.0-cp311-cp311-manylinux_2_17_x86_64`.
+fn extracted_path(cache_path: &Path, filename: &str) -> PathBuf {
+    cache_path
+        .join("extracted")
+        .join(filename.trim_end_matches(".whl"))
+}
+
+/// Make every file under `dir` read-only, or writable again. Cached files are read-only so a
+/// package writing to its own files, through a hardlink, fails instead of changing the cache,
+/// and every other project linked to it.
+#[cfg(any(unix, test))]
+fn set_read_only(dir: &Path, read_only: bool) {
+    for rel in files_under(dir) {
+        let path = dir.join(rel);
+        if let Ok(metadata) = fs::metadata(&path) {
+            let mut permissions = metadata.permissions();
+            permissions.set_readonly(read_only);
+            fs::set_permissions(&path, permissions).unwrap_or(());
+        }
+    }
+}
+
+/// Remove an extracted wheel, leaving its files' modes alone: projects may still link to them.
+/// Removing a read-only file only needs its directory to be writable.
+fn remove_extracted(dir: &Path) {
+    if dir.exists() {
+        fs::remove_dir_all(dir).unwrap_or(());
+    }
+}
+
+/// Whether the files in an extracted wheel still have the sizes its `RECORD` gives. A cheap
+/// check that nothing wrote through a hardlink; `pyflow verify` checks hashes.
+fn extracted_intact(dir: &Path) -> bool {
+    let record = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| e.path())
+        .find(|p| p.to_string_lossy().ends_with(".dist-info"))
+        .and_then(|p| fs::read_to_string(p.join("RECORD")).ok());
+    let record = match record {
+        Some(r) => r,
+        None => return false,
+    };
+    record
+        .lines()
+        .filter_map(parse_record_line)
+        .all(|entry| match entry.size {
+            Some(size) => fs::metadata(dir.join(&entry.path)).is_ok_and(|m| m.len() == size),
+            None => true,
+        })
+}
+
+/// Remove the extracted copies of a package in the cache, eg so `pyflow install --reinstall`
+/// doesn't link files that were changed through a hardlink.
+pub fn forget_extracted(cache_path: &Path, name: &str, version: &Version) {
+    for dir in fs::read_dir(cache_path.join("extracted"))
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        let matches = fs::read_dir(dir.path())
+            .into_iter()
+            .flatten()
+            .flatten()
+            .any(|e| {
+                let folder = e.file_name().to_string_lossy().to_string();
+                match folder.trim_end_matches(".dist-info").split_once('-') {
+                    Some((n, v)) if folder.ends_with(".dist-info") => {
+                        util::compare_names(n, name)
+                            && (v == version.to_string()
+                                || Version::from_str(v).ok().as_ref() == Some(version))
+                    }
+                    _ => false,
+                }
+            });
+        if matches {
+            remove_extracted(&dir.path());
+        }
+    }
+}
+
+/// Whether a file in `from` can be hardlinked into `to`; not if they're on different
+/// filesystems, for example.
+fn can_link(from: &Path, to: &Path) -> bool {
+    let source = match files_under(from).into_iter().next() {
+        Some(f) => from.join(f),
+        None => return false,
+    };
+    let probe = to.join(".pyflow-link-probe");
+    fs::remove_file(&probe).unwrap_or(());
+    let result = fs::hard_link(&source, &probe).is_ok();
+    fs::remove_file(&probe).unwrap_or(());
+    result
+}
+
+/// Install a wheel by hardlinking its files from an extracted copy in the cache, extracting
+/// it there first if needed. This saves the time to extract it again, and the space for
+/// another copy, in each project that uses it. Returns `false`, having installed nothing, if
+/// the files can't be linked in `LinkMode::Auto`. There are no reflinks (copy-on-write clones,
+/// eg on Btrfs or APFS): they'd need a per-platform `ioctl` or `clonefile` call for each file,
+/// and a filesystem that supports them to test on.
+fn link_wheel(
+    archive_file: &fs::File,
+    filename: &str,
+    paths: &util::Paths,
+    mode: LinkMode,
+) -> bool {
+    let extracted = extracted_path(&paths.cache, filename);
+    if extracted.exists() && !extracted_intact(&extracted) {
+        log::warn!(
+            "The cached copy of {} was changed, or is incomplete; extracting it again",
+            filename
+        );
+        remove_extracted(&extracted);
+    }
+    if !extracted.exists() {
+        // Extract somewhere else first, so an interrupted install doesn't leave a partial copy
+        // to link from.
+        let partial = extracted.with_file_name(format!("{}.partial", filename));
+        remove_extracted(&partial);
+        util::extract_zip(archive_file, &partial, &None, &None);
+        #[cfg(unix)]
+        set_read_only(&partial, true);
+        // Another install may have got there first.
+        if fs::rename(&partial, &extracted).is_err() {
+            remove_extracted(&partial);
+            if !extracted.exists() {
+                return false;
+            }
+        }
+    }
+
+    if !can_link(&extracted, &paths.lib) {
+        if mode == LinkMode::Hardlink {
+            util::abort(&format!(
+                "Can't hardlink {} from the cache, {:?}, into {:?}; they may be on different \
+                 filesystems. Use `--link-mode copy`, or `auto`",
+                filename, paths.cache, paths.lib
+            ))
+        }
+        log::debug!("Can't hardlink from {:?}; extracting instead", extracted);
+        return false;
+    }
+
+    for rel in files_under(&extracted) {
+        let source = extracted.join(&rel);
+        let target = paths.lib.join(&rel);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).unwrap_or(());
+        }
+        if target.exists() {
+            // As in `util::extract_zip`, keep another namespace member's `__init__.py`.
+            if rel.ends_with("__init__.py")
+                && fs::read_to_string(&source).is_ok_and(|d| is_namespace_init(&d))
+            {
+                continue;
+            }
+            fs::remove_file(&target).unwrap_or(());
+        }
+        if let Err(e) = fs::hard_link(&source, &target) {
+            util::abort(&format!(
+                "Problem linking {:?} from the cache: {}. Try `--link-mode copy`",
+                target, e
+            ))
+        }
+    }
+    true
+}
+
 /// Download and install a package. For wheels, we can just extract the contents into
 /// the lib folder.  For source dists, make a wheel first.
 #[allow(clippy::too_many_arguments)]
@@ -174,69 +741,177 @@ pub fn download_and_install_package(
     paths: &util::Paths,
     package_type: PackageType,
     rename: &Option<(u32, String)>,
-) -> Result<(), reqwest::Error> {
-    if !paths.lib.exists() {
-        fs::create_dir_all(&paths.lib).expect("Problem creating lib directory");
+) -> Result<(), String> {
+    if let Some(archive) = download_package(url, filename, &paths.cache)? {
+        install_package(
+            name,
+            version,
+            &archive,
+            filename,
+            expected_digest,
+            paths,
+            package_type,
+            rename,
+        );
     }
-    if !paths.cache.exists() {
-        fs::create_dir_all(&paths.cache).expect("Problem creating cache directory");
+    Ok(())
+}
+
+/// A package archive in the cache.
+pub struct Downloaded {
+    pub path: PathBuf,
+    /// Hex SHA256, computed as it was downloaded, or read from the cache; `None` if a cached copy
+    /// couldn't be read.
+    pub sha256: Option<String>,
+}
+
+/// Download a package's archive into the cache, unless it's there already. It's streamed to disk,
+/// and hashed on the way, so is never held in memory. It's written under a temporary name, and
+/// renamed once complete, so an interrupted download is never taken for a cached one. `None` if
+/// cancelled with Ctrl-C; see `util::pipeline`. A cached archive is hashed here too, so that
+/// happens on the download workers, rather than holding up installing. Problems are returned,
+/// not aborted on, so the installing thread can stop between packages.
+pub fn download_package(
+    url: &str,
+    filename: &str,
+    cache: &Path,
+) -> Result<Option<Downloaded>, String> {
+    if !cache.exists() {
+        fs::create_dir_all(cache)
+            .map_err(|e| format!("Problem creating the cache directory {:?}: {}", cache, e))?;
     }
-    let archive_path = paths.cache.join(filename);
+    let archive_path = cache.join(filename);
 
     // If the archive is already in the lib folder, don't re-download it. Note that this
     // isn't the usual flow, but may have some uses.
-    if !archive_path.exists() {
-        // Save the file
-        let mut resp = reqwest::get(url)?; // Download the file
-        let mut out =
-            fs::File::create(&archive_path).expect("Failed to save downloaded package file");
-        // TODO: DRY between here and py_versions.
-        if let Err(e) = io::copy(&mut resp, &mut out) {
+    if archive_path.exists() {
+        log::debug!("Using cached {}", filename);
+        util::http::record_cache_hit();
+        let sha256 = fs::File::open(&archive_path)
+            .and_then(|f| sha256_digest(io::BufReader::with_capacity(64 * 1024, f)))
+            .ok()
+            .map(|d| data_encoding::HEXLOWER.encode(d.as_ref()));
+        return Ok(Some(Downloaded {
+            path: archive_path,
+            sha256,
+        }));
+    }
+    let start = Instant::now();
+    let partial_path = cache.join(format!("{}.{}.part", filename, process::id()));
+    let mut resp = util::http_client()
+        .get(url)
+        .send()
+        .map_err(|e| format!("Problem downloading {}: {}", filename, e))?;
+    let mut out = fs::File::create(&partial_path)
+        .map_err(|e| format!("Problem saving {} to the cache: {}", filename, e))?;
+    // TODO: DRY between here and py_versions.
+    let mut buffer = vec![0; 64 * 1024];
+    let mut hasher = digest::Context::new(&digest::SHA256);
+    let mut received = 0;
+    loop {
+        if util::pipeline::cancelled() {
+            drop(out);
+            let _ = fs::remove_file(&partial_path);
+            return Ok(None);
+        }
+        let copied = match resp.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => {
+                received += n;
+                hasher.update(&buffer[..n]);
+                out.write_all(&buffer[..n])
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = copied {
             // Clean up the downloaded file, or we'll get an error next time.
-            fs::remove_file(&archive_path).expect("Problem removing the broken file");
-            util::abort(&format!("Problem downloading the package archive: {:?}", e));
+            drop(out);
+            let _ = fs::remove_file(&partial_path);
+            return Err(format!("Problem downloading {}: {}", filename, e));
         }
     }
+    drop(out);
+    util::http::record_request(received);
+    if let Err(e) = fs::rename(&partial_path, &archive_path) {
+        let _ = fs::remove_file(&partial_path);
+        return Err(format!("Problem saving {} to the cache: {}", filename, e));
+    }
+    log::debug!("Downloaded {} in {:.2?}", filename, start.elapsed());
+    Ok(Some(Downloaded {
+        path: archive_path,
+        sha256: Some(data_encoding::HEXLOWER.encode(hasher.finish().as_ref())),
+    }))
+}
 
-    let file = util::open_archive(&archive_path);
-
-    // https://rust-lang-nursery.github.io/rust-cookbook/cryptography/hashing.html
-    let reader = io::BufReader::new(&file);
-    let file_digest = sha256_digest(reader).unwrap_or_else(|_| {
-        util::abort(&format!("Problem reading hash for {}", filename));
-    });
-
-    let file_digest_str = data_encoding::HEXUPPER.encode(file_digest.as_ref());
-    if file_digest_str.to_lowercase() != expected_digest.to_lowercase() {
-        util::print_color(&format!("Hash failed for {}. Expected: {}, Actual: {}. Continue with installation anyway? (yes / no)", filename, expected_digest.to_lowercase(), file_digest_str.to_lowercase()), Color::Red);
-
-        let mut input = String::new();
-        io::stdin()
-            .read_line(&mut input)
-            .expect("Unable to read user input hash fail decision");
-
-        let input = input
-            .chars()
-            .next()
-            .expect("Problem reading input")
-            .to_string();
-
-        if input.to_lowercase().contains('y') {
-        } else {
+/// Check a downloaded archive's hash, and install it.
+#[allow(clippy::too_many_arguments)]
+pub fn install_package(
+    name: &str,
+    version: &Version,
+    archive: &Downloaded,
+    filename: &str,
+    expected_digest: &str,
+    paths: &util::Paths,
+    package_type: PackageType,
+    rename: &Option<(u32, String)>,
+) {
+    if !paths.lib.exists() {
+        fs::create_dir_all(&paths.lib).expect("Problem creating lib directory");
+    }
+    let archive_path = archive.path.as_path();
+
+    // A cached archive that couldn't be hashed on a download worker.
+    let file_digest_str = match &archive.sha256 {
+        Some(d) => d.clone(),
+        None => {
+            let reader = io::BufReader::with_capacity(64 * 1024, util::open_archive(archive_path));
+            let file_digest = sha256_digest(reader).unwrap_or_else(|_| {
+                util::abort(&format!("Problem reading hash for {}", filename));
+            });
+            data_encoding::HEXLOWER.encode(file_digest.as_ref())
+        }
+    };
+    if file_digest_str != expected_digest.to_lowercase() {
+        util::print_color(
+            &format!(
+                "Hash failed for {}. Expected: {}, Actual: {}.",
+                filename,
+                expected_digest.to_lowercase(),
+                file_digest_str
+            ),
+            Color::Red,
+        );
+        if !util::prompts::confirm(
+            "Continue with installation anyway?",
+            Fallback::Fail("Exiting due to failed hash."),
+        ) {
             util::abort("Exiting due to failed hash");
         }
     }
 
-    // We must re-open the file after computing the hash.
-    let archive_file = util::open_archive(&archive_path);
+    let archive_file = util::open_archive(archive_path);
 
     let rename = rename
         .as_ref()
         .map(|(_, new)| (name.to_owned(), new.to_owned()));
 
+    let start = Instant::now();
     match package_type {
         PackageType::Wheel => {
-            util::extract_zip(&archive_file, &paths.lib, &rename, &None);
+            let mode = CliConfig::current().settings.link_mode;
+            #[cfg(windows)]
+            let mode = if mode == LinkMode::Auto {
+                LinkMode::Copy
+            } else {
+                mode
+            };
+            // A renamed package's files are changed as they're extracted, so can't be shared.
+            let linked = mode != LinkMode::Copy
+                && rename.is_none()
+                && link_wheel(&archive_file, filename, paths, mode);
+            if !linked {
+                util::extract_zip(&archive_file, &paths.lib, &rename, &None);
+            }
         }
         PackageType::Source => {
             // TODO: Support .tar.bz2
@@ -291,7 +966,7 @@ pub fn download_and_install_package(
                                             .unwrap()
                                             .to_lowercase()
                                             .contains("readme")
-                                            && fs::File::create(&paths.lib.join(f.path().unwrap()))
+                                            && fs::File::create(paths.lib.join(f.path().unwrap()))
                                                 .is_err()
                                         {
                                             print_color(
@@ -316,17 +991,17 @@ pub fn download_and_install_package(
             }
             // Check if we have a zip file instead.
             if let Err(e) = archive_error {
-                println!(
+                log::info!(
                     "Problem opening the tar.gz archive: {:?}: {:?}, checking if it's a zip...",
-                    &archive_file, e
+                    &archive_file,
+                    e
                 );
                 util::extract_zip(&archive_file, &paths.lib, &None, &Some((name, filename)));
             }
 
             // The archive is now unpacked into a parent folder from the `tar.gz`. Place
             // its sub-folders directly in the lib folder, and delete the parent.
-            let re = Regex::new(r"^(.*?)(?:\.tar\.gz|\.zip)$").unwrap();
-            let folder_name = re
+            let folder_name = util::patterns::SDIST_FILENAME_RE
                 .captures(filename)
                 .expect("Problem matching extracted folder name")
                 .get(1)
@@ -374,7 +1049,7 @@ pub fn download_and_install_package(
             {
                 let output = Command::new("python3")
                     .current_dir(&extracted_parent)
-                    .args(&["setup.py", "bdist_wheel"])
+                    .args(["setup.py", "bdist_wheel"])
                     .output()
                     .unwrap_or_else(|_| {
                         panic!(
@@ -479,22 +1154,185 @@ pub fn download_and_install_package(
             }
         }
     }
+    log::debug!("Extracted {} in {:.2?}", filename, start.elapsed());
+    install_data(name, version, paths);
     setup_scripts(name, version, &paths.lib, &paths.entry_pt);
+}
 
-    Ok(())
+/// Lines of a namespace package's `__init__.py`, with spaces removed and single quotes. Some try
+/// `pkg_resources`, and fall back to `pkgutil`.
+const NAMESPACE_INIT_LINES: &[&str] = &[
+    "__path__=__import__('pkgutil').extend_path(__path__,__name__)",
+    "__import__('pkg_resources').declare_namespace(__name__)",
+    "importpkgutil",
+    "__path__=pkgutil.extend_path(__path__,__name__)",
+    "frompkgutilimportextend_path",
+    "__path__=extend_path(__path__,__name__)",
+    "importpkg_resources",
+    "pkg_resources.declare_namespace(__name__)",
+    "try:",
+    "except:",
+    "exceptImportError:",
+];
+
+/// Whether an `__init__.py` only declares a namespace package, the pre-PEP 420 way, eg
+/// `__path__ = __import__('pkgutil').extend_path(__path__, __name__)`. Each member of the namespace
+/// ships the same file, and any one of them will do. Other code, besides comments, means it's a
+/// regular package's.
+pub fn is_namespace_init(data: &str) -> bool {
+    let mut declares = false;
+    for line in data.lines() {
+        let code = line.split('#').next().unwrap_or_default();
+        let code: String = code
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| if c == '"' { '\'' } else { c })
+            .collect();
+        if code.is_empty() {
+            continue;
+        }
+        if !NAMESPACE_INIT_LINES.contains(&code.as_str()) {
+            return false;
+        }
+        declares |= code.contains("extend_path(") || code.contains("declare_namespace(");
+    }
+    declares
+}
+
+/// Paths listed in the `RECORD` of every installed package, other than the one in
+/// `dist_info_path`. Members of a namespace package may each list a shared file, like
+/// `google/__init__.py`; it belongs to all of them.
+fn paths_owned_by_others(dist_info_path: &Path, lib_path: &Path) -> HashSet<String> {
+    let mut result = HashSet::new();
+    let entries = match lib_path.read_dir() {
+        Ok(e) => e,
+        Err(_) => return result,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path == dist_info_path || !path.to_string_lossy().ends_with(".dist-info") {
+            continue;
+        }
+        if let Ok(data) = fs::read_to_string(path.join("RECORD")) {
+            result.extend(data.lines().filter_map(parse_record_line).map(|e| e.path));
+        }
+    }
+    result
+}
+
+/// Remove the compiled files for a `.py` file we've removed, eg
+/// `__pycache__/storage.cpython-311.pyc` for `storage.py`.
+fn remove_compiled(py_path: &Path) {
+    let (dir, stem) = match (py_path.parent(), py_path.file_stem()) {
+        (Some(d), Some(s)) => (d, s.to_string_lossy().to_string()),
+        _ => return,
+    };
+    let entries = match dir.join("__pycache__").read_dir() {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+    let prefix = format!("{}.", stem);
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with(&prefix) && name.ends_with(".pyc") {
+            fs::remove_file(entry.path()).unwrap_or(());
+        }
+    }
+}
+
+/// Remove the files listed in a package's `RECORD`, then directories left empty, and the
+/// `dist-info` folder. Files other installed packages also list are left, as are directories
+/// they still have files in, so removing one member of a namespace package, like
+/// `google-cloud-storage`, doesn't break the others. Returns `false` if there's no `RECORD` to
+/// work from. Entries outside the environment, ie `lib`'s parent, eg absolute paths, or ones
+/// with too many `..`s, are left alone.
+fn uninstall_from_record(dist_info_path: &Path, lib_path: &Path) -> bool {
+    let data = match fs::read_to_string(dist_info_path.join("RECORD")) {
+        Ok(d) => d,
+        Err(_) => return false,
+    };
+    let shared = paths_owned_by_others(dist_info_path, lib_path);
+    let lib = util::normalize_path(lib_path);
+    let env_root = lib.parent().unwrap_or(&lib).to_owned();
+
+    let mut dirs = vec![];
+    for entry in data.lines().filter_map(parse_record_line) {
+        if shared.contains(&entry.path) {
+            continue;
+        }
+        let path = util::normalize_path(&lib.join(&entry.path));
+        if !path.starts_with(&env_root) || path == env_root {
+            log::warn!(
+                "Not removing {:?}, which {:?} lists; it's outside the environment",
+                path,
+                dist_info_path.join("RECORD")
+            );
+            continue;
+        }
+        fs::remove_file(&path).unwrap_or(());
+        if path.extension().is_some_and(|e| e == "py") {
+            remove_compiled(&path);
+        }
+
+        // Don't clean up directories outside `lib`, eg `bin` for scripts.
+        if !path.starts_with(&lib) {
+            continue;
+        }
+        let mut parent = path.parent();
+        while let Some(dir) = parent {
+            if dir == lib {
+                break;
+            }
+            dirs.push(dir.to_owned());
+            parent = dir.parent();
+        }
+    }
+
+    // Deepest first, so parents are empty by the time we reach them.
+    dirs.sort();
+    dirs.dedup();
+    dirs.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
+    for dir in dirs {
+        // Each only succeeds if empty; other packages may share this directory.
+        fs::remove_dir(dir.join("__pycache__")).unwrap_or(());
+        fs::remove_dir(&dir).unwrap_or(());
+    }
+
+    fs::remove_dir_all(dist_info_path).unwrap_or(());
+    true
+}
+
+/// Top-level folders other installed packages use, from their `top_level.txt`; eg `google` for
+/// each `google-cloud-*` package.
+fn top_levels_of_others(dist_info_path: &Path, lib_path: &Path) -> HashSet<String> {
+    let mut result = HashSet::new();
+    let entries = match lib_path.read_dir() {
+        Ok(e) => e,
+        Err(_) => return result,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path == dist_info_path || !path.to_string_lossy().ends_with(".dist-info") {
+            continue;
+        }
+        if let Ok(data) = fs::read_to_string(path.join("top_level.txt")) {
+            result.extend(data.lines().map(|l| l.trim().to_owned()));
+        }
+    }
+    result
 }
 
 pub fn uninstall(name_ins: &str, vers_ins: &Version, lib_path: &Path) {
     #[cfg(target_os = "windows")]
-    println!(
+    log::info!(
         "Uninstalling {}: {}...",
         name_ins,
         vers_ins.to_string_color()
     );
     #[cfg(target_os = "linux")]
-    println!("🗑 Uninstalling {}: {}...", name_ins, vers_ins);
+    log::info!("🗑 Uninstalling {}: {}...", name_ins, vers_ins);
     #[cfg(target_os = "macos")]
-    println!("🗑 Uninstalling {}: {}...", name_ins, vers_ins);
+    log::info!("🗑 Uninstalling {}: {}...", name_ins, vers_ins);
 
     // Uninstall the package
     // package folders appear to be lowercase, while metadata keeps the package title's casing.
@@ -502,6 +1340,13 @@ pub fn uninstall(name_ins: &str, vers_ins: &Version, lib_path: &Path) {
     let dist_info_path = find_dist_info_path(name_ins, vers_ins, lib_path);
     let egg_info_path = lib_path.join(format!("{}-{}.egg-info", name_ins, vers_ins));
 
+    // Prefer the exact file list from `RECORD`; fall back to `top_level.txt` if it's missing.
+    if uninstall_from_record(&dist_info_path, lib_path) {
+        fs::remove_dir_all(lib_path.join(format!("{}-{}.data", name_ins, vers_ins))).unwrap_or(());
+        remove_scripts(&[name_ins.into()], &lib_path.join("../bin"));
+        return;
+    }
+
     // TODO: could top_level.txt be in egg-info too?
     // Sometimes the folder unpacked to isn't the same name as on pypi. Check for `top_level.txt`.
     let folder_names = match fs::File::open(dist_info_path.join("top_level.txt")) {
@@ -514,12 +1359,25 @@ pub fn uninstall(name_ins: &str, vers_ins: &Version, lib_path: &Path) {
         }
         Err(_) => vec![name_ins.to_lowercase()],
     };
+    let shared = top_levels_of_others(&dist_info_path, lib_path);
 
     for folder_name in folder_names {
+        // Without a `RECORD`, we can't tell which of a shared namespace folder's files are this
+        // package's; leave them rather than break the other packages.
+        if shared.contains(&folder_name) {
+            print_color(
+                &format!(
+                    "Leaving `{}` for other packages that share it; files from {} may remain",
+                    folder_name, name_ins
+                ),
+                Color::Yellow,
+            );
+            continue;
+        }
         if fs::remove_dir_all(lib_path.join(&folder_name)).is_err() {
             // Some packages include a .py file directly in the lib directory instead of a folder.
             // Check that if removing the folder fails.
-            if fs::remove_file(lib_path.join(&format!("{}.py", folder_name))).is_err() {
+            if fs::remove_file(lib_path.join(format!("{}.py", folder_name))).is_err() {
                 print_color(
                     &format!("Problem uninstalling {} {}", name_ins, vers_ins,),
                     Color::Red, // Dark
@@ -606,6 +1464,7 @@ pub fn rename_metadata(path: &Path, _old: &str, new: &str) {
 pub fn download_and_install_git(
     name: &str,
     url: &str,
+    git_ref: Option<&GitRef>,
     git_path: &Path,
     paths: &util::Paths,
 ) -> util::Metadata {
@@ -635,11 +1494,21 @@ pub fn download_and_install_git(
       //        }
       //}
 
+    if let Some(git_ref) = git_ref {
+        let (_, reference) = git_ref.parts();
+        if commands::git_checkout(&git_path.join(&folder_name), reference).is_err() {
+            util::abort(&format!(
+                "Problem checking out `{}` from {}",
+                reference, url
+            ));
+        }
+    }
+
     // Build a wheel from the repo
     let output = Command::new(paths.bin.join("python"))
         // We assume that the module code is in the repo's immediate subfolder that has
         // the package's name.
-        .current_dir(&git_path.join(&folder_name))
+        .current_dir(git_path.join(&folder_name))
         .args(["setup.py", "bdist_wheel"])
         .output()
         .expect("Problem running setup.py bdist_wheel");
@@ -663,8 +1532,9 @@ pub fn download_and_install_git(
 
     // Use the wheel's name to find the dist-info path, to avoid the chicken-egg scenario
     // of need the dist-info path to find the version.
-    let re = Regex::new(r"^(.*?)-(.*?)-.*$").unwrap();
-    let dist_info = if let Some(caps) = re.captures(filename.to_str().unwrap()) {
+    let dist_info = if let Some(caps) =
+        util::patterns::WHEEL_FILENAME_RE.captures(filename.to_str().unwrap())
+    {
         format!(
             "{}-{}.dist-info",
             caps.get(1).unwrap().as_str(),
@@ -676,6 +1546,7 @@ pub fn download_and_install_git(
 
     let metadata = util::parse_metadata(&paths.lib.join(dist_info).join("METADATA")); // todo temp!
 
+    install_data(name, &metadata.version, paths);
     setup_scripts(name, &metadata.version, &paths.lib, &paths.entry_pt);
 
     // Remove the created and moved wheel
@@ -687,3 +1558,520 @@ pub fn download_and_install_git(
     }
     metadata
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pyproject::EnvMode;
+    use crate::util::temp_dir::TempDir;
+    use std::io::Write;
+
+    const NAMESPACE_INIT: &str =
+        "__path__ = __import__('pkgutil').extend_path(__path__, __name__)\n";
+
+    /// Write a wheel with `files`, and a `RECORD` listing them, with hashes and sizes.
+    fn write_wheel(path: &Path, dist_info: &str, files: &[(String, String)]) {
+        let mut record: String = files
+            .iter()
+            .map(|(f, data)| {
+                let hash = digest::digest(&digest::SHA256, data.as_bytes());
+                let hash = data_encoding::BASE64URL_NOPAD.encode(hash.as_ref());
+                format!("{},sha256={},{}\n", f, hash, data.len())
+            })
+            .collect();
+        record.push_str(&format!("{}/RECORD,,\n", dist_info));
+
+        let mut zip = zip::ZipWriter::new(fs::File::create(path).unwrap());
+        for (name, data) in files
+            .iter()
+            .chain(&[(format!("{}/RECORD", dist_info), record)])
+        {
+            zip.start_file(name.as_str(), zip::write::FileOptions::default())
+                .unwrap();
+            zip.write_all(data.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    /// Write a wheel for one member of the `google.cloud` namespace, eg `storage`, and install
+    /// it into `lib`.
+    fn install_member(root: &Path, lib: &Path, member: &str) {
+        let dist_info = format!("google_cloud_{}-1.0.0.dist-info", member);
+        let files = [
+            ("google/__init__.py".to_owned(), NAMESPACE_INIT.to_owned()),
+            (
+                format!("google/cloud/{}/__init__.py", member),
+                "".to_owned(),
+            ),
+            (
+                format!("{}/top_level.txt", dist_info),
+                "google\n".to_owned(),
+            ),
+        ];
+        let wheel_path = root.join(format!("{}.whl", member));
+        write_wheel(&wheel_path, &dist_info, &files);
+
+        util::extract_zip(&fs::File::open(&wheel_path).unwrap(), lib, &None, &None);
+    }
+
+    /// A `RECORD` can't remove files outside the environment.
+    #[test]
+    fn hostile_record() {
+        let root = TempDir::new("hostile");
+        let env = root.join("env");
+        let lib = env.join("lib");
+        let dist_info = lib.join("tool-1.0.0.dist-info");
+        fs::create_dir_all(&dist_info).unwrap();
+        fs::create_dir_all(lib.join("tool")).unwrap();
+        fs::create_dir_all(env.join("bin")).unwrap();
+        let victims = [root.join("absolute.txt"), root.join("relative.txt")];
+        for path in victims
+            .iter()
+            .chain(&[lib.join("tool/__init__.py"), env.join("bin/tool")])
+        {
+            fs::write(path, "").unwrap();
+        }
+        let record = format!(
+            "{}/absolute.txt,,\n../../relative.txt,,\nlib/../../../relative.txt,,\n\
+             tool/__init__.py,,\n../bin/tool,,\ntool-1.0.0.dist-info/RECORD,,\n",
+            root.display()
+        );
+        fs::write(dist_info.join("RECORD"), record).unwrap();
+
+        uninstall("tool", &Version::new(1, 0, 0), &lib);
+        assert!(victims.iter().all(|v| v.exists()));
+        assert!(!lib.join("tool").exists());
+        assert!(!env.join("bin/tool").exists());
+        assert!(!dist_info.exists());
+    }
+
+    #[test]
+    fn namespace_inits() {
+        assert!(is_namespace_init(NAMESPACE_INIT));
+        assert!(is_namespace_init(
+            "# -*- coding: utf-8 -*-\nfrom pkgutil import extend_path\n\
+             __path__ = extend_path(__path__, __name__)  # noqa\n"
+        ));
+        assert!(is_namespace_init(
+            "try:\n    __import__(\"pkg_resources\").declare_namespace(__name__)\n\
+             except ImportError:\n    \
+             __path__ = __import__('pkgutil').extend_path(__path__, __name__)\n"
+        ));
+
+        // Eg `pkg_resources` itself, which mentions both, and a package with its own code.
+        assert!(!is_namespace_init(
+            "def declare_namespace(packageName):\n    \"\"\"Declare that package \
+             'packageName' is a namespace package\"\"\"\n    path = extend_path(path)\n"
+        ));
+        assert!(!is_namespace_init(
+            "__path__ = __import__('pkgutil').extend_path(__path__, __name__)\n\
+             from .core import *\n"
+        ));
+        assert!(!is_namespace_init("# declare_namespace\n"));
+        assert!(!is_namespace_init(""));
+    }
+
+    #[test]
+    fn namespace_packages() {
+        let root = TempDir::new("namespace");
+        let lib = root.join("lib");
+        fs::create_dir_all(&lib).unwrap();
+        fs::create_dir_all(root.join("bin")).unwrap();
+
+        install_member(&root, &lib, "storage");
+        // A regular package's `__init__.py` isn't replaced by a namespace declaration.
+        fs::write(lib.join("google/__init__.py"), "# Kept\n").unwrap();
+        install_member(&root, &lib, "pubsub");
+        assert_eq!(
+            fs::read_to_string(lib.join("google/__init__.py")).unwrap(),
+            "# Kept\n"
+        );
+        fs::write(lib.join("google/__init__.py"), NAMESPACE_INIT).unwrap();
+        fs::create_dir_all(lib.join("google/cloud/storage/__pycache__")).unwrap();
+        fs::write(
+            lib.join("google/cloud/storage/__pycache__/__init__.cpython-311.pyc"),
+            "",
+        )
+        .unwrap();
+
+        uninstall("google_cloud_storage", &Version::new(1, 0, 0), &lib);
+        assert!(!lib.join("google/cloud/storage").exists());
+        assert!(!lib.join("google_cloud_storage-1.0.0.dist-info").exists());
+        assert!(lib.join("google/__init__.py").exists());
+        assert!(lib.join("google/cloud/pubsub/__init__.py").exists());
+
+        uninstall("google_cloud_pubsub", &Version::new(1, 0, 0), &lib);
+        assert!(!lib.join("google").exists());
+    }
+
+    #[test]
+    fn links_from_cache() {
+        let root = TempDir::new("link");
+        let paths = util::Paths {
+            bin: root.join(".venv/bin"),
+            lib: root.join("lib"),
+            entry_pt: root.join("bin"),
+            cache: root.join("cache"),
+            mode: EnvMode::Pypackages,
+        };
+        fs::create_dir_all(&paths.lib).unwrap();
+        fs::create_dir_all(&paths.cache).unwrap();
+        let filename = "tool-1.0.0-py3-none-any.whl";
+        let wheel_path = paths.cache.join(filename);
+        let files = [
+            ("tool/__init__.py".to_owned(), "VALUE = 1\n".to_owned()),
+            ("tool-1.0.0.dist-info/METADATA".to_owned(), "".to_owned()),
+        ];
+        write_wheel(&wheel_path, "tool-1.0.0.dist-info", &files);
+
+        let wheel = fs::File::open(&wheel_path).unwrap();
+        assert!(link_wheel(&wheel, filename, &paths, LinkMode::Hardlink));
+        let cached = extracted_path(&paths.cache, filename).join("tool/__init__.py");
+        assert_eq!(
+            fs::read_to_string(paths.lib.join("tool/__init__.py")).unwrap(),
+            "VALUE = 1\n"
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let metadata = fs::metadata(&cached).unwrap();
+            assert_eq!(metadata.nlink(), 2);
+            assert!(metadata.permissions().readonly());
+        }
+
+        // A change that slipped through is noticed, and the cache extracted again.
+        set_read_only(&extracted_path(&paths.cache, filename), false);
+        fs::write(&cached, "VALUE = 2  # Changed\n").unwrap();
+        assert!(!extracted_intact(&extracted_path(&paths.cache, filename)));
+        assert!(link_wheel(&wheel, filename, &paths, LinkMode::Auto));
+        assert_eq!(fs::read_to_string(&cached).unwrap(), "VALUE = 1\n");
+
+        forget_extracted(&paths.cache, "tool", &Version::new(1, 0, 0));
+        assert!(!extracted_path(&paths.cache, filename).exists());
+        let linked = paths.lib.join("tool/__init__.py");
+        assert!(linked.exists());
+        // Its mode was left alone.
+        #[cfg(unix)]
+        assert!(fs::metadata(&linked).unwrap().permissions().readonly());
+    }
+
+    /// Stamping a package linked from the cache replaces its `RECORD`, instead of writing to the
+    /// cache's copy; the next sync finds it intact.
+    #[test]
+    fn stamps_linked_install() {
+        let root = TempDir::new("link-stamp");
+        let paths = util::Paths {
+            bin: root.join(".venv/bin"),
+            lib: root.join("lib"),
+            entry_pt: root.join("bin"),
+            cache: root.join("cache"),
+            mode: EnvMode::Pypackages,
+        };
+        fs::create_dir_all(&paths.lib).unwrap();
+        fs::create_dir_all(&paths.cache).unwrap();
+        let filename = "tool-1.0.0-py3-none-any.whl";
+        let wheel_path = paths.cache.join(filename);
+        let files = [
+            ("tool/__init__.py".to_owned(), "VALUE = 1\n".to_owned()),
+            ("tool-1.0.0.dist-info/METADATA".to_owned(), "".to_owned()),
+        ];
+        write_wheel(&wheel_path, "tool-1.0.0.dist-info", &files);
+        let version = Version::new(1, 0, 0);
+        let cached_record =
+            extracted_path(&paths.cache, filename).join("tool-1.0.0.dist-info/RECORD");
+
+        let wheel = fs::File::open(&wheel_path).unwrap();
+        assert!(link_wheel(&wheel, filename, &paths, LinkMode::Hardlink));
+        let before = fs::read_to_string(&cached_record).unwrap();
+        stamp_installed("tool", &version, &paths.lib);
+        let record = fs::read_to_string(paths.lib.join("tool-1.0.0.dist-info/RECORD")).unwrap();
+        assert_eq!(record.matches(INSTALL_STAMP).count(), 1);
+
+        // The second sync.
+        assert!(installed_intact("tool", &version, &paths.lib));
+        assert_eq!(fs::read_to_string(&cached_record).unwrap(), before);
+        assert!(fs::metadata(&cached_record)
+            .unwrap()
+            .permissions()
+            .readonly());
+        assert!(extracted_intact(&extracted_path(&paths.cache, filename)));
+    }
+
+    /// Extracting a wheel over files linked from the cache replaces them, instead of writing to
+    /// the cache's read-only copies.
+    #[test]
+    fn extracts_over_linked_install() {
+        let root = TempDir::new("link-extract");
+        let paths = util::Paths {
+            bin: root.join(".venv/bin"),
+            lib: root.join("lib"),
+            entry_pt: root.join("bin"),
+            cache: root.join("cache"),
+            mode: EnvMode::Pypackages,
+        };
+        fs::create_dir_all(&paths.lib).unwrap();
+        fs::create_dir_all(&paths.cache).unwrap();
+        let filename = "tool-1.0.0-py3-none-any.whl";
+        let wheel_path = paths.cache.join(filename);
+        let files = [
+            ("tool/__init__.py".to_owned(), "VALUE = 1\n".to_owned()),
+            ("tool/core.py".to_owned(), "CORE = 1\n".to_owned()),
+            ("tool-1.0.0.dist-info/METADATA".to_owned(), "".to_owned()),
+        ];
+        write_wheel(&wheel_path, "tool-1.0.0.dist-info", &files);
+        let wheel = fs::File::open(&wheel_path).unwrap();
+        assert!(link_wheel(&wheel, filename, &paths, LinkMode::Hardlink));
+
+        let other_path = root.join(filename);
+        let files = [
+            ("tool/__init__.py".to_owned(), "VALUE = 2\n".to_owned()),
+            ("tool/core.py".to_owned(), "CORE = 2\n".to_owned()),
+            ("tool-1.0.0.dist-info/METADATA".to_owned(), "".to_owned()),
+        ];
+        write_wheel(&other_path, "tool-1.0.0.dist-info", &files);
+        util::extract_zip(
+            &fs::File::open(&other_path).unwrap(),
+            &paths.lib,
+            &None,
+            &None,
+        );
+
+        for (file, installed, cached) in [
+            ("tool/__init__.py", "VALUE = 2\n", "VALUE = 1\n"),
+            ("tool/core.py", "CORE = 2\n", "CORE = 1\n"),
+        ] {
+            assert_eq!(fs::read_to_string(paths.lib.join(file)).unwrap(), installed);
+            let cached_path = extracted_path(&paths.cache, filename).join(file);
+            assert_eq!(fs::read_to_string(cached_path).unwrap(), cached);
+        }
+        assert!(extracted_intact(&extracted_path(&paths.cache, filename)));
+    }
+
+    #[test]
+    fn install_stamp() {
+        let root = TempDir::new("stamp");
+        let lib = root.join("lib");
+        fs::create_dir_all(&lib).unwrap();
+        let wheel_path = root.join("tool-1.0.0-py3-none-any.whl");
+        let files = [
+            ("tool/__init__.py".to_owned(), "VALUE = 1\n".to_owned()),
+            ("tool-1.0.0.dist-info/METADATA".to_owned(), "".to_owned()),
+        ];
+        write_wheel(&wheel_path, "tool-1.0.0.dist-info", &files);
+        util::extract_zip(&fs::File::open(&wheel_path).unwrap(), &lib, &None, &None);
+        let version = Version::new(1, 0, 0);
+
+        // Installed before we stamped packages, or by another tool: checked, and stamped.
+        let stamp = lib.join("tool-1.0.0.dist-info").join(INSTALL_STAMP);
+        let init = lib.join("tool/__init__.py");
+        fs::write(&init, "VALUE = 3\n").unwrap();
+        assert!(!installed_intact("tool", &version, &lib));
+        assert!(!stamp_installed("tool", &version, &lib));
+        assert!(!stamp.exists());
+        fs::write(&init, "VALUE = 1\n").unwrap();
+        assert!(installed_intact("tool", &version, &lib));
+        assert!(stamp.exists());
+        fs::remove_file(&stamp).unwrap();
+        assert!(stamp_installed("tool", &version, &lib));
+        assert!(installed_intact("tool", &version, &lib));
+        // Stamping again doesn't list the stamp twice.
+        stamp_installed("tool", &version, &lib);
+        let record = fs::read_to_string(lib.join("tool-1.0.0.dist-info/RECORD")).unwrap();
+        assert_eq!(record.matches(INSTALL_STAMP).count(), 1);
+        assert!(installed_intact("tool", &version, &lib));
+
+        // Touching, or copying, a file isn't a change.
+        fs::File::open(&init)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(60))
+            .unwrap();
+        assert!(installed_intact("tool", &version, &lib));
+        fs::write(&init, "VALUE = 2  # Changed\n").unwrap();
+        assert!(!installed_intact("tool", &version, &lib));
+
+        // The stamp's removed with the package.
+        uninstall("tool", &version, &lib);
+        assert!(!lib.join("tool-1.0.0.dist-info").exists());
+    }
+
+    #[test]
+    fn data_folder() {
+        let root = TempDir::new("data");
+        let paths = util::Paths {
+            bin: root.join(".venv/bin"),
+            lib: root.join("lib"),
+            entry_pt: root.join("bin"),
+            cache: root.join("cache"),
+            mode: EnvMode::Pypackages,
+        };
+        let data = paths.lib.join("tool-1.0.0.data");
+        let dist_info = paths.lib.join("tool-1.0.0.dist-info");
+        for (path, contents) in &[
+            ("scripts/tool-run", "#!python\nprint('hi')\n"),
+            ("purelib/tool_hook.pth", "import tool_hook\n"),
+            ("data/share/tool/config.ini", "[tool]\n"),
+        ] {
+            let path = data.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        fs::create_dir_all(&dist_info).unwrap();
+        fs::write(
+            dist_info.join("RECORD"),
+            "tool-1.0.0.data/scripts/tool-run,sha256=old,22\n\
+             tool-1.0.0.data/purelib/tool_hook.pth,sha256=abc,18\n\
+             tool-1.0.0.data/data/share/tool/config.ini,,\n\
+             tool-1.0.0.dist-info/RECORD,,\n",
+        )
+        .unwrap();
+
+        install_data("tool", &Version::new(1, 0, 0), &paths);
+
+        assert!(!data.exists());
+        assert!(paths.lib.join("tool_hook.pth").exists());
+        assert!(root.join("share/tool/config.ini").exists());
+        let script = fs::read_to_string(paths.entry_pt.join("tool-run")).unwrap();
+        assert_eq!(
+            script,
+            format!("#!{}\nprint('hi')\n", paths.bin.join("python").display())
+        );
+
+        let record: Vec<RecordEntry> = fs::read_to_string(dist_info.join("RECORD"))
+            .unwrap()
+            .lines()
+            .filter_map(parse_record_line)
+            .collect();
+        assert_eq!(record[0].path, "../bin/tool-run");
+        assert_eq!(record[0].size, Some(script.len() as u64));
+        assert_ne!(record[0].hash, Some(("sha256".into(), "old".into())));
+        assert_eq!(
+            record[1],
+            RecordEntry {
+                path: "tool_hook.pth".into(),
+                hash: Some(("sha256".into(), "abc".into())),
+                size: Some(18),
+            }
+        );
+        assert_eq!(record[2].path, "../share/tool/config.ini");
+    }
+
+    #[test]
+    fn record_line_round_trip() {
+        for line in &[
+            "a/b.py,sha256=xyz,3",
+            r#""data/a,b.txt",sha256=xyz,3"#,
+            "RECORD,,",
+        ] {
+            assert_eq!(format_record_line(&parse_record_line(line).unwrap()), *line);
+        }
+    }
+
+    #[test]
+    fn record_line() {
+        assert_eq!(
+            parse_record_line("black/__init__.py,sha256=abc_-123,4096"),
+            Some(RecordEntry {
+                path: "black/__init__.py".into(),
+                hash: Some(("sha256".into(), "abc_-123".into())),
+                size: Some(4096),
+            })
+        );
+    }
+
+    #[test]
+    fn record_line_no_hash() {
+        assert_eq!(
+            parse_record_line("black-22.1.0.dist-info/RECORD,,"),
+            Some(RecordEntry {
+                path: "black-22.1.0.dist-info/RECORD".into(),
+                hash: None,
+                size: None,
+            })
+        );
+    }
+
+    #[test]
+    fn record_line_quoted() {
+        assert_eq!(
+            parse_record_line(r#""data/a,b.txt",sha256=xyz,3"#),
+            Some(RecordEntry {
+                path: "data/a,b.txt".into(),
+                hash: Some(("sha256".into(), "xyz".into())),
+                size: Some(3),
+            })
+        );
+    }
+
+    /// Cancelled once the first package is installed: the downloads in progress stop, and leave
+    /// no partial files in the cache.
+    #[test]
+    fn cancel_mid_download() {
+        use std::{io::BufReader, net::TcpListener, thread, time::Duration};
+
+        let _lock = util::pipeline::TESTS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let _cancel = util::pipeline::Cancel::catch();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut request = String::new();
+                    reader.read_line(&mut request).unwrap();
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).unwrap() > 2 {
+                        line.clear();
+                    }
+                    // `fast` arrives at once; the rest trickle in, and can't finish in time.
+                    let fast = request.contains("/fast");
+                    let chunks = if fast { 1 } else { 64 * 1024 };
+                    let _ = write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        chunks * 1024
+                    );
+                    for _ in 0..chunks {
+                        if stream.write_all(&[7; 1024]).is_err() {
+                            break;
+                        }
+                        if !fast {
+                            thread::sleep(Duration::from_millis(5));
+                        }
+                    }
+                });
+            }
+        });
+
+        let cache = TempDir::new("cancel");
+        let names = ["slow1", "fast", "slow2", "slow3"];
+        let mut installed = vec![];
+        util::pipeline::run(
+            &names,
+            util::pipeline::DOWNLOADS,
+            |name| {
+                download_package(
+                    &format!("{}/{}.whl", base, name),
+                    &format!("{}.whl", name),
+                    &cache,
+                )
+            },
+            |name, downloaded| {
+                if downloaded.unwrap().is_some() {
+                    installed.push(*name);
+                }
+                util::pipeline::stop();
+            },
+        );
+        let mut cached: Vec<_> = fs::read_dir(&cache)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        cached.sort();
+
+        assert_eq!(installed, ["fast"]);
+        assert_eq!(cached, ["fast.whl"]);
+    }
+}
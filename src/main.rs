@@ -1,14 +1,17 @@
 use crate::actions::run;
-use crate::cli_options::{ExternalCommand, ExternalSubcommands, Opt, SubCommand};
-use crate::dep_types::{Lock, Package, Req, Version};
-use crate::pyproject::{Config, CFG_FILENAME};
+use crate::cli_options::{
+    EnvCommand, ExportFormat, ExternalCommand, ExternalSubcommands, Opt, SubCommand,
+};
+use crate::dep_types::{Lock, Package, Version};
+use crate::pyproject::{Config, EnvMode, Hooks, CFG_FILENAME};
 use crate::util::abort;
 use crate::util::deps::sync;
+use crate::util::settings::Settings;
 
 use std::process;
 use std::{
-    path::PathBuf,
-    sync::{Arc, RwLock},
+    env,
+    sync::{Arc, LazyLock, RwLock},
 };
 
 use termcolor::{Color, ColorChoice};
@@ -30,36 +33,44 @@ mod util;
 type PackToInstall = ((String, Version), Option<(u32, String)>); // ((Name, Version), (parent id, rename name))
 
 ///////////////////////////////////////////////////////////////////////////////
-/// Global multithreaded variables part
+// Global multithreaded variables part
 ///////////////////////////////////////////////////////////////////////////////
 
 struct CliConfig {
     pub color_choice: ColorChoice,
+    /// Stdout is reserved for a JSON document; messages go to stderr.
+    pub json: bool,
+    /// Prompts take their defaults, or abort; see `util::prompts::prompt`.
+    pub non_interactive: bool,
+    /// From flags, the environment, the project, and the global config file.
+    pub settings: Settings,
 }
 
 impl Default for CliConfig {
     fn default() -> Self {
         Self {
             color_choice: ColorChoice::Auto,
+            json: false,
+            non_interactive: false,
+            settings: Settings::default(),
         }
     }
 }
 
 impl CliConfig {
     pub fn current() -> Arc<CliConfig> {
-        CLI_CONFIG.with(|c| c.read().unwrap().clone())
+        CLI_CONFIG.read().unwrap().clone()
     }
     pub fn make_current(self) {
-        CLI_CONFIG.with(|c| *c.write().unwrap() = Arc::new(self))
+        *CLI_CONFIG.write().unwrap() = Arc::new(self)
     }
 }
 
-thread_local! {
-    static CLI_CONFIG: RwLock<Arc<CliConfig>> = RwLock::new(Default::default());
-}
+// Shared by every thread, since downloads run on workers; see `util::pipeline`.
+static CLI_CONFIG: LazyLock<RwLock<Arc<CliConfig>>> = LazyLock::new(Default::default);
 
 ///////////////////////////////////////////////////////////////////////////////
-/// \ Global multithreaded variables part
+// \ Global multithreaded variables part
 ///////////////////////////////////////////////////////////////////////////////
 
 /// We process input commands in a deliberate order, to ensure the required, and only the required
@@ -68,23 +79,40 @@ thread_local! {
 #[allow(clippy::single_match)]
 // TODO: Remove clippy::match_single_binding and clippy::single_match after full function refactoring
 fn main() {
-    let (pyflow_path, dep_cache_path, script_env_path, git_path) = util::paths::get_paths();
-    let os = util::get_os();
-
     let opt = <Opt as structopt::StructOpt>::from_args();
-    #[cfg(debug_assertions)]
-    eprintln!("opts {:?}", opt);
 
+    let cli_settings = util::settings::cli_layer(
+        opt.color.as_deref(),
+        opt.non_interactive,
+        opt.link_mode.as_deref(),
+    );
+    let (settings, warnings) = util::settings::load(&cli_settings);
     CliConfig {
-        color_choice: util::handle_color_option(
-            opt.color.unwrap_or_else(|| String::from("auto")).as_str(),
-        ),
+        color_choice: util::color::from_option(&settings.color),
+        json: opt.subcmds.json(),
+        non_interactive: settings.non_interactive,
+        settings,
     }
     .make_current();
+    util::color::enable_ansi();
+    util::logging::init(
+        util::logging::level_from_flags(opt.quiet, opt.verbose),
+        env::var("PYFLOW_LOG").ok().as_deref(),
+    );
+    for warning in warnings {
+        log::warn!("{}", warning);
+    }
+    log::trace!("opts {:?}", opt);
+
+    let (pyflow_path, dep_cache_path, script_env_path, git_path) = util::paths::get_paths();
+    let python_path = util::paths::python_installs_path(&pyflow_path);
+    let os = util::get_os();
 
     // Handle commands that don't involve operating out of a project before one that do, with setup
     // code in-between.
     let subcmd = opt.subcmds;
+    let no_hooks = opt.no_hooks;
+    let package = opt.package;
 
     let extcmd = if let SubCommand::External(ref x) = subcmd {
         Some(ExternalCommand::from_opt(x.to_owned()))
@@ -94,16 +122,125 @@ fn main() {
 
     match &subcmd {
         // Actions requires nothing to know about the project
-        SubCommand::New { name } => actions::new(name),
+        SubCommand::New {
+            name,
+            lib,
+            app,
+            minimal,
+            license,
+        } => {
+            let template = match (*lib, *app, *minimal) {
+                (true, _, _) => actions::Template::Lib,
+                (_, true, _) => actions::Template::App,
+                (_, _, true) => actions::Template::Minimal,
+                _ => actions::Template::Default,
+            };
+            actions::new(name, template, license.as_deref())
+        }
         SubCommand::Init => actions::init(CFG_FILENAME),
-        SubCommand::Reset {} => actions::reset(),
-        SubCommand::Clear {} => actions::clear(&pyflow_path, &dep_cache_path, &script_env_path),
-        SubCommand::Switch { version } => actions::switch(version),
+        SubCommand::Reset => actions::reset(),
+        SubCommand::Clear => actions::clear(&pyflow_path, &dep_cache_path, &script_env_path),
+        SubCommand::Config { cmd } => {
+            actions::config(cmd, &cli_settings);
+            process::exit(0)
+        }
+        SubCommand::Version { cmd } => {
+            actions::version(cmd.as_ref());
+            process::exit(0)
+        }
+        SubCommand::SelfCmd { cmd } => {
+            actions::self_cmd(cmd);
+            process::exit(0)
+        }
+        SubCommand::Cache { cmd } => {
+            actions::cache(cmd, &pyflow_path, &python_path);
+            process::exit(0)
+        }
+        SubCommand::Clean {
+            cache,
+            metadata,
+            script_envs,
+            pypackages,
+            pypackages_stale,
+            pythons,
+            all,
+        } => {
+            let targets = if *all {
+                actions::CleanTargets::all()
+            } else {
+                actions::CleanTargets {
+                    cache: *cache,
+                    metadata: *metadata,
+                    script_envs: *script_envs,
+                    pypackages: *pypackages,
+                    pypackages_stale: *pypackages_stale,
+                    pythons: *pythons,
+                }
+            };
+            actions::clean(targets, &pyflow_path, &python_path);
+            process::exit(0)
+        }
+        SubCommand::ScriptEnvs { cmd } => {
+            actions::script_envs(cmd, &script_env_path);
+            process::exit(0)
+        }
+        SubCommand::Completions { shell } => {
+            actions::completions(*shell);
+            process::exit(0)
+        }
+        SubCommand::Complete { kind } => {
+            actions::complete(kind);
+            process::exit(0)
+        }
+        SubCommand::Search { term, limit, json } => {
+            actions::search(term, *limit, *json, &pyflow_path);
+            process::exit(0)
+        }
+        SubCommand::Info {
+            package,
+            version,
+            json,
+        } => {
+            actions::info(package, version.as_deref(), *json);
+            process::exit(0)
+        }
+        // Doesn't create an environment, or install anything, so runs before we set those up.
+        SubCommand::Check { skip, only, json } => actions::check(skip, only, *json),
+        SubCommand::Hooks { cmd } => {
+            actions::hooks(cmd);
+            process::exit(0)
+        }
+        SubCommand::Doctor {
+            skip,
+            timeout,
+            json,
+        } => actions::doctor(
+            skip,
+            *timeout,
+            *json,
+            &pyflow_path,
+            &python_path,
+            &dep_cache_path,
+        ),
+        SubCommand::RefreshIndex => {
+            actions::refresh_index(&pyflow_path);
+            process::exit(0)
+        }
+        SubCommand::Switch { version, latest } => actions::switch(version, *latest, &python_path),
         SubCommand::External(ref x) => match ExternalCommand::from_opt(x.to_owned()) {
-            ExternalCommand { cmd, args } => match cmd {
+            ExternalCommand { cmd, args, .. } => match cmd {
                 ExternalSubcommands::Script => {
-                    script::run_script(&script_env_path, &dep_cache_path, os, &args, &pyflow_path);
+                    script::run_script(&script_env_path, &dep_cache_path, os, &args, &python_path);
+                    process::exit(0)
+                }
+                // Eg `pyflow run ./tool.py`, for a script with PEP 723 metadata.
+                ExternalSubcommands::Run
+                    if args.first().is_some_and(|a| script::has_inline_metadata(a)) =>
+                {
+                    script::run_script(&script_env_path, &dep_cache_path, os, &args, &python_path);
+                    process::exit(0)
                 }
+                ExternalSubcommands::Python => actions::python(&args, &python_path),
                 // TODO: Move branches to omitted match
                 _ => (),
             },
@@ -113,43 +250,362 @@ fn main() {
         _ => {}
     }
 
-    let pcfg = pyproject::current::get_config().unwrap_or_else(|| process::exit(1));
+    // Eg `pyflow -p api install`, or `pyflow run -p api uvicorn app:app`, from anywhere in a
+    // workspace.
+    if let Some(name) = package
+        .as_deref()
+        .or_else(|| extcmd.as_ref().and_then(|x| x.package.as_deref()))
+    {
+        pyproject::current::enter_member(name);
+    }
+
+    let mut pcfg = pyproject::current::get_config().unwrap_or_else(|| process::exit(1));
+    // Only these need a version from git tags; others work without a tag.
+    if matches!(
+        subcmd,
+        SubCommand::Build { .. } | SubCommand::Publish { .. } | SubCommand::Package { .. }
+    ) {
+        pcfg.config.resolve_version();
+    }
+    if let SubCommand::Install { constraint, .. } = &subcmd {
+        pcfg.constraints
+            .pins
+            .append(&mut pyproject::read_constraints(constraint));
+        pcfg.constraints.install_files = constraint.clone();
+    }
+
+    // The Dockerfile builds its own environment.
+    if let SubCommand::Dockerfile {
+        ref python,
+        ref entry,
+        ref output,
+        force,
+    } = subcmd
+    {
+        actions::dockerfile(
+            &pcfg,
+            python.as_deref(),
+            entry.as_deref(),
+            output.as_deref(),
+            force,
+        );
+        process::exit(0)
+    }
+
+    // Distributions are built from `pyproject.toml` and the sources alone, and uploaded as-is, so
+    // need no environment; only a wheel with a `build` script does, and waits for it, below.
+    if let SubCommand::Build {
+        sdist,
+        wheel,
+        out_dir,
+        list_files,
+        no_verify_classifiers,
+        reproducible,
+    } = &subcmd
+    {
+        // Set for the `build` script too, since build tools honor it.
+        if *reproducible && env::var_os("SOURCE_DATE_EPOCH").is_none() {
+            env::set_var("SOURCE_DATE_EPOCH", build::DEFAULT_EPOCH.to_string());
+        }
+        let out_dir = out_dir
+            .clone()
+            .unwrap_or_else(|| pcfg.project_path.join("dist"));
+        if *list_files {
+            let mut lists = vec![];
+            if *sdist {
+                lists.push((
+                    "sdist",
+                    build::list_sdist(&pcfg.project_path, &pcfg.config, &out_dir),
+                ));
+            }
+            if *wheel || !*sdist {
+                lists.push(("wheel", build::list_wheel(&pcfg.project_path, &pcfg.config)));
+            }
+            for (kind, paths) in lists {
+                util::print_color(&format!("In the {}:", kind), Color::Blue);
+                for path in paths {
+                    println!("{}", path);
+                }
+            }
+            if let (Some(script), true) = (&pcfg.config.build, *wheel || !*sdist) {
+                println!(
+                    "And what {} writes to {}",
+                    script,
+                    pcfg.config
+                        .build_output
+                        .as_deref()
+                        .unwrap_or(build::DEFAULT_OUTPUT)
+                );
+            }
+            process::exit(0)
+        }
+        build::check_license(&pcfg.config);
+        if !*no_verify_classifiers {
+            build::verify_classifiers(
+                &pyflow_path,
+                &pcfg.config.classifiers,
+                pcfg.config.license.is_some(),
+            );
+        }
+        let mut built = vec![];
+        if *sdist {
+            built.push(build::build_sdist(
+                &pcfg.project_path,
+                &pcfg.config,
+                &out_dir,
+            ));
+        }
+        if (*wheel || !*sdist) && pcfg.config.build.is_none() {
+            built.push(build::build_wheel(
+                &pcfg.project_path,
+                &pcfg.config,
+                &out_dir,
+                None,
+            ));
+        }
+        if !built.is_empty() {
+            let built: Vec<String> = built.iter().map(|p| p.display().to_string()).collect();
+            util::success(&format!("Built {}", built.join(", ")));
+        }
+    }
+    if let SubCommand::Publish {
+        files,
+        repository,
+        repository_url,
+        skip_existing,
+        dry_run,
+        check,
+        json,
+        no_verify_classifiers,
+        allow_dev,
+        no_build,
+    } = &subcmd
+    {
+        // Build what's uploaded, or dry-run, unless we're given files. A wheel with a `build`
+        // script needs an environment, so is built with `pyflow build` beforehand.
+        if files.is_empty() && !*check && !*no_build {
+            build::check_license(&pcfg.config);
+            let out_dir = pcfg.project_path.join("dist");
+            build::build_sdist(&pcfg.project_path, &pcfg.config, &out_dir);
+            if pcfg.config.build.is_none() {
+                build::build_wheel(&pcfg.project_path, &pcfg.config, &out_dir, None);
+            }
+        }
+        let mode = if *check {
+            build::PublishMode::Check { json: *json }
+        } else if *dry_run {
+            build::PublishMode::DryRun
+        } else {
+            build::PublishMode::Upload
+        };
+        build::publish(
+            &pcfg.project_path,
+            &pcfg.config,
+            files,
+            repository.as_deref(),
+            repository_url.as_deref(),
+            *skip_existing,
+            mode,
+            Some(pyflow_path.as_path()).filter(|_| !*no_verify_classifiers),
+            *allow_dev,
+        );
+    }
+
+    let python_requires = py_versions::parse_python_requires(&pcfg.config.python_requires);
     let cfg_vers = if let Some(v) = pcfg.config.py_version.clone() {
         v
+    } else if let Some(v) = pcfg
+        .config
+        .python
+        .as_ref()
+        .and_then(|p| commands::find_py_version(p.to_str()?))
+    {
+        // A specific interpreter is pinned; use its version.
+        Version::new_opt(v.major, v.minor, None)
+    } else if let Some((maj, mi)) = util::find_venvs(&pcfg.pypackages_path)
+        .into_iter()
+        .rev()
+        .find(|(maj, mi)| {
+            !python_requires.is_empty()
+                && py_versions::satisfies(&Version::new_short(*maj, *mi), &python_requires)
+        })
+    {
+        // No `py_version`, but an environment satisfying `python_requires` exists.
+        Version::new_short(maj, mi)
+    } else if let Some(i) = py_versions::newest_satisfying(
+        &python_path,
+        pcfg.config.python_implementation,
+        &python_requires,
+    ) {
+        // No `py_version`; use the newest interpreter satisfying `python_requires`.
+        Version::new_opt(i.version.major, i.version.minor, None)
     } else {
         let specified = util::prompts::py_vers();
 
         if !pcfg.config_path.exists() {
             pcfg.config.write_file(&pcfg.config_path);
         }
-        files::change_py_vers(&pcfg.config_path, &specified);
+        files::change_py_vers(
+            &pcfg.config_path,
+            &specified,
+            pcfg.config.python_implementation,
+        );
 
         specified
     };
 
+    // Tox and nox sessions get their own environments, so the project's isn't set up.
+    if let SubCommand::Env {
+        cmd:
+            EnvCommand::Create {
+                ref path,
+                ref python,
+                ref groups,
+            },
+    } = subcmd
+    {
+        actions::create_env(
+            &pcfg,
+            path,
+            python.as_deref(),
+            groups,
+            &python_path,
+            &dep_cache_path,
+            &cfg_vers,
+            os,
+        );
+        process::exit(0)
+    }
+
+    pyproject::current::check_env_mode(&pcfg);
+    let env_mode = pcfg.config.env_mode;
+    // With `env-mode = "venv"`, the project root stands in for a version folder in
+    // `__pypackages__`; the `.venv` is in the same place relative to each.
+    let env_dir = match env_mode {
+        EnvMode::Pypackages => pcfg.pypackages_path.clone(),
+        EnvMode::Venv => pcfg
+            .lock_path
+            .parent()
+            .unwrap_or(&pcfg.project_path)
+            .to_owned(),
+    };
+    let fresh_env = !match env_mode {
+        EnvMode::Pypackages => env_dir.join(cfg_vers.to_string_med()),
+        EnvMode::Venv => env_dir.clone(),
+    }
+    .join(".venv")
+    .exists();
     // Check for environments. Create one if none exist. Set `vers_path`.
     let (vers_path, py_vers) = util::find_or_create_venv(
         &cfg_vers,
-        &pcfg.pypackages_path,
-        &pyflow_path,
+        &env_dir,
+        &python_path,
         &dep_cache_path,
+        pcfg.config.python.as_deref(),
+        pcfg.config.python_implementation,
+        &python_requires,
+        env_mode,
     );
 
+    // Eg after `pyflow switch 3.11`, the `3.9` environment is left behind.
+    let running = matches!(
+        extcmd,
+        Some(ExternalCommand {
+            cmd: ExternalSubcommands::Run,
+            ..
+        })
+    );
+    if running || matches!(subcmd, SubCommand::Install { .. }) {
+        actions::check_stale_envs(&pcfg.pypackages_path, &py_vers, fresh_env, running);
+    }
+
+    if let SubCommand::Install { .. } = subcmd {
+        actions::check_gitignore(&match env_mode {
+            EnvMode::Pypackages => pcfg.pypackages_path.clone(),
+            EnvMode::Venv => vers_path.join(".venv"),
+        });
+    }
+
+    let bin_path = util::find_bin_path(&vers_path);
+    let (lib_path, entry_pt_path) = match env_mode {
+        EnvMode::Pypackages => (vers_path.join("lib"), vers_path.join("bin")),
+        // Console scripts go beside the venv's own, as pip would put them.
+        EnvMode::Venv => (
+            util::find_site_packages(&vers_path)
+                .unwrap_or_else(|| abort("Can't find the `site-packages` folder in `.venv`")),
+            bin_path.clone(),
+        ),
+    };
     let paths = util::Paths {
-        bin: util::find_bin_path(&vers_path),
-        lib: vers_path.join("lib"),
-        entry_pt: vers_path.join("bin"),
+        bin: bin_path,
+        lib: lib_path,
+        entry_pt: entry_pt_path,
         cache: dep_cache_path,
+        mode: env_mode,
     };
 
     // Add all path reqs to the PYTHONPATH; this is the way we make these packages accessible when
     // running `pyflow`.
     let mut pythonpath = vec![paths.lib.clone()];
+    // Make the project's own packages importable in a `src`-style layout.
+    if let Some(dir) = &pcfg.config.package_dir {
+        pythonpath.push(pcfg.project_path.join(dir));
+    }
+    // They're relative to the project root, which may not be where we run from.
     for r in pcfg.config.reqs.iter().filter(|r| r.path.is_some()) {
-        pythonpath.push(PathBuf::from(r.path.clone().unwrap()));
+        pythonpath.push(pcfg.project_path.join(r.path.clone().unwrap()));
     }
     for r in pcfg.config.dev_reqs.iter().filter(|r| r.path.is_some()) {
-        pythonpath.push(PathBuf::from(r.path.clone().unwrap()));
+        pythonpath.push(pcfg.project_path.join(r.path.clone().unwrap()));
+    }
+    // The `build` script runs with the project's interpreter.
+    if let SubCommand::Build {
+        sdist,
+        wheel,
+        ref out_dir,
+        ..
+    } = subcmd
+    {
+        if pcfg.config.build.is_some() && (wheel || !sdist) {
+            let out_dir = out_dir
+                .clone()
+                .unwrap_or_else(|| pcfg.project_path.join("dist"));
+            let env = build::BuildEnv {
+                paths: &paths,
+                py_vers: &py_vers,
+                os,
+            };
+            let built = build::build_wheel(&pcfg.project_path, &pcfg.config, &out_dir, Some(&env));
+            util::success(&format!("Built {}", built.display()));
+        }
+    }
+
+    if let SubCommand::Env { ref cmd } = subcmd {
+        actions::env(
+            cmd,
+            &pcfg,
+            &paths,
+            &vers_path,
+            &py_vers,
+            &pythonpath,
+            &cli_settings,
+        );
+        process::exit(0)
+    }
+
+    if let SubCommand::Ide {
+        editor,
+        pycharm,
+        print,
+    } = subcmd
+    {
+        let kind = if pycharm {
+            cli_options::IdeKind::PyCharm
+        } else {
+            editor
+        };
+        actions::ide(kind, print, &pcfg, &paths, &pythonpath);
+        process::exit(0)
     }
 
     let mut found_lock = false;
@@ -163,16 +619,196 @@ fn main() {
 
     let lockpacks = lock.package.unwrap_or_else(Vec::new);
 
-    sync(
+    // Verify before syncing; syncing could repair, and hide, the problems we're looking for.
+    if let SubCommand::Verify { json } = subcmd {
+        actions::verify(&paths.lib, json);
+    }
+    if let SubCommand::Licenses { json, ref fail_on } = subcmd {
+        actions::licenses(&paths.lib, json, fail_on);
+        process::exit(0)
+    }
+    // Listing shouldn't install anything.
+    if let SubCommand::Which { ref name, quiet } = subcmd {
+        actions::which(name, &pcfg.config, &paths.bin, &paths.entry_pt, quiet);
+        process::exit(0)
+    }
+    if let SubCommand::Scripts { json } = subcmd {
+        actions::scripts(
+            &pcfg.config.scripts,
+            &pcfg.config.script_chains,
+            &paths.lib,
+            &paths.entry_pt,
+            json,
+        );
+        process::exit(0)
+    }
+    // `--outdated` asks the index, below.
+    if let SubCommand::List {
+        outdated: false,
+        json,
+        ..
+    } = subcmd
+    {
+        actions::list(
+            &paths.lib,
+            &paths.entry_pt,
+            &[pcfg.config.reqs.as_slice(), pcfg.config.dev_reqs.as_slice()].concat(),
+            json,
+        );
+        process::exit(0)
+    }
+
+    // Exporting reads the config, and the lock; it shouldn't change the environment.
+    if let SubCommand::Export {
+        format: ExportFormat::Poetry,
+        ref output,
+        write,
+        ..
+    } = subcmd
+    {
+        actions::export_poetry(&pcfg.config, &pcfg.config_path, output.as_deref(), write);
+        process::exit(0)
+    }
+    if let SubCommand::Export {
+        format,
+        dev,
+        no_dev,
+        ref extras,
+        ref output,
+        write,
+    } = subcmd
+    {
+        if write {
+            abort("`--write` only works with `--format poetry`");
+        }
+        if !found_lock {
+            abort("Can't find `pyflow.lock`; run `pyflow install` first");
+        }
+        let selection = actions::Selection {
+            dev: dev || !(no_dev || util::no_dev_from_env()),
+            extras,
+        };
+        actions::export(
+            &pcfg.config,
+            &pcfg.project_path,
+            &lockpacks,
+            format,
+            &selection,
+            output.as_deref(),
+            os,
+            &py_vers,
+        );
+        process::exit(0)
+    }
+
+    if let SubCommand::Audit {
+        severity,
+        ref ignore,
+        offline,
+        json,
+    } = subcmd
+    {
+        if !found_lock {
+            abort("Can't find `pyflow.lock`; run `pyflow install` first");
+        }
+        let ignore = [ignore.as_slice(), pcfg.config.audit.ignore.as_slice()].concat();
+        actions::audit(&lockpacks, &pyflow_path, severity, &ignore, offline, json);
+    }
+
+    if let SubCommand::List {
+        outdated: true,
+        json,
+        exit_code,
+        within,
+    } = subcmd
+    {
+        if !found_lock {
+            abort("Can't find `pyflow.lock`; run `pyflow install` first");
+        }
+        let reqs = [pcfg.config.reqs.as_slice(), pcfg.config.dev_reqs.as_slice()].concat();
+        actions::outdated(&lockpacks, &reqs, &py_vers, within, json, exit_code);
+        process::exit(0)
+    }
+
+    let hooks = if no_hooks {
+        Hooks::default()
+    } else {
+        pcfg.config.hooks.clone()
+    };
+
+    // Reinstall straight from the lock, without resolving or rewriting it.
+    if let SubCommand::Install {
+        reinstall,
+        reinstall_all,
+        ..
+    } = &subcmd
+    {
+        if *reinstall_all || !reinstall.is_empty() {
+            if !found_lock {
+                abort("Can't find `pyflow.lock`; run `pyflow install` first");
+            }
+            let names: &[String] = if *reinstall_all { &[] } else { reinstall };
+            util::deps::reinstall(&paths, &lockpacks, names, os, &py_vers, &hooks);
+            // For interpreters started from the environment without our `PYTHONPATH`.
+            util::write_pth(&vers_path, &pythonpath);
+            util::success("Reinstall complete");
+            process::exit(0)
+        }
+    }
+
+    // The requirements file stands in for the lock, so `pyproject.toml` isn't resolved.
+    if let SubCommand::Sync {
+        ref from_requirements,
+        write_lock,
+        require_hashes,
+        json,
+    } = subcmd
+    {
+        let summary = actions::sync(
+            &pcfg,
+            &paths,
+            from_requirements,
+            write_lock,
+            require_hashes,
+            os,
+            &py_vers,
+            &hooks,
+        );
+        util::write_pth(&vers_path, &pythonpath);
+        util::print_status("Sync complete", Color::Green);
+        if json {
+            util::print_json(&summary);
+        }
+        process::exit(0)
+    }
+
+    let no_dev = match subcmd {
+        SubCommand::Install { no_dev, .. } | SubCommand::Add { no_dev, .. } => no_dev,
+        _ => false,
+    } || util::no_dev_from_env();
+
+    // In a workspace, every project's requirements, since they share the lock and environment.
+    let (sync_reqs, sync_dev_reqs) = pcfg.sync_reqs(&pcfg.config.reqs, &pcfg.config.dev_reqs);
+    let mut summary = sync(
         &paths,
         &lockpacks,
-        &pcfg.config.reqs,
-        &pcfg.config.dev_reqs,
-        &util::find_dont_uninstall(&pcfg.config.reqs, &pcfg.config.dev_reqs),
+        &sync_reqs,
+        &sync_dev_reqs,
+        &pcfg.constraints,
+        &util::find_dont_uninstall(&sync_reqs, &sync_dev_reqs),
         os,
         &py_vers,
         &pcfg.lock_path,
+        no_dev,
+        &hooks,
     );
+    util::write_pth(&vers_path, &pythonpath);
+
+    // Later phases build on the lock `sync` just wrote, not the one it replaced.
+    let lockpacks = util::read_lock(&pcfg.lock_path)
+        .ok()
+        .and_then(|l| l.package)
+        .unwrap_or(lockpacks);
 
     // Now handle subcommands that require info about the environment
     match subcmd {
@@ -181,89 +817,147 @@ fn main() {
         // We use data from three sources: `pyproject.toml`, `pyflow.lock`, and
         // the currently-installed packages, found by crawling metadata in the `lib` path.
         // See the readme section `How installation and locking work` for details.
-        SubCommand::Install { packages, dev } | SubCommand::Add { packages, dev } => {
-            actions::install(
+        SubCommand::Install { .. } | SubCommand::Add { .. } => {
+            let (added, dev, json) = match subcmd {
+                SubCommand::Add {
+                    packages,
+                    dev,
+                    source,
+                    json,
+                    ..
+                } => (actions::reqs_to_add(&packages, &source), dev, json),
+                SubCommand::Install {
+                    packages,
+                    dev,
+                    json,
+                    ..
+                } => (util::parse_cli_reqs(&packages), dev, json),
+                _ => unreachable!(),
+            };
+            summary.extend(actions::install(
+                &pcfg, &git_path, &paths, found_lock, added, dev, no_dev, &lockpacks, &os,
+                &py_vers, &hooks,
+            ));
+            if json {
+                util::print_json(&summary);
+            }
+        }
+
+        SubCommand::Remove {
+            packages,
+            dev,
+            group,
+            json,
+        } => {
+            let (reqs, dev_reqs) = actions::remove(
                 &pcfg.config_path,
                 &pcfg.config,
-                &git_path,
-                &paths,
-                found_lock,
                 &packages,
                 dev,
-                &lockpacks,
-                &os,
-                &py_vers,
-                &pcfg.lock_path,
-            )
-        }
-
-        SubCommand::Uninstall { packages } => {
-            // TODO: uninstall dev?
-            // Remove dependencies specified in the CLI from the config, then lock and sync.
-
-            let removed_reqs: Vec<String> = packages
-                .into_iter()
-                .map(|p| {
-                    Req::from_str(&p, false)
-                        .expect("Problem parsing req while uninstalling")
-                        .name
-                })
-                .collect();
-
-            files::remove_reqs_from_cfg(&pcfg.config_path, &removed_reqs);
-
-            // Filter reqs here instead of re-reading the config from file.
-            let updated_reqs: Vec<Req> = pcfg
-                .config
-                .clone()
-                .reqs
-                .into_iter()
-                .filter(|req| !removed_reqs.contains(&req.name))
-                .collect();
+                group.as_deref(),
+            );
+            let (reqs, dev_reqs) = pcfg.sync_reqs(&reqs, &dev_reqs);
 
-            sync(
+            summary.extend(sync(
                 &paths,
                 &lockpacks,
-                &updated_reqs,
-                &pcfg.config.dev_reqs,
+                &reqs,
+                &dev_reqs,
+                &pcfg.constraints,
                 &[],
                 os,
                 &py_vers,
                 &pcfg.lock_path,
-            );
-            util::print_color("Uninstall complete", Color::Green);
+                no_dev,
+                &hooks,
+            ));
+            util::print_status("Remove complete", Color::Green);
+            if json {
+                util::print_json(&summary);
+            }
         }
 
-        SubCommand::Package { extras } => actions::package(
-            &paths,
-            &lockpacks,
-            os,
-            &py_vers,
-            &pcfg.lock_path,
-            &pcfg.config,
-            &extras,
-        ),
-        SubCommand::Publish {} => build::publish(&paths.bin, &pcfg.config),
-        SubCommand::List {} => actions::list(
-            &paths.lib,
-            &[pcfg.config.reqs.as_slice(), pcfg.config.dev_reqs.as_slice()]
-                .concat()
-                .into_iter()
-                .filter(|r| r.path.is_some())
-                .collect::<Vec<Req>>(),
-        ),
+        SubCommand::Update {
+            packages,
+            within,
+            json,
+        } => {
+            if !found_lock {
+                abort("Can't find `pyflow.lock`; run `pyflow install` first");
+            }
+            summary.extend(actions::update(
+                &pcfg, &paths, &packages, within, &lockpacks, os, &py_vers, no_dev, &hooks,
+            ));
+            util::print_status("Update complete", Color::Green);
+            if json {
+                util::print_json(&summary);
+            }
+        }
+
+        SubCommand::Package { extras } => {
+            actions::package(&paths, &lockpacks, os, &py_vers, &pcfg, &extras, &hooks)
+        }
+        SubCommand::Repl { ipython } => {
+            util::run_env::apply(&util::run_env::collect(&pcfg, None));
+            if !pcfg.config.allow_user_site {
+                util::run_env::isolate_user_site(util::user_site_packages(&py_vers));
+            }
+            if env_mode == EnvMode::Venv {
+                util::run_env::activate_venv(&vers_path.join(".venv"), &paths.bin);
+            }
+            // Keep the startup file out of the project root with `env-mode = "venv"`.
+            let startup_dir = match env_mode {
+                EnvMode::Pypackages => vers_path.clone(),
+                EnvMode::Venv => vers_path.join(".venv"),
+            };
+            process::exit(actions::shell(&paths, &pythonpath, &startup_dir, ipython))
+        }
         _ => (),
     }
 
     if let Some(x) = extcmd {
+        // Eg `pyflow run serve`, with `serve = { run = "app.main:serve", env = { PORT = "8000" } }`.
+        let script = match x.cmd {
+            ExternalSubcommands::Run => x.args.first().map(String::as_str),
+            _ => None,
+        };
+        util::run_env::apply(&util::run_env::collect(&pcfg, script));
+        if !(pcfg.config.allow_user_site || x.system_site) {
+            util::run_env::isolate_user_site(util::user_site_packages(&py_vers));
+        }
+        if env_mode == EnvMode::Venv {
+            util::run_env::activate_venv(&vers_path.join(".venv"), &paths.bin);
+        }
+        if let ExternalSubcommands::Run = x.cmd {
+            let current_dir = env::current_dir().expect("Can't access current directory");
+            if let Some(dir) = actions::working_dir(
+                x.cwd.as_deref(),
+                &pcfg.config,
+                &pcfg.project_path,
+                script,
+                &current_dir,
+            ) {
+                if !dir.is_dir() || env::set_current_dir(&dir).is_err() {
+                    abort(&format!("Can't run from {:?}; is it a directory?", dir))
+                }
+            }
+        }
         match x.cmd {
             ExternalSubcommands::Python => {
-                if commands::run_python(&paths.bin, &pythonpath, &x.args).is_err() {
-                    abort("Problem running Python");
-                }
+                commands::exit_on_failure(
+                    commands::run_python(&paths.bin, &pythonpath, &x.args),
+                    "Problem running Python",
+                );
             }
             ExternalSubcommands::Run => {
-                run(&paths.lib, &paths.bin, &vers_path, &pcfg.config, x.args);
+                run(
+                    &pythonpath,
+                    &paths.bin,
+                    &paths.entry_pt,
+                    &pcfg.config,
+                    x.args,
+                    x.quiet,
+                );
             }
             x => {
                 abort(&format!(
@@ -1,274 +1,543 @@
 //! Manages Python installations
 
 use crate::commands;
-use crate::dep_types::Version;
-use crate::{install, util};
+use crate::dep_types::{Constraint, Version};
+use crate::pyproject::EnvMode;
+use crate::{install, util, util::prompts::Fallback};
+use regex::Regex;
+use serde::Deserialize;
 use std::error::Error;
 #[allow(unused_imports)]
-use std::{fmt, fs, io, path::Path, path::PathBuf};
+use std::{env, fmt, fs, io, path::Path, path::PathBuf};
 use termcolor::Color;
 
-/// Only versions we've built and hosted
-#[derive(Clone, Copy, Debug)]
-enum PyVers {
-    V3_12_0, // unreleased
-    V3_11_0, // unreleased
-    V3_10_2, // Win
-    V3_9_0,  // either Os
-    V3_8_0,  // either Os
-    V3_7_4,  // either Os
-    V3_6_9,  // Linux
-    V3_6_8,  // Win
-    V3_5_7,  // Linux
-    V3_5_4,  // Win
-    V3_4_10, // Linux
-}
-
-/// Reduces code repetition for error messages related to Python binaries we don't support.
-fn abort_helper(version: &str, os: &str) {
-    util::abort(&format!(
-        "Automatic installation of Python {} on {} is currently unsupported. If you'd like \
-         to use this version of Python, please install it.",
-        version, os
+/// Releases of [python-build-standalone](https://github.com/astral-sh/python-build-standalone),
+/// newest first. Each release contains builds of the latest patch of every supported minor
+/// version, for every platform.
+const STANDALONE_RELEASES_URL: &str =
+    "https://api.github.com/repos/astral-sh/python-build-standalone/releases?per_page=30";
+
+#[derive(Debug, Deserialize)]
+struct GithubRelease {
+    assets: Vec<GithubAsset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubAsset {
+    name: String,
+    browser_download_url: String,
+}
+
+/// A standalone Python build we can download, and where to find its checksum.
+#[derive(Debug)]
+struct StandaloneBuild {
+    version: Version,
+    filename: String,
+    url: String,
+    /// The build's own `.sha256` file, used by older releases.
+    sha256_url: Option<String>,
+    /// A `SHA256SUMS` file covering every asset in the release, used by newer ones.
+    sums_url: Option<String>,
+}
+
+/// The target triple python-build-standalone uses for this machine.
+fn host_triple() -> Option<&'static str> {
+    match (env::consts::OS, env::consts::ARCH) {
+        ("linux", "x86_64") => Some("x86_64-unknown-linux-gnu"),
+        ("linux", "aarch64") => Some("aarch64-unknown-linux-gnu"),
+        ("macos", "x86_64") => Some("x86_64-apple-darwin"),
+        ("macos", "aarch64") => Some("aarch64-apple-darwin"),
+        ("windows", "x86_64") => Some("x86_64-pc-windows-msvc"),
+        ("windows", "x86") => Some("i686-pc-windows-msvc"),
+        _ => None,
+    }
+}
+
+/// Find the Python version of a build from its asset name, if it's a build for `triple`.
+/// Names look like `cpython-3.12.3+20240415-x86_64-unknown-linux-gnu-install_only.tar.gz`;
+/// older Windows builds have `-shared` after the triple.
+fn parse_asset_name(name: &str, triple: &str) -> Option<Version> {
+    let re = Regex::new(&format!(
+        r"^cpython-(\d+\.\d+\.\d+)\+\d+-{}(?:-shared)?-install_only\.tar\.gz$",
+        regex::escape(triple)
     ))
+    .unwrap();
+    re.captures(name)?.get(1)?.as_str().parse().ok()
 }
 
-impl From<(Version, Os)> for PyVers {
-    fn from(v_o: (Version, Os)) -> Self {
-        let unsupported = "Unsupported python version requested; only Python ≥ 3.4 is supported. \
-        to fix this, edit the `py_version` line of `pyproject.toml`, or run `pyflow switch 3.7`";
-        if v_o.0.major != Some(3) {
-            util::abort(unsupported)
+/// Major and minor versions match, and the patch too if `requested` specifies one.
+pub fn version_matches(candidate: &Version, requested: &Version) -> bool {
+    candidate.major == requested.major
+        && candidate.minor == requested.minor
+        && requested.patch.map_or(true, |p| candidate.patch == Some(p))
+}
+
+fn fetch_standalone_releases() -> Vec<GithubRelease> {
+    util::http_client()
+        .get(STANDALONE_RELEASES_URL)
+        .header("User-Agent", "pyflow")
+        .send()
+        .and_then(|r| r.json())
+        .unwrap_or_else(|_| util::abort("Problem fetching the list of available Python builds"))
+}
+
+/// The CPython versions we can download for this machine: the newest patch of each minor
+/// version, from the latest release that has builds for it.
+pub fn available_cpython_versions() -> Vec<Version> {
+    let triple = match host_triple() {
+        Some(t) => t,
+        None => return vec![],
+    };
+    for release in fetch_standalone_releases() {
+        let mut result: Vec<Version> = release
+            .assets
+            .iter()
+            .filter_map(|a| parse_asset_name(&a.name, triple))
+            .collect();
+        if !result.is_empty() {
+            result.sort();
+            result.dedup();
+            result.reverse();
+            return result;
         }
-        match v_o.0.minor.unwrap_or(0) {
-            4 => match v_o.1 {
-                Os::Windows => {
-                    abort_helper("3.4", "Windows");
-                    unreachable!()
-                }
-                Os::Ubuntu | Os::Centos => Self::V3_4_10,
-                _ => {
-                    abort_helper("3.4", "Mac");
-                    unreachable!()
-                }
-            },
-            5 => match v_o.1 {
-                Os::Windows => Self::V3_5_4,
-                Os::Ubuntu | Os::Centos => Self::V3_5_7,
-                _ => {
-                    abort_helper("3.5", "Mac");
-                    unreachable!()
-                }
-            },
-            6 => match v_o.1 {
-                Os::Windows => Self::V3_6_8,
-                Os::Ubuntu | Os::Centos => Self::V3_6_9,
-                _ => {
-                    abort_helper("3.6", "Mac");
-                    unreachable!()
-                }
-            },
-            7 => match v_o.1 {
-                Os::Windows | Os::Ubuntu | Os::Centos => Self::V3_7_4,
-                _ => {
-                    abort_helper("3.7", "Mac");
-                    unreachable!()
-                }
-            },
-            8 => match v_o.1 {
-                Os::Windows | Os::Ubuntu | Os::Centos => Self::V3_8_0,
-                _ => {
-                    abort_helper("3.8", "Mac");
-                    unreachable!()
-                }
-            },
-            9 => match v_o.1 {
-                Os::Windows | Os::Ubuntu | Os::Centos => Self::V3_9_0,
-                _ => {
-                    abort_helper("3.9", "Mac");
-                    unreachable!()
-                }
-            },
-            10 => match v_o.1 {
-                Os::Windows => Self::V3_10_2,
-                Os::Ubuntu | Os::Centos => {
-                    abort_helper("3.10", "Linux");
-                    unreachable!()
-                }
-                _ => {
-                    abort_helper("3.10", "Mac");
-                    unreachable!()
-                }
-            },
-            11 => match v_o.1 {
-                Os::Windows | Os::Ubuntu | Os::Centos => Self::V3_11_0,
-                _ => {
-                    abort_helper("3.11", "Mac");
-                    unreachable!()
+    }
+    vec![]
+}
+
+/// Search recent releases for the newest build matching `version`'s major and minor version,
+/// or its exact version if it has a patch. We don't use a baked-in table, so new Python
+/// versions work as soon as builds exist.
+fn find_standalone_build(version: &Version, triple: &str) -> Option<StandaloneBuild> {
+    for release in fetch_standalone_releases() {
+        let mut best: Option<(Version, &GithubAsset)> = None;
+        for asset in &release.assets {
+            if let Some(v) = parse_asset_name(&asset.name, triple) {
+                if !version_matches(&v, version) {
+                    continue;
                 }
-            },
-            12 => match v_o.1 {
-                Os::Windows | Os::Ubuntu | Os::Centos => Self::V3_12_0,
-                _ => {
-                    abort_helper("3.12", "Mac");
-                    unreachable!()
+                if best.as_ref().map_or(true, |(b, _)| v > *b) {
+                    best = Some((v, asset));
                 }
-            },
-            _ => util::abort(unsupported),
+            }
+        }
+
+        if let Some((v, asset)) = best {
+            let find_url = |name: &str| {
+                release
+                    .assets
+                    .iter()
+                    .find(|a| a.name == name)
+                    .map(|a| a.browser_download_url.clone())
+            };
+            return Some(StandaloneBuild {
+                version: v,
+                filename: asset.name.clone(),
+                url: asset.browser_download_url.clone(),
+                sha256_url: find_url(&format!("{}.sha256", asset.name)),
+                sums_url: find_url("SHA256SUMS"),
+            });
         }
     }
+    None
 }
 
-impl ToString for PyVers {
-    fn to_string(&self) -> String {
-        match self {
-            Self::V3_12_0 => "3.12.0".into(),
-            Self::V3_11_0 => "3.11.0".into(),
-            Self::V3_10_2 => "3.10.2".into(),
-            Self::V3_9_0 => "3.9.0".into(),
-            Self::V3_8_0 => "3.8.0".into(),
-            Self::V3_7_4 => "3.7.4".into(),
-            Self::V3_6_9 => "3.6.9".into(),
-            Self::V3_6_8 => "3.6.8".into(),
-            Self::V3_5_7 => "3.5.7".into(),
-            Self::V3_5_4 => "3.5.4".into(),
-            Self::V3_4_10 => "3.4.10".into(),
+/// Find the published SHA-256 digest for a GitHub release asset, as lowercase hex: from its own
+/// `.sha256` file, or a `SHA256SUMS` file covering the release.
+pub fn fetch_checksum(
+    filename: &str,
+    sha256_url: Option<&str>,
+    sums_url: Option<&str>,
+) -> Option<String> {
+    let fetch = |url: &str| {
+        util::http_client()
+            .get(url)
+            .send()
+            .and_then(|r| r.text())
+            .ok()
+    };
+
+    if let Some(url) = sha256_url {
+        return fetch(url)?.split_whitespace().next().map(str::to_lowercase);
+    }
+    // Lines look like `<digest>  <filename>`.
+    let sums = fetch(sums_url?)?;
+    sums.lines().find_map(|l| {
+        let mut parts = l.split_whitespace();
+        let digest = parts.next()?;
+        if parts.next()?.trim_start_matches('*') == filename {
+            Some(digest.to_lowercase())
+        } else {
+            None
         }
+    })
+}
+
+/// Download the newest patch of `version` for this implementation, and return its interpreter.
+fn download(pyflow_dir: &Path, version: &Version, implementation: Implementation) -> Interpreter {
+    match implementation {
+        Implementation::CPython => download_cpython(pyflow_dir, version),
+        Implementation::PyPy => download_pypy(pyflow_dir, version),
     }
 }
 
-impl PyVers {
-    fn to_vers(self) -> Version {
-        match self {
-            Self::V3_12_0 => Version::new(3, 12, 0),
-            Self::V3_11_0 => Version::new(3, 11, 0),
-            Self::V3_10_2 => Version::new(3, 10, 2),
-            Self::V3_9_0 => Version::new(3, 9, 0),
-            Self::V3_8_0 => Version::new(3, 8, 0),
-            Self::V3_7_4 => Version::new(3, 7, 4),
-            Self::V3_6_9 => Version::new(3, 6, 9),
-            Self::V3_6_8 => Version::new(3, 6, 8),
-            Self::V3_5_7 => Version::new(3, 5, 7),
-            Self::V3_5_4 => Version::new(3, 5, 4),
-            Self::V3_4_10 => Version::new(3, 4, 10),
+/// Download a file to `path`, unless it's already there.
+fn download_file(url: &str, path: &Path, description: &str) {
+    if path.exists() {
+        return;
+    }
+    util::print_status(&format!("Downloading {}...", description), Color::Cyan);
+    let start = std::time::Instant::now();
+    let mut resp = util::http_client()
+        .get(url)
+        .send()
+        .expect("Problem downloading Python");
+    let mut out = fs::File::create(path).expect("Failed to save downloaded Python archive");
+    if let Err(e) = io::copy(&mut resp, &mut out) {
+        // Clean up the downloaded file, or we'll get an error next time.
+        fs::remove_file(path).expect("Problem removing the broken file");
+        util::abort(&format!("Problem downloading the Python archive: {:?}", e));
+    }
+    log::debug!("Downloaded {} in {:.2?}", description, start.elapsed());
+}
+
+/// Records where a Python we installed came from: the URL we downloaded it from, or the
+/// archive it was installed from with `pyflow python install`.
+const ORIGIN_RECORD: &str = ".pyflow-origin";
+
+/// Extract a python-build-standalone `install_only` archive to `python_dir/python-x.y.z`, after
+/// checking that it runs here, and is the version we expect. Returns its interpreter.
+fn install_standalone(
+    python_dir: &Path,
+    archive_path: &Path,
+    version: &Version,
+    origin: &str,
+) -> Interpreter {
+    let install_path = python_dir.join(format!("python-{}", version));
+
+    // The archive contains a single `python` folder; extract beside the final location,
+    // so a failed extraction doesn't leave a broken install.
+    let tmp_path = python_dir.join(format!("python-{}-tmp", version));
+    if tmp_path.exists() {
+        fs::remove_dir_all(&tmp_path).expect("Problem removing a previous extraction");
+    }
+    let start = std::time::Instant::now();
+    util::unpack_tar_gz(archive_path, &tmp_path);
+    log::debug!("Extracted {:?} in {:.2?}", archive_path, start.elapsed());
+
+    let extracted = tmp_path.join("python");
+    match Interpreter::from_path(&extracted.join(PYFLOW_PY_NAME), InterpreterSource::Pyflow) {
+        Some(i)
+            if i.implementation == Implementation::CPython
+                && version_matches(&i.version, version) => {}
+        Some(i) => {
+            fs::remove_dir_all(&tmp_path).unwrap_or(());
+            util::abort(&format!(
+                "{:?} contains {}, but Python {} was expected",
+                archive_path,
+                i.name(),
+                version
+            ))
+        }
+        None => {
+            fs::remove_dir_all(&tmp_path).unwrap_or(());
+            util::abort(&format!(
+                "The Python in {:?} doesn't run on this system. Is it built for {} {}?",
+                archive_path,
+                env::consts::OS,
+                env::consts::ARCH
+            ))
         }
     }
+
+    fs::rename(&extracted, &install_path).expect("Problem moving extracted Python folder");
+    fs::remove_dir_all(&tmp_path).unwrap_or(());
+    fs::write(install_path.join(ORIGIN_RECORD), origin).unwrap_or(());
+
+    Interpreter {
+        path: install_path.join(PYFLOW_PY_NAME),
+        version: version.clone(),
+        implementation: Implementation::CPython,
+        source: InterpreterSource::Pyflow,
+    }
 }
 
-/// Only Oses we've built and hosted
-/// TODO: How cross-compat are these? Eg work across diff versions of Ubuntu?
-/// TODO: 32-bit
-#[derive(Clone, Copy, Debug)]
-#[allow(dead_code)]
-enum Os {
-    // Don't confuse with crate::Os
-    Ubuntu, // Builds on Ubuntu 18.04 work on Ubuntu 19.04, Debian, Arch, and Kali
-    Centos, // Will this work on Red Hat and Fedora as well?
-    Windows,
-    Mac,
+/// The Python version of a python-build-standalone `install_only` archive, from its filename.
+/// Unlike `parse_asset_name`, accepts any platform.
+fn parse_archive_name(name: &str) -> Option<Version> {
+    let re = Regex::new(r"^cpython-(\d+\.\d+\.\d+)\+\d+-[\w.-]+-install_only\.tar\.gz$").unwrap();
+    re.captures(name)?.get(1)?.as_str().parse().ok()
 }
 
-/// For use in the Linux distro prompt
-impl fmt::Display for Os {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                Self::Ubuntu => "Ubuntu",
-                Self::Centos => "Centos",
-                Self::Windows => "Windows",
-                Self::Mac => "Mac",
-            }
-        )
+/// Register a python-build-standalone archive we didn't download, eg one copied from a
+/// share for machines without internet access. Used by `pyflow python install`.
+pub fn install_archive(python_dir: &Path, archive_path: &Path) -> Interpreter {
+    if !archive_path.is_file() {
+        util::abort(&format!("Can't find the archive {:?}", archive_path))
+    }
+    let filename = archive_path
+        .file_name()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let version = parse_archive_name(&filename).unwrap_or_else(|| {
+        util::abort(&format!(
+            "{} isn't a python-build-standalone `install_only` archive, eg \
+             `cpython-3.12.3+20240415-x86_64-unknown-linux-gnu-install_only.tar.gz`",
+            filename
+        ))
+    });
+
+    if !python_dir.exists() && fs::create_dir_all(python_dir).is_err() {
+        util::abort(&format!("Problem creating {:?}", python_dir))
+    }
+    let install_path = python_dir.join(format!("python-{}", version));
+    if install_path.exists() {
+        util::abort(&format!(
+            "Python {} is already installed, at {:?}",
+            version, install_path
+        ))
     }
+
+    let origin = fs::canonicalize(archive_path).unwrap_or_else(|_| archive_path.to_owned());
+    util::print_status(&format!("Installing Python {}...", version), Color::Cyan);
+    install_standalone(
+        python_dir,
+        archive_path,
+        &version,
+        &origin.to_string_lossy(),
+    )
 }
 
-fn download(py_install_path: &Path, version: &Version) {
-    // We use the `.xz` format due to its small size compared to `.zip`. On order half the size.
-    let os;
-    let os_str;
-    #[cfg(target_os = "windows")]
-    {
-        os = Os::Windows;
-        os_str = "windows";
+/// Where an interpreter we installed came from, if recorded.
+pub fn install_origin(python_dir: &Path, interpreter: &Interpreter) -> Option<String> {
+    let rel = interpreter.path.strip_prefix(python_dir).ok()?;
+    let install_dir = python_dir.join(rel.components().next()?);
+    let data = fs::read_to_string(install_dir.join(ORIGIN_RECORD)).ok()?;
+    Some(data.trim().to_owned())
+}
+
+/// Compare a downloaded archive's SHA-256 digest with the expected, lowercase hex, one.
+/// Removes the archive and aborts if they differ.
+pub fn check_digest(archive_path: &Path, expected: &str) {
+    let actual = install::sha256_digest(io::BufReader::new(util::open_archive(archive_path)))
+        .map(|d| data_encoding::HEXLOWER.encode(d.as_ref()))
+        .unwrap_or_default();
+    if actual != expected {
+        fs::remove_file(archive_path).unwrap_or(());
+        util::abort(&format!(
+            "Hash failed for {:?}. Expected: {}, Actual: {}. The download has been removed; \
+             try again.",
+            archive_path, expected, actual
+        ));
     }
-    #[cfg(target_os = "linux")]
-    {
-        let result = util::prompts::list(
-            "Please enter the number corresponding to your Linux distro:",
-            "Linux distro",
-            &[
-                (
-                    "2016 or newer (Ubuntu≥16.04, Debian≥9, SUSE≥15, Arch, Kali, etc)".to_owned(),
-                    Os::Ubuntu,
-                ),
-                (
-                    "Older (Centos, Redhat, Fedora, older versions of distros listed in option 1)"
-                        .to_owned(),
-                    Os::Centos,
-                ),
-            ],
-            false,
+}
+
+/// Download a standalone build of the newest patch of `version`, verify it against its
+/// published checksum, and extract it to `pyflow_dir/python-x.y.z`. Returns its interpreter.
+fn download_cpython(pyflow_dir: &Path, version: &Version) -> Interpreter {
+    let triple = host_triple().unwrap_or_else(|| {
+        util::abort(&format!(
+            "Automatic installation of Python isn't supported on {} {}. If you'd like to use \
+             this version of Python, please install it.",
+            env::consts::OS,
+            env::consts::ARCH
+        ))
+    });
+
+    let build = find_standalone_build(version, triple).unwrap_or_else(|| {
+        util::abort(&format!(
+            "Can't find a Python {} build for {}. If you'd like to use this version of \
+             Python, please install it, or set `py_version` in `pyproject.toml` to a \
+             different one.",
+            version, triple
+        ))
+    });
+
+    let install_path = pyflow_dir.join(format!("python-{}", build.version));
+    // Kept, so we don't need to download it again if we remove the extracted copy.
+    let archive_path = pyflow_dir.join(&build.filename);
+
+    if !install_path.exists() {
+        download_file(
+            &build.url,
+            &archive_path,
+            &format!("Python {}", build.version),
         );
-        os = result.1;
-        os_str = match os {
-            Os::Ubuntu => "ubuntu",
-            Os::Centos => "centos",
-            _ => {
-                util::abort(
-                    "Unfortunately, we don't yet support other Operating systems.\
-                     It's worth trying the other options, to see if one works anyway.",
-                );
-                unreachable!()
-            }
-        };
-    }
-    #[cfg(target_os = "macos")]
-    {
-        os = Os::Mac;
-        os_str = "mac";
-    }
-
-    // Match up our version to the closest match (major+minor will match) we've built.
-    let vers_to_dl2: PyVers = (version.clone(), os).into();
-    let vers_to_dl = vers_to_dl2.to_string();
-
-    let url = format!(
-        "https://github.com/David-OConnor/pybin/releases/\
-         download/{}/python-{}-{}.tar.xz",
-        vers_to_dl, vers_to_dl, os_str
-    );
-
-    // eg `python-3.7.4-ubuntu.tar.xz`
-    let archive_path = py_install_path.join(format!("python-{}-{}.tar.xz", vers_to_dl, os_str));
-    if !archive_path.exists() {
-        // Save the file
-        util::print_color(
-            &format!("Downloading Python {}...", vers_to_dl),
+
+        let expected = fetch_checksum(
+            &build.filename,
+            build.sha256_url.as_deref(),
+            build.sums_url.as_deref(),
+        )
+        .unwrap_or_else(|| util::abort(&format!("Can't find a checksum for {}", build.filename)));
+        check_digest(&archive_path, &expected);
+
+        util::print_status(
+            &format!("Installing Python {}...", build.version),
             Color::Cyan,
         );
-        let mut resp = reqwest::get(&url).expect("Problem downloading Python"); // Download the file
-        let mut out =
-            fs::File::create(&archive_path).expect("Failed to save downloaded Python archive");
-        if let Err(e) = io::copy(&mut resp, &mut out) {
-            // Clean up the downloaded file, or we'll get an error next time.
-            fs::remove_file(&archive_path).expect("Problem removing the broken file");
-            util::abort(&format!("Problem downloading the Python archive: {:?}", e));
+        return install_standalone(pyflow_dir, &archive_path, &build.version, &build.url);
+    }
+
+    Interpreter {
+        path: install_path.join(PYFLOW_PY_NAME),
+        version: build.version,
+        implementation: Implementation::CPython,
+        source: InterpreterSource::Pyflow,
+    }
+}
+
+/// PyPy's index of its releases, and the files for each.
+const PYPY_VERSIONS_URL: &str = "https://downloads.python.org/pypy/versions.json";
+/// PyPy publishes checksums for its downloads here, as `<digest>  <filename>`.
+const PYPY_CHECKSUMS_URL: &str = "https://www.pypy.org/checksums.html";
+
+#[derive(Debug, Deserialize)]
+struct PypyRelease {
+    python_version: String,
+    stable: bool,
+    files: Vec<PypyFile>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct PypyFile {
+    filename: String,
+    arch: String,
+    platform: String,
+    download_url: String,
+}
+
+/// The platform and arch PyPy's index uses for this machine.
+fn pypy_platform() -> Option<(&'static str, &'static str)> {
+    match (env::consts::OS, env::consts::ARCH) {
+        ("linux", "x86_64") => Some(("linux", "x64")),
+        ("linux", "aarch64") => Some(("linux", "aarch64")),
+        ("macos", "x86_64") => Some(("darwin", "x64")),
+        ("macos", "aarch64") => Some(("darwin", "arm64")),
+        ("windows", "x86_64") => Some(("win64", "x64")),
+        _ => None,
+    }
+}
+
+fn fetch_pypy_checksum(filename: &str) -> Option<String> {
+    let page = util::http_client()
+        .get(PYPY_CHECKSUMS_URL)
+        .send()
+        .and_then(|r| r.text())
+        .ok()?;
+    let re = Regex::new(&format!(r"([0-9a-f]{{64}})\s+{}", regex::escape(filename))).unwrap();
+    Some(re.captures(&page)?.get(1)?.as_str().to_owned())
+}
+
+/// Find the newest stable PyPy build implementing `version`, for this platform.
+fn find_pypy_build(version: &Version, platform: &str, arch: &str) -> Option<(Version, PypyFile)> {
+    let releases: Vec<PypyRelease> = util::http_client()
+        .get(PYPY_VERSIONS_URL)
+        .send()
+        .and_then(|r| r.json())
+        .unwrap_or_else(|_| util::abort("Problem fetching the list of available PyPy builds"));
+
+    // Releases are listed newest first.
+    releases
+        .into_iter()
+        .filter(|r| r.stable)
+        .filter_map(|r| {
+            let v: Version = r.python_version.parse().ok()?;
+            if !version_matches(&v, version) {
+                return None;
+            }
+            let file = r
+                .files
+                .into_iter()
+                .find(|f| f.platform == platform && f.arch == arch)?;
+            Some((v, file))
+        })
+        .next()
+}
+
+/// Whether there are builds of this implementation we can download for this machine.
+pub fn can_download(implementation: Implementation) -> bool {
+    match implementation {
+        Implementation::CPython => host_triple().is_some(),
+        Implementation::PyPy => pypy_platform().is_some(),
+    }
+}
+
+/// The newest patch of `version` available to download, if any.
+pub fn newest_available(version: &Version, implementation: Implementation) -> Option<Version> {
+    let version = Version::new_opt(version.major, version.minor, None);
+    match implementation {
+        Implementation::CPython => {
+            find_standalone_build(&version, host_triple()?).map(|b| b.version)
+        }
+        Implementation::PyPy => {
+            let (platform, arch) = pypy_platform()?;
+            find_pypy_build(&version, platform, arch).map(|(v, _)| v)
         }
     }
-    util::print_color(&format!("Installing Python {}...", vers_to_dl), Color::Cyan);
+}
 
-    util::unpack_tar_xz(&archive_path, py_install_path);
+/// Download the newest stable PyPy implementing `version`, verify its checksum, and extract it
+/// to `pyflow_dir/pypy-x.y.z`. Returns its interpreter.
+fn download_pypy(pyflow_dir: &Path, version: &Version) -> Interpreter {
+    let (platform, arch) = pypy_platform().unwrap_or_else(|| {
+        util::abort(&format!(
+            "Automatic installation of PyPy isn't supported on {} {}. If you'd like to use \
+             it, please install it.",
+            env::consts::OS,
+            env::consts::ARCH
+        ))
+    });
 
-    // Strip the OS tag from the extracted Python folder name
-    let extracted_path = py_install_path.join(format!("python-{}", vers_to_dl));
+    let (py_version, file) = find_pypy_build(version, platform, arch).unwrap_or_else(|| {
+        util::abort(&format!(
+            "Can't find a PyPy build implementing Python {} for {} {}.",
+            version, platform, arch
+        ))
+    });
 
-    fs::rename(
-        py_install_path.join(format!("python-{}-{}", vers_to_dl, os_str)),
-        extracted_path,
-    )
-    .expect("Problem renaming extracted Python folder");
+    let install_path = pyflow_dir.join(format!("pypy-{}", py_version));
+    let archive_path = pyflow_dir.join(&file.filename);
+
+    if !install_path.exists() {
+        download_file(
+            &file.download_url,
+            &archive_path,
+            &format!("PyPy {}", py_version),
+        );
+
+        let expected = fetch_pypy_checksum(&file.filename).unwrap_or_else(|| {
+            util::abort(&format!("Can't find a checksum for {}", file.filename))
+        });
+        check_digest(&archive_path, &expected);
+
+        util::print_status(&format!("Installing PyPy {}...", py_version), Color::Cyan);
+
+        // The archive contains a single folder named after itself, eg `pypy3.10-v7.3.15-linux64`.
+        let tmp_path = pyflow_dir.join(format!("pypy-{}-tmp", py_version));
+        if tmp_path.exists() {
+            fs::remove_dir_all(&tmp_path).expect("Problem removing a previous extraction");
+        }
+        if file.filename.ends_with(".zip") {
+            util::extract_zip(&util::open_archive(&archive_path), &tmp_path, &None, &None);
+        } else {
+            util::unpack_tar_bz2(&archive_path, &tmp_path);
+        }
+        let extracted = util::find_folders(&tmp_path)
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| util::abort(&format!("Problem extracting {}", file.filename)));
+        fs::rename(tmp_path.join(extracted), &install_path)
+            .expect("Problem moving extracted PyPy folder");
+        fs::remove_dir_all(&tmp_path).unwrap_or(());
+        fs::write(install_path.join(ORIGIN_RECORD), &file.download_url).unwrap_or(());
+    }
+
+    Interpreter {
+        path: install_path.join(PYPY_PY_NAME),
+        version: py_version,
+        implementation: Implementation::PyPy,
+        source: InterpreterSource::Pyflow,
+    }
 }
 
 #[derive(Debug)]
@@ -288,61 +557,448 @@ impl fmt::Display for AliasError {
     }
 }
 
-/// Make an educated guess at the command needed to execute python the
-/// current system.  An alternative approach is trying to find python
-/// installations.
-pub fn find_py_aliases(version: &Version) -> Vec<(String, Version)> {
-    let possible_aliases = &[
-        "python3.19",
-        "python3.18",
-        "python3.17",
-        "python3.16",
-        "python3.15",
-        "python3.14",
-        "python3.13",
-        "python3.12",
-        "python3.11",
-        "python3.10",
-        "python3.9",
-        "python3.8",
-        "python3.7",
-        "python3.6",
-        "python3.5",
-        "python3.4",
-        "python3.3",
-        "python3.2",
-        "python3.1",
-        "python3",
-        "python",
-        "python2",
-    ];
-
-    let mut result = Vec::new();
-    let mut found_dets = Vec::new();
-
-    for alias in possible_aliases {
-        // We use the --version command as a quick+effective way to determine if
-        // this command is associated with Python.
-        let dets = commands::find_py_dets(alias);
-        if let Some(v) = commands::find_py_version(alias) {
-            if v.major == version.major && v.minor == version.minor && !found_dets.contains(&dets) {
-                result.push((alias.to_string(), v));
-                found_dets.push(dets);
-            }
+/// Which Python implementation to use. Set by `python_implementation` in `pyproject.toml`, or
+/// by a prefix on `py_version`, eg `py_version = "pypy3.10"`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[derive(Default)]
+pub enum Implementation {
+    #[default]
+    CPython,
+    PyPy,
+}
+
+impl fmt::Display for Implementation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CPython => write!(f, "CPython"),
+            Self::PyPy => write!(f, "PyPy"),
         }
     }
-    result
 }
 
-// Find versions installed with this tool.
-fn find_installed_versions(pyflow_dir: &Path) -> Vec<Version> {
+impl std::str::FromStr for Implementation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_ref() {
+            "cpython" | "python" | "cp" => Ok(Self::CPython),
+            "pypy" | "pp" => Ok(Self::PyPy),
+            _ => Err(format!("Unknown Python implementation: {}", s)),
+        }
+    }
+}
+
+impl Implementation {
+    /// The prefix used for this implementation in `py_version`.
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Self::CPython => "",
+            Self::PyPy => "pypy",
+        }
+    }
+}
+
+/// Split an implementation prefix off a version string, eg `pypy3.10` -> (`PyPy`, `3.10`).
+pub fn split_implementation(s: &str) -> (Option<Implementation>, &str) {
+    let s = s.trim();
+    for (prefix, implementation) in &[
+        ("pypy", Implementation::PyPy),
+        ("cpython", Implementation::CPython),
+    ] {
+        if s.len() >= prefix.len() && s[..prefix.len()].eq_ignore_ascii_case(prefix) {
+            return (
+                Some(*implementation),
+                s[prefix.len()..].trim_start_matches('-'),
+            );
+        }
+    }
+    (None, s)
+}
+
+/// Find which implementation a virtual environment was created with, from its `bin` folder.
+/// PyPy adds its own executables alongside `python`.
+pub fn venv_implementation(bin_path: &Path) -> Implementation {
+    if ["pypy3", "pypy", "pypy3.exe", "pypy.exe"]
+        .iter()
+        .any(|name| bin_path.join(name).exists())
+    {
+        Implementation::PyPy
+    } else {
+        Implementation::CPython
+    }
+}
+
+/// Where we found a Python interpreter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InterpreterSource {
+    System,
+    Pyenv,
+    Pyflow, // Downloaded by this tool
+}
+
+/// A Python interpreter available on this machine.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Interpreter {
+    pub path: PathBuf,
+    pub version: Version,
+    pub implementation: Implementation,
+    pub source: InterpreterSource,
+}
+
+impl fmt::Display for InterpreterSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = match self {
+            Self::System => "system",
+            Self::Pyenv => "pyenv",
+            Self::Pyflow => "installed by pyflow",
+        };
+        f.pad(source)
+    }
+}
+
+impl fmt::Display for Interpreter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}): {}",
+            self.name(),
+            self.source,
+            self.path.display()
+        )
+    }
+}
+
+impl Interpreter {
+    /// Run the binary to find its exact version, and implementation.
+    pub fn from_path(path: &Path, source: InterpreterSource) -> Option<Self> {
+        let (version, implementation) = commands::find_py_details(path.to_str()?)?;
+        Some(Self {
+            path: path.to_owned(),
+            version,
+            implementation,
+            source,
+        })
+    }
+
+    /// Major and minor versions and the implementation match. We only care about patch if
+    /// `version` specifies one, eg `py_version = "3.11.8"`.
+    pub fn matches(&self, version: &Version, implementation: Implementation) -> bool {
+        version_matches(&self.version, version) && self.implementation == implementation
+    }
+
+    /// Eg `Python 3.11.8`, or `PyPy 3.10.13`.
+    pub fn name(&self) -> String {
+        let name = match self.implementation {
+            Implementation::CPython => "Python",
+            Implementation::PyPy => "PyPy",
+        };
+        format!("{} {}", name, self.version)
+    }
+}
+
+/// Locations Python is commonly installed to, that may not be on the `PATH`.
+fn common_python_dirs() -> Vec<PathBuf> {
     #[cfg(target_os = "windows")]
-    let py_name = "python";
+    {
+        vec![]
+    }
     #[cfg(target_os = "linux")]
-    let py_name = "bin/python3";
+    {
+        vec![PathBuf::from("/usr/bin"), PathBuf::from("/usr/local/bin")]
+    }
     #[cfg(target_os = "macos")]
-    let py_name = "bin/python3";
+    {
+        vec![
+            PathBuf::from("/usr/bin"),
+            PathBuf::from("/usr/local/bin"),
+            PathBuf::from("/opt/homebrew/bin"),
+            PathBuf::from("/Library/Frameworks/Python.framework/Versions/Current/bin"),
+        ]
+    }
+}
+
+/// Ask the Windows `py` launcher, which reads the registry, where its interpreters are.
+/// Output lines look like ` -V:3.12 *        C:\Python312\python.exe`.
+#[cfg(target_os = "windows")]
+fn find_py_launcher_paths() -> Vec<PathBuf> {
+    let output = match std::process::Command::new("py").arg("-0p").output() {
+        Ok(o) => o,
+        Err(_) => return vec![],
+    };
+    let re = Regex::new(r"^\s*-\S+(?:\s+\*)?\s+(.+?\.exe)\s*$").unwrap();
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|l| {
+            re.captures(l)
+                .map(|c| PathBuf::from(c.get(1).unwrap().as_str()))
+        })
+        .collect()
+}
+
+/// Shown when all we find is the Windows Store's `python.exe` alias.
+const WINDOWS_STORE_ALIAS_MSG: &str = "The only Python found is the Windows Store alias, which \
+    doesn't run Python. Install Python from python.org (or the Store), or disable the alias in \
+    Settings → Apps → Advanced app settings → App execution aliases.";
+
+/// The Windows Store's app execution aliases live directly in
+/// `%LOCALAPPDATA%\Microsoft\WindowsApps`, as zero-length reparse points. Those for a Python
+/// installed from the Store run it, and have its package's folder beside them. Without one, the
+/// `python.exe` and `python3.exe` aliases are stubs that open the Store instead.
+#[cfg(target_os = "windows")]
+fn is_windows_store_alias(path: &Path) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_REPARSE_POINT: u32 = 0x400;
 
+    let is_alias = fs::symlink_metadata(path).is_ok_and(|m| {
+        m.len() == 0
+            && !m.file_type().is_symlink()
+            && m.file_attributes() & FILE_ATTRIBUTE_REPARSE_POINT != 0
+    });
+    if !is_alias {
+        return false;
+    }
+    let siblings: Vec<String> = path
+        .parent()
+        .and_then(|dir| dir.read_dir().ok())
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default();
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    is_store_stub(&name, &siblings)
+}
+
+#[cfg(not(target_os = "windows"))]
+fn is_windows_store_alias(_path: &Path) -> bool {
+    false
+}
+
+/// Whether an app execution alias named `name` opens the Store, given the names of the other
+/// entries in its folder.
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+fn is_store_stub(name: &str, siblings: &[String]) -> bool {
+    let name = name.to_lowercase();
+    (name == "python.exe" || name == "python3.exe")
+        && !siblings
+            .iter()
+            .any(|s| s.starts_with("PythonSoftwareFoundation.Python."))
+}
+
+/// Find Python interpreters on the `PATH`, and in common install locations. We run each
+/// one to find its exact version, since names like `python3` don't tell us.
+pub fn find_system_interpreters() -> Vec<Interpreter> {
+    let re = Regex::new(r"^(python|pypy)(\d+(\.\d+)?)?(\.exe)?$").unwrap();
+
+    let mut dirs: Vec<PathBuf> = match env::var_os("PATH") {
+        Some(p) => env::split_paths(&p).collect(),
+        None => vec![],
+    };
+    dirs.append(&mut common_python_dirs());
+
+    // pyenv's shims just dispatch to its versions; we find those directly.
+    let pyenv_shims = pyenv_root().map(|r| r.join("shims"));
+
+    // On Windows, the `py` launcher knows about every registered installation; prefer those.
+    #[cfg(target_os = "windows")]
+    let mut candidates = find_py_launcher_paths();
+    #[cfg(not(target_os = "windows"))]
+    let mut candidates = vec![];
+    let mut found_store_alias = false;
+
+    for dir in dirs {
+        if Some(&dir) == pyenv_shims.as_ref() {
+            continue;
+        }
+        // Don't pick up a project environment that happens to be activated.
+        if dir.components().any(|c| {
+            let c = c.as_os_str().to_string_lossy();
+            c == "__pypackages__" || c == ".venv"
+        }) {
+            continue;
+        }
+        if let Ok(entries) = dir.read_dir() {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if !re.is_match(&name) {
+                    continue;
+                }
+                if is_windows_store_alias(&entry.path()) {
+                    found_store_alias = true;
+                    continue;
+                }
+                candidates.push(entry.path());
+            }
+        }
+    }
+
+    // `python3`, `python3.10` etc are often symlinks to the same binary.
+    let mut seen = vec![];
+    let mut result: Vec<Interpreter> = vec![];
+    for path in candidates {
+        let canon = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        if seen.contains(&canon) {
+            continue;
+        }
+        seen.push(canon);
+
+        if let Some(interpreter) = Interpreter::from_path(&path, InterpreterSource::System) {
+            if !result.iter().any(|i| {
+                i.version == interpreter.version && i.implementation == interpreter.implementation
+            }) {
+                result.push(interpreter);
+            }
+        }
+    }
+
+    if result.is_empty() && found_store_alias {
+        util::print_color(WINDOWS_STORE_ALIAS_MSG, Color::Yellow);
+    }
+
+    result.sort_by(|a, b| b.version.cmp(&a.version));
+    result
+}
+
+/// `PYENV_ROOT` if set, otherwise `~/.pyenv`.
+fn pyenv_root() -> Option<PathBuf> {
+    if let Some(root) = env::var_os("PYENV_ROOT") {
+        return Some(PathBuf::from(root));
+    }
+    directories::BaseDirs::new().map(|b| b.home_dir().join(".pyenv"))
+}
+
+/// Folders containing one pyenv-managed Python each, eg `~/.pyenv/versions/3.10.12`, oldest
+/// first. Ones not named for a version, eg `pypy3.10-7.3.12`, come before the rest.
+fn pyenv_version_dirs(root: &Path) -> Vec<PathBuf> {
+    let mut result = vec![];
+    // `pyenv-win` nests its versions one level deeper.
+    for versions_dir in &[
+        root.join("versions"),
+        root.join("pyenv-win").join("versions"),
+    ] {
+        if let Ok(entries) = versions_dir.read_dir() {
+            for entry in entries.flatten() {
+                if entry.path().is_dir() {
+                    result.push(entry.path());
+                }
+            }
+        }
+    }
+    // By version, not name, or 3.11.10 would come before 3.11.9.
+    result.sort_by_cached_key(|p| {
+        let name = p.file_name().unwrap_or_default().to_string_lossy();
+        (name.parse::<Version>().ok(), p.clone())
+    });
+    result
+}
+
+/// Find the interpreter in a pyenv version folder. Use the folder name for its version if it's
+/// a plain `x.y.z`, to avoid running every one; otherwise, ask the binary.
+fn pyenv_interpreter(version_dir: &Path) -> Option<Interpreter> {
+    let path = [
+        "bin/python3",
+        "bin/python",
+        "bin/pypy3",
+        "python.exe",
+        "pypy3.exe",
+    ]
+    .iter()
+    .map(|name| version_dir.join(name))
+    .find(|p| p.exists())?;
+
+    let dir_name = version_dir.file_name()?.to_string_lossy().to_string();
+    let re = Regex::new(r"^\d+\.\d+\.\d+$").unwrap();
+    if re.is_match(&dir_name) {
+        if let Ok(version) = dir_name.parse() {
+            return Some(Interpreter {
+                path,
+                version,
+                implementation: Implementation::CPython,
+                source: InterpreterSource::Pyenv,
+            });
+        }
+    }
+    Interpreter::from_path(&path, InterpreterSource::Pyenv)
+}
+
+/// Find Python interpreters managed by pyenv.
+pub fn find_pyenv_interpreters() -> Vec<Interpreter> {
+    let root = match pyenv_root() {
+        Some(r) => r,
+        None => return vec![],
+    };
+    let mut result: Vec<Interpreter> = pyenv_version_dirs(&root)
+        .iter()
+        .filter_map(|d| pyenv_interpreter(d))
+        .collect();
+    result.sort_by(|a, b| b.version.cmp(&a.version));
+    result
+}
+
+/// If the project has a pyenv `.python-version` file, find the first interpreter it names
+/// that's compatible with `py_version`.
+fn find_pyenv_preferred(
+    project_path: &Path,
+    cfg_v: &Version,
+    implementation: Implementation,
+) -> Option<Interpreter> {
+    let data = fs::read_to_string(project_path.join(".python-version")).ok()?;
+    let root = pyenv_root()?;
+
+    for name in data
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+    {
+        // Entries may be exact folder names, or prefixes like `3.10`.
+        let mut dirs: Vec<PathBuf> = pyenv_version_dirs(&root)
+            .into_iter()
+            .filter(|d| {
+                let dir_name = d.file_name().unwrap_or_default().to_string_lossy();
+                dir_name == name || dir_name.starts_with(&format!("{}.", name))
+            })
+            .collect();
+        dirs.reverse(); // Newest patch first.
+
+        for dir in dirs {
+            if let Some(i) = pyenv_interpreter(&dir) {
+                if i.matches(cfg_v, implementation) {
+                    return Some(i);
+                }
+            }
+        }
+    }
+    None
+}
+
+/// We record the interpreter an environment was created from, so we can rebuild it from the
+/// same one without searching again.
+const INTERPRETER_RECORD: &str = ".interpreter";
+
+fn read_interpreter_record(
+    vers_path: &Path,
+    cfg_v: &Version,
+    implementation: Implementation,
+) -> Option<Interpreter> {
+    Interpreter::from_path(&recorded_interpreter(vers_path)?, InterpreterSource::System)
+        .filter(|i| i.matches(cfg_v, implementation))
+}
+
+/// Where the interpreter is, in the builds we download.
+#[cfg(target_os = "windows")]
+const PYFLOW_PY_NAME: &str = "python.exe";
+#[cfg(not(target_os = "windows"))]
+const PYFLOW_PY_NAME: &str = "bin/python3";
+#[cfg(target_os = "windows")]
+const PYPY_PY_NAME: &str = "pypy3.exe";
+#[cfg(not(target_os = "windows"))]
+const PYPY_PY_NAME: &str = "bin/pypy3";
+
+// Find versions installed with this tool.
+fn find_installed_versions(pyflow_dir: &Path) -> Vec<Interpreter> {
     if !&pyflow_dir.exists() && fs::create_dir_all(pyflow_dir).is_err() {
         util::abort("Problem creating the Pyflow directory")
     }
@@ -357,146 +1013,288 @@ fn find_installed_versions(pyflow_dir: &Path) -> Vec<Version> {
             continue;
         }
 
-        if let Some(v) = commands::find_py_version(entry.path().join(py_name).to_str().unwrap()) {
-            result.push(v);
+        if let Some(i) = [PYFLOW_PY_NAME, PYPY_PY_NAME]
+            .iter()
+            .map(|name| entry.path().join(name))
+            .filter(|path| path.exists())
+            .find_map(|path| Interpreter::from_path(&path, InterpreterSource::Pyflow))
+        {
+            result.push(i);
         }
     }
     result
 }
 
-/// Create a new virtual environment, and install `wheel`.
+/// Parse `python_requires` from `pyproject.toml`, eg `>=3.8,<3.12`.
+pub fn parse_python_requires(python_requires: &Option<String>) -> Vec<Constraint> {
+    match python_requires {
+        Some(s) => Constraint::from_str_multiple(s).unwrap_or_else(|_| {
+            util::abort(&format!(
+                "Problem parsing `python_requires` in `pyproject.toml`: {}",
+                s
+            ))
+        }),
+        None => vec![],
+    }
+}
+
+/// A version satisfies every constraint in `python_requires`.
+pub fn satisfies(version: &Version, python_requires: &[Constraint]) -> bool {
+    python_requires.iter().all(|c| c.is_compatible(version))
+}
+
+/// Some patch of `version` satisfies `python_requires`, or `version` itself if it has a patch.
+pub fn could_satisfy(version: &Version, python_requires: &[Constraint]) -> bool {
+    if version.patch.is_some() {
+        return satisfies(version, python_requires);
+    }
+    // Checking both ends covers bounds like `<3.12`, and `>=3.8.1`.
+    [0, 999].iter().any(|p| {
+        satisfies(
+            &Version::new_opt(version.major, version.minor, Some(*p)),
+            python_requires,
+        )
+    })
+}
+
+/// Format `python_requires` constraints for messages.
+pub fn requires_str(python_requires: &[Constraint]) -> String {
+    python_requires
+        .iter()
+        .map(|c| c.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// A warning if an existing environment's Python doesn't satisfy `python_requires`.
+pub fn check_python_requires(vers_path: &Path, python_requires: &[Constraint]) -> Option<String> {
+    let version = venv_version(vers_path)?;
+    if satisfies(&version, python_requires) {
+        return None;
+    }
+    Some(format!(
+        "This project's environment uses Python {}, which doesn't satisfy \
+         `python_requires = \"{}\"`. Run `pyflow switch` to change it.",
+        version,
+        requires_str(python_requires)
+    ))
+}
+
+/// The newest interpreter of this implementation satisfying `python_requires`; used when
+/// `py_version` isn't set. `None` if `python_requires` isn't set either.
+pub fn newest_satisfying(
+    pyflow_dir: &Path,
+    implementation: Implementation,
+    python_requires: &[Constraint],
+) -> Option<Interpreter> {
+    if python_requires.is_empty() {
+        return None;
+    }
+    find_all_interpreters(pyflow_dir)
+        .into_iter()
+        .filter(|i| i.implementation == implementation && satisfies(&i.version, python_requires))
+        .max_by(|a, b| a.version.cmp(&b.version))
+}
+
+/// Every interpreter we can find: on the system, from pyenv, and ones we've downloaded.
+pub fn find_all_interpreters(pyflow_dir: &Path) -> Vec<Interpreter> {
+    find_system_interpreters()
+        .into_iter()
+        .chain(find_pyenv_interpreters())
+        .chain(find_installed_versions(pyflow_dir))
+        .collect()
+}
+
+/// The interpreter an environment was created from, if recorded. It's beside the venv in
+/// `__pypackages__`, and inside it with `env-mode = "venv"`.
+pub fn recorded_interpreter(vers_path: &Path) -> Option<PathBuf> {
+    let data = fs::read_to_string(vers_path.join(INTERPRETER_RECORD))
+        .or_else(|_| fs::read_to_string(vers_path.join(".venv").join(INTERPRETER_RECORD)))
+        .ok()?;
+    Some(PathBuf::from(data.trim()))
+}
+
+/// Whether `root` has a `.venv` we made with `env-mode = "venv"`.
+pub fn is_project_venv(root: &Path) -> bool {
+    root.join(".venv").join(INTERPRETER_RECORD).is_file()
+}
+
+/// Read the exact version of an environment from its `pyvenv.cfg`.
+pub fn venv_version(vers_path: &Path) -> Option<Version> {
+    venv_cfg_version(&vers_path.join(".venv"))
+}
+
+/// Read the exact version of a virtual environment at any path.
+pub fn venv_cfg_version(venv_path: &Path) -> Option<Version> {
+    let data = fs::read_to_string(venv_path.join("pyvenv.cfg")).ok()?;
+    data.lines().find_map(|l| {
+        let mut parts = l.splitn(2, '=');
+        let key = parts.next()?.trim();
+        if key != "version" && key != "version_info" {
+            return None;
+        }
+        // `virtualenv` writes eg `3.11.8.final.0`.
+        let v: Vec<&str> = parts.next()?.trim().split('.').take(3).collect();
+        v.join(".").parse().ok()
+    })
+}
+
+/// An existing environment was created with a different implementation, or a different patch
+/// version than `py_version` pins, so must be rebuilt.
+pub fn venv_is_stale(vers_path: &Path, cfg_v: &Version, implementation: Implementation) -> bool {
+    if venv_implementation(&util::find_bin_path(vers_path)) != implementation {
+        return true;
+    }
+    if cfg_v.patch.is_some() {
+        if let Some(v) = venv_version(vers_path) {
+            return !version_matches(&v, cfg_v);
+        }
+    }
+    false
+}
+
+/// Create a new virtual environment, and install `wheel`. With `EnvMode::Pypackages`, it's in a
+/// version folder in `pypackages_dir`; with `EnvMode::Venv`, `pypackages_dir` is the project
+/// root, and it's `.venv` there.
+#[allow(clippy::too_many_arguments)]
 pub fn create_venv(
     cfg_v: &Version,
     pypackages_dir: &Path,
     pyflow_dir: &Path,
     dep_cache_path: &Path,
+    python_override: Option<&Path>,
+    implementation: Implementation,
+    python_requires: &[Constraint],
+    mode: EnvMode,
 ) -> Version {
-    let os;
-    let python_name;
-    #[allow(unused_mut)]
-    let mut py_name;
     #[cfg(target_os = "windows")]
-    {
-        py_name = "python".to_string();
-        os = Os::Windows;
-        python_name = "python.exe";
+    let python_name = "python.exe";
+    #[cfg(not(target_os = "windows"))]
+    let python_name = "python";
+
+    // Eg `py_version = "3.12"` with `python_requires = "<3.12"`; fail before downloading.
+    if !could_satisfy(cfg_v, python_requires) {
+        util::abort(&format!(
+            "`py_version = \"{}\"` doesn't satisfy `python_requires = \"{}\"` in `pyproject.toml`",
+            cfg_v,
+            requires_str(python_requires)
+        ))
     }
-    #[cfg(target_os = "linux")]
+
+    let interpreter = if let Some(path) = python_override {
+        // A specific binary, from `python` in `pyproject.toml`.
+        if is_windows_store_alias(path) {
+            util::abort(WINDOWS_STORE_ALIAS_MSG)
+        }
+        let i = Interpreter::from_path(path, InterpreterSource::System).unwrap_or_else(|| {
+            util::abort(&format!(
+                "Can't run the Python specified by `python` in `pyproject.toml`: {:?}",
+                path
+            ))
+        });
+        if !i.matches(cfg_v, implementation) {
+            util::abort(&format!(
+                "The Python specified by `python` in `pyproject.toml` is {} {}, \
+                 but `py_version` is {} {}",
+                i.implementation,
+                i.version,
+                implementation,
+                cfg_v.to_string_med()
+            ));
+        }
+        Some(i)
+    } else if let Some(i) = read_interpreter_record(
+        &match mode {
+            EnvMode::Pypackages => pypackages_dir.join(cfg_v.to_string_med()),
+            EnvMode::Venv => pypackages_dir.to_owned(),
+        },
+        cfg_v,
+        implementation,
+    )
+    .filter(|i| satisfies(&i.version, python_requires))
     {
-        py_name = "bin/python3".to_string();
-        os = Os::Ubuntu;
-        python_name = "python";
+        Some(i)
+    } else if let Some(i) = match mode {
+        EnvMode::Pypackages => pypackages_dir.parent(),
+        EnvMode::Venv => Some(pypackages_dir),
     }
-    #[cfg(target_os = "macos")]
+    .and_then(|project_path| find_pyenv_preferred(project_path, cfg_v, implementation))
+    .filter(|i| satisfies(&i.version, python_requires))
     {
-        py_name = "bin/python3".to_string();
-        os = Os::Mac;
-        python_name = "python";
-    }
-
-    let mut alias = None;
-    let mut alias_path = None;
-    let mut py_ver = None;
-
-    // If we find both a system alias, and internal version installed, go with the internal.
-    // One's this tool installed
-    let installed_versions = find_installed_versions(pyflow_dir);
-    for iv in &installed_versions {
-        if iv.major == cfg_v.major && iv.minor == cfg_v.minor {
-            let folder_name = format!("python-{}", iv);
-            alias_path = Some(pyflow_dir.join(folder_name).join(&py_name));
-            py_ver = Some(iv.clone());
-            break;
-        }
-    }
+        Some(i)
+    } else {
+        // Prefer system interpreters, then ones we've already downloaded.
+        let compatible: Vec<Interpreter> = find_all_interpreters(pyflow_dir)
+            .into_iter()
+            .filter(|i| i.matches(cfg_v, implementation) && satisfies(&i.version, python_requires))
+            .collect();
 
-    // todo perhaps move alias finding back into create_venv, or make a
-    // todo create_venv_if_doesnt_exist fn.
-    // Only search for a system Python if we don't have an internal one.
-    // TODO: Why did we choose to prioritize portable over system? Perhaps do the
-    // todo other way around.
-    if py_ver.is_none() {
-        let aliases = find_py_aliases(cfg_v);
-        match aliases.len() {
-            0 => (),
-            1 => {
-                let r = aliases[0].clone();
-                alias = Some(r.0);
-                py_ver = Some(r.1);
-            }
+        match compatible.len() {
+            0 => None,
+            1 => Some(compatible[0].clone()),
             _ => {
-                //                let r = prompt_alias(&aliases);
-                let r = util::prompts::list(
-                    "Found multiple compatible Python versions. Please enter the number associated with the one you'd like to use:",
-                    "Python alias",
-                    &aliases,
-                    true,
-                );
-                alias = Some(r.0);
-                py_ver = Some(r.1);
-            }
-        };
-    }
-
-    if py_ver.is_none() {
-        // Download and install the appropriate Python binary, if we can't find either a
-        // custom install, or on the Path.
-        download(pyflow_dir, cfg_v);
-        let py_ver2: PyVers = (cfg_v.clone(), os).into();
-        py_ver = Some(py_ver2.to_vers());
-
-        let folder_name = format!("python-{}", py_ver2.to_string());
-
-        // We appear to have symlink issues on some builds, where `python3` won't work, but
-        // `python3.7` (etc) will. Note that this is no longer applicable once the venv is built,
-        // and we're using its `python`.
-        #[cfg(target_os = "linux")]
-        {
-            match py_ver.clone().unwrap().minor.unwrap_or(0) {
-                12 => py_name += ".12",
-                11 => py_name += ".11",
-                10 => py_name += ".10",
-                9 => py_name += ".9",
-                8 => py_name += ".8",
-                7 => py_name += ".7",
-                6 => py_name += ".6",
-                5 => py_name += ".5",
-                4 => py_name += ".4",
-                _ => panic!("Invalid python minor version"),
+                let items: Vec<(String, Interpreter)> =
+                    compatible.into_iter().map(|i| (i.to_string(), i)).collect();
+                // Non-interactively, the newest.
+                let newest = items
+                    .iter()
+                    .enumerate()
+                    .max_by(|(_, (_, a)), (_, (_, b))| a.version.cmp(&b.version))
+                    .map_or(1, |(i, _)| i + 1);
+                Some(
+                    util::prompts::list(
+                        "Found multiple compatible Python versions. Please enter the number associated with the one you'd like to use:",
+                        "Python interpreter",
+                        &items,
+                        false,
+                        Fallback::Answer(newest.to_string()),
+                    )
+                    .1,
+                )
             }
         }
+    };
 
-        alias_path = Some(pyflow_dir.join(folder_name).join(py_name));
+    // Download and install the appropriate Python binary, if we can't find a compatible one
+    // on this system.
+    let interpreter = interpreter.unwrap_or_else(|| download(pyflow_dir, cfg_v, implementation));
+    // Covers `python` overrides, and downloads, which we can't filter in advance.
+    if !satisfies(&interpreter.version, python_requires) {
+        util::abort(&format!(
+            "{} doesn't satisfy `python_requires = \"{}\"` in `pyproject.toml`",
+            interpreter.name(),
+            requires_str(python_requires)
+        ))
     }
+    let (alias_path, py_ver) = (interpreter.path, interpreter.version);
 
-    let py_ver = py_ver.expect("missing Python version");
-
-    let vers_path = pypackages_dir.join(py_ver.to_string_med());
-
-    let lib_path = vers_path.join("lib");
-
-    if !lib_path.exists() {
-        fs::create_dir_all(&lib_path).expect("Problem creating __pypackages__ directory");
-    }
+    let (vers_path, record_path) = match mode {
+        EnvMode::Pypackages => {
+            let vers_path = pypackages_dir.join(py_ver.to_string_med());
+            let lib_path = vers_path.join("lib");
+            if !lib_path.exists() {
+                fs::create_dir_all(&lib_path).expect("Problem creating __pypackages__ directory");
+            }
+            let record_path = vers_path.join(INTERPRETER_RECORD);
+            (vers_path, record_path)
+        }
+        EnvMode::Venv => (
+            pypackages_dir.to_owned(),
+            pypackages_dir.join(".venv").join(INTERPRETER_RECORD),
+        ),
+    };
 
     #[cfg(target_os = "windows")]
-    println!("Setting up Python...");
+    log::info!("Setting up Python...");
     #[cfg(target_os = "linux")]
-    println!("🐍 Setting up Python..."); // Beware! Snake may be invisible.
+    log::info!("🐍 Setting up Python..."); // Beware! Snake may be invisible.
     #[cfg(target_os = "macos")]
-    println!("🐍 Setting up Python...");
+    log::info!("🐍 Setting up Python...");
 
-    // For an alias on the PATH
-    if let Some(alias) = alias {
-        if commands::create_venv(&alias, &lib_path, ".venv").is_err() {
-            util::abort("Problem creating virtual environment");
-        }
-    // For a Python one we've installed.
-    } else if let Some(alias_path) = alias_path {
-        if commands::create_venv2(&alias_path, &lib_path, ".venv").is_err() {
-            util::abort("Problem creating virtual environment");
-        }
+    if commands::create_venv2(&alias_path, &vers_path, ".venv").is_err() {
+        util::abort("Problem creating virtual environment");
     }
+    fs::write(record_path, alias_path.to_string_lossy().as_bytes()).unwrap_or(());
 
     let bin_path = util::find_bin_path(&vers_path);
 
@@ -511,12 +1309,19 @@ pub fn create_venv(
         "lib"
     };
 
+    // PyPy names its folder `pypy3.x` instead of `python3.x`.
+    #[allow(unused_variables)]
+    let lib_name = match implementation {
+        Implementation::CPython => "python",
+        Implementation::PyPy => "pypy",
+    };
+
     #[cfg(target_os = "windows")]
     let venv_lib_path = "Lib";
     #[cfg(target_os = "linux")]
-    let venv_lib_path = PathBuf::from(lib).join(&format!("python{}", py_ver.to_string_med()));
+    let venv_lib_path = PathBuf::from(lib).join(format!("{}{}", lib_name, py_ver.to_string_med()));
     #[cfg(target_os = "macos")]
-    let venv_lib_path = PathBuf::from(lib).join(format!("python{}", py_ver.to_string_med()));
+    let venv_lib_path = PathBuf::from(lib).join(format!("{}{}", lib_name, py_ver.to_string_med()));
 
     let paths = util::Paths {
         bin: bin_path.clone(),
@@ -526,11 +1331,59 @@ pub fn create_venv(
             .join("site-packages"),
         entry_pt: bin_path,
         cache: dep_cache_path.to_owned(),
+        mode: EnvMode::Venv,
     };
 
-    // We need `wheel` installed to build wheels from source.
-    // We use `twine` to upload packages to pypi.
     // Note: This installs to the venv's site-packages, not __pypackages__/3.x/lib.
+    install_wheel(&paths);
+
+    py_ver
+}
+
+/// Create a plain virtual environment at `path`, eg for a tox or nox session, from an
+/// interpreter matching `version`, downloading one if there isn't one. Returns its version.
+pub fn create_venv_at(
+    path: &Path,
+    pyflow_dir: &Path,
+    dep_cache_path: &Path,
+    version: &Version,
+    implementation: Implementation,
+) -> Version {
+    let interpreter = find_all_interpreters(pyflow_dir)
+        .into_iter()
+        .filter(|i| i.matches(version, implementation))
+        .max_by(|a, b| a.version.cmp(&b.version))
+        .unwrap_or_else(|| download(pyflow_dir, version, implementation));
+
+    let (dir, name) = match (path.parent(), path.file_name().and_then(|n| n.to_str())) {
+        (Some(dir), Some(name)) => (dir, name),
+        _ => util::abort(&format!("Can't create an environment at {:?}", path)),
+    };
+    if fs::create_dir_all(dir).is_err() {
+        util::abort(&format!("Problem creating {:?}", dir));
+    }
+    log::info!("Creating {:?} with {}", path, interpreter.name());
+    if commands::create_venv2(&interpreter.path, dir, name).is_err() {
+        util::abort("Problem creating virtual environment");
+    }
+
+    let bin_path = util::venv_bin_path(path);
+    let paths = util::Paths {
+        lib: util::venv_site_packages(path)
+            .unwrap_or_else(|| util::abort("Can't find the new environment's `site-packages`")),
+        bin: bin_path.clone(),
+        entry_pt: bin_path,
+        cache: dep_cache_path.to_owned(),
+        mode: EnvMode::Venv,
+    };
+    install_wheel(&paths);
+
+    interpreter.version
+}
+
+/// We need `wheel` installed to build wheels from source.
+/// We use `twine` to upload packages to pypi.
+fn install_wheel(paths: &util::Paths) {
     let wheel_url = "https://files.pythonhosted.org/packages/00/83/b4a77d044e78ad1a45610eb88f745be2fd2c6d658f9798a15e384b7d57c9/wheel-0.33.6-py2.py3-none-any.whl";
 
     install::download_and_install_package(
@@ -539,11 +1392,120 @@ pub fn create_venv(
         wheel_url,
         "wheel-0.33.6-py2.py3-none-any.whl",
         "f4da1763d3becf2e2cd92a14a7c920f0f00eca30fdde9ea992c836685b9faf28",
-        &paths,
+        paths,
         install::PackageType::Wheel,
         &None,
     )
-    .expect("Problem installing `wheel`");
+    .unwrap_or_else(|e| util::abort(&format!("Problem installing `wheel`: {}", e)));
+}
 
-    py_ver
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    use crate::util::temp_dir::TempDir;
+
+    #[rstest(
+        name,
+        triple,
+        expected,
+        case(
+            "cpython-3.12.3+20240415-x86_64-unknown-linux-gnu-install_only.tar.gz",
+            "x86_64-unknown-linux-gnu",
+            Some(Version::new(3, 12, 3))
+        ),
+        case(
+            "cpython-3.10.13+20240107-x86_64-pc-windows-msvc-shared-install_only.tar.gz",
+            "x86_64-pc-windows-msvc",
+            Some(Version::new(3, 10, 13))
+        ),
+        case(
+            "cpython-3.13.0+20241016-aarch64-apple-darwin-install_only.tar.gz",
+            "aarch64-apple-darwin",
+            Some(Version::new(3, 13, 0))
+        ),
+        case(
+            "cpython-3.12.3+20240415-aarch64-unknown-linux-gnu-install_only.tar.gz",
+            "x86_64-unknown-linux-gnu",
+            None
+        ),
+        case(
+            "cpython-3.12.3+20240415-x86_64-unknown-linux-gnu-pgo+lto-full.tar.zst",
+            "x86_64-unknown-linux-gnu",
+            None
+        ),
+        case(
+            "cpython-3.12.3+20240415-x86_64-unknown-linux-gnu-install_only.tar.gz.sha256",
+            "x86_64-unknown-linux-gnu",
+            None
+        )
+    )]
+    fn standalone_asset_names(name: &str, triple: &str, expected: Option<Version>) {
+        assert_eq!(parse_asset_name(name, triple), expected);
+    }
+
+    #[rstest(
+        version,
+        requires,
+        expected,
+        case("3.11", ">=3.8,<3.12", true),
+        case("3.12", ">=3.8,<3.12", false),
+        case("3.8", ">=3.8.1", true),
+        case("3.8.0", ">=3.8.1", false),
+        case("3.7", "", true)
+    )]
+    fn python_requires_could_satisfy(version: &str, requires: &str, expected: bool) {
+        let requires = parse_python_requires(&Some(requires.to_string()));
+        assert_eq!(
+            could_satisfy(&version.parse().unwrap(), &requires),
+            expected
+        );
+    }
+
+    #[rstest(
+        name,
+        expected,
+        case(
+            "cpython-3.12.3+20240415-x86_64-unknown-linux-gnu-install_only.tar.gz",
+            Some(Version::new(3, 12, 3))
+        ),
+        case(
+            "cpython-3.10.13+20240107-x86_64-pc-windows-msvc-shared-install_only.tar.gz",
+            Some(Version::new(3, 10, 13))
+        ),
+        case("python-3.12.3.tar.gz", None),
+        case(
+            "cpython-3.12.3+20240415-x86_64-unknown-linux-gnu-pgo+lto-full.tar.zst",
+            None
+        )
+    )]
+    fn local_archive_names(name: &str, expected: Option<Version>) {
+        assert_eq!(parse_archive_name(name), expected);
+    }
+
+    #[test]
+    fn pyenv_dirs_by_version() {
+        let root = TempDir::new("pyenv");
+        for name in &["3.11.10", "3.11.9", "3.9.18", "pypy3.10-7.3.12"] {
+            fs::create_dir_all(root.join("versions").join(name)).unwrap();
+        }
+        let names: Vec<_> = pyenv_version_dirs(&root)
+            .iter()
+            .map(|d| d.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, ["pypy3.10-7.3.12", "3.9.18", "3.11.9", "3.11.10"]);
+    }
+
+    #[test]
+    fn store_stubs() {
+        let installed = vec![
+            "python3.11.exe".to_owned(),
+            "PythonSoftwareFoundation.Python.3.11_qbz5n2kfra8p0".to_owned(),
+        ];
+        assert!(is_store_stub("python.exe", &[]));
+        assert!(is_store_stub("Python3.exe", &["winget.exe".to_owned()]));
+        assert!(!is_store_stub("python.exe", &installed));
+        assert!(!is_store_stub("python3.11.exe", &installed));
+    }
 }
@@ -1,10 +1,17 @@
-use std::{env, path::PathBuf};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
 
 use termcolor::Color;
 
-use crate::util;
+use crate::{
+    py_versions,
+    util::{self, paths},
+    CliConfig,
+};
 
-use super::{Config, PresentConfig, CFG_FILENAME, LOCK_FILENAME};
+use super::{workspace::Workspace, Config, EnvMode, PresentConfig, CFG_FILENAME, LOCK_FILENAME};
 
 const NOT_FOUND_ERROR_MESSAGE: &str = indoc::indoc! {r#"
 To get started, run `pyflow new projname` to create a project folder, or
@@ -12,45 +19,258 @@ To get started, run `pyflow new projname` to create a project folder, or
 `pyflow help`.
 "#};
 
-pub fn get_config() -> Option<PresentConfig> {
-    let mut config_path = PathBuf::from(CFG_FILENAME);
-    if !&config_path.exists() {
-        // Try looking recursively in parent directories for a config file.
-        let recursion_limit = 8; // How my levels to look up
-        let mut current_level = env::current_dir().expect("Can't access current directory");
-        for _ in 0..recursion_limit {
-            if let Some(parent) = current_level.parent() {
-                let parent_cfg_path = parent.join(CFG_FILENAME);
-                if parent_cfg_path.exists() {
-                    config_path = parent_cfg_path;
-                    break;
-                }
-                current_level = parent.to_owned();
-            }
-        }
+/// Find `pyproject.toml` in the current directory, or one of its parents.
+pub fn find_config_path() -> Option<PathBuf> {
+    // Absolute, so paths based on it still work if `pyflow run` changes directory.
+    let current_dir = env::current_dir().expect("Can't access current directory");
+    let config_path = current_dir.join(CFG_FILENAME);
+    if config_path.exists() {
+        return Some(config_path);
+    }
+
+    // Try looking recursively in parent directories for a config file.
+    let recursion_limit = 8; // How my levels to look up
+    current_dir
+        .ancestors()
+        .skip(1)
+        .take(recursion_limit)
+        .map(|parent| parent.join(CFG_FILENAME))
+        .find(|p| p.exists())
+}
 
-        if !&config_path.exists() {
+pub fn get_config() -> Option<PresentConfig> {
+    let config_path = match find_config_path() {
+        Some(p) => p,
+        None => {
             // we still can't find it after searching parents.
             util::print_color(NOT_FOUND_ERROR_MESSAGE, Color::Cyan); // Dark Cyan
             return None;
         }
-    }
+    };
 
-    // Base pypackages_path and lock_path on the `pyproject.toml` folder.
     let project_path = config_path
         .parent()
         .expect("Can't find project path via parent")
         .to_path_buf();
-    let pypackages_path = project_path.join("__pypackages__");
-    let lock_path = project_path.join(LOCK_FILENAME);
 
     let mut config = Config::from_file(&config_path).unwrap_or_default();
+    let workspace = Workspace::find(&project_path);
+    // Base pypackages_path and lock_path on the `pyproject.toml` folder, or in a workspace, its
+    // root's; members share them.
+    let shared_path = workspace
+        .as_ref()
+        .map_or(project_path.clone(), |ws| ws.root.clone());
+    let lock_path = shared_path.join(LOCK_FILENAME);
+
+    if let Some(ws) = &workspace {
+        ws.link_members(&project_path, &mut config.reqs);
+        ws.link_members(&project_path, &mut config.dev_reqs);
+        // Members share an environment, so the root's Python version and `env-mode` apply.
+        if ws.root != project_path {
+            if let Some(root_config) = Config::from_file(&ws.root.join(CFG_FILENAME)) {
+                if let Some(v) = root_config.py_version {
+                    config.py_version = Some(v);
+                }
+                config.env_mode = root_config.env_mode;
+            }
+        }
+    }
     config.populate_path_subreqs();
+    let pypackages_path = pypackages_path(&shared_path, &config);
+    warn_moved_pypackages(&shared_path, &pypackages_path);
+    let constraint_files: Vec<PathBuf> = config
+        .constraint_files
+        .iter()
+        .map(|f| project_path.join(f))
+        .collect();
+    let constraints = super::Constraints {
+        pins: super::read_constraints(&constraint_files),
+        install_files: vec![],
+    };
     Some(PresentConfig {
         config,
         config_path,
         project_path,
         pypackages_path,
         lock_path,
+        workspace,
+        constraints,
     })
 }
+
+/// Where the project rooted at `shared_path` keeps its environments: `__pypackages__`, or the
+/// `packages-dir` setting, relative to the root, eg `../.envs/{project}`. `{project}` is the root
+/// folder's name, and `{py}` the project's Python version, eg `3.11`.
+pub fn pypackages_path(shared_path: &Path, config: &Config) -> PathBuf {
+    let template = match &CliConfig::current().settings.packages_dir {
+        Some(t) => t.clone(),
+        None => return shared_path.join("__pypackages__"),
+    };
+    let project = shared_path
+        .file_name()
+        .map_or(String::new(), |n| n.to_string_lossy().to_string());
+    let mut dir = template.replace("{project}", &project);
+    if dir.contains("{py}") {
+        let py = config.py_version.as_ref().unwrap_or_else(|| {
+            util::abort(
+                "`packages-dir` uses `{py}`, but this project has no `py_version`; set one \
+                 with `pyflow switch`",
+            )
+        });
+        dir = dir.replace("{py}", &py.to_string_med());
+    }
+    shared_path.join(dir)
+}
+
+/// An environment left where this project's environments were kept before, in
+/// `__pypackages__` or an earlier `packages-dir`, is no longer used; say so, rather than quietly
+/// building a second one. Earlier `packages-dir`s are remembered until they're emptied.
+fn warn_moved_pypackages(shared_path: &Path, pypackages_path: &Path) {
+    let default = shared_path.join("__pypackages__");
+    let record_path = paths::packages_dirs_path(&paths::pyflow_path());
+    let record = fs::read_to_string(&record_path).unwrap_or_default();
+    let key = shared_path.to_string_lossy();
+    let (ours, mut lines): (Vec<&str>, Vec<&str>) = record
+        .lines()
+        .partition(|l| l.split('\t').next() == Some(&key));
+
+    let mut earlier = vec![default.clone()];
+    earlier.extend(
+        ours.iter()
+            .filter_map(|l| l.split_once('\t'))
+            .map(|(_, dir)| PathBuf::from(dir)),
+    );
+    earlier.dedup();
+    let mut kept = vec![];
+    for dir in earlier {
+        if dir == pypackages_path || util::find_venvs(&dir).is_empty() {
+            continue;
+        }
+        log::warn!(
+            "This project's environments are now kept in {:?}, but {:?}, where they were kept \
+             before `packages-dir` changed, has one too, which pyflow no longer uses. Move its \
+             version folders there, or delete it and run `pyflow install`",
+            pypackages_path,
+            dir
+        );
+        if dir != default {
+            kept.push(dir);
+        }
+    }
+    if pypackages_path != default {
+        kept.push(pypackages_path.to_owned());
+    }
+
+    let kept_lines: Vec<String> = kept
+        .iter()
+        .map(|dir| format!("{}\t{}", key, dir.display()))
+        .collect();
+    if kept_lines
+        .iter()
+        .map(String::as_str)
+        .eq(ours.iter().copied())
+    {
+        return;
+    }
+    lines.extend(kept_lines.iter().map(String::as_str));
+    let mut contents = lines.join("\n");
+    if !contents.is_empty() {
+        contents.push('\n');
+    }
+    let saved = record_path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&record_path, contents));
+    if let Err(e) = saved {
+        log::warn!("Problem saving {:?}: {}", record_path, e);
+    }
+}
+
+/// A project's environment is in `__pypackages__`, or in a `.venv`, never both, so changing
+/// `env-mode` under an existing environment stops here instead of quietly building a second one.
+pub fn check_env_mode(pcfg: &PresentConfig) {
+    if let Some(e) = env_mode_conflict(pcfg) {
+        util::abort(&e)
+    }
+}
+
+/// Why the project's environment doesn't match its `env-mode`, if it doesn't. A `.venv` pyflow
+/// made records the interpreter it was made from, so we can tell it from one made some other way.
+fn env_mode_conflict(pcfg: &PresentConfig) -> Option<String> {
+    let root = pcfg.lock_path.parent().unwrap_or(&pcfg.project_path);
+    match pcfg.config.env_mode {
+        EnvMode::Venv if !util::find_venvs(&pcfg.pypackages_path).is_empty() => Some(format!(
+            "`env-mode = \"venv\"` is set, but this project's environments are in {:?}. \
+             Remove them with `pyflow clean --pypackages`, or remove `env-mode`",
+            pcfg.pypackages_path
+        )),
+        EnvMode::Pypackages if py_versions::is_project_venv(root) => Some(format!(
+            "This project's environment is {:?}, made with `env-mode = \"venv\"`, which is no \
+             longer set. Set it again, or delete that folder to use `__pypackages__`",
+            root.join(".venv")
+        )),
+        _ => None,
+    }
+}
+
+/// Change to the workspace member called `name`, so it's the project we find. Aborts if we're
+/// not in a workspace, or it has no such member.
+pub fn enter_member(name: &str) {
+    let current_dir = env::current_dir().expect("Can't access current directory");
+    let ws = Workspace::find(&current_dir)
+        .unwrap_or_else(|| util::abort("`-p` only works inside a workspace"));
+    let member = ws.member(name).unwrap_or_else(|| {
+        let names: Vec<&str> = ws.members.iter().map(|m| m.name.as_str()).collect();
+        util::abort(&format!(
+            "There's no workspace member called `{}`. Members are: {}",
+            name,
+            names.join(", ")
+        ))
+    });
+    if env::set_current_dir(&member.path).is_err() {
+        util::abort(&format!("Can't change to {:?}", member.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::util::temp_dir::TempDir;
+
+    #[test]
+    fn env_mode_conflicts() {
+        let root = TempDir::new("env-mode");
+        let mut pcfg = PresentConfig {
+            project_path: root.to_path_buf(),
+            config_path: root.join(CFG_FILENAME),
+            pypackages_path: root.join("__pypackages__"),
+            lock_path: root.join(LOCK_FILENAME),
+            ..Default::default()
+        };
+        assert!(env_mode_conflict(&pcfg).is_none());
+        pcfg.config.env_mode = EnvMode::Venv;
+        assert!(env_mode_conflict(&pcfg).is_none());
+
+        // An environment in `__pypackages__`, then `env-mode = "venv"` set.
+        let python = pcfg.pypackages_path.join("3.11/.venv/bin/python");
+        fs::create_dir_all(python.parent().unwrap()).unwrap();
+        fs::write(&python, "").unwrap();
+        assert!(env_mode_conflict(&pcfg).is_some());
+        pcfg.config.env_mode = EnvMode::Pypackages;
+        assert!(env_mode_conflict(&pcfg).is_none());
+        fs::remove_dir_all(&pcfg.pypackages_path).unwrap();
+
+        // A `.venv` pyflow made, then `env-mode` removed. One made some other way is left alone.
+        fs::create_dir_all(root.join(".venv")).unwrap();
+        assert!(env_mode_conflict(&pcfg).is_none());
+        fs::write(root.join(".venv/.interpreter"), "").unwrap();
+        let conflict = env_mode_conflict(&pcfg);
+        pcfg.config.env_mode = EnvMode::Venv;
+        let venv_conflict = env_mode_conflict(&pcfg);
+
+        assert!(conflict.unwrap().contains(".venv"));
+        assert!(venv_conflict.is_none());
+    }
+}
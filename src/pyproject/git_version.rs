@@ -0,0 +1,120 @@
+//! The project's version from git tags, for `version = { from = "git" }`: the latest tag reachable
+//! from `HEAD`, eg `v1.4.2`, as a dev release if `HEAD` is past it, eg `1.4.2.dev3+g1a2b3c4`,
+//! with `dirty` in the local label if there are uncommitted changes.
+
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    commands,
+    dep_types::{Version, VersionModifier},
+    util::abort,
+};
+
+/// The only place a version can come from, other than `pyproject.toml`.
+pub const SOURCE: &str = "git";
+
+/// Where to find a version from git; `pyproject::Config::resolve_version` finds it.
+#[derive(Clone, Debug, Default)]
+pub struct GitVersion {
+    pub project_path: PathBuf,
+    /// `fallback-version`, eg when building from an sdist, outside a git checkout.
+    pub fallback: Option<String>,
+}
+
+/// The version `git describe` gives for the project. Outside a git checkout, or without a
+/// version tag, `fallback` is used if there is one, eg when building from an sdist; otherwise
+/// aborts.
+pub fn from_git(project_path: &Path, fallback: Option<&str>) -> Version {
+    let described = commands::git_describe(project_path)
+        .map_err(|e| e.to_string())
+        .and_then(|d| parse_describe(&d));
+    match (described, fallback) {
+        (Ok(v), _) => v,
+        (Err(e), Some(f)) => {
+            log::info!("Using `fallback-version` {}, since {}", f, e);
+            Version::from_str(f).unwrap_or_else(|_| {
+                abort(&format!(
+                    "`fallback-version` in `pyproject.toml` isn't a valid version: {}",
+                    f
+                ))
+            })
+        }
+        (Err(e), None) => abort(&format!(
+            "Can't find the project's version from git: {}\nTag a release, eg `git tag v0.1.0`, \
+             or set `fallback-version` in `[tool.pyflow]`",
+            e
+        )),
+    }
+}
+
+/// A version from `git describe --tags --long --dirty` output, eg `v1.4.2-3-g1a2b3c4-dirty`.
+/// A leading `v` is dropped from the tag.
+fn parse_describe(described: &str) -> Result<Version, String> {
+    let unexpected = || format!("unexpected `git describe` output: {}", described);
+    let (rest, dirty) = match described.trim().strip_suffix("-dirty") {
+        Some(r) => (r, true),
+        None => (described.trim(), false),
+    };
+    // From the right, since tags can have dashes.
+    let mut parts = rest.rsplitn(3, '-');
+    let (hash, distance, tag) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(h), Some(n), Some(t)) if h.starts_with('g') => {
+            (h, n.parse::<u32>().map_err(|_| unexpected())?, t)
+        }
+        _ => return Err(unexpected()),
+    };
+
+    let number = tag.strip_prefix(['v', 'V']).unwrap_or(tag);
+    let mut version = Version::from_str(number)
+        .map_err(|_| format!("the tag `{}` isn't a version, like `v1.4.2`", tag))?;
+    if distance > 0 {
+        if version.modifier.is_some() {
+            return Err(format!(
+                "`HEAD` is {} commits past the pre-release tag `{}`, and a pre-release can't \
+                 also be a dev release; tag `HEAD`",
+                distance, tag
+            ));
+        }
+        // With its dot, in PEP 440's normal form; `Version::from_str` keeps other modifiers as
+        // they're written.
+        version.modifier = Some((VersionModifier::Other(".dev".into()), distance));
+        version.local = Some(hash.to_owned());
+    }
+    if dirty {
+        version.local = Some(match version.local {
+            Some(l) => format!("{}.dirty", l),
+            None => "dirty".into(),
+        });
+    }
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        described,
+        expected,
+        case("v1.4.2-0-g1a2b3c4", "1.4.2"),
+        case("1.4.2-0-g1a2b3c4", "1.4.2"),
+        case("v1.4.2-3-g1a2b3c4", "1.4.2.dev3+g1a2b3c4"),
+        case("v1.4.2-0-g1a2b3c4-dirty", "1.4.2+dirty"),
+        case("v1.4.2-3-g1a2b3c4-dirty", "1.4.2.dev3+g1a2b3c4.dirty"),
+        case("v2.0.0rc1-0-g1a2b3c4", "2.0.0rc1"),
+        case("release-v1-2-0-g1a2b3c4", "error"),
+        case("v2.0.0rc1-2-g1a2b3c4", "error"),
+        case("v1.4.2", "error")
+    )]
+    fn describes(described: &str, expected: &str) {
+        let result = parse_describe(described).map(|v| v.to_string());
+        match expected {
+            "error" => assert!(result.is_err()),
+            _ => assert_eq!(result.unwrap(), expected),
+        }
+    }
+}
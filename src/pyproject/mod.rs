@@ -1,4 +1,7 @@
 pub mod current;
+pub mod git_version;
+pub mod setup_py;
+pub mod workspace;
 
 use std::{
     collections::HashMap,
@@ -7,17 +10,21 @@ use std::{
     str::FromStr,
 };
 
-use regex::Regex;
 use serde::Deserialize;
 
 use crate::{
-    dep_types::{Constraint, Req, Version},
+    dep_types::{Constraint, GitRef, Req, ReqType, Version},
     files,
+    py_versions::{self, Implementation},
     util::{self, abort},
 };
 
 pub const CFG_FILENAME: &str = "pyproject.toml";
 pub const LOCK_FILENAME: &str = "pyflow.lock";
+/// The lock's `[metadata]` key for `Config::deps_hash`, from when it was written.
+pub const LOCK_HASH_KEY: &str = "pyproject-hash";
+/// The lock's `[metadata]` key for the `install -c` files it was resolved with, one per line.
+pub const LOCK_CONSTRAINTS_KEY: &str = "install-constraints";
 
 #[derive(Clone, Debug, Default)]
 pub struct PresentConfig {
@@ -26,6 +33,158 @@ pub struct PresentConfig {
     pub pypackages_path: PathBuf,
     pub lock_path: PathBuf,
     pub config: Config,
+    /// The workspace this project is the root or a member of, if any.
+    pub workspace: Option<workspace::Workspace>,
+    pub constraints: Constraints,
+}
+
+/// Pins applied to matching packages when resolving, like pip's `-c`.
+#[derive(Clone, Debug, Default)]
+pub struct Constraints {
+    /// From `constraint-files`, and `install -c`.
+    pub pins: Vec<Req>,
+    /// `install -c`'s files. The lock records them, since `pyproject.toml` doesn't.
+    pub install_files: Vec<PathBuf>,
+}
+
+impl PresentConfig {
+    /// The requirements to resolve and install, given this project's: in a workspace, those of
+    /// every project in it, which share a lock and environment.
+    pub fn sync_reqs(&self, reqs: &[Req], dev_reqs: &[Req]) -> (Vec<Req>, Vec<Req>) {
+        match &self.workspace {
+            Some(ws) => ws.combined_reqs(&self.project_path, reqs, dev_reqs),
+            None => (reqs.to_vec(), dev_reqs.to_vec()),
+        }
+    }
+}
+
+/// The pins in pip constraints files, like pip's `-c`: every requirement in them, and in the
+/// files they include. Lines we can't use are skipped, with a warning.
+pub fn read_constraints(paths: &[PathBuf]) -> Vec<Req> {
+    let mut result = vec![];
+    for path in paths {
+        let mut parsed = files::parse_requirements_file(path).unwrap_or_else(|| {
+            abort(&format!(
+                "Can't read the constraints file {}",
+                path.to_string_lossy()
+            ))
+        });
+        for skipped in &parsed.skipped {
+            log::warn!("Skipped {}", skipped);
+        }
+        result.append(&mut parsed.reqs);
+        result.append(&mut parsed.constraints);
+    }
+    result
+}
+
+/// The `Config::deps_hash` of what the lock in `lock_dir` is resolved from: its
+/// `pyproject.toml`, and if it's a workspace's root, every member's too, and the contents of
+/// any `install -c` files.
+pub fn lock_deps_hash(lock_dir: &Path, install_constraints: &[PathBuf]) -> Option<String> {
+    let cfg = Config::from_file(&lock_dir.join(CFG_FILENAME))?;
+    let ws = workspace::Workspace::find(lock_dir).filter(|ws| ws.root == lock_dir);
+    if ws.is_none() && install_constraints.is_empty() {
+        return Some(cfg.deps_hash(lock_dir));
+    }
+    let mut hashes = vec![cfg.deps_hash(lock_dir)];
+    for member in ws.iter().flat_map(|ws| &ws.members) {
+        if let Some(member_cfg) = Config::from_file(&member.path.join(CFG_FILENAME)) {
+            hashes.push(format!(
+                "{} {}",
+                member.name,
+                member_cfg.deps_hash(&member.path)
+            ));
+        }
+    }
+    for path in install_constraints {
+        hashes.push(format!("-c {}", file_digest(&lock_dir.join(path))));
+    }
+    let hash = ring::digest::digest(&ring::digest::SHA256, hashes.join("\n").as_bytes());
+    Some(data_encoding::HEXLOWER.encode(hash.as_ref()))
+}
+
+/// The lock's `[metadata]`, which lets `pyflow check` tell if what it's resolved from changed
+/// since: `lock_deps_hash`, and the `install -c` files, relative to `lock_dir` if they're in it.
+pub fn lock_metadata(lock_dir: &Path, install_constraints: &[PathBuf]) -> HashMap<String, String> {
+    let files: Vec<PathBuf> = install_constraints
+        .iter()
+        .map(|f| {
+            let f = f.canonicalize().unwrap_or_else(|_| f.clone());
+            let dir = lock_dir
+                .canonicalize()
+                .unwrap_or_else(|_| lock_dir.to_owned());
+            f.strip_prefix(&dir).map(Path::to_path_buf).unwrap_or(f)
+        })
+        .collect();
+    let mut metadata = HashMap::new();
+    if let Some(hash) = lock_deps_hash(lock_dir, &files) {
+        metadata.insert(LOCK_HASH_KEY.to_owned(), hash);
+    }
+    if !files.is_empty() {
+        let files: Vec<String> = files.iter().map(|f| f.to_string_lossy().into()).collect();
+        metadata.insert(LOCK_CONSTRAINTS_KEY.to_owned(), files.join("\n"));
+    }
+    metadata
+}
+
+/// A file's SHA-256, in hex, or `missing` if we can't read it.
+fn file_digest(path: &Path) -> String {
+    match fs::read(path) {
+        Ok(data) => {
+            let hash = ring::digest::digest(&ring::digest::SHA256, &data);
+            data_encoding::HEXLOWER.encode(hash.as_ref())
+        }
+        Err(_) => "missing".into(),
+    }
+}
+
+/// A dependency's `sys_platform`, as in PEP 508: eg `"win32"`, or `"!= win32"` for everywhere
+/// else.
+fn parse_sys_platform(name: &str, value: &str) -> (ReqType, util::Os) {
+    let value = value.trim();
+    let (type_, platform) = match value.strip_prefix("!=") {
+        Some(p) => (ReqType::Ne, p.trim()),
+        None => (ReqType::Exact, value.trim_start_matches("==").trim()),
+    };
+    match platform.parse::<util::Os>() {
+        Ok(os) if os != util::Os::Any => (type_, os),
+        _ => abort(&format!(
+            "The dependency `{}` in `pyproject.toml` has `sys_platform = \"{}\"`; use `win32`, \
+             `linux`, or `darwin`, with `!=` in front for everywhere else",
+            name, value
+        )),
+    }
+}
+
+/// A dependency's constraints, eg `"^2.31"`, or `">=1.4, <2"`.
+fn parse_constraints(constrs: &str) -> Vec<Constraint> {
+    Constraint::from_str_multiple(constrs).unwrap_or_else(|_| {
+        abort(&format!(
+            "Problem parsing constraints in `pyproject.toml`: {}",
+            constrs
+        ))
+    })
+}
+
+/// A git dependency's `rev`, `tag`, or `branch`, of which it may have one.
+fn parse_git_ref(
+    name: &str,
+    rev: Option<String>,
+    tag: Option<String>,
+    branch: Option<String>,
+) -> Option<GitRef> {
+    match (rev, tag, branch) {
+        (Some(r), None, None) => Some(GitRef::Rev(r)),
+        (None, Some(t), None) => Some(GitRef::Tag(t)),
+        (None, None, Some(b)) => Some(GitRef::Branch(b)),
+        (None, None, None) => None,
+        _ => abort(&format!(
+            "The dependency `{}` in `pyproject.toml` may only have one of `rev`, `tag`, or \
+             `branch`",
+            name
+        )),
+    }
 }
 
 /// A config, parsed from pyproject.toml
@@ -37,99 +196,218 @@ pub struct Config {
     pub reqs: Vec<Req>,
     pub dev_reqs: Vec<Req>,
     pub version: Option<Version>,
+    /// `version` is from `git describe`, not `pyproject.toml`; see `resolve_version`.
+    #[serde(skip)]
+    pub version_from_git: Option<git_version::GitVersion>,
     pub authors: Vec<String>,
     pub license: Option<String>,
-    pub extras: HashMap<String, String>,
+    pub extras: HashMap<String, Vec<String>>, // `[tool.pyflow.extras]` groups
     pub description: Option<String>,
     pub classifiers: Vec<String>, // https://pypi.org/classifiers/
     pub keywords: Vec<String>,
     pub homepage: Option<String>,
     pub repository: Option<String>,
+    #[allow(dead_code)]
     pub repo_url: Option<String>,
     pub package_url: Option<String>,
     pub readme: Option<String>,
-    pub build: Option<String>, // A python file used to build non-python extensions
+    pub readme_content_type: Option<String>, // Overrides the type the readme's extension implies
+    pub build: Option<String>,               // A python file used to build non-python extensions
+    pub build_output: Option<String>,        // Where `build` writes what it builds, for the wheel
+    pub build_requires: Vec<String>,         // `[build-system] requires`, installed for `build`
     //    entry_points: HashMap<String, Vec<String>>, // todo option?
     pub scripts: HashMap<String, String>, //TODO: put under [tool.pyflow.scripts] ?
+    pub script_chains: HashMap<String, Vec<String>>, // Scripts that run other scripts in order
+    pub script_env: HashMap<String, HashMap<String, String>>, // Per-script `env` tables
+    pub env: HashMap<String, String>,     // Set for `pyflow run`, from `[tool.pyflow.env]`
+    pub env_file: Option<String>,         // A `.env` file, relative to the project root
+    pub script_cwd: HashMap<String, String>, // Per-script `cwd`, relative to the project root
+    pub run_from_root: bool,              // Run scripts from the project root, not the current dir
+    pub allow_user_site: bool,            // Let processes we run see `pip install --user` packages
+    pub package_dir: Option<String>,      // Where packages live, eg `src`, relative to the root
+    pub version_file: Option<String>,     // Has a `__version__` line `pyflow version` keeps in sync
+    pub include: Vec<String>,             // `[tool.pyflow.build]` patterns of files to package
+    pub exclude: Vec<String>,             // Patterns of files to leave out of wheels and sdists
     //    console_scripts: Vec<String>, // We don't parse these; pass them to `setup.py` as-entered.
     pub python_requires: Option<String>,
+    pub hooks: Hooks,
+    pub audit: Audit,
+    pub publish: Publish,
+    pub python: Option<PathBuf>, // A specific interpreter to use, instead of searching for one
+    pub python_implementation: Implementation,
+    pub env_mode: EnvMode, // Where the environment lives: `__pypackages__`, or a `.venv`
+    pub index_url: Option<String>, // The package index's JSON API; read with the other settings
+    pub constraint_files: Vec<String>, // pip constraints files, relative to the project root
+}
+
+/// Where a project's environment lives, from `env-mode` in `pyproject.toml`. Set once per
+/// project; see `current::check_env_mode`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[derive(Default)]
+pub enum EnvMode {
+    /// `__pypackages__/3.x/lib`, per PEP 582, beside a venv that only holds the interpreter.
+    #[default]
+    Pypackages,
+    /// A standard `.venv` at the project root, with packages in its `site-packages`, for tools
+    /// that don't understand PEP 582.
+    Venv,
+}
+
+impl FromStr for EnvMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_ref() {
+            "pypackages" | "__pypackages__" => Ok(Self::Pypackages),
+            "venv" | ".venv" => Ok(Self::Venv),
+            _ => Err(format!(
+                "Unknown `env-mode`: {}. Use \"pypackages\" or \"venv\"",
+                s
+            )),
+        }
+    }
+}
+
+/// Scripts to run around dependency installs, from `[tool.pyflow.hooks]`. Each is either
+/// `module:function`, or a shell command.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Hooks {
+    #[serde(rename = "pre-install")]
+    pub pre_install: Option<String>,
+    #[serde(rename = "post-install")]
+    pub post_install: Option<String>,
+}
+
+/// Settings for `pyflow audit`, from `[tool.pyflow.audit]`.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Audit {
+    /// Advisories to accept, by ID or alias.
+    #[serde(default)]
+    pub ignore: Vec<String>,
+}
+
+/// Settings for `pyflow publish`, from `[tool.pyflow.publish]`.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Publish {
+    /// Run for each file before it's uploaded, to write a detached signature next to it.
+    #[serde(rename = "sign-command")]
+    pub sign_command: Option<String>,
+    /// Write a `.sha256` file next to each file uploaded.
+    #[serde(default)]
+    pub digests: bool,
 }
 
 impl Config {
-    /// Helper fn to prevent repetition
-    pub fn parse_deps(deps: HashMap<String, files::DepComponentWrapper>) -> Vec<Req> {
-        let mut result = Vec::new();
+    /// Fill in `version` from git tags, for `version = { from = "git" }`. Only commands that use
+    /// the version call this, so others work in a checkout without a tag.
+    pub fn resolve_version(&mut self) {
+        if let (None, Some(git)) = (&self.version, &self.version_from_git) {
+            self.version = Some(git_version::from_git(
+                &git.project_path,
+                git.fallback.as_deref(),
+            ));
+        }
+    }
+
+    /// Requirements from a dependency table, in the order they're written.
+    pub fn parse_deps(deps: Vec<(String, files::DepComponentWrapper)>) -> Vec<Req> {
+        let mut result = Vec::with_capacity(deps.len());
         for (name, data) in deps {
-            let constraints;
-            let mut extras = None;
-            let mut git = None;
-            let mut path = None;
-            let mut python_version = None;
+            let mut req = Req::new(name, vec![]);
             match data {
                 files::DepComponentWrapper::A(constrs) => {
-                    constraints = if let Ok(c) = Constraint::from_str_multiple(&constrs) {
-                        c
-                    } else {
-                        abort(&format!(
-                            "Problem parsing constraints in `pyproject.toml`: {}",
-                            &constrs
-                        ))
-                    };
+                    req.constraints = parse_constraints(&constrs);
                 }
                 files::DepComponentWrapper::B(subdata) => {
-                    constraints = match subdata.constrs {
-                        Some(constrs) => {
-                            if let Ok(c) = Constraint::from_str_multiple(&constrs) {
-                                c
-                            } else {
-                                abort(&format!(
-                                    "Problem parsing constraints in `pyproject.toml`: {}",
-                                    &constrs
-                                ))
-                            }
-                        }
-                        None => vec![],
-                    };
-
-                    if let Some(ex) = subdata.extras {
-                        extras = Some(ex);
-                    }
-                    if let Some(p) = subdata.path {
-                        path = Some(p);
-                    }
-                    if let Some(repo) = subdata.git {
-                        git = Some(repo);
+                    if let Some(constrs) = subdata.constrs {
+                        req.constraints = parse_constraints(&constrs);
                     }
+                    req.install_with_extras = subdata.extras;
+                    req.path = subdata.path;
+                    req.git = subdata.git;
+                    req.git_ref =
+                        parse_git_ref(&req.name, subdata.rev, subdata.tag, subdata.branch);
                     if let Some(v) = subdata.python {
                         let pv = Constraint::from_str(&v)
                             .expect("Problem parsing python version in dependency");
-                        python_version = Some(vec![pv]);
+                        req.python_version = Some(vec![pv]);
+                    }
+                    if let Some(p) = subdata.sys_platform {
+                        req.sys_platform = Some(parse_sys_platform(&req.name, &p));
                     }
                 }
             }
-
-            result.push(Req {
-                name,
-                constraints,
-                extra: None,
-                sys_platform: None,
-                python_version,
-                install_with_extras: extras,
-                path,
-                git,
-            });
+            result.push(req);
         }
         result
     }
 
+    /// Requirements from `[tool.poetry.dependencies]`, or `dev-dependencies`, and the Python
+    /// version from its `python` entry, which only Poetry has.
+    fn parse_poetry_deps(
+        deps: Vec<(String, files::DepComponentWrapperPoetry)>,
+    ) -> (Vec<Req>, Option<Version>) {
+        let mut reqs = Vec::with_capacity(deps.len());
+        let mut py_version = None;
+        for (name, data) in deps {
+            let mut req = Req::new(name, vec![]);
+            match data {
+                files::DepComponentWrapperPoetry::A(constrs) => {
+                    req.constraints = parse_constraints(&constrs);
+                }
+                files::DepComponentWrapperPoetry::B(subdata) => {
+                    if let Some(constrs) = subdata.constrs {
+                        req.constraints = parse_constraints(&constrs);
+                    }
+                    req.install_with_extras = subdata.extras;
+                    if let Some(v) = subdata.python {
+                        req.python_version = Some(parse_constraints(&v));
+                    }
+                    if let Some(p) = subdata.platform {
+                        req.sys_platform = Some(parse_sys_platform(&req.name, &p));
+                    }
+                    req.path = subdata.path;
+                    req.git = subdata.git;
+                    req.git_ref =
+                        parse_git_ref(&req.name, subdata.rev, subdata.tag, subdata.branch);
+                    // todo repository etc
+                }
+            }
+            if req.name.eq_ignore_ascii_case("python") {
+                if let Some(constr) = req.constraints.into_iter().next() {
+                    py_version = Some(constr.version)
+                }
+            } else {
+                reqs.push(req);
+            }
+        }
+        (reqs, py_version)
+    }
+
+    /// Requirements in PEP 508's format, eg from flit. Ones we can't parse are skipped, with a
+    /// warning.
+    fn parse_pep508_reqs(specs: &[String]) -> Vec<Req> {
+        specs
+            .iter()
+            .filter_map(|spec| {
+                let req = Req::from_pip_str(spec.trim());
+                if req.is_none() {
+                    log::warn!(
+                        "Skipped `{}` in `pyproject.toml`, which we couldn't parse",
+                        spec
+                    );
+                }
+                req
+            })
+            .collect()
+    }
+
     // TODO: DRY at the top from `from_file`.
     pub fn from_pipfile(path: &Path) -> Option<Self> {
         // TODO: Lots of tweaks and QC could be done re what fields to parse, and how best to
         // todo parse and store them.
-        let toml_str = match fs::read_to_string(path).ok() {
-            Some(d) => d,
-            None => return None,
-        };
+        let toml_str = fs::read_to_string(path).ok()?;
 
         let decoded: files::Pipfile = if let Ok(d) = toml::from_str(&toml_str) {
             d
@@ -149,11 +427,20 @@ impl Config {
     }
 
     /// Pull config data from `pyproject.toml`. We use this to deserialize things like Versions
-    /// and requirements.
+    /// and requirements. Each file's only read from disk once per invocation, until it's written.
     pub fn from_file(path: &Path) -> Option<Self> {
+        if let Some(cfg) = util::loaded::config(path) {
+            return Some(cfg);
+        }
+        let cfg = Self::parse_file(path)?;
+        util::loaded::keep_config(path, &cfg);
+        Some(cfg)
+    }
+
+    fn parse_file(path: &Path) -> Option<Self> {
         // TODO: Lots of tweaks and QC could be done re what fields to parse, and how best to
         // todo parse and store them.
-        let toml_str = match fs::read_to_string(path) {
+        let toml_str = match util::loaded::read_to_string(path) {
             Ok(d) => d,
             Err(_) => return None,
         };
@@ -165,6 +452,10 @@ impl Config {
         };
         let mut result = Self::default();
 
+        if let Some(bs) = decoded.build_system {
+            result.build_requires = bs.requires.unwrap_or_default();
+        }
+
         // Parse Poetry first, since we'll use pyflow if there's a conflict.
         if let Some(po) = decoded.tool.poetry {
             if let Some(v) = po.name {
@@ -199,13 +490,16 @@ impl Config {
             if let Some(v) = po.keywords {
                 result.keywords = v;
             }
+            if let Some(v) = po.include {
+                result.include = v;
+            }
+            if let Some(v) = po.exclude {
+                result.exclude = v;
+            }
 
-            //                        if let Some(v) = po.source {
-            //                result.source = v;
-            //            }
-            //            if let Some(v) = po.scripts {
-            //                result.console_scripts = v;
-            //            }
+            if let Some(v) = po.scripts {
+                result.scripts = v;
+            }
             if let Some(v) = po.extras {
                 result.extras = v;
             }
@@ -216,51 +510,70 @@ impl Config {
                 )
             }
 
-            // TODO: DRY (c+p) from pyflow dependency parsing, other than parsing python version here,
-            // todo which only poetry does.
-            // TODO: Parse poetry dev deps
             if let Some(deps) = po.dependencies {
-                for (name, data) in deps {
-                    let constraints;
-                    let mut extras = None;
-                    let mut python_version = None;
-                    match data {
-                        files::DepComponentWrapperPoetry::A(constrs) => {
-                            constraints = Constraint::from_str_multiple(&constrs)
-                                .expect("Problem parsing constraints in `pyproject.toml`.");
-                        }
-                        files::DepComponentWrapperPoetry::B(subdata) => {
-                            constraints = Constraint::from_str_multiple(&subdata.constrs)
-                                .expect("Problem parsing constraints in `pyproject.toml`.");
-                            if let Some(ex) = subdata.extras {
-                                extras = Some(ex);
-                            }
-                            if let Some(v) = subdata.python {
-                                let pv = Constraint::from_str(&v)
-                                    .expect("Problem parsing python version in dependency");
-                                python_version = Some(vec![pv]);
-                            }
-                            // todo repository etc
-                        }
-                    }
-                    if &name.to_lowercase() == "python" {
-                        if let Some(constr) = constraints.first() {
-                            result.py_version = Some(constr.version.clone())
-                        }
+                let (reqs, py_version) = Self::parse_poetry_deps(deps);
+                result.reqs = reqs;
+                if py_version.is_some() {
+                    result.py_version = py_version;
+                }
+            }
+            if let Some(deps) = po.dev_dependencies {
+                result.dev_reqs = Self::parse_poetry_deps(deps).0;
+            }
+        }
+
+        // Flit's, for the same reason. Its version is in the module, so isn't read.
+        if let Some(flit) = decoded.tool.flit {
+            if let Some(md) = flit.metadata {
+                if let Some(v) = md.dist_name.or(md.module) {
+                    result.name = Some(v);
+                }
+                if let Some(v) = md.author {
+                    result.authors = match md.author_email {
+                        Some(email) => vec![format!("{} <{}>", v, email)],
+                        None => vec![v],
+                    };
+                }
+                if let Some(v) = md.home_page {
+                    result.homepage = Some(v);
+                }
+                if let Some(v) = md.description_file {
+                    result.readme = Some(v);
+                }
+                if let Some(v) = md.license {
+                    result.license = Some(v);
+                }
+                if let Some(v) = md.classifiers {
+                    result.classifiers = v;
+                }
+                if let Some(v) = md.keywords {
+                    result.keywords = v.split(',').map(|k| k.trim().to_owned()).collect();
+                }
+                if let Some(v) = md.requires_python {
+                    result.python_requires = Some(v);
+                }
+                if let Some(v) = md.requires {
+                    result.reqs = Self::parse_pep508_reqs(&v);
+                }
+                if let Some(v) = md.dev_requires {
+                    result.dev_reqs = Self::parse_pep508_reqs(&v);
+                }
+                // Extras are optional dependencies, in groups; flit's `dev` is for development.
+                for (group, specs) in md.requires_extra.unwrap_or_default() {
+                    let mut reqs = Self::parse_pep508_reqs(&specs);
+                    if group == "dev" {
+                        result.dev_reqs.append(&mut reqs);
                     } else {
-                        result.reqs.push(Req {
-                            name,
-                            constraints,
-                            extra: None,
-                            sys_platform: None,
-                            python_version,
-                            install_with_extras: extras,
-                            path: None,
-                            git: None,
-                        });
+                        result
+                            .extras
+                            .insert(group, reqs.iter().map(|r| r.name.clone()).collect());
+                        result.reqs.append(&mut reqs);
                     }
                 }
             }
+            if let Some(v) = flit.scripts {
+                result.scripts = v;
+            }
         }
 
         if let Some(pf) = decoded.tool.pyflow {
@@ -295,36 +608,158 @@ impl Config {
             if let Some(v) = pf.keywords {
                 result.keywords = v;
             }
-            if let Some(v) = pf.readme {
-                result.readme = Some(v);
+            match pf.readme {
+                Some(files::ReadmeWrapper::A(file)) => result.readme = Some(file),
+                Some(files::ReadmeWrapper::B(r)) => {
+                    result.readme = Some(r.file);
+                    result.readme_content_type = r.content_type;
+                }
+                None => (),
             }
-            if let Some(v) = pf.build {
-                result.build = Some(v);
+            match pf.build {
+                Some(files::BuildWrapper::A(script)) => result.build = Some(script),
+                Some(files::BuildWrapper::B(b)) => {
+                    if let Some(v) = b.script {
+                        result.build = Some(v);
+                    }
+                    result.build_output = b.output;
+                    result.include.extend(b.include.unwrap_or_default());
+                    result.exclude.extend(b.exclude.unwrap_or_default());
+                }
+                None => (),
             }
             //            if let Some(v) = pf.entry_points {
             //                result.entry_points = v;
             //            } // todo
             if let Some(v) = pf.scripts {
-                result.scripts = v;
+                for (name, script) in v {
+                    match script {
+                        files::ScriptComponentWrapper::A(run) => {
+                            result.scripts.insert(name, run);
+                        }
+                        files::ScriptComponentWrapper::B(s) => {
+                            if let Some(env) = s.env {
+                                result.script_env.insert(name.clone(), env);
+                            }
+                            if let Some(cwd) = s.cwd {
+                                result.script_cwd.insert(name.clone(), cwd);
+                            }
+                            result.scripts.insert(name, s.run);
+                        }
+                        files::ScriptComponentWrapper::C(steps) => {
+                            result.script_chains.insert(name, steps);
+                        }
+                    }
+                }
+            }
+
+            if let Some(v) = pf.env {
+                result.env = v;
+            }
+            if let Some(v) = pf.extras {
+                result.extras = v;
+            }
+            if let Some(v) = pf.env_file {
+                result.env_file = Some(v);
+            }
+            if let Some(v) = pf.run_from_root {
+                result.run_from_root = v;
+            }
+            if let Some(v) = pf.allow_user_site {
+                result.allow_user_site = v;
+            }
+            if let Some(v) = pf.index_url {
+                result.index_url = Some(v);
+            }
+            if let Some(v) = pf.constraint_files {
+                result.constraint_files = v;
+            }
+            if let Some(v) = pf.package_dir {
+                result.package_dir = Some(v);
+            }
+            if let Some(v) = pf.version_file {
+                result.version_file = Some(v);
+            }
+            // Also accepted directly under `[tool.pyflow]`.
+            if let Some(v) = pf.include {
+                result.include.extend(v);
+            }
+            if let Some(v) = pf.exclude {
+                result.exclude.extend(v);
             }
 
             if let Some(v) = pf.python_requires {
                 result.python_requires = Some(v);
             }
 
+            if let Some(v) = pf.hooks {
+                result.hooks = v;
+            }
+
+            if let Some(v) = pf.audit {
+                result.audit = v;
+            }
+
+            if let Some(v) = pf.publish {
+                result.publish = v;
+            }
+
+            if let Some(v) = pf.python {
+                result.python = Some(PathBuf::from(v));
+            }
+
+            if let Some(v) = &pf.python_implementation {
+                result.python_implementation = v.parse().unwrap_or_else(|e: String| abort(&e));
+            }
+
+            if let Some(v) = &pf.env_mode {
+                result.env_mode = v.parse().unwrap_or_else(|e: String| abort(&e));
+            }
+
             if let Some(v) = pf.package_url {
                 result.package_url = Some(v);
             }
 
-            if let Some(v) = pf.version {
-                result.version = Some(
-                    Version::from_str(&v).expect("Problem parsing version in `pyproject.toml`"),
-                )
+            // `version = { from = "git" }`, or `dynamic-version = "git"`.
+            let source = match (pf.version, pf.dynamic_version) {
+                (Some(files::VersionWrapper::A(_)), Some(_)) => {
+                    abort("Set `version`, or `dynamic-version`, in `[tool.pyflow]`, not both")
+                }
+                (Some(files::VersionWrapper::A(v)), None) => {
+                    result.version = Some(
+                        Version::from_str(&v).expect("Problem parsing version in `pyproject.toml`"),
+                    );
+                    None
+                }
+                (Some(files::VersionWrapper::B(t)), _) => Some(t.from),
+                (None, d) => d,
+            };
+            if let Some(source) = source {
+                if source != git_version::SOURCE {
+                    abort(&format!(
+                        "The version can come from `{}`, not `{}`",
+                        git_version::SOURCE,
+                        source
+                    ))
+                }
+                let project_path = path
+                    .parent()
+                    .filter(|p| !p.as_os_str().is_empty())
+                    .unwrap_or_else(|| Path::new("."));
+                result.version_from_git = Some(git_version::GitVersion {
+                    project_path: project_path.to_owned(),
+                    fallback: pf.fallback_version,
+                });
             }
 
             if let Some(v) = pf.py_version {
+                // Eg `pypy3.10`; a separate `python_implementation` takes precedence.
+                let (implementation, v) = py_versions::split_implementation(&v);
+                if let (Some(i), None) = (implementation, &pf.python_implementation) {
+                    result.python_implementation = i;
+                }
                 result.py_version = Some(
-                    Version::from_str(&v)
+                    Version::from_str(v)
                         .expect("Problem parsing python version in `pyproject.toml`"),
                 );
             }
@@ -347,6 +782,50 @@ impl Config {
             .append(&mut pop_reqs_helper(&self.dev_reqs, true));
     }
 
+    /// A hash of what the lock is resolved from: the Python version, dependencies, and the
+    /// contents of constraints files, relative to `project_path`. Other edits to
+    /// `pyproject.toml` don't change it.
+    pub fn deps_hash(&self, project_path: &Path) -> String {
+        // Not `to_cfg_string`, which looks up a version for unconstrained requirements.
+        let line = |r: &Req| {
+            let constraints: Vec<String> = r
+                .constraints
+                .iter()
+                .map(|c| c.to_string2(true, false))
+                .collect();
+            format!(
+                "{} {} {:?} {:?} {:?} {:?} {:?} {:?}",
+                r.name,
+                constraints.join(","),
+                r.install_with_extras,
+                r.path,
+                r.git,
+                r.git_ref.as_ref().map(GitRef::parts),
+                r.sys_platform,
+                r.python_version
+                    .as_ref()
+                    .map(|cs| cs.iter().map(ToString::to_string).collect::<Vec<_>>()),
+            )
+        };
+        let mut lines: Vec<String> = self
+            .reqs
+            .iter()
+            .map(line)
+            .chain(self.dev_reqs.iter().map(|r| format!("dev {}", line(r))))
+            .chain(
+                self.constraint_files
+                    .iter()
+                    .map(|f| format!("constraints {} {}", f, file_digest(&project_path.join(f)))),
+            )
+            .collect();
+        lines.sort();
+        if let Some(v) = &self.py_version {
+            lines.insert(0, format!("python {}", v));
+        }
+        let hash = ring::digest::digest(&ring::digest::SHA256, lines.join("\n").as_bytes());
+        data_encoding::HEXLOWER.encode(hash.as_ref())
+    }
+
     /// Create a new `pyproject.toml` file.
     pub fn write_file(&self, path: &Path) {
         let file = path;
@@ -357,67 +836,225 @@ impl Config {
         let mut result = String::new();
 
         result.push_str("\n[tool.pyflow]\n");
-        if let Some(name) = &self.name {
-            result.push_str(&("name = \"".to_owned() + name + "\"\n"));
-        } else {
-            // Give name, and a few other fields default values.
-            result.push_str(&("name = \"\"".to_owned() + "\n"));
+        // Give name, and a few other fields default values.
+        emit_kv(&mut result, "name", self.name.as_deref().unwrap_or(""));
+        emit_kv(
+            &mut result,
+            "py_version",
+            self.py_version
+                .as_ref()
+                .map_or_else(|| "3.8".to_owned(), Version::to_string_no_patch),
+        );
+        emit_kv(
+            &mut result,
+            "version",
+            self.version
+                .as_ref()
+                .map_or_else(|| "0.1.0".to_owned(), Version::to_string),
+        );
+        if !self.authors.is_empty() {
+            emit_kv(&mut result, "authors", self.authors.clone());
         }
-        if let Some(py_v) = &self.py_version {
-            result.push_str(&("py_version = \"".to_owned() + &py_v.to_string_no_patch() + "\"\n"));
-        } else {
-            result.push_str(&("py_version = \"3.8\"".to_owned() + "\n"));
+        let optional = [
+            ("description", &self.description),
+            ("homepage", &self.homepage),
+            ("license", &self.license),
+            ("python_requires", &self.python_requires),
+            ("package-dir", &self.package_dir),
+        ];
+        for (key, value) in &optional {
+            if let Some(v) = value {
+                emit_kv(&mut result, key, v.as_str());
+            }
         }
-        if let Some(vers) = self.version.clone() {
-            result.push_str(&(format!("version = \"{}\"", vers.to_string() + "\n")));
-        } else {
-            result.push_str("version = \"0.1.0\"");
-            result.push('\n');
+        if self.run_from_root {
+            emit_kv(&mut result, "run-from-root", true);
         }
-        if !self.authors.is_empty() {
-            result.push_str("authors = [\"");
-            for (i, author) in self.authors.iter().enumerate() {
-                if i != 0 {
-                    result.push_str(", ");
-                }
-                result.push_str(author);
-            }
-            result.push_str("\"]\n");
+        if self.allow_user_site {
+            emit_kv(&mut result, "allow-user-site", true);
         }
-
-        if let Some(v) = &self.description {
-            result.push_str(&(format!("description = \"{}\"", v) + "\n"));
+        if self.env_mode == EnvMode::Venv {
+            emit_kv(&mut result, "env-mode", "venv");
+        }
+        if let Some(v) = &self.index_url {
+            emit_kv(&mut result, "index-url", v.as_str());
         }
-        if let Some(v) = &self.homepage {
-            result.push_str(&(format!("homepage = \"{}\"", v) + "\n"));
+        if !self.constraint_files.is_empty() {
+            emit_kv(
+                &mut result,
+                "constraint-files",
+                self.constraint_files.clone(),
+            );
         }
 
         // TODO: More fields
 
         result.push('\n');
         result.push_str("[tool.pyflow.scripts]\n");
-        for (name, mod_fn) in &self.scripts {
-            result.push_str(&(format!("{} = \"{}\"", name, mod_fn) + "\n"));
-        }
+        self.emit_scripts(&mut result);
 
         result.push('\n');
         result.push_str("[tool.pyflow.dependencies]\n");
         for dep in &self.reqs {
-            result.push_str(&(dep.to_cfg_string() + "\n"));
+            let (name, value) = dep.to_cfg_kv();
+            emit_kv(&mut result, &name, value);
         }
 
         result.push('\n');
         result.push_str("[tool.pyflow.dev-dependencies]\n");
         for dep in &self.dev_reqs {
-            result.push_str(&(dep.to_cfg_string() + "\n"));
+            let (name, value) = dep.to_cfg_kv();
+            emit_kv(&mut result, &name, value);
+        }
+
+        if !self.extras.is_empty() {
+            result.push('\n');
+            result.push_str("[tool.pyflow.extras]\n");
+            let mut extras: Vec<_> = self.extras.iter().collect();
+            extras.sort();
+            for (group, names) in extras {
+                emit_kv(&mut result, group, names.clone());
+            }
         }
 
         result.push('\n'); // trailing newline
 
-        if fs::write(file, result).is_err() {
+        if util::loaded::write(file, result).is_err() {
             abort("Problem writing `pyproject.toml`")
         }
     }
+
+    /// `[tool.pyflow.scripts]`'s entries, sorted by name: `name = "..."`, `name = ["a", "b"]` for
+    /// composite scripts, and an inline table for ones with their own `env` or `cwd`.
+    fn emit_scripts(&self, result: &mut String) {
+        let mut entries = vec![];
+        for (name, run) in &self.scripts {
+            let mut fields = vec![("run".to_owned(), run.as_str().into())];
+            if let Some(cwd) = self.script_cwd.get(name) {
+                fields.push(("cwd".to_owned(), cwd.as_str().into()));
+            }
+            if let Some(env) = self.script_env.get(name).filter(|e| !e.is_empty()) {
+                let mut vars: Vec<(String, TomlValue)> = env
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.as_str().into()))
+                    .collect();
+                vars.sort_by(|a, b| a.0.cmp(&b.0));
+                fields.push(("env".to_owned(), TomlValue::Table(vars)));
+            }
+
+            let value = if fields.len() == 1 {
+                fields.remove(0).1
+            } else {
+                TomlValue::Table(fields)
+            };
+            entries.push((name, value));
+        }
+        for (name, steps) in &self.script_chains {
+            entries.push((name, steps.clone().into()));
+        }
+        entries.sort_by_key(|(name, _)| toml_key(name));
+
+        for (name, value) in entries {
+            emit_kv(result, name, value);
+        }
+    }
+}
+
+/// A TOML basic string: quotes, backslashes, and control characters are escaped, and anything
+/// else, including non-ASCII, is written as it is.
+pub fn toml_quote(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            '\u{8}' => result.push_str("\\b"),
+            '\u{c}' => result.push_str("\\f"),
+            c if c.is_control() => result.push_str(&format!("\\u{:04X}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// A value for `emit_kv`. Strings are escaped by `toml_quote`, including in arrays and tables.
+#[derive(Clone, Debug)]
+pub enum TomlValue {
+    Str(String),
+    Bool(bool),
+    Array(Vec<TomlValue>),
+    /// An inline table, with its fields in this order.
+    Table(Vec<(String, TomlValue)>),
+}
+
+impl From<&str> for TomlValue {
+    fn from(s: &str) -> Self {
+        Self::Str(s.to_owned())
+    }
+}
+
+impl From<String> for TomlValue {
+    fn from(s: String) -> Self {
+        Self::Str(s)
+    }
+}
+
+impl From<bool> for TomlValue {
+    fn from(b: bool) -> Self {
+        Self::Bool(b)
+    }
+}
+
+impl<T: Into<TomlValue>> From<Vec<T>> for TomlValue {
+    fn from(values: Vec<T>) -> Self {
+        Self::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl std::fmt::Display for TomlValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Str(s) => write!(f, "{}", toml_quote(s)),
+            Self::Bool(b) => write!(f, "{}", b),
+            Self::Array(values) => {
+                let values: Vec<String> = values.iter().map(ToString::to_string).collect();
+                write!(f, "[{}]", values.join(", "))
+            }
+            Self::Table(fields) => {
+                let fields: Vec<String> = fields.iter().map(|(k, v)| toml_kv(k, v)).collect();
+                write!(f, "{{ {} }}", fields.join(", "))
+            }
+        }
+    }
+}
+
+/// `key = value`, for every field we write to `pyproject.toml`.
+pub fn toml_kv(key: &str, value: &TomlValue) -> String {
+    format!("{} = {}", toml_key(key), value)
+}
+
+/// `key = value`, and a newline, for `Config::write_file`.
+fn emit_kv(result: &mut String, key: &str, value: impl Into<TomlValue>) {
+    result.push_str(&toml_kv(key, &value.into()));
+    result.push('\n');
+}
+
+/// A TOML key: bare if it can be, eg package names with dots need quotes.
+pub fn toml_key(name: &str) -> String {
+    if !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        name.to_owned()
+    } else {
+        toml_quote(name)
+    }
 }
 
 /// Reduce repetition between reqs and dev reqs when populating reqs of path reqs.
@@ -445,7 +1082,28 @@ fn pop_reqs_helper(reqs: &[Req], dev: bool) -> Vec<Req> {
             result.append(&mut dummy_cfg.reqs);
         }
 
-        // We don't parse `setup.py`, since it involves running arbitrary Python code.
+        // `setup.py` is only read, never run, so only literal requirements are found.
+        if !dev {
+            if let Ok(data) = fs::read_to_string(req_path.join("setup.py")) {
+                let setup = setup_py::parse(&data);
+                let (mut reqs, bad) = setup.reqs();
+                if setup.dynamic.iter().any(|a| a == "install_requires") {
+                    log::warn!(
+                        "{:?}'s `setup.py` computes `install_requires`, so its requirements \
+                         can't be read without running it",
+                        req_path
+                    );
+                }
+                for spec in bad {
+                    log::warn!(
+                        "Skipped `{}` in {:?}'s `setup.py`, which we couldn't parse",
+                        spec,
+                        req_path
+                    );
+                }
+                result.append(&mut reqs);
+            }
+        }
 
         if pyproj.exists() {
             let mut req_cfg = Config::from_file(&PathBuf::from(&pyproj))
@@ -455,9 +1113,7 @@ fn pop_reqs_helper(reqs: &[Req], dev: bool) -> Vec<Req> {
 
         // Check for metadata of a built wheel
         for folder_name in util::find_folders(&req_path) {
-            // TODO: Dry from `util` and `install`.
-            let re_dist = Regex::new(r"^(.*?)-(.*?)\.dist-info$").unwrap();
-            if re_dist.captures(&folder_name).is_some() {
+            if util::patterns::DIST_INFO_RE.is_match(&folder_name) {
                 let metadata_path = req_path.join(folder_name).join("METADATA");
                 let mut metadata = util::parse_metadata(&metadata_path);
 
@@ -467,3 +1123,222 @@ fn pop_reqs_helper(reqs: &[Req], dev: bool) -> Vec<Req> {
     }
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::temp_dir::TempDir;
+
+    #[test]
+    fn env_modes() {
+        assert_eq!(" Venv ".parse(), Ok(EnvMode::Venv));
+        assert_eq!(".venv".parse(), Ok(EnvMode::Venv));
+        assert_eq!("__pypackages__".parse(), Ok(EnvMode::Pypackages));
+        assert_eq!("pypackages".parse(), Ok(EnvMode::Pypackages));
+        assert!("conda".parse::<EnvMode>().is_err());
+        assert_eq!(EnvMode::default(), EnvMode::Pypackages);
+    }
+
+    #[test]
+    fn flit_metadata() {
+        let data = r#"
+[build-system]
+requires = ["flit_core >=3.2,<4"]
+
+[tool.flit.metadata]
+module = "mylib"
+author = "Me"
+author-email = "me@example.com"
+keywords = "http, client"
+requires-python = ">=3.8"
+requires = ["requests>=2.0", "idna"]
+
+[tool.flit.metadata.requires-extra]
+socks = ["PySocks"]
+dev = ["pytest"]
+
+[tool.flit.scripts]
+mylib = "mylib.cli:main"
+"#;
+        let dir = TempDir::new("flit");
+        let path = dir.join(CFG_FILENAME);
+        fs::write(&path, data).unwrap();
+        let cfg = Config::from_file(&path).unwrap();
+
+        assert_eq!(cfg.name.as_deref(), Some("mylib"));
+        assert_eq!(cfg.authors, vec!["Me <me@example.com>".to_owned()]);
+        assert_eq!(cfg.keywords, vec!["http".to_owned(), "client".to_owned()]);
+        assert_eq!(cfg.python_requires.as_deref(), Some(">=3.8"));
+        let names: Vec<&str> = cfg.reqs.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["requests", "idna", "PySocks"]);
+        assert_eq!(cfg.dev_reqs.len(), 1);
+        assert_eq!(cfg.dev_reqs[0].name, "pytest");
+        assert_eq!(cfg.extras["socks"], vec!["PySocks".to_owned()]);
+        assert_eq!(cfg.scripts["mylib"], "mylib.cli:main");
+    }
+
+    #[test]
+    fn constraint_files() {
+        let dir = TempDir::new("constraints");
+        fs::write(
+            dir.join(CFG_FILENAME),
+            "[tool.pyflow]\nname = \"app\"\nconstraint-files = [\"constraints.txt\"]\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("constraints.txt"),
+            "-c shared.txt\nurllib3<2; python_version < \"3.8\"\n",
+        )
+        .unwrap();
+        fs::write(dir.join("shared.txt"), "idna==3.6\n").unwrap();
+        let cfg = Config::from_file(&dir.join(CFG_FILENAME)).unwrap();
+        let files: Vec<PathBuf> = cfg.constraint_files.iter().map(|f| dir.join(f)).collect();
+        let constraints = read_constraints(&files);
+
+        let names: Vec<&str> = constraints.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["urllib3", "idna"]);
+        assert!(constraints[0].python_version.is_some());
+    }
+
+    /// The version from git tags is only found when it's used, so other commands work without
+    /// a tag, or outside a checkout.
+    #[test]
+    fn version_from_git_lazily() {
+        let dir = TempDir::new("git-version");
+        fs::write(
+            dir.join(CFG_FILENAME),
+            "[tool.pyflow]\nname = \"app\"\nversion = { from = \"git\" }\n\
+             fallback-version = \"1.4.2+local.1\"\n",
+        )
+        .unwrap();
+        let mut cfg = Config::from_file(&dir.join(CFG_FILENAME)).unwrap();
+        assert!(cfg.version.is_none());
+        cfg.resolve_version();
+
+        assert_eq!(cfg.version.unwrap().to_string(), "1.4.2+local.1");
+    }
+
+    /// Quotes, backslashes, control characters, and non-ASCII, in every text field `write_file`
+    /// writes, read back as they were.
+    #[test]
+    fn write_file_hostile_strings() {
+        let hostile = "A \"fast\" tool \\o/ 'q' \t\r\n\u{0}\u{1f}\u{7f}\u{85} Zoë 🐍 \\u0041";
+        let with = |prefix: &str| format!("{}{}", prefix, hostile);
+
+        let mut git = Req::new("zope.interface".into(), vec![]);
+        git.git = Some(with("https://x.test/"));
+        git.git_ref = Some(GitRef::Branch(hostile.into()));
+        let mut path_dep = Req::new("utils".into(), vec![]);
+        path_dep.path = Some(r#"C:\Users\me\"quoted" utils\"#.into());
+        path_dep.install_with_extras = Some(vec![hostile.into()]);
+
+        let cfg = Config {
+            name: Some(hostile.into()),
+            version: Some(Version::new(1, 2, 3)),
+            authors: vec![hostile.into(), "Zoë \"Z\" <z@example.com>".into()],
+            description: Some(hostile.into()),
+            homepage: Some(with("https://x.test/")),
+            license: Some(hostile.into()),
+            python_requires: Some(hostile.into()),
+            package_dir: Some(r"src\pkg".into()),
+            index_url: Some(with("https://index.test/")),
+            constraint_files: vec![hostile.into(), r"C:\constraints.txt".into()],
+            scripts: vec![
+                ("serve".into(), hostile.into()),
+                ("a.b".into(), hostile.into()),
+            ]
+            .into_iter()
+            .collect(),
+            script_env: vec![(
+                "serve".into(),
+                vec![("MSG".into(), hostile.into())].into_iter().collect(),
+            )]
+            .into_iter()
+            .collect(),
+            script_cwd: vec![("serve".into(), hostile.into())].into_iter().collect(),
+            script_chains: vec![("all".into(), vec![hostile.into(), "serve".into()])]
+                .into_iter()
+                .collect(),
+            extras: vec![("my extra".into(), vec![hostile.into()])]
+                .into_iter()
+                .collect(),
+            reqs: vec![git.clone(), path_dep.clone()],
+            ..Config::default()
+        };
+
+        let dir = TempDir::new("hostile");
+        let path = dir.join(CFG_FILENAME);
+        cfg.write_file(&path);
+        let written = fs::read_to_string(&path).unwrap();
+        let read = Config::from_file(&path);
+        let read = read.unwrap_or_else(|| panic!("Can't parse what we wrote:\n{}", written));
+
+        assert_eq!(read.name, cfg.name);
+        assert_eq!(read.version, cfg.version);
+        assert_eq!(read.authors, cfg.authors);
+        assert_eq!(read.description, cfg.description);
+        assert_eq!(read.homepage, cfg.homepage);
+        assert_eq!(read.license, cfg.license);
+        assert_eq!(read.python_requires, cfg.python_requires);
+        assert_eq!(read.package_dir, cfg.package_dir);
+        assert_eq!(read.index_url, cfg.index_url);
+        assert_eq!(read.constraint_files, cfg.constraint_files);
+        assert_eq!(read.scripts, cfg.scripts);
+        assert_eq!(read.script_env, cfg.script_env);
+        assert_eq!(read.script_cwd, cfg.script_cwd);
+        assert_eq!(read.script_chains, cfg.script_chains);
+        assert_eq!(read.extras, cfg.extras);
+        assert_eq!(read.reqs.len(), 2);
+        assert_eq!(read.reqs[0].name, git.name);
+        assert_eq!(read.reqs[0].git, git.git);
+        assert_eq!(read.reqs[0].git_ref, git.git_ref);
+        assert_eq!(read.reqs[1].path, path_dep.path);
+        assert_eq!(
+            read.reqs[1].install_with_extras,
+            path_dep.install_with_extras
+        );
+    }
+
+    /// Parsing a large config; run with `--nocapture` to see how long it takes.
+    #[test]
+    fn parse_1000_deps() {
+        let mut data = "[tool.pyflow]\nname = \"big\"\n\n[tool.pyflow.dependencies]\n".to_owned();
+        for i in 0..1000 {
+            data.push_str(&match i % 4 {
+                0 => format!("pkg{} = \"^1.{}\"\n", i, i),
+                1 => format!("pkg{} = \">= 1.0, < 2\"\n", i),
+                2 => format!(
+                    "pkg{} = {{ version = \"~2.1\", extras = [\"a\"], python = \">=3.8\" }}\n",
+                    i
+                ),
+                _ => format!(
+                    "pkg{} = {{ git = \"https://x.test/pkg.git\", tag = \"v1\" }}\n",
+                    i
+                ),
+            });
+        }
+        let dir = TempDir::new("big");
+        let path = dir.join(CFG_FILENAME);
+        fs::write(&path, data).unwrap();
+
+        let start = std::time::Instant::now();
+        let cfg = Config::parse_file(&path).unwrap();
+        println!("Parsed 1000 dependencies in {:?}", start.elapsed());
+
+        // In the file's order.
+        assert_eq!(cfg.reqs.len(), 1000);
+        assert!(cfg
+            .reqs
+            .iter()
+            .enumerate()
+            .all(|(i, r)| r.name == format!("pkg{}", i)));
+        assert_eq!(
+            cfg.reqs[0].constraints,
+            vec![Constraint::from_str("^1.0").unwrap()]
+        );
+        assert_eq!(cfg.reqs[1].constraints.len(), 2);
+        assert_eq!(cfg.reqs[2].install_with_extras, Some(vec!["a".to_owned()]));
+        assert!(cfg.reqs[2].python_version.is_some());
+        assert_eq!(cfg.reqs[3].git_ref, Some(GitRef::Tag("v1".into())));
+    }
+}
@@ -0,0 +1,350 @@
+//! Read the metadata and requirements a `setup.py` passes to `setup()`, without running it.
+//! Only literals are understood: strings, and lists, tuples, and dicts of them, either in the
+//! call, or assigned to a name at the top of the file. Anything computed is left out.
+
+use std::{collections::HashMap, sync::LazyLock};
+
+use regex::Regex;
+
+use crate::dep_types::Req;
+
+/// What we could read. Arguments that are there, but computed, are `None`, and named in
+/// `dynamic`.
+#[derive(Debug, Default, PartialEq)]
+pub struct SetupPy {
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub python_requires: Option<String>,
+    pub install_requires: Option<Vec<String>>,
+    pub extras_require: Option<HashMap<String, Vec<String>>>,
+    pub dynamic: Vec<String>,
+}
+
+impl SetupPy {
+    /// `install_requires`, and the specs that aren't valid requirements.
+    pub fn reqs(&self) -> (Vec<Req>, Vec<String>) {
+        parse_specs(self.install_requires.iter().flatten())
+    }
+
+    /// Each `extras_require` group's requirements, sorted by group, and the specs that aren't
+    /// valid requirements.
+    pub fn extra_reqs(&self) -> (Vec<(String, Vec<Req>)>, Vec<String>) {
+        let mut groups: Vec<_> = self.extras_require.iter().flatten().collect();
+        groups.sort();
+        let mut bad = vec![];
+        let result = groups
+            .into_iter()
+            .map(|(group, specs)| {
+                let (reqs, mut b) = parse_specs(specs.iter());
+                bad.append(&mut b);
+                (group.clone(), reqs)
+            })
+            .collect();
+        (result, bad)
+    }
+}
+
+fn parse_specs<'a>(specs: impl Iterator<Item = &'a String>) -> (Vec<Req>, Vec<String>) {
+    let mut reqs = vec![];
+    let mut bad = vec![];
+    for spec in specs {
+        match Req::from_pip_str(spec) {
+            Some(r) => reqs.push(r),
+            None => bad.push(spec.clone()),
+        }
+    }
+    (reqs, bad)
+}
+
+#[derive(Debug, PartialEq)]
+enum Literal {
+    Str(String),
+    List(Vec<Literal>),
+    Dict(Vec<(String, Literal)>),
+}
+
+impl Literal {
+    fn into_str(self) -> Option<String> {
+        match self {
+            Self::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn into_strs(self) -> Option<Vec<String>> {
+        match self {
+            Self::List(items) => items
+                .into_iter()
+                .map(|i| i.into_str().map(|s| s.trim().to_owned()))
+                .collect(),
+            // A single requirement is allowed too.
+            Self::Str(s) => Some(vec![s.trim().to_owned()]),
+            Self::Dict(_) => None,
+        }
+    }
+
+    fn into_groups(self) -> Option<HashMap<String, Vec<String>>> {
+        match self {
+            Self::Dict(entries) => entries
+                .into_iter()
+                .map(|(k, v)| v.into_strs().map(|v| (k, v)))
+                .collect(),
+            _ => None,
+        }
+    }
+}
+
+/// A line that only has a comment.
+static COMMENT_LINE_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?m)^\s*#.*$").unwrap());
+
+/// A comment after code, if there's no quote in it.
+static COMMENT_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"(?m)#[^'"\n]*$"#).unwrap());
+
+/// Read what we can from a `setup.py`'s source.
+pub fn parse(data: &str) -> SetupPy {
+    // Drop comments, so commented-out arguments and requirements don't match.
+    let data = COMMENT_LINE_RE.replace_all(data, "");
+    let data = COMMENT_RE.replace_all(&data, "").into_owned();
+
+    let mut result = SetupPy::default();
+    let mut dynamic = vec![];
+    let mut arg = |key: &str| match argument(&data, key) {
+        Some(Some(value)) => Some(value),
+        Some(None) => {
+            dynamic.push(key.to_owned());
+            None
+        }
+        None => None,
+    };
+
+    result.name = arg("name").and_then(|l| l.into_str());
+    result.version = arg("version").and_then(|l| l.into_str());
+    result.python_requires = arg("python_requires").and_then(|l| l.into_str());
+    result.install_requires = arg("install_requires").and_then(|l| l.into_strs());
+    result.extras_require = arg("extras_require").and_then(|l| l.into_groups());
+    // Literals of the wrong shape count as computed, too.
+    for (key, found) in &[
+        ("name", result.name.is_some()),
+        ("version", result.version.is_some()),
+        ("python_requires", result.python_requires.is_some()),
+        ("install_requires", result.install_requires.is_some()),
+        ("extras_require", result.extras_require.is_some()),
+    ] {
+        if !found && argument(&data, key).is_some_and(|v| v.is_some()) {
+            dynamic.push((*key).to_owned());
+        }
+    }
+    result.dynamic = dynamic;
+    result
+}
+
+/// A Python identifier, eg a variable an argument is set to.
+static IDENTIFIER_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^([A-Za-z_]\w*)").unwrap());
+
+/// The `key=` argument to `setup()`: `None` if it isn't there, and `Some(None)` if it's
+/// computed. A name assigned a literal at the top of the file is followed.
+fn argument(data: &str, key: &str) -> Option<Option<Literal>> {
+    let call = data.find("setup(").map_or(data, |i| &data[i..]);
+    let key_re = Regex::new(&format!(r"[(,\s]{}\s*=\s*", key)).unwrap();
+    let m = key_re.find(call)?;
+    let value = &call[m.end()..];
+
+    if let Some((literal, rest)) = parse_literal(value) {
+        return Some(if ends_argument(rest) {
+            Some(literal)
+        } else {
+            None
+        });
+    }
+    let name = match IDENTIFIER_RE.captures(value) {
+        Some(caps) if ends_argument(&value[caps[1].len()..]) => caps[1].to_owned(),
+        _ => return Some(None),
+    };
+    let assign_re = Regex::new(&format!(r"(?m)^{}\s*=\s*", regex::escape(&name))).unwrap();
+    let changed_re =
+        Regex::new(&format!(r"(?m)^{}\s*(\S=|\.\w+\()", regex::escape(&name))).unwrap();
+    if changed_re.is_match(data) {
+        return Some(None);
+    }
+    let mut assigned: Vec<_> = assign_re
+        .find_iter(data)
+        .map(|m| parse_literal(&data[m.end()..]))
+        .collect();
+    // Assigned more than once, or something computed, it could be anything.
+    Some(match assigned.pop() {
+        Some(Some((literal, rest)))
+            if assigned.is_empty() && rest.lines().next().map_or(true, |l| l.trim().is_empty()) =>
+        {
+            Some(literal)
+        }
+        _ => None,
+    })
+}
+
+/// Whether what follows a value shows it's the whole argument, not eg the start of a `+`.
+fn ends_argument(rest: &str) -> bool {
+    matches!(rest.trim_start().chars().next(), Some(',') | Some(')'))
+}
+
+/// A literal at the start of `s`, after any whitespace, and what follows it.
+fn parse_literal(s: &str) -> Option<(Literal, &str)> {
+    let s = s.trim_start();
+    let first = s.chars().next()?;
+    match first {
+        '\'' | '"' => {
+            let (mut value, mut rest) = parse_str(s)?;
+            // Adjacent strings are joined, as in Python.
+            while let Some((more, r)) = parse_str(rest.trim_start()) {
+                value.push_str(&more);
+                rest = r;
+            }
+            Some((Literal::Str(value), rest))
+        }
+        '[' | '(' => {
+            let close = if first == '[' { ']' } else { ')' };
+            let mut items = vec![];
+            let mut rest = &s[1..];
+            loop {
+                rest = rest.trim_start();
+                if let Some(r) = rest.strip_prefix(close) {
+                    return Some((Literal::List(items), r));
+                }
+                let (item, r) = parse_literal(rest)?;
+                items.push(item);
+                rest = r.trim_start();
+                if let Some(r) = rest.strip_prefix(',') {
+                    rest = r;
+                } else if !rest.starts_with(close) {
+                    return None;
+                }
+            }
+        }
+        '{' => {
+            let mut entries = vec![];
+            let mut rest = &s[1..];
+            loop {
+                rest = rest.trim_start();
+                if let Some(r) = rest.strip_prefix('}') {
+                    return Some((Literal::Dict(entries), r));
+                }
+                let (key, r) = parse_str(rest)?;
+                let (value, r) = parse_literal(r.trim_start().strip_prefix(':')?)?;
+                entries.push((key, value));
+                rest = r.trim_start();
+                if let Some(r) = rest.strip_prefix(',') {
+                    rest = r;
+                } else if !rest.starts_with('}') {
+                    return None;
+                }
+            }
+        }
+        _ => None,
+    }
+}
+
+/// A single- or double-quoted string at the start of `s`. Escapes are kept as written, other
+/// than quotes; requirements and versions don't need them.
+fn parse_str(s: &str) -> Option<(String, &str)> {
+    let quote = s.chars().next().filter(|c| *c == '\'' || *c == '"')?;
+    let mut value = String::new();
+    let mut chars = s[1..].char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                let (_, escaped) = chars.next()?;
+                if escaped != quote {
+                    value.push('\\');
+                }
+                value.push(escaped);
+            }
+            '\n' => return None,
+            c if c == quote => return Some((value, &s[i + 2..])),
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literals() {
+        let data = r#"
+from setuptools import setup
+
+REQUIRES = [
+    "requests>=2.0",  # HTTP
+    # "unused",
+    'numpy',
+]
+
+setup(
+    name="mypackage",
+    version='1.2.0',
+    python_requires=">=3.8",
+    install_requires=REQUIRES,
+    extras_require={"test": ["pytest>=7", 'pytest-cov'], "docs": ("sphinx",)},
+)
+"#;
+        let setup = parse(data);
+        assert_eq!(
+            setup,
+            SetupPy {
+                name: Some("mypackage".into()),
+                version: Some("1.2.0".into()),
+                python_requires: Some(">=3.8".into()),
+                install_requires: Some(vec!["requests>=2.0".into(), "numpy".into()]),
+                extras_require: Some(HashMap::from([
+                    (
+                        "test".to_owned(),
+                        vec!["pytest>=7".into(), "pytest-cov".into()]
+                    ),
+                    ("docs".to_owned(), vec!["sphinx".into()]),
+                ])),
+                dynamic: vec![],
+            }
+        );
+        let (extras, bad) = setup.extra_reqs();
+        assert!(bad.is_empty());
+        assert_eq!(extras[0].0, "docs");
+        assert_eq!(extras[1].1.len(), 2);
+    }
+
+    #[test]
+    fn dynamic_skipped() {
+        let data = r#"
+import os
+from setuptools import setup
+
+def read_reqs():
+    with open("requirements.txt") as f:
+        return f.read().splitlines()
+
+BASE = ["requests"]
+BASE += ["numpy"]
+
+setup(
+    name="mypackage",
+    version=os.environ.get("VERSION", "0.0.0"),
+    install_requires=BASE,
+    extras_require={"test": ["pytest"], "all": read_reqs() + ["six"]},
+    python_requires=">=3.8",
+)
+"#;
+        assert_eq!(
+            parse(data),
+            SetupPy {
+                name: Some("mypackage".into()),
+                python_requires: Some(">=3.8".into()),
+                dynamic: vec![
+                    "version".into(),
+                    "install_requires".into(),
+                    "extras_require".into()
+                ],
+                ..Default::default()
+            }
+        );
+    }
+}
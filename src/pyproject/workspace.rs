@@ -0,0 +1,441 @@
+//! Workspaces: several member projects, each with its own `pyproject.toml`, listed in a root
+//! `pyproject.toml`:
+//!
+//! ```toml
+//! [tool.pyflow.workspace]
+//! members = ["libs/*", "services/api"]
+//! ```
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use regex::Regex;
+
+use super::{pop_reqs_helper, Config, CFG_FILENAME};
+use crate::{
+    dep_types::{self, Req},
+    util,
+};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Workspace {
+    /// The folder with the `pyproject.toml` that lists the members.
+    pub root: PathBuf,
+    pub members: Vec<Member>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Member {
+    /// The project's name, or its folder's name if it doesn't have one.
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl Workspace {
+    /// Find the workspace `dir` is in, by looking for a `pyproject.toml` with a
+    /// `[tool.pyflow.workspace]` table in it, or its parents. A project under the root that
+    /// isn't a member isn't in it.
+    pub fn find(dir: &Path) -> Option<Self> {
+        let ws = dir.ancestors().find_map(|d| {
+            let patterns = member_patterns(&d.join(CFG_FILENAME))?;
+            Some(Self {
+                root: d.to_owned(),
+                members: expand(d, &patterns)
+                    .into_iter()
+                    .map(|path| Member {
+                        name: member_name(&path),
+                        path,
+                    })
+                    .collect(),
+            })
+        })?;
+
+        let project = dir
+            .ancestors()
+            .find(|d| d.join(CFG_FILENAME).is_file())
+            .unwrap_or(dir);
+        if project == ws.root || ws.member_for(project).is_some_and(|m| m.path == project) {
+            Some(ws)
+        } else {
+            None
+        }
+    }
+
+    /// The member `dir` is in, if any.
+    pub fn member_for(&self, dir: &Path) -> Option<&Member> {
+        self.members.iter().find(|m| dir.starts_with(&m.path))
+    }
+
+    /// A member, by its project name, or its folder's name.
+    pub fn member(&self, name: &str) -> Option<&Member> {
+        self.members.iter().find(|m| {
+            util::compare_names(&m.name, name) || m.path.file_name().is_some_and(|f| f == name)
+        })
+    }
+
+    /// Make requirements on other members path requirements, relative to `project_path`, so
+    /// members always use each other's working copies.
+    pub fn link_members(&self, project_path: &Path, reqs: &mut [Req]) {
+        for req in reqs
+            .iter_mut()
+            .filter(|r| r.path.is_none() && r.git.is_none())
+        {
+            if let Some(member) = self
+                .members
+                .iter()
+                .find(|m| m.path != project_path && util::compare_names(&m.name, &req.name))
+            {
+                let path = relative_path(project_path, &member.path);
+                req.path = Some(path.to_string_lossy().replace('\\', "/"));
+                req.constraints.clear();
+            }
+        }
+    }
+
+    /// The requirements of the root and every member, resolved together into the workspace's
+    /// lock and environment. `reqs` and `dev_reqs` are used for the project at `project_path`,
+    /// instead of what's in its `pyproject.toml`. Path requirements are made relative to
+    /// `project_path`. A package needed by several projects gets all their constraints; aborts,
+    /// naming the projects, if those can't all be met.
+    pub fn combined_reqs(
+        &self,
+        project_path: &Path,
+        reqs: &[Req],
+        dev_reqs: &[Req],
+    ) -> (Vec<Req>, Vec<Req>) {
+        let projects = std::iter::once((self.root.as_path(), "the workspace root")).chain(
+            self.members
+                .iter()
+                .map(|m| (m.path.as_path(), m.name.as_str())),
+        );
+
+        let mut combined: Vec<Req> = vec![];
+        let mut combined_dev: Vec<Req> = vec![];
+        // Which projects need each package, and how, for error messages.
+        let mut owners: HashMap<String, Vec<String>> = HashMap::new();
+
+        for (path, label) in projects {
+            let (mut own, mut own_dev) = if path == project_path {
+                (reqs.to_vec(), dev_reqs.to_vec())
+            } else {
+                match Config::from_file(&path.join(CFG_FILENAME)) {
+                    Some(cfg) => (cfg.reqs, cfg.dev_reqs),
+                    None => continue,
+                }
+            };
+            self.link_members(path, &mut own);
+            self.link_members(path, &mut own_dev);
+
+            for (mut list, dev) in [(own, false), (own_dev, true)] {
+                for req in list.iter_mut() {
+                    if let Some(p) = &req.path {
+                        let absolute = util::normalize_path(&path.join(p));
+                        let relative = relative_path(project_path, &absolute);
+                        req.path = Some(relative.to_string_lossy().replace('\\', "/"));
+                    }
+                }
+                // `reqs` and `dev_reqs` have these already, from `get_config`. The helper reads
+                // paths relative to the working directory.
+                if path != project_path {
+                    let located: Vec<Req> = list
+                        .iter()
+                        .cloned()
+                        .map(|mut r| {
+                            r.path = r
+                                .path
+                                .map(|p| project_path.join(p).to_string_lossy().into_owned());
+                            r
+                        })
+                        .collect();
+                    let mut sub_reqs = pop_reqs_helper(&located, dev);
+                    list.append(&mut sub_reqs);
+                }
+
+                let target = if dev {
+                    &mut combined_dev
+                } else {
+                    &mut combined
+                };
+                for req in list {
+                    let constraints: Vec<String> = req
+                        .constraints
+                        .iter()
+                        .map(|c| c.to_string2(false, false))
+                        .collect();
+                    owners
+                        .entry(util::standardize_name(&req.name))
+                        .or_default()
+                        .push(if constraints.is_empty() {
+                            label.to_owned()
+                        } else {
+                            format!("{} ({})", label, constraints.join(", "))
+                        });
+                    merge(target, req);
+                }
+            }
+        }
+
+        // Installed as a main dependency already, but its constraints still apply.
+        for req in std::mem::take(&mut combined_dev) {
+            if combined
+                .iter()
+                .any(|r| util::compare_names(&r.name, &req.name))
+            {
+                merge(&mut combined, req);
+            } else {
+                combined_dev.push(req);
+            }
+        }
+
+        for req in combined.iter().chain(combined_dev.iter()) {
+            if !req.constraints.is_empty()
+                && req.path.is_none()
+                && dep_types::intersection_many(&req.constraints).is_empty()
+            {
+                util::abort(&format!(
+                    "Workspace projects need incompatible versions of {}: {}",
+                    req.name,
+                    owners[&util::standardize_name(&req.name)].join("; ")
+                ))
+            }
+        }
+        (combined, combined_dev)
+    }
+}
+
+/// Add `req` to `reqs`, or its constraints and extras to the existing requirement for the same
+/// package.
+fn merge(reqs: &mut Vec<Req>, req: Req) {
+    match reqs
+        .iter_mut()
+        .find(|r| util::compare_names(&r.name, &req.name))
+    {
+        Some(existing) => {
+            // A path requirement is a workspace member; its version is whatever's there.
+            if existing.path.is_some() || req.path.is_some() {
+                existing.path = existing.path.take().or(req.path);
+                existing.constraints.clear();
+                return;
+            }
+            for c in req.constraints {
+                if !existing.constraints.contains(&c) {
+                    existing.constraints.push(c);
+                }
+            }
+            if let Some(extras) = req.install_with_extras {
+                let all = existing.install_with_extras.get_or_insert_with(Vec::new);
+                for extra in extras {
+                    if !all.contains(&extra) {
+                        all.push(extra);
+                    }
+                }
+            }
+        }
+        None => reqs.push(req),
+    }
+}
+
+/// `members` from the `[tool.pyflow.workspace]` table in a `pyproject.toml`, if it has one.
+fn member_patterns(cfg_path: &Path) -> Option<Vec<String>> {
+    let data = util::loaded::read_to_string(cfg_path).ok()?;
+    let table = data.parse::<toml::Value>().ok()?;
+    let workspace = table.get("tool")?.get("pyflow")?.get("workspace")?;
+    Some(
+        workspace
+            .get("members")
+            .and_then(|m| m.as_array())
+            .map(|members| {
+                members
+                    .iter()
+                    .filter_map(|m| m.as_str().map(str::to_owned))
+                    .collect()
+            })
+            .unwrap_or_default(),
+    )
+}
+
+/// Folders under `root` matching `patterns`, which contain a `pyproject.toml`. Each part of a
+/// pattern may contain `*` and `?` wildcards, eg `libs/*`.
+fn expand(root: &Path, patterns: &[String]) -> Vec<PathBuf> {
+    let mut result = vec![];
+    for pattern in patterns {
+        let mut dirs = vec![root.to_owned()];
+        for part in pattern.split('/').filter(|p| !p.is_empty() && *p != ".") {
+            dirs = if part.contains('*') || part.contains('?') {
+                let re = wildcard_regex(part);
+                dirs.iter()
+                    .filter_map(|d| fs::read_dir(d).ok())
+                    .flat_map(|entries| entries.flatten())
+                    .filter(|e| e.path().is_dir())
+                    .filter(|e| re.is_match(&e.file_name().to_string_lossy()))
+                    .map(|e| e.path())
+                    .collect()
+            } else {
+                dirs.iter().map(|d| d.join(part)).collect()
+            };
+        }
+        for dir in dirs {
+            if dir.join(CFG_FILENAME).exists() && dir != root && !result.contains(&dir) {
+                result.push(dir);
+            }
+        }
+    }
+    result.sort();
+    result
+}
+
+fn wildcard_regex(pattern: &str) -> Regex {
+    let escaped = regex::escape(pattern)
+        .replace(r"\*", ".*")
+        .replace(r"\?", ".");
+    Regex::new(&format!("^{}$", escaped)).unwrap()
+}
+
+fn member_name(path: &Path) -> String {
+    Config::from_file(&path.join(CFG_FILENAME))
+        .and_then(|c| c.name)
+        .unwrap_or_else(|| {
+            path.file_name()
+                .map(|f| f.to_string_lossy().to_string())
+                .unwrap_or_default()
+        })
+}
+
+/// The path from `from` to `to`, eg `../core` from `libs/client` to `libs/core`. Both must be
+/// absolute.
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut result = PathBuf::new();
+    for _ in common..from.len() {
+        result.push("..");
+    }
+    for part in &to[common..] {
+        result.push(part);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::temp_dir::TempDir;
+
+    fn project(path: &Path, contents: &str) {
+        fs::create_dir_all(path).unwrap();
+        fs::write(path.join(CFG_FILENAME), contents).unwrap();
+    }
+
+    #[test]
+    fn members() {
+        let root = TempDir::new("workspace-members");
+        project(
+            &root,
+            "[tool.pyflow.workspace]\nmembers = [\"libs/*\", \"services/api\"]\n",
+        );
+        project(&root.join("libs/core"), "[tool.pyflow]\nname = \"core\"\n");
+        project(
+            &root.join("libs/client"),
+            "[tool.pyflow]\nname = \"acme-client\"\n",
+        );
+        fs::create_dir_all(root.join("libs/notes")).unwrap();
+        project(&root.join("services/api"), "[tool.pyflow]\n");
+
+        let ws = Workspace::find(&root.join("services/api/src")).unwrap();
+        assert_eq!(ws.root, *root);
+        let names: Vec<&str> = ws.members.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["acme-client", "core", "api"]);
+
+        let here = ws.member_for(&root.join("libs/core/core")).unwrap();
+        assert_eq!(here.name, "core");
+        // Folders in the root are part of it, but other projects aren't.
+        assert!(Workspace::find(&root.join("libs/notes")).is_some());
+        project(&root.join("tools/lint"), "[tool.pyflow]\n");
+        assert_eq!(Workspace::find(&root.join("tools/lint/src")), None);
+        assert_eq!(ws.member("client").unwrap().name, "acme-client");
+        assert_eq!(ws.member("api").unwrap().path, root.join("services/api"));
+
+        let mut reqs = vec![
+            Req::from_pip_str("core>=1.0").unwrap(),
+            Req::from_pip_str("requests").unwrap(),
+        ];
+        ws.link_members(&root.join("services/api"), &mut reqs);
+        assert_eq!(reqs[0].path.as_deref(), Some("../../libs/core"));
+        assert!(reqs[0].constraints.is_empty());
+        assert_eq!(reqs[1].path, None);
+    }
+
+    #[test]
+    fn combines_reqs() {
+        let root = TempDir::new("workspace-combined");
+        project(&root, "[tool.pyflow.workspace]\nmembers = [\"libs/*\"]\n");
+        project(
+            &root.join("libs/client"),
+            "[tool.pyflow]\nname = \"client\"\n\n[tool.pyflow.dependencies]\n\
+             requests = \">=2.0\"\ncore = \"^1.0\"\n\n[tool.pyflow.dev-dependencies]\n\
+             pytest = \"*\"\n",
+        );
+        project(
+            &root.join("libs/core"),
+            "[tool.pyflow]\nname = \"core\"\n\n[tool.pyflow.dependencies]\n\
+             requests = \"<3\"\n\n[tool.pyflow.dev-dependencies]\nrequests = \"*\"\n",
+        );
+
+        let ws = Workspace::find(&root).unwrap();
+        let (reqs, dev_reqs) = ws.combined_reqs(&root.join("libs/client"), &[], &[]);
+        let names: Vec<&str> = reqs.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["requests"]);
+        // `core`'s `<3`, and its dev `*`.
+        assert_eq!(reqs[0].constraints.len(), 2);
+        assert!(dev_reqs.is_empty());
+
+        let (reqs, dev_reqs) = ws.combined_reqs(&root, &[], &[]);
+        assert_eq!(reqs.len(), 2);
+        let find = |name: &str| reqs.iter().find(|r| r.name == name).unwrap();
+        assert_eq!(find("requests").constraints.len(), 3);
+        assert_eq!(find("core").path.as_deref(), Some("libs/core"));
+        assert!(find("core").constraints.is_empty());
+        // `requests` is a main dependency of `core`.
+        let dev_names: Vec<&str> = dev_reqs.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(dev_names, vec!["pytest"]);
+    }
+
+    #[test]
+    fn dev_constraints_apply_to_main() {
+        let root = TempDir::new("workspace-dev-main");
+        project(&root, "[tool.pyflow.workspace]\nmembers = [\"libs/*\"]\n");
+        project(
+            &root.join("libs/client"),
+            "[tool.pyflow]\nname = \"client\"\n\n[tool.pyflow.dependencies]\n\
+             requests = \">=2.0\"\n",
+        );
+        project(
+            &root.join("libs/core"),
+            "[tool.pyflow]\nname = \"core\"\n\n[tool.pyflow.dev-dependencies]\n\
+             requests = \"<2.5\"\n",
+        );
+
+        let ws = Workspace::find(&root).unwrap();
+        let (reqs, dev_reqs) = ws.combined_reqs(&root, &[], &[]);
+        assert_eq!(reqs.len(), 1);
+        assert_eq!(reqs[0].constraints.len(), 2);
+        assert!(dev_reqs.is_empty());
+    }
+
+    #[test]
+    fn relative_paths() {
+        assert_eq!(
+            relative_path(Path::new("/ws/libs/client"), Path::new("/ws/libs/core")),
+            PathBuf::from("../core")
+        );
+        assert_eq!(
+            relative_path(Path::new("/ws"), Path::new("/ws/services/api")),
+            PathBuf::from("services/api")
+        );
+    }
+}
@@ -1,13 +1,23 @@
 use crate::dep_resolution::res;
-use crate::dep_types::{Constraint, Extras, Lock, Req, ReqType, Version};
-use crate::util;
+use crate::dep_types::{Constraint, Extras, Req, ReqType, Version};
+use crate::{py_versions, util};
 use regex::Regex;
+use ring::digest;
+use serde::Deserialize;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use crate::commands;
-use crate::dep_parser::parse_version;
 use std::str::FromStr;
+use std::sync::LazyLock;
+
+/// Marks a cached script environment as fully installed; holds the requirements it was built
+/// for. Environments without it are rebuilt.
+pub const ENV_READY_FILE: &str = ".ready";
+/// The scripts that have used a cached environment, one path per line.
+pub const ENV_SCRIPTS_FILE: &str = "scripts.txt";
+/// Locked while an environment is being built, so concurrent runs of a script don't clash.
+pub const ENV_LOCK_FILE: &str = ".lock";
 
 /// Run a standalone script file, with package management
 /// TODO: We're using script name as unique identifier; address this in the future,
@@ -19,8 +29,7 @@ pub fn run_script(
     args: &[String],
     pyflow_dir: &Path,
 ) {
-    #[cfg(debug_assertions)]
-    eprintln!("Run script args: {:?}", args);
+    log::debug!("Run script args: {:?}", args);
 
     // TODO: DRY with run_cli_tool and subcommand::Install
     let filename = if let Some(arg) = args.first() {
@@ -31,65 +40,122 @@ pub fn run_script(
         );
     };
 
-    // TODO: Consider a metadata file, but for now, we'll use folders
-    //    let scripts_data_path = script_env_path.join("scripts.toml");
-
-    let env_path = util::canon_join(script_env_path, filename);
-    if !env_path.exists() {
-        fs::create_dir_all(&env_path).expect("Problem creating environment for the script");
+    // Each script's folder holds the Python version we picked for it; its environment is
+    // shared by every script with the same requirements.
+    let script_path = util::canon_join(script_env_path, filename);
+    if !script_path.exists() {
+        fs::create_dir_all(&script_path).expect("Problem creating environment for the script");
     }
 
     // Write the version we found to a file.
     let cfg_vers;
-    let py_vers_path = env_path.join("py_vers.txt");
+    let py_vers_path = script_path.join("py_vers.txt");
+    let stored_vers = fs::read_to_string(&py_vers_path)
+        .ok()
+        .and_then(|v| Version::from_str(v.trim()).ok());
 
     let script = fs::read_to_string(filename).expect("Problem opening the Python script file.");
-    let dunder_python_vers = check_for_specified_py_vers(&script);
+    // PEP 723 metadata takes precedence over `__python__` and `__requires__`.
+    let metadata = parse_inline_metadata(&script);
+    let python_pin = match metadata.as_ref().and_then(|m| m.requires_python.as_ref()) {
+        Some(spec) => Some(parse_python_pin(spec, "requires-python")),
+        None => check_for_specified_py_vers(&script),
+    };
 
-    if let Some(dpv) = dunder_python_vers {
-        cfg_vers = dpv;
+    if let Some(pin) = &python_pin {
+        // Reuse the version we picked last time, unless the pin's changed to exclude it.
+        cfg_vers = match stored_vers.filter(|v| py_versions::satisfies(v, pin)) {
+            Some(v) => v,
+            None => select_pinned_version(pin, pyflow_dir),
+        };
         create_or_update_version_file(&py_vers_path, &cfg_vers);
-    } else if py_vers_path.exists() {
-        cfg_vers = Version::from_str(
-            &fs::read_to_string(py_vers_path)
-                .expect("Problem reading Python version for this script")
-                .replace('\n', ""),
-        )
-        .expect("Problem parsing version from file");
+    } else if let Some(v) = stored_vers {
+        cfg_vers = v;
     } else {
         cfg_vers = util::prompts::py_vers();
         create_or_update_version_file(&py_vers_path, &cfg_vers);
     }
 
+    let deps = match metadata {
+        Some(m) => parse_script_reqs(&m.dependencies),
+        None => parse_script_reqs(&find_deps_from_script(&script)),
+    };
+    let requirements = normalize_requirements(&deps);
+
+    let env_path = envs_path(script_env_path).join(env_hash(&requirements, &cfg_vers));
+    if !env_path.exists() {
+        fs::create_dir_all(&env_path).expect("Problem creating environment for the script");
+    }
+    let ready_path = env_path.join(ENV_READY_FILE);
+    // Only lock while building; a ready environment is never modified.
+    let env_lock = if ready_path.exists() {
+        None
+    } else {
+        Some(EnvLock::acquire(&env_path))
+    };
+
     // todo DRY
     let pypackages_dir = env_path.join("__pypackages__");
-    let (vers_path, py_vers) =
-        util::find_or_create_venv(&cfg_vers, &pypackages_dir, pyflow_dir, dep_cache_path);
+    let (vers_path, py_vers) = util::find_or_create_venv(
+        &cfg_vers,
+        &pypackages_dir,
+        pyflow_dir,
+        dep_cache_path,
+        None,
+        Default::default(),
+        python_pin.as_deref().unwrap_or(&[]),
+        Default::default(),
+    );
 
     let bin_path = util::find_bin_path(&vers_path);
     let lib_path = vers_path.join("lib");
-    let script_path = vers_path.join("bin");
-    let lock_path = env_path.join("pyproject.lock");
+    let entry_pt_path = vers_path.join("bin");
+    let lock_path = env_path.join("pyflow.lock");
 
     let paths = util::Paths {
         bin: bin_path,
         lib: lib_path,
-        entry_pt: script_path,
+        entry_pt: entry_pt_path,
         cache: dep_cache_path.to_owned(),
+        mode: crate::pyproject::EnvMode::Pypackages,
     };
 
-    let deps = find_deps_from_script(&script);
+    // Another run may have built it while we waited for the lock.
+    if !ready_path.exists() {
+        install_script_deps(&paths, &deps, os, &py_vers, &lock_path);
+        fs::write(&ready_path, requirements.join("\n"))
+            .expect("Problem marking the script environment as ready");
+    }
+    record_script(&env_path, filename);
+    drop(env_lock);
 
-    let lock = match util::read_lock(&lock_path) {
-        Ok(l) => l,
-        Err(_) => Lock::default(),
-    };
+    commands::exit_on_failure(
+        commands::run_python(&paths.bin, &[paths.lib], args),
+        "Problem running this script",
+    );
+}
 
-    let lockpacks = lock.package.unwrap_or_else(Vec::new);
+/// Resolve, lock, and install a script's dependencies into its environment.
+fn install_script_deps(
+    paths: &util::Paths,
+    deps: &[Req],
+    os: util::Os,
+    py_vers: &Version,
+    lock_path: &Path,
+) {
+    let lock = util::read_lock(lock_path).unwrap_or_default();
+
+    let lockpacks = lock.package.unwrap_or_default();
 
     let reqs: Vec<Req> = deps
         .iter()
-        .map(|name| {
+        .map(|req| {
+            // Constraints from `__requires__` are resolved as-is.
+            if !req.constraints.is_empty() {
+                return req.clone();
+            }
+
+            let name = &req.name;
             let (fmtd_name, version) = if let Some(lp) = lockpacks
                 .iter()
                 .find(|lp| util::compare_names(&lp.name, name))
@@ -111,24 +177,176 @@ pub fn run_script(
                 (vinfo.0, vinfo.1)
             };
 
-            Req::new(fmtd_name, vec![Constraint::new(ReqType::Caret, version)])
+            let mut r = Req::new(fmtd_name, vec![Constraint::new(ReqType::Caret, version)]);
+            r.install_with_extras = req.install_with_extras.clone();
+            r
         })
         .collect();
 
     util::deps::sync(
-        &paths,
+        paths,
         &lockpacks,
         &reqs,
         &[],
+        &Default::default(),
         &[],
         os,
-        &py_vers,
-        &lock_path,
+        py_vers,
+        lock_path,
+        false,
+        &Default::default(),
     );
+}
+
+/// Where environments shared between scripts are kept, named by `env_hash`.
+pub fn envs_path(script_env_path: &Path) -> PathBuf {
+    script_env_path.join("envs")
+}
+
+/// Parse `__requires__` entries as requirements, eg `requests>=2.31`, or
+/// `pandas[excel]==2.1.*`.
+fn parse_script_reqs(deps: &[String]) -> Vec<Req> {
+    deps.iter()
+        .map(|d| {
+            Req::from_cli_str(d).unwrap_or_else(|_| {
+                util::abort(&format!(
+                    "Problem parsing \"{}\" in `__requires__`. Entries should look like \
+                     \"requests\", \"requests>=2.31\", or \"pandas[excel]==2.1.*\"",
+                    d
+                ))
+            })
+        })
+        .collect()
+}
+
+/// A requirement in a standard form, so equivalent `__requires__` entries share an environment,
+/// and changing a constraint doesn't.
+fn normalize_requirement(req: &Req) -> String {
+    let mut result = util::standardize_name(&req.name);
+    if let Some(extras) = &req.install_with_extras {
+        let mut extras: Vec<String> = extras.iter().map(|e| util::standardize_name(e)).collect();
+        extras.sort();
+        result.push_str(&format!("[{}]", extras.join(",")));
+    }
+    let constraints: Vec<String> = req.constraints.iter().map(|c| c.to_string()).collect();
+    result.push_str(&constraints.join(","));
+    // Markers are rare in scripts; include them as parsed.
+    if req.extra.is_some() || req.sys_platform.is_some() || req.python_version.is_some() {
+        result.push_str(&format!(
+            "; {:?} {:?} {:?}",
+            req.extra, req.sys_platform, req.python_version
+        ));
+    }
+    result
+}
+
+/// Normalize, and sort, a script's requirements.
+fn normalize_requirements(deps: &[Req]) -> Vec<String> {
+    let mut result: Vec<String> = deps.iter().map(normalize_requirement).collect();
+    result.sort();
+    result.dedup();
+    result
+}
+
+/// Identify an environment by its requirements, and Python version.
+fn env_hash(requirements: &[String], py_vers: &Version) -> String {
+    let data = format!("python={}\n{}", py_vers, requirements.join("\n"));
+    let hash = digest::digest(&digest::SHA256, data.as_bytes());
+    data_encoding::HEXLOWER.encode(hash.as_ref())[..16].to_owned()
+}
 
-    if commands::run_python(&paths.bin, &[paths.lib], args).is_err() {
-        util::abort("Problem running this script")
+/// Note that a script uses this environment, for `pyflow script-envs`.
+fn record_script(env_path: &Path, filename: &str) {
+    let script = match fs::canonicalize(filename) {
+        Ok(p) => p.to_string_lossy().to_string(),
+        Err(_) => return,
     };
+    let path = env_path.join(ENV_SCRIPTS_FILE);
+    let mut scripts: Vec<String> = fs::read_to_string(&path)
+        .unwrap_or_default()
+        .lines()
+        .map(str::to_owned)
+        .collect();
+    if !scripts.contains(&script) {
+        scripts.push(script);
+        fs::write(&path, scripts.join("\n")).unwrap_or(());
+    }
+}
+
+/// An OS lock on the environment's lock file, held while building it. The OS releases it
+/// when the process exits, even if it aborts.
+struct EnvLock {
+    _file: fs::File,
+}
+
+impl EnvLock {
+    /// Wait until no other process is building this environment, then lock it.
+    fn acquire(env_path: &Path) -> Self {
+        let file = open_lock_file(env_path);
+        if !lock_file(&file, false) {
+            log::info!("Waiting for another pyflow process to set up this script's environment");
+            if !lock_file(&file, true) {
+                util::abort("Problem locking the script environment");
+            }
+        }
+        Self { _file: file }
+    }
+}
+
+/// Whether another process is building the environment at `env_path`.
+pub fn env_locked(env_path: &Path) -> bool {
+    if !env_path.join(ENV_LOCK_FILE).exists() {
+        return false;
+    }
+    // Our own lock on this handle is released when it's closed.
+    !lock_file(&open_lock_file(env_path), false)
+}
+
+fn open_lock_file(env_path: &Path) -> fs::File {
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(env_path.join(ENV_LOCK_FILE))
+        .expect("Problem opening the script environment's lock file")
+}
+
+/// Take an exclusive lock on `file`; if `wait`, block until it's free.
+#[cfg(unix)]
+fn lock_file(file: &fs::File, wait: bool) -> bool {
+    use std::os::unix::io::AsRawFd;
+
+    let flags = if wait {
+        libc::LOCK_EX
+    } else {
+        libc::LOCK_EX | libc::LOCK_NB
+    };
+    unsafe { libc::flock(file.as_raw_fd(), flags) == 0 }
+}
+
+#[cfg(windows)]
+fn lock_file(file: &fs::File, wait: bool) -> bool {
+    use std::os::windows::io::AsRawHandle;
+    use winapi::um::fileapi::LockFileEx;
+    use winapi::um::minwinbase::{LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY, OVERLAPPED};
+    use winapi::um::winnt::HANDLE;
+
+    let flags = if wait {
+        LOCKFILE_EXCLUSIVE_LOCK
+    } else {
+        LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY
+    };
+    unsafe {
+        let mut overlapped: OVERLAPPED = std::mem::zeroed();
+        LockFileEx(
+            file.as_raw_handle() as HANDLE,
+            flags,
+            0,
+            !0,
+            !0,
+            &mut overlapped,
+        ) != 0
+    }
 }
 
 /// Create the `py_vers.txt` if it doesn't exist, and then store `cfg_vers` within.
@@ -140,51 +358,164 @@ fn create_or_update_version_file(py_vers_path: &Path, cfg_vers: &Version) {
     fs::write(py_vers_path, cfg_vers.to_string()).expect("Problem writing Python version file.");
 }
 
+/// Eg `__python__ = "3.11"`.
+static PYTHON_PIN_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"^__python__\s*=\s*["'](.*?)["']\s*$"#).unwrap());
+
 /// Find a script's Python version specificion by looking for the `__python__` variable.
 ///
-/// If a `__python__` variable is identified, the version must have major, minor, and
-/// patch components to be considered valid. Otherwise, there is still some ambiguity in
-/// which version to use and an error is thrown.
-fn check_for_specified_py_vers(script: &str) -> Option<Version> {
-    let re = Regex::new(r#"^__python__\s*=\s*"(.*?)"$"#).unwrap();
-
+/// This may be an exact version, eg `__python__ = "3.9.1"`, or constraints, eg
+/// `__python__ = ">=3.8,<3.12"`. A version without a patch, eg `"3.11"`, matches any patch.
+fn check_for_specified_py_vers(script: &str) -> Option<Vec<Constraint>> {
     for line in script.lines() {
-        if let Some(capture) = re.captures(line) {
-            let specification = capture.get(1).unwrap().as_str();
-            let (_, version) = parse_version(specification).unwrap();
-            match version {
-                Version {
-                    major: Some(_),
-                    minor: Some(_),
-                    patch: Some(_),
-                    extra_num: None,
-                    modifier: None,
-                    ..
-                } => return Some(version),
-                _ => {
-                    util::abort(
-                        "Problem parsing `__python__` variable. Make sure you've included \
-                        major, minor, and patch specifications (eg `__python__ = X.Y.Z`)",
-                    );
-                }
-            }
+        if let Some(capture) = PYTHON_PIN_RE.captures(line) {
+            return Some(parse_python_pin(
+                capture.get(1).unwrap().as_str(),
+                "__python__",
+            ));
         }
     }
     None
 }
 
-/// Find a script's dependencies from a variable: `__requires__ = [dep1, dep2]`
+/// Parse a script's Python version specification, from `key`. A version without a patch
+/// matches any patch.
+fn parse_python_pin(specification: &str, key: &str) -> Vec<Constraint> {
+    let mut constrs = match Constraint::from_str_multiple(specification) {
+        Ok(c) => c,
+        Err(_) => util::abort(&format!(
+            "Problem parsing `{} = \"{}\"`. It should look like `{} = \"3.9.1\"`, or \
+             `{} = \">=3.11\"`",
+            key, specification, key, key
+        )),
+    };
+    for constr in &mut constrs {
+        if constr.type_ == ReqType::Exact && constr.version.patch.is_none() {
+            constr.version.star = true;
+        }
+    }
+    constrs
+}
+
+/// [PEP 723](https://peps.python.org/pep-0723/) inline script metadata, as used by pipx and uv.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+struct InlineMetadata {
+    #[serde(default)]
+    dependencies: Vec<String>,
+    requires_python: Option<String>,
+}
+
+/// Find and parse a `# /// script` block: TOML, with each line commented. The block ends at the
+/// last `# ///` before the comments do.
+fn parse_inline_metadata(script: &str) -> Option<InlineMetadata> {
+    let lines: Vec<&str> = script.lines().map(|l| l.trim_end_matches('\r')).collect();
+    let start = lines.iter().position(|l| l.trim_end() == "# /// script")?;
+
+    let comments: Vec<&str> = lines[start + 1..]
+        .iter()
+        .take_while(|l| l.starts_with('#'))
+        .copied()
+        .collect();
+    let end = comments
+        .iter()
+        .rposition(|l| l.trim_end() == "# ///")
+        .unwrap_or_else(|| {
+            util::abort("The `# /// script` block in this script isn't closed with `# ///`")
+        });
+
+    if lines[start + 1 + end..]
+        .iter()
+        .any(|l| l.trim_end() == "# /// script")
+    {
+        util::abort("This script has more than one `# /// script` block")
+    }
+
+    let content: Vec<&str> = comments[..end]
+        .iter()
+        .map(|l| {
+            let l = &l[1..];
+            l.strip_prefix(' ').unwrap_or(l)
+        })
+        .collect();
+
+    match toml::from_str(&content.join("\n")) {
+        Ok(m) => Some(m),
+        Err(e) => util::abort(&format!(
+            "Problem parsing the `# /// script` block in this script: {}",
+            e
+        )),
+    }
+}
+
+/// A script declares PEP 723 metadata, so can be run in script mode with `pyflow run`.
+pub fn has_inline_metadata(filename: &str) -> bool {
+    filename.ends_with(".py")
+        && fs::read_to_string(filename)
+            .is_ok_and(|s| s.lines().any(|l| l.trim_end() == "# /// script"))
+}
+
+/// Pick a Python version satisfying a script's `__python__` pin: the newest installed one, or
+/// failing that, the newest we can download.
+fn select_pinned_version(pin: &[Constraint], pyflow_dir: &Path) -> Version {
+    // An exact version; download it if we don't have it.
+    if let [c] = pin {
+        if c.type_ == ReqType::Exact && !c.version.star {
+            return c.version.clone();
+        }
+    }
+
+    let implementation = py_versions::Implementation::CPython;
+    if let Some(i) = py_versions::newest_satisfying(pyflow_dir, implementation, pin) {
+        return i.version;
+    }
+
+    let available = py_versions::available_cpython_versions();
+    if let Some(v) = available.iter().find(|v| py_versions::satisfies(v, pin)) {
+        return v.clone();
+    }
+
+    let mut installed: Vec<String> = py_versions::find_all_interpreters(pyflow_dir)
+        .iter()
+        .map(|i| i.version.to_string())
+        .collect();
+    installed.dedup();
+    util::abort(&format!(
+        "No Python satisfies this script's requirement of \"{}\".\nInstalled: {}\nAvailable to download: {}",
+        py_versions::requires_str(pin),
+        if installed.is_empty() {
+            "none".to_string()
+        } else {
+            installed.join(", ")
+        },
+        available
+            .iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    ))
+}
+
+/// The contents of a `__requires__ = [...]` list.
+static REQUIRES_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?ms)^__requires__\s*=\s*\[(.*?)\]$").unwrap());
+
+/// A single- or double-quoted string.
+static QUOTED_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#""([^"]*)"|'([^']*)'"#).unwrap());
+
+/// Find a script's dependencies from a variable: `__requires__ = [dep1, dep2]`. Each entry is
+/// a quoted requirement, which may contain commas, eg `"requests>=2.31,<3"`.
 fn find_deps_from_script(script: &str) -> Vec<String> {
     // TODO: Helper for this type of logic? We use it several times in the program.
-    let re = Regex::new(r"(?ms)^__requires__\s*=\s*\[(.*?)\]$").unwrap();
 
     let mut result = vec![];
 
-    if let Some(c) = re.captures(script) {
-        let deps_list = c.get(1).unwrap().as_str().to_owned();
-        result = deps_list
-            .split(',')
-            .map(|d| d.to_owned().replace([' ', '\'', '"', '\n'], ""))
+    if let Some(c) = REQUIRES_RE.captures(script) {
+        let deps_list = c.get(1).unwrap().as_str();
+        result = QUOTED_RE
+            .captures_iter(deps_list)
+            .filter_map(|caps| caps.get(1).or_else(|| caps.get(2)))
+            .map(|d| d.as_str().trim().to_owned())
             .filter(|d| !d.is_empty())
             .collect();
     }
@@ -206,9 +537,7 @@ mod tests {
                 print("Hello, world")
         "# };
 
-        let version: Option<Version> = None;
-
-        let expected = version;
+        let expected: Option<Vec<Constraint>> = None;
         let actual = check_for_specified_py_vers(script);
 
         assert_eq!(expected, actual);
@@ -223,21 +552,46 @@ mod tests {
                 print("Hello, world")
         "# };
 
-        let version: Option<Version> = Some(Version {
+        let version = Version {
             major: Some(3),
             minor: Some(9),
             patch: Some(1),
             extra_num: None,
             modifier: None,
             star: false,
-        });
+            local: None,
+        };
+
+        let expected = Some(vec![Constraint::new(ReqType::Exact, version)]);
+        let actual = check_for_specified_py_vers(script);
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn parse_python_version_with_constraints_specified() {
+        let script = indoc! { r#"
+            __python__ = ">=3.8,<3.12"
+            __requires__ = ["requests"]
+        "# };
 
-        let expected = version;
+        let expected = Some(vec![
+            Constraint::new(ReqType::Gte, Version::new_short(3, 8)),
+            Constraint::new(ReqType::Lt, Version::new_short(3, 12)),
+        ]);
         let actual = check_for_specified_py_vers(script);
 
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn python_version_without_patch_matches_any_patch() {
+        let pin = check_for_specified_py_vers(r#"__python__ = "3.11""#).unwrap();
+
+        assert!(py_versions::satisfies(&Version::new(3, 11, 8), &pin));
+        assert!(!py_versions::satisfies(&Version::new(3, 12, 0), &pin));
+    }
+
     #[test]
     fn parse_no_dependencies_with_no_requires() {
         let script = indoc! { r#"
@@ -346,4 +700,90 @@ mod tests {
 
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn parse_dependencies_with_constraints_and_extras() {
+        let script = indoc! { r#"
+            __requires__ = [
+                "requests>=2.31,<3",
+                'pandas[excel]==2.1.*',
+            ]
+        "# };
+
+        let expected: Vec<&str> = vec!["requests>=2.31,<3", "pandas[excel]==2.1.*"];
+        let actual = find_deps_from_script(script);
+
+        assert_eq!(expected, actual);
+
+        let reqs = parse_script_reqs(&actual);
+        assert_eq!(reqs[0].constraints.len(), 2);
+        assert_eq!(reqs[1].install_with_extras, Some(vec!["excel".to_string()]));
+    }
+
+    #[test]
+    fn equivalent_requirements_share_an_env() {
+        let reqs = |deps: &[&str]| {
+            normalize_requirements(&parse_script_reqs(
+                &deps.iter().map(|d| d.to_string()).collect::<Vec<String>>(),
+            ))
+        };
+        let a = reqs(&["Requests>=2.31", "python-dateutil"]);
+        let b = reqs(&["python_dateutil", "REQUESTS>=2.31"]);
+        let v = Version::new_short(3, 11);
+
+        assert_eq!(env_hash(&a, &v), env_hash(&b, &v));
+        assert_ne!(env_hash(&a, &v), env_hash(&a, &Version::new_short(3, 12)));
+        assert_ne!(
+            env_hash(&a, &v),
+            env_hash(&reqs(&["requests>=2.32", "python-dateutil"]), &v)
+        );
+    }
+
+    #[test]
+    fn parse_inline_script_metadata() {
+        let script = indoc! { r#"
+            # /// script
+            # requires-python = ">=3.11"
+            # dependencies = [
+            #   "requests<3",
+            #   "rich",
+            # ]
+            #
+            # [tool.uv]
+            # exclude-newer = "2024-01-01T00:00:00Z"
+            # ///
+
+            __requires__ = ["numpy"]
+        "# };
+
+        let expected = InlineMetadata {
+            dependencies: vec!["requests<3".into(), "rich".into()],
+            requires_python: Some(">=3.11".into()),
+        };
+
+        assert_eq!(parse_inline_metadata(script), Some(expected));
+    }
+
+    #[test]
+    fn parse_inline_script_metadata_missing() {
+        let script = indoc! { r#"
+            # /// pyproject
+            # ///
+            __requires__ = ["numpy"]
+        "# };
+
+        assert_eq!(parse_inline_metadata(script), None);
+    }
+
+    #[test]
+    fn env_lock_is_released_on_drop() {
+        let env = crate::util::temp_dir::TempDir::new("script-env-lock");
+        assert!(!env_locked(&env));
+
+        let lock = EnvLock::acquire(&env);
+        assert!(env_locked(&env));
+
+        drop(lock);
+        assert!(!env_locked(&env));
+    }
 }
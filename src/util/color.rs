@@ -0,0 +1,143 @@
+//! Whether to color output. `--color` sets a preference; with `auto`, the default, we color
+//! a stream only if it's a terminal, and `NO_COLOR` isn't set. Everything colored is written
+//! through here, so the choice is made the same way everywhere.
+
+use std::{env, io};
+
+use termcolor::{Buffer, BufferWriter, Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
+
+use crate::CliConfig;
+
+/// Parse the `--color` option.
+pub fn from_option(s: &str) -> ColorChoice {
+    match s {
+        "always" => ColorChoice::Always,
+        "ansi" => ColorChoice::AlwaysAnsi,
+        "never" => ColorChoice::Never,
+        _ => ColorChoice::Auto,
+    }
+}
+
+/// Settle `auto` for one stream. Forcing colors on, or off, ignores `NO_COLOR`.
+fn resolve(choice: ColorChoice, no_color: bool, is_tty: bool) -> ColorChoice {
+    match choice {
+        ColorChoice::Auto if no_color || !is_tty => ColorChoice::Never,
+        c => c,
+    }
+}
+
+/// https://no-color.org: set, and not empty.
+fn no_color_from_env() -> bool {
+    env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty())
+}
+
+fn choice_for(stream: atty::Stream) -> ColorChoice {
+    resolve(
+        CliConfig::current().color_choice,
+        no_color_from_env(),
+        atty::is(stream),
+    )
+}
+
+/// Where messages for people go: stdout, unless it's reserved for `--json` output.
+pub fn message_stream() -> StandardStream {
+    if CliConfig::current().json {
+        StandardStream::stderr(choice_for(atty::Stream::Stderr))
+    } else {
+        StandardStream::stdout(choice_for(atty::Stream::Stdout))
+    }
+}
+
+/// Where errors go, whatever the output mode.
+pub fn error_stream() -> StandardStream {
+    StandardStream::stderr(choice_for(atty::Stream::Stderr))
+}
+
+/// A buffer for building a colored string, eg a version, that's printed as part of a message.
+pub fn buffer() -> Buffer {
+    if CliConfig::current().json {
+        BufferWriter::stderr(choice_for(atty::Stream::Stderr)).buffer()
+    } else {
+        BufferWriter::stdout(choice_for(atty::Stream::Stdout)).buffer()
+    }
+}
+
+/// Write `message` in a color, then reset formatting. A writer with colors disabled gets the
+/// plain message.
+pub fn write_color(
+    w: &mut impl WriteColor,
+    message: &str,
+    color: Color,
+    newline: bool,
+) -> io::Result<()> {
+    w.set_color(ColorSpec::new().set_fg(Some(color)))?;
+    if newline {
+        writeln!(w, "{}", message)?;
+    } else {
+        write!(w, "{}", message)?;
+    }
+    w.reset()
+}
+
+/// Windows 10 terminals understand ANSI escapes, but only once virtual terminal processing is
+/// enabled for the console. Older consoles refuse, and termcolor falls back to the console API.
+#[cfg(windows)]
+pub fn enable_ansi() {
+    use winapi_util::console::Console;
+
+    for console in [Console::stdout(), Console::stderr()].iter_mut() {
+        if let Ok(c) = console {
+            c.set_virtual_terminal_processing(true).unwrap_or(());
+        }
+    }
+}
+
+#[cfg(not(windows))]
+pub fn enable_ansi() {}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest(
+        choice,
+        no_color,
+        is_tty,
+        expected,
+        case::tty(ColorChoice::Auto, false, true, ColorChoice::Auto),
+        case::piped(ColorChoice::Auto, false, false, ColorChoice::Never),
+        case::no_color(ColorChoice::Auto, true, true, ColorChoice::Never),
+        case::forced_on(ColorChoice::Always, true, false, ColorChoice::Always),
+        case::forced_ansi(ColorChoice::AlwaysAnsi, true, false, ColorChoice::AlwaysAnsi),
+        case::forced_off(ColorChoice::Never, false, true, ColorChoice::Never)
+    )]
+    fn resolving(choice: ColorChoice, no_color: bool, is_tty: bool, expected: ColorChoice) {
+        assert_eq!(resolve(choice, no_color, is_tty), expected);
+    }
+
+    #[test]
+    fn options() {
+        assert_eq!(from_option("always"), ColorChoice::Always);
+        assert_eq!(from_option("never"), ColorChoice::Never);
+        assert_eq!(from_option("auto"), ColorChoice::Auto);
+    }
+
+    #[test]
+    fn forced_output() {
+        let mut colored = Buffer::ansi();
+        write_color(&mut colored, "Installation complete", Color::Green, true).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(colored.as_slice()),
+            "\x1b[0m\x1b[32mInstallation complete\n\x1b[0m"
+        );
+
+        let mut plain = Buffer::no_color();
+        write_color(&mut plain, "Installation complete", Color::Green, true).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(plain.as_slice()),
+            "Installation complete\n"
+        );
+    }
+}
@@ -1,32 +1,80 @@
-use std::{collections::HashMap, path::Path, str::FromStr};
+use std::{collections::HashMap, path::Path, str::FromStr, time::Instant};
 
-use regex::Regex;
+use serde::Serialize;
 use termcolor::Color;
 
 use crate::{
     dep_resolution::res,
     dep_types::{Constraint, Lock, LockPackage, Package, Rename, Req, ReqType, Version},
-    install,
-    util::{self, abort},
+    install, py_versions,
+    pyproject::{self, Constraints, EnvMode, Hooks},
+    util::{
+        self, abort,
+        hooks::{self, Event},
+    },
     PackToInstall,
 };
 
+/// What `python -m venv`, and creating an environment, put in a `.venv`'s `site-packages`; we
+/// leave them there when syncing with `env-mode = "venv"`, unless the lock has another version.
+pub const VENV_PACKAGES: [&str; 3] = ["pip", "setuptools", "wheel"];
+
+/// A package installed or removed by a sync.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PackageChange {
+    pub name: String,
+    pub version: String,
+}
+
+/// A package whose installed version changed; downgrades included.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Upgrade {
+    pub name: String,
+    pub from: String,
+    pub to: String,
+}
+
+/// What a sync changed in the environment; the `--json` output of `install`, `add`, and
+/// `remove`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct SyncSummary {
+    pub added: Vec<PackageChange>,
+    pub removed: Vec<PackageChange>,
+    pub upgraded: Vec<Upgrade>,
+    /// Locked packages that were already installed, and unchanged, so were skipped.
+    #[serde(skip)]
+    pub up_to_date: usize,
+}
+
+impl SyncSummary {
+    /// Include the changes of a later sync.
+    pub fn extend(&mut self, other: Self) {
+        self.added.extend(other.added);
+        self.removed.extend(other.removed);
+        self.upgraded.extend(other.upgraded);
+        // Each sync checks every locked package, so the latest count covers the earlier ones.
+        self.up_to_date = other.up_to_date;
+    }
+}
+
 /// Function used by `Install` and `Uninstall` subcommands to syn dependencies with
-/// the config and lock files.
+/// the config and lock files. If `no_dev` is set, the lock still covers dev reqs, but only
+/// packages reachable from `reqs` are installed.
 #[allow(clippy::too_many_arguments)]
 pub fn sync(
     paths: &util::Paths,
     lockpacks: &[LockPackage],
     reqs: &[Req],
     dev_reqs: &[Req],
+    constraints: &Constraints,
     dont_uninstall: &[String],
     os: util::Os,
     py_vers: &Version,
     lock_path: &Path,
-) {
+    no_dev: bool,
+    hooks: &Hooks,
+) -> SyncSummary {
     let installed = util::find_installed(&paths.lib);
-    // We control the lock format, so this regex will always match
-    let dep_re = Regex::new(r"^(.*?)\s(.*)\s.*$").unwrap();
 
     // We don't need to resolve reqs that are already locked.
     let locked: Vec<Package> = lockpacks
@@ -34,7 +82,8 @@ pub fn sync(
         .map(|lp| {
             let mut deps = vec![];
             for dep in lp.dependencies.as_ref().unwrap_or(&vec![]) {
-                let caps = dep_re
+                // We control the lock format, so this regex will always match
+                let caps = util::patterns::LOCK_DEP_RE
                     .captures(dep)
                     .expect("Problem reading lock file dependencies");
                 let name = caps.get(1).unwrap().as_str().to_owned();
@@ -71,11 +120,19 @@ pub fn sync(
         combined_reqs.push(dev_req);
     }
 
-    let resolved = if let Ok(r) = res::resolve(&combined_reqs, &locked, os, py_vers) {
-        r
-    } else {
-        abort("Problem resolving dependencies")
-    };
+    let start = Instant::now();
+    let resolved =
+        if let Ok(r) = res::resolve(&combined_reqs, &constraints.pins, &locked, os, py_vers) {
+            r
+        } else {
+            abort("Problem resolving dependencies")
+        };
+    log::debug!(
+        "Resolved {} packages from {} requirements in {:.2?}",
+        resolved.len(),
+        combined_reqs.len(),
+        start.elapsed()
+    );
 
     // Now merge the existing lock packages with new ones from resolved packages.
     // We have a collection of requirements; attempt to merge them with the already-locked ones.
@@ -121,27 +178,66 @@ pub fn sync(
         });
     }
 
+    // Lets `pyflow check` tell if `pyproject.toml` changed since. We read it from disk, since
+    // `reqs` may have had git and path requirements replaced by their own requirements.
+    let metadata = lock_path
+        .parent()
+        .map(|dir| pyproject::lock_metadata(dir, &constraints.install_files))
+        .unwrap_or_default();
     let updated_lock = Lock {
-        //        metadata: Some(lock_metadata),
-        metadata: HashMap::new(), // TODO: Problem with toml conversion.
+        metadata,
         package: Some(updated_lock_packs.clone()),
     };
     if util::write_lock(lock_path, &updated_lock).is_err() {
         abort("Problem writing lock file");
     }
 
+    let to_sync = if no_dev {
+        reachable_from(&updated_lock_packs, reqs)
+    } else {
+        updated_lock_packs
+    };
+
     // Now that we've confirmed or modified the lock file, we're ready to sync installed
     // dependencies with it.
     sync_deps(
         paths,
-        &updated_lock_packs,
+        &to_sync,
         dont_uninstall,
         &installed,
         os,
         py_vers,
-    );
+        hooks,
+        &HashMap::new(),
+    )
 }
+
+/// Install exactly these packages, eg pinned by pip-compile, without resolving, and remove
+/// any others. Packages with entries in `hashes`, by standardized name, must have a release
+/// matching one of them.
+pub fn sync_exact(
+    paths: &util::Paths,
+    lock_packs: &[LockPackage],
+    hashes: &HashMap<String, Vec<String>>,
+    os: util::Os,
+    py_vers: &Version,
+    hooks: &Hooks,
+) -> SyncSummary {
+    let installed = util::find_installed(&paths.lib);
+    sync_deps(
+        paths,
+        lock_packs,
+        &[],
+        &installed,
+        os,
+        py_vers,
+        hooks,
+        hashes,
+    )
+}
+
 /// Install/uninstall deps as required from the passed list, and re-write the lock file.
+#[allow(clippy::too_many_arguments)]
 fn sync_deps(
     paths: &util::Paths,
     lock_packs: &[LockPackage],
@@ -149,7 +245,9 @@ fn sync_deps(
     installed: &[(String, Version, Vec<String>)],
     os: util::Os,
     python_vers: &Version,
-) {
+    hooks: &Hooks,
+    hashes: &HashMap<String, Vec<String>>,
+) -> SyncSummary {
     let packages: Vec<PackToInstall> = lock_packs
         .iter()
         .map(|lp| {
@@ -170,12 +268,35 @@ fn sync_deps(
         .map(|t| (t.0.clone(), t.1.clone()))
         .collect();
 
+    // Locked packages already installed, whose files haven't changed since; these are skipped
+    // without downloading, or extracting anything. Changed ones are reinstalled. Ones installed
+    // before we stamped packages, or by another tool, are kept if they match their `RECORD`.
+    let up_to_date: Vec<&(String, Version)> = installed
+        .iter()
+        .filter(|inst| {
+            packages
+                .iter()
+                .any(|(pack, _)| util::compare_names(&pack.0, &inst.0) && pack.1 == inst.1)
+        })
+        .filter(|inst| {
+            let intact = install::installed_intact(&inst.0, &inst.1, &paths.lib);
+            if !intact {
+                log::debug!(
+                    "{} {} changed since it was installed; installing it again",
+                    inst.0,
+                    inst.1
+                );
+            }
+            intact
+        })
+        .collect();
+
     // Filter by not-already-installed.
     let to_install: Vec<&PackToInstall> = packages
         .iter()
         .filter(|(pack, _)| {
             let mut contains = false;
-            for inst in &installed {
+            for inst in &up_to_date {
                 if util::compare_names(&pack.0, &inst.0) && pack.1 == inst.1 {
                     contains = true;
                     break;
@@ -188,22 +309,15 @@ fn sync_deps(
         })
         .collect();
 
-    // TODO: Once you include rename info in installed, you won't need to use the map logic here.
-    let packages_only: Vec<&(String, Version)> = packages.iter().map(|(p, _)| p).collect();
     let to_uninstall: Vec<&(String, Version)> = installed
         .iter()
         .filter(|inst| {
             // Don't standardize the name here; we need original capitalization to uninstall
             // metadata etc.
-            let inst = (inst.0.clone(), inst.1.clone());
-            let mut contains = false;
             // We can't just use the contains method, due to needing compare_names().
-            for pack in &packages_only {
-                if util::compare_names(&pack.0, &inst.0) && pack.1 == inst.1 {
-                    contains = true;
-                    break;
-                }
-            }
+            let mut contains = up_to_date
+                .iter()
+                .any(|pack| util::compare_names(&pack.0, &inst.0) && pack.1 == inst.1);
 
             for name in dont_uninstall {
                 if util::compare_names(name, &inst.0) {
@@ -211,57 +325,209 @@ fn sync_deps(
                     break;
                 }
             }
+            if is_venv_tool(paths.mode, &inst.0, &packages) {
+                contains = true;
+            }
 
             !contains
         })
         .collect();
 
+    let changed: Vec<String> = to_uninstall
+        .iter()
+        .map(|(name, version)| format!("{}=={}", name, version))
+        .chain(
+            to_install
+                .iter()
+                .map(|((name, version), _)| format!("{}=={}", name, version)),
+        )
+        .collect();
+    if changed.is_empty() {
+        return SyncSummary {
+            up_to_date: up_to_date.len(),
+            ..SyncSummary::default()
+        };
+    }
+
+    run_pre_install_hook(hooks, paths, &changed);
+
     for (name, version) in &to_uninstall {
         // TODO: Deal with renamed. Currently won't work correctly with them.
         install::uninstall(name, version, &paths.lib)
     }
 
-    for ((name, version), rename) in &to_install {
-        let data =
-            res::get_warehouse_release(name, version).expect("Problem getting warehouse data");
+    install_packs(paths, lock_packs, &to_install, os, python_vers, hashes);
 
-        let (best_release, package_type) =
-            util::find_best_release(&data, name, version, os, python_vers);
-
-        // Powershell  doesn't like emojis
-        // todo format literal issues, so repeating this whole statement.
-        #[cfg(target_os = "windows")]
-        util::print_color_(&format!("Installing {}", &name), Color::Cyan);
-        #[cfg(target_os = "linux")]
-        util::print_color_(&format!("⬇ Installing {}", &name), Color::Cyan);
-        #[cfg(target_os = "macos")]
-        util::print_color_(&format!("⬇ Installing {}", &name), Color::Cyan);
-        println!(" {} ...", &version.to_string_color());
-
-        if install::download_and_install_package(
-            name,
-            version,
-            &best_release.url,
-            &best_release.filename,
-            &best_release.digests.sha256,
-            paths,
-            package_type,
-            rename,
-        )
-        .is_err()
+    run_post_install_hook(hooks, paths, &changed);
+
+    SyncSummary {
+        up_to_date: up_to_date.len(),
+        ..summarize(&to_uninstall, &to_install)
+    }
+}
+
+/// With `env-mode = "venv"`, packages share `site-packages` with the venv's own tools; these are
+/// kept, unless the lock has them at another version.
+fn is_venv_tool(mode: EnvMode, name: &str, packages: &[PackToInstall]) -> bool {
+    mode == EnvMode::Venv
+        && VENV_PACKAGES.iter().any(|n| util::compare_names(n, name))
+        && !packages
+            .iter()
+            .any(|(pack, _)| util::compare_names(&pack.0, name))
+}
+
+/// Pair up removals and installs of the same package as upgrades.
+fn summarize(to_uninstall: &[&(String, Version)], to_install: &[&PackToInstall]) -> SyncSummary {
+    let mut result = SyncSummary::default();
+    for ((name, version), _) in to_install {
+        match to_uninstall
+            .iter()
+            .find(|(n, _)| util::compare_names(n, name))
+        {
+            Some((_, old)) => result.upgraded.push(Upgrade {
+                name: name.clone(),
+                from: old.to_string(),
+                to: version.to_string(),
+            }),
+            None => result.added.push(PackageChange {
+                name: name.clone(),
+                version: version.to_string(),
+            }),
+        }
+    }
+    for (name, version) in to_uninstall {
+        if !to_install
+            .iter()
+            .any(|((n, _), _)| util::compare_names(n, name))
         {
-            abort("Problem downloading packages");
+            result.removed.push(PackageChange {
+                name: name.clone(),
+                version: version.to_string(),
+            });
+        }
+    }
+    result
+}
+
+/// A failing pre-install hook aborts the operation.
+fn run_pre_install_hook(hooks: &Hooks, paths: &util::Paths, changed: &[String]) {
+    if let Some(hook) = &hooks.pre_install {
+        if !hooks::run(Event::PreInstall, hook, paths, changed) {
+            abort(&format!(
+                "The pre-install hook `{}` failed; no packages were changed",
+                hook
+            ));
+        }
+    }
+}
+
+/// A failing post-install hook only warns, since packages are already installed.
+fn run_post_install_hook(hooks: &Hooks, paths: &util::Paths, changed: &[String]) {
+    if let Some(hook) = &hooks.post_install {
+        if !hooks::run(Event::PostInstall, hook, paths, changed) {
+            util::print_color(
+                &format!(
+                    "WARNING: The post-install hook `{}` failed. Packages were installed, \
+                     but any steps it performs may not have completed.",
+                    hook
+                ),
+                Color::Red,
+            );
+        }
+    }
+}
+
+/// Download and install packages from the lock, then perform any renames. A package with
+/// `hashes` must pick a release with one of them.
+fn install_packs(
+    paths: &util::Paths,
+    lock_packs: &[LockPackage],
+    to_install: &[&PackToInstall],
+    os: util::Os,
+    python_vers: &Version,
+    hashes: &HashMap<String, Vec<String>>,
+) {
+    let implementation = py_versions::venv_implementation(&paths.bin);
+
+    // Find and download each package on worker threads, and install them here as they arrive.
+    // A worker reports problems, instead of aborting, so we never exit mid-extraction.
+    let fetch = |((name, version), _): &&PackToInstall| -> Result<_, String> {
+        let data = res::get_warehouse_release(name, version)
+            .map_err(|e| format!("Problem getting warehouse data: {}", e.details))?;
+
+        let (best_release, package_type) =
+            util::find_best_release(&data, name, version, os, python_vers, implementation)?;
+        if let Some(allowed) = hashes.get(&util::standardize_name(name)) {
+            if !allowed.contains(&best_release.digests.sha256.to_lowercase()) {
+                return Err(format!(
+                    "None of the hashes for {} {} match {}, the file we'd install. \
+                     Regenerate them, eg with `pip-compile --generate-hashes`",
+                    name, version, best_release.filename
+                ));
+            }
         }
+        let archive =
+            install::download_package(&best_release.url, &best_release.filename, &paths.cache)?;
+        Ok(archive.map(|a| (best_release, package_type, a)))
+    };
+
+    let _cancel = util::pipeline::Cancel::catch();
+    let mut failed = None;
+    util::pipeline::run(
+        to_install,
+        util::pipeline::DOWNLOADS,
+        fetch,
+        |((name, version), rename), fetched| {
+            let (best_release, package_type, archive) = match fetched {
+                Ok(Some(f)) => f,
+                // Cancelled mid-download.
+                Ok(None) => return,
+                // Stop the other downloads, and remove their partial files, before aborting.
+                Err(e) => {
+                    failed = Some(e);
+                    util::pipeline::stop();
+                    return;
+                }
+            };
+
+            // Powershell  doesn't like emojis
+            // todo format literal issues, so repeating this whole statement.
+            #[cfg(target_os = "windows")]
+            log::info!("Installing {} {} ...", &name, &version.to_string_color());
+            #[cfg(target_os = "linux")]
+            log::info!("⬇ Installing {} {} ...", &name, &version.to_string_color());
+            #[cfg(target_os = "macos")]
+            log::info!("⬇ Installing {} {} ...", &name, &version.to_string_color());
+
+            install::install_package(
+                name,
+                version,
+                &archive,
+                &best_release.filename,
+                &best_release.digests.sha256,
+                paths,
+                package_type,
+                rename,
+            );
+        },
+    );
+    if let Some(e) = failed {
+        abort(&e);
+    }
+    if util::pipeline::cancelled() {
+        abort("Cancelled; the packages already installed were kept, and the rest skipped");
     }
+
     // Perform renames after all packages are installed, or we may attempt to rename a package
     // we haven't yet installed.
-    for ((name, version), rename) in &to_install {
+    for ((name, version), rename) in to_install {
         if let Some((id, new)) = rename {
             // Rename in the renamed package
 
             let renamed_path = &paths.lib.join(util::standardize_name(new));
 
-            util::wait_for_dirs(&[renamed_path.clone()]).expect("Problem creating renamed path");
+            util::wait_for_dirs(std::slice::from_ref(renamed_path))
+                .expect("Problem creating renamed path");
             install::rename_package_files(renamed_path, name, new);
 
             // Rename in the parent calling the renamed package. // TODO: Multiple parents?
@@ -277,12 +543,126 @@ fn sync_deps(
 
             // TODO: Handle this more generally, in case we don't have proper semver dist-info paths.
             install::rename_metadata(
-                &paths.lib.join(&format!("{}-{}.dist-info", name, version)),
+                &paths.lib.join(format!("{}-{}.dist-info", name, version)),
                 name,
                 new,
             );
         }
     }
+
+    // Last, so a later sync can tell they're complete and unchanged.
+    let installed = util::find_installed(&paths.lib);
+    for ((name, version), _) in to_install {
+        if let Some((inst_name, inst_version, _)) = installed
+            .iter()
+            .find(|(n, v, _)| util::compare_names(n, name) && v == version)
+        {
+            install::stamp_installed(inst_name, inst_version, &paths.lib);
+        }
+    }
+}
+
+/// Wipe and re-extract locked packages in place, without resolving or modifying the lock.
+/// Reinstalls all locked packages if `names` is empty.
+pub fn reinstall(
+    paths: &util::Paths,
+    lock_packs: &[LockPackage],
+    names: &[String],
+    os: util::Os,
+    python_vers: &Version,
+    hooks: &Hooks,
+) {
+    for name in names {
+        if !lock_packs
+            .iter()
+            .any(|lp| util::compare_names(&lp.name, name))
+        {
+            abort(&format!(
+                "{} isn't in `pyflow.lock`; run `pyflow install` first",
+                name
+            ));
+        }
+    }
+
+    let packages: Vec<PackToInstall> = lock_packs
+        .iter()
+        .filter(|lp| names.is_empty() || names.iter().any(|n| util::compare_names(n, &lp.name)))
+        .map(|lp| {
+            (
+                (
+                    util::standardize_name(&lp.name),
+                    Version::from_str(&lp.version).expect("Problem parsing lock version"),
+                ),
+                lp.rename.as_ref().map(|rn| parse_lockpack_rename(rn)),
+            )
+        })
+        .collect();
+
+    let changed: Vec<String> = packages
+        .iter()
+        .map(|((name, version), _)| format!("{}=={}", name, version))
+        .collect();
+    run_pre_install_hook(hooks, paths, &changed);
+
+    // Their files may be linked from the cache, and what's wrong with them may be wrong there too.
+    for ((name, version), _) in &packages {
+        install::forget_extracted(&paths.cache, name, version);
+    }
+
+    // Remove whatever version is currently installed, even if it doesn't match the lock.
+    for (inst_name, inst_version, _) in util::find_installed(&paths.lib) {
+        if packages
+            .iter()
+            .any(|((name, _), _)| util::compare_names(name, &inst_name))
+        {
+            install::uninstall(&inst_name, &inst_version, &paths.lib);
+        }
+    }
+
+    let to_install: Vec<&PackToInstall> = packages
+        .iter()
+        .filter(|((name, _), _)| name != "typing")
+        .collect();
+    install_packs(
+        paths,
+        lock_packs,
+        &to_install,
+        os,
+        python_vers,
+        &HashMap::new(),
+    );
+
+    run_post_install_hook(hooks, paths, &changed);
+}
+
+/// Find the lock packages required, directly or transitively, by `reqs`. Used to skip
+/// packages only needed by dev requirements.
+pub fn reachable_from(lock_packs: &[LockPackage], reqs: &[Req]) -> Vec<LockPackage> {
+    let mut to_visit: Vec<String> = reqs.iter().map(|r| r.name.clone()).collect();
+    let mut visited: Vec<String> = vec![];
+
+    while let Some(name) = to_visit.pop() {
+        if visited.iter().any(|v| util::compare_names(v, &name)) {
+            continue;
+        }
+        if let Some(lp) = lock_packs
+            .iter()
+            .find(|lp| util::compare_names(&lp.name, &name))
+        {
+            for dep in lp.dependencies.as_ref().unwrap_or(&vec![]) {
+                if let Some(dep_name) = dep.split_whitespace().next() {
+                    to_visit.push(dep_name.to_owned());
+                }
+            }
+        }
+        visited.push(name);
+    }
+
+    lock_packs
+        .iter()
+        .filter(|lp| visited.iter().any(|v| util::compare_names(v, &lp.name)))
+        .cloned()
+        .collect()
 }
 
 fn already_locked(locked: &[Package], name: &str, constraints: &[Constraint]) -> bool {
@@ -300,8 +680,7 @@ fn already_locked(locked: &[Package], name: &str, constraints: &[Constraint]) ->
 }
 
 fn parse_lockpack_rename(rename: &str) -> (u32, String) {
-    let re = Regex::new(r"^(\d+)\s(.*)$").unwrap();
-    let caps = re
+    let caps = util::patterns::LOCK_RENAME_RE
         .captures(rename)
         .expect("Problem reading lock file rename");
 
@@ -310,3 +689,146 @@ fn parse_lockpack_rename(rename: &str) -> (u32, String) {
 
     (id, name)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::util::temp_dir::TempDir;
+
+    #[test]
+    fn venv_tools() {
+        let pip = (("pip".to_owned(), Version::new(24, 0, 0)), None);
+        assert!(is_venv_tool(
+            EnvMode::Venv,
+            "setuptools",
+            std::slice::from_ref(&pip)
+        ));
+        assert!(!is_venv_tool(
+            EnvMode::Venv,
+            "Pip",
+            std::slice::from_ref(&pip)
+        ));
+        assert!(!is_venv_tool(EnvMode::Venv, "requests", &[]));
+        assert!(!is_venv_tool(EnvMode::Pypackages, "setuptools", &[pip]));
+    }
+
+    /// `idna` is needed by two packages, and `requests` and `urllib3` need each other; each
+    /// is kept once, and dev-only packages aren't.
+    #[test]
+    fn reachable_through_shared_and_cyclic_deps() {
+        let pack = |id, name: &str, deps: &[&str]| LockPackage {
+            id,
+            name: name.to_owned(),
+            version: "1.0.0".to_owned(),
+            source: None,
+            dependencies: Some(deps.iter().map(|d| format!("{} 1.0.0", d)).collect()),
+            rename: None,
+        };
+        let lock = vec![
+            pack(1, "requests", &["urllib3", "idna"]),
+            pack(2, "urllib3", &["requests"]),
+            pack(3, "idna", &[]),
+            pack(4, "Click", &["idna"]),
+            pack(5, "pytest", &["pluggy", "idna"]),
+            pack(6, "pluggy", &[]),
+        ];
+        let reqs = vec![
+            Req::new("requests".to_owned(), vec![]),
+            Req::new("click".to_owned(), vec![]),
+        ];
+
+        let names: Vec<String> = reachable_from(&lock, &reqs)
+            .into_iter()
+            .map(|lp| lp.name)
+            .collect();
+        assert_eq!(names, vec!["requests", "urllib3", "idna", "Click"]);
+    }
+
+    /// Syncing to an empty lock removes every package, but leaves a venv's own tools.
+    #[test]
+    fn sync_keeps_venv_tools() {
+        for mode in [EnvMode::Venv, EnvMode::Pypackages] {
+            let root = TempDir::new("venv-sync");
+            let paths = util::Paths {
+                bin: root.join("bin"),
+                lib: root.join("site-packages"),
+                entry_pt: root.join("bin"),
+                cache: root.join("cache"),
+                mode,
+            };
+            for (name, version) in &[("pip", "23.0.1"), ("requests", "2.31.0")] {
+                let dist_info = format!("{}-{}.dist-info", name, version);
+                fs::create_dir_all(paths.lib.join(name)).unwrap();
+                fs::create_dir_all(paths.lib.join(&dist_info)).unwrap();
+                fs::write(paths.lib.join(name).join("__init__.py"), "").unwrap();
+                fs::write(
+                    paths.lib.join(&dist_info).join("RECORD"),
+                    format!("{}/__init__.py,,\n{}/RECORD,,\n", name, dist_info),
+                )
+                .unwrap();
+            }
+
+            let summary = sync_exact(
+                &paths,
+                &[],
+                &HashMap::new(),
+                util::get_os(),
+                &Version::new_short(3, 11),
+                &Hooks::default(),
+            );
+            let kept: Vec<String> = util::find_installed(&paths.lib)
+                .into_iter()
+                .map(|(name, _, _)| name)
+                .collect();
+
+            let mut removed: Vec<&str> = summary.removed.iter().map(|c| c.name.as_str()).collect();
+            removed.sort_unstable();
+            match mode {
+                EnvMode::Venv => {
+                    assert_eq!(kept, ["pip"]);
+                    assert_eq!(removed, ["requests"]);
+                }
+                EnvMode::Pypackages => {
+                    assert!(kept.is_empty());
+                    assert_eq!(removed, ["pip", "requests"]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn summary_json() {
+        let requests_old = ("Requests".to_owned(), Version::new(2, 30, 0));
+        let idna = ("idna".to_owned(), Version::new(3, 4, 0));
+        let requests_new = (("requests".to_owned(), Version::new(2, 31, 0)), None);
+        let certifi = (("certifi".to_owned(), Version::new(2023, 7, 22)), None);
+
+        let summary = summarize(&[&requests_old, &idna], &[&requests_new, &certifi]);
+        assert_eq!(
+            serde_json::to_string_pretty(&summary).unwrap(),
+            r#"{
+  "added": [
+    {
+      "name": "certifi",
+      "version": "2023.7.22"
+    }
+  ],
+  "removed": [
+    {
+      "name": "idna",
+      "version": "3.4.0"
+    }
+  ],
+  "upgraded": [
+    {
+      "name": "requests",
+      "from": "2.30.0",
+      "to": "2.31.0"
+    }
+  ]
+}"#
+        );
+    }
+}
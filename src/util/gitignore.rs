@@ -0,0 +1,286 @@
+//! Whether git ignores a path, from the `.gitignore` files between the repository's root and
+//! it, and `.git/info/exclude`; as `git check-ignore` would say, without needing git.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+
+/// One line of a `.gitignore`.
+struct Rule {
+    regex: Regex,
+    /// Starts with `!`: re-include what an earlier rule ignored.
+    negate: bool,
+    /// Ends with `/`: only matches folders.
+    dir_only: bool,
+}
+
+fn parse(data: &str) -> Vec<Rule> {
+    data.lines().filter_map(parse_line).collect()
+}
+
+/// A rule from one line, or `None` for blanks, comments and patterns we can't match.
+fn parse_line(line: &str) -> Option<Rule> {
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    if line.starts_with('#') {
+        return None;
+    }
+    let (negate, line) = match line.strip_prefix('!') {
+        Some(p) => (true, p),
+        None => (false, line),
+    };
+    let mut chars: Vec<char> = line.chars().collect();
+    // Trailing spaces are dropped, unless escaped with `\`.
+    while chars.last() == Some(&' ') && !escaped(&chars, chars.len() - 1) {
+        chars.pop();
+    }
+    let dir_only = chars.last() == Some(&'/') && !escaped(&chars, chars.len() - 1);
+    if dir_only {
+        chars.pop();
+    }
+    if chars.is_empty() {
+        return None;
+    }
+    // A `/` at the start or in the middle anchors the pattern to the `.gitignore`'s folder.
+    let anchored = (0..chars.len()).any(|i| chars[i] == '/' && !escaped(&chars, i));
+    if chars[0] == '/' {
+        chars.remove(0);
+    }
+    let body = translate(&chars);
+    let prefix = if anchored { "" } else { "(?:.*/)?" };
+    Some(Rule {
+        regex: Regex::new(&format!("^{}{}$", prefix, body)).ok()?,
+        negate,
+        dir_only,
+    })
+}
+
+/// Whether the character at `i` is preceded by an odd number of backslashes.
+fn escaped(chars: &[char], i: usize) -> bool {
+    chars[..i].iter().rev().take_while(|&&c| c == '\\').count() % 2 == 1
+}
+
+/// A regex matching what the glob `chars` does: `*` and `?` within one path component, `**` as
+/// a whole component across any number of them, `[...]` classes, and `\` escapes.
+fn translate(chars: &[char]) -> String {
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                out.push_str(&regex::escape(&chars[i + 1].to_string()));
+                i += 2;
+                continue;
+            }
+            '*' if chars.get(i + 1) == Some(&'*')
+                && (i == 0 || chars[i - 1] == '/')
+                && matches!(chars.get(i + 2), None | Some('/')) =>
+            {
+                if i + 2 == chars.len() {
+                    // `**` or `x/**`: everything, or everything in `x`.
+                    out.push_str(".*");
+                } else {
+                    // `**/x` or `x/**/y`: any number of folders, including none.
+                    out.push_str("(?:.*/)?");
+                    i += 1;
+                }
+                i += 2;
+                continue;
+            }
+            '*' => {
+                while chars.get(i + 1) == Some(&'*') {
+                    i += 1;
+                }
+                out.push_str("[^/]*");
+            }
+            '?' => out.push_str("[^/]"),
+            '[' => {
+                if let Some((class, end)) = class(chars, i) {
+                    out.push_str(&class);
+                    i = end + 1;
+                    continue;
+                }
+                out.push_str("\\[");
+            }
+            c => out.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    out
+}
+
+/// The regex for the `[...]` class starting at `start`, and the index of its closing `]`; `None`
+/// if it isn't closed, so the `[` is literal.
+fn class(chars: &[char], start: usize) -> Option<(String, usize)> {
+    let mut i = start + 1;
+    let negate = matches!(chars.get(i), Some('!') | Some('^'));
+    if negate {
+        i += 1;
+    }
+    let first = i;
+    let mut set = String::new();
+    loop {
+        let c = *chars.get(i)?;
+        match c {
+            // A `]` first is part of the set, not its end.
+            ']' if i > first => break,
+            '[' if chars.get(i + 1) == Some(&':') => {
+                let len = (i + 2..chars.len()).find(|&j| chars[j..].starts_with(&[':', ']']))?;
+                let name: String = chars[i + 2..len].iter().collect();
+                set.push_str(&format!("[:{}:]", name));
+                i = len + 2;
+                continue;
+            }
+            '\\' => {
+                i += 1;
+                set.push_str(&regex::escape(&chars.get(i)?.to_string()));
+            }
+            '-' if i > first && chars.get(i + 1).is_some_and(|&n| n != ']') => set.push('-'),
+            '[' | ']' | '&' | '~' | '^' | '-' => {
+                set.push('\\');
+                set.push(c);
+            }
+            _ => set.push(c),
+        }
+        i += 1;
+    }
+    // Classes never match the `/` between path components.
+    let class = if negate {
+        format!("[^{}/]", set)
+    } else {
+        format!("[{}&&[^/]]", set)
+    };
+    Some((class, i))
+}
+
+/// The repository `path` is in: the nearest folder, at or above it, with a `.git`.
+pub fn find_repo_root(path: &Path) -> Option<PathBuf> {
+    path.ancestors()
+        .find(|p| p.join(".git").exists())
+        .map(Path::to_path_buf)
+}
+
+/// Whether git ignores `path`, in the repository at `root`. Each `.gitignore` applies to paths
+/// relative to its folder, the last matching rule wins, deeper files override shallower ones,
+/// and nothing in an ignored folder can be re-included.
+pub fn is_ignored(root: &Path, path: &Path, is_dir: bool) -> bool {
+    let rel: Vec<String> = match path.strip_prefix(root) {
+        Ok(r) => r
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect(),
+        Err(_) => return false,
+    };
+
+    // `.git/info/exclude` applies from the root, below every `.gitignore`.
+    let mut levels = vec![(
+        0,
+        parse(&fs::read_to_string(root.join(".git/info/exclude")).unwrap_or_default()),
+    )];
+    // Each of `path`'s ancestors, then `path` itself.
+    for depth in 1..=rel.len() {
+        let folder = root.join(rel[..depth - 1].iter().collect::<PathBuf>());
+        if let Ok(data) = fs::read_to_string(folder.join(".gitignore")) {
+            levels.push((depth - 1, parse(&data)));
+        }
+        let target_is_dir = depth < rel.len() || is_dir;
+        let mut ignored = false;
+        for (level, rules) in &levels {
+            let within = rel[*level..depth].join("/");
+            for rule in rules {
+                if (!rule.dir_only || target_is_dir) && rule.regex.is_match(&within) {
+                    ignored = !rule.negate;
+                }
+            }
+        }
+        if ignored {
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::temp_dir::TempDir;
+    use rstest::rstest;
+
+    #[rstest(
+        line,
+        path,
+        expected,
+        case("__pypackages__", "a/__pypackages__", true),
+        case("/__pypackages__", "a/__pypackages__", false),
+        case("a/*.py", "a/b/c.py", false),
+        case("a/**/c.py", "a/c.py", true),
+        case("a/**/c.py", "a/b/d/c.py", true),
+        case("**/c.py", "a/b/c.py", true),
+        case("a/**", "a/b/c", true),
+        case("\\#notes", "#notes", true),
+        case("#notes", "#notes", false),
+        case("\\!important", "!important", true),
+        case("trailing   ", "trailing", true),
+        case("trailing\\ ", "trailing ", true),
+        case("trailing\\ ", "trailing", false),
+        case("[a-c]x", "bx", true),
+        case("[a-c]x", "dx", false),
+        case("[!a-c]x", "dx", true),
+        case("[!a-c]x", "/x", false),
+        case("[^a-c]x", "ax", false),
+        case("[]]x", "]x", true),
+        case("[[:digit:]]x", "7x", true),
+        case("[[:digit:]]x", "ax", false),
+        case("[a-]x", "-x", true),
+        case("x[y", "x[y", true),
+        case("file?.txt", "file1.txt", true),
+        case("file?.txt", "file/.txt", false),
+        case("a.b", "axb", false)
+    )]
+    fn patterns(line: &str, path: &str, expected: bool) {
+        // Comments have no rule, so match nothing.
+        let matched = parse_line(line).is_some_and(|r| r.regex.is_match(path));
+        assert_eq!(matched, expected, "{} on {}", line, path);
+    }
+
+    #[test]
+    fn nested_gitignores() {
+        let root = TempDir::new("gitignore");
+        fs::create_dir_all(root.join(".git/info")).unwrap();
+        fs::create_dir_all(root.join("apps/api")).unwrap();
+        let api = root.join("apps/api");
+
+        assert!(!is_ignored(&root, &api.join("__pypackages__"), true));
+        // A substring isn't a match.
+        fs::write(
+            root.join(".gitignore"),
+            "# __pypackages__\n__pypackages__.bak\n",
+        )
+        .unwrap();
+        assert!(!is_ignored(&root, &api.join("__pypackages__"), true));
+
+        fs::write(root.join(".gitignore"), "__pypackages__/\n").unwrap();
+        assert!(is_ignored(&root, &api.join("__pypackages__"), true));
+        assert!(!is_ignored(&root, &api.join("__pypackages__"), false));
+
+        // A deeper `.gitignore` re-includes it.
+        fs::write(api.join(".gitignore"), "!__pypackages__\n").unwrap();
+        assert!(!is_ignored(&root, &api.join("__pypackages__"), true));
+
+        // Anchored to the folder of the `.gitignore` it's in.
+        fs::write(root.join(".gitignore"), "/__pypackages__\n").unwrap();
+        fs::remove_file(api.join(".gitignore")).unwrap();
+        assert!(!is_ignored(&root, &api.join("__pypackages__"), true));
+        fs::write(root.join("apps/.gitignore"), "/api/__pypackages__\n").unwrap();
+        assert!(is_ignored(&root, &api.join("__pypackages__"), true));
+
+        // Nothing in an ignored folder can be re-included.
+        fs::write(root.join(".git/info/exclude"), "apps/\n").unwrap();
+        fs::write(root.join("apps/.gitignore"), "!api/__pypackages__\n").unwrap();
+        assert!(is_ignored(&root, &api.join("__pypackages__"), true));
+
+        assert_eq!(find_repo_root(&api), Some(root.to_path_buf()));
+    }
+}
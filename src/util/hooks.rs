@@ -0,0 +1,111 @@
+use std::{env, fmt, io, path::Path, process::Command, sync::LazyLock};
+
+use regex::Regex;
+
+use crate::{
+    util::{self, Paths},
+    CliConfig,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub enum Event {
+    PreInstall,
+    PostInstall,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::PreInstall => "pre-install",
+            Self::PostInstall => "post-install",
+        })
+    }
+}
+
+/// Run a hook from `[tool.pyflow.hooks]`, with the project's interpreter and `__pypackages__`
+/// available. `changed` lists packages being installed or removed, as `name==version`.
+/// Returns `false` if the hook failed to run, or exited with a non-zero code.
+pub fn run(event: Event, hook: &str, paths: &Paths, changed: &[String]) -> bool {
+    util::set_pythonpath(std::slice::from_ref(&paths.lib));
+
+    let mut cmd = command(hook, &paths.bin, &paths.entry_pt);
+    cmd.env("PYFLOW_EVENT", event.to_string())
+        .env("PYFLOW_CHANGED_PACKAGES", changed.join(" "));
+    if CliConfig::current().json {
+        // Stdout is reserved for the JSON summary.
+        cmd.stdout(io::stderr());
+    }
+
+    match cmd.status() {
+        Ok(status) => status.success(),
+        Err(_) => false,
+    }
+}
+
+/// Build a command for a hook or script: `module:function` runs in the project's Python;
+/// anything else runs in the shell, with the project's Python and console scripts on the `PATH`.
+pub fn command(hook: &str, bin_path: &Path, entry_pt_path: &Path) -> Command {
+    let hook = hook.trim();
+    let mut cmd = if is_function(hook) {
+        let (module, function) = hook.split_at(hook.find(':').unwrap());
+        let mut c = Command::new(bin_path.join("python"));
+        c.arg("-c").arg(format!(
+            "import {}; {}.{}()",
+            module,
+            module,
+            &function[1..]
+        ));
+        c
+    } else {
+        #[cfg(target_os = "windows")]
+        let mut c = Command::new("cmd");
+        #[cfg(target_os = "windows")]
+        c.args(["/C", hook]);
+        #[cfg(not(target_os = "windows"))]
+        let mut c = Command::new("sh");
+        #[cfg(not(target_os = "windows"))]
+        c.args(["-c", hook]);
+        c
+    };
+
+    // Put the project's Python and console scripts ahead of any others.
+    let mut path_var = vec![bin_path.to_owned(), entry_pt_path.to_owned()];
+    if let Some(existing) = env::var_os("PATH") {
+        path_var.extend(env::split_paths(&existing));
+    }
+    if let Ok(joined) = env::join_paths(path_var) {
+        cmd.env("PATH", joined);
+    }
+    cmd
+}
+
+/// Eg `package.module:function`.
+static FUNCTION_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[\w.]+:[\w.]+$").unwrap());
+
+/// Hooks in the form `module:function` run in Python; anything else runs in the shell.
+pub fn is_function(hook: &str) -> bool {
+    FUNCTION_RE.is_match(hook.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hook_kind() {
+        assert!(is_function("codegen.main:run"));
+        assert!(!is_function("python scripts/codegen.py"));
+        assert!(!is_function("make generate"));
+    }
+
+    #[test]
+    fn function_hook_padded() {
+        let cmd = command(
+            " codegen.main:run\n",
+            Path::new("bin"),
+            Path::new("scripts"),
+        );
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args, ["-c", "import codegen.main; codegen.main.run()"]);
+    }
+}
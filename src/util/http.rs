@@ -0,0 +1,326 @@
+//! One HTTP client per invocation, so connections to the index are kept alive and reused, over
+//! HTTP/2 where the server offers it during the TLS handshake, and HTTP/1.1 otherwise. Responses
+//! are gzip or brotli-compressed. Each URL fetched with `get_text` is requested once per
+//! invocation: callers asking for it while it's in flight wait for that request, and later ones
+//! get the same body, while the bodies kept fit in `KEEP_BYTES`. Counts of requests, bytes
+//! received, and cache hits are logged on exit, at `-vv`.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, Read},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Condvar, LazyLock, Mutex, Once, OnceLock,
+    },
+};
+
+use flate2::read::GzDecoder;
+use reqwest::{
+    blocking::Client,
+    header::{ACCEPT_ENCODING, CONTENT_ENCODING},
+};
+
+use crate::{util::abort, CliConfig};
+
+static CLIENT: OnceLock<Client> = OnceLock::new();
+static INDEX_CLIENT: OnceLock<Client> = OnceLock::new();
+static REPORT: Once = Once::new();
+
+static REQUESTS: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+static SHARED: AtomicUsize = AtomicUsize::new(0);
+static CACHE_HITS: AtomicUsize = AtomicUsize::new(0);
+
+/// A fetch: `None` until it's done, then the body, or `None` inside if it failed.
+type Fetch = Arc<(Mutex<Option<Option<Arc<String>>>>, Condvar)>;
+
+/// Fetches in flight, and finished ones, oldest first, whose bodies are kept for later callers.
+#[derive(Default)]
+struct Fetches {
+    by_url: HashMap<String, Fetch>,
+    finished: VecDeque<(String, usize)>,
+    kept: usize,
+}
+
+static FETCHES: LazyLock<Mutex<Fetches>> = LazyLock::new(Default::default);
+
+/// How much of the finished bodies to keep, beyond which the oldest are dropped, and fetched
+/// again if asked for. Enough for every package's metadata in a large resolve.
+const KEEP_BYTES: usize = 64 * 1024 * 1024;
+
+/// Set in tests, to show a command never reaches the network: `client` panics.
+pub const FORBID_NETWORK: &str = "PYFLOW_FORBID_NETWORK";
+
+/// The client, built on first use, through the `proxy` setting if there is one. Settings are per
+/// thread, so this is first called on the main one.
+pub fn client() -> Client {
+    shared(&CLIENT, true)
+}
+
+/// Like `client`, but leaving bodies compressed, so `fetch_text` can count the bytes received.
+fn index_client() -> Client {
+    shared(&INDEX_CLIENT, false)
+}
+
+fn shared(cell: &OnceLock<Client>, decompress: bool) -> Client {
+    if std::env::var_os(FORBID_NETWORK).is_some() {
+        panic!("Tried to use the network with {} set", FORBID_NETWORK);
+    }
+    cell.get_or_init(|| {
+        let mut builder = Client::builder();
+        if !decompress {
+            builder = builder.no_gzip().no_brotli();
+        }
+        if let Some(proxy) = &CliConfig::current().settings.proxy {
+            match reqwest::Proxy::all(proxy.as_str()) {
+                Ok(p) => builder = builder.proxy(p),
+                Err(_) => abort(&format!("Problem using the proxy `{}`", proxy)),
+            }
+        }
+        // Safe: `report` only reads atomics, and logs.
+        REPORT.call_once(|| unsafe {
+            libc::atexit(report);
+        });
+        builder
+            .build()
+            .unwrap_or_else(|_| abort("Problem setting up an HTTP client"))
+    })
+    .clone()
+}
+
+/// The body at `url`, fetched once per invocation. If it's in flight, eg on another thread, wait
+/// for that request instead of making another; if it failed, try again, so each caller sees its
+/// own error.
+pub fn get_text(url: &str) -> Result<Arc<String>, String> {
+    let (fetch, leader) = {
+        let mut fetches = FETCHES.lock().unwrap();
+        match fetches.by_url.get(url) {
+            Some(f) => (f.clone(), false),
+            None => {
+                let f = Fetch::default();
+                fetches.by_url.insert(url.to_owned(), f.clone());
+                (f, true)
+            }
+        }
+    };
+
+    if !leader {
+        let (done, finished) = &*fetch;
+        let mut done = done.lock().unwrap();
+        while done.is_none() {
+            done = finished.wait(done).unwrap();
+        }
+        if let Some(Some(body)) = &*done {
+            SHARED.fetch_add(1, Ordering::Relaxed);
+            return Ok(body.clone());
+        }
+        drop(done);
+        return fetch_text(url).map(Arc::new);
+    }
+
+    let result = fetch_text(url).map(Arc::new);
+    {
+        let mut fetches = FETCHES.lock().unwrap();
+        match &result {
+            Ok(body) => keep(&mut fetches, url, body.len(), KEEP_BYTES),
+            Err(_) => {
+                fetches.by_url.remove(url);
+            }
+        }
+    }
+    let (done, finished) = &*fetch;
+    *done.lock().unwrap() = Some(result.as_ref().ok().cloned());
+    finished.notify_all();
+    result
+}
+
+/// Keep a finished fetch's body, dropping the oldest ones beyond `limit`. Callers waiting on a
+/// dropped one still get it.
+fn keep(fetches: &mut Fetches, url: &str, len: usize, limit: usize) {
+    fetches.finished.push_back((url.to_owned(), len));
+    fetches.kept += len;
+    while fetches.kept > limit {
+        let (oldest, len) = match fetches.finished.pop_front() {
+            Some(f) => f,
+            None => break,
+        };
+        fetches.by_url.remove(&oldest);
+        fetches.kept -= len;
+    }
+}
+
+fn fetch_text(url: &str) -> Result<String, String> {
+    let problem = |e: &dyn std::fmt::Display| format!("Problem fetching {}: {}", url, e);
+    let response = index_client()
+        .get(url)
+        .header(ACCEPT_ENCODING, "gzip, br")
+        .send()
+        .map_err(|e| problem(&e))?;
+    let encoding = response
+        .headers()
+        .get(CONTENT_ENCODING)
+        .and_then(|e| e.to_str().ok())
+        .map(str::to_owned);
+    let body = response.bytes().map_err(|e| problem(&e))?;
+    record_request(body.len());
+    decode(encoding.as_deref(), &body).map_err(|e| problem(&e))
+}
+
+/// A body sent with the `Content-Encoding` `encoding`, as text.
+fn decode(encoding: Option<&str>, body: &[u8]) -> io::Result<String> {
+    let mut text = String::new();
+    match encoding {
+        None | Some("identity") => return Ok(String::from_utf8_lossy(body).into_owned()),
+        Some("gzip") => GzDecoder::new(body).read_to_string(&mut text)?,
+        Some("br") => brotli::Decompressor::new(body, 4096).read_to_string(&mut text)?,
+        Some(other) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported content encoding `{}`", other),
+            ))
+        }
+    };
+    Ok(text)
+}
+
+/// A request made, and the size of its body as received.
+pub fn record_request(bytes: usize) {
+    REQUESTS.fetch_add(1, Ordering::Relaxed);
+    BYTES.fetch_add(bytes, Ordering::Relaxed);
+}
+
+/// Something we didn't have to fetch, or parse again, since it was cached.
+pub fn record_cache_hit() {
+    CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+}
+
+extern "C" fn report() {
+    log::trace!(
+        "{} HTTP requests, {} received; {} answered by an earlier request; {} cache hits",
+        REQUESTS.load(Ordering::Relaxed),
+        super::format_size(BYTES.load(Ordering::Relaxed) as u64),
+        SHARED.load(Ordering::Relaxed),
+        CACHE_HITS.load(Ordering::Relaxed),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+        time::Duration,
+    };
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+
+    /// Eight threads ask for the same URL at once, then once more each; the server sees one
+    /// request.
+    #[test]
+    fn one_request_per_url() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/pypi/idna/json", listener.local_addr().unwrap());
+        let served = Arc::new(AtomicUsize::new(0));
+        let counter = served.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                counter.fetch_add(1, Ordering::SeqCst);
+                // Slow enough that the other threads find it in flight.
+                thread::sleep(Duration::from_millis(200));
+                let body = "{\"info\": {}}";
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+            }
+        });
+
+        let bodies: Vec<_> = thread::scope(|s| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    s.spawn(|| {
+                        let first = get_text(&url).unwrap();
+                        let again = get_text(&url).unwrap();
+                        assert!(Arc::ptr_eq(&first, &again));
+                        first
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert!(bodies.iter().all(|b| b.as_str() == "{\"info\": {}}"));
+        assert_eq!(served.load(Ordering::SeqCst), 1);
+    }
+
+    /// The server is asked for gzip or brotli, and its gzipped body is counted as sent, then
+    /// decoded.
+    #[test]
+    fn decodes_compressed_bodies() {
+        let body = "{\"info\": {\"name\": \"idna\"}}";
+        let mut gzipped = GzEncoder::new(Vec::new(), Compression::default());
+        gzipped.write_all(body.as_bytes()).unwrap();
+        let gzipped = gzipped.finish().unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/pypi/idna/json", listener.local_addr().unwrap());
+        let response = gzipped.clone();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut headers = String::new();
+            while reader.read_line(&mut headers).unwrap() > 2 {}
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+                response.len()
+            )
+            .unwrap();
+            stream.write_all(&response).unwrap();
+            headers.to_lowercase()
+        });
+        let before = BYTES.load(Ordering::SeqCst);
+        assert_eq!(get_text(&url).unwrap().as_str(), body);
+        assert!(BYTES.load(Ordering::SeqCst) - before >= gzipped.len());
+        assert!(server
+            .join()
+            .unwrap()
+            .contains("accept-encoding: gzip, br\r\n"));
+
+        let mut brotli = Vec::new();
+        brotli::CompressorReader::new(body.as_bytes(), 4096, 5, 22)
+            .read_to_end(&mut brotli)
+            .unwrap();
+        assert_eq!(decode(Some("br"), &brotli).unwrap(), body);
+        assert_eq!(decode(None, body.as_bytes()).unwrap(), body);
+        assert!(decode(Some("zstd"), body.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn drops_oldest_bodies() {
+        let mut fetches = Fetches::default();
+        for (url, len) in &[("a", 40), ("b", 40), ("c", 40)] {
+            fetches.by_url.insert(url.to_string(), Fetch::default());
+            keep(&mut fetches, url, *len, 100);
+        }
+        assert!(!fetches.by_url.contains_key("a"));
+        assert!(fetches.by_url.contains_key("b") && fetches.by_url.contains_key("c"));
+        assert_eq!(fetches.kept, 80);
+
+        // One larger than the limit isn't kept at all.
+        fetches.by_url.insert("d".to_owned(), Fetch::default());
+        keep(&mut fetches, "d", 200, 100);
+        assert!(fetches.by_url.is_empty());
+        assert_eq!(fetches.kept, 0);
+    }
+}
@@ -0,0 +1,141 @@
+//! `pyproject.toml`s and locks parsed during this invocation. Each is read from disk once, and
+//! kept until something writes it, so every phase of a command sees the same contents.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{dep_types::Lock, pyproject::Config};
+
+#[derive(Default)]
+struct Loaded {
+    texts: HashMap<PathBuf, String>,
+    configs: HashMap<PathBuf, Config>,
+    locks: HashMap<PathBuf, Lock>,
+    /// Reads of each file from disk; a file's only read again after it's written.
+    reads: HashMap<PathBuf, usize>,
+}
+
+thread_local! {
+    static LOADED: RefCell<Loaded> = RefCell::new(Loaded::default());
+}
+
+/// The same file, however it's reached; eg from a relative path, or through a symlink.
+fn key(path: &Path) -> PathBuf {
+    let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    match (parent.canonicalize(), path.file_name()) {
+        (Ok(parent), Some(name)) => parent.join(name),
+        _ => path.to_owned(),
+    }
+}
+
+/// A `pyproject.toml` or lock's contents. Every read of them goes through here, so each is read
+/// from disk once, until something writes it.
+pub fn read_to_string(path: &Path) -> io::Result<String> {
+    let key = key(path);
+    if let Some(text) = LOADED.with(|l| l.borrow().texts.get(&key).cloned()) {
+        return Ok(text);
+    }
+    let text = fs::read_to_string(path)?;
+    log::trace!("Read {:?} from disk", key);
+    LOADED.with(|l| {
+        let mut l = l.borrow_mut();
+        *l.reads.entry(key.clone()).or_default() += 1;
+        l.texts.insert(key, text.clone());
+    });
+    Ok(text)
+}
+
+/// Write a `pyproject.toml` or lock, and forget what was read from it, so it's read again.
+pub fn write(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    fs::write(path, contents)?;
+    let key = key(path);
+    LOADED.with(|l| {
+        let mut l = l.borrow_mut();
+        l.texts.remove(&key);
+        l.configs.remove(&key);
+        l.locks.remove(&key);
+    });
+    Ok(())
+}
+
+pub fn config(path: &Path) -> Option<Config> {
+    LOADED.with(|l| l.borrow().configs.get(&key(path)).cloned())
+}
+
+pub fn lock(path: &Path) -> Option<Lock> {
+    LOADED.with(|l| l.borrow().locks.get(&key(path)).cloned())
+}
+
+/// Keep a config parsed from `read_to_string`'s contents.
+pub fn keep_config(path: &Path, config: &Config) {
+    LOADED.with(|l| l.borrow_mut().configs.insert(key(path), config.clone()));
+}
+
+/// Keep a lock parsed from `read_to_string`'s contents, or just written.
+pub fn keep_lock(path: &Path, lock: &Lock) {
+    LOADED.with(|l| l.borrow_mut().locks.insert(key(path), lock.clone()));
+}
+
+/// How many times this invocation read a file from disk.
+#[cfg(test)]
+pub fn reads(path: &Path) -> usize {
+    LOADED.with(|l| {
+        l.borrow()
+            .reads
+            .get(&key(path))
+            .copied()
+            .unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        dep_types::Req,
+        files,
+        pyproject::{self, CFG_FILENAME, LOCK_FILENAME},
+        util,
+    };
+
+    use super::*;
+    use crate::util::temp_dir::TempDir;
+
+    /// What `pyflow install` reads: the config when finding the project, the lock, the config
+    /// again for the lock's hash after resolving, and the lock it wrote.
+    #[test]
+    fn one_read_per_file() {
+        let dir = TempDir::new("loaded");
+        let (cfg_path, lock_path) = (dir.join(CFG_FILENAME), dir.join(LOCK_FILENAME));
+        fs::write(
+            &cfg_path,
+            "[tool.pyflow]\nname = \"demo\"\n\n[tool.pyflow.dependencies]\nrequests = \"^2.31\"\n",
+        )
+        .unwrap();
+        fs::write(&lock_path, "").unwrap();
+
+        let cfg = Config::from_file(&cfg_path).unwrap();
+        // Another path to the same file, and its raw text, as `check` reads it.
+        read_to_string(&dir.join(".").join(CFG_FILENAME)).unwrap();
+        util::read_lock(&lock_path).unwrap();
+        pyproject::lock_deps_hash(&dir, &[]).unwrap();
+        util::write_lock(&lock_path, &Lock::default()).unwrap();
+        util::read_lock(&lock_path).unwrap();
+        assert_eq!(reads(&cfg_path), 1);
+        assert_eq!(reads(&lock_path), 1);
+
+        // Writing the config means it's read again, and the change is seen.
+        files::add_reqs_to_cfg(&cfg_path, &[Req::from_cli_str("idna>=3.4").unwrap()], &[]);
+        let updated = Config::from_file(&cfg_path).unwrap();
+        assert_eq!(reads(&cfg_path), 2);
+        assert_eq!(updated.reqs.len(), cfg.reqs.len() + 1);
+    }
+}
@@ -0,0 +1,174 @@
+//! Status messages and diagnostics go through the `log` crate. Verbosity flags set the default
+//! level, and `PYFLOW_LOG` overrides it, per module if needed; eg
+//! `PYFLOW_LOG=pyflow::dep_resolution=trace`.
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use termcolor::Color;
+
+use crate::util;
+
+/// A `PYFLOW_LOG` entry: a level for one module and those inside it.
+#[derive(Debug, PartialEq)]
+struct Directive {
+    module: String,
+    level: LevelFilter,
+}
+
+struct Logger {
+    default: LevelFilter,
+    directives: Vec<Directive>,
+}
+
+impl Logger {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        // The most specific module wins.
+        self.directives
+            .iter()
+            .filter(|d| target == d.module || target.starts_with(&format!("{}::", d.module)))
+            .max_by_key(|d| d.module.len())
+            .map_or(self.default, |d| d.level)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .map(|d| d.level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    /// Messages at info and above are for people, and read like the rest of our output.
+    /// Debug and trace ones go to stderr, labeled with their level and module.
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let message = record.args().to_string();
+        match record.level() {
+            Level::Error => util::print_color(&message, Color::Red),
+            Level::Warn => util::print_color(&message, Color::Yellow),
+            Level::Info => util::print_plain(&message),
+            level => eprintln!("[{} {}] {}", level, record.target(), message),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// The default level for `-q`, no flag, `-v`, and `-vv`.
+pub fn level_from_flags(quiet: bool, verbose: u8) -> LevelFilter {
+    match (quiet, verbose) {
+        (true, _) => LevelFilter::Warn,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Parse a `PYFLOW_LOG` value: comma-separated `module=level` entries, and optionally a bare
+/// level that replaces the default. Entries we can't parse are returned separately.
+fn parse_spec(spec: &str) -> (Option<LevelFilter>, Vec<Directive>, Vec<String>) {
+    let mut default = None;
+    let mut directives = vec![];
+    let mut bad = vec![];
+
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        match entry.split_once('=') {
+            Some((module, level)) => match level.trim().parse() {
+                Ok(level) => directives.push(Directive {
+                    module: module.trim().to_owned(),
+                    level,
+                }),
+                Err(_) => bad.push(entry.to_owned()),
+            },
+            None => match entry.parse() {
+                Ok(level) => default = Some(level),
+                Err(_) => bad.push(entry.to_owned()),
+            },
+        }
+    }
+    (default, directives, bad)
+}
+
+/// Install the logger. Call once, before anything logs.
+pub fn init(default: LevelFilter, spec: Option<&str>) {
+    let (spec_default, directives, bad) = parse_spec(spec.unwrap_or(""));
+    let logger = Logger {
+        default: spec_default.unwrap_or(default),
+        directives,
+    };
+    log::set_max_level(logger.max_level());
+    if log::set_boxed_logger(Box::new(logger)).is_err() {
+        panic!("The logger was already set");
+    }
+
+    for entry in bad {
+        log::warn!(
+            "Ignoring `{}` in `PYFLOW_LOG`; expected eg `pyflow::install=debug`",
+            entry
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spec() {
+        let (default, directives, bad) =
+            parse_spec("warn, pyflow::dep_resolution=trace,pyflow::install = debug,nope=loud");
+        assert_eq!(default, Some(LevelFilter::Warn));
+        assert_eq!(
+            directives,
+            vec![
+                Directive {
+                    module: "pyflow::dep_resolution".into(),
+                    level: LevelFilter::Trace
+                },
+                Directive {
+                    module: "pyflow::install".into(),
+                    level: LevelFilter::Debug
+                },
+            ]
+        );
+        assert_eq!(bad, vec!["nope=loud".to_owned()]);
+    }
+
+    #[test]
+    fn module_levels() {
+        let logger = Logger {
+            default: LevelFilter::Info,
+            directives: vec![
+                Directive {
+                    module: "pyflow".into(),
+                    level: LevelFilter::Debug,
+                },
+                Directive {
+                    module: "pyflow::dep_resolution".into(),
+                    level: LevelFilter::Trace,
+                },
+            ],
+        };
+        assert_eq!(
+            logger.level_for("pyflow::dep_resolution::res"),
+            LevelFilter::Trace
+        );
+        assert_eq!(logger.level_for("pyflow::install"), LevelFilter::Debug);
+        assert_eq!(logger.level_for("pyflow_other"), LevelFilter::Info);
+        assert_eq!(logger.max_level(), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn flags() {
+        assert_eq!(level_from_flags(true, 2), LevelFilter::Warn);
+        assert_eq!(level_from_flags(false, 0), LevelFilter::Info);
+        assert_eq!(level_from_flags(false, 1), LevelFilter::Debug);
+        assert_eq!(level_from_flags(false, 5), LevelFilter::Trace);
+    }
+}
@@ -0,0 +1,302 @@
+//! Package metadata parsed from the index, kept on disk in a compact binary format, so resolving
+//! doesn't parse every release's version and `requires_python` from JSON again on each run.
+//! Entries are keyed by the index URL and `dep_types::SCHEMA_VERSION`, and hold a digest of the
+//! JSON they were parsed from, so they're replaced when the index's data changes. A corrupt
+//! entry fails its checksum, and is parsed again, and rewritten.
+
+use std::{fs, path::PathBuf, process};
+
+use ring::digest;
+
+use crate::{
+    dep_resolution::Candidates,
+    dep_types::{Constraint, ReqType, Version, VersionModifier, SCHEMA_VERSION},
+    util::paths,
+};
+
+const MAGIC: &[u8; 4] = b"PFMC";
+const DIGEST_LEN: usize = 32;
+
+fn sha256(data: &[u8]) -> Vec<u8> {
+    digest::digest(&digest::SHA256, data).as_ref().to_vec()
+}
+
+fn entry_path(url: &str) -> PathBuf {
+    let key = sha256(format!("{} {}", SCHEMA_VERSION, url).as_bytes());
+    paths::index_cache_path(&paths::pyflow_path())
+        .join("metadata")
+        .join(data_encoding::HEXLOWER.encode(&key[..16]))
+}
+
+/// The candidates cached for `url`, if they were parsed from this `raw` JSON.
+pub fn load(url: &str, raw: &str) -> Option<Candidates> {
+    decode(&fs::read(entry_path(url)).ok()?, &sha256(raw.as_bytes()))
+}
+
+/// Cache candidates parsed from the `raw` JSON at `url`. Failing to is only slower next time.
+pub fn store(url: &str, raw: &str, candidates: &Candidates) {
+    let path = entry_path(url);
+    let dir = match path.parent() {
+        Some(d) => d,
+        None => return,
+    };
+    // Written beside it, then renamed, so concurrent runs never see half an entry.
+    let temp = path.with_extension(process::id().to_string());
+    let data = encode(candidates, &sha256(raw.as_bytes()));
+    if fs::create_dir_all(dir).is_err()
+        || fs::write(&temp, data).is_err()
+        || fs::rename(&temp, &path).is_err()
+    {
+        let _ = fs::remove_file(&temp);
+        log::debug!("Problem caching metadata for {}", candidates.name);
+    }
+}
+
+/// `MAGIC`, the schema version, the raw JSON's digest, the payload's digest, then the payload.
+fn encode(candidates: &Candidates, raw_digest: &[u8]) -> Vec<u8> {
+    let mut payload = Writer::default();
+    payload.string(&candidates.name);
+    payload.u32(candidates.files.len() as u32);
+    for (version, python) in &candidates.files {
+        payload.version(version);
+        payload.u32(python.len() as u32);
+        for constraint in python {
+            payload.u8(req_type_code(constraint.type_));
+            payload.version(&constraint.version);
+        }
+    }
+
+    let mut result = MAGIC.to_vec();
+    result.extend(&SCHEMA_VERSION.to_le_bytes());
+    result.extend(raw_digest);
+    result.extend(sha256(&payload.0));
+    result.extend(payload.0);
+    result
+}
+
+fn decode(data: &[u8], raw_digest: &[u8]) -> Option<Candidates> {
+    let header = MAGIC.len() + 4 + 2 * DIGEST_LEN;
+    if data.len() < header || &data[..4] != MAGIC || data[4..8] != SCHEMA_VERSION.to_le_bytes() {
+        return None;
+    }
+    let (digests, payload) = data[8..].split_at(2 * DIGEST_LEN);
+    if &digests[..DIGEST_LEN] != raw_digest || digests[DIGEST_LEN..] != sha256(payload)[..] {
+        return None;
+    }
+
+    let mut r = Reader { data: payload };
+    let name = r.string()?;
+    let count = r.u32()?;
+    let mut files = Vec::new();
+    for _ in 0..count {
+        let version = r.version()?;
+        let mut python = Vec::new();
+        for _ in 0..r.u32()? {
+            let type_ = req_type(r.u8()?)?;
+            python.push(Constraint::new(type_, r.version()?));
+        }
+        files.push((version, python));
+    }
+    if !r.data.is_empty() {
+        return None;
+    }
+    Some(Candidates { name, files })
+}
+
+const REQ_TYPES: [ReqType; 9] = [
+    ReqType::Exact,
+    ReqType::Gte,
+    ReqType::Lte,
+    ReqType::Ne,
+    ReqType::Gt,
+    ReqType::Lt,
+    ReqType::Caret,
+    ReqType::Tilde,
+    ReqType::TildeEq,
+];
+
+fn req_type_code(type_: ReqType) -> u8 {
+    REQ_TYPES.iter().position(|t| *t == type_).unwrap_or(0) as u8
+}
+
+fn req_type(code: u8) -> Option<ReqType> {
+    REQ_TYPES.get(code as usize).copied()
+}
+
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, v: u8) {
+        self.0.push(v);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.0.extend(&v.to_le_bytes());
+    }
+
+    fn string(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.0.extend(s.as_bytes());
+    }
+
+    fn opt_u32(&mut self, v: Option<u32>) {
+        match v {
+            Some(n) => {
+                self.u8(1);
+                self.u32(n);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    fn version(&mut self, v: &Version) {
+        for n in &[v.major, v.minor, v.patch, v.extra_num] {
+            self.opt_u32(*n);
+        }
+        match &v.modifier {
+            None => self.u8(0),
+            Some((m, n)) => {
+                match m {
+                    VersionModifier::Alpha => self.u8(1),
+                    VersionModifier::Beta => self.u8(2),
+                    VersionModifier::ReleaseCandidate => self.u8(3),
+                    VersionModifier::Dep => self.u8(4),
+                    VersionModifier::Null => self.u8(5),
+                    VersionModifier::Other(s) => {
+                        self.u8(6);
+                        self.string(s);
+                    }
+                }
+                self.u32(*n);
+            }
+        }
+        self.u8(v.star as u8);
+        match &v.local {
+            Some(l) => {
+                self.u8(1);
+                self.string(l);
+            }
+            None => self.u8(0),
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.data.len() < n {
+            return None;
+        }
+        let (taken, rest) = self.data.split_at(n);
+        self.data = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Some(u32::from_le_bytes(bytes))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+
+    fn opt_u32(&mut self) -> Option<Option<u32>> {
+        match self.u8()? {
+            0 => Some(None),
+            1 => Some(Some(self.u32()?)),
+            _ => None,
+        }
+    }
+
+    fn version(&mut self) -> Option<Version> {
+        let (major, minor, patch, extra_num) = (
+            self.opt_u32()?,
+            self.opt_u32()?,
+            self.opt_u32()?,
+            self.opt_u32()?,
+        );
+        let modifier = match self.u8()? {
+            0 => None,
+            code => {
+                let m = match code {
+                    1 => VersionModifier::Alpha,
+                    2 => VersionModifier::Beta,
+                    3 => VersionModifier::ReleaseCandidate,
+                    4 => VersionModifier::Dep,
+                    5 => VersionModifier::Null,
+                    6 => VersionModifier::Other(self.string()?),
+                    _ => return None,
+                };
+                Some((m, self.u32()?))
+            }
+        };
+        let star = self.u8()? == 1;
+        let local = match self.u8()? {
+            0 => None,
+            1 => Some(self.string()?),
+            _ => return None,
+        };
+        Some(Version {
+            major,
+            minor,
+            patch,
+            extra_num,
+            modifier,
+            star,
+            local,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn round_trip_and_corruption() {
+        let candidates = Candidates {
+            name: "Django".into(),
+            files: vec![
+                (
+                    Version::from_str("4.2.11").unwrap(),
+                    Constraint::from_str_multiple(">=3.8").unwrap(),
+                ),
+                (
+                    Version {
+                        modifier: Some((VersionModifier::ReleaseCandidate, 1)),
+                        local: Some("local.7".into()),
+                        ..Version::new(5, 0, 0)
+                    },
+                    Constraint::from_str_multiple(">=3.10,!=3.11.0").unwrap(),
+                ),
+            ],
+        };
+        let raw = sha256(b"{\"info\": {}}");
+        let data = encode(&candidates, &raw);
+        let decoded = decode(&data, &raw).unwrap();
+        assert_eq!(decoded, candidates);
+        assert_eq!(decoded.files[1].0.local.as_deref(), Some("local.7"));
+
+        // The index's data changed.
+        assert!(decode(&data, &sha256(b"{}")).is_none());
+        // A flipped bit, or a truncated file.
+        let mut corrupt = data.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 1;
+        assert!(decode(&corrupt, &raw).is_none());
+        assert!(decode(&data[..data.len() - 3], &raw).is_none());
+    }
+}
@@ -1,6 +1,19 @@
+pub mod color;
 pub mod deps;
+pub mod gitignore;
+pub mod hooks;
+pub mod http;
+pub mod loaded;
+pub mod logging;
+pub mod metadata_cache;
 pub mod paths;
+pub mod patterns;
+pub mod pipeline;
 pub mod prompts;
+pub mod run_env;
+pub mod settings;
+#[cfg(test)]
+pub mod temp_dir;
 
 mod os;
 pub use os::{get_os, Os};
@@ -15,10 +28,13 @@ use crate::{
     dep_types::{Constraint, DependencyError, Lock, Req, ReqType, Version},
     files,
     install::{self, PackageType},
-    py_versions, util, CliConfig,
+    py_versions,
+    pyproject::EnvMode,
+    util, CliConfig,
 };
+use bzip2::read::BzDecoder;
+use flate2::read::GzDecoder;
 use ini::Ini;
-use regex::Regex;
 
 use std::fs;
 use std::io::{self, BufRead, BufReader, Read, Write};
@@ -31,8 +47,7 @@ use std::{
     process, thread, time,
 };
 use tar::Archive;
-use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
-use xz2::read::XzDecoder;
+use termcolor::Color;
 
 #[derive(Debug)]
 pub struct Paths {
@@ -40,6 +55,8 @@ pub struct Paths {
     pub lib: PathBuf,
     pub entry_pt: PathBuf,
     pub cache: PathBuf,
+    /// With `EnvMode::Venv`, `lib` is a venv's `site-packages`, shared with its own tools.
+    pub mode: EnvMode,
 }
 
 /// Used to store a Wheel's metadata, from dist-info/METADATA
@@ -51,97 +68,148 @@ pub struct Metadata {
     pub author: Option<String>,
     pub author_email: Option<String>,
     pub license: Option<String>,
+    /// An SPDX expression, from newer metadata; preferred to `license`.
+    pub license_expression: Option<String>,
+    pub classifiers: Vec<String>,
     pub keywords: Vec<String>,
     pub platform: Option<String>,
+    pub home_page: Option<String>,
+    pub requires_python: Option<String>,
     pub requires_dist: Vec<Req>,
+    pub provides_extra: Vec<String>,
 }
 
 /// Print line in a color, then reset formatting.
 pub fn print_color(message: &str, color: Color) {
-    if let Err(_e) = print_color_res(message, color) {
+    if let Err(_e) = color::write_color(&mut color::message_stream(), message, color, true) {
         panic!("Error printing in color");
     }
 }
 
-fn print_color_res(message: &str, color: Color) -> io::Result<()> {
-    let mut stdout = StandardStream::stdout(CliConfig::current().color_choice);
-    stdout.set_color(ColorSpec::new().set_fg(Some(color)))?;
-    writeln!(&mut stdout, "{}", message)?;
-    stdout.reset()?;
-    Ok(())
-}
-
 /// Print in a color, then reset formatting. (no newline)
 pub fn print_color_(message: &str, color: Color) {
-    if let Err(_e) = print_color_res_(message, color) {
+    if let Err(_e) = color::write_color(&mut color::message_stream(), message, color, false) {
         panic!("Error printing in color")
     }
 }
 
-fn print_color_res_(message: &str, color: Color) -> io::Result<()> {
-    let mut stdout = StandardStream::stdout(CliConfig::current().color_choice);
-    stdout.set_color(ColorSpec::new().set_fg(Some(color)))?;
-    write!(&mut stdout, "{}", message)?;
-    stdout.reset()?;
-    Ok(())
+/// A progress or completion message, like `Installing Python 3.12...`, which `-q` hides. What a
+/// command was run to show, and warnings, use `print_color`, and are always shown.
+pub fn print_status(message: &str, color: Color) {
+    if log::log_enabled!(target: "pyflow", log::Level::Info) {
+        print_color(message, color)
+    }
+}
+
+/// Print a line without formatting, to the same stream as `print_color`.
+pub fn print_plain(message: &str) {
+    if let Err(_e) = writeln!(&mut color::message_stream(), "{}", message) {
+        panic!("Error printing")
+    }
+}
+
+/// Print `--json` output; the only thing written to stdout in that mode.
+pub fn print_json<T: serde::Serialize>(value: &T) {
+    match serde_json::to_string_pretty(value) {
+        Ok(s) => println!("{}", s),
+        Err(_) => abort("Problem serializing output"),
+    }
 }
 
 /// Used when the program should exit from a condition that may arise normally from program use,
 /// like incorrect info in config files, problems with dependencies, or internet connection problems.
 /// We use `expect`, `panic!` etc for problems that indicate a bug in this program.
+/// The message always goes to stderr, whatever the log level.
 pub fn abort(message: &str) -> ! {
-    print_color(message, Color::Red);
+    color::write_color(&mut color::error_stream(), message, Color::Red, true).unwrap_or(());
     process::exit(1)
 }
 
 pub fn success(message: &str) {
-    print_color(message, Color::Green);
+    print_status(message, Color::Green);
     process::exit(0)
 }
 
 /// Find which virtual environments exist.
 pub fn find_venvs(pypackages_dir: &Path) -> Vec<(u32, u32)> {
-    let py_versions: &[(u32, u32)] = &[
-        (2, 6),
-        (2, 7),
-        (2, 8),
-        (2, 9),
-        (3, 0),
-        (3, 1),
-        (3, 2),
-        (3, 3),
-        (3, 4),
-        (3, 5),
-        (3, 6),
-        (3, 7),
-        (3, 8),
-        (3, 9),
-        (3, 10),
-        (3, 11),
-        (3, 12),
-    ];
-
     let mut result = vec![];
-    for (maj, mi) in py_versions.iter() {
-        let venv_path = pypackages_dir.join(&format!("{}.{}/.venv", maj, mi));
+    if let Ok(entries) = pypackages_dir.read_dir() {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let caps = match patterns::PYPACKAGES_VERSION_RE.captures(&name) {
+                Some(c) => c,
+                None => continue,
+            };
+            let (maj, mi) = match (caps[1].parse(), caps[2].parse()) {
+                (Ok(maj), Ok(mi)) => (maj, mi),
+                _ => continue,
+            };
 
-        if venv_path.join("bin/python").exists() || venv_path.join("Scripts/python.exe").exists() {
-            result.push((*maj, *mi))
+            let venv_path = entry.path().join(".venv");
+            if venv_path.join("bin/python").exists()
+                || venv_path.join("Scripts/python.exe").exists()
+            {
+                result.push((maj, mi))
+            }
         }
     }
 
+    result.sort_unstable();
+    result
+}
+
+/// Resolve `.` and `..` in a path without touching the filesystem, so `RECORD` paths like
+/// `../bin/black` compare equal to walked ones.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for comp in path.components() {
+        match comp {
+            Component::ParentDir => {
+                result.pop();
+            }
+            Component::CurDir => (),
+            c => result.push(c),
+        }
+    }
     result
 }
 
 /// Checks whether the path is under `/bin` (Linux generally) or `/Scripts` (Windows generally)
 /// Returns the bin path (ie under the venv)
 pub fn find_bin_path(vers_path: &Path) -> PathBuf {
+    venv_bin_path(&vers_path.join(".venv"))
+}
+
+/// The bin path of a virtual environment at any path, eg one made for tox.
+pub fn venv_bin_path(venv_path: &Path) -> PathBuf {
     #[cfg(target_os = "windows")]
-    return vers_path.join(".venv/Scripts");
+    return venv_path.join("Scripts");
     #[cfg(target_os = "linux")]
-    return vers_path.join(".venv/bin");
+    return venv_path.join("bin");
     #[cfg(target_os = "macos")]
-    return vers_path.join(".venv/bin");
+    return venv_path.join("bin");
+}
+
+/// Files named `name` on the `PATH`, in the order a shell would find them.
+pub fn find_on_path(name: &str) -> Vec<PathBuf> {
+    let names = if cfg!(target_os = "windows") {
+        vec![format!("{}.exe", name), name.to_owned()]
+    } else {
+        vec![name.to_owned()]
+    };
+
+    let mut result = vec![];
+    if let Some(path_var) = env::var_os("PATH") {
+        for dir in env::split_paths(&path_var) {
+            for name in &names {
+                let path = dir.join(name);
+                if path.is_file() && !result.contains(&path) {
+                    result.push(path);
+                }
+            }
+        }
+    }
+    result
 }
 
 /// Wait for directories to be created; required between modifying the filesystem,
@@ -167,14 +235,130 @@ pub fn wait_for_dirs(dirs: &[PathBuf]) -> Result<(), crate::py_versions::AliasEr
 }
 
 /// Sets the `PYTHONPATH` environment variable, causing Python to look for
-/// dependencies in `__pypackages__`,
+/// dependencies in `__pypackages__`. It's inherited by everything we run, and what that runs,
+/// eg `multiprocessing` and `pytest-xdist` workers. Paths already in it are kept, after ours.
 pub fn set_pythonpath(paths: &[PathBuf]) {
-    let formatted_paths = paths
-        .iter()
-        .map(|p| p.to_str().unwrap())
-        .collect::<Vec<&str>>()
-        .join(":");
-    env::set_var("PYTHONPATH", formatted_paths);
+    env::set_var(
+        "PYTHONPATH",
+        merge_pythonpath(paths, env::var_os("PYTHONPATH").as_deref()),
+    );
+}
+
+/// `paths`, then those in `existing` that aren't among them, joined with the platform's
+/// separator.
+fn merge_pythonpath(paths: &[PathBuf], existing: Option<&std::ffi::OsStr>) -> std::ffi::OsString {
+    let mut result = paths.to_vec();
+    for path in existing.map(env::split_paths).into_iter().flatten() {
+        if !path.as_os_str().is_empty() && !result.contains(&path) {
+            result.push(path);
+        }
+    }
+    env::join_paths(&result).unwrap_or_else(|e| {
+        abort(&format!(
+            "Can't put {:?} on the `PYTHONPATH`: {}",
+            result, e
+        ))
+    })
+}
+
+/// The file in the environment's `site-packages` that puts `__pypackages__` on the path, for
+/// interpreters started without our `PYTHONPATH`, eg by a subprocess given its own environment.
+const PTH_FILENAME: &str = "_pyflow_pypackages.pth";
+
+/// Have the environment at `vers_path` put `paths` on the path of any interpreter started from
+/// it, with a `.pth` file, like `PYTHONPATH` does for those we start. Only written if it changed.
+/// The first of `paths`, `lib`, is also added as a site directory, so `.pth` files packages
+/// install there, eg for coverage hooks, take effect; Python ignores them in `PYTHONPATH`.
+pub fn write_pth(vers_path: &Path, paths: &[PathBuf]) {
+    let site_packages = match find_site_packages(vers_path) {
+        Some(s) => s,
+        None => return,
+    };
+    let mut contents: String = paths.iter().map(|p| format!("{}\n", p.display())).collect();
+    // With `env-mode = "venv"`, `lib` is `site-packages` itself, which Python already reads.
+    if let Some(lib) = paths.first().filter(|lib| **lib != site_packages) {
+        contents.push_str(&format!(
+            "import site; site.addsitedir({})\n",
+            python_str(&lib.to_string_lossy())
+        ));
+    }
+    let path = site_packages.join(PTH_FILENAME);
+    if fs::read_to_string(&path).ok().as_deref() == Some(contents.as_str()) {
+        return;
+    }
+    if let Err(e) = fs::write(&path, contents) {
+        log::warn!(
+            "Problem writing {:?}, so subprocesses that reset their environment won't find \
+             `__pypackages__`: {}",
+            path,
+            e
+        );
+    }
+}
+
+/// `s` as a Python string literal. Only ASCII is written, since Python may read a `.pth` file in
+/// the locale's encoding.
+fn python_str(s: &str) -> String {
+    let mut result = String::from("'");
+    for c in s.chars() {
+        match c {
+            '\\' | '\'' => {
+                result.push('\\');
+                result.push(c);
+            }
+            ' '..='~' => result.push(c),
+            c if (c as u32) <= 0xff => result.push_str(&format!("\\x{:02x}", c as u32)),
+            c if (c as u32) <= 0xffff => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push_str(&format!("\\U{:08x}", c as u32)),
+        }
+    }
+    result.push('\'');
+    result
+}
+
+/// Where `pip install --user` puts packages for Python `version`, as `site.getusersitepackages`
+/// finds it: under `PYTHONUSERBASE`, or the platform's default. macOS framework builds use
+/// `~/Library/Python` instead, which we don't look for.
+pub fn user_site_packages(version: &Version) -> Option<PathBuf> {
+    let (major, minor) = (version.major?, version.minor?);
+    let base = env::var_os("PYTHONUSERBASE").map(PathBuf::from);
+    #[cfg(target_os = "windows")]
+    {
+        let base =
+            base.or_else(|| env::var_os("APPDATA").map(|a| PathBuf::from(a).join("Python")))?;
+        Some(
+            base.join(format!("Python{}{}", major, minor))
+                .join("site-packages"),
+        )
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        let base = base.or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".local")))?;
+        Some(
+            base.join("lib")
+                .join(format!("python{}.{}", major, minor))
+                .join("site-packages"),
+        )
+    }
+}
+
+/// The environment's `site-packages`: `.venv/Lib/site-packages` on Windows, and
+/// `.venv/lib/pythonX.Y/site-packages` elsewhere.
+pub fn find_site_packages(vers_path: &Path) -> Option<PathBuf> {
+    venv_site_packages(&vers_path.join(".venv"))
+}
+
+/// The `site-packages` of a virtual environment at any path.
+pub fn venv_site_packages(venv_path: &Path) -> Option<PathBuf> {
+    let windows = venv_path.join("Lib/site-packages");
+    if windows.is_dir() {
+        return Some(windows);
+    }
+    fs::read_dir(venv_path.join("lib"))
+        .ok()?
+        .flatten()
+        .map(|e| e.path().join("site-packages"))
+        .find(|p| p.is_dir())
 }
 
 /// Find the packages installed, by browsing the lib folder for metadata.
@@ -187,9 +371,7 @@ pub fn find_installed(lib_path: &Path) -> Vec<(String, Version, Vec<String>)> {
     let mut result = vec![];
 
     for folder_name in &find_folders(lib_path) {
-        let re_dist = Regex::new(r"^(.*?)-(.*?)\.dist-info$").unwrap();
-
-        if let Some(caps) = re_dist.captures(folder_name) {
+        if let Some(caps) = patterns::DIST_INFO_RE.captures(folder_name) {
             let name = caps.get(1).unwrap().as_str();
             let vers = Version::from_str(
                 caps.get(2)
@@ -215,52 +397,77 @@ pub fn find_installed(lib_path: &Path) -> Vec<(String, Version, Vec<String>)> {
     }
     result
 }
+
+/// Allow skipping dev dependencies via the environment, eg in CI or Docker builds.
+pub fn no_dev_from_env() -> bool {
+    env_flag("PYFLOW_NO_DEV")
+}
+
+/// The HTTP client shared by this invocation, through the `proxy` setting if there is one.
+pub fn http_client() -> reqwest::blocking::Client {
+    http::client()
+}
+
+/// A package's JSON metadata on the configured index; the latest version's, if `version` is
+/// `None`.
+pub fn index_json_url(name: &str, version: Option<&str>) -> String {
+    CliConfig::current().settings.json_url(name, version)
+}
+
+/// The configured index's simple API, which lists every project.
+pub fn index_simple_url() -> String {
+    CliConfig::current().settings.simple_url()
+}
+
+fn env_flag(name: &str) -> bool {
+    match env::var(name) {
+        Ok(v) => !matches!(v.to_lowercase().as_str(), "" | "0" | "false" | "no"),
+        Err(_) => false,
+    }
+}
+
 /// Handle reqs added via the CLI. Result is (normal reqs, dev reqs)
 pub fn merge_reqs(
-    added: &[String],
+    added_reqs: Vec<Req>,
     dev: bool,
     cfg: &crate::Config,
     cfg_path: &Path,
 ) -> (Vec<Req>, Vec<Req>) {
-    let mut added_reqs = vec![];
-    for p in added.iter() {
-        let trimmed = p.replace(',', "");
-        match Req::from_str(&trimmed, false) {
-            Ok(r) => added_reqs.push(r),
-            Err(_) => abort(&format!("Unable to parse this package: {}. \
-                    Note that installing a specific version via the CLI is currently unsupported. If you need to specify a version,\
-                     edit `pyproject.toml`", &p)),
-        }
-    }
-
     let existing = if dev { &cfg.dev_reqs } else { &cfg.reqs };
 
-    // Reqs to add to `pyproject.toml`
-    let mut added_reqs_unique: Vec<Req> = added_reqs
-        .into_iter()
-        .filter(|ar| {
-            // return true if the added req's not in the cfg reqs, or if it is
-            // and the version's different.
-            let mut add = true;
-
-            for cr in existing.iter() {
-                if cr == ar
-                    || (cr.name.to_lowercase() == ar.name.to_lowercase()
-                        && ar.constraints.is_empty())
-                {
-                    // Same req/version exists
-                    add = false;
-                    break;
-                }
+    // Reqs to add to `pyproject.toml`. If the package's already in the config, keep its
+    // constraints unless new ones were specified, and merge extras instead of replacing them.
+    let mut added_reqs_unique: Vec<Req> = vec![];
+    for mut ar in added_reqs.into_iter() {
+        let from_source = ar.path.is_some() || ar.git.is_some();
+        if let Some(cr) = existing.iter().find(|cr| compare_names(&cr.name, &ar.name)) {
+            // Leave path and git dependencies alone, unless a new source was given.
+            if (cr.path.is_some() || cr.git.is_some()) && !from_source {
+                continue;
             }
-            add
-        })
-        .collect();
+            ar.name = cr.name.clone();
+            if ar.constraints.is_empty() {
+                ar.constraints = cr.constraints.clone();
+            }
+            ar.install_with_extras = merge_extras(&cr.install_with_extras, &ar.install_with_extras);
+
+            if ar.constraints == cr.constraints
+                && ar.install_with_extras == cr.install_with_extras
+                && ar.path == cr.path
+                && ar.git == cr.git
+                && ar.git_ref == cr.git_ref
+            {
+                // Same req/version exists
+                continue;
+            }
+        }
+        added_reqs_unique.push(ar);
+    }
 
     // If no constraints are specified, use a caret constraint with the latest
     // version.
     for added_req in &mut added_reqs_unique {
-        if added_req.constraints.is_empty() {
+        if added_req.constraints.is_empty() && added_req.path.is_none() && added_req.git.is_none() {
             let (_, vers, _) = if let Ok(r) = res::get_version_info(
                 &added_req.name,
                 Some(Req::new_with_extras(
@@ -287,24 +494,22 @@ pub fn merge_reqs(
 
     let mut result = vec![]; // Reqs to sync
 
-    // Merge reqs from the config and added via CLI. If there's a conflict in version,
-    // use the added req.
+    // Merge reqs from the config and added via CLI. If there's a conflict, use the added req.
     for cr in existing.iter() {
-        let mut replaced = false;
-        for added_req in &added_reqs_unique {
-            if compare_names(&added_req.name, &cr.name) && added_req.constraints != cr.constraints {
-                result.push(added_req.clone());
-                replaced = true;
-                break;
-            }
+        match added_reqs_unique
+            .iter()
+            .find(|ar| compare_names(&ar.name, &cr.name))
+        {
+            Some(ar) => result.push(ar.clone()),
+            None => result.push(cr.clone()),
         }
-        if !replaced {
-            result.push(cr.clone());
+    }
+    for ar in &added_reqs_unique {
+        if !existing.iter().any(|cr| compare_names(&ar.name, &cr.name)) {
+            result.push(ar.clone());
         }
     }
 
-    result.append(&mut added_reqs_unique.clone());
-
     if dev {
         if !added_reqs_unique.is_empty() {
             files::add_reqs_to_cfg(cfg_path, &[], &added_reqs_unique);
@@ -318,6 +523,43 @@ pub fn merge_reqs(
     }
 }
 
+/// Parse packages passed on the command line.
+pub fn parse_cli_reqs(added: &[String]) -> Vec<Req> {
+    let mut added_reqs = vec![];
+    for p in added.iter() {
+        // Allow comma-separated package lists, eg `pyflow install numpy, scipy`.
+        let trimmed = p.trim_end_matches(',');
+        match Req::from_cli_str(trimmed) {
+            Ok(r) => added_reqs.push(r),
+            Err(_) => abort(&format!(
+                "Unable to parse this package: {}. Use the form `name`, `name>=1.0`, \
+                 or `name[extra1,extra2]>=1.0`; quote it so your shell doesn't interpret `>` or `[`",
+                &p
+            )),
+        }
+    }
+    added_reqs
+}
+
+/// Combine extras from an existing requirement with newly-requested ones, preserving order
+/// and ignoring duplicates.
+pub fn merge_extras(
+    existing: &Option<Vec<String>>,
+    added: &Option<Vec<String>>,
+) -> Option<Vec<String>> {
+    let mut result: Vec<String> = existing.clone().unwrap_or_default();
+    for extra in added.iter().flatten() {
+        if !result.iter().any(|e| compare_names(e, extra)) {
+            result.push(extra.clone());
+        }
+    }
+    if result.is_empty() {
+        None
+    } else {
+        Some(result)
+    }
+}
+
 pub fn standardize_name(name: &str) -> String {
     name.to_lowercase().replace(['-', '.'], "_")
 }
@@ -327,7 +569,75 @@ pub fn compare_names(name1: &str, name2: &str) -> bool {
     standardize_name(name1) == standardize_name(name2)
 }
 
-/// Extract the wheel or zip.
+/// Names in `candidates` that look like `name`, eg it misspelled, closest first.
+pub fn close_matches(name: &str, candidates: &[String]) -> Vec<String> {
+    let name = standardize_name(name);
+    let mut scored: Vec<(usize, &String)> = candidates
+        .iter()
+        .filter_map(|c| {
+            let std_c = standardize_name(c);
+            let distance = edit_distance(&name, &std_c);
+            let close = distance <= 2.max(name.len() / 4)
+                || (name.len() >= 3 && (std_c.contains(&name) || name.contains(&std_c)));
+            if close {
+                Some((distance, c))
+            } else {
+                None
+            }
+        })
+        .collect();
+    scored.sort();
+    scored.into_iter().map(|(_, c)| c.clone()).collect()
+}
+
+/// Levenshtein distance, by character.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = prev[j] + usize::from(ca != *cb);
+            current.push(substitute.min(prev[j + 1] + 1).min(current[j] + 1));
+        }
+        prev = current;
+    }
+    prev[b.len()]
+}
+
+/// Total size of the files under `path`, not following symlinks.
+pub fn dir_size(path: &Path) -> u64 {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(m) => m,
+        Err(_) => return 0,
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    match fs::read_dir(path) {
+        Ok(entries) => entries.flatten().map(|e| dir_size(&e.path())).sum(),
+        Err(_) => 0,
+    }
+}
+
+/// Eg `1.5 MB`.
+pub fn format_size(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024. && unit < units.len() - 1 {
+        size /= 1024.;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+/// Extract the wheel or zip. Entries are decompressed straight into their files, a chunk at a
+/// time, so memory use doesn't grow with their size.
 /// From [this example](https://github.com/mvdnes/zip-rs/blob/master/examples/extract.rs#L32)
 pub fn extract_zip(
     file: &fs::File,
@@ -394,8 +704,25 @@ pub fn extract_zip(
                     fs::create_dir_all(p).unwrap();
                 }
             }
-            let mut outfile = fs::File::create(&outpath).unwrap();
-            io::copy(&mut file, &mut outfile).unwrap();
+            if outpath.exists() && outpath.file_name().is_some_and(|n| n == "__init__.py") {
+                let mut data = vec![];
+                file.read_to_end(&mut data).unwrap();
+                // Another member of a namespace package, eg `google-cloud-pubsub` after
+                // `google-cloud-storage`, already has one; keep it.
+                if install::is_namespace_init(&String::from_utf8_lossy(&data)) {
+                    continue;
+                }
+                fs::remove_file(&outpath).unwrap_or(());
+                fs::write(&outpath, data).unwrap();
+            } else {
+                // It may be hardlinked from the read-only extracted cache; replace it, instead of
+                // writing through to the cache's copy.
+                if outpath.exists() {
+                    fs::remove_file(&outpath).unwrap_or(());
+                }
+                let mut outfile = fs::File::create(&outpath).unwrap();
+                io::copy(&mut file, &mut outfile).unwrap();
+            }
         }
 
         // Get and Set permissions
@@ -410,37 +737,75 @@ pub fn extract_zip(
     }
 }
 
-pub fn unpack_tar_xz(archive_path: &Path, dest: &Path) {
-    let archive_bytes = fs::read(archive_path).expect("Problem reading archive as bytes");
-
-    let mut tar: Vec<u8> = Vec::new();
-    let mut decompressor = XzDecoder::new(&archive_bytes[..]);
-    if decompressor.read_to_end(&mut tar).is_err() {
+pub fn unpack_tar_gz(archive_path: &Path, dest: &Path) {
+    let archive_file = open_archive(archive_path);
+    let mut archive = Archive::new(GzDecoder::new(&archive_file));
+    if archive.unpack(dest).is_err() {
         abort(&format!(
-            "Problem decompressing the archive: {:?}. This may be due to a failed download. \
-        Try deleting it, then try again. Note that Pyflow will only install officially-released \
-        Python versions. If you'd like to use a pre-release, you must install it manually.",
+            "Problem unpacking the archive: {:?}. This may be due to a failed download. \
+             Try deleting it, then try again.",
             archive_path
         ))
     }
+}
 
-    // We've decompressed the .xz; now unpack the tar.
-    let mut archive = Archive::new(&tar[..]);
+pub fn unpack_tar_bz2(archive_path: &Path, dest: &Path) {
+    let archive_file = open_archive(archive_path);
+    let mut archive = Archive::new(BzDecoder::new(&archive_file));
     if archive.unpack(dest).is_err() {
         abort(&format!(
-            "Problem unpacking tar: {}",
-            archive_path.to_str().unwrap()
+            "Problem unpacking the archive: {:?}. This may be due to a failed download. \
+             Try deleting it, then try again.",
+            archive_path
         ))
     }
 }
 
-/// Find venv info, creating a venv as required.
+/// Find venv info, creating a venv as required. With `EnvMode::Venv`, `pypackages_dir` is the
+/// project root, which has the `.venv`, and is returned in place of a version folder.
+#[allow(clippy::too_many_arguments)]
 pub fn find_or_create_venv(
     cfg_vers: &Version,
     pypackages_dir: &Path,
     pyflow_dir: &Path,
     dep_cache_path: &Path,
+    python_override: Option<&Path>,
+    implementation: py_versions::Implementation,
+    python_requires: &[Constraint],
+    mode: EnvMode,
 ) -> (PathBuf, Version) {
+    if mode == EnvMode::Venv {
+        return find_or_create_root_venv(
+            cfg_vers,
+            pypackages_dir,
+            pyflow_dir,
+            dep_cache_path,
+            python_override,
+            implementation,
+            python_requires,
+        );
+    }
+
+    // Packages installed for one implementation or version may not work with another, so start
+    // over if `py_version` no longer matches the environment.
+    let cfg_vers_path = pypackages_dir.join(cfg_vers.to_string_med());
+    if cfg_vers_path.join(".venv").exists()
+        && py_versions::venv_is_stale(&cfg_vers_path, cfg_vers, implementation)
+    {
+        print_color(
+            &format!(
+                "Rebuilding the Python {} environment with {} {}",
+                cfg_vers.to_string_med(),
+                implementation,
+                cfg_vers
+            ),
+            Color::Yellow,
+        );
+        if fs::remove_dir_all(&cfg_vers_path).is_err() {
+            abort(&format!("Problem removing {:?}", cfg_vers_path))
+        }
+    }
+
     let venvs = find_venvs(pypackages_dir);
     // The version's explicitly specified; check if an environment for that version
     let compatible_venvs: Vec<&(u32, u32)> = venvs
@@ -452,8 +817,16 @@ pub fn find_or_create_venv(
     let py_vers;
     match compatible_venvs.len() {
         0 => {
-            let vers =
-                py_versions::create_venv(cfg_vers, pypackages_dir, pyflow_dir, dep_cache_path);
+            let vers = py_versions::create_venv(
+                cfg_vers,
+                pypackages_dir,
+                pyflow_dir,
+                dep_cache_path,
+                python_override,
+                implementation,
+                python_requires,
+                EnvMode::Pypackages,
+            );
             vers_path = pypackages_dir.join(vers.to_string_med());
             py_vers = Version::new_opt(vers.major, vers.minor, None); // Don't include patch.
         }
@@ -463,6 +836,9 @@ pub fn find_or_create_venv(
                 compatible_venvs[0].0, compatible_venvs[0].1
             ));
             py_vers = Version::new_short(compatible_venvs[0].0, compatible_venvs[0].1);
+            if let Some(warning) = py_versions::check_python_requires(&vers_path, python_requires) {
+                print_color(&warning, Color::Yellow);
+            }
         }
         _ => {
             abort(
@@ -483,13 +859,10 @@ pub fn find_or_create_venv(
         let vers_path = fs::canonicalize(vers_path);
         let vers_path = match vers_path {
             Ok(path) => path,
-            Err(error) => {
-                abort(&format!(
-                    "Problem converting path to absolute path: {:?}",
-                    error
-                ));
-                unreachable!()
-            }
+            Err(error) => abort(&format!(
+                "Problem converting path to absolute path: {:?}",
+                error
+            )),
         };
         (vers_path, py_vers)
     }
@@ -508,6 +881,67 @@ pub fn find_or_create_venv(
     }
 }
 
+/// With `env-mode = "venv"`: the `.venv` in the project root, `root`, rebuilt if it's for
+/// another Python than `py_version`, and created if it doesn't exist. One made some other way is
+/// used as-is if its version matches.
+fn find_or_create_root_venv(
+    cfg_vers: &Version,
+    root: &Path,
+    pyflow_dir: &Path,
+    dep_cache_path: &Path,
+    python_override: Option<&Path>,
+    implementation: py_versions::Implementation,
+    python_requires: &[Constraint],
+) -> (PathBuf, Version) {
+    let venv_path = root.join(".venv");
+    if venv_path.exists() {
+        let same_minor = py_versions::venv_version(root)
+            .is_some_and(|v| v.major == cfg_vers.major && v.minor == cfg_vers.minor);
+        if !same_minor || py_versions::venv_is_stale(root, cfg_vers, implementation) {
+            print_color(
+                &format!(
+                    "Rebuilding {:?} with {} {}",
+                    venv_path, implementation, cfg_vers
+                ),
+                Color::Yellow,
+            );
+            if fs::remove_dir_all(&venv_path).is_err() {
+                abort(&format!("Problem removing {:?}", venv_path))
+            }
+        }
+    }
+
+    let py_vers = if venv_path.exists() {
+        if let Some(warning) = py_versions::check_python_requires(root, python_requires) {
+            print_color(&warning, Color::Yellow);
+        }
+        Version::new_opt(cfg_vers.major, cfg_vers.minor, None)
+    } else {
+        let vers = py_versions::create_venv(
+            cfg_vers,
+            root,
+            pyflow_dir,
+            dep_cache_path,
+            python_override,
+            implementation,
+            python_requires,
+            EnvMode::Venv,
+        );
+        Version::new_opt(vers.major, vers.minor, None) // Don't include patch.
+    };
+
+    #[cfg(target_os = "windows")]
+    let root = root.to_owned();
+    #[cfg(not(target_os = "windows"))]
+    let root = fs::canonicalize(root).unwrap_or_else(|error| {
+        abort(&format!(
+            "Problem converting path to absolute path: {:?}",
+            error
+        ))
+    });
+    (root, py_vers)
+}
+
 ///// Remove all files (but not folders) in a path.
 //pub fn wipe_dir(path: &Path) {
 //    if !path.exists() {
@@ -541,8 +975,7 @@ fn os_from_wheel_fname(filename: &str) -> Result<Os, DependencyError> {
     // Also works with formats like this:
     // `PyQt5-5.13.0-5.13.0-cp35.cp36.cp37.cp38-none-win32.whl` too.
     // The point is, pull the last part before ".whl".
-    let re = Regex::new(r"^(?:.*?-)+(.*).whl$").unwrap();
-    if let Some(caps) = re.captures(filename) {
+    if let Some(caps) = patterns::WHEEL_PLATFORM_RE.captures(filename) {
         let parsed = caps.get(1).unwrap().as_str();
         return Ok(
             Os::from_str(parsed).unwrap_or_else(|_| panic!("Problem parsing Os: {}", parsed))
@@ -552,19 +985,48 @@ fn os_from_wheel_fname(filename: &str) -> Result<Os, DependencyError> {
     Err(DependencyError::new("Problem parsing os from wheel name"))
 }
 
-/// Find the most appropriate release to download. Ie Windows vs Linux, wheel vs source.
+/// Check a wheel's interpreter and ABI tags against the implementation we're installing for.
+/// Eg `cp310` and `abi3` wheels only work on CPython, and `pp310` / `pypy310_pp73` ones only on
+/// PyPy. Pure-Python `py3-none` wheels work on either.
+fn wheel_supports_implementation(
+    filename: &str,
+    implementation: py_versions::Implementation,
+) -> bool {
+    // Format is "name-version(-build)?-pythontags-abi-platform.whl"
+    let parts: Vec<&str> = filename.trim_end_matches(".whl").rsplitn(4, '-').collect();
+    if parts.len() < 4 {
+        return true;
+    }
+    let (abi, py_tags) = (parts[1], parts[2]);
+    let has_tag = |prefix: &str| py_tags.split('.').any(|t| t.starts_with(prefix));
+
+    match implementation {
+        py_versions::Implementation::CPython => {
+            !abi.starts_with("pypy") && (has_tag("cp") || has_tag("py"))
+        }
+        py_versions::Implementation::PyPy => {
+            (abi == "none" || abi.starts_with("pypy")) && (has_tag("pp") || has_tag("py"))
+        }
+    }
+}
+
+/// Find the most appropriate release to download. Ie Windows vs Linux, wheel vs source. Reports
+/// there being none, instead of aborting, since it's called from worker threads.
 pub fn find_best_release(
     data: &[WarehouseRelease],
     name: &str,
     version: &Version,
     os: Os,
     python_vers: &Version,
-) -> (WarehouseRelease, PackageType) {
+    implementation: py_versions::Implementation,
+) -> Result<(WarehouseRelease, PackageType), String> {
     // Find which release we should download. Preferably wheels, and if so, for the right OS and
     // Python version.
     let mut compatible_releases = vec![];
     // Store source releases as a fallback, for if no wheels are found.
     let mut source_releases = vec![];
+    // Wheels that would otherwise work, but are built for a different implementation.
+    let mut wrong_implementation = false;
 
     for rel in data.iter() {
         let mut compatible = true;
@@ -573,8 +1035,9 @@ pub fn find_best_release(
                 // Now determine if this wheel is appropriate for the Os and Python version.
                 if let Some(py_ver) = &rel.requires_python {
                     // If a version constraint exists, make sure it's compatible.
-                    let py_constrs = Constraint::from_str_multiple(py_ver)
-                        .expect("Problem parsing constraint from requires_python");
+                    let py_constrs = Constraint::from_str_multiple(py_ver).map_err(|_| {
+                        format!("Problem parsing `requires_python` for {}: {}", name, py_ver)
+                    })?;
 
                     for constr in &py_constrs {
                         if !constr.is_compatible(python_vers) {
@@ -583,12 +1046,17 @@ pub fn find_best_release(
                     }
                 }
 
-                let wheel_os =
-                    os_from_wheel_fname(&rel.filename).expect("Problem getting os from wheel name");
+                let wheel_os = os_from_wheel_fname(&rel.filename)
+                    .map_err(|_| format!("Problem getting the OS from {}", rel.filename))?;
                 if wheel_os != os && wheel_os != Os::Any {
                     compatible = false;
                 }
 
+                if compatible && !wheel_supports_implementation(&rel.filename, implementation) {
+                    wrong_implementation = true;
+                    compatible = false;
+                }
+
                 // Packages that use C code(eg numpy) may fail to load C extensions if installing
                 // for the wrong version of python (eg  cp35 when python 3.7 is installed), even
                 // if `requires_python` doesn't indicate an incompatibility. Check `python_version`
@@ -605,7 +1073,7 @@ pub fn find_best_release(
                         compatible = false;
                     }
                 } else {
-                    println!(
+                    log::debug!(
                         "Unable to match python version from python_version: {}",
                         &rel.python_version
                     )
@@ -618,7 +1086,7 @@ pub fn find_best_release(
             "sdist" => source_releases.push(rel.clone()),
             "bdist_wininst" | "bdist_msi" | "bdist_egg" => (), // Don't execute Windows installers
             _ => {
-                println!("Found surprising package type: {}", rel.packagetype);
+                log::debug!("Found surprising package type: {}", rel.packagetype);
                 continue;
             }
         }
@@ -629,12 +1097,30 @@ pub fn find_best_release(
     // TODO: Sort further / try to match exact python_version if able.
     if compatible_releases.is_empty() {
         if source_releases.is_empty() {
-            abort(&format!(
+            if wrong_implementation {
+                return Err(format!(
+                    "{} {} only has wheels for other Python implementations than {}, and no \
+                     source distribution to build from",
+                    name,
+                    version.to_string_color(),
+                    implementation
+                ));
+            }
+            return Err(format!(
                 "Unable to find a compatible release for {}: {}",
                 name,
                 version.to_string_color()
-            ))
+            ));
         } else {
+            if wrong_implementation {
+                print_color(
+                    &format!(
+                        "No {} wheels found for {} {}; building it from source",
+                        implementation, name, version
+                    ),
+                    Color::Yellow,
+                );
+            }
             best_release = source_releases[0].clone();
             package_type = install::PackageType::Source;
         }
@@ -643,7 +1129,7 @@ pub fn find_best_release(
         package_type = install::PackageType::Wheel;
     }
 
-    (best_release, package_type)
+    Ok((best_release, package_type))
 }
 
 /// Find the global git config's user and email, and format it to go in the config's `authors` field.
@@ -705,25 +1191,67 @@ pub fn open_archive(path: &Path) -> fs::File {
 
 /// Parse a wheel's `METADATA` file.
 pub fn parse_metadata(path: &Path) -> Metadata {
-    let re = |key: &str| Regex::new(&format!(r"^{}:\s*(.*)$", key)).unwrap();
+    let data = fs::read_to_string(path).expect("Problem reading METADATA");
+    parse_metadata_str(&data)
+}
 
+/// Parse the headers of a `METADATA` file; the description that may follow them is ignored.
+pub fn parse_metadata_str(data: &str) -> Metadata {
     let mut result = Metadata::default();
 
-    let data = fs::read_to_string(path).expect("Problem reading METADATA");
     for line in data.lines() {
-        if let Some(caps) = re("Version").captures(line) {
-            let val = caps.get(1).unwrap().as_str();
-            result.version =
-                Version::from_str(val).expect("Problem parsing version from `METADATA`");
+        if line.trim().is_empty() {
+            break;
         }
-        if let Some(caps) = re("Requires-Dist").captures(line) {
-            let val = caps.get(1).unwrap().as_str();
-            let req =
-                Req::from_str(val, true).expect("Problem parsing requirement from `METADATA`");
-            result.requires_dist.push(req);
+        let (key, val) = match line.split_once(':') {
+            Some((k, v)) => (k.trim(), v.trim()),
+            None => continue,
+        };
+        let val_opt = if val.is_empty() || val == "UNKNOWN" {
+            None
+        } else {
+            Some(val.to_owned())
+        };
+
+        match key {
+            "Name" => result.name = val.to_owned(),
+            "Version" => {
+                result.version =
+                    Version::from_str(val).expect("Problem parsing version from `METADATA`")
+            }
+            "Summary" => result.summary = val_opt,
+            "Author" => result.author = val_opt,
+            "Author-email" => result.author_email = val_opt,
+            "License" => result.license = val_opt,
+            "License-Expression" => result.license_expression = val_opt,
+            "Classifier" => result.classifiers.push(val.to_owned()),
+            "Keywords" => {
+                result.keywords = val
+                    .split([',', ' '])
+                    .filter(|k| !k.is_empty())
+                    .map(|k| k.to_owned())
+                    .collect()
+            }
+            "Platform" => result.platform = val_opt,
+            "Home-page" => result.home_page = val_opt,
+            // Newer metadata puts the homepage here instead, eg `Homepage, https://...`.
+            "Project-URL" if result.home_page.is_none() => {
+                if let Some((label, url)) = val.split_once(',') {
+                    if label.trim().eq_ignore_ascii_case("homepage") {
+                        result.home_page = Some(url.trim().to_owned());
+                    }
+                }
+            }
+            "Requires-Python" => result.requires_python = val_opt,
+            "Requires-Dist" => {
+                let req =
+                    Req::from_str(val, true).expect("Problem parsing requirement from `METADATA`");
+                result.requires_dist.push(req);
+            }
+            "Provides-Extra" => result.provides_extra.push(val.to_owned()),
+            _ => (),
         }
     }
-    // TODO: For now, just pull version and requires_dist. Add more as-required.
     result
 }
 
@@ -826,6 +1354,7 @@ pub fn process_reqs(reqs: Vec<Req>, git_path: &Path, paths: &util::Paths) -> Vec
             &req.name,
             //  util::GitPath::Git(req.git.clone().unwrap()),
             &req.git.clone().unwrap(),
+            req.git_ref.as_ref(),
             git_path,
             paths,
         );
@@ -843,44 +1372,127 @@ pub fn process_reqs(reqs: Vec<Req>, git_path: &Path, paths: &util::Paths) -> Vec
     updated_reqs
 }
 
-/// Read dependency data from a lock file.
+/// Read dependency data from a lock file. It's only read from disk once per invocation, unless
+/// something else writes it.
 pub fn read_lock(path: &Path) -> Result<Lock, Box<dyn Error>> {
-    let data = fs::read_to_string(path)?;
-    Ok(toml::from_str(&data)?)
+    if let Some(lock) = loaded::lock(path) {
+        return Ok(lock);
+    }
+    let data = loaded::read_to_string(path)?;
+    let lock = toml::from_str(&data)?;
+    loaded::keep_lock(path, &lock);
+    Ok(lock)
 }
 
 /// Write dependency data to a lock file.
 pub fn write_lock(path: &Path, data: &Lock) -> Result<(), Box<dyn Error>> {
-    let data = toml::to_string(data)?;
-    fs::write(path, data)?;
+    let serialized = toml::to_string(data)?;
+    loaded::write(path, serialized)?;
+    loaded::keep_lock(path, data);
     Ok(())
 }
 
-pub fn handle_color_option(s: &str) -> ColorChoice {
-    match s {
-        "always" => ColorChoice::Always,
-        "ansi" => ColorChoice::AlwaysAnsi,
-        "auto" => {
-            if atty::is(atty::Stream::Stdout) {
-                ColorChoice::Auto
-            } else {
-                ColorChoice::Never
-            }
-        }
-        _ => ColorChoice::Never,
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use rstest::rstest;
 
     use super::*;
     use crate::dep_types;
+    use crate::util::temp_dir::TempDir;
 
     #[test]
     fn dummy_test() {}
 
+    /// A `.venv` for `py_version`, eg made with `python -m venv`, is used as it is.
+    #[test]
+    fn root_venv_reused() {
+        let root = TempDir::new("root-venv");
+        let bin = root
+            .join(".venv")
+            .join(if cfg!(windows) { "Scripts" } else { "bin" });
+        fs::create_dir_all(&bin).unwrap();
+        fs::write(
+            root.join(".venv/pyvenv.cfg"),
+            "home = /usr/bin\nversion = 3.11.4\n",
+        )
+        .unwrap();
+        fs::write(bin.join("marker"), "").unwrap();
+
+        let (found, version) = find_or_create_root_venv(
+            &Version::new_short(3, 11),
+            &root,
+            &root.join("pyflow"),
+            &root.join("cache"),
+            None,
+            py_versions::Implementation::CPython,
+            &[],
+        );
+        let kept = bin.join("marker").exists();
+
+        assert!(kept);
+        assert_eq!(version, Version::new_short(3, 11));
+        assert_eq!(found.file_name(), root.file_name());
+    }
+
+    #[test]
+    fn pythonpath_merges() {
+        let ours = vec![
+            PathBuf::from("/proj/__pypackages__/3.11/lib"),
+            PathBuf::from("/proj/src"),
+        ];
+        let existing = env::join_paths(&[PathBuf::from("/opt/lib"), ours[1].clone()]).unwrap();
+        let merged = merge_pythonpath(&ours, Some(&existing));
+        assert_eq!(
+            env::split_paths(&merged).collect::<Vec<_>>(),
+            vec![ours[0].clone(), ours[1].clone(), PathBuf::from("/opt/lib")]
+        );
+        assert_eq!(merge_pythonpath(&ours[..1], None), ours[0].as_os_str());
+    }
+
+    /// `lib` is a path, and a site directory, so `.pth` files in it are read too.
+    #[test]
+    fn pth_contents() {
+        let root = TempDir::new("pth");
+        let lib = root.join("__pypackages__/3.11/lib");
+        let site_packages = root.join("venv/.venv/lib/python3.11/site-packages");
+        fs::create_dir_all(&site_packages).unwrap();
+        write_pth(&root.join("venv"), std::slice::from_ref(&lib));
+        assert_eq!(
+            fs::read_to_string(site_packages.join(PTH_FILENAME)).unwrap(),
+            format!(
+                "{}\nimport site; site.addsitedir('{}')\n",
+                lib.display(),
+                lib.display()
+            )
+        );
+    }
+
+    #[test]
+    fn python_strings() {
+        assert_eq!(python_str("/home/ana/lib"), "'/home/ana/lib'");
+        assert_eq!(
+            python_str("C:\\Users\\O'Brien\\José\\日本\\🐍\n"),
+            r"'C:\\Users\\O\'Brien\\Jos\xe9\\\u65e5\u672c\\\U0001f40d\x0a'"
+        );
+
+        let python = match find_on_path("python3").into_iter().next() {
+            Some(p) => p,
+            None => return,
+        };
+        let path = "/tmp/O'Brien\\José 日本 🐍\t";
+        let output = process::Command::new(python)
+            .args([
+                "-c",
+                &format!(
+                    "import sys; sys.stdout.buffer.write({}.encode())",
+                    python_str(path)
+                ),
+            ])
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), path);
+    }
+
     #[rstest(
         input,
         expected,
@@ -909,4 +1521,104 @@ mod tests {
     fn test_os_from_str(input: &str, expected: Result<Os, dep_types::DependencyError>) {
         assert_eq!(Os::from_str(input), expected);
     }
+
+    #[test]
+    fn merge_extras_keeps_existing() {
+        let existing = Some(vec!["standard".to_string()]);
+        let added = Some(vec!["watchfiles".to_string(), "Standard".to_string()]);
+        assert_eq!(
+            merge_extras(&existing, &added),
+            Some(vec!["standard".to_string(), "watchfiles".to_string()])
+        );
+        assert_eq!(merge_extras(&None, &None), None);
+    }
+
+    #[rstest(
+        filename,
+        cpython,
+        pypy,
+        case("numpy-1.26.4-cp310-cp310-manylinux_2_17_x86_64.whl", true, false),
+        case("numpy-1.26.4-pp39-pypy39_pp73-manylinux_2_17_x86_64.whl", false, true),
+        case("cryptography-42.0.5-cp39-abi3-manylinux_2_28_x86_64.whl", true, false),
+        case("requests-2.31.0-py3-none-any.whl", true, true),
+        case("six-1.16.0-py2.py3-none-any.whl", true, true),
+        case("PyQt5-5.13.0-5.13.0-cp35.cp36.cp37.cp38-none-win32.whl", true, false)
+    )]
+    fn wheel_implementation(filename: &str, cpython: bool, pypy: bool) {
+        use crate::py_versions::Implementation;
+        assert_eq!(
+            wheel_supports_implementation(filename, Implementation::CPython),
+            cpython
+        );
+        assert_eq!(
+            wheel_supports_implementation(filename, Implementation::PyPy),
+            pypy
+        );
+    }
+
+    #[test]
+    fn close_names() {
+        let names: Vec<String> = ["requests", "numpy", "requests-toolbelt", "black"]
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        assert_eq!(
+            close_matches("reqeusts", &names),
+            vec!["requests".to_string()]
+        );
+        assert_eq!(
+            close_matches("Requests", &names),
+            vec!["requests".to_string(), "requests-toolbelt".to_string()]
+        );
+        assert!(close_matches("scipy", &names).is_empty());
+    }
+
+    #[test]
+    fn metadata_headers() {
+        let data = "Metadata-Version: 2.1
+Name: requests
+Version: 2.31.0
+Summary: Python HTTP for Humans.
+License: Apache 2.0
+Classifier: License :: OSI Approved :: Apache Software License
+Project-URL: Documentation, https://requests.readthedocs.io
+Project-URL: Homepage, https://requests.readthedocs.io/en/latest
+Requires-Python: >=3.7
+Requires-Dist: idna (<4,>=2.5)
+Provides-Extra: socks
+Requires-Dist: PySocks (!=1.5.7,>=1.5.6) ; extra == 'socks'
+
+Version: not a header
+";
+        let metadata = parse_metadata_str(data);
+        assert_eq!(metadata.name, "requests");
+        assert_eq!(metadata.version, Version::new(2, 31, 0));
+        assert_eq!(metadata.license.as_deref(), Some("Apache 2.0"));
+        assert_eq!(
+            metadata.classifiers,
+            vec!["License :: OSI Approved :: Apache Software License".to_string()]
+        );
+        assert_eq!(
+            metadata.home_page.as_deref(),
+            Some("https://requests.readthedocs.io/en/latest")
+        );
+        assert_eq!(metadata.requires_python.as_deref(), Some(">=3.7"));
+        assert_eq!(metadata.requires_dist.len(), 2);
+        assert_eq!(metadata.provides_extra, vec!["socks".to_string()]);
+    }
+
+    #[test]
+    fn normalize_parent_dirs() {
+        assert_eq!(
+            normalize_path(Path::new("/a/lib/../bin/black")),
+            PathBuf::from("/a/bin/black")
+        );
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(util::format_size(512), "512 B");
+        assert_eq!(util::format_size(1536), "1.5 KB");
+        assert_eq!(util::format_size(3 * 1024 * 1024 * 1024), "3.0 GB");
+    }
 }
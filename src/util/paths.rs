@@ -1,5 +1,7 @@
 use std::path::{Path, PathBuf};
 
+use crate::CliConfig;
+
 pub fn pyflow_path() -> PathBuf {
     directories::BaseDirs::new()
         .expect("Problem finding base directory")
@@ -8,14 +10,59 @@ pub fn pyflow_path() -> PathBuf {
         .join("pyflow")
 }
 
+/// The global config file, eg `~/.config/pyflow/config.toml`, or `%APPDATA%\pyflow\config.toml`.
+pub fn global_config_path() -> PathBuf {
+    directories::BaseDirs::new()
+        .expect("Problem finding base directory")
+        .config_dir()
+        .join("pyflow")
+        .join("config.toml")
+}
+
+/// Where we install Python interpreters. Set `python-dir` (or `PYFLOW_PYTHON_DIR`) to use
+/// another location, eg one pre-seeded with `pyflow python install`.
+pub fn python_installs_path(pyflow_path: &Path) -> PathBuf {
+    CliConfig::current()
+        .settings
+        .python_dir
+        .clone()
+        .unwrap_or_else(|| pyflow_path.to_owned())
+}
+
+/// Where we cache downloads. Set `cache-dir` (or `PYFLOW_CACHE_DIR`) to use another location,
+/// eg a shared or CI-cached directory.
+pub fn cache_root(pyflow_path: &Path) -> PathBuf {
+    CliConfig::current()
+        .settings
+        .cache_dir
+        .clone()
+        .unwrap_or_else(|| pyflow_path.to_owned())
+}
+
 pub fn dep_cache_path(pyflow_path: &Path) -> PathBuf {
-    pyflow_path.join("dependency_cache")
+    cache_root(pyflow_path).join("dependency_cache")
 }
 
 pub fn script_env_path(pyflow_path: &Path) -> PathBuf {
     pyflow_path.join("script_envs")
 }
 
+/// Where we keep data fetched from the package index, like its list of project names.
+pub fn index_cache_path(pyflow_path: &Path) -> PathBuf {
+    cache_root(pyflow_path).join("index_cache")
+}
+
+/// Folders we were told not to add to a `.gitignore`, one per line, so we don't ask again.
+pub fn gitignore_declined_path(pyflow_path: &Path) -> PathBuf {
+    pyflow_path.join("gitignore_declined.txt")
+}
+
+/// Each project's earlier `packages-dir` folders, as `<project root>\t<folder>` lines, so an
+/// environment left in one is noticed after the setting changes, or goes.
+pub fn packages_dirs_path(pyflow_path: &Path) -> PathBuf {
+    pyflow_path.join("packages_dirs.txt")
+}
+
 pub fn git_path(pyflow_path: &Path) -> PathBuf {
     pyflow_path.join("git")
 }
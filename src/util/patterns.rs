@@ -0,0 +1,161 @@
+//! Regexes used on hot paths, or in more than one place, compiled once, on first use.
+
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+/// An installed package's metadata folder, eg `zope.interface-6.1+local.2.dist-info`: the name,
+/// then the version. Versions have no dashes, so the last one ends the name.
+pub static DIST_INFO_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(.*)-(.*?)\.dist-info$").unwrap());
+
+/// A wheel's filename, eg `typing_extensions-4.9.0-py3-none-any.whl`: the name, then the version.
+/// Wheels escape dashes in names to `_`, so the first dash ends it.
+pub static WHEEL_FILENAME_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(.*?)-(.*?)-.*$").unwrap());
+
+/// The platform tag: the last part of a wheel's filename, eg `manylinux2014_x86_64`.
+pub static WHEEL_PLATFORM_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(?:.*?-)+(.*)\.whl$").unwrap());
+
+/// A source distribution's filename, without its extension.
+pub static SDIST_FILENAME_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(.*?)(?:\.tar\.gz|\.zip)$").unwrap());
+
+/// A console script, eg `black = black:patched_main`: the name, module, and function.
+pub static ENTRY_POINT_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(.*?)\s*=\s*(.*?):(.*)$").unwrap());
+
+/// A lock package's dependency, eg `idna 3.6 pypi+https://pypi.org/pypi/idna/3.6/json`: the
+/// name, then the version.
+pub static LOCK_DEP_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(.*?)\s(.*)\s.*$").unwrap());
+
+/// A lock package's `rename`, eg `3 urllib3_vendored`: the parent's id, then the new name.
+pub static LOCK_RENAME_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(\d+)\s(.*)$").unwrap());
+
+/// A `__pypackages__` folder for a Python version, eg `3.11`.
+pub static PYPACKAGES_VERSION_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(\d+)\.(\d+)$").unwrap());
+
+/// The output of `python --version`, eg `Python 3.11.4`, or PyPy's longer one.
+pub static PYTHON_VERSION_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"Python\s+(\d{1,4})\.(\d{1,4})\.(\d{1,4})").unwrap());
+
+/// Runs of the characters package and extra names treat as the same, eg in `zope.interface` and
+/// `zope_interface`.
+pub static NAME_SEPARATORS_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[-_.]+").unwrap());
+
+/// A TOML table header, eg `[tool.pyflow]`, possibly followed by whitespace.
+pub static SECTION_HEADER_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\[.*\]\s*$").unwrap());
+
+/// Platform tags, for `util::Os`.
+pub static OS_LINUX32_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(many)?linux.*i686").unwrap());
+pub static OS_LINUX_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"((many)?linux.*|cygwin|(open)?bsd6*)").unwrap());
+pub static OS_WIN_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^win(dows|_amd64)?").unwrap());
+pub static OS_MAC_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(macosx.*|darwin|.*mac.*)").unwrap());
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caps(re: &Regex, s: &str) -> Option<Vec<String>> {
+        re.captures(s).map(|c| {
+            c.iter()
+                .skip(1)
+                .map(|m| m.map_or("", |m| m.as_str()).to_owned())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn tricky_names() {
+        assert_eq!(
+            caps(&DIST_INFO_RE, "zope.interface-6.1+local.2.dist-info"),
+            Some(vec!["zope.interface".into(), "6.1+local.2".into()])
+        );
+        // Names with dashes aren't written to `dist-info`s, but if they are, they're kept whole.
+        assert_eq!(
+            caps(&DIST_INFO_RE, "backports-zoneinfo-0.2.1.dist-info"),
+            Some(vec!["backports-zoneinfo".into(), "0.2.1".into()])
+        );
+        assert_eq!(caps(&DIST_INFO_RE, "requests-2.31.0.egg-info"), None);
+
+        assert_eq!(
+            caps(
+                &WHEEL_FILENAME_RE,
+                "ruamel.yaml.clib-0.2.8+cpu-cp311-cp311-win32.whl"
+            ),
+            Some(vec!["ruamel.yaml.clib".into(), "0.2.8+cpu".into()])
+        );
+        assert_eq!(
+            caps(
+                &WHEEL_PLATFORM_RE,
+                "PyQt5-5.13.0-5.13.0-cp35.cp36.cp37.cp38-none-win32.whl"
+            ),
+            Some(vec!["win32".into()])
+        );
+        assert_eq!(
+            caps(
+                &WHEEL_PLATFORM_RE,
+                "typing_extensions-4.9.0-py3-none-any.whl"
+            ),
+            Some(vec!["any".into()])
+        );
+        assert_eq!(caps(&WHEEL_PLATFORM_RE, "pkg-1.0-py3-none-anyxwhl"), None);
+        assert_eq!(
+            caps(&SDIST_FILENAME_RE, "python-dateutil-2.8.2.tar.gz"),
+            Some(vec!["python-dateutil-2.8.2".into()])
+        );
+
+        assert_eq!(
+            caps(&ENTRY_POINT_RE, "black = black:patched_main"),
+            Some(vec!["black".into(), "black".into(), "patched_main".into()])
+        );
+        assert_eq!(
+            caps(
+                &LOCK_DEP_RE,
+                "zope.interface 6.1+local.2 pypi+https://pypi.org/pypi/zope.interface/6.1/json"
+            ),
+            Some(vec!["zope.interface".into(), "6.1+local.2".into()])
+        );
+        assert_eq!(
+            caps(&LOCK_RENAME_RE, "3 urllib3_vendored"),
+            Some(vec!["3".into(), "urllib3_vendored".into()])
+        );
+        assert_eq!(
+            caps(&PYPACKAGES_VERSION_RE, "3.11"),
+            Some(vec!["3".into(), "11".into()])
+        );
+        assert_eq!(caps(&PYPACKAGES_VERSION_RE, "3.11.4"), None);
+        assert_eq!(
+            caps(
+                &PYTHON_VERSION_RE,
+                "Python 3.10.13 (f1607341da97, Dec 2023)\n[PyPy 7.3.15]"
+            ),
+            Some(vec!["3".into(), "10".into(), "13".into()])
+        );
+
+        assert_eq!(
+            NAME_SEPARATORS_RE.replace_all("zope.interface--_x", "_"),
+            "zope_interface_x"
+        );
+        assert!(SECTION_HEADER_RE.is_match("[tool.pyflow]"));
+        assert!(SECTION_HEADER_RE.is_match("[tool.pyflow.dependencies]  "));
+        assert!(SECTION_HEADER_RE.is_match("[[tool.pyflow.sources]]"));
+        assert!(!SECTION_HEADER_RE.is_match("  [tool.pyflow]"));
+        assert!(!SECTION_HEADER_RE.is_match("[tool.pyflow]  # Comment"));
+        assert!(!SECTION_HEADER_RE.is_match("extras = [\"socks\"]"));
+
+        assert!(OS_LINUX32_RE.is_match("manylinux2014_i686"));
+        assert!(OS_LINUX_RE.is_match("musllinux_1_1_x86_64"));
+        assert!(OS_WIN_RE.is_match("win_amd64"));
+        assert!(OS_MAC_RE.is_match("macosx_11_0_arm64"));
+    }
+}
@@ -0,0 +1,216 @@
+//! Download packages on a few threads while the main thread installs the ones that are done, so
+//! extracting overlaps with waiting on the network. Ctrl-C stops new work: downloads in progress
+//! are abandoned, and their partial files removed, but a package being installed is finished,
+//! so none is left half-extracted. Also, `map`, for spreading eg hashing across cores.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+/// How many packages download at once.
+pub const DOWNLOADS: usize = 4;
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn cancel(_signal: libc::c_int) {
+    CANCELLED.store(true, Ordering::SeqCst);
+}
+
+/// Whether Ctrl-C was pressed while a `Cancel` was alive. Long-running steps check this, and
+/// stop at a point that leaves nothing half-done.
+pub fn cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+/// Cancelling is per-process; tests that run the pipeline take this, so that one's cancelling
+/// doesn't stop another's.
+#[cfg(test)]
+pub static TESTS: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Stop the same way Ctrl-C does, eg when a download fails.
+pub fn stop() {
+    CANCELLED.store(true, Ordering::SeqCst);
+}
+
+/// While alive, Ctrl-C sets `cancelled`, instead of exiting immediately.
+pub struct Cancel {
+    sigint: libc::sighandler_t,
+}
+
+impl Cancel {
+    /// Start catching Ctrl-C.
+    pub fn catch() -> Self {
+        CANCELLED.store(false, Ordering::SeqCst);
+        unsafe {
+            Self {
+                sigint: libc::signal(libc::SIGINT, cancel as *const () as libc::sighandler_t),
+            }
+        }
+    }
+}
+
+impl Drop for Cancel {
+    fn drop(&mut self) {
+        unsafe {
+            libc::signal(libc::SIGINT, self.sigint);
+        }
+    }
+}
+
+/// Run `fetch` on each item on up to `workers` threads, and `install` on the main thread with
+/// each result as it's ready, in the order they finish. At most `workers` finished results wait
+/// for `install`, so a slow install holds back downloads, instead of filling the disk. Stops
+/// early if cancelled; items not yet installed are skipped.
+pub fn run<T, R, F, I>(items: &[T], workers: usize, fetch: F, mut install: I)
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+    I: FnMut(&T, R),
+{
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::sync_channel(workers.max(1));
+    thread::scope(|s| {
+        for _ in 0..workers.max(1).min(items.len()) {
+            let (tx, next, fetch) = (tx.clone(), &next, &fetch);
+            s.spawn(move || loop {
+                if cancelled() {
+                    break;
+                }
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                if i >= items.len() || tx.send((i, fetch(&items[i]))).is_err() {
+                    break;
+                }
+            });
+        }
+        // Only the workers' senders are left, so the loop ends when they're all done.
+        drop(tx);
+        for (i, result) in rx {
+            if cancelled() {
+                break;
+            }
+            install(&items[i], result);
+        }
+    });
+}
+
+/// `f` of each item, on a thread per core, in the items' order. Each thread takes the next item
+/// when it's done with one, so a few large ones don't hold the rest up.
+pub fn map<T, R, F>(items: &[T], f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let workers = thread::available_parallelism().map_or(1, |n| n.get());
+    let next = AtomicUsize::new(0);
+    let (next, f) = (&next, &f);
+    let mut done: Vec<(usize, R)> = thread::scope(|s| {
+        let handles: Vec<_> = (0..workers.min(items.len()))
+            .map(|_| {
+                s.spawn(move || {
+                    let mut done = vec![];
+                    loop {
+                        let i = next.fetch_add(1, Ordering::SeqCst);
+                        if i >= items.len() {
+                            break done;
+                        }
+                        done.push((i, f(&items[i])));
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().expect("A worker thread panicked"))
+            .collect()
+    });
+    done.sort_unstable_by_key(|(i, _)| *i);
+    done.into_iter().map(|(_, r)| r).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Mutex, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn overlaps_and_installs_each() {
+        let _lock = TESTS.lock().unwrap_or_else(|e| e.into_inner());
+        let _cancel = Cancel::catch();
+        let items: Vec<u64> = (0..12).collect();
+        let fetched = Mutex::new(vec![]);
+        let mut installed = vec![];
+        run(
+            &items,
+            DOWNLOADS,
+            |i| {
+                // Later items finish first, so installs follow completion, not the list.
+                thread::sleep(Duration::from_millis(30 - 2 * i));
+                fetched.lock().unwrap().push(*i);
+                i * 10
+            },
+            |i, result| {
+                assert_eq!(result, i * 10);
+                installed.push(*i);
+            },
+        );
+        installed.sort_unstable();
+        assert_eq!(installed, items);
+        assert_eq!(fetched.lock().unwrap().len(), items.len());
+        // With one worker, nothing overlaps; with four, waits run alongside each other.
+        let timed = |workers| {
+            let start = std::time::Instant::now();
+            run(
+                &items,
+                workers,
+                |_| thread::sleep(Duration::from_millis(40)),
+                |_, _| {},
+            );
+            start.elapsed()
+        };
+        assert!(timed(DOWNLOADS) * 2 < timed(1));
+    }
+
+    #[test]
+    fn stops_when_cancelled() {
+        let _lock = TESTS.lock().unwrap_or_else(|e| e.into_inner());
+        let _cancel = Cancel::catch();
+        let items: Vec<u64> = (0..40).collect();
+        let fetched = AtomicUsize::new(0);
+        let mut installed = vec![];
+        run(
+            &items,
+            DOWNLOADS,
+            |i| {
+                thread::sleep(Duration::from_millis(10));
+                fetched.fetch_add(1, Ordering::SeqCst);
+                *i
+            },
+            |i, _| {
+                installed.push(*i);
+                stop();
+            },
+        );
+        assert!(cancelled());
+        // Results already finished aren't installed after the flag is set, and no more start.
+        assert_eq!(installed.len(), 1);
+        assert!(fetched.load(Ordering::SeqCst) <= 1 + 2 * DOWNLOADS);
+    }
+
+    #[test]
+    fn map_keeps_order() {
+        let items: Vec<u64> = (0..100).collect();
+        let doubled = map(&items, |i| {
+            thread::sleep(Duration::from_millis(i % 3));
+            i * 2
+        });
+        assert_eq!(doubled, items.iter().map(|i| i * 2).collect::<Vec<_>>());
+        assert!(map(&[] as &[u64], |i| *i).is_empty());
+    }
+}
@@ -1,31 +1,183 @@
 use std::{
     collections::HashMap,
-    io::{self, Write},
+    io::{self, BufRead, Write},
 };
 
 use termcolor::Color;
 
 use crate::{
     dep_types::Version,
-    util::{abort, default_python, fallible_v_parse, print_color},
+    util::{abort, default_python, fallible_v_parse, print_color, print_color_},
+    CliConfig,
 };
 
-/// Ask the user what Python version to use.
+/// What a prompt answers when there's nobody to ask: in non-interactive mode, or when stdin is
+/// closed.
+pub enum Fallback<'a> {
+    /// Take this answer, eg the default shown in the prompt.
+    Answer(String),
+    /// Abort. Explains how to make the decision without a prompt, eg with a flag.
+    Fail(&'a str),
+}
+
+/// Show `msg`, and read a line of input. Every prompt goes through here, so non-interactive
+/// mode applies to all of them.
+pub fn prompt(msg: &str, fallback: Fallback) -> String {
+    if !msg.is_empty() {
+        print_color_(msg, Color::Magenta);
+        io::stdout().flush().unwrap();
+    }
+    let answer = read_answer(
+        &mut io::stdin().lock(),
+        CliConfig::current().non_interactive,
+    );
+    resolve(answer, fallback).unwrap_or_else(|e| abort(&e))
+}
+
+/// Like `prompt`, but what's typed isn't shown, eg for a password or token. There's no
+/// sensible answer to fall back to, so aborts, explaining `how` to provide it instead.
+pub fn secret(msg: &str, how: &str) -> String {
+    print_color_(msg, Color::Magenta);
+    io::stdout().flush().unwrap();
+    let answer = {
+        let _echo_off = EchoOff::new();
+        read_answer(
+            &mut io::stdin().lock(),
+            CliConfig::current().non_interactive,
+        )
+    };
+    // The newline typed wasn't echoed either.
+    println!();
+    resolve(answer, Fallback::Fail(how)).unwrap_or_else(|e| abort(&e))
+}
+
+/// Turns off the terminal's echo while it's alive, if stdin is a terminal.
+#[cfg(unix)]
+struct EchoOff(Option<libc::termios>);
+
+#[cfg(unix)]
+impl EchoOff {
+    fn new() -> Self {
+        // Safe: `termios` is plain data, filled in by `tcgetattr`, and we only change the
+        // settings if we could read them to restore.
+        unsafe {
+            let mut term: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut term) != 0 {
+                return Self(None);
+            }
+            let original = term;
+            term.c_lflag &= !libc::ECHO;
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &term);
+            Self(Some(original))
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for EchoOff {
+    fn drop(&mut self) {
+        if let Some(original) = &self.0 {
+            // Safe: restores the settings read in `new`.
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original);
+            }
+        }
+    }
+}
+
+/// Turns off the console's echo while it's alive, if stdin is a console. Holds the mode to
+/// restore.
+#[cfg(windows)]
+struct EchoOff(Option<u32>);
+
+#[cfg(windows)]
+impl EchoOff {
+    fn new() -> Self {
+        use std::os::windows::io::AsRawHandle;
+        use winapi::um::consoleapi::{GetConsoleMode, SetConsoleMode};
+        use winapi::um::wincon::ENABLE_ECHO_INPUT;
+        use winapi::um::winnt::HANDLE;
+
+        let handle = io::stdin().as_raw_handle() as HANDLE;
+        let mut mode: u32 = 0;
+        // Safe: `handle` is stdin's, and we only change the mode if we could read it to restore.
+        unsafe {
+            if GetConsoleMode(handle, &mut mode) == 0 {
+                return Self(None);
+            }
+            SetConsoleMode(handle, mode & !ENABLE_ECHO_INPUT);
+        }
+        Self(Some(mode))
+    }
+}
+
+#[cfg(windows)]
+impl Drop for EchoOff {
+    fn drop(&mut self) {
+        use std::os::windows::io::AsRawHandle;
+        use winapi::um::consoleapi::SetConsoleMode;
+        use winapi::um::winnt::HANDLE;
+
+        if let Some(mode) = self.0 {
+            // Safe: restores the mode read in `new`.
+            unsafe {
+                SetConsoleMode(io::stdin().as_raw_handle() as HANDLE, mode);
+            }
+        }
+    }
+}
+
+/// A line of input, or `None` if we shouldn't, or can't, read one.
+fn read_answer(input: &mut impl BufRead, non_interactive: bool) -> Option<String> {
+    if non_interactive {
+        return None;
+    }
+    let mut line = String::new();
+    match input.read_line(&mut line) {
+        // End of input: stdin is closed, or not attached to anything.
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim().to_owned()),
+    }
+}
+
+fn resolve(answer: Option<String>, fallback: Fallback) -> Result<String, String> {
+    match (answer, fallback) {
+        (Some(a), _) => Ok(a),
+        (None, Fallback::Answer(a)) => {
+            print_color(&format!("{} (non-interactive)", a), Color::Magenta);
+            Ok(a)
+        }
+        (None, Fallback::Fail(how)) => Err(format!(
+            "Can't prompt for input, since pyflow is running non-interactively, or stdin is \
+             closed. {}",
+            how
+        )),
+    }
+}
+
+/// Ask the user what Python version to use. Non-interactively, uses the default shown. If
+/// `py-version` is set, uses that without asking.
 pub fn py_vers() -> Version {
+    if let Some(v) = &CliConfig::current().settings.py_version {
+        log::info!("Using Python {}, from the `py-version` setting", v);
+        return v.clone();
+    }
     print_color(
         "Please enter the Python version for this project: (eg: 3.8)",
         Color::Magenta,
     );
+    let found = crate::py_versions::find_system_interpreters();
+    if !found.is_empty() {
+        println!("Found on this system (others will be downloaded):");
+        for i in &found {
+            println!("  {}", i);
+        }
+    }
     let default_ver = default_python();
-    print!("Default [{}]:", default_ver);
-    std::io::stdout().flush().unwrap();
-    let mut input = String::new();
-    io::stdin()
-        .read_line(&mut input)
-        .expect("Unable to read user input for version");
-
-    input.pop(); // Remove trailing newline.
-    let input = input.replace(['\n', '\r'], "");
+    let input = prompt(
+        &format!("Default [{}]:", default_ver),
+        Fallback::Answer(default_ver.to_string()),
+    );
     if !input.is_empty() {
         fallible_v_parse(&input)
     } else {
@@ -33,12 +185,21 @@ pub fn py_vers() -> Version {
     }
 }
 
-/// A generic prompt function, where the user selects from a list
+/// Ask a yes / no question. Anything other than yes is no.
+pub fn confirm(msg: &str, fallback: Fallback) -> bool {
+    prompt(&format!("{} (yes / no) ", msg), fallback)
+        .to_lowercase()
+        .starts_with('y')
+}
+
+/// A generic prompt function, where the user selects from a list. A `Fallback::Answer` is the
+/// item's number, starting at 1.
 pub fn list<T: Clone + ToString>(
     init_msg: &str,
     type_: &str,
     items: &[(String, T)],
     show_item: bool,
+    fallback: Fallback,
 ) -> (String, T) {
     print_color(init_msg, Color::Magenta);
     for (i, (name, content)) in items.iter().enumerate() {
@@ -54,19 +215,9 @@ pub fn list<T: Clone + ToString>(
         mapping.insert(i + 1, item);
     }
 
-    let mut input = String::new();
-    io::stdin()
-        .read_line(&mut input)
-        .expect("Problem reading input");
-
-    let input = input
-        .chars()
-        .next()
-        .expect("Problem parsing input")
-        .to_string()
-        .parse::<usize>();
+    let input = prompt("", fallback);
 
-    let input = if let Ok(ip) = input {
+    let input = if let Ok(ip) = input.parse::<usize>() {
         ip
     } else {
         abort("Please try again; enter a number like 1 or 2 .")
@@ -83,3 +234,33 @@ pub fn list<T: Clone + ToString>(
 
     (name.to_string(), content.clone())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn closed_stdin() {
+        assert_eq!(read_answer(&mut io::empty(), false), None);
+        assert_eq!(
+            read_answer(&mut "3.11\n".as_bytes(), false),
+            Some("3.11".into())
+        );
+        assert_eq!(read_answer(&mut "\n".as_bytes(), false), Some("".into()));
+        assert_eq!(read_answer(&mut "yes\n".as_bytes(), true), None);
+    }
+
+    #[test]
+    fn fallbacks() {
+        assert_eq!(
+            resolve(Some("no".into()), Fallback::Answer("yes".into())),
+            Ok("no".into())
+        );
+        assert_eq!(
+            resolve(None, Fallback::Answer("yes".into())),
+            Ok("yes".into())
+        );
+        let err = resolve(None, Fallback::Fail("Pass `--pythons`.")).unwrap_err();
+        assert!(err.ends_with("Pass `--pythons`."));
+    }
+}
@@ -0,0 +1,191 @@
+//! Environment variables for `pyflow run`, and `pyflow python`: from `[tool.pyflow.env]`, an
+//! `env-file`, and per-script `env` tables. Values may hold secrets, so are never printed.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+use termcolor::Color;
+
+use crate::{pyproject::PresentConfig, util};
+
+/// Parse a `.env` file: `KEY=value` lines, optionally prefixed with `export`, with `#`
+/// comments. Values may be quoted.
+pub fn parse_dotenv(data: &str) -> Vec<(String, String)> {
+    let mut result = vec![];
+    for line in data.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let mut parts = line.splitn(2, '=');
+        let key = parts.next().unwrap_or_default().trim();
+        let value = match parts.next() {
+            Some(v) => v.trim(),
+            None => continue,
+        };
+        if key.is_empty() {
+            continue;
+        }
+
+        let value = if value.len() >= 2
+            && ((value.starts_with('"') && value.ends_with('"'))
+                || (value.starts_with('\'') && value.ends_with('\'')))
+        {
+            &value[1..value.len() - 1]
+        } else {
+            value
+        };
+        result.push((key.to_owned(), value.to_owned()));
+    }
+    result
+}
+
+/// Replace `${VAR}` with `VAR` from `outer`, or nothing if it isn't set.
+pub fn expand(value: &str, outer: impl Fn(&str) -> Option<String>) -> String {
+    let re = Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}").unwrap();
+    re.replace_all(value, |caps: &regex::Captures| {
+        outer(&caps[1]).unwrap_or_default()
+    })
+    .into_owned()
+}
+
+/// Variables from the `env-file`, then `[tool.pyflow.env]`, then the script's own `env`; later
+/// ones win. `${VAR}`s are expanded from the process's environment.
+pub fn collect(pcfg: &PresentConfig, script: Option<&str>) -> Vec<(String, String)> {
+    let mut vars = vec![];
+
+    if let Some(env_file) = &pcfg.config.env_file {
+        let path = pcfg.project_path.join(env_file);
+        match fs::read_to_string(&path) {
+            Ok(data) => vars.extend(parse_dotenv(&data)),
+            Err(_) => util::print_color(
+                &format!("Can't read the `env-file` {:?}; skipping it", path),
+                Color::Yellow,
+            ),
+        }
+    }
+
+    vars.extend(sorted(&pcfg.config.env));
+    if let Some(script_env) = script.and_then(|s| pcfg.config.script_env.get(s)) {
+        vars.extend(sorted(script_env));
+    }
+
+    vars.into_iter()
+        .map(|(k, v)| {
+            let v = expand(&v, |name| env::var(name).ok());
+            (k, v)
+        })
+        .collect()
+}
+
+fn sorted(vars: &HashMap<String, String>) -> Vec<(String, String)> {
+    let mut result: Vec<(String, String)> =
+        vars.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+    result.sort();
+    result
+}
+
+/// Set variables for child processes we run. Like `set_pythonpath`.
+pub fn apply(vars: &[(String, String)]) {
+    for (key, value) in vars {
+        env::set_var(key, value);
+    }
+}
+
+/// Activate a `.venv` the way its `activate` script does: its `bin` first on the `PATH`,
+/// `VIRTUAL_ENV` set, and `PYTHONHOME` unset. For `env-mode = "venv"`, so tools that look for an
+/// active venv find it.
+pub fn activate_venv(venv_path: &Path, bin_path: &Path) {
+    let mut path_var = vec![bin_path.to_owned()];
+    if let Some(existing) = env::var_os("PATH") {
+        path_var.extend(env::split_paths(&existing).filter(|p| p != bin_path));
+    }
+    if let Ok(joined) = env::join_paths(path_var) {
+        env::set_var("PATH", joined);
+    }
+    env::set_var("VIRTUAL_ENV", venv_path);
+    env::remove_var("PYTHONHOME");
+}
+
+/// Written to pyflow's folder once we've explained `isolate_user_site`.
+const USER_SITE_NOTICE: &str = "user-site-notice";
+
+thread_local! {
+    /// The user `site-packages` hidden from processes we run, if it has packages; see
+    /// `explain_user_site`.
+    static HIDDEN_USER_SITE: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+/// Keep packages from `pip install --user`, in `user_site`, out of processes we run for the
+/// project, so a dependency missing from `pyproject.toml` fails here, as it would in CI.
+pub fn isolate_user_site(user_site: Option<PathBuf>) {
+    env::set_var("PYTHONNOUSERSITE", "1");
+    let has_packages = user_site
+        .as_ref()
+        .and_then(|dir| fs::read_dir(dir).ok())
+        .is_some_and(|mut entries| entries.next().is_some());
+    if has_packages {
+        HIDDEN_USER_SITE.with(|h| *h.borrow_mut() = user_site);
+    }
+}
+
+/// After a process we ran failed: if it couldn't see user packages, it may have failed for want
+/// of one, so say why, the first time.
+pub fn explain_user_site() {
+    let dir = match HIDDEN_USER_SITE.with(|h| h.borrow().clone()) {
+        Some(d) => d,
+        None => return,
+    };
+    let marker = util::paths::get_paths().0.join(USER_SITE_NOTICE);
+    if marker.exists() {
+        return;
+    }
+    util::print_color(
+        &format!(
+            "Note: programs pyflow runs don't see packages installed with `pip install --user`, \
+             in {:?}. If this failed on a missing import, add the package to `pyproject.toml`; or \
+             to allow them, set `allow-user-site = true` under `[tool.pyflow]`, or use \
+             `pyflow run --system-site`. This note is only shown once.",
+            dir
+        ),
+        Color::Yellow,
+    );
+    fs::write(marker, "").unwrap_or(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dotenv() {
+        let data = "# Settings\nexport DJANGO_SETTINGS_MODULE=app.settings\nPORT = \"8000\"\n\nNAME='a b'\nBROKEN\n";
+        assert_eq!(
+            parse_dotenv(data),
+            vec![
+                (
+                    "DJANGO_SETTINGS_MODULE".to_string(),
+                    "app.settings".to_string()
+                ),
+                ("PORT".to_string(), "8000".to_string()),
+                ("NAME".to_string(), "a b".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn expand_from_outer() {
+        let outer = |name: &str| match name {
+            "HOME" => Some("/home/raz".to_string()),
+            _ => None,
+        };
+        assert_eq!(expand("${HOME}/data:${MISSING}", outer), "/home/raz/data:");
+        assert_eq!(expand("$HOME", outer), "$HOME");
+    }
+}
@@ -0,0 +1,602 @@
+//! Per-user defaults, and the order they're applied in. Each setting is taken from the first of:
+//! a command-line flag, an environment variable, the project's `[tool.pyflow]`, the global config
+//! file, then a built-in default. Not every setting can be set everywhere; see `KEYS`.
+
+use std::{collections::HashMap, fmt, fs, path::Path, path::PathBuf, str::FromStr};
+
+use crate::{dep_types::Version, install::LinkMode, pyproject::current, util};
+
+pub const DEFAULT_INDEX_URL: &str = "https://pypi.org/pypi";
+const COLORS: &[&str] = &["auto", "always", "ansi", "never"];
+
+/// A setting, and where else, besides the global config file, it can be set.
+pub struct Key {
+    pub name: &'static str,
+    pub env: &'static str,
+    /// Can be set under `[tool.pyflow]` in `pyproject.toml`.
+    pub project: bool,
+    pub about: &'static str,
+}
+
+pub const KEYS: &[Key] = &[
+    Key {
+        name: "index-url",
+        env: "PYFLOW_INDEX_URL",
+        project: true,
+        about: "The package index's JSON API, eg a PyPi mirror",
+    },
+    Key {
+        name: "proxy",
+        env: "PYFLOW_PROXY",
+        project: false,
+        about: "A proxy for all HTTP requests, eg `http://proxy.local:3128`",
+    },
+    Key {
+        name: "cache-dir",
+        env: "PYFLOW_CACHE_DIR",
+        project: false,
+        about: "Where to cache downloaded packages",
+    },
+    Key {
+        name: "python-dir",
+        env: "PYFLOW_PYTHON_DIR",
+        project: false,
+        about: "Where to install Python interpreters",
+    },
+    Key {
+        name: "packages-dir",
+        env: "PYFLOW_PACKAGES_DIR",
+        project: true,
+        about: "Where to keep project environments, instead of `__pypackages__`",
+    },
+    Key {
+        name: "py-version",
+        env: "PYFLOW_PY_VERSION",
+        project: false,
+        about: "The Python version for new projects, instead of asking",
+    },
+    Key {
+        name: "link-mode",
+        env: "PYFLOW_LINK_MODE",
+        project: false,
+        about: "copy, hardlink, or auto: how packages get from the cache into environments",
+    },
+    Key {
+        name: "color",
+        env: "PYFLOW_COLOR",
+        project: false,
+        about: "auto, always, ansi, or never",
+    },
+    Key {
+        name: "non-interactive",
+        env: "PYFLOW_NON_INTERACTIVE",
+        project: false,
+        about: "Never prompt; take defaults, or fail",
+    },
+];
+
+/// Indexes `pyflow publish --repository` knows without any config.
+pub const REPOSITORIES: &[(&str, &str)] = &[
+    ("pypi", "https://upload.pypi.org/legacy/"),
+    ("testpypi", "https://test.pypi.org/legacy/"),
+];
+
+/// An index to publish to: a built-in one, or one from `[repositories.<name>]` in the global
+/// config, which can also give it a token.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Repository {
+    pub name: String,
+    pub url: String,
+    pub token: Option<String>,
+}
+
+/// Values from one place settings can come from, by key.
+pub type Layer = HashMap<String, String>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Origin {
+    Flag,
+    Env,
+    Project,
+    Global,
+    Default,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Flag => "command line",
+            Self::Env => "environment",
+            Self::Project => "pyproject.toml",
+            Self::Global => "global config",
+            Self::Default => "default",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// A key's value, and where it came from. `None` if nothing sets it.
+pub struct Setting {
+    pub key: &'static Key,
+    pub value: Option<(String, Origin)>,
+}
+
+/// The settings every command uses.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub index_url: String,
+    pub proxy: Option<String>,
+    pub cache_dir: Option<PathBuf>,
+    pub python_dir: Option<PathBuf>,
+    /// Relative to the project root; `{project}` and `{py}` are filled in by
+    /// `pyproject::current::pypackages_path`.
+    pub packages_dir: Option<String>,
+    pub py_version: Option<Version>,
+    pub link_mode: LinkMode,
+    pub color: String,
+    pub non_interactive: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            index_url: DEFAULT_INDEX_URL.to_owned(),
+            proxy: None,
+            cache_dir: None,
+            python_dir: None,
+            packages_dir: None,
+            py_version: None,
+            link_mode: LinkMode::default(),
+            color: "auto".to_owned(),
+            non_interactive: false,
+        }
+    }
+}
+
+impl Settings {
+    /// A package's JSON metadata on the index; the latest version's, if `version` is `None`.
+    pub fn json_url(&self, name: &str, version: Option<&str>) -> String {
+        let base = self.index_url.trim_end_matches('/');
+        match version {
+            Some(v) => format!("{}/{}/{}/json", base, name, v),
+            None => format!("{}/{}/json", base, name),
+        }
+    }
+
+    /// The index's simple API, which lists every project: on PyPI and its mirrors, it's next to
+    /// the JSON one, eg `https://pypi.org/simple/` for `https://pypi.org/pypi`.
+    pub fn simple_url(&self) -> String {
+        let url = self.index_url.trim_end_matches('/');
+        match url.strip_suffix("/pypi") {
+            Some(base) => format!("{}/simple/", base),
+            None => format!("{}/", url),
+        }
+    }
+}
+
+pub fn find_key(name: &str) -> Option<&'static Key> {
+    KEYS.iter().find(|k| k.name == name)
+}
+
+/// Settings from flags on the command line.
+pub fn cli_layer(color: Option<&str>, non_interactive: bool, link_mode: Option<&str>) -> Layer {
+    let mut result = Layer::new();
+    if let Some(c) = color {
+        result.insert("color".into(), c.into());
+    }
+    if let Some(m) = link_mode {
+        result.insert("link-mode".into(), m.into());
+    }
+    // Without the flag, it's up to the other layers.
+    if non_interactive {
+        result.insert("non-interactive".into(), "true".into());
+    }
+    result
+}
+
+fn env_layer() -> Layer {
+    KEYS.iter()
+        .filter_map(|k| match std::env::var(k.env) {
+            Ok(v) if !v.is_empty() => Some((k.name.to_owned(), env_value(k.name, v))),
+            _ => None,
+        })
+        .collect()
+}
+
+/// As with other `PYFLOW_` flags, like `PYFLOW_NO_DEV`, any value but `0`, `false` or `no` turns
+/// `PYFLOW_NON_INTERACTIVE` on, eg `on` or `ci`.
+fn env_value(key: &str, value: String) -> String {
+    if key != "non-interactive" {
+        return value;
+    }
+    let off = matches!(value.to_lowercase().as_str(), "0" | "false" | "no");
+    (!off).to_string()
+}
+
+/// Settings under `[tool.pyflow]` in the current project. Problems with the file are reported
+/// by the commands that use it, so they're ignored here.
+fn project_layer() -> Layer {
+    let table = current::find_config_path()
+        .and_then(|p| fs::read_to_string(p).ok())
+        .and_then(|data| data.parse::<toml::Value>().ok());
+    let pyflow = table
+        .as_ref()
+        .and_then(|t| t.get("tool"))
+        .and_then(|t| t.get("pyflow"));
+
+    KEYS.iter()
+        .filter(|k| k.project)
+        .filter_map(|k| {
+            let value = pyflow?.get(k.name)?.as_str()?;
+            Some((k.name.to_owned(), value.to_owned()))
+        })
+        .collect()
+}
+
+fn default_layer() -> Layer {
+    let defaults = Settings::default();
+    vec![
+        ("index-url", defaults.index_url),
+        ("link-mode", "auto".to_owned()),
+        ("color", defaults.color),
+        ("non-interactive", defaults.non_interactive.to_string()),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_owned(), v))
+    .collect()
+}
+
+/// Parse the global config file. Problems are returned as warnings, naming the file; a broken
+/// config shouldn't stop every command.
+fn parse_file(data: &str, path: &Path) -> (Layer, Vec<String>) {
+    let mut layer = Layer::new();
+    let mut warnings = vec![];
+
+    let table = match data.parse::<toml::Value>() {
+        Ok(toml::Value::Table(t)) => t,
+        Ok(_) => return (layer, warnings),
+        Err(e) => {
+            warnings.push(format!("Problem parsing {:?}: {}", path, e));
+            return (layer, warnings);
+        }
+    };
+
+    for (name, value) in table {
+        if name == "repositories" {
+            // Read by `repositories`.
+            continue;
+        }
+        if find_key(&name).is_none() {
+            warnings.push(format!("Unknown key `{}` in {:?}", name, path));
+            continue;
+        }
+        let value = match value {
+            toml::Value::String(s) => s,
+            // Not numbers: `py-version = 3.10` would be read as 3.1.
+            toml::Value::Boolean(b) => b.to_string(),
+            _ => {
+                warnings.push(format!(
+                    "`{}` in {:?} should be a string, or a boolean",
+                    name, path
+                ));
+                continue;
+            }
+        };
+        layer.insert(name, value);
+    }
+    (layer, warnings)
+}
+
+/// Read the global config file, if there is one.
+pub fn read_global(path: &Path) -> (Layer, Vec<String>) {
+    match fs::read_to_string(path) {
+        Ok(data) => parse_file(&data, path),
+        Err(_) => (Layer::new(), vec![]),
+    }
+}
+
+/// The built-in repositories, and ones from `[repositories.<name>]` tables in the global config,
+/// with warnings about ones that can't be used.
+fn parse_repositories(data: &str, path: &Path) -> (Vec<Repository>, Vec<String>) {
+    let mut result: Vec<Repository> = REPOSITORIES
+        .iter()
+        .map(|(name, url)| Repository {
+            name: (*name).to_owned(),
+            url: (*url).to_owned(),
+            token: None,
+        })
+        .collect();
+    let mut warnings = vec![];
+
+    // Problems parsing the file are reported by `parse_file`.
+    let table = match data.parse::<toml::Value>() {
+        Ok(t) => t,
+        Err(_) => return (result, warnings),
+    };
+    let repos = match table.get("repositories") {
+        Some(toml::Value::Table(t)) => t,
+        Some(_) => {
+            warnings.push(format!(
+                "`repositories` in {:?} should be a table, like `[repositories.internal]`",
+                path
+            ));
+            return (result, warnings);
+        }
+        None => return (result, warnings),
+    };
+
+    for (name, repo) in repos {
+        let get = |key: &str| {
+            repo.get(key)
+                .and_then(toml::Value::as_str)
+                .map(ToOwned::to_owned)
+        };
+        let (url, token) = (get("url"), get("token"));
+        if let Some(u) = &url {
+            if let Err(e) = reqwest::Url::parse(u) {
+                warnings.push(format!(
+                    "Ignoring repository `{}` in {:?}: `{}` isn't a URL: {}",
+                    name, path, u, e
+                ));
+                continue;
+            }
+        }
+        match (result.iter_mut().find(|r| &r.name == name), url) {
+            // A built-in one, with a token, or somewhere else.
+            (Some(existing), url) => {
+                if let Some(u) = url {
+                    existing.url = u;
+                }
+                existing.token = token;
+            }
+            (None, Some(url)) => result.push(Repository {
+                name: name.clone(),
+                url,
+                token,
+            }),
+            (None, None) => warnings.push(format!(
+                "Ignoring repository `{}` in {:?}: it needs a `url`",
+                name, path
+            )),
+        }
+    }
+    (result, warnings)
+}
+
+/// Every repository `pyflow publish` can upload to by name.
+pub fn repositories() -> (Vec<Repository>, Vec<String>) {
+    let path = util::paths::global_config_path();
+    parse_repositories(&fs::read_to_string(&path).unwrap_or_default(), &path)
+}
+
+/// Every key, with its value from the first layer that sets it.
+fn resolve(layers: &[(Origin, &Layer)]) -> Vec<Setting> {
+    KEYS.iter()
+        .map(|key| Setting {
+            key,
+            value: layers
+                .iter()
+                .find_map(|(origin, layer)| layer.get(key.name).map(|v| (v.clone(), *origin))),
+        })
+        .collect()
+}
+
+/// Every key's value, and where it's from, given flags from the command line.
+pub fn resolve_all(cli: &Layer) -> (Vec<Setting>, Vec<String>) {
+    let (global, warnings) = read_global(&util::paths::global_config_path());
+    let (env, project, defaults) = (env_layer(), project_layer(), default_layer());
+    let layers = [
+        (Origin::Flag, cli),
+        (Origin::Env, &env),
+        (Origin::Project, &project),
+        (Origin::Global, &global),
+        (Origin::Default, &defaults),
+    ];
+    (resolve(&layers), warnings)
+}
+
+/// Check a value is usable for `key`.
+pub fn validate(key: &str, value: &str) -> Result<(), String> {
+    match key {
+        "index-url" | "proxy" => reqwest::Url::parse(value)
+            .map(|_| ())
+            .map_err(|e| format!("`{}` isn't a URL: {}", value, e)),
+        "py-version" => Version::from_str(value)
+            .map(|_| ())
+            .map_err(|_| format!("`{}` isn't a Python version, like 3.12", value)),
+        "color" if !COLORS.contains(&value) => Err(format!(
+            "`{}` isn't a color option; use one of {}",
+            value,
+            COLORS.join(", ")
+        )),
+        "link-mode" => LinkMode::from_str(value).map(|_| ()),
+        "non-interactive" if parse_bool(value).is_none() => {
+            Err(format!("`{}` isn't `true` or `false`", value))
+        }
+        _ => Ok(()),
+    }
+}
+
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "1" | "yes" => Some(true),
+        "false" | "0" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// Build `Settings` from resolved values. Ones that aren't valid are skipped, with a warning,
+/// and the default used.
+pub fn from_resolved(resolved: &[Setting]) -> (Settings, Vec<String>) {
+    let mut result = Settings::default();
+    let mut warnings = vec![];
+
+    for setting in resolved {
+        let (value, origin) = match &setting.value {
+            Some(v) => v,
+            None => continue,
+        };
+        if let Err(e) = validate(setting.key.name, value) {
+            warnings.push(format!(
+                "Ignoring `{}` from the {}: {}",
+                setting.key.name, origin, e
+            ));
+            continue;
+        }
+        match setting.key.name {
+            "index-url" => result.index_url = value.clone(),
+            "proxy" => result.proxy = Some(value.clone()),
+            "cache-dir" => result.cache_dir = Some(PathBuf::from(value)),
+            "python-dir" => result.python_dir = Some(PathBuf::from(value)),
+            "packages-dir" => result.packages_dir = Some(value.clone()),
+            "py-version" => result.py_version = Version::from_str(value).ok(),
+            "link-mode" => result.link_mode = LinkMode::from_str(value).unwrap_or_default(),
+            "color" => result.color = value.clone(),
+            "non-interactive" => result.non_interactive = parse_bool(value).unwrap_or(false),
+            _ => (),
+        }
+    }
+    (result, warnings)
+}
+
+/// Load settings, given flags from the command line. Returns warnings to show once logging is
+/// set up.
+pub fn load(cli: &Layer) -> (Settings, Vec<String>) {
+    let (resolved, mut warnings) = resolve_all(cli);
+    let (settings, more) = from_resolved(&resolved);
+    warnings.extend(more);
+    (settings, warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(entries: &[(&str, &str)]) -> Layer {
+        entries
+            .iter()
+            .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn precedence() {
+        let cli = layer(&[("color", "never")]);
+        let env = layer(&[("color", "always"), ("proxy", "http://env:3128")]);
+        let project = layer(&[("index-url", "https://mirror.local/pypi")]);
+        let global = layer(&[
+            ("index-url", "https://global.local/pypi"),
+            ("proxy", "http://global:3128"),
+            ("py-version", "3.12"),
+        ]);
+        let defaults = default_layer();
+        let resolved = resolve(&[
+            (Origin::Flag, &cli),
+            (Origin::Env, &env),
+            (Origin::Project, &project),
+            (Origin::Global, &global),
+            (Origin::Default, &defaults),
+        ]);
+        let value = |name: &str| {
+            resolved
+                .iter()
+                .find(|s| s.key.name == name)
+                .unwrap()
+                .value
+                .clone()
+        };
+
+        assert_eq!(value("color"), Some(("never".into(), Origin::Flag)));
+        assert_eq!(
+            value("proxy"),
+            Some(("http://env:3128".into(), Origin::Env))
+        );
+        assert_eq!(
+            value("index-url"),
+            Some(("https://mirror.local/pypi".into(), Origin::Project))
+        );
+        assert_eq!(value("py-version"), Some(("3.12".into(), Origin::Global)));
+        assert_eq!(
+            value("non-interactive"),
+            Some(("false".into(), Origin::Default))
+        );
+        assert_eq!(value("cache-dir"), None);
+
+        let (settings, warnings) = from_resolved(&resolved);
+        assert!(warnings.is_empty());
+        assert_eq!(settings.py_version, Some(Version::new_short(3, 12)));
+        assert_eq!(
+            settings.json_url("requests", Some("2.31.0")),
+            "https://mirror.local/pypi/requests/2.31.0/json"
+        );
+        assert_eq!(settings.simple_url(), "https://mirror.local/simple/");
+        assert_eq!(Settings::default().simple_url(), "https://pypi.org/simple/");
+    }
+
+    #[test]
+    fn global_file() {
+        let path = Path::new("/home/me/.config/pyflow/config.toml");
+        let (layer, warnings) = parse_file(
+            "index-url = \"https://mirror.local/pypi\"\nnon-interactive = true\nindex = \"x\"\n",
+            path,
+        );
+        assert_eq!(layer["non-interactive"], "true");
+        assert_eq!(layer.len(), 2);
+        assert_eq!(warnings, vec![format!("Unknown key `index` in {:?}", path)]);
+    }
+
+    #[test]
+    fn repository_tables() {
+        let path = Path::new("/home/me/.config/pyflow/config.toml");
+        let data = "color = \"never\"\n\n[repositories.internal]\n\
+                    url = \"https://pypi.internal/legacy/\"\ntoken = \"pypi-abc\"\n\n\
+                    [repositories.testpypi]\ntoken = \"pypi-test\"\n\n\
+                    [repositories.broken]\ntoken = \"pypi-xyz\"\n";
+        let (repos, warnings) = parse_repositories(data, path);
+        let find = |name: &str| repos.iter().find(|r| r.name == name).cloned();
+
+        assert_eq!(
+            find("internal"),
+            Some(Repository {
+                name: "internal".into(),
+                url: "https://pypi.internal/legacy/".into(),
+                token: Some("pypi-abc".into()),
+            })
+        );
+        let testpypi = find("testpypi").unwrap();
+        assert_eq!(testpypi.url, "https://test.pypi.org/legacy/");
+        assert_eq!(testpypi.token.as_deref(), Some("pypi-test"));
+        assert_eq!(find("pypi").unwrap().token, None);
+        assert_eq!(find("broken"), None);
+        assert_eq!(warnings.len(), 1);
+
+        // Not an unknown key.
+        let (_, warnings) = parse_file(data, path);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn env_flags() {
+        assert_eq!(env_value("non-interactive", "ON".into()), "true");
+        assert_eq!(env_value("non-interactive", "ci".into()), "true");
+        assert_eq!(env_value("non-interactive", "No".into()), "false");
+        assert_eq!(env_value("color", "never".into()), "never");
+    }
+
+    #[test]
+    fn invalid_values() {
+        assert!(validate("color", "sometimes").is_err());
+        assert!(validate("py-version", "three").is_err());
+        assert!(validate("proxy", "not a url").is_err());
+        assert!(validate("non-interactive", "yes").is_ok());
+        assert!(validate("cache-dir", "/tmp/pyflow").is_ok());
+
+        let resolved = vec![Setting {
+            key: find_key("color").unwrap(),
+            value: Some(("sometimes".into(), Origin::Global)),
+        }];
+        let (settings, warnings) = from_resolved(&resolved);
+        assert_eq!(settings.color, "auto");
+        assert_eq!(warnings.len(), 1);
+    }
+}
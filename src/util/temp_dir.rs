@@ -0,0 +1,40 @@
+//! Scratch folders for tests, removed when the test ends, whether or not it passed.
+
+use std::{
+    fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+/// An empty folder, unique to `name` and this process, removed on drop.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// `name` is in the folder's name; each test in a process should use its own.
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("pyflow-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,187 @@
+//! Installing a wheel decompresses each file straight to disk, so memory use doesn't grow with
+//! the size of what's in it. Runs a sync against a local index, serving one small wheel, then
+//! one with a 32 MiB file, and compares the peak memory of each. Its own binary, since the peak
+//! covers every child the test process runs.
+//!
+//! Linux only: `ru_maxrss` is in bytes on macOS, and there's no `getrusage` on Windows.
+#![cfg(target_os = "linux")]
+
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    path::{Path, PathBuf},
+    process::Command,
+    thread,
+};
+
+/// A wheel with a file of `size` bytes, compressed, and its hex SHA256.
+fn write_wheel(path: &Path, name: &str, size: usize) -> String {
+    let mut zip = zip::ZipWriter::new(fs::File::create(path).unwrap());
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    zip.start_file(format!("{}/data.bin", name), options)
+        .unwrap();
+    let chunk = vec![7; 1024 * 1024];
+    let mut written = 0;
+    while written < size {
+        let n = chunk.len().min(size - written);
+        zip.write_all(&chunk[..n]).unwrap();
+        written += n;
+    }
+    let dist_info = format!("{}-1.0.dist-info", name);
+    zip.start_file(format!("{}/METADATA", dist_info), options)
+        .unwrap();
+    write!(zip, "Name: {}\nVersion: 1.0\n", name).unwrap();
+    zip.start_file(format!("{}/RECORD", dist_info), options)
+        .unwrap();
+    write!(
+        zip,
+        "{0}/data.bin,,\n{1}/METADATA,,\n{1}/RECORD,,\n",
+        name, dist_info
+    )
+    .unwrap();
+    zip.finish().unwrap();
+
+    let data = fs::read(path).unwrap();
+    data_encoding::HEXLOWER.encode(ring::digest::digest(&ring::digest::SHA256, &data).as_ref())
+}
+
+/// Serve each package's JSON, and its wheel, from `root`, until the test ends.
+fn serve(root: PathBuf) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            // Eg `GET /pypi/small/json HTTP/1.1`, or `GET /files/small-1.0-py3-none-any.whl`.
+            let path = request.split(' ').nth(1).unwrap_or("");
+            let file = root.join(path.trim_start_matches('/').replace('/', "_"));
+            let body = match fs::read(&file) {
+                Ok(b) => b,
+                Err(_) => {
+                    let _ = write!(
+                        stream,
+                        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n"
+                    );
+                    continue;
+                }
+            };
+            let _ = write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            let _ = stream.write_all(&body);
+        }
+    });
+    base
+}
+
+/// Add a package to the index in `served`, as `pypi/<name>/json` and `files/<wheel>`.
+fn publish(served: &Path, base: &str, name: &str, size: usize) {
+    let filename = format!("{}-1.0-py3-none-any.whl", name);
+    let sha256 = write_wheel(&served.join(format!("files_{}", filename)), name, size);
+    let release = format!(
+        r#"{{"filename": "{0}", "has_sig": false, "digests": {{"md5": "", "sha256": "{1}"}},
+            "packagetype": "bdist_wheel", "python_version": "py3", "requires_python": null,
+            "url": "{2}/files/{0}", "dependencies": null}}"#,
+        filename, sha256, base
+    );
+    let json = format!(
+        r#"{{"info": {{"name": "{0}", "requires_dist": null, "requires_python": null,
+            "version": "1.0"}}, "releases": {{"1.0": [{1}]}}, "urls": [{1}]}}"#,
+        name, release
+    );
+    fs::write(served.join(format!("pypi_{}_json", name)), json).unwrap();
+}
+
+/// A project with Python 3.11 set up, and no packages, in a fresh home directory.
+fn project(root: &Path, name: &str) -> PathBuf {
+    let project = root.join(name);
+    let env = project.join("__pypackages__").join("3.11");
+    fs::create_dir_all(env.join(".venv").join("bin")).unwrap();
+    fs::create_dir_all(env.join("bin")).unwrap();
+    fs::create_dir_all(env.join("lib")).unwrap();
+    fs::write(env.join(".venv").join("bin").join("python"), "").unwrap();
+    fs::write(
+        project.join("pyproject.toml"),
+        format!(
+            "[tool.pyflow]\nname = \"{}-demo\"\npy_version = \"3.11\"\n",
+            name
+        ),
+    )
+    .unwrap();
+    fs::write(project.join("requirements.txt"), format!("{}==1.0\n", name)).unwrap();
+    project
+}
+
+/// Sync `package` into a project of its own, and return the peak memory, in KiB, of every
+/// child run so far.
+fn sync(root: &Path, index: &str, package: &str) -> libc::c_long {
+    let project = project(root, package);
+    let home = root.join("home");
+    let output = Command::new(env!("CARGO_BIN_EXE_pyflow"))
+        .args(["sync", "--from-requirements", "requirements.txt"])
+        .current_dir(&project)
+        .env("PYFLOW_INDEX_URL", index)
+        .env("PYFLOW_LINK_MODE", "copy")
+        .env("HOME", &home)
+        .env("XDG_DATA_HOME", home.join("data"))
+        .env("XDG_CONFIG_HOME", home.join("config"))
+        .env("XDG_CACHE_HOME", home.join("cache"))
+        .env_remove("HTTP_PROXY")
+        .env_remove("HTTPS_PROXY")
+        .env_remove("ALL_PROXY")
+        .env("NO_PROXY", "127.0.0.1")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "Syncing {} failed:\n{}",
+        package,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let installed = project.join("__pypackages__/3.11/lib").join(package);
+    assert!(installed.join("data.bin").exists());
+
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    assert_eq!(
+        unsafe { libc::getrusage(libc::RUSAGE_CHILDREN, &mut usage) },
+        0
+    );
+    usage.ru_maxrss
+}
+
+#[test]
+fn extract_large_wheel() {
+    const SIZE: usize = 32 * 1024 * 1024;
+    let root = std::env::temp_dir().join(format!("pyflow-large-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    let served = root.join("served");
+    fs::create_dir_all(&served).unwrap();
+    let base = serve(served.clone());
+    publish(&served, &base, "small", 1024);
+    publish(&served, &base, "big", SIZE);
+
+    let index = format!("{}/pypi", base);
+    let small = sync(&root, &index, "small");
+    let big = sync(&root, &index, "big");
+    let extracted = fs::metadata(root.join("big/__pypackages__/3.11/lib/big/data.bin"));
+    let _ = fs::remove_dir_all(&root);
+
+    assert_eq!(extracted.unwrap().len(), SIZE as u64);
+    // Both runs share everything but the wheel's size; holding the file would add 32 MiB.
+    assert!(
+        big - small < 8 * 1024,
+        "Installing the large wheel used {} KiB more",
+        big - small
+    );
+}
@@ -0,0 +1,537 @@
+//! Commands that only read the project and its environment run with the network forbidden, and
+//! must finish without it.
+//!
+//! Unix only: the project's built with a Unix environment layout, and on Windows, the home and
+//! data directories come from the system, not environment variables we could point elsewhere.
+#![cfg(unix)]
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+/// A project with Python 3.11 set up, and `idna` installed, in a fresh home directory, so no
+/// interpreter needs finding, and no cached settings apply.
+fn project(test: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("pyflow-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    let project = root.join("demo");
+    let env = project.join("__pypackages__").join("3.11");
+    let dist_info = env.join("lib").join("idna-3.6.dist-info");
+    fs::create_dir_all(env.join(".venv").join("bin")).unwrap();
+    fs::create_dir_all(env.join("bin")).unwrap();
+    fs::create_dir_all(&dist_info).unwrap();
+    fs::create_dir_all(root.join("home")).unwrap();
+    fs::write(env.join(".venv").join("bin").join("python"), "").unwrap();
+    fs::write(dist_info.join("METADATA"), "Name: idna\nVersion: 3.6\n").unwrap();
+    fs::write(dist_info.join("RECORD"), "idna-3.6.dist-info/METADATA,,\n").unwrap();
+    fs::write(
+        project.join("pyproject.toml"),
+        "[tool.pyflow]\nname = \"demo\"\npy_version = \"3.11\"\n\n\
+         [tool.pyflow.scripts]\nserve = \"demo:main\"\n\n\
+         [tool.pyflow.dependencies]\nidna = \"^3.6\"\n",
+    )
+    .unwrap();
+    project
+}
+
+fn pyflow(project: &Path, args: &[&str]) -> Output {
+    command(project, args).output().unwrap()
+}
+
+fn command(project: &Path, args: &[&str]) -> Command {
+    let home = project.parent().unwrap().join("home");
+    let mut command = Command::new(env!("CARGO_BIN_EXE_pyflow"));
+    command
+        .args(args)
+        .current_dir(project)
+        .env("PYFLOW_FORBID_NETWORK", "1")
+        .env("HOME", &home)
+        .env("XDG_DATA_HOME", home.join("data"))
+        .env("XDG_CONFIG_HOME", home.join("config"))
+        .env("XDG_CACHE_HOME", home.join("cache"))
+        // Unreachable, so a request that gets past the client fails too.
+        .env("HTTPS_PROXY", "http://127.0.0.1:9")
+        .env("HTTP_PROXY", "http://127.0.0.1:9");
+    command
+}
+
+#[test]
+fn offline_commands() {
+    let project = project("offline");
+    for args in &[
+        &["list"][..],
+        &["list", "--json"],
+        &["scripts"],
+        &["env", "path"],
+        &["check", "--skip", "git-deps"],
+    ] {
+        let output = pyflow(&project, args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            !stderr.contains("Tried to use the network"),
+            "`pyflow {}` used the network:\n{}",
+            args.join(" "),
+            stderr
+        );
+        // `check` exits with 1 for errors it finds, like the missing lock; that's not a crash.
+        assert!(
+            output.status.code().is_some_and(|c| c <= 1),
+            "`pyflow {}` failed:\n{}",
+            args.join(" "),
+            stderr
+        );
+        if args[0] == "list" || args[0] == "env" {
+            assert!(
+                output.status.success(),
+                "`pyflow {}` failed",
+                args.join(" ")
+            );
+        }
+    }
+
+    let listed = String::from_utf8_lossy(&pyflow(&project, &["list"]).stdout).into_owned();
+    let _ = fs::remove_dir_all(project.parent().unwrap());
+    assert!(listed.contains("idna"));
+}
+
+/// Reinstalling works from the lock as it is: nothing's resolved, and the lock isn't rewritten.
+/// This lock has no packages, so there's nothing to download.
+#[test]
+fn reinstall_keeps_lock() {
+    let project = project("reinstall");
+    let lock = "# Written by hand; resolving would replace this.\n";
+    fs::write(project.join("pyflow.lock"), lock).unwrap();
+
+    let output = pyflow(&project, &["install", "--reinstall-all"]);
+    let after = fs::read_to_string(project.join("pyflow.lock")).unwrap();
+    let _ = fs::remove_dir_all(project.parent().unwrap());
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(after, lock);
+}
+
+/// An install reads `pyproject.toml` and the lock from disk once each, though several phases
+/// use them.
+#[test]
+fn install_reads_each_file_once() {
+    let project = project("reads");
+    fs::write(project.join("pyflow.lock"), "").unwrap();
+
+    let output = command(&project, &["install", "--reinstall-all"])
+        .env("PYFLOW_LOG", "pyflow::util::loaded=trace")
+        .output()
+        .unwrap();
+    let _ = fs::remove_dir_all(project.parent().unwrap());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    for file in &["pyproject.toml", "pyflow.lock"] {
+        let reads = stderr
+            .lines()
+            .filter(|l| l.contains("from disk") && l.contains(&format!("{}\"", file)))
+            .count();
+        assert_eq!(reads, 1, "{} was read {} times:\n{}", file, reads, stderr);
+    }
+}
+
+/// With `cache-dir` set, the caches are cleaned there, not refused for being outside pyflow's
+/// own directory.
+#[test]
+fn clean_relocated_cache() {
+    let project = project("clean-cache");
+    let cache = project.parent().unwrap().join("shared-cache");
+    fs::create_dir_all(cache.join("dependency_cache")).unwrap();
+    fs::create_dir_all(cache.join("index_cache")).unwrap();
+    fs::write(cache.join("dependency_cache/idna-3.6.tar.gz"), "").unwrap();
+
+    let output = command(&project, &["clean", "--cache", "--metadata"])
+        .env("PYFLOW_CACHE_DIR", &cache)
+        .output()
+        .unwrap();
+    let removed = !cache.join("dependency_cache").exists() && !cache.join("index_cache").exists();
+    let _ = fs::remove_dir_all(project.parent().unwrap());
+    assert!(
+        output.status.success() && removed,
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
+
+/// `-q` hides progress messages; errors reach stderr even with logging off.
+#[test]
+fn quiet_and_errors() {
+    let project = project("quiet");
+    fs::write(project.join("pyflow.lock"), "").unwrap();
+
+    let installed = pyflow(&project, &["-q", "install", "--reinstall-all"]);
+    let failed = command(&project, &["check", "--only", "nope"])
+        .env("PYFLOW_LOG", "off")
+        .output()
+        .unwrap();
+    let _ = fs::remove_dir_all(project.parent().unwrap());
+    assert!(installed.status.success());
+    assert!(!String::from_utf8_lossy(&installed.stdout).contains("Reinstall complete"));
+    assert_eq!(failed.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&failed.stderr).contains("There's no check called `nope`"));
+}
+
+/// A dry run builds the sdist and wheel, and checks them, without sending anything.
+#[test]
+fn publish_dry_run_builds() {
+    let project = project("publish");
+    fs::create_dir_all(project.join("demo")).unwrap();
+    fs::write(project.join("demo").join("__init__.py"), "").unwrap();
+    fs::write(
+        project.join("pyproject.toml"),
+        "[tool.pyflow]\nname = \"demo\"\nversion = \"0.1.0\"\ndescription = \"Demo\"\n\
+         authors = [\"A <a@example.com>\"]\nlicense = \"MIT\"\n",
+    )
+    .unwrap();
+
+    let output = command(
+        &project,
+        &[
+            "publish",
+            "--dry-run",
+            "--repository-url",
+            "http://127.0.0.1:9/legacy/",
+            "--no-verify-classifiers",
+        ],
+    )
+    // The HTTP client's made before anything's sent; the proxy's unreachable anyway.
+    .env_remove("PYFLOW_FORBID_NETWORK")
+    .output()
+    .unwrap();
+    let built = ["demo-0.1.0.tar.gz", "demo-0.1.0-py3-none-any.whl"]
+        .iter()
+        .all(|f| project.join("dist").join(f).exists());
+    let _ = fs::remove_dir_all(project.parent().unwrap());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(built);
+    assert!(stdout.contains("2 files are ready to upload"), "{}", stdout);
+}
+
+/// An `--out-dir` relative to the working directory isn't packed into the sdist.
+#[test]
+fn sdist_skips_relative_out_dir() {
+    let project = project("sdist-out-dir");
+    fs::write(
+        project.join("pyproject.toml"),
+        "[tool.pyflow]\nname = \"demo\"\nversion = \"0.1.0\"\n",
+    )
+    .unwrap();
+    fs::create_dir_all(project.join("demo")).unwrap();
+    fs::write(project.join("demo").join("__init__.py"), "").unwrap();
+    fs::create_dir_all(project.join("out")).unwrap();
+    fs::write(project.join("out").join("demo-0.0.9.tar.gz"), "").unwrap();
+
+    let output = pyflow(
+        &project,
+        &["build", "--sdist", "--list-files", "--out-dir", "out"],
+    );
+    let _ = fs::remove_dir_all(project.parent().unwrap());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains("pyproject.toml"), "{}", stdout);
+    assert!(!stdout.contains("out/"), "{}", stdout);
+}
+
+/// Moving the environments with `packages-dir`, then back to `__pypackages__`, warns about the
+/// environment left behind each time.
+#[test]
+fn packages_dir_moves() {
+    let project = project("packages-dir");
+    let moved = project.parent().unwrap().join("envs");
+    fs::rename(project.join("__pypackages__"), &moved).unwrap();
+    // Listing an sdist's files needs no environment, so one isn't made in `__pypackages__`.
+    fs::write(
+        project.join("pyproject.toml"),
+        "[tool.pyflow]\nname = \"demo\"\nversion = \"0.1.0\"\npy_version = \"3.11\"\n",
+    )
+    .unwrap();
+    fs::create_dir_all(project.join("demo")).unwrap();
+    fs::write(project.join("demo").join("__init__.py"), "").unwrap();
+    let warnings = |packages_dir: Option<&str>| {
+        let mut command = command(&project, &["build", "--sdist", "--list-files"]);
+        if let Some(dir) = packages_dir {
+            command.env("PYFLOW_PACKAGES_DIR", dir);
+        }
+        let output = command.output().unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    let custom = warnings(Some("../envs"));
+    // The setting's gone; the environments are still in `envs`.
+    let back = warnings(None);
+    fs::remove_dir_all(moved).unwrap();
+    let emptied = warnings(None);
+    let _ = fs::remove_dir_all(project.parent().unwrap());
+
+    assert!(!custom.contains("no longer uses"), "{}", custom);
+    assert!(back.contains("no longer uses"), "{}", back);
+    assert!(back.contains("envs\", where"), "{}", back);
+    assert!(!emptied.contains("no longer uses"), "{}", emptied);
+}
+
+/// A project with no dependencies, and a real Python 3.11 virtual environment, for running
+/// things in. `None` if there's no `python3.11` to make one with.
+fn venv_project(test: &str) -> Option<PathBuf> {
+    let project = project(test);
+    let venv = project.join("__pypackages__").join("3.11").join(".venv");
+    fs::remove_dir_all(&venv).unwrap();
+    let made = Command::new("python3.11")
+        .args(["-m", "venv", "--without-pip"])
+        .arg(&venv)
+        .status();
+    if !made.is_ok_and(|s| s.success()) {
+        let _ = fs::remove_dir_all(project.parent().unwrap());
+        return None;
+    }
+    fs::write(
+        project.join("pyproject.toml"),
+        "[tool.pyflow]\nname = \"demo\"\npy_version = \"3.11\"\n",
+    )
+    .unwrap();
+    fs::write(project.join("pyflow.lock"), "").unwrap();
+    Some(project)
+}
+
+/// A process started by `pyflow run`'s target, with an empty environment, still finds
+/// `__pypackages__`, through the `.pth` file a sync writes. Skipped without Python 3.11.
+#[test]
+fn run_subprocesses_find_pypackages() {
+    let project = match venv_project("pth") {
+        Some(p) => p,
+        None => return,
+    };
+    let lib = project.join("__pypackages__").join("3.11").join("lib");
+    fs::write(lib.join("pyflow_grandchild_dep.py"), "").unwrap();
+
+    let spawn = "import subprocess, sys\n\
+                 sys.exit(subprocess.call([sys.executable, '-c', 'import pyflow_grandchild_dep'], \
+                 env={}))";
+    let output = pyflow(&project, &["run", "python", "-c", spawn]);
+    let _ = fs::remove_dir_all(project.parent().unwrap());
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+/// Arguments after the program, including ones that look like pyflow's own flags, empty ones,
+/// and ones with spaces and quotes, reach it as given. Skipped without Python 3.11.
+#[test]
+fn run_passes_argv_through() {
+    let project = match venv_project("argv") {
+        Some(p) => p,
+        None => return,
+    };
+    let print_argv = "import json, sys; print(json.dumps(sys.argv[1:]))";
+    let args = [
+        "-k",
+        "not slow",
+        "",
+        "-x",
+        "--lf",
+        "-q",
+        "--help",
+        "say \"hi\"",
+    ];
+    let argv = |prefix: &[&str]| {
+        let mut all: Vec<&str> = prefix.to_vec();
+        all.extend(&args);
+        let output = pyflow(&project, &all);
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .last()
+            .unwrap_or_default()
+            .to_owned()
+    };
+    let results = [
+        argv(&["run", "python", "-c", print_argv]),
+        argv(&["run", "--", "python", "-c", print_argv]),
+        argv(&["run", "python", "--", "-c", print_argv]),
+        argv(&["python", "-c", print_argv]),
+    ];
+    // A later `--` is the program's.
+    let later_dashes = argv(&["run", "python", "-c", print_argv, "--"]);
+    let _ = fs::remove_dir_all(project.parent().unwrap());
+
+    let expected = r#"["-k", "not slow", "", "-x", "--lf", "-q", "--help", "say \"hi\""]"#;
+    for result in &results {
+        assert_eq!(result, expected);
+    }
+    assert_eq!(later_dashes, expected.replacen('[', r#"["--", "#, 1));
+}
+
+/// `pyflow run` runs its program in the directory `--cwd`, a script's `cwd`, or
+/// `run-from-root` chooses, and `__pypackages__` is found from each. Skipped without Python 3.11.
+#[test]
+fn run_in_chosen_cwd() {
+    let project = match venv_project("cwd") {
+        Some(p) => p,
+        None => return,
+    };
+    let lib = project.join("__pypackages__").join("3.11").join("lib");
+    fs::write(lib.join("pyflow_cwd_dep.py"), "").unwrap();
+    fs::create_dir_all(project.join("docs")).unwrap();
+    fs::create_dir_all(project.join("sub")).unwrap();
+    let print_cwd = "import os, pyflow_cwd_dep; print(os.getcwd())";
+    // A relative path argument, found from the script's directory.
+    fs::write(project.join("docs").join("where.py"), print_cwd).unwrap();
+
+    let cwd = |mut command: Command| {
+        let output = command.output().unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let printed = String::from_utf8_lossy(&output.stdout);
+        PathBuf::from(printed.lines().last().unwrap_or_default())
+    };
+    let flag = cwd(command(
+        &project,
+        &["run", "--cwd", "docs", "python", "-c", print_cwd],
+    ));
+    let staying = cwd(command(&project, &["run", "python", "-c", print_cwd]));
+
+    fs::write(
+        project.join("pyproject.toml"),
+        "[tool.pyflow]\nname = \"demo\"\npy_version = \"3.11\"\nrun-from-root = true\n\n\
+         [tool.pyflow.scripts]\nwhere = { run = \"python where.py\", cwd = \"docs\" }\n",
+    )
+    .unwrap();
+    let script = cwd(command(&project, &["run", "where"]));
+    let mut from_sub = command(&project, &["run", "python", "-c", print_cwd]);
+    from_sub.current_dir(project.join("sub"));
+    let root = cwd(from_sub);
+
+    let docs = project.join("docs").canonicalize().unwrap();
+    let expected = project.canonicalize().unwrap();
+    let _ = fs::remove_dir_all(project.parent().unwrap());
+    assert_eq!(flag, docs);
+    assert_eq!(staying, expected);
+    assert_eq!(script, docs);
+    assert_eq!(root, expected);
+}
+
+/// Saying no to adding `__pypackages__` to `.gitignore` is remembered, so later installs don't
+/// ask again; a closed stdin isn't an answer. Skipped without Python 3.11.
+#[test]
+fn gitignore_no_is_remembered() {
+    let project = match venv_project("gitignore-no") {
+        Some(p) => p,
+        None => return,
+    };
+    fs::create_dir_all(project.join(".git")).unwrap();
+
+    let install = |input: &[u8]| {
+        let mut child = command(&project, &["install"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(input).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).contains("Git doesn't ignore")
+    };
+    let closed = install(b"");
+    let no = install(b"no\n");
+    let again = install(b"");
+
+    let gitignore = project.join(".gitignore").exists();
+    let _ = fs::remove_dir_all(project.parent().unwrap());
+    assert!(closed);
+    assert!(no);
+    assert!(!again);
+    assert!(!gitignore);
+}
+
+/// `pyflow new`'s default template writes the `pyproject.toml` in `tests/golden`.
+#[test]
+fn new_project_pyproject() {
+    let project = project("golden");
+    let home = project.parent().unwrap().join("home");
+    fs::write(
+        home.join(".gitconfig"),
+        "[user]\nname = Jane Doe\nemail = jane@example.com\n",
+    )
+    .unwrap();
+    let output = command(&project, &["new", "my-project"])
+        .env("PYFLOW_PY_VERSION", "3.11")
+        .output()
+        .unwrap();
+    let written = fs::read_to_string(project.join("my-project").join("pyproject.toml"));
+    let _ = fs::remove_dir_all(project.parent().unwrap());
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        written.unwrap(),
+        include_str!("golden/new_project.pyproject.toml")
+    );
+}
+
+/// With stdin closed, prompts take their default, or fail saying how to decide without one,
+/// rather than wait.
+#[test]
+fn closed_stdin() {
+    let project = project("closed-stdin");
+    let new = |name: &str| {
+        command(&project, &["new", name])
+            .stdin(Stdio::null())
+            .output()
+            .unwrap()
+    };
+    // Asks for a Python version, and takes the default.
+    let defaulted = new("proj");
+    let written = fs::read_to_string(project.join("proj").join("pyproject.toml"));
+    // Asks whether to use a name that can't be imported, which needs a decision.
+    let refused = new("1proj");
+    let refused_dir = project.join("1proj").exists();
+    let _ = fs::remove_dir_all(project.parent().unwrap());
+
+    assert!(
+        defaulted.status.success(),
+        "{}",
+        String::from_utf8_lossy(&defaulted.stderr)
+    );
+    assert!(written.unwrap().contains("py_version = \"3."));
+    assert!(!refused.status.success());
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("Pick a name without these problems"));
+    assert!(!refused_dir);
+}